@@ -0,0 +1,204 @@
+use crate::completions;
+use arbor_core::cmd::Executable;
+use arbor_core::*;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::RwLock;
+use structopt::clap::{AppSettings, ErrorKind};
+
+/// Key of the alias table in the settings file, next to the logging settings
+pub const SETTINGS_KEY: &str = "aliases";
+
+/// Short aliases that are always available. User defined aliases with the same name take
+/// precedence
+pub const BUILTIN: [(&str, &str); 10] = [
+    ("nn", "new node"),
+    ("ne", "new edge"),
+    ("en", "edit node"),
+    ("ee", "edit edge"),
+    ("rn", "remove node"),
+    ("re", "remove edge"),
+    ("ls", "list"),
+    ("u", "undo"),
+    ("r", "redo"),
+    ("f", "find"),
+];
+
+/// Aliases used to expand commands, see init. Only the built in aliases until init is called
+static ALIASES: RwLock<Aliases> = RwLock::new(Aliases {
+    user: BTreeMap::new(),
+});
+
+/// Load the user defined aliases from a settings file once, when the CLI starts. Commands are
+/// expanded with these aliases from then on, and the alias command keeps them up to date
+///
+/// # Errors
+///
+/// Error if the file exists but cannot be read or parsed. Only the built in aliases are used in
+/// that case
+pub fn init(path: &Path) -> Result<()> {
+    set(Aliases::load(path)?);
+    Ok(())
+}
+
+/// Replace the aliases used to expand commands
+fn set(aliases: Aliases) {
+    *ALIASES.write().unwrap_or_else(|e| e.into_inner()) = aliases;
+}
+
+/// Expand the first argument with the aliases loaded by init, see Aliases::expand
+pub fn expand(args: &[String]) -> Result<Vec<String>> {
+    ALIASES
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .expand(args)
+}
+
+/// User defined aliases, by name
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Aliases {
+    pub user: BTreeMap<String, String>,
+}
+
+impl Aliases {
+    /// Read the aliases from a settings file. A missing file or a file without aliases gives no
+    /// user defined aliases
+    ///
+    /// # Errors
+    ///
+    /// Error if the file exists but cannot be read or parsed
+    pub fn load(path: &Path) -> Result<Aliases> {
+        let settings = read_settings(path)?;
+        let user = match settings.get(SETTINGS_KEY) {
+            Some(aliases) => serde_json::from_value(aliases.clone())?,
+            None => BTreeMap::new(),
+        };
+        Ok(Aliases { user })
+    }
+
+    /// Write the aliases to a settings file, keeping every other setting in the file as it is
+    ///
+    /// # Errors
+    ///
+    /// Error if the file exists but cannot be parsed, or cannot be written
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut settings = read_settings(path)?;
+        settings.insert(SETTINGS_KEY.to_string(), serde_json::to_value(&self.user)?);
+        std::fs::write(path, serde_json::to_string_pretty(&settings)?)?;
+        Ok(())
+    }
+
+    /// Get the command an alias expands to
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.user.get(name).map(String::as_str).or_else(|| {
+            BUILTIN
+                .iter()
+                .find(|(alias, _)| *alias == name)
+                .map(|(_, expansion)| *expansion)
+        })
+    }
+
+    /// Replace the first argument with the arguments it expands to if it is an alias. Aliases
+    /// are only expanded once, so an alias cannot refer to another alias
+    ///
+    /// # Errors
+    ///
+    /// Error if the expansion cannot be split into arguments
+    pub fn expand(&self, args: &[String]) -> Result<Vec<String>> {
+        match args.first().and_then(|first| self.get(first)) {
+            Some(expansion) => {
+                let mut expanded = shellwords::split(expansion)?;
+                expanded.extend_from_slice(&args[1..]);
+                Ok(expanded)
+            }
+            None => Ok(args.to_vec()),
+        }
+    }
+}
+
+/// Read the settings file as a json object. A missing file gives an empty object
+fn read_settings(path: &Path) -> Result<serde_json::Map<String, serde_json::Value>> {
+    match std::fs::read_to_string(path) {
+        Ok(s) => Ok(serde_json::from_str(&s)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(serde_json::Map::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Define a shortcut for a command, or list the aliases if no name is given
+///
+/// Aliases are expanded before the command is parsed, so any arguments after the alias are
+/// passed on to the command it expands to. For example, after `alias nn "new node"`, the line
+/// `nn cat "Hello"` runs `new node cat "Hello"`. Giving only a name prints what the alias expands
+/// to. Aliases are saved in the settings file, see logging::Settings::path. Several short
+/// aliases are built in, such as nn for new node and u for undo. Returns the number of user
+/// defined aliases.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "alias", setting = AppSettings::NoBinaryName)]
+pub struct Alias {
+    /// Name of the alias
+    name: Option<String>,
+    /// Command the alias expands to, e.g. "new node"
+    #[structopt(conflicts_with = "remove")]
+    expansion: Option<String>,
+    /// Remove the user defined alias with the name
+    #[structopt(short, long, requires = "name")]
+    remove: bool,
+}
+
+impl Executable for Alias {
+    fn execute(&self, state: &mut EditorState) -> Result<usize> {
+        let path = logging::Settings::path();
+        let mut aliases = Aliases::load(&path)?;
+        match (&self.name, &self.expansion) {
+            (None, _) => {
+                for (name, expansion) in aliases.user.iter() {
+                    state
+                        .scratchpad
+                        .push_str(&format!("{} = {}\r\n", name, expansion));
+                }
+                for (name, expansion) in BUILTIN.iter() {
+                    if !aliases.user.contains_key(*name) {
+                        state
+                            .scratchpad
+                            .push_str(&format!("{} = {} (built in)\r\n", name, expansion));
+                    }
+                }
+            }
+            (Some(name), None) if self.remove => {
+                if aliases.user.remove(name).is_none() {
+                    return Err(match aliases.get(name) {
+                        Some(_) => cmd::Error::BuiltinAlias(name.clone()).into(),
+                        None => cmd::Error::AliasNotExists(name.clone()).into(),
+                    });
+                }
+                aliases.save(&path)?;
+                set(aliases.clone());
+            }
+            (Some(name), None) => {
+                let expansion = aliases
+                    .get(name)
+                    .ok_or_else(|| cmd::Error::AliasNotExists(name.clone()))?;
+                state
+                    .scratchpad
+                    .push_str(&format!("{} = {}\r\n", name, expansion));
+            }
+            (Some(name), Some(expansion)) => {
+                // an alias named after a command would make the command unreachable
+                anyhow::ensure!(!is_command(name), cmd::Error::AliasIsCommand(name.clone()));
+                shellwords::split(expansion)?;
+                aliases.user.insert(name.clone(), expansion.clone());
+                aliases.save(&path)?;
+                set(aliases.clone());
+            }
+        }
+        print!("{}", state.scratchpad);
+        Ok(aliases.user.len())
+    }
+}
+
+/// Check if a name is a top level command of the CLI
+fn is_command(name: &str) -> bool {
+    let res = completions::app().get_matches_from_safe([name, "--help"]);
+    matches!(res, Err(e) if e.kind == ErrorKind::HelpDisplayed)
+}
@@ -0,0 +1,141 @@
+use crate::alias;
+use crate::script;
+use arbor_core::cmd::Executable;
+use arbor_core::*;
+use structopt::clap::{App, AppSettings, Shell};
+
+/// Name of the CLI binary, used by the generated completion scripts
+pub const BIN_NAME: &str = "arbor_cli";
+
+/// Environment variable holding the path of the project file that bash completes name and val
+/// keys from
+pub const PROJECT_VAR: &str = "ARBOR_PROJECT";
+
+/// Build the full command line of the CLI, the editor commands along with the commands that are
+/// only available from the CLI
+pub fn app() -> App<'static, 'static> {
+    cmd::Parse::clap()
+        .name(BIN_NAME)
+        .bin_name(BIN_NAME)
+        .subcommand(script::Script::clap())
+        .subcommand(alias::Alias::clap())
+        .subcommand(Completions::clap())
+        .subcommand(CompleteKeys::clap().setting(AppSettings::Hidden))
+}
+
+/// Generate a tab completion script for a shell
+///
+/// The script completes commands run from the shell, as in `arbor_cli list --raw`. For bash,
+/// the keys of name and val commands are also completed from the project file set in the
+/// ARBOR_PROJECT environment variable. To enable completions for the current bash session:
+///
+/// ```text
+/// source <(arbor_cli completions bash)
+/// ```
+#[derive(StructOpt, Debug)]
+#[structopt(name = "completions", setting = AppSettings::NoBinaryName)]
+pub struct Completions {
+    /// One of bash, zsh, fish, powershell, or elvish
+    #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+    shell: Shell,
+    /// Write the script to a file rather than the editor scratchpad
+    #[structopt(short, long)]
+    output: Option<String>,
+}
+
+impl Executable for Completions {
+    /// Returns the length of the script in bytes
+    fn execute(&self, state: &mut EditorState) -> Result<usize> {
+        let mut buf = Vec::new();
+        app().gen_completions_to(BIN_NAME, self.shell, &mut buf);
+        let mut completions = String::from_utf8(buf)?;
+        if let Shell::Bash = self.shell {
+            completions.push_str(&bash_keys());
+        }
+
+        match &self.output {
+            Some(path) => std::fs::write(path, &completions)?,
+            None => {
+                state.scratchpad.push_str(&completions);
+                print!("{}", state.scratchpad);
+            }
+        }
+        Ok(completions.len())
+    }
+}
+
+/// Bash function that completes the key of name and val commands by calling complete-keys, and
+/// falls back to the generated completions for everything else
+fn bash_keys() -> String {
+    format!(
+        r#"
+_{bin}_keys() {{
+    local kind=""
+    case "${{COMP_WORDS[1]}} ${{COMP_WORDS[2]}}" in
+        "edit name"|"remove name"|"rename name") kind=names ;;
+        "edit val"|"remove val"|"rename val") kind=vals ;;
+    esac
+    if [[ -n "$kind" && -n "${var}" && ${{COMP_CWORD}} -eq 3 ]]; then
+        COMPREPLY=( $({bin} complete-keys "${var}" "$kind" "${{COMP_WORDS[COMP_CWORD]}}" 2>/dev/null) )
+        return 0
+    fi
+    _{bin} "$@"
+}}
+
+complete -F _{bin}_keys -o bashdefault -o default {bin}
+"#,
+        bin = BIN_NAME,
+        var = PROJECT_VAR
+    )
+}
+
+/// Kind of key to complete
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyKind {
+    Names,
+    Vals,
+}
+
+impl std::str::FromStr for KeyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "names" => Ok(KeyKind::Names),
+            "vals" => Ok(KeyKind::Vals),
+            _ => Err(format!("expected names or vals, found {}", s)),
+        }
+    }
+}
+
+/// Print the name or val keys of a saved project that match a prefix, one per line. Used by the
+/// generated bash completions
+#[derive(StructOpt, Debug)]
+#[structopt(name = "complete-keys", setting = AppSettings::NoBinaryName)]
+pub struct CompleteKeys {
+    /// Path to the project file
+    project: String,
+    /// names or vals
+    kind: KeyKind,
+    /// Start of the key to complete
+    #[structopt(default_value = "")]
+    prefix: String,
+}
+
+impl Executable for CompleteKeys {
+    /// Returns the number of matching keys
+    fn execute(&self, state: &mut EditorState) -> Result<usize> {
+        let (dir, name) = cmd::util::split_tree_path(std::path::Path::new(&self.project))?;
+        let file_store = store::FileStore::new(dir);
+        let data = store::read_data(&file_store, &(name + TREE_EXT))?;
+        let keys = match self.kind {
+            KeyKind::Names => cmd::util::complete_key(&self.prefix, data.name_table.keys()),
+            KeyKind::Vals => cmd::util::complete_key(&self.prefix, data.val_table.keys()),
+        };
+        for key in keys.iter() {
+            state.scratchpad.push_str(&format!("{}\n", key));
+        }
+        print!("{}", state.scratchpad);
+        Ok(keys.len())
+    }
+}
@@ -0,0 +1,5 @@
+pub mod alias;
+pub mod completions;
+pub mod logger;
+pub mod repl;
+pub mod script;
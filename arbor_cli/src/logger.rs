@@ -0,0 +1,198 @@
+//! Logger installed by the arbor frontends. Verbosity is read per module from the settings file,
+//! see logging::Settings, and log output can be copied to a log file that is rotated once it
+//! grows too large
+use arbor_core::logging::{Filter, Settings};
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Function called with each log record at or above the notify level, used by frontends to show
+/// errors to the user
+pub type Notify = Box<dyn Fn(&Record) + Send + Sync>;
+
+/// Log file that is rotated once it grows larger than a size limit. Rotated files are
+/// renamed with a numbered suffix, with .1 being the most recent
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    /// Open a log file for appending, creating it if it does not exist
+    pub fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            written: file.metadata()?.len(),
+            file,
+            max_size,
+            max_files,
+        })
+    }
+
+    /// Path of a rotated log file
+    pub fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    /// Write a line to the file, rotating it first if the line would exceed the size limit
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.written > 0 && self.written + len > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.written += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            self.file = File::create(&self.path)?;
+        } else {
+            // the oldest file is overwritten by the rename
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated_path(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+            self.file = File::create(&self.path)?;
+        }
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Logger writing to stderr, and optionally to a rotating log file
+pub struct Logger {
+    filter: Filter,
+    file: Option<Mutex<RotatingFile>>,
+    notify: Option<(LevelFilter, Notify)>,
+}
+
+impl Logger {
+    /// Create a logger from settings. Each verbose step raises the log level by one
+    ///
+    /// # Errors
+    ///
+    /// Error if the settings contain an invalid level, or the log file cannot be opened
+    pub fn new(settings: &Settings, verbose: usize) -> anyhow::Result<Logger> {
+        let file = match settings.file.as_ref() {
+            Some(path) => Some(Mutex::new(RotatingFile::open(
+                path,
+                settings.max_file_size,
+                settings.max_files,
+            )?)),
+            None => None,
+        };
+        Ok(Logger {
+            filter: settings.filter(verbose)?,
+            file,
+            notify: None,
+        })
+    }
+
+    /// Call notify with each record at or above level, regardless of module verbosity
+    pub fn notify(mut self, level: LevelFilter, notify: Notify) -> Self {
+        self.notify = Some((level, notify));
+        self
+    }
+
+    /// Install the logger as the global logger
+    ///
+    /// # Errors
+    ///
+    /// Error if a global logger has already been installed
+    pub fn init(self) -> anyhow::Result<()> {
+        let max = match self.notify.as_ref() {
+            Some((level, _)) => std::cmp::max(self.filter.max(), *level),
+            None => self.filter.max(),
+        };
+        // SetLoggerError only implements std::error::Error with the std feature of log
+        log::set_logger(Box::leak(Box::new(self))).map_err(|e| anyhow::anyhow!("{}", e))?;
+        log::set_max_level(max);
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+            || matches!(&self.notify, Some((level, _)) if metadata.level() <= *level)
+    }
+
+    fn log(&self, record: &Record) {
+        if let Some((level, notify)) = self.notify.as_ref() {
+            if record.level() <= *level {
+                notify(record);
+            }
+        }
+        if record.level() > self.filter.level(record.target()) {
+            return;
+        }
+        let line = format!("[{} {}] {}", record.level(), record.target(), record.args());
+        eprintln!("{}", line);
+        if let Some(file) = self.file.as_ref() {
+            if let Ok(mut file) = file.lock() {
+                // a failing log file must not take down the program, so the error is dropped
+                let _ = file.write_line(&line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = self.file.as_ref() {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.file.flush();
+            }
+        }
+    }
+}
+
+/// Count and remove --verbose and -v flags from the start of command line arguments, before
+/// any command. Repeated short flags such as -vv count once per v
+pub fn take_verbose(args: &mut Vec<String>) -> usize {
+    let mut verbose = 0;
+    let mut flags = 0;
+    for arg in args.iter() {
+        match arg.as_str() {
+            "--verbose" => verbose += 1,
+            a if a.len() > 1 && a.starts_with('-') && a[1..].chars().all(|c| c == 'v') => {
+                verbose += a.len() - 1
+            }
+            _ => break,
+        }
+        flags += 1;
+    }
+    args.drain(..flags);
+    verbose
+}
+
+/// Load settings from the default settings path and install a logger. Failures are printed
+/// to stderr, and logging falls back to the default settings
+pub fn init(verbose: usize, notify: Option<(LevelFilter, Notify)>) {
+    let path = Settings::path();
+    let logger = Settings::load(&path)
+        .and_then(|settings| Logger::new(&settings, verbose))
+        .or_else(|e| {
+            eprintln!("invalid log settings in {}: {}", path.display(), e);
+            Logger::new(&Settings::default(), verbose)
+        });
+    let logger = match (logger, notify) {
+        (Ok(logger), Some((level, notify))) => Ok(logger.notify(level, notify)),
+        (logger, _) => logger,
+    };
+    if let Err(e) = logger.and_then(Logger::init) {
+        eprintln!("failed to start logger: {}", e);
+    }
+}
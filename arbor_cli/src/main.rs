@@ -1,33 +1,73 @@
-use arbor_core::cmd::Executable;
+use arbor_cli::alias;
+use arbor_cli::logger;
+use arbor_cli::repl::{self, LineError};
 use arbor_core::*;
 
 fn main() {
     let mut cmd_buf = String::with_capacity(1000);
 
     let mut state = EditorState::new(DialogueTreeData::default());
+
+    // verbosity flags come before any command, e.g. arbor_cli -vv check
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let verbose = logger::take_verbose(&mut args);
+    logger::init(verbose, None);
+
+    // backups and journals may be kept apart from the project files, see store::BackupSettings
+    let backups = store::BackupSettings::load(&logging::Settings::path())
+        .and_then(|settings| settings.backups());
+    match backups {
+        Ok(backups) => state.backups = backups,
+        Err(e) => eprintln!("failed to read backup settings: {}", e),
+    }
+
+    // aliases are read once, so a broken settings file is reported here rather than by every
+    // command
+    if let Err(e) = alias::init(&logging::Settings::path()) {
+        eprintln!("failed to read aliases: {}", e);
+    }
+
+    // run a single command given as arguments and exit, e.g. arbor_cli completions bash
+    // the exit status tells scripts what kind of failure occured, see cmd::ErrorCode
+    if !args.is_empty() {
+        // expand any alias first, so the exit status follows the command the alias stands for
+        let (args, result) = match alias::expand(&args) {
+            Ok(expanded) => {
+                let result = repl::run_expanded(&expanded, &mut state);
+                (expanded, result)
+            }
+            Err(e) => (args, Err(LineError::Command(e))),
+        };
+        let code = repl::exit_code(&args, &result);
+        match result {
+            Ok(_r) => {}
+            Err(LineError::Command(f)) => eprintln!("error[{}]: {}", code, f),
+            Err(LineError::Parse(e)) => eprintln!("{}", e),
+        }
+        std::process::exit(code.code());
+    }
+
     loop {
         // print default header
         println!("------------");
         println!("project: {}", state.active.name);
+        if let Some(cursor) = state.active.cursor {
+            println!("node: {}", cursor);
+        }
         println!("------------");
 
         cmd::util::prompt_input(&mut cmd_buf);
 
-        let cmds = shellwords::split(&cmd_buf).unwrap();
-        let cmd_result = cmd::Parse::from_iter_safe(cmds);
-
         // Handle results/errors
-        match cmd_result {
-            Ok(v) => match v.execute(&mut state) {
-                Ok(_r) => println!("success"),
-                // errors from arbor operations
-                Err(f) => {
-                    // pretty print top level error message
-                    println!("\u{1b}[1;31merror:\u{1b}[0m {}", f);
-                }
-            },
+        match repl::run_line(&cmd_buf, &mut state) {
+            Ok(_r) => println!("success"),
+            // errors from arbor operations
+            Err(LineError::Command(f)) => {
+                // pretty print top level error message
+                println!("\u{1b}[1;31merror:\u{1b}[0m {}", f);
+            }
             // errors from CLI interface
-            Err(e) => println!("{}", e),
+            Err(LineError::Parse(e)) => println!("{}", e),
         }
 
         // clear input buffers before starting next input loop
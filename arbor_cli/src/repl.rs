@@ -0,0 +1,95 @@
+use crate::alias;
+use crate::completions;
+use crate::script;
+use arbor_core::cmd::Executable;
+use arbor_core::*;
+
+/// Error from running a line of REPL input
+#[derive(Debug)]
+pub enum LineError {
+    /// The line could not be parsed as a command. Contains the usage or help message
+    Parse(String),
+    /// The command was parsed, but failed when executed
+    Command(anyhow::Error),
+}
+
+impl LineError {
+    /// Classify the error for the process exit status
+    pub fn code(&self) -> cmd::ErrorCode {
+        match self {
+            LineError::Parse(_) => cmd::ErrorCode::Parse,
+            LineError::Command(e) => cmd::ErrorCode::of(e),
+        }
+    }
+}
+
+/// Parse and execute a single line of REPL input
+///
+/// Any output from the command is left in the editor scratchpad, which should be cleared by the
+/// caller before the next line is run
+pub fn run_line(line: &str, state: &mut EditorState) -> std::result::Result<usize, LineError> {
+    let cmds = shellwords::split(line).map_err(|e| LineError::Parse(e.to_string()))?;
+    run_args(&cmds, state)
+}
+
+/// Parse and execute a command that has already been split into arguments. The first argument
+/// is expanded first if it is an alias, see alias::expand
+pub fn run_args(cmds: &[String], state: &mut EditorState) -> std::result::Result<usize, LineError> {
+    let cmds = alias::expand(cmds).map_err(LineError::Command)?;
+    run_expanded(&cmds, state)
+}
+
+/// Parse and execute a command whose aliases have already been expanded, such as the arguments
+/// the CLI was started with
+pub fn run_expanded(
+    cmds: &[String],
+    state: &mut EditorState,
+) -> std::result::Result<usize, LineError> {
+    // commands that are only available from the CLI are parsed separately from the editor
+    // commands
+    let cmd: Box<dyn Executable> = match cmds.first().map(String::as_str) {
+        Some("script") => {
+            let script = script::Script::from_iter_safe(&cmds[1..]);
+            Box::new(script.map_err(|e| LineError::Parse(e.to_string()))?)
+        }
+        Some("completions") => {
+            let completions = completions::Completions::from_iter_safe(&cmds[1..]);
+            Box::new(completions.map_err(|e| LineError::Parse(e.to_string()))?)
+        }
+        Some("alias") => {
+            let alias = alias::Alias::from_iter_safe(&cmds[1..]);
+            Box::new(alias.map_err(|e| LineError::Parse(e.to_string()))?)
+        }
+        Some("complete-keys") => {
+            let complete = completions::CompleteKeys::from_iter_safe(&cmds[1..]);
+            Box::new(complete.map_err(|e| LineError::Parse(e.to_string()))?)
+        }
+        _ => {
+            let parse = cmd::Parse::from_iter_safe(cmds);
+            Box::new(parse.map_err(|e| LineError::Parse(e.to_string()))?)
+        }
+    };
+    cmd.execute(state).map_err(LineError::Command)
+}
+
+/// Exit status for the result of a command run in batch mode. A check that finds problems, or a
+/// test with failed assertions, is reported as a validation failure, even though the command
+/// itself succeeded. cmds are the arguments after alias expansion
+pub fn exit_code(
+    cmds: &[String],
+    result: &std::result::Result<usize, LineError>,
+) -> cmd::ErrorCode {
+    match result {
+        Ok(problems)
+            if *problems > 0
+                && matches!(
+                    cmds.first().map(String::as_str),
+                    Some("check") | Some("test")
+                ) =>
+        {
+            cmd::ErrorCode::Validation
+        }
+        Ok(_) => cmd::ErrorCode::Success,
+        Err(e) => e.code(),
+    }
+}
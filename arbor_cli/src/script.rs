@@ -0,0 +1,252 @@
+use arbor_core::cmd::Executable;
+use arbor_core::editor::Editor;
+use arbor_core::*;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, INT};
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use structopt::clap::AppSettings;
+
+/// Run a rhai script against the active project
+///
+/// Scripts have access to the following functions for querying and modifying the project. Nodes
+/// and edges are referenced by index. A script is a single change in the undo/redo history: if
+/// it fails, none of its modifications are kept, otherwise they are all undone in one step.
+///
+/// ```text
+/// node_count(), edge_count()                   number of nodes/edges in the tree
+/// speaker(node), text(node), choice(edge)      text with all names substituted
+/// outgoing(node)                               array of outgoing edges from a node
+/// source(edge), target(edge)                   nodes connected by an edge
+/// names(), vals()                              maps of the name and value tables
+/// new_node(speaker, text)                      add a node, returns its index
+/// new_edge(source, target, text [, req, eff])  add an edge, returns its index
+/// edit_node(node, speaker, text)               replace the speaker and text of a node
+/// edit_edge(edge, text)                        replace the text of an edge
+/// remove_node(node), remove_edge(edge)         remove a node or edge
+/// new_name(key, name), new_val(key, value)     add to the name or value table
+/// run(command)                                 run any editor command, e.g. run("save")
+/// ```
+///
+/// For example, to add a 'Leave' choice to every node without outgoing edges:
+///
+/// ```text
+/// let end = new_node("narrator", "The end");
+/// for node in 0..node_count() - 1 {
+///     if outgoing(node).is_empty() { new_edge(node, end, "Leave"); }
+/// }
+/// ```
+#[derive(StructOpt, Debug)]
+#[structopt(name = "script", setting = AppSettings::NoBinaryName)]
+pub struct Script {
+    /// Path to the script file to run
+    #[structopt(required_unless = "eval")]
+    file: Option<String>,
+    /// Run a script given directly on the command line rather than from a file
+    #[structopt(short, long)]
+    eval: Option<String>,
+}
+
+impl Executable for Script {
+    fn execute(&self, state: &mut EditorState) -> Result<usize> {
+        let source = match (&self.eval, &self.file) {
+            (Some(source), _) => source.clone(),
+            (None, Some(file)) => std::fs::read_to_string(file)?,
+            (None, None) => return Err(cmd::Error::Generic.into()),
+        };
+
+        // The script runs as a single change, so a failing script leaves the project as it was
+        // and a successful one is undone in one step
+        state.transaction(|state| {
+            // Move the state into an editor shared with the script engine for the duration of
+            // the script, and move it back out afterwards regardless of the result
+            let placeholder = EditorState::new(DialogueTreeData::default());
+            let editor = Rc::new(RefCell::new(Editor::from(std::mem::replace(
+                state,
+                placeholder,
+            ))));
+            let res = engine(&editor).run(&source);
+            let placeholder = Editor::new(DialogueTreeData::default());
+            *state = editor.replace(placeholder).into_state();
+
+            res.map_err(|e| anyhow::anyhow!("{}", e))?;
+            Ok(0)
+        })
+    }
+}
+
+type ScriptResult<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+/// Convert an editor error to a script error, so that it can be caught or reported by the engine
+fn script_err<E: std::fmt::Display>(e: E) -> Box<EvalAltResult> {
+    e.to_string().into()
+}
+
+fn key(key: &str) -> ScriptResult<KeyString> {
+    KeyString::from(key).map_err(script_err)
+}
+
+fn index(i: INT) -> ScriptResult<usize> {
+    if i < 0 {
+        Err(script_err(format!("invalid index {}", i)))
+    } else {
+        Ok(i as usize)
+    }
+}
+
+/// Create a script engine with all editor functions registered
+fn engine(editor: &Rc<RefCell<Editor>>) -> Engine {
+    let mut engine = Engine::new();
+
+    let e = editor.clone();
+    engine.register_fn("node_count", move || {
+        e.borrow().data().tree.nodes().len() as INT
+    });
+    let e = editor.clone();
+    engine.register_fn("edge_count", move || {
+        e.borrow().data().tree.edges().len() as INT
+    });
+
+    let e = editor.clone();
+    engine.register_fn("speaker", move |node: INT| -> ScriptResult<String> {
+        let (speaker, _) = e.borrow().node_text(index(node)?).map_err(script_err)?;
+        Ok(speaker)
+    });
+    let e = editor.clone();
+    engine.register_fn("text", move |node: INT| -> ScriptResult<String> {
+        let (_, text) = e.borrow().node_text(index(node)?).map_err(script_err)?;
+        Ok(text)
+    });
+    let e = editor.clone();
+    engine.register_fn("choice", move |edge: INT| -> ScriptResult<String> {
+        e.borrow().edge_text(index(edge)?).map_err(script_err)
+    });
+    let e = editor.clone();
+    engine.register_fn("outgoing", move |node: INT| -> ScriptResult<Array> {
+        let edges = e
+            .borrow()
+            .outgoing_edges(index(node)?)
+            .map_err(script_err)?;
+        Ok(edges.into_iter().map(|i| Dynamic::from(i as INT)).collect())
+    });
+    let e = editor.clone();
+    engine.register_fn("source", move |edge: INT| -> ScriptResult<INT> {
+        let (source, _) = e.borrow().endpoints(index(edge)?).map_err(script_err)?;
+        Ok(source as INT)
+    });
+    let e = editor.clone();
+    engine.register_fn("target", move |edge: INT| -> ScriptResult<INT> {
+        let (_, target) = e.borrow().endpoints(index(edge)?).map_err(script_err)?;
+        Ok(target as INT)
+    });
+    let e = editor.clone();
+    engine.register_fn("names", move || -> Map {
+        e.borrow()
+            .data()
+            .name_table
+            .iter()
+            .map(|(k, v)| (k.as_str().into(), Dynamic::from(v.to_string())))
+            .collect()
+    });
+    let e = editor.clone();
+    engine.register_fn("vals", move || -> Map {
+        e.borrow()
+            .data()
+            .val_table
+            .iter()
+            .map(|(k, v)| (k.as_str().into(), Dynamic::from(*v as INT)))
+            .collect()
+    });
+
+    let e = editor.clone();
+    engine.register_fn(
+        "new_node",
+        move |speaker: &str, text: &str| -> ScriptResult<INT> {
+            let node = e.borrow_mut().new_node(speaker, text).map_err(script_err)?;
+            Ok(node as INT)
+        },
+    );
+    let e = editor.clone();
+    engine.register_fn(
+        "new_edge",
+        move |source: INT, target: INT, text: &str| -> ScriptResult<INT> {
+            let edge = e
+                .borrow_mut()
+                .new_edge(
+                    index(source)?,
+                    index(target)?,
+                    text,
+                    ReqKind::No,
+                    EffectKind::No,
+                )
+                .map_err(script_err)?;
+            Ok(edge as INT)
+        },
+    );
+    let e = editor.clone();
+    engine.register_fn(
+        "new_edge",
+        move |source: INT, target: INT, text: &str, req: &str, eff: &str| -> ScriptResult<INT> {
+            let req: ReqKind = req.parse().map_err(|_| script_err("invalid requirement"))?;
+            let eff: EffectKind = eff.parse().map_err(|_| script_err("invalid effect"))?;
+            let edge = e
+                .borrow_mut()
+                .new_edge(index(source)?, index(target)?, text, req, eff)
+                .map_err(script_err)?;
+            Ok(edge as INT)
+        },
+    );
+    let e = editor.clone();
+    engine.register_fn(
+        "edit_node",
+        move |node: INT, speaker: &str, text: &str| -> ScriptResult<()> {
+            e.borrow_mut()
+                .edit_node(index(node)?, key(speaker)?, text)
+                .map_err(script_err)
+        },
+    );
+    let e = editor.clone();
+    engine.register_fn(
+        "edit_edge",
+        move |edge: INT, text: &str| -> ScriptResult<()> {
+            let edge = index(edge)?;
+            let choice = e
+                .borrow()
+                .data()
+                .tree
+                .get_edge(edge)
+                .map_err(script_err)?
+                .clone();
+            e.borrow_mut()
+                .edit_edge(edge, text, choice.requirement, choice.effect)
+                .map_err(script_err)
+        },
+    );
+    let e = editor.clone();
+    engine.register_fn("remove_node", move |node: INT| -> ScriptResult<()> {
+        e.borrow_mut().remove_node(index(node)?).map_err(script_err)
+    });
+    let e = editor.clone();
+    engine.register_fn("remove_edge", move |edge: INT| -> ScriptResult<()> {
+        e.borrow_mut().remove_edge(index(edge)?).map_err(script_err)
+    });
+    let e = editor.clone();
+    engine.register_fn("new_name", move |k: &str, name: &str| -> ScriptResult<()> {
+        let name = NameString::from(name).map_err(script_err)?;
+        e.borrow_mut().new_name(key(k)?, name).map_err(script_err)
+    });
+    let e = editor.clone();
+    engine.register_fn("new_val", move |k: &str, value: INT| -> ScriptResult<()> {
+        let value = u32::try_from(value).map_err(script_err)?;
+        e.borrow_mut().new_val(key(k)?, value).map_err(script_err)
+    });
+    let e = editor.clone();
+    engine.register_fn("run", move |command: &str| -> ScriptResult<INT> {
+        let words = shellwords::split(command).map_err(script_err)?;
+        let parsed = cmd::Parse::from_iter_safe(words).map_err(script_err)?;
+        let res = e.borrow_mut().execute(&parsed).map_err(script_err)?;
+        Ok(res as INT)
+    });
+
+    engine
+}
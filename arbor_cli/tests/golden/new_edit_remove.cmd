@@ -0,0 +1,24 @@
+# build a small project from scratch
+new project golden -s
+new name cat Behemoth
+new name dog Sharik
+new val hunger 40
+new node cat "Well, who knows, who knows"
+new node dog "Woof, ::cat::"
+new node cat "Dostoevsky is immortal"
+new edge 0 1 "Bark"
+new edge 0 2 "Ask about ::dog::" -r Greater(hunger,10) -e Sub(hunger,5)
+list
+
+# edits replace the text and names in place
+edit node 1 dog "Grr, ::cat::"
+edit edge 0 "Growl"
+edit name dog Polygraf
+list
+
+# removals, including one that is refused
+remove edge 1
+remove node 2
+remove name dog
+list
+meta
@@ -0,0 +1,17 @@
+new project golden -s
+new name cat Behemoth
+new node cat "Well, who knows, who knows"
+new node cat "Dostoevsky is immortal"
+new node cat "Manuscripts don't burn"
+new edge 0 1 "Dostoevsky's dead"
+new edge 1 2 "Burn it"
+edit node 0 cat "Who knows"
+remove edge 1
+remove node 2
+stats
+
+# rebuild drops the text of removed and edited sections, and clears the history
+rebuild
+stats
+list
+undo
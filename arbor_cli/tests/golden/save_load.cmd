@@ -0,0 +1,17 @@
+new project golden -s
+new name cat Behemoth
+new node cat "Well, who knows, who knows"
+new node cat "Dostoevsky is immortal"
+new edge 0 1 "Dostoevsky's dead"
+save
+
+# unsaved changes are dropped when the saved copy is loaded
+new node cat "Never saved"
+remove edge 0
+list
+load golden
+list
+
+# loading a project that does not exist leaves the active project alone
+load missing
+meta
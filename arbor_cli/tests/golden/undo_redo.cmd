@@ -0,0 +1,25 @@
+new project golden -s
+new name cat Behemoth
+new node cat "Well, who knows, who knows"
+new node cat "Dostoevsky is immortal"
+new edge 0 1 "Dostoevsky's dead"
+edit node 1 cat "Dostoevsky is still immortal"
+list
+
+# undo everything, and one step more than there is history for
+undo
+undo
+undo
+undo
+undo
+undo
+list
+
+# redo part of the way back, then branch off with a new change
+redo
+redo
+redo
+new node cat "A new branch"
+redo
+list
+history list
@@ -0,0 +1,303 @@
+use arbor_cli::alias;
+use arbor_cli::logger;
+use arbor_cli::repl::{self, LineError};
+use arbor_core::*;
+use std::path::PathBuf;
+
+/// Environment variable that makes golden tests write their output to the golden files instead
+/// of comparing against them. Use after an intended change to the output, and review the diff
+const BLESS_VAR: &str = "ARBOR_BLESS";
+
+fn golden_path(file: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(file)
+}
+
+/// Run each line of a command file through the REPL, and return a transcript of the commands
+/// along with their output and result. Blank lines and lines starting with '#' are skipped
+fn run_script(script: &str, state: &mut EditorState) -> String {
+    let mut transcript = String::new();
+    for line in script.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        transcript.push_str(&format!("> {}\n", line));
+        let res = repl::run_line(line, state);
+        transcript.push_str(&state.scratchpad.replace("\r\n", "\n"));
+        match res {
+            Ok(_) => transcript.push_str("success\n"),
+            Err(LineError::Command(e)) => transcript.push_str(&format!("error: {}\n", e)),
+            // only keep the first line of usage errors, the rest is help text
+            Err(LineError::Parse(e)) => {
+                transcript.push_str(e.lines().next().unwrap_or_default());
+                transcript.push('\n');
+            }
+        }
+        state.scratchpad.clear();
+    }
+    transcript
+}
+
+/// Compare output against a golden file, or replace the golden file if BLESS_VAR is set
+fn check_golden(file: &str, actual: &str) {
+    let path = golden_path(file);
+    if std::env::var_os(BLESS_VAR).is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e));
+    assert!(
+        expected == actual,
+        "output does not match {}, set {} to update it\n--- expected\n{}\n--- actual\n{}",
+        path.display(),
+        BLESS_VAR,
+        expected,
+        actual
+    );
+}
+
+/// Run tests/golden/<name>.cmd in a fresh in-memory project, and compare the transcript against
+/// <name>.out and the final project data against <name>.json
+fn golden(name: &str) {
+    let script = std::fs::read_to_string(golden_path(&format!("{}.cmd", name))).unwrap();
+    cmd::util::seed_uids(Some(0));
+    let store = store::MemoryStore::default();
+    let mut state =
+        EditorState::with_store(DialogueTreeData::default(), std::sync::Arc::new(store));
+
+    let transcript = run_script(&script, &mut state);
+    check_golden(&format!("{}.out", name), &transcript);
+
+    // build the snapshot from json values, which sort the keys of the hashmap based tables. The
+    // uid is left out since json values cannot hold a u128, it is checked by the meta command
+    let data = &state.active;
+    let value = serde_json::json!({
+        "name": data.name,
+        "tree": data.tree,
+        "text": data.text,
+        "name_table": data.name_table,
+        "val_table": data.val_table,
+        "bookmarks": data.bookmarks,
+    });
+    let json = serde_json::to_string_pretty(&value).unwrap() + "\n";
+    check_golden(&format!("{}.json", name), &json);
+}
+
+#[test]
+/// Test creating, editing and removing nodes, edges, names and vals
+fn new_edit_remove() {
+    golden("new_edit_remove");
+}
+
+#[test]
+/// Test undoing and redoing a sequence of edits, including undoing past the start of history
+fn undo_redo() {
+    golden("undo_redo");
+}
+
+#[test]
+/// Test saving a project, making changes, and loading the saved copy back
+fn save_load() {
+    golden("save_load");
+}
+
+#[test]
+/// Test rebuilding a project after removals leave unused text in the buffer
+fn rebuild() {
+    golden("rebuild");
+}
+
+#[test]
+/// Test generating completion scripts, and completing keys from a saved project
+fn completions() {
+    let mut state = EditorState::new(DialogueTreeData::default());
+    for shell in ["bash", "zsh", "fish", "powershell"].iter() {
+        let len = repl::run_line(&format!("completions {}", shell), &mut state).unwrap();
+        assert_eq!(len, state.scratchpad.len());
+        assert!(state.scratchpad.contains("bookmark"));
+        if *shell == "bash" {
+            assert!(state.scratchpad.contains("complete -F _arbor_cli_keys"));
+        }
+        state.scratchpad.clear();
+    }
+    assert!(matches!(
+        repl::run_line("completions tcsh", &mut state),
+        Err(LineError::Parse(_))
+    ));
+
+    let dir = std::env::temp_dir().join("arbor_completions_test");
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut state = EditorState::with_store(
+        DialogueTreeData::new("demo"),
+        std::sync::Arc::new(store::FileStore::new(&dir)),
+    );
+    repl::run_line("new name cat Behemoth", &mut state).unwrap();
+    repl::run_line("new name cab Driver", &mut state).unwrap();
+    repl::run_line("new val rus_lit 50", &mut state).unwrap();
+    repl::run_line("save", &mut state).unwrap();
+    state.scratchpad.clear();
+
+    let project = dir.join("demo.tree");
+    let line = format!("complete-keys {} names ca", project.display());
+    assert_eq!(repl::run_line(&line, &mut state).unwrap(), 2);
+    assert_eq!(state.scratchpad, "cab\ncat\n");
+    state.scratchpad.clear();
+    let line = format!("complete-keys {} vals", project.display());
+    assert_eq!(repl::run_line(&line, &mut state).unwrap(), 1);
+    assert_eq!(state.scratchpad, "rus_lit\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+/// Test that batch commands report the kind of failure in their exit status
+fn exit_codes() {
+    let store = store::MemoryStore::default();
+    let mut state =
+        EditorState::with_store(DialogueTreeData::default(), std::sync::Arc::new(store));
+    let mut code = |line: &str| {
+        let args = alias::expand(&shellwords::split(line).unwrap()).unwrap();
+        let result = repl::run_expanded(&args, &mut state);
+        state.scratchpad.clear();
+        repl::exit_code(&args, &result)
+    };
+    assert_eq!(code("check"), cmd::ErrorCode::Success);
+    assert_eq!(code("frobnicate"), cmd::ErrorCode::Parse);
+    assert_eq!(code("new name cat Behemoth"), cmd::ErrorCode::Success);
+    assert_eq!(code("new name cat Behemoth"), cmd::ErrorCode::Validation);
+    // the unused name is a problem found by check
+    assert_eq!(code("check"), cmd::ErrorCode::Validation);
+    assert_eq!(code("load missing"), cmd::ErrorCode::Io);
+    assert_eq!(cmd::ErrorCode::Corruption.code(), 5);
+}
+
+#[test]
+/// Test expanding built in and user defined aliases, and saving them next to other settings
+fn aliases() {
+    let args = |line: &str| shellwords::split(line).unwrap();
+    let mut aliases = alias::Aliases::default();
+    assert_eq!(
+        aliases.expand(&args("nn cat \"Hello\"")).unwrap(),
+        args("new node cat \"Hello\"")
+    );
+    assert_eq!(aliases.expand(&args("list -r")).unwrap(), args("list -r"));
+
+    aliases
+        .user
+        .insert("nn".to_string(), "new node narrator".to_string());
+    aliases
+        .user
+        .insert("bm".to_string(), "bookmark add".to_string());
+    assert_eq!(
+        aliases.expand(&args("nn \"Night falls\"")).unwrap(),
+        args("new node narrator \"Night falls\""),
+        "user aliases replace built in aliases"
+    );
+
+    let path = std::env::temp_dir().join("arbor_alias_settings.json");
+    std::fs::write(&path, r#"{"level": "info"}"#).unwrap();
+    aliases.save(&path).unwrap();
+    assert_eq!(alias::Aliases::load(&path).unwrap(), aliases);
+    let settings = logging::Settings::load(&path).unwrap();
+    assert_eq!(settings.level, "info");
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        alias::Aliases::load(&path).unwrap(),
+        alias::Aliases::default()
+    );
+
+    let mut state = EditorState::new(DialogueTreeData::default());
+    assert!(matches!(
+        repl::run_line("alias -r", &mut state),
+        Err(LineError::Parse(_))
+    ));
+
+    // aliases are loaded once, and a broken settings file leaves only the built in aliases
+    let path = std::env::temp_dir().join("arbor_alias_init.json");
+    std::fs::write(&path, r#"{"aliases": {"chk": "check"}}"#).unwrap();
+    alias::init(&path).unwrap();
+    std::fs::write(&path, "{").unwrap();
+    assert!(alias::init(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+    let expanded = alias::expand(&args("chk")).unwrap();
+    assert_eq!(expanded, args("check"));
+
+    // the exit status follows the command an alias expands to
+    repl::run_line("new name cat Behemoth", &mut state).unwrap();
+    let result = repl::run_expanded(&expanded, &mut state);
+    assert_eq!(
+        repl::exit_code(&expanded, &result),
+        cmd::ErrorCode::Validation
+    );
+    assert!(repl::run_line("ls", &mut state).is_ok());
+}
+
+#[test]
+/// Test that scripts change the project through the editor, and run as a single change that is
+/// undone in one step and leaves the project untouched if it fails
+fn scripts() {
+    let store = store::MemoryStore::default();
+    let mut state = EditorState::with_store(
+        DialogueTreeData::new("script_test"),
+        std::sync::Arc::new(store),
+    );
+    repl::run_line("new name cat Behemoth", &mut state).unwrap();
+    let line = concat!(
+        "script -e 'let a = new_node(\"cat\", \"Well, who knows, who knows\");",
+        "let b = new_node(\"cat\", \"Dostoevsky is immortal\");",
+        "new_edge(a, b, \"Dostoevsky is dead\");'"
+    );
+    repl::run_line(line, &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 2);
+    assert_eq!(state.active.tree.edges().len(), 1);
+    assert_eq!(state.history.position, 2);
+
+    let expected = format!("{:?}", state.active);
+    let line = "script -e 'new_node(\"cat\", \"Manuscripts do not burn\"); remove_node(7);'";
+    assert!(matches!(
+        repl::run_line(line, &mut state),
+        Err(LineError::Command(_))
+    ));
+    assert_eq!(format!("{:?}", state.active), expected);
+    assert_eq!(state.history.position, 2);
+
+    repl::run_line("undo", &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 0);
+    assert!(state.active.name_table.contains_key("cat"));
+    repl::run_line("redo", &mut state).unwrap();
+    assert_eq!(format!("{:?}", state.active), expected);
+}
+
+#[test]
+/// Test verbose flags and log file rotation
+fn logger() {
+    let mut args: Vec<String> = vec!["-vv", "--verbose", "new", "node", "-v"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    assert_eq!(logger::take_verbose(&mut args), 3);
+    assert_eq!(args, ["new", "node", "-v"]);
+
+    let dir = std::env::temp_dir().join("arbor_logging_test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("arbor.log");
+    let mut file = logger::RotatingFile::open(&path, 10, 2).unwrap();
+    for line in ["first", "second", "third", "fourth"].iter() {
+        file.write_line(line).unwrap();
+    }
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "fourth\n");
+    assert_eq!(
+        std::fs::read_to_string(file.rotated_path(1)).unwrap(),
+        "third\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(file.rotated_path(2)).unwrap(),
+        "second\n"
+    );
+    assert!(!file.rotated_path(3).exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
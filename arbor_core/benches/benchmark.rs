@@ -111,10 +111,60 @@ fn stress_undo_redo(c: &mut Criterion) {
     });
 }
 
+/// Build a large project for validation benchmarks, with a single chain of nodes and edges
+fn large_project(nodes: usize) -> EditorState {
+    let mut state = EditorState::new(DialogueTreeData::default());
+    let test_key = KeyString::from("cat").unwrap();
+    let test_name = NameString::from("Behemoth").unwrap();
+
+    cmd::new::Name::new(test_key, test_name)
+        .execute(&mut state)
+        .unwrap();
+
+    for i in 0..nodes {
+        cmd::new::Node::new(test_key.to_string(), format!("test dialogue {}", i))
+            .execute(&mut state)
+            .unwrap();
+        if i > 0 {
+            cmd::new::Edge::new(i - 1, i, format!("test choice {}", i), None, None)
+                .execute(&mut state)
+                .unwrap();
+        }
+    }
+    state
+}
+
+/// Compare validating a 20k node project from scratch against validating it incrementally, after
+/// a single node has been edited since the last validation
+fn validate_large_tree(c: &mut Criterion) {
+    let mut state = large_project(20000);
+    let test_key = KeyString::from("cat").unwrap();
+    cmd::util::validate_tree_incremental(&mut state.active).unwrap();
+
+    // bench part
+    c.bench_function("validate_full", |b| {
+        b.iter(|| {
+            cmd::edit::Node::new(0, test_key, "edited dialogue".to_string())
+                .execute(&mut state)
+                .unwrap();
+            cmd::util::validate_tree(&state.active).unwrap();
+        })
+    });
+    c.bench_function("validate_incremental", |b| {
+        b.iter(|| {
+            cmd::edit::Node::new(0, test_key, "edited dialogue".to_string())
+                .execute(&mut state)
+                .unwrap();
+            cmd::util::validate_tree_incremental(&mut state.active).unwrap();
+        })
+    });
+}
+
 criterion_group!(
     benches,
     quick_parse_node,
     stress_parse_node,
-    stress_undo_redo
+    stress_undo_redo,
+    validate_large_tree
 );
 criterion_main!(benches);
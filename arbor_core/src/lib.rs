@@ -3,13 +3,15 @@ pub use cmd::Executable;
 use derive_new::*;
 use enum_dispatch::*;
 use fixedbitset::FixedBitSet;
-use log::{debug, info, trace};
+use log::{debug, info, trace, warn};
 use rayon::prelude::*;
 use seahash::hash;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 pub use std::collections::{HashMap, VecDeque};
 use std::io;
 use std::io::Write;
+use std::ops::ControlFlow;
 pub use std::ops::Range;
 use structopt::clap::AppSettings;
 pub use structopt::StructOpt;
@@ -17,7 +19,8 @@ use thiserror::Error;
 use tree::{
     // events are fully typed to allow for use with enum_dispatch
     event::{EdgeEdit, EdgeInsert, EdgeRemove, LinkMove, NodeEdit, NodeInsert, NodeRemove},
-    Dfs,
+    EdgeIndex,
+    NodeIndex,
     Tree,
 };
 
@@ -46,7 +49,24 @@ use tree::{
 
 pub static TREE_EXT: &str = ".tree";
 pub static BACKUP_EXT: &str = ".bkp";
+pub static PACKAGE_EXT: &str = ".arbor";
+pub static OLD_EXT: &str = ".old";
+pub static TEMP_EXT: &str = ".tmp";
+pub static JOURNAL_EXT: &str = ".journal";
+pub static SEGMENT_EXT: &str = ".seg";
+pub static LIBRARY_EXT: &str = ".lib";
+pub static THUMBNAIL_EXT: &str = ".thumb.png";
+pub static REPAIR_SUFFIX: &str = "_repaired";
+/// Text used in place of node or edge text that was lost to corruption
+pub static REPAIR_PLACEHOLDER: &str = "[missing text]";
 pub static TOKEN_SEP: &str = "::";
+/// Escaped form of TOKEN_SEP, for writing a literal "::" in node or edge text. Escaped separators
+/// don't start or end a name, and are shown as "::" once the text is parsed
+pub static TOKEN_ESCAPE: &str = "\\::";
+/// Reserved speaker key for narration or system text. Nodes using this speaker do not need an
+/// entry in the name table. If the name table does contain this key, its name is used as the
+/// narrator label, otherwise the speaker name is left empty
+pub static NARRATOR_KEY: &str = "_";
 
 pub const KEY_MAX_LEN: usize = 8;
 pub const NAME_MAX_LEN: usize = 32;
@@ -57,8 +77,8 @@ pub type KeyString = arrayvec::ArrayString<KEY_MAX_LEN>;
 /// Stack allocated string with max length suitable for keys
 pub type NameString = arrayvec::ArrayString<NAME_MAX_LEN>;
 
-/// Struct for storing the 2d position of a node. Used for graph visualization
-#[derive(new, Debug, Serialize, Deserialize, Clone, Copy)]
+/// Struct for storing the 2d position of a node. Used for graph visualization, see LayoutTable
+#[derive(new, Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub struct Position {
     pub x: f32,
     pub y: f32,
@@ -139,16 +159,20 @@ pub mod tree {
         InvalidEdgeLinks,
         #[error("Nodes list full, node list cannot be larger than usize::MAX - 1")]
         NodesFull,
+        #[error("Moving the edge would create a cycle, the new source is below its target")]
+        WouldCreateCycle,
     }
 
     /// Modifying events that occur in the tree. These are returned by methods that cause the given
     /// event. Event structs store the data needed to reconstruct the event after the fact
     pub mod event {
         use super::{Choice, Dialogue, EdgeIndex, NodeIndex, PlacementIndex};
+        use serde::{Deserialize, Serialize};
 
         /// Information about a node insertion such that the event can be reconstructed
         ///
         /// This structure is returned by methods in the tree module that perform an equivalent event
+        #[derive(Serialize, Deserialize, Clone)]
         pub struct NodeInsert {
             pub index: NodeIndex,
             pub node: Dialogue,
@@ -157,6 +181,7 @@ pub mod tree {
         /// Information about a node removal such that the event can be reconstructed
         ///
         /// This structure is returned by methods in the tree module that perform an equivalent event
+        #[derive(Serialize, Deserialize, Clone)]
         pub struct NodeRemove {
             pub index: NodeIndex,
             pub node: Dialogue,
@@ -165,6 +190,7 @@ pub mod tree {
         /// Information about a node edit such that the event can be reconstructed
         ///
         /// This structure is returned by methods in the tree module that perform an equivalent event
+        #[derive(Serialize, Deserialize, Clone)]
         pub struct NodeEdit {
             pub index: NodeIndex,
             pub from: Dialogue,
@@ -174,6 +200,7 @@ pub mod tree {
         /// Information about an edge insertion such that the event can be reconstructed
         ///
         /// This structure is returned by methods in the tree module that perform an equivalent event
+        #[derive(Serialize, Deserialize, Clone)]
         pub struct EdgeInsert {
             pub source: NodeIndex,
             pub target: NodeIndex,
@@ -185,6 +212,7 @@ pub mod tree {
         /// Information about an edge removal such that the event can be reconstructed
         ///
         /// This structure is returned by methods in the tree module that perform an equivalent event
+        #[derive(Serialize, Deserialize, Clone)]
         pub struct EdgeRemove {
             pub source: NodeIndex,
             pub target: NodeIndex,
@@ -196,6 +224,7 @@ pub mod tree {
         /// Information about a edge edit such that the event can be reconstructed
         ///
         /// This structure is returned by methods in the tree module that perform an equivalent event
+        #[derive(Serialize, Deserialize, Clone)]
         pub struct EdgeEdit {
             pub index: EdgeIndex,
             pub from: Choice,
@@ -206,6 +235,7 @@ pub mod tree {
         /// list to another
         ///
         /// This structure is returned by methods in the tree module that perform an equivalent event
+        #[derive(Serialize, Deserialize, Clone)]
         pub struct LinkMove {
             pub source: NodeIndex,
             pub index: EdgeIndex,
@@ -479,6 +509,10 @@ pub mod tree {
 
         /// Edit the contents in an existing node and return the old contents.
         ///
+        /// If the text of the node changes and the new contents keep the line id of the node, the
+        /// revision of the line is increased, see LineId. Contents with a different line id, such
+        /// as the old contents of the node when an edit is undone, are written as given
+        ///
         /// # Errors
         ///
         /// If the index is invalid, a corresponding error will be returned with no modification to
@@ -494,6 +528,10 @@ pub mod tree {
             let old_node_value = *node;
 
             *node = new_node;
+            if node.line == old_node_value.line && node.section.hash != old_node_value.section.hash
+            {
+                node.line.revision += 1;
+            }
 
             // Create and return event information
             let event = event::NodeEdit {
@@ -511,7 +549,7 @@ pub mod tree {
         ///
         /// If the index is invalid, or if an edge currently uses the node as a source or target,
         /// an error is returned with no modification to the tree
-        pub fn remove_node(&mut self, index: NodeIndex) -> Result<event::NodeInsert> {
+        pub fn remove_node(&mut self, index: NodeIndex) -> Result<event::NodeRemove> {
             info!("Remove node {}", index);
 
             trace!("check that node index is valid");
@@ -547,7 +585,7 @@ pub mod tree {
                     }
                 }
                 // Create and return event information
-                let event = event::NodeInsert {
+                let event = event::NodeRemove {
                     index,
                     node: removed_node,
                 };
@@ -579,17 +617,21 @@ pub mod tree {
 
             info!("swap added node with node at the clamped desired index");
             self.nodes.swap(swap_index, clamped_desired);
+            self.node_links.swap(swap_index, clamped_desired);
 
             info!("resolve any edge sources/targets that have changed due to the swap");
-
             for source in self.edge_sources.as_mut_slice() {
                 if *source == swap_index {
-                    *source = clamped_desired
+                    *source = clamped_desired;
+                } else if *source == clamped_desired {
+                    *source = swap_index;
                 }
             }
             for target in self.edge_targets.as_mut_slice() {
                 if *target == swap_index {
-                    *target = clamped_desired
+                    *target = clamped_desired;
+                } else if *target == clamped_desired {
+                    *target = swap_index;
                 }
             }
 
@@ -694,7 +736,7 @@ pub mod tree {
                 .ok_or(tree::Error::InvalidNodeIndex)?;
 
             trace!("push new edge to the edges, edge_links, and edge_targets list");
-            self.edges.push(edge);
+            self.edges.push(edge.clone());
             self.edge_sources.push(source);
             self.edge_targets.push(target);
             self.edge_links.push(EdgeIndex::end());
@@ -723,6 +765,8 @@ pub mod tree {
         /// Edit the choice in an existing edge. The source or target node cannot be modified, the
         /// edge will have to be deleted and readded
         ///
+        /// The revision of the line is increased if the text changes, as in edit_node
+        ///
         /// # Errors
         ///
         /// If the index is invalid, a corresponding error will be returned
@@ -738,13 +782,15 @@ pub mod tree {
                 .get_mut(index)
                 .ok_or(tree::Error::InvalidEdgeIndex)?;
 
-            let old_choice = *choice;
-            *choice = new_choice;
+            let old_choice = std::mem::replace(choice, new_choice);
+            if choice.line == old_choice.line && choice.section.hash != old_choice.section.hash {
+                choice.line.revision += 1;
+            }
 
             let event = event::EdgeEdit {
                 index,
                 from: old_choice,
-                to: new_choice,
+                to: choice.clone(),
             };
             Ok(event)
         }
@@ -757,7 +803,7 @@ pub mod tree {
         /// # Errors
         ///
         /// If the index is invalid, an error will be returned without modifying the tree
-        pub fn remove_edge(&mut self, index: EdgeIndex) -> Result<event::EdgeInsert> {
+        pub fn remove_edge(&mut self, index: EdgeIndex) -> Result<event::EdgeRemove> {
             trace!("check validity of edge index");
             self.edges.get(index).ok_or(tree::Error::InvalidEdgeIndex)?;
 
@@ -810,7 +856,7 @@ pub mod tree {
                 }
             }
 
-            let event = event::EdgeInsert {
+            let event = event::EdgeRemove {
                 source,
                 target,
                 index,
@@ -842,8 +888,8 @@ pub mod tree {
                 source, target, desired_index, desired_placement
             );
 
-            // clamp index by nodes list length
-            let clamped_desired_index = std::cmp::min(desired_index, self.nodes.len());
+            // clamp index by edges list length
+            let clamped_desired_index = std::cmp::min(desired_index, self.edges.len());
             debug!(
                 "clamped index {} to {}",
                 desired_index, clamped_desired_index
@@ -890,6 +936,44 @@ pub mod tree {
             Ok(event)
         }
 
+        /// Move an edge to a different source node, keeping its index, target, and choice. The
+        /// edge is placed at the end of the new source's outgoing edges. This moves the whole
+        /// subtree below the edge's target under the new source
+        ///
+        /// Returns the removal of the edge from its old source and its insertion at the new
+        /// source, which together undo or redo the move
+        ///
+        /// # Errors
+        ///
+        /// Error if any index is invalid, or if the new source can be reached from the edge's
+        /// target, as the move would then create a cycle. The tree is not modified on error
+        pub fn reparent_edge(
+            &mut self,
+            index: EdgeIndex,
+            new_source: NodeIndex,
+        ) -> Result<(event::EdgeRemove, event::EdgeInsert)> {
+            info!("Reparent edge {} to source {}", index, new_source);
+            self.get_node(new_source)?;
+            let target = self.target_of(index)?;
+
+            trace!("check that the new source is not in the subtree being moved");
+            let cycle = walk(self, target, |node_index, _, _| {
+                if node_index == new_source {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(Visit::Children)
+                }
+            })?;
+            if cycle.is_some() {
+                return Err(tree::Error::WouldCreateCycle.into());
+            }
+
+            let removed = self.remove_edge(index)?;
+            let edge = removed.edge.clone();
+            let inserted = self.insert_edge(new_source, target, edge, index, usize::MAX)?;
+            Ok((removed, inserted))
+        }
+
         /// Edit the link order of an edge. This modifies where an edge appears in the linked list
         /// of outgoing edges from its source node. This is useful if a given edge needs to appear
         /// in a specific ordering when accessing the outgoing edges of a node
@@ -1024,7 +1108,7 @@ pub mod tree {
         /// ```
         /// # use arbor_core::*;
         /// # use arbor_core::tree::*;
-        /// # let dialogue = Dialogue::new(Section::new([0, 0], 0), Position::new(0.0, 0.0));
+        /// # let dialogue = Dialogue::new(Section::new([0, 0], 0));
         /// # let choice = Choice::new(Section::new([0,0],0), ReqKind::No, EffectKind::No);
         /// let mut tree = Tree::with_capacity(10, 10);
         /// // add two nodes with dummy dialogue values
@@ -1035,7 +1119,7 @@ pub mod tree {
         /// let first_edge_event: event::EdgeInsert = tree.add_edge(
         ///     first_node_event.index,
         ///     second_node_event.index,
-        ///     choice).unwrap();
+        ///     choice.clone()).unwrap();
         /// let second_edge_event: event::EdgeInsert = tree.add_edge(
         ///     first_node_event.index,
         ///     second_node_event.index,
@@ -1055,6 +1139,26 @@ pub mod tree {
                 next: self.node_links[index],
             })
         }
+
+        /// Check if a node has no outgoing edges, meaning the dialogue ends at that node
+        ///
+        /// # Errors
+        ///
+        /// Error if index is invalid
+        #[inline]
+        pub fn is_leaf(&self, index: NodeIndex) -> Result<bool> {
+            self.nodes.get(index).ok_or(tree::Error::InvalidNodeIndex)?;
+            Ok(self.node_links[index] == EdgeIndex::end())
+        }
+
+        /// Iterate over the indices of all nodes with no outgoing edges, in index order
+        pub fn terminal_nodes(&self) -> impl Iterator<Item = NodeIndex> + '_ {
+            self.node_links
+                .iter()
+                .enumerate()
+                .filter(|(_, link)| **link == EdgeIndex::end())
+                .map(|(index, _)| index)
+        }
     }
 
     /// Modified from https://docs.rs/petgraph/0.5.1/src/petgraph/visit/mod.rs.html#582
@@ -1080,6 +1184,9 @@ pub mod tree {
 
     /// Depth first search tree walker
     /// Adapted from https://docs.rs/petgraph/0.5.1/src/petgraph/visit/traversal.rs.html#37
+    ///
+    /// See walk for a depth first search that tracks the depth of each node and can be pruned or
+    /// stopped early
     pub struct Dfs {
         /// stack of nodes to visit
         pub stack: Vec<NodeIndex>,
@@ -1119,6 +1226,88 @@ pub mod tree {
             Ok(None)
         }
     }
+
+    /// What a walk does after visiting a node
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Visit {
+        /// Walk into the nodes that the visited node leads to
+        Children,
+        /// Skip the nodes that the visited node leads to, unless they are reached another way
+        Prune,
+    }
+
+    /// Walk the nodes reachable from root in depth first order, visiting each node once
+    ///
+    /// The visitor is called with the index of each node, its depth in the walk, and the edge
+    /// followed to reach it, which is None for the root. Returning ControlFlow::Continue decides
+    /// whether the walk goes into the node's children, and returning ControlFlow::Break stops the
+    /// walk early. Returns the break value if the walk was stopped.
+    ///
+    /// # Errors
+    ///
+    /// Error if root or any index in the tree is invalid
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use arbor_core::*;
+    /// # use arbor_core::tree::*;
+    /// # use std::ops::ControlFlow;
+    /// # let dialogue = Dialogue::new(Section::new([0, 0], 0));
+    /// # let choice = Choice::new(Section::new([0,0],0), ReqKind::No, EffectKind::No);
+    /// let mut tree = Tree::with_capacity(10, 10);
+    /// for _ in 0..3 {
+    ///     tree.add_node(dialogue).unwrap();
+    /// }
+    /// tree.add_edge(0, 1, choice.clone()).unwrap();
+    /// tree.add_edge(1, 2, choice).unwrap();
+    ///
+    /// // find the depth of node 2
+    /// let depth = walk(&tree, 0, |node, depth, _via| match node {
+    ///     2 => ControlFlow::Break(depth),
+    ///     _ => ControlFlow::Continue(Visit::Children),
+    /// });
+    /// assert_eq!(depth.unwrap(), Some(2));
+    /// ```
+    pub fn walk<B, F>(tree: &Tree, root: NodeIndex, visit: F) -> Result<Option<B>>
+    where
+        F: FnMut(NodeIndex, usize, Option<EdgeIndex>) -> ControlFlow<B, Visit>,
+    {
+        walk_from(tree, std::iter::once(root), visit)
+    }
+
+    /// Same as walk, but starts a new walk from each root in order. Roots visited by an earlier
+    /// walk are skipped, so each node is still visited at most once. Walking from every node
+    /// index visits the whole tree, including nodes that cannot be reached from the first root
+    pub fn walk_from<B, F, R>(tree: &Tree, roots: R, mut visit: F) -> Result<Option<B>>
+    where
+        F: FnMut(NodeIndex, usize, Option<EdgeIndex>) -> ControlFlow<B, Visit>,
+        R: IntoIterator<Item = NodeIndex>,
+    {
+        let mut stack = Vec::with_capacity(tree.nodes.len());
+        let mut discovered = FixedBitSet::with_capacity(tree.nodes.len());
+        for root in roots {
+            tree.get_node(root)?;
+            stack.push((root, 0, None));
+            while let Some((node_index, depth, via)) = stack.pop() {
+                if !discovered.visit(node_index) {
+                    continue;
+                }
+                match visit(node_index, depth, via) {
+                    ControlFlow::Break(value) => return Ok(Some(value)),
+                    ControlFlow::Continue(Visit::Prune) => continue,
+                    ControlFlow::Continue(Visit::Children) => {}
+                }
+                for edge_index in tree.outgoing_from_index(node_index)? {
+                    let target_node_index = tree.target_of(edge_index)?;
+                    if !discovered.is_visited(&target_node_index) {
+                        stack.push((target_node_index, depth + 1, Some(edge_index)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
 }
 
 /// Typedef representing the hashmap type used to store names in dialogue trees. These may be
@@ -1129,6 +1318,7 @@ pub type NameTable = HashMap<KeyString, NameString>;
 ///
 /// This structure should be returned by methods that perform an equivalent transformation to a
 /// NameTable
+#[derive(Serialize, Deserialize, Clone)]
 pub struct NameTableInsert {
     pub key: KeyString,
     pub name: NameString,
@@ -1138,6 +1328,7 @@ pub struct NameTableInsert {
 ///
 /// This structure should be returned by methods that perform an equivalent transformation to a
 /// NameTable
+#[derive(Serialize, Deserialize, Clone)]
 pub struct NameTableRemove {
     pub key: KeyString,
     pub name: NameString,
@@ -1147,6 +1338,7 @@ pub struct NameTableRemove {
 ///
 /// This structure should be returned by methods that perform an equivalent transformation to a
 /// NameTable
+#[derive(Serialize, Deserialize, Clone)]
 pub struct NameTableEdit {
     pub key: KeyString,
     pub from: NameString,
@@ -1157,11 +1349,51 @@ pub struct NameTableEdit {
 /// requirements or effects from player choices.
 pub type ValTable = HashMap<KeyString, u32>;
 
+/// How long a value keeps changes made during a playthrough, selected by a prefix on the value's
+/// key. Keys without a prefix are global, e.g. "gold", "c.trust", "t.rolled"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValScope {
+    /// Kept for the whole playthrough
+    Global,
+    /// Reset to its initial value when a conversation starts or ends, prefixed with "c.". Calls
+    /// start a new conversation, and the caller's values are restored when the call returns
+    Conversation,
+    /// Reset to its initial value whenever a choice is made, prefixed with "t.". A value set by
+    /// an effect is only kept until the next choice
+    Temporary,
+}
+
+impl ValScope {
+    /// Separates the scope prefix from the rest of the key
+    pub const SEPARATOR: char = '.';
+
+    /// Get the scope of a value from its key
+    ///
+    /// # Errors
+    /// Error if the key has a scope separator, but the prefix is not a known scope or nothing
+    /// follows the separator
+    pub fn of(key: &str) -> Result<ValScope> {
+        match key.split_once(ValScope::SEPARATOR) {
+            None => Ok(ValScope::Global),
+            Some(("c", rest)) if !rest.is_empty() => Ok(ValScope::Conversation),
+            Some(("t", rest)) if !rest.is_empty() => Ok(ValScope::Temporary),
+            _ => Err(cmd::Error::UnknownValScope(key.to_string()).into()),
+        }
+    }
+
+    /// Get a key without its scope prefix
+    pub fn unscoped(key: &str) -> &str {
+        key.split_once(ValScope::SEPARATOR)
+            .map_or(key, |(_, rest)| rest)
+    }
+}
+
 /// Information about an insertion (an addition or removal) to the ValTable such that the event
 /// can be reconstructed later
 ///
 /// This structure should be returned by methods that perform an equivalent transformation to a
 /// ValTable
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ValTableInsert {
     pub key: KeyString,
     pub value: u32,
@@ -1171,6 +1403,7 @@ pub struct ValTableInsert {
 ///
 /// This structure should be returned by methods that perform an equivalent transformation to a
 /// ValTable
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ValTableRemove {
     pub key: KeyString,
     pub val: u32,
@@ -1180,12 +1413,233 @@ pub struct ValTableRemove {
 ///
 /// This structure should be returned by methods that perform an equivalent transformation to a
 /// ValTable
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ValTableEdit {
     pub key: KeyString,
     pub from: u32,
     pub to: u32,
 }
 
+/// Typedef representing the hashmap type used to store bookmarks in dialogue trees. Bookmarks give
+/// nodes a name that can be used to find them again, and are kept pointing at the same node as
+/// other nodes are removed or inserted.
+pub type BookmarkTable = HashMap<KeyString, NodeIndex>;
+
+/// Information about an insertion to the BookmarkTable such that the event can be reconstructed
+/// later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BookmarkInsert {
+    pub key: KeyString,
+    pub index: NodeIndex,
+}
+
+/// Information about a removal from the BookmarkTable such that the event can be reconstructed
+/// later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BookmarkRemove {
+    pub key: KeyString,
+    pub index: NodeIndex,
+}
+
+/// Typedef representing the hashmap type used to store the stage directions of nodes in dialogue
+/// trees. Like bookmarks, directions are kept with the same node as other nodes are removed or
+/// inserted. Nodes without directions have no entry.
+pub type DirectionTable = HashMap<NodeIndex, Vec<Direction>>;
+
+/// Information about a change to the directions of a node such that the event can be
+/// reconstructed later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DirectionsEdit {
+    pub index: NodeIndex,
+    pub from: Vec<Direction>,
+    pub to: Vec<Direction>,
+}
+
+/// Typedef representing the hashmap type used to store the on-enter effects of nodes in dialogue
+/// trees. On-enter effects are applied whenever the node is shown, regardless of which choice led
+/// there. Like directions, they are kept with the same node as other nodes are removed or
+/// inserted. Nodes without on-enter effects have no entry.
+pub type OnEnterTable = HashMap<NodeIndex, Vec<EffectKind>>;
+
+/// Information about a change to the on-enter effects of a node such that the event can be
+/// reconstructed later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OnEnterEdit {
+    pub index: NodeIndex,
+    pub from: Vec<EffectKind>,
+    pub to: Vec<EffectKind>,
+}
+
+/// Typedef representing the hashmap type used to store the editor positions of nodes in dialogue
+/// trees, keyed by the stable id of each node's line, see LineId. Positions are only used for
+/// graph visualization, so they are kept out of the tree and stripped from runtime packages. Since
+/// line ids don't change when other nodes are removed or inserted, positions stay with their node
+/// without any bookkeeping. Nodes without an entry are at the origin. Use
+/// DialogueTreeData::position and set_position to look up positions by node index.
+pub type LayoutTable = HashMap<u32, Position>;
+
+/// Information about a change to the position of a node such that the event can be
+/// reconstructed later. None if the node has no entry in the layout table
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LayoutEdit {
+    pub index: NodeIndex,
+    pub from: Option<Position>,
+    pub to: Option<Position>,
+}
+
+/// A project level rule that moves a playthrough to the target node as soon as the requirement is
+/// met, checked at every node before the choices are presented. For example, jumping to an arrest
+/// scene once suspicion is greater than 10
+#[derive(new, Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Interrupt {
+    pub requirement: ReqKind,
+    pub target: NodeIndex,
+}
+
+/// Information about an insertion into the interrupt list such that the event can be
+/// reconstructed later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InterruptInsert {
+    pub position: usize,
+    pub interrupt: Interrupt,
+}
+
+/// Information about a removal from the interrupt list such that the event can be reconstructed
+/// later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct InterruptRemove {
+    pub position: usize,
+    pub interrupt: Interrupt,
+}
+
+/// Typedef representing the hashmap type used to store the assets used by a dialogue tree, such as
+/// portraits, voice lines, and locale files. Paths are stored relative to the asset root so that
+/// projects can be moved between machines, see DialogueTreeData::asset_root
+pub type AssetTable = HashMap<KeyString, String>;
+
+/// Information about an insertion to the AssetTable such that the event can be reconstructed later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AssetInsert {
+    pub key: KeyString,
+    pub path: String,
+}
+
+/// Information about a removal from the AssetTable such that the event can be reconstructed later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AssetRemove {
+    pub key: KeyString,
+    pub path: String,
+}
+
+/// Information about a change to the asset root such that the event can be reconstructed later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AssetRootEdit {
+    pub from: String,
+    pub to: String,
+}
+
+/// Information about a change to the choice policy such that the event can be reconstructed
+/// later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ChoicePolicyEdit {
+    pub from: ChoicePolicy,
+    pub to: ChoicePolicy,
+}
+
+/// Information about a change to the naming rules such that the event can be reconstructed later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct NamingRulesEdit {
+    pub from: NamingRules,
+    pub to: NamingRules,
+}
+
+/// Typedef representing the map type used to store the starting value presets of dialogue trees,
+/// by preset name. A preset replaces some of the names and values of the project when a
+/// playthrough starts, such as a "chapter 3 save" or "max stats" profile. Sorted so that presets
+/// are listed in a stable order
+pub type PresetTable = std::collections::BTreeMap<String, runner::Assumptions>;
+
+/// Information about a change to a preset such that the event can be reconstructed later. None if
+/// the preset did not exist before or after the change
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PresetEdit {
+    pub name: String,
+    pub from: Option<runner::Assumptions>,
+    pub to: Option<runner::Assumptions>,
+}
+
+/// Names and values shared between projects, such as a studio's character roster. Libraries are
+/// stored as JSON files in the project store and referenced by name from the projects that use
+/// them, see DialogueTreeData::libraries. Library entries are merged into the name and val tables
+/// when a project is loaded, and left out of the project file when it is saved
+///
+/// ```text
+/// {"names": {"cat": "Behemoth"}, "vals": {"rus_lit": 50}}
+/// ```
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct Library {
+    #[serde(default)]
+    pub names: NameTable,
+    #[serde(default)]
+    pub vals: ValTable,
+}
+
+/// Information about a library being referenced by a project such that the event can be
+/// reconstructed later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LibraryInsert {
+    pub position: usize,
+    pub name: String,
+}
+
+/// Information about a library no longer being referenced by a project such that the event can be
+/// reconstructed later
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LibraryRemove {
+    pub position: usize,
+    pub name: String,
+}
+
+/// Information about text overwritten in place in the text buffer such that the event can be
+/// reconstructed later. Both strings have the same length and cover the same range of the buffer
+/// starting at start, see cmd::util::rewrite_section
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TextRewrite {
+    pub start: usize,
+    pub from: String,
+    pub to: String,
+}
+
+/// 128 bit unique identifier for a project. Stored in the dialogue tree, and used to associate
+/// files and other metadata with the correct project in the case that multiple projects exist with
+/// the same name. Displayed and parsed as 32 hex digits
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Uid(pub u128);
+
+impl Uid {
+    /// The low bits of the uid, for use where a usize id is expected, such as the return value of
+    /// commands
+    pub fn low(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl std::fmt::Display for Uid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+impl std::str::FromStr for Uid {
+    type Err = cmd::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        u128::from_str_radix(s, 16)
+            .map(Uid)
+            .map_err(|_| cmd::Error::InvalidUid(s.to_string()))
+    }
+}
+
 /// Top level data structure for storing a dialogue tree
 ///
 /// This struct contains the tree representing the dialogue nodes and player actions connecting
@@ -1193,12 +1647,57 @@ pub struct ValTableEdit {
 /// variables such as player names, conditionals, etc.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DialogueTreeData {
-    pub uid: usize,
+    pub uid: Uid,
     pub tree: Tree,
     pub text: String,
     pub name_table: NameTable,
     pub val_table: ValTable,
     pub name: String,
+    #[serde(default)]
+    pub bookmarks: BookmarkTable,
+    #[serde(default)]
+    pub directions: DirectionTable,
+    /// Editor positions of nodes, not included in runtime packages
+    #[serde(default)]
+    pub layout: LayoutTable,
+    /// Interrupts in priority order, the first one with a met requirement is taken
+    #[serde(default)]
+    pub interrupts: Vec<Interrupt>,
+    /// Directory that asset paths are relative to. A relative root is relative to the directory
+    /// of the project file, so an empty root is the project directory itself
+    #[serde(default)]
+    pub asset_root: String,
+    #[serde(default)]
+    pub assets: AssetTable,
+    /// Names of the shared libraries used by the project, in priority order. Entries of earlier
+    /// libraries are merged first, see Library
+    #[serde(default)]
+    pub libraries: Vec<String>,
+    /// Last line id given to a node or edge. Ids are never reused, see LineId
+    #[serde(default)]
+    pub next_line: u32,
+    /// How runners and readers order and show choices
+    #[serde(default)]
+    pub choice_policy: ChoicePolicy,
+    /// Named starting values for playthroughs, see runner::Runner::with_assumptions
+    #[serde(default)]
+    pub presets: PresetTable,
+    /// Effects applied when a node is shown, see runner::Runner
+    #[serde(default)]
+    pub on_enter: OnEnterTable,
+    /// Conventions for key names and tags, reported by the check command. Editor only
+    #[serde(default)]
+    pub naming: NamingRules,
+    /// Sections whose hashes have been verified, not saved with the project
+    #[serde(skip)]
+    pub hash_cache: cmd::util::HashCache,
+    /// Index of the text for find and replace, not saved with the project
+    #[serde(skip)]
+    pub search_index: cmd::util::SearchIndex,
+    /// The current node, used as a starting point for navigating the tree. Kept on the same node
+    /// as nodes are inserted and removed, not saved with the project
+    #[serde(skip)]
+    pub cursor: Option<NodeIndex>,
 }
 
 impl DialogueTreeData {
@@ -1210,6 +1709,21 @@ impl DialogueTreeData {
             name_table: HashMap::default(),
             val_table: HashMap::default(),
             name: String::new(),
+            bookmarks: HashMap::default(),
+            directions: HashMap::default(),
+            layout: HashMap::default(),
+            interrupts: Vec::new(),
+            asset_root: String::new(),
+            assets: HashMap::default(),
+            libraries: Vec::new(),
+            next_line: 0,
+            choice_policy: ChoicePolicy::default(),
+            presets: PresetTable::new(),
+            on_enter: HashMap::default(),
+            naming: NamingRules::default(),
+            hash_cache: cmd::util::HashCache::default(),
+            search_index: cmd::util::SearchIndex::default(),
+            cursor: None,
         }
     }
     pub fn new(name: &str) -> Self {
@@ -1220,16 +1734,289 @@ impl DialogueTreeData {
             name_table: HashMap::default(),
             val_table: HashMap::default(),
             name: String::from(name),
+            bookmarks: HashMap::default(),
+            directions: HashMap::default(),
+            layout: HashMap::default(),
+            interrupts: Vec::new(),
+            asset_root: String::new(),
+            assets: HashMap::default(),
+            libraries: Vec::new(),
+            next_line: 0,
+            choice_policy: ChoicePolicy::default(),
+            presets: PresetTable::new(),
+            on_enter: HashMap::default(),
+            naming: NamingRules::default(),
+            hash_cache: cmd::util::HashCache::default(),
+            search_index: cmd::util::SearchIndex::default(),
+            cursor: None,
+        }
+    }
+
+    /// Get a starting value preset by name
+    ///
+    /// # Errors
+    ///
+    /// Error with PresetNotExists if the project has no preset with the name
+    pub fn preset(&self, name: &str) -> Result<&runner::Assumptions> {
+        self.presets
+            .get(name)
+            .ok_or_else(|| cmd::Error::PresetNotExists(name.to_string()).into())
+    }
+
+    /// Take the next unused line id, for a new node or edge. Commands that create nodes and edges
+    /// give each one a line id before adding it to the tree
+    pub fn new_line(&mut self) -> LineId {
+        self.next_line += 1;
+        LineId {
+            id: self.next_line,
+            revision: 0,
+        }
+    }
+
+    /// Mark a line id as used, so that it is not given out again. Redoing the insert of a node or
+    /// edge marks its line, which keeps ids unique when events are replayed onto a project that
+    /// never took them, such as from a journal
+    pub fn use_line(&mut self, line: LineId) {
+        self.next_line = self.next_line.max(line.id);
+    }
+
+    /// Remove a node from the tree, keeping the cursor, bookmarks, directions, on-enter effects,
+    /// and interrupts with the same nodes. The cursor is cleared if it is on the removed node. The
+    /// position of the removed node is dropped, so commands record a LayoutEdit before removing a
+    /// node to restore it on undo. See Tree::remove_node
+    ///
+    /// # Errors
+    ///
+    /// Error if the node is bookmarked, has directions or on-enter effects, or is the target of an
+    /// interrupt, or if the node cannot be removed from the tree
+    pub fn remove_node(&mut self, index: NodeIndex) -> Result<NodeRemove> {
+        if let Some((key, _)) = self.bookmarks.iter().find(|(_, i)| **i == index) {
+            return Err(cmd::Error::NodeBookmarked(*key).into());
+        }
+        anyhow::ensure!(
+            !self.directions.contains_key(&index),
+            cmd::Error::NodeHasDirections(index)
+        );
+        anyhow::ensure!(
+            !self.on_enter.contains_key(&index),
+            cmd::Error::NodeHasOnEnter(index)
+        );
+        if let Some(position) = self.interrupts.iter().position(|i| i.target == index) {
+            return Err(cmd::Error::NodeInterruptTarget(position).into());
+        }
+        let swapped_index = self.tree.nodes().len().saturating_sub(1);
+        let event = self.tree.remove_node(index)?;
+        self.layout.remove(&event.node.line.id);
+        // the last node is swapped into the removed index
+        for bookmark in self.bookmarks.values_mut() {
+            if *bookmark == swapped_index {
+                *bookmark = index;
+            }
+        }
+        if self.cursor == Some(index) {
+            self.cursor = None;
+        } else if self.cursor == Some(swapped_index) {
+            self.cursor = Some(index);
+        }
+        if let Some(directions) = self.directions.remove(&swapped_index) {
+            self.directions.insert(index, directions);
+        }
+        if let Some(effects) = self.on_enter.remove(&swapped_index) {
+            self.on_enter.insert(index, effects);
+        }
+        for interrupt in self.interrupts.iter_mut() {
+            if interrupt.target == swapped_index {
+                interrupt.target = index;
+            }
+        }
+        Ok(event)
+    }
+
+    /// Insert a node into the tree, keeping the cursor, bookmarks, directions, on-enter effects,
+    /// and interrupts with the same nodes. See Tree::insert_node
+    pub fn insert_node(&mut self, node: Dialogue, index: NodeIndex) -> Result<NodeInsert> {
+        let event = self.tree.insert_node(node, index)?;
+        // the node previously at the insertion index is swapped to the end of the list
+        let moved_index = self.tree.nodes().len() - 1;
+        if event.index != moved_index {
+            for bookmark in self.bookmarks.values_mut() {
+                if *bookmark == event.index {
+                    *bookmark = moved_index;
+                }
+            }
+            if self.cursor == Some(event.index) {
+                self.cursor = Some(moved_index);
+            }
+            if let Some(directions) = self.directions.remove(&event.index) {
+                self.directions.insert(moved_index, directions);
+            }
+            if let Some(effects) = self.on_enter.remove(&event.index) {
+                self.on_enter.insert(moved_index, effects);
+            }
+            for interrupt in self.interrupts.iter_mut() {
+                if interrupt.target == event.index {
+                    interrupt.target = moved_index;
+                }
+            }
+        }
+        Ok(event)
+    }
+
+    /// Replace the directions of a node. An empty list removes the node's entry from the
+    /// direction table
+    pub fn set_directions(
+        &mut self,
+        index: NodeIndex,
+        directions: Vec<Direction>,
+    ) -> Result<DirectionsEdit> {
+        self.tree.get_node(index)?;
+        let from = if directions.is_empty() {
+            self.directions.remove(&index)
+        } else {
+            self.directions.insert(index, directions.clone())
+        };
+        Ok(DirectionsEdit {
+            index,
+            from: from.unwrap_or_default(),
+            to: directions,
+        })
+    }
+
+    /// Replace the on-enter effects of a node. An empty list removes the node's entry from the
+    /// on-enter table
+    pub fn set_on_enter(
+        &mut self,
+        index: NodeIndex,
+        effects: Vec<EffectKind>,
+    ) -> Result<OnEnterEdit> {
+        self.tree.get_node(index)?;
+        let from = if effects.is_empty() {
+            self.on_enter.remove(&index)
+        } else {
+            self.on_enter.insert(index, effects.clone())
+        };
+        Ok(OnEnterEdit {
+            index,
+            from: from.unwrap_or_default(),
+            to: effects,
+        })
+    }
+
+    /// Editor position of a node, nodes without an entry in the layout table are at the origin
+    pub fn position(&self, index: NodeIndex) -> Position {
+        self.placement(index).unwrap_or_default()
+    }
+
+    /// Entry of a node in the layout table, None if the node has not been placed or does not
+    /// exist
+    pub fn placement(&self, index: NodeIndex) -> Option<Position> {
+        let node = self.tree.get_node(index).ok()?;
+        self.layout.get(&node.line.id).copied()
+    }
+
+    /// Replace the position of a node. None removes the node's entry from the layout table
+    pub fn set_position(&mut self, index: NodeIndex, pos: Option<Position>) -> Result<LayoutEdit> {
+        let id = self.tree.get_node(index)?.line.id;
+        let from = match pos {
+            Some(pos) => self.layout.insert(id, pos),
+            None => self.layout.remove(&id),
+        };
+        Ok(LayoutEdit {
+            index,
+            from,
+            to: pos,
+        })
+    }
+
+    /// Approximate number of bytes allocated for each part of the project. History is not part of
+    /// the project data, see EditorState::memory_stats
+    pub fn memory_stats(&self) -> MemoryStats {
+        let tree = &self.tree;
+        MemoryStats {
+            text: self.text.capacity(),
+            nodes: vec_bytes(&tree.nodes),
+            edges: vec_bytes(&tree.edges),
+            links: vec_bytes(&tree.node_links)
+                + vec_bytes(&tree.edge_links)
+                + vec_bytes(&tree.edge_sources)
+                + vec_bytes(&tree.edge_targets),
+            tables: map_bytes(&self.name_table)
+                + map_bytes(&self.val_table)
+                + map_bytes(&self.bookmarks)
+                + map_bytes(&self.directions)
+                + self.directions.values().map(vec_bytes).sum::<usize>()
+                + map_bytes(&self.on_enter)
+                + self.on_enter.values().map(vec_bytes).sum::<usize>()
+                + map_bytes(&self.layout)
+                + vec_bytes(&self.interrupts)
+                + self.asset_root.capacity()
+                + map_bytes(&self.assets)
+                + self.assets.values().map(String::capacity).sum::<usize>()
+                + vec_bytes(&self.libraries)
+                + self.libraries.iter().map(String::capacity).sum::<usize>()
+                + self.naming.speaker_prefix.capacity(),
+            cache: self.hash_cache.memory_usage() + self.search_index.memory_usage(),
+            history: 0,
         }
     }
 }
 
+/// Approximate number of bytes allocated by a project and its editor, returned by
+/// DialogueTreeData::memory_stats and EditorState::memory_stats
+///
+/// Sizes count the capacity of each buffer rather than its length, so space freed by removals is
+/// still included until the project is rebuilt
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Text buffer
+    pub text: usize,
+    /// Node array
+    pub nodes: usize,
+    /// Edge array
+    pub edges: usize,
+    /// Link arrays of the tree, connecting nodes to their outgoing edges
+    pub links: usize,
+    /// Name, val and bookmark tables
+    pub tables: usize,
+    /// Sections remembered for incremental validation, and the search index
+    pub cache: usize,
+    /// Undo/redo history
+    pub history: usize,
+}
+
+impl MemoryStats {
+    /// Total bytes used by all parts
+    pub fn total(&self) -> usize {
+        self.text + self.nodes + self.edges + self.links + self.tables + self.cache + self.history
+    }
+}
+
+/// Bytes allocated by a vector
+fn vec_bytes<T>(v: &Vec<T>) -> usize {
+    v.capacity() * std::mem::size_of::<T>()
+}
+
+/// Approximate bytes allocated by a hashmap, ignoring the overhead of the hashmap itself
+fn map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (std::mem::size_of::<K>() + std::mem::size_of::<V>())
+}
+
+/// Approximate bytes allocated by the tables of a preset
+fn assumption_bytes(assumptions: &runner::Assumptions) -> usize {
+    map_bytes(&assumptions.names) + map_bytes(&assumptions.vals)
+}
+
 /// Struct storing a record of DialogueTreeEvent. Allows for simple linear undo/redo history
 pub struct DialogueTreeHistory {
     /// Record of events
     pub record: Vec<DialogueTreeEvent>,
     /// Current position in the record
     pub position: usize,
+    /// Journal persisting changes made since the project was last saved, if journaling is active
+    pub journal: Option<Journal>,
+    /// Position in the record matching the last saved copy of the project. None if the saved
+    /// copy can no longer be reached with undo/redo
+    saved: Option<usize>,
 }
 
 impl Default for DialogueTreeHistory {
@@ -1237,23 +2024,74 @@ impl Default for DialogueTreeHistory {
         Self {
             record: Vec::with_capacity(1000),
             position: 0,
+            journal: None,
+            saved: Some(0),
         }
     }
 }
 
 impl DialogueTreeHistory {
     /// Push a new event onto the history. This will remove record of all 'undone' changes.
-    pub fn push(&mut self, event: DialogueTreeEvent) {
+    ///
+    /// tree is the tree the event was applied to. Any text added to the buffer along with the
+    /// event is recorded in the journal, so that the event can be replayed, and the search index
+    /// of the tree is invalidated if the event edited text
+    pub fn push(&mut self, event: DialogueTreeEvent, tree: &mut DialogueTreeData) {
+        tree.search_index.invalidate(&event);
+        if let Some(journal) = self.journal.as_mut() {
+            let added = tree.text.get(journal.text_len..).unwrap_or_default();
+            journal.text_len = tree.text.len();
+            self.journal(JournalRecord::Push {
+                text: added,
+                event: &event,
+            });
+        }
+        // the saved position is lost if it is one of the undone events drained below
+        if self.saved.is_some_and(|saved| saved > self.position) {
+            self.saved = None;
+        }
         // drain any undone events before pushing
         self.record.drain(self.position..);
         self.record.push(event);
         self.position += 1;
     }
 
-    /// clear the history, this permanently deletes all events
+    /// Record a rebuild of the tree in the journal. The history itself is cleared by a rebuild
+    ///
+    /// text is the rebuilt text buffer
+    pub fn journal_rebuild(&mut self, text: &str) {
+        if let Some(journal) = self.journal.as_mut() {
+            journal.text_len = text.len();
+        }
+        self.journal(JournalRecord::Rebuild);
+    }
+
+    /// Append an operation to the journal, if journaling is active. Journal failures are logged
+    /// rather than returned, so that a failing store never blocks editing
+    fn journal(&mut self, record: JournalRecord) {
+        if let Some(journal) = self.journal.as_mut() {
+            if let Err(e) = journal.append(&record) {
+                warn!("failed to write journal: {}", e);
+            }
+        }
+    }
+
+    /// clear the history, this permanently deletes all events. The tree is treated as modified
+    /// afterwards, since the saved copy can no longer be reached
     pub fn clear(&mut self) {
         self.record.clear();
         self.position = 0;
+        self.saved = None;
+    }
+
+    /// Mark the current position in the history as matching the saved copy of the project
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.position);
+    }
+
+    /// Check if the tree has changed since it was last saved
+    pub fn is_modified(&self) -> bool {
+        self.saved != Some(self.position)
     }
 
     /// Undo the most recent event in the history.
@@ -1263,10 +2101,13 @@ impl DialogueTreeHistory {
     /// undo
     pub fn undo(&mut self, tree: &mut DialogueTreeData) -> Result<()> {
         // Cannot undo if position is 0, return an error
-        anyhow::ensure!(self.position > 0);
+        anyhow::ensure!(self.position > 0, cmd::Error::EventHistoryEmpty);
 
         self.position -= 1;
-        self.record[self.position].undo(tree)
+        self.record[self.position].undo(tree)?;
+        tree.search_index.invalidate(&self.record[self.position]);
+        self.journal(JournalRecord::Undo);
+        Ok(())
     }
 
     /// Redo the most recently undone event in the history.
@@ -1274,72 +2115,407 @@ impl DialogueTreeHistory {
     /// # Errors
     /// Fails and returns an error if there are no undone events to redo
     pub fn redo(&mut self, tree: &mut DialogueTreeData) -> Result<()> {
-        // Cannot undo if position is 0, return an error
-        anyhow::ensure!(self.position < self.record.len());
+        // Cannot redo if every event is applied, return an error
+        anyhow::ensure!(
+            self.position < self.record.len(),
+            cmd::Error::EventFuturesEmpty
+        );
 
         let res = self.record[self.position].redo(tree);
+        tree.search_index.invalidate(&self.record[self.position]);
         self.position += 1;
-        res
+        res?;
+        self.journal(JournalRecord::Redo);
+        Ok(())
+    }
+
+    /// Undo a single earlier change without undoing the changes made after it. The inverse of
+    /// the change is pushed onto the history as a Revert event, so the revert itself can be
+    /// undone. change is the number of the change as printed by history list, counting from 1
+    ///
+    /// The change is only reverted if no later change depends on it, which is worked out from
+    /// the nodes, edges, and keys each change touches, see Footprint. Later changes that depend
+    /// on the change, such as an edge added to a node the change created, or that edit the same
+    /// things, such as a later edit of the same node, prevent the revert.
+    ///
+    /// # Errors
+    ///
+    /// Error with HistoryOutOfRange if the change doesn't exist or has been undone, or with
+    /// RevertConflict naming the first later change that prevents the revert. The tree is not
+    /// modified on error
+    pub fn revert(&mut self, tree: &mut DialogueTreeData, change: usize) -> Result<()> {
+        anyhow::ensure!(
+            change > 0 && change <= self.position,
+            cmd::Error::HistoryOutOfRange(change)
+        );
+        let event = &self.record[change - 1];
+        let later = &self.record[change..self.position];
+
+        trace!("check the later changes against what the change touched");
+        let footprint = event.footprint(tree);
+        for (i, replayed) in later.iter().enumerate() {
+            let touched = replayed.footprint(tree);
+            if footprint.conflicts(&touched) {
+                let reason = if footprint.writes.iter().any(|t| touched.reads.contains(t)) {
+                    "it is needed"
+                } else {
+                    "the same part of the project is changed"
+                };
+                return Err(cmd::Error::RevertConflict(
+                    change,
+                    format!(
+                        "{} by change {}: {}",
+                        reason,
+                        change + i + 1,
+                        replayed.describe(tree)
+                    ),
+                )
+                .into());
+            }
+        }
+
+        trace!("apply the inverse of the change");
+        let revert = Revert {
+            change,
+            event: event.clone(),
+        };
+        revert.redo(tree)?;
+        self.push(revert.into(), tree);
+        Ok(())
+    }
+
+    /// Approximate number of bytes allocated by the recorded events. The journal is stored in the
+    /// project store and is not included
+    pub fn memory_usage(&self) -> usize {
+        vec_bytes(&self.record) + self.record.iter().map(event_heap_bytes).sum::<usize>()
     }
 }
 
-/// Trait representing an event. Types implementing event should store enough data to completely
-/// undo or redo all state changes performed by the event
-#[enum_dispatch]
-pub trait Event {
-    fn undo(&self, target: &mut DialogueTreeData) -> Result<()>;
-    fn redo(&self, target: &mut DialogueTreeData) -> Result<()>;
+/// An operation on the editor history, as stored in a journal. Replaying the entries of a journal
+/// in order onto the last saved copy of a project reproduces the unsaved changes
+#[derive(Deserialize)]
+pub enum JournalEntry {
+    /// An event pushed onto the history, along with any text added to the text buffer with it
+    Push {
+        text: String,
+        event: DialogueTreeEvent,
+    },
+    Undo,
+    Redo,
+    Rebuild,
 }
 
-/// Enum of different types of events that modify a DialogueTree. These variants store the
-/// information required to reconstruct the event, and implements the Event trait along with
-/// enum_dispatch to support undoing/redoing the event, and allow a unified call to undo() or
-/// redo() to propogate to the inner event type.
-///
-/// The Enum is flattened such that all events are granular changes to an underlying datastructure,
-/// and there are no nested enum types of events. This is done to avoid extra padding/discriminant
-/// words increasing the size of DialogueTreeEvent
-#[enum_dispatch(Event)]
-pub enum DialogueTreeEvent {
-    NodeInsert,
-    NodeRemove,
-    NodeEdit,
-    EdgeInsert,
-    EdgeRemove,
-    EdgeEdit,
-    LinkMove,
-    NameTableInsert,
-    NameTableRemove,
-    NameTableEdit,
-    ValTableInsert,
-    ValTableRemove,
-    ValTableEdit,
+/// Borrowed form of JournalEntry used when writing, so events don't need to be cloned. Variants
+/// must stay in the same order as JournalEntry
+#[derive(Serialize)]
+enum JournalRecord<'a> {
+    Push {
+        text: &'a str,
+        event: &'a DialogueTreeEvent,
+    },
+    Undo,
+    Redo,
+    Rebuild,
 }
 
-/// Event implementations for all Event enum types
+impl<'a> From<&'a JournalEntry> for JournalRecord<'a> {
+    fn from(entry: &'a JournalEntry) -> Self {
+        match entry {
+            JournalEntry::Push { text, event } => JournalRecord::Push { text, event },
+            JournalEntry::Undo => JournalRecord::Undo,
+            JournalEntry::Redo => JournalRecord::Redo,
+            JournalEntry::Rebuild => JournalRecord::Rebuild,
+        }
+    }
+}
+
+/// Append-only log of the history operations made since a project was last saved, stored next to
+/// the project as project_name.tree.journal. The journal starts with the uid of the project and
+/// the length of its text buffer when the journal began, followed by serialized JournalEntry
+/// records.
+///
+/// The journal file is deleted when the project is saved, so a journal left behind after a crash
+/// always holds changes newer than the last save. Loading a project leaves an existing journal in
+/// place until the first new change is made, so it can be recovered with the replay command
+pub struct Journal {
+    store: std::sync::Arc<dyn store::ProjectStore>,
+    path: String,
+    uid: Uid,
+    /// Length of the project text buffer when the journal began
+    base_len: usize,
+    /// Length of the project text buffer as of the last journaled event
+    text_len: usize,
+    /// Set once this session has written to the journal file. The first write replaces any journal
+    /// left behind by a previous session
+    started: bool,
+}
+
+impl Journal {
+    /// Create a journal for a project. Nothing is written until the first entry is appended
+    pub fn new(store: std::sync::Arc<dyn store::ProjectStore>, data: &DialogueTreeData) -> Self {
+        Journal {
+            store,
+            path: Journal::path(&data.name),
+            uid: data.uid,
+            base_len: data.text.len(),
+            text_len: data.text.len(),
+            started: false,
+        }
+    }
+
+    /// Name of the journal file for a project
+    pub fn path(name: &str) -> String {
+        name.to_string() + TREE_EXT + JOURNAL_EXT
+    }
+
+    /// Returns true if this session has written to the journal file
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    fn append(&mut self, record: &JournalRecord) -> Result<()> {
+        let encoded = bincode::serialize(record)?;
+        if self.started {
+            self.store.append(&self.path, &encoded)
+        } else {
+            let mut contents = bincode::serialize(&(self.uid, self.base_len))?;
+            contents.extend(encoded);
+            self.store.write(&self.path, &contents)?;
+            self.started = true;
+            Ok(())
+        }
+    }
+
+    /// Replace the journal file with the given entries
+    pub fn rewrite(&mut self, entries: &[JournalEntry]) -> Result<()> {
+        let mut contents = bincode::serialize(&(self.uid, self.base_len))?;
+        for entry in entries.iter() {
+            contents.extend(bincode::serialize(&JournalRecord::from(entry))?);
+        }
+        self.store.write(&self.path, &contents)?;
+        self.started = true;
+        Ok(())
+    }
+
+    /// Delete the journal file, after the changes it records have been saved
+    pub fn discard(&mut self) -> Result<()> {
+        if self.store.exists(&self.path)? {
+            self.store.delete(&self.path)?;
+        }
+        self.started = false;
+        Ok(())
+    }
+
+    /// Read a journal file from a store, returning the project uid, the length of the text buffer
+    /// the journal starts from, and the entries it holds
+    ///
+    /// A crash while appending can leave a partially written entry at the end of the journal.
+    /// Reading stops at the first entry that fails to decode, and the entries before it are
+    /// returned. Returns None if the journal does not exist.
+    pub fn read(
+        store: &dyn store::ProjectStore,
+        name: &str,
+    ) -> Result<Option<(Uid, usize, Vec<JournalEntry>)>> {
+        let path = Journal::path(name);
+        if !store.exists(&path)? {
+            return Ok(None);
+        }
+        let bytes = store.read(&path)?;
+        let mut reader = std::io::Cursor::new(bytes.as_slice());
+        let (uid, base_len): (Uid, usize) = bincode::deserialize_from(&mut reader)?;
+        let mut entries = Vec::new();
+        while (reader.position() as usize) < bytes.len() {
+            match bincode::deserialize_from(&mut reader) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    warn!("journal ends with a damaged entry, ignoring it: {}", e);
+                    break;
+                }
+            }
+        }
+        Ok(Some((uid, base_len, entries)))
+    }
+}
+
+/// Trait representing an event. Types implementing event should store enough data to completely
+/// undo or redo all state changes performed by the event
+#[enum_dispatch]
+pub trait Event {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()>;
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()>;
+    /// Describe the event in plain words for end users, e.g. "Edited node 12: 'Hello...' →
+    /// 'Hi...'". target is the tree the event was recorded on, and is used to look up text
+    fn describe(&self, target: &DialogueTreeData) -> String;
+}
+
+/// Number of characters of text previewed when describing events
+const EVENT_PREVIEW_WIDTH: usize = 24;
+
+/// Short preview of the text a section points to. Text buffers are only added to between
+/// rebuilds, which also clear the history, so sections stored in events are still valid. The
+/// exception is text rewritten in place, which edits describe from their TextRewrite instead
+fn event_preview(target: &DialogueTreeData, section: &Section) -> String {
+    text_preview(target.text.get(section[0]..section[1]))
+}
+
+/// Short preview of a text, or a placeholder if the text could not be found
+fn text_preview(text: Option<&str>) -> String {
+    match text {
+        Some(text) => format!("'{}'", cmd::util::truncate(text, EVENT_PREVIEW_WIDTH)),
+        None => "<missing text>".to_string(),
+    }
+}
+
+/// Enum of different types of events that modify a DialogueTree. These variants store the
+/// information required to reconstruct the event, and implements the Event trait along with
+/// enum_dispatch to support undoing/redoing the event, and allow a unified call to undo() or
+/// redo() to propogate to the inner event type.
+///
+/// The Enum is flattened such that all events are granular changes to an underlying datastructure,
+/// and there are no nested enum types of events. This is done to avoid extra padding/discriminant
+/// words increasing the size of DialogueTreeEvent. For the same reason, events that store large
+/// payloads (such as full Choice copies) are boxed so that they don't inflate every event in the
+/// history. Keep DialogueTreeEvent within EVENT_SIZE_BUDGET when adding new variants.
+#[enum_dispatch(Event)]
+#[derive(Serialize, Deserialize, Clone)]
+pub enum DialogueTreeEvent {
+    NodeInsert,
+    NodeRemove,
+    NodeEdit(Box<NodeEdit>),
+    EdgeInsert(Box<EdgeInsert>),
+    EdgeRemove(Box<EdgeRemove>),
+    EdgeEdit(Box<EdgeEdit>),
+    LinkMove,
+    NameTableInsert,
+    NameTableRemove,
+    NameTableEdit(Box<NameTableEdit>),
+    ValTableInsert,
+    ValTableRemove,
+    ValTableEdit,
+    BookmarkInsert,
+    BookmarkRemove,
+    DirectionsEdit,
+    OnEnterEdit,
+    LayoutEdit,
+    InterruptInsert(Box<InterruptInsert>),
+    InterruptRemove(Box<InterruptRemove>),
+    AssetInsert,
+    AssetRemove,
+    AssetRootEdit,
+    ChoicePolicyEdit,
+    NamingRulesEdit(Box<NamingRulesEdit>),
+    PresetEdit(Box<PresetEdit>),
+    LibraryInsert,
+    LibraryRemove,
+    TextRewrite,
+    Revert(Box<Revert>),
+    EventGroup,
+}
+
+/// A group of events that are undone and redone together as a single event
+///
+/// This is used by commands that perform many granular modifications that should appear as a
+/// single change in the history. Events are undone in the reverse order they were recorded.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct EventGroup {
+    pub events: Vec<DialogueTreeEvent>,
+}
+
+/// An earlier change undone out of order, by applying its inverse on top of the changes made
+/// after it. See DialogueTreeHistory::revert
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Revert {
+    /// Number of the reverted change in the history, as printed by history list
+    pub change: usize,
+    /// Copy of the reverted event
+    pub event: DialogueTreeEvent,
+}
+
+/// Maximum size in bytes of a DialogueTreeEvent
+pub const EVENT_SIZE_BUDGET: usize = 80;
+
+/// Implement From for the unboxed payload of boxed event variants, so that events returned by the
+/// tree module can be converted with into() regardless of how they are stored
+macro_rules! impl_from_boxed_event {
+    ($($event:ident),*) => {
+        $(
+            impl From<$event> for DialogueTreeEvent {
+                fn from(event: $event) -> Self {
+                    Box::new(event).into()
+                }
+            }
+        )*
+    };
+}
+
+impl_from_boxed_event!(
+    NodeEdit,
+    EdgeInsert,
+    EdgeRemove,
+    EdgeEdit,
+    NameTableEdit,
+    InterruptInsert,
+    InterruptRemove,
+    NamingRulesEdit,
+    PresetEdit,
+    Revert
+);
+
+/// Event implementations for all Event enum types
+
+impl<T: Event> Event for Box<T> {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        self.as_ref().undo(target)
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        self.as_ref().redo(target)
+    }
+
+    fn describe(&self, target: &DialogueTreeData) -> String {
+        self.as_ref().describe(target)
+    }
+}
 
 impl Event for NodeInsert {
     fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        let _new_event = target.tree.remove_node(self.index)?;
+        let _new_event = target.remove_node(self.index)?;
         Ok(())
     }
 
     fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        let _new_event = target.tree.insert_node(self.node, self.index)?;
+        let _new_event = target.insert_node(self.node, self.index)?;
+        target.use_line(self.node.line);
         Ok(())
     }
+
+    fn describe(&self, target: &DialogueTreeData) -> String {
+        format!(
+            "Added node {}: {}",
+            self.index,
+            event_preview(target, &self.node.section)
+        )
+    }
 }
 
 impl Event for NodeRemove {
     fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        let _new_event = target.tree.remove_node(self.index)?;
+        let _new_event = target.insert_node(self.node, self.index)?;
         Ok(())
     }
 
     fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        let _new_event = target.tree.insert_node(self.node, self.index)?;
+        let _new_event = target.remove_node(self.index)?;
         Ok(())
     }
+
+    fn describe(&self, target: &DialogueTreeData) -> String {
+        format!(
+            "Removed node {}: {}",
+            self.index,
+            event_preview(target, &self.node.section)
+        )
+    }
 }
 
 impl Event for NodeEdit {
@@ -1352,6 +2528,30 @@ impl Event for NodeEdit {
         let _new_event = target.tree.edit_node(self.index, self.to)?;
         Ok(())
     }
+
+    fn describe(&self, target: &DialogueTreeData) -> String {
+        self.describe_text(
+            target.text.get(self.from.section[0]..self.from.section[1]),
+            target.text.get(self.to.section[0]..self.to.section[1]),
+        )
+    }
+}
+
+impl NodeEdit {
+    /// Describe the edit given the old and new text of the node, which are not both in the
+    /// buffer if the edit rewrote its text in place, see TextRewrite
+    fn describe_text(&self, old: Option<&str>, new: Option<&str>) -> String {
+        if self.from.section.text == self.to.section.text {
+            format!("Edited settings of node {}", self.index)
+        } else {
+            format!(
+                "Edited node {}: {} → {}",
+                self.index,
+                text_preview(old),
+                text_preview(new)
+            )
+        }
+    }
 }
 
 impl Event for EdgeInsert {
@@ -1364,12 +2564,23 @@ impl Event for EdgeInsert {
         let _new_event = target.tree.insert_edge(
             self.source,
             self.target,
-            self.edge,
+            self.edge.clone(),
             self.index,
             self.placement,
         )?;
+        target.use_line(self.edge.line);
         Ok(())
     }
+
+    fn describe(&self, target: &DialogueTreeData) -> String {
+        format!(
+            "Added edge {} from node {} to node {}: {}",
+            self.index,
+            self.source,
+            self.target,
+            event_preview(target, &self.edge.section)
+        )
+    }
 }
 
 impl Event for EdgeRemove {
@@ -1377,7 +2588,7 @@ impl Event for EdgeRemove {
         let _new_event = target.tree.insert_edge(
             self.source,
             self.target,
-            self.edge,
+            self.edge.clone(),
             self.index,
             self.placement,
         )?;
@@ -1388,18 +2599,60 @@ impl Event for EdgeRemove {
         let _new_event = target.tree.remove_edge(self.index)?;
         Ok(())
     }
+
+    fn describe(&self, target: &DialogueTreeData) -> String {
+        format!(
+            "Removed edge {} from node {} to node {}: {}",
+            self.index,
+            self.source,
+            self.target,
+            event_preview(target, &self.edge.section)
+        )
+    }
 }
 
 impl Event for EdgeEdit {
     fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        let _new_event = target.tree.edit_edge(self.index, self.from)?;
+        let _new_event = target.tree.edit_edge(self.index, self.from.clone())?;
         Ok(())
     }
 
     fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        let _new_event = target.tree.edit_edge(self.index, self.to)?;
+        let _new_event = target.tree.edit_edge(self.index, self.to.clone())?;
         Ok(())
     }
+
+    fn describe(&self, target: &DialogueTreeData) -> String {
+        self.describe_text(
+            target.text.get(self.from.section[0]..self.from.section[1]),
+            target.text.get(self.to.section[0]..self.to.section[1]),
+        )
+    }
+}
+
+impl EdgeEdit {
+    /// Describe the edit given the old and new text of the edge, which are not both in the
+    /// buffer if the edit rewrote its text in place, see TextRewrite
+    fn describe_text(&self, old: Option<&str>, new: Option<&str>) -> String {
+        let mut changes = Vec::new();
+        if self.from.section.text != self.to.section.text {
+            changes.push(format!("{} → {}", text_preview(old), text_preview(new)));
+        }
+        if self.from.requirement != self.to.requirement {
+            changes.push(format!(
+                "requirement {} → {}",
+                self.from.requirement, self.to.requirement
+            ));
+        }
+        if self.from.effect != self.to.effect {
+            changes.push(format!("effect {} → {}", self.from.effect, self.to.effect));
+        }
+        if changes.is_empty() {
+            format!("Edited edge {}", self.index)
+        } else {
+            format!("Edited edge {}: {}", self.index, changes.join(", "))
+        }
+    }
 }
 
 impl Event for LinkMove {
@@ -1416,1501 +2669,17151 @@ impl Event for LinkMove {
             .edit_link_order(self.source, self.index, self.to)?;
         Ok(())
     }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!(
+            "Moved edge {} of node {} from position {} to {}",
+            self.index, self.source, self.from, self.to
+        )
+    }
 }
 
-impl Event for NameTableInsert {
+impl Event for BookmarkInsert {
     fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.name_table.remove(&self.key);
+        target.bookmarks.remove(&self.key);
         Ok(())
     }
 
     fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.name_table.insert(self.key, self.name);
+        target.bookmarks.insert(self.key, self.index);
         Ok(())
     }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Added bookmark {} at node {}", self.key, self.index)
+    }
 }
 
-impl Event for NameTableRemove {
+impl Event for BookmarkRemove {
     fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.name_table.insert(self.key, self.name);
+        target.bookmarks.insert(self.key, self.index);
         Ok(())
     }
 
     fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.name_table.remove(&self.key);
+        target.bookmarks.remove(&self.key);
         Ok(())
     }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Removed bookmark {} at node {}", self.key, self.index)
+    }
 }
 
-impl Event for NameTableEdit {
+impl Event for AssetInsert {
     fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.name_table.insert(self.key, self.from);
+        target.assets.remove(&self.key);
         Ok(())
     }
 
     fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.name_table.insert(self.key, self.to);
+        target.assets.insert(self.key, self.path.clone());
         Ok(())
     }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Added asset {} at {}", self.key, self.path)
+    }
 }
 
-impl Event for ValTableInsert {
+impl Event for AssetRemove {
     fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.val_table.remove(&self.key);
+        target.assets.insert(self.key, self.path.clone());
         Ok(())
     }
 
     fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.val_table.insert(self.key, self.value);
+        target.assets.remove(&self.key);
         Ok(())
     }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Removed asset {} at {}", self.key, self.path)
+    }
 }
 
-impl Event for ValTableRemove {
+impl Event for AssetRootEdit {
     fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.val_table.insert(self.key, self.val);
+        target.asset_root = self.from.clone();
         Ok(())
     }
 
     fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.val_table.remove(&self.key);
+        target.asset_root = self.to.clone();
         Ok(())
     }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Changed asset root from {:?} to {:?}", self.from, self.to)
+    }
 }
 
-impl Event for ValTableEdit {
+impl Event for PresetEdit {
     fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.val_table.insert(self.key, self.from);
+        set_preset(target, &self.name, self.from.as_ref());
         Ok(())
     }
 
     fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
-        target.val_table.insert(self.key, self.to);
+        set_preset(target, &self.name, self.to.as_ref());
         Ok(())
     }
-}
 
-/// State information for an editor instance. Includes two copies of the dialogue tree (one active
-/// and one backup) as well as other state information
-#[derive(Serialize, Deserialize)]
-pub struct EditorState {
-    pub active: DialogueTreeData,
-    pub backup: DialogueTreeData,
-    pub scratchpad: String,
-    #[serde(skip)]
-    pub history: DialogueTreeHistory,
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        match (&self.from, &self.to) {
+            (None, Some(to)) => format!("Added preset {}: {}", self.name, to),
+            (Some(_), Some(to)) => format!("Changed preset {} to {}", self.name, to),
+            (_, None) => format!("Removed preset {}", self.name),
+        }
+    }
 }
 
-impl EditorState {
-    /// Create a new Editor state.
-    ///
-    /// Editor state needs to take ownership of the data. However since
-    /// a backup copy needs to be created on construction, the data is moved, and then cloned
-    pub fn new(data: DialogueTreeData) -> Self {
-        EditorState {
-            active: data.clone(),
-            backup: data,
-            scratchpad: String::with_capacity(1000),
-            history: Default::default(),
+/// Set or remove a preset, used to undo and redo preset edits
+fn set_preset(target: &mut DialogueTreeData, name: &str, preset: Option<&runner::Assumptions>) {
+    match preset {
+        Some(preset) => {
+            target.presets.insert(name.to_string(), preset.clone());
+        }
+        None => {
+            target.presets.remove(name);
         }
     }
+}
 
-    /// Swap the active and backup trees without copying any of the underlying data
-    pub fn swap(&mut self) {
-        std::mem::swap(&mut self.active, &mut self.backup);
+impl Event for ChoicePolicyEdit {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.choice_policy = self.from;
+        Ok(())
     }
-}
 
-/// Struct storing the information for a player choice. Stored in the edges of a dialogue tree
-#[derive(new, Debug, Serialize, Deserialize, Clone, Copy)]
-pub struct Choice {
-    pub section: Section,
-    pub requirement: ReqKind,
-    pub effect: EffectKind,
-}
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.choice_policy = self.to;
+        Ok(())
+    }
 
-/// Struct for storing the information for a line of dialogue. Stored in the nodes of a dialogue
-/// tree
-#[derive(new, Debug, Serialize, Deserialize, Clone, Copy)]
-pub struct Dialogue {
-    pub section: Section,
-    pub pos: Position,
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Changed choice policy from {} to {}", self.from, self.to)
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
-pub enum ReqKind {
-    /// No requirement
-    No,
-    /// Must be greater than num
-    Greater(KeyString, u32),
-    /// Must be less than num
-    Less(KeyString, u32),
-    /// Must be equal to num
-    Equal(KeyString, u32),
-    /// Must match name string
-    Cmp(KeyString, NameString),
-}
+impl Event for NamingRulesEdit {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.naming = self.from.clone();
+        Ok(())
+    }
 
-impl std::str::FromStr for ReqKind {
-    type Err = anyhow::Error;
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.naming = self.to.clone();
+        Ok(())
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        info!("Parsing ReqKind from string");
-        // Implementation notes:
-        // The enum string format is set up to directly map to how the enum is declared in rust:
-        // e.g. 'GreaterThan(my_key,10)'
-        // This is tokenized on the presence of '(' ',' and ')' special characters. In reverse
-        // order:
-        // e.g. ['', '10', 'my_key', 'GreaterThan']
-        //
-        // This is done in reverse order so that the required key and val can be built up before
-        // converting the enum itself, (since the key and val are required to declare the enum
-        //
-        // Importantly, the 'val' that is tested against can be a string or a u32. This is handled
-        // by waiting to unwrap the val parameter until building the Enum
-        let mut split = s.rsplit(&['(', ',', ')'][..]);
-        debug!("{}", s);
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Changed naming rules from {} to {}", self.from, self.to)
+    }
+}
 
-        trace!("Check that first item is ''");
-        anyhow::ensure!(split.next().ok_or(cmd::Error::Generic)?.is_empty());
+impl Event for LibraryInsert {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        anyhow::ensure!(
+            self.position < target.libraries.len(),
+            cmd::Error::UndoFailed
+        );
+        target.libraries.remove(self.position);
+        Ok(())
+    }
 
-        trace!(
-            "second item should be number or string, check for valid length, wait to check if int"
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        anyhow::ensure!(
+            self.position <= target.libraries.len(),
+            cmd::Error::RedoFailed
         );
-        let val = match NameString::from(split.next().ok_or(cmd::Error::Generic)?) {
-            Ok(v) => Ok(v),
-            Err(e) => Err(e.simplify()),
-        }?;
+        target.libraries.insert(self.position, self.name.clone());
+        Ok(())
+    }
 
-        trace!("third item should be key, check that the key is a valid length");
-        // match required due to lifetime limitations on CapacityError
-        let key = match KeyString::from(split.next().ok_or(cmd::Error::Generic)?) {
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Added library {}", self.name)
+    }
+}
+
+impl Event for LibraryRemove {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        anyhow::ensure!(
+            self.position <= target.libraries.len(),
+            cmd::Error::UndoFailed
+        );
+        target.libraries.insert(self.position, self.name.clone());
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        anyhow::ensure!(
+            self.position < target.libraries.len(),
+            cmd::Error::RedoFailed
+        );
+        target.libraries.remove(self.position);
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Removed library {}", self.name)
+    }
+}
+
+impl TextRewrite {
+    /// Old and new text of an edit that was grouped with this rewrite, taken from the rewrite
+    /// rather than the buffer. Both sections start at the start of the rewrite
+    fn texts(&self, from: &Section, to: &Section) -> (Option<&str>, Option<&str>) {
+        (
+            self.from.get(..from[1] - from[0]),
+            self.to.get(..to[1] - to[0]),
+        )
+    }
+
+    /// Overwrite the text at start, after checking that the buffer holds the text being replaced
+    fn write(
+        target: &mut DialogueTreeData,
+        start: usize,
+        old: &str,
+        new: &str,
+        error: cmd::Error,
+    ) -> Result<()> {
+        let range = start..start + old.len();
+        anyhow::ensure!(
+            old.len() == new.len() && target.text.get(range.clone()) == Some(old),
+            error
+        );
+        target.text.replace_range(range, new);
+        Ok(())
+    }
+}
+
+impl Event for TextRewrite {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        TextRewrite::write(
+            target,
+            self.start,
+            &self.to,
+            &self.from,
+            cmd::Error::UndoFailed,
+        )
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        TextRewrite::write(
+            target,
+            self.start,
+            &self.from,
+            &self.to,
+            cmd::Error::RedoFailed,
+        )
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!(
+            "Rewrote text in place: '{}' → '{}'",
+            cmd::util::truncate(self.from.trim_end(), EVENT_PREVIEW_WIDTH),
+            cmd::util::truncate(self.to.trim_end(), EVENT_PREVIEW_WIDTH)
+        )
+    }
+}
+
+impl Event for DirectionsEdit {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.set_directions(self.index, self.from.clone())?;
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.set_directions(self.index, self.to.clone())?;
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!(
+            "Edited directions of node {}: {} → {}",
+            self.index,
+            self.from.len(),
+            self.to.len()
+        )
+    }
+}
+
+impl Event for OnEnterEdit {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.set_on_enter(self.index, self.from.clone())?;
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.set_on_enter(self.index, self.to.clone())?;
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!(
+            "Edited on-enter effects of node {}: {} → {}",
+            self.index,
+            self.from.len(),
+            self.to.len()
+        )
+    }
+}
+
+impl Event for LayoutEdit {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.set_position(self.index, self.from)?;
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.set_position(self.index, self.to)?;
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        match self.to {
+            Some(pos) => format!("Moved node {} to ({}, {})", self.index, pos.x, pos.y),
+            None => format!("Cleared position of node {}", self.index),
+        }
+    }
+}
+
+impl Event for InterruptInsert {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        anyhow::ensure!(
+            self.position < target.interrupts.len(),
+            cmd::Error::InvalidInterruptPosition(self.position)
+        );
+        target.interrupts.remove(self.position);
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        anyhow::ensure!(
+            self.position <= target.interrupts.len(),
+            cmd::Error::InvalidInterruptPosition(self.position)
+        );
+        target
+            .interrupts
+            .insert(self.position, self.interrupt.clone());
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!(
+            "Added interrupt {}: {} → node {}",
+            self.position, self.interrupt.requirement, self.interrupt.target
+        )
+    }
+}
+
+impl Event for InterruptRemove {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        anyhow::ensure!(
+            self.position <= target.interrupts.len(),
+            cmd::Error::InvalidInterruptPosition(self.position)
+        );
+        target
+            .interrupts
+            .insert(self.position, self.interrupt.clone());
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        anyhow::ensure!(
+            self.position < target.interrupts.len(),
+            cmd::Error::InvalidInterruptPosition(self.position)
+        );
+        target.interrupts.remove(self.position);
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!(
+            "Removed interrupt {}: {} → node {}",
+            self.position, self.interrupt.requirement, self.interrupt.target
+        )
+    }
+}
+
+impl Event for NameTableInsert {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.name_table.remove(&self.key);
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.name_table.insert(self.key, self.name);
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Added name {}: '{}'", self.key, self.name)
+    }
+}
+
+impl Event for NameTableRemove {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.name_table.insert(self.key, self.name);
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.name_table.remove(&self.key);
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Removed name {}: '{}'", self.key, self.name)
+    }
+}
+
+impl Event for NameTableEdit {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.name_table.insert(self.key, self.from);
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.name_table.insert(self.key, self.to);
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Edited name {}: '{}' → '{}'", self.key, self.from, self.to)
+    }
+}
+
+impl Event for ValTableInsert {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.val_table.remove(&self.key);
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.val_table.insert(self.key, self.value);
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Added val {}: {}", self.key, self.value)
+    }
+}
+
+impl Event for ValTableRemove {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.val_table.insert(self.key, self.val);
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.val_table.remove(&self.key);
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Removed val {}: {}", self.key, self.val)
+    }
+}
+
+impl Event for ValTableEdit {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.val_table.insert(self.key, self.from);
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        target.val_table.insert(self.key, self.to);
+        Ok(())
+    }
+
+    fn describe(&self, _target: &DialogueTreeData) -> String {
+        format!("Edited val {}: {} → {}", self.key, self.from, self.to)
+    }
+}
+
+/// Bytes allocated on the heap by an event, on top of the size of the DialogueTreeEvent itself
+fn event_heap_bytes(event: &DialogueTreeEvent) -> usize {
+    match event {
+        DialogueTreeEvent::EdgeInsert(_) => std::mem::size_of::<EdgeInsert>(),
+        DialogueTreeEvent::EdgeRemove(_) => std::mem::size_of::<EdgeRemove>(),
+        DialogueTreeEvent::EdgeEdit(_) => std::mem::size_of::<EdgeEdit>(),
+        DialogueTreeEvent::NameTableEdit(_) => std::mem::size_of::<NameTableEdit>(),
+        DialogueTreeEvent::DirectionsEdit(edit) => vec_bytes(&edit.from) + vec_bytes(&edit.to),
+        DialogueTreeEvent::OnEnterEdit(edit) => vec_bytes(&edit.from) + vec_bytes(&edit.to),
+        DialogueTreeEvent::InterruptInsert(_) => std::mem::size_of::<InterruptInsert>(),
+        DialogueTreeEvent::InterruptRemove(_) => std::mem::size_of::<InterruptRemove>(),
+        DialogueTreeEvent::AssetInsert(insert) => insert.path.capacity(),
+        DialogueTreeEvent::AssetRemove(remove) => remove.path.capacity(),
+        DialogueTreeEvent::AssetRootEdit(edit) => edit.from.capacity() + edit.to.capacity(),
+        DialogueTreeEvent::NamingRulesEdit(edit) => {
+            std::mem::size_of::<NamingRulesEdit>()
+                + edit.from.speaker_prefix.capacity()
+                + edit.to.speaker_prefix.capacity()
+        }
+        DialogueTreeEvent::PresetEdit(edit) => {
+            std::mem::size_of::<PresetEdit>()
+                + edit.name.capacity()
+                + edit
+                    .from
+                    .iter()
+                    .chain(edit.to.iter())
+                    .map(assumption_bytes)
+                    .sum::<usize>()
+        }
+        DialogueTreeEvent::LibraryInsert(insert) => insert.name.capacity(),
+        DialogueTreeEvent::LibraryRemove(remove) => remove.name.capacity(),
+        DialogueTreeEvent::TextRewrite(rewrite) => rewrite.from.capacity() + rewrite.to.capacity(),
+        DialogueTreeEvent::Revert(revert) => {
+            std::mem::size_of::<Revert>() + event_heap_bytes(&revert.event)
+        }
+        DialogueTreeEvent::EventGroup(group) => {
+            vec_bytes(&group.events) + group.events.iter().map(event_heap_bytes).sum::<usize>()
+        }
+        _ => 0,
+    }
+}
+
+/// Text of a node or edge before and after an event that edited it, see
+/// DialogueTreeEvent::text_change
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextChange {
+    /// What was changed, e.g. "Edited node 3"
+    pub title: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// A part of a project that an event reads or changes, see Footprint
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Touch {
+    /// Whether there is a node at an index
+    NodeSlot(NodeIndex),
+    /// The text and settings of a node
+    Node(NodeIndex),
+    /// The number of nodes. Inserting or removing a node swaps the last node into its place
+    Nodes,
+    /// The text, requirement, and effect of an edge
+    Edge(EdgeIndex),
+    /// The number of edges. Inserting or removing an edge swaps the last edge into its place
+    Edges,
+    /// The order of the outgoing edges of a node
+    Outgoing(NodeIndex),
+    Directions(NodeIndex),
+    OnEnter(NodeIndex),
+    Layout(NodeIndex),
+    /// A name or value key
+    Key(KeyString),
+    Bookmark(KeyString),
+    Asset(KeyString),
+    Preset(String),
+    Interrupts,
+    AssetRoot,
+    ChoicePolicy,
+    NamingRules,
+    Libraries,
+    /// Text rewritten in place at an offset of the text buffer, see TextRewrite
+    Text(usize),
+}
+
+/// The parts of a project an event reads and changes, used to find the changes that depend on
+/// each other without replaying them, see DialogueTreeHistory::revert
+#[derive(Debug, Default, Clone)]
+pub struct Footprint {
+    pub reads: HashSet<Touch>,
+    pub writes: HashSet<Touch>,
+}
+
+impl Footprint {
+    /// Check if a later event depends on this one, or changes the same parts of the project. If
+    /// not, this event can be undone without undoing the later one
+    pub fn conflicts(&self, later: &Footprint) -> bool {
+        self.writes
+            .iter()
+            .any(|t| later.reads.contains(t) || later.writes.contains(t))
+            || later.writes.iter().any(|t| self.reads.contains(t))
+    }
+
+    /// Read the keys used by the text of a node or edge. Sections that are no longer in the text
+    /// buffer read no keys
+    fn read_text(&mut self, target: &DialogueTreeData, section: &Section) {
+        let text = target.text.get(section[0]..section[1]).unwrap_or_default();
+        for key in cmd::util::split_tokens(text).skip(1).step_by(2) {
+            self.read_key(key);
+        }
+    }
+
+    fn read_key(&mut self, key: &str) {
+        if let Ok(key) = KeyString::from(key) {
+            self.reads.insert(Touch::Key(key));
+        }
+    }
+
+    fn read_requirement(&mut self, requirement: &ReqKind) {
+        match requirement {
+            ReqKind::No => {}
+            ReqKind::Greater(key, _)
+            | ReqKind::Less(key, _)
+            | ReqKind::Equal(key, _)
+            | ReqKind::Cmp(key, _)
+            | ReqKind::NotCmp(key, _)
+            | ReqKind::OneOf(key, _) => self.read_key(key),
+        }
+    }
+
+    fn read_effect(&mut self, effect: &EffectKind) {
+        match effect {
+            EffectKind::No => {}
+            EffectKind::Add(key, _)
+            | EffectKind::Sub(key, _)
+            | EffectKind::Set(key, _)
+            | EffectKind::Assign(key, _)
+            | EffectKind::Prompt(key) => self.read_key(key),
+        }
+    }
+
+    fn read_choice(&mut self, target: &DialogueTreeData, choice: &Choice) {
+        self.read_text(target, &choice.section);
+        self.read_requirement(&choice.requirement);
+        self.read_effect(&choice.effect);
+    }
+}
+
+impl DialogueTreeEvent {
+    /// The parts of the project the event reads and changes. target is the tree the event was
+    /// recorded on, and is used to look up the keys used by text
+    pub fn footprint(&self, target: &DialogueTreeData) -> Footprint {
+        let mut footprint = Footprint::default();
+        self.add_footprint(target, &mut footprint);
+        footprint
+    }
+
+    fn add_footprint(&self, target: &DialogueTreeData, fp: &mut Footprint) {
+        use DialogueTreeEvent as E;
+        match self {
+            E::NodeInsert(NodeInsert { index, node })
+            | E::NodeRemove(NodeRemove { index, node }) => {
+                fp.writes
+                    .extend([Touch::NodeSlot(*index), Touch::Node(*index), Touch::Nodes]);
+                fp.read_text(target, &node.section);
+            }
+            E::NodeEdit(edit) => {
+                fp.writes.insert(Touch::Node(edit.index));
+                fp.reads.insert(Touch::NodeSlot(edit.index));
+                fp.read_text(target, &edit.from.section);
+                fp.read_text(target, &edit.to.section);
+            }
+            E::EdgeInsert(edit) => {
+                fp.writes.extend([
+                    Touch::Edge(edit.index),
+                    Touch::Edges,
+                    Touch::Outgoing(edit.source),
+                ]);
+                fp.reads
+                    .extend([Touch::NodeSlot(edit.source), Touch::NodeSlot(edit.target)]);
+                fp.read_choice(target, &edit.edge);
+            }
+            E::EdgeRemove(edit) => {
+                fp.writes.extend([
+                    Touch::Edge(edit.index),
+                    Touch::Edges,
+                    Touch::Outgoing(edit.source),
+                ]);
+                fp.reads
+                    .extend([Touch::NodeSlot(edit.source), Touch::NodeSlot(edit.target)]);
+                fp.read_choice(target, &edit.edge);
+            }
+            E::EdgeEdit(edit) => {
+                fp.writes.insert(Touch::Edge(edit.index));
+                fp.read_choice(target, &edit.from);
+                fp.read_choice(target, &edit.to);
+            }
+            E::LinkMove(edit) => {
+                fp.writes.insert(Touch::Outgoing(edit.source));
+                fp.reads.insert(Touch::Edge(edit.index));
+            }
+            E::NameTableInsert(NameTableInsert { key, .. })
+            | E::NameTableRemove(NameTableRemove { key, .. })
+            | E::ValTableInsert(ValTableInsert { key, .. })
+            | E::ValTableRemove(ValTableRemove { key, .. })
+            | E::ValTableEdit(ValTableEdit { key, .. }) => {
+                fp.writes.insert(Touch::Key(*key));
+            }
+            E::NameTableEdit(edit) => {
+                fp.writes.insert(Touch::Key(edit.key));
+            }
+            E::BookmarkInsert(BookmarkInsert { key, index })
+            | E::BookmarkRemove(BookmarkRemove { key, index }) => {
+                fp.writes.insert(Touch::Bookmark(*key));
+                fp.reads.insert(Touch::NodeSlot(*index));
+            }
+            E::DirectionsEdit(edit) => {
+                fp.writes.insert(Touch::Directions(edit.index));
+                fp.reads.insert(Touch::NodeSlot(edit.index));
+                for direction in edit.from.iter().chain(edit.to.iter()) {
+                    match direction {
+                        Direction::Enter(key)
+                        | Direction::Exit(key)
+                        | Direction::Animate(key, _) => fp.read_key(key),
+                        Direction::Background(_) => {}
+                    }
+                }
+            }
+            E::OnEnterEdit(edit) => {
+                fp.writes.insert(Touch::OnEnter(edit.index));
+                fp.reads.insert(Touch::NodeSlot(edit.index));
+                for effect in edit.from.iter().chain(edit.to.iter()) {
+                    fp.read_effect(effect);
+                }
+            }
+            E::LayoutEdit(edit) => {
+                fp.writes.insert(Touch::Layout(edit.index));
+                fp.reads.insert(Touch::NodeSlot(edit.index));
+            }
+            E::InterruptInsert(edit) => {
+                fp.writes.insert(Touch::Interrupts);
+                fp.reads.insert(Touch::NodeSlot(edit.interrupt.target));
+                fp.read_requirement(&edit.interrupt.requirement);
+            }
+            E::InterruptRemove(edit) => {
+                fp.writes.insert(Touch::Interrupts);
+                fp.reads.insert(Touch::NodeSlot(edit.interrupt.target));
+                fp.read_requirement(&edit.interrupt.requirement);
+            }
+            E::AssetInsert(AssetInsert { key, .. }) | E::AssetRemove(AssetRemove { key, .. }) => {
+                fp.writes.insert(Touch::Asset(*key));
+            }
+            E::AssetRootEdit(_) => {
+                fp.writes.insert(Touch::AssetRoot);
+            }
+            E::ChoicePolicyEdit(_) => {
+                fp.writes.insert(Touch::ChoicePolicy);
+            }
+            E::NamingRulesEdit(_) => {
+                fp.writes.insert(Touch::NamingRules);
+            }
+            E::PresetEdit(edit) => {
+                fp.writes.insert(Touch::Preset(edit.name.clone()));
+                for assumptions in edit.from.iter().chain(edit.to.iter()) {
+                    for key in assumptions.names.keys().chain(assumptions.vals.keys()) {
+                        fp.read_key(key);
+                    }
+                }
+            }
+            E::LibraryInsert(_) | E::LibraryRemove(_) => {
+                fp.writes.insert(Touch::Libraries);
+            }
+            E::TextRewrite(rewrite) => {
+                fp.writes.insert(Touch::Text(rewrite.start));
+            }
+            E::Revert(revert) => revert.event.add_footprint(target, fp),
+            E::EventGroup(group) => {
+                for event in group.events.iter() {
+                    event.add_footprint(target, fp);
+                }
+            }
+        }
+    }
+
+    /// Check if the event changes the text of any node or edge, or which nodes and edges exist
+    pub fn edits_text(&self) -> bool {
+        match self {
+            DialogueTreeEvent::NodeInsert(_)
+            | DialogueTreeEvent::NodeRemove(_)
+            | DialogueTreeEvent::NodeEdit(_)
+            | DialogueTreeEvent::EdgeInsert(_)
+            | DialogueTreeEvent::EdgeRemove(_)
+            | DialogueTreeEvent::EdgeEdit(_)
+            | DialogueTreeEvent::TextRewrite(_) => true,
+            DialogueTreeEvent::Revert(revert) => revert.event.edits_text(),
+            DialogueTreeEvent::EventGroup(group) => group.events.iter().any(|e| e.edits_text()),
+            _ => false,
+        }
+    }
+
+    /// The text changed by an edit of a node or edge, so that it can be shown as a diff rather
+    /// than described. None for other events, and for edits that left the text as it was
+    ///
+    /// Edits that rewrote their text in place are grouped with a TextRewrite, see
+    /// cmd::util::with_rewrite. The buffer only holds one of the two texts in that case, so both
+    /// are taken from the rewrite
+    pub fn text_change(&self, target: &DialogueTreeData) -> Option<TextChange> {
+        let (edit, rewrite) = match self {
+            DialogueTreeEvent::Revert(revert) => {
+                let change = revert.event.text_change(target)?;
+                return Some(TextChange {
+                    title: format!("Reverted change {}: {}", revert.change, change.title),
+                    old: change.new,
+                    new: change.old,
+                });
+            }
+            DialogueTreeEvent::EventGroup(group) => match group.events.as_slice() {
+                [edit] => (edit, None),
+                [edit, DialogueTreeEvent::TextRewrite(rewrite)] => (edit, Some(rewrite)),
+                _ => return None,
+            },
+            edit => (edit, None),
+        };
+        let (title, from, to) = match edit {
+            DialogueTreeEvent::NodeEdit(edit) => (
+                format!("Edited node {}", edit.index),
+                edit.from.section,
+                edit.to.section,
+            ),
+            DialogueTreeEvent::EdgeEdit(edit) => (
+                format!("Edited edge {}", edit.index),
+                edit.from.section,
+                edit.to.section,
+            ),
+            _ => return None,
+        };
+        let (old, new) = match rewrite {
+            Some(rewrite) => match rewrite.texts(&from, &to) {
+                (Some(old), Some(new)) => (old, new),
+                _ => return None,
+            },
+            None => (
+                target.text.get(from[0]..from[1])?,
+                target.text.get(to[0]..to[1])?,
+            ),
+        };
+        if old == new {
+            return None;
+        }
+        Some(TextChange {
+            title,
+            old: old.to_string(),
+            new: new.to_string(),
+        })
+    }
+}
+
+impl Event for Revert {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        self.event.redo(target)
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        self.event.undo(target)
+    }
+
+    fn describe(&self, target: &DialogueTreeData) -> String {
+        format!(
+            "Reverted change {}: {}",
+            self.change,
+            self.event.describe(target)
+        )
+    }
+}
+
+impl Event for EventGroup {
+    fn undo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        for event in self.events.iter().rev() {
+            event.undo(target)?;
+        }
+        Ok(())
+    }
+
+    fn redo(&self, target: &mut DialogueTreeData) -> Result<()> {
+        for event in self.events.iter() {
+            event.redo(target)?;
+        }
+        Ok(())
+    }
+
+    /// Groups are described by their first event, along with the number of other events. An
+    /// edit grouped with the text it rewrote in place is described as the edit alone, with the
+    /// texts taken from the rewrite since the buffer only holds one of them, see
+    /// cmd::util::with_rewrite
+    fn describe(&self, target: &DialogueTreeData) -> String {
+        if let [edit, DialogueTreeEvent::TextRewrite(rewrite)] = self.events.as_slice() {
+            match edit {
+                DialogueTreeEvent::NodeEdit(edit) => {
+                    let (old, new) = rewrite.texts(&edit.from.section, &edit.to.section);
+                    return edit.describe_text(old, new);
+                }
+                DialogueTreeEvent::EdgeEdit(edit) => {
+                    let (old, new) = rewrite.texts(&edit.from.section, &edit.to.section);
+                    return edit.describe_text(old, new);
+                }
+                _ => (),
+            }
+        }
+        match self.events.split_first() {
+            None => "No changes".to_string(),
+            Some((first, [])) => first.describe(target),
+            Some((first, rest)) => format!(
+                "{} (and {} more changes)",
+                first.describe(target),
+                rest.len()
+            ),
+        }
+    }
+}
+
+/// State information for an editor instance. Includes two copies of the dialogue tree (one active
+/// and one backup) as well as other state information
+#[derive(Serialize, Deserialize)]
+pub struct EditorState {
+    pub active: DialogueTreeData,
+    pub backup: DialogueTreeData,
+    pub scratchpad: String,
+    #[serde(skip)]
+    pub history: DialogueTreeHistory,
+    /// Where project files are saved and loaded
+    #[serde(skip, default = "store::default_store")]
+    pub store: std::sync::Arc<dyn store::ProjectStore>,
+    /// Where backups and journals of unsaved changes are written, see store::Backups
+    #[serde(skip, default = "store::default_backups")]
+    pub backups: store::Backups,
+    /// Other projects open in the editor, see the project command
+    #[serde(skip)]
+    pub open: Vec<OpenProject>,
+}
+
+/// A project that is open in the editor but not active. Each open project keeps its own backup
+/// copy and undo/redo history, which are swapped into the editor state when the project is made
+/// active
+pub struct OpenProject {
+    pub active: DialogueTreeData,
+    pub backup: DialogueTreeData,
+    pub history: DialogueTreeHistory,
+}
+
+impl OpenProject {
+    /// Open a project, journaling its changes to the given store if one is given and the project
+    /// is named, see EditorState::journal_store
+    pub fn new(
+        data: DialogueTreeData,
+        journals: Option<std::sync::Arc<dyn store::ProjectStore>>,
+    ) -> Self {
+        let mut history = DialogueTreeHistory::default();
+        if let Some(journals) = journals.filter(|_| !data.name.is_empty()) {
+            history.journal = Some(Journal::new(journals, &data));
+        }
+        OpenProject {
+            active: data.clone(),
+            backup: data,
+            history,
+        }
+    }
+}
+
+impl EditorState {
+    /// Create a new Editor state.
+    ///
+    /// Editor state needs to take ownership of the data. However since
+    /// a backup copy needs to be created on construction, the data is moved, and then cloned
+    ///
+    /// Changes are not journaled, use with_store or with_backups to keep a journal of unsaved
+    /// changes
+    pub fn new(data: DialogueTreeData) -> Self {
+        EditorState::with_backups(data, store::default_store(), store::default_backups())
+    }
+
+    /// Create a new Editor state that saves and loads project files in the given store, and
+    /// journals unsaved changes next to them
+    pub fn with_store(
+        data: DialogueTreeData,
+        store: std::sync::Arc<dyn store::ProjectStore>,
+    ) -> Self {
+        EditorState::with_backups(data, store, store::Backups::default())
+    }
+
+    /// Create a new Editor state that saves and loads project files in the given store, and
+    /// writes backups and journals to the given backups
+    pub fn with_backups(
+        data: DialogueTreeData,
+        store: std::sync::Arc<dyn store::ProjectStore>,
+        backups: store::Backups,
+    ) -> Self {
+        let OpenProject {
+            active,
+            backup,
+            history,
+        } = OpenProject::new(data, backups.journal.then(|| backups.store(&store)));
+        EditorState {
+            active,
+            backup,
+            scratchpad: String::with_capacity(1000),
+            history,
+            store,
+            backups,
+            open: Vec::new(),
+        }
+    }
+
+    /// Store that backups and journals are written to, which is the project store unless the
+    /// backups are kept apart from the project files
+    pub fn backup_store(&self) -> std::sync::Arc<dyn store::ProjectStore> {
+        self.backups.store(&self.store)
+    }
+
+    /// Store that journals are written to, or None if changes are not journaled
+    pub fn journal_store(&self) -> Option<std::sync::Arc<dyn store::ProjectStore>> {
+        self.backups.journal.then(|| self.backup_store())
+    }
+
+    /// Swap the active and backup trees without copying any of the underlying data
+    pub fn swap(&mut self) {
+        std::mem::swap(&mut self.active, &mut self.backup);
+    }
+
+    /// Position of an open project other than the active one in the open list
+    pub fn open_position(&self, name: &str) -> Option<usize> {
+        self.open.iter().position(|p| p.active.name == name)
+    }
+
+    /// Make the open project at the given position in the open list active. The previously
+    /// active project takes its place in the list
+    ///
+    /// # Panics
+    /// Panics if the position is out of bounds
+    pub fn switch_project(&mut self, position: usize) {
+        let parked = &mut self.open[position];
+        std::mem::swap(&mut self.active, &mut parked.active);
+        std::mem::swap(&mut self.backup, &mut parked.backup);
+        std::mem::swap(&mut self.history, &mut parked.history);
+    }
+
+    /// Run a sequence of changes as a single change
+    ///
+    /// If f fails, the active and backup trees and the history are restored to how they were
+    /// before f ran. Otherwise the events f pushed are combined into one EventGroup, so they are
+    /// undone and redone in one step. The journal is paused while f runs and records the group
+    /// once f has finished. If f saves or replaces the project, or undoes changes made before it
+    /// ran, its changes are kept as they are without grouping, and the paused journal is
+    /// dropped until the next save.
+    pub fn transaction<T>(&mut self, f: impl FnOnce(&mut EditorState) -> Result<T>) -> Result<T> {
+        let uid = self.active.uid;
+        let start = self.history.position;
+        let saved = self.history.saved;
+        let record = self.history.record.clone();
+        let active = self.active.clone();
+        let backup = self.backup.clone();
+        let journal = self.history.journal.take();
+
+        let res = f(self);
+        // a new journal is started by a save or load while f runs
+        let restarted = self.history.journal.is_some();
+        if let Err(e) = res {
+            self.active = active;
+            self.backup = backup;
+            self.history.record = record;
+            self.history.position = start;
+            if restarted {
+                // the tree on disk no longer matches the restored tree or the paused journal
+                self.history.saved = None;
+                self.history.journal = None;
+            } else {
+                self.history.saved = saved;
+                self.history.journal = journal;
+            }
+            return Err(e);
+        }
+
+        if restarted || self.active.uid != uid || self.history.position < start {
+            // the events of f can't be told apart from the rest of the history, or from the
+            // changes in a journal started by f
+            return res;
+        }
+        self.history.journal = journal;
+        let end = self.history.position;
+        self.history.record.truncate(end);
+        let mut events: Vec<DialogueTreeEvent> = self.history.record.drain(start..).collect();
+        self.history.position = start;
+        match events.len() {
+            0 => {}
+            1 => self.history.push(events.remove(0), &mut self.active),
+            _ => self
+                .history
+                .push(EventGroup { events }.into(), &mut self.active),
+        }
+        res
+    }
+
+    /// Approximate number of bytes allocated by the active project and the undo/redo history.
+    /// The backup copy of the project is not included
+    pub fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            history: self.history.memory_usage(),
+            ..self.active.memory_stats()
+        }
+    }
+
+    /// Resolve the absolute path of an asset of the active project, see util::resolve_asset
+    ///
+    /// # Errors
+    /// Error if the project has no asset with the given key
+    pub fn resolve_asset(&self, key: &str) -> Result<std::path::PathBuf> {
+        let path =
+            self.active.assets.get(key).ok_or_else(|| {
+                cmd::Error::AssetNotExists(KeyString::from(key).unwrap_or_default())
+            })?;
+        Ok(cmd::util::resolve_asset(
+            self.store.root(),
+            &self.active.asset_root,
+            path,
+        ))
+    }
+}
+
+/// Manifest describing the contents of a project package
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PackageManifest {
+    /// Name of the packaged project
+    pub name: String,
+    /// uid of the packaged project
+    pub uid: Uid,
+    /// Path of the entry storing the serialized dialogue tree
+    pub tree: String,
+    /// Paths of all other entries in the package
+    pub assets: Vec<String>,
+}
+
+/// A single file stored in a project package
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PackageEntry {
+    pub path: String,
+    pub data: Vec<u8>,
+}
+
+/// Archive bundling a project's dialogue tree with any assets it needs into a single file for
+/// distribution. Runtime data may be read directly from the package without extracting it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Package {
+    pub manifest: PackageManifest,
+    pub entries: Vec<PackageEntry>,
+}
+
+impl Package {
+    /// Create a package from a dialogue tree, the files in its asset table, and a list of locale
+    /// files. Assets are stored under their path in the asset table, and locale files under the
+    /// name they were given. All files are read through the project store, with asset paths
+    /// resolved against the asset root. The layout table, presets, and naming rules are editor
+    /// only, so they are left out of the packaged tree
+    ///
+    /// # Errors
+    /// Error if the tree fails to serialize or any file cannot be read from the store
+    pub fn new(
+        data: &DialogueTreeData,
+        store: &dyn store::ProjectStore,
+        locales: &[String],
+    ) -> Result<Self> {
+        let tree = data.name.clone() + TREE_EXT;
+        let runtime = DialogueTreeData {
+            layout: LayoutTable::default(),
+            presets: PresetTable::new(),
+            naming: NamingRules::default(),
+            ..data.clone()
+        };
+
+        // sort by key so that packages of the same project are identical
+        let mut assets: Vec<_> = data.assets.iter().collect();
+        assets.sort_unstable();
+        let mut files: Vec<(String, String)> = assets
+            .into_iter()
+            .map(|(_, path)| {
+                let name = std::path::Path::new(&data.asset_root).join(path);
+                (path.clone(), name.to_string_lossy().into_owned())
+            })
+            .collect();
+        for locale in locales.iter() {
+            if !files.iter().any(|(path, _)| path == locale) {
+                files.push((locale.clone(), locale.clone()));
+            }
+        }
+
+        let mut entries = Vec::with_capacity(files.len() + 1);
+        entries.push(PackageEntry {
+            path: tree.clone(),
+            data: migrate::encode(&runtime)?,
+        });
+        for (path, name) in files.iter() {
+            entries.push(PackageEntry {
+                path: path.clone(),
+                data: store.read(name)?,
+            });
+        }
+
+        Ok(Package {
+            manifest: PackageManifest {
+                name: data.name.clone(),
+                uid: data.uid,
+                tree,
+                assets: files.into_iter().map(|(path, _)| path).collect(),
+            },
+            entries,
+        })
+    }
+
+    /// Read a package from disk
+    ///
+    /// # Errors
+    /// Error with Encrypted if the package is encrypted
+    pub fn read(path: &str) -> Result<Self> {
+        Package::decode(&std::fs::read(path)?)
+    }
+
+    /// Write a package to disk
+    pub fn write(&self, path: &str) -> Result<()> {
+        std::fs::write(path, bincode::serialize(self)?)?;
+        Ok(())
+    }
+
+    /// Read a package from a project store
+    ///
+    /// # Errors
+    /// Error with Encrypted if the package is encrypted
+    pub fn read_from(store: &dyn store::ProjectStore, name: &str) -> Result<Self> {
+        Package::decode(&store.read(name)?)
+    }
+
+    /// Write a package to a project store
+    pub fn write_to(&self, store: &dyn store::ProjectStore, name: &str) -> Result<()> {
+        store.write(name, &bincode::serialize(self)?)
+    }
+
+    /// Read an encrypted package from a project store
+    #[cfg(feature = "encryption")]
+    pub fn read_encrypted(
+        store: &dyn store::ProjectStore,
+        name: &str,
+        secret: &crypt::Secret,
+    ) -> Result<Self> {
+        Package::decode(&crypt::decrypt(secret, &store.read(name)?)?)
+    }
+
+    /// Encrypt a package and write it to a project store
+    #[cfg(feature = "encryption")]
+    pub fn write_encrypted(
+        &self,
+        store: &dyn store::ProjectStore,
+        name: &str,
+        secret: &crypt::Secret,
+    ) -> Result<()> {
+        store.write(name, &crypt::encrypt(secret, &bincode::serialize(self)?)?)
+    }
+
+    /// Deserialize a package, checking that it isn't encrypted first
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        anyhow::ensure!(!store::is_encrypted(bytes), cmd::Error::Encrypted);
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// Get the contents of an entry in the package
+    ///
+    /// # Errors
+    /// Error if no entry with the given path exists
+    pub fn entry(&self, path: &str) -> Result<&[u8]> {
+        self.entries
+            .iter()
+            .find(|e| e.path == path)
+            .map(|e| e.data.as_slice())
+            .ok_or_else(|| cmd::Error::PackageEntryNotExists(path.to_string()).into())
+    }
+
+    /// Deserialize and validate the dialogue tree stored in the package
+    pub fn tree(&self) -> Result<DialogueTreeData> {
+        let data = migrate::decode(self.entry(&self.manifest.tree)?)?;
+        cmd::util::validate_tree(&data)?;
+        Ok(data)
+    }
+}
+
+/// Encryption of project files, so that shipped runtime exports don't contain trivially readable
+/// story content. Files are encrypted with ChaCha20-Poly1305, using either a raw key or a key
+/// derived from a password with PBKDF2.
+///
+/// Encrypted files start with store::ENCRYPTED_MAGIC, followed by a byte for the kind of secret,
+/// the salt used for password derivation, and the nonce. The header is authenticated along with
+/// the encrypted contents.
+#[cfg(feature = "encryption")]
+pub mod crypt {
+    use super::*;
+    use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey};
+    use ring::rand::{SecureRandom, SystemRandom};
+
+    pub const KEY_LEN: usize = 32;
+    const SALT_LEN: usize = 16;
+    const HEADER_LEN: usize = 4 + 1 + SALT_LEN + aead::NONCE_LEN;
+    const PBKDF2_ITERATIONS: u32 = 100_000;
+
+    /// Secret used to encrypt and decrypt a file
+    #[derive(Clone)]
+    pub enum Secret {
+        Password(String),
+        Key([u8; KEY_LEN]),
+    }
+
+    impl std::fmt::Debug for Secret {
+        /// Never print the secret itself
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Secret::Password(_) => write!(f, "Password(..)"),
+                Secret::Key(_) => write!(f, "Key(..)"),
+            }
+        }
+    }
+
+    impl Secret {
+        fn kind(&self) -> u8 {
+            match self {
+                Secret::Key(_) => 0,
+                Secret::Password(_) => 1,
+            }
+        }
+
+        /// Get the encryption key, deriving it from the password if needed
+        fn key(&self, salt: &[u8]) -> Result<LessSafeKey> {
+            let mut bytes = [0u8; KEY_LEN];
+            match self {
+                Secret::Key(key) => bytes = *key,
+                Secret::Password(password) => ring::pbkdf2::derive(
+                    ring::pbkdf2::PBKDF2_HMAC_SHA256,
+                    std::num::NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+                    salt,
+                    password.as_bytes(),
+                    &mut bytes,
+                ),
+            }
+            let key = UnboundKey::new(&aead::CHACHA20_POLY1305, &bytes)
+                .map_err(|_| cmd::Error::DecryptFailed)?;
+            Ok(LessSafeKey::new(key))
+        }
+    }
+
+    /// Encrypt the contents of a file
+    pub fn encrypt(secret: &Secret, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let rng = SystemRandom::new();
+        let mut header = Vec::with_capacity(HEADER_LEN + plaintext.len() + aead::MAX_TAG_LEN);
+        header.extend_from_slice(store::ENCRYPTED_MAGIC);
+        header.push(secret.kind());
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        rng.fill(&mut salt)
+            .and_then(|_| rng.fill(&mut nonce))
+            .map_err(|_| cmd::Error::EncryptFailed)?;
+        header.extend_from_slice(&salt);
+        header.extend_from_slice(&nonce);
+
+        trace!("encrypt file contents");
+        let key = secret.key(&salt)?;
+        let mut contents = plaintext.to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(&header),
+            &mut contents,
+        )
+        .map_err(|_| cmd::Error::EncryptFailed)?;
+        header.extend(contents);
+        Ok(header)
+    }
+
+    /// Decrypt the contents of a file encrypted with encrypt
+    ///
+    /// # Errors
+    /// Error with DecryptFailed if the secret is wrong or the file has been modified
+    pub fn decrypt(secret: &Secret, encrypted: &[u8]) -> Result<Vec<u8>> {
+        anyhow::ensure!(
+            encrypted.len() >= HEADER_LEN && store::is_encrypted(encrypted),
+            cmd::Error::DecryptFailed
+        );
+        let (header, contents) = encrypted.split_at(HEADER_LEN);
+        anyhow::ensure!(header[4] == secret.kind(), cmd::Error::DecryptFailed);
+        let salt = &header[5..5 + SALT_LEN];
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        nonce.copy_from_slice(&header[5 + SALT_LEN..]);
+
+        trace!("decrypt file contents");
+        let key = secret.key(salt)?;
+        let mut contents = contents.to_vec();
+        let len = key
+            .open_in_place(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(header),
+                &mut contents,
+            )
+            .map_err(|_| cmd::Error::DecryptFailed)?
+            .len();
+        contents.truncate(len);
+        Ok(contents)
+    }
+}
+
+/// Storage backends for project files. All project IO performed by the editor (saving, loading,
+/// backups, and packages) goes through a ProjectStore, so that projects can be kept somewhere
+/// other than the local filesystem, such as in memory for tests or in a game engine's asset system.
+///
+/// Files in a store are identified by name, including the file extension (e.g. my_project.tree).
+pub mod store {
+    use super::*;
+    use std::collections::hash_map::Entry;
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    /// A place where project files can be read and written by name
+    pub trait ProjectStore: Send + Sync {
+        /// Read the contents of a file
+        ///
+        /// # Errors
+        /// Error with FileNotExists if there is no file with the given name
+        fn read(&self, name: &str) -> Result<Vec<u8>>;
+
+        /// Write the contents of a file, replacing the file if it already exists
+        fn write(&self, name: &str, data: &[u8]) -> Result<()>;
+
+        /// List the names of all files in the store, in sorted order
+        fn list(&self) -> Result<Vec<String>>;
+
+        /// Delete a file
+        ///
+        /// # Errors
+        /// Error with FileNotExists if there is no file with the given name
+        fn delete(&self, name: &str) -> Result<()>;
+
+        /// Check if a file exists
+        fn exists(&self, name: &str) -> Result<bool> {
+            Ok(self.list()?.iter().any(|n| n == name))
+        }
+
+        /// Append to the end of a file, creating it if it does not exist
+        ///
+        /// The default implementation reads and rewrites the whole file
+        fn append(&self, name: &str, data: &[u8]) -> Result<()> {
+            let mut contents = if self.exists(name)? {
+                self.read(name)?
+            } else {
+                Vec::new()
+            };
+            contents.extend_from_slice(data);
+            self.write(name, &contents)
+        }
+
+        /// Replace the contents of a file such that an interrupted write never leaves a partially
+        /// written file in its place. The previous contents of the file are kept as name.old
+        ///
+        /// The default implementation copies the previous contents and then writes the file, which
+        /// is only safe for stores where write is already atomic
+        fn replace(&self, name: &str, data: &[u8]) -> Result<()> {
+            if self.exists(name)? {
+                self.write(&(name.to_string() + OLD_EXT), &self.read(name)?)?;
+            }
+            self.write(name, data)
+        }
+
+        /// Directory on the filesystem that the store keeps its files in, if any. Relative asset
+        /// paths of projects in the store are resolved from this directory
+        fn root(&self) -> Option<&std::path::Path> {
+            None
+        }
+
+        /// Time a file was last modified, if the store keeps track of it
+        ///
+        /// # Errors
+        /// Error with FileNotExists if there is no file with the given name
+        fn modified(&self, name: &str) -> Result<Option<std::time::SystemTime>> {
+            if self.exists(name)? {
+                Ok(None)
+            } else {
+                Err(cmd::Error::FileNotExists(name.to_string()).into())
+            }
+        }
+    }
+
+    /// Options controlling how a FileStore replaces files
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub struct WriteOptions {
+        /// Flush file contents to disk before renaming them into place
+        pub sync: bool,
+        /// Keep the previous contents of a replaced file as name.old
+        pub keep_old: bool,
+    }
+
+    impl Default for WriteOptions {
+        fn default() -> Self {
+            WriteOptions {
+                sync: true,
+                keep_old: true,
+            }
+        }
+    }
+
+    /// Store that keeps files in a directory on the filesystem
+    #[derive(Debug, Clone)]
+    pub struct FileStore {
+        root: PathBuf,
+        options: WriteOptions,
+    }
+
+    impl FileStore {
+        /// Create a store for files in the given directory
+        pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+            FileStore::with_options(root, WriteOptions::default())
+        }
+
+        /// Create a store for files in the given directory that replaces files with the given
+        /// options
+        pub fn with_options<P: Into<PathBuf>>(root: P, options: WriteOptions) -> Self {
+            FileStore {
+                root: root.into(),
+                options,
+            }
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.root.join(name)
+        }
+
+        /// Convert missing file errors to FileNotExists
+        fn not_found(e: std::io::Error, name: &str) -> anyhow::Error {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                cmd::Error::FileNotExists(name.to_string()).into()
+            } else {
+                e.into()
+            }
+        }
+    }
+
+    impl Default for FileStore {
+        /// Store files in the current working directory
+        fn default() -> Self {
+            FileStore::new(".")
+        }
+    }
+
+    impl ProjectStore for FileStore {
+        fn read(&self, name: &str) -> Result<Vec<u8>> {
+            std::fs::read(self.path(name)).map_err(|e| Self::not_found(e, name))
+        }
+
+        fn root(&self) -> Option<&std::path::Path> {
+            Some(&self.root)
+        }
+
+        fn write(&self, name: &str, data: &[u8]) -> Result<()> {
+            std::fs::write(self.path(name), data)?;
+            Ok(())
+        }
+
+        fn list(&self) -> Result<Vec<String>> {
+            let mut names = Vec::new();
+            for entry in std::fs::read_dir(&self.root)? {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    names.push(entry.file_name().to_string_lossy().into_owned());
+                }
+            }
+            names.sort_unstable();
+            Ok(names)
+        }
+
+        fn delete(&self, name: &str) -> Result<()> {
+            std::fs::remove_file(self.path(name)).map_err(|e| Self::not_found(e, name))
+        }
+
+        fn exists(&self, name: &str) -> Result<bool> {
+            Ok(self.path(name).is_file())
+        }
+
+        fn modified(&self, name: &str) -> Result<Option<std::time::SystemTime>> {
+            let metadata =
+                std::fs::metadata(self.path(name)).map_err(|e| Self::not_found(e, name))?;
+            Ok(Some(metadata.modified()?))
+        }
+
+        fn append(&self, name: &str, data: &[u8]) -> Result<()> {
+            use std::io::Write;
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path(name))?;
+            file.write_all(data)?;
+            if self.options.sync {
+                file.sync_data()?;
+            }
+            Ok(())
+        }
+
+        /// Write the new contents to name.tmp, then rename it over the target, so the target is
+        /// always either the complete previous file or the complete new file
+        fn replace(&self, name: &str, data: &[u8]) -> Result<()> {
+            use std::io::Write;
+            let path = self.path(name);
+            let temp = self.path(&(name.to_string() + TEMP_EXT));
+
+            trace!("write new contents to temporary file");
+            let mut file = std::fs::File::create(&temp)?;
+            file.write_all(data)?;
+            if self.options.sync {
+                file.sync_all()?;
+            }
+            drop(file);
+
+            if self.options.keep_old && path.is_file() {
+                trace!("keep previous contents as old file");
+                let old = self.path(&(name.to_string() + OLD_EXT));
+                let _res = std::fs::remove_file(&old);
+                if std::fs::hard_link(&path, &old).is_err() {
+                    std::fs::copy(&path, &old)?;
+                }
+            }
+
+            trace!("rename temporary file over target");
+            std::fs::rename(&temp, &path)?;
+            if self.options.sync {
+                // persist the rename itself. Directories can't be opened for syncing on all
+                // platforms, so failures here are ignored
+                if let Ok(dir) = std::fs::File::open(&self.root) {
+                    let _res = dir.sync_all();
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Store that keeps files in memory. Clones of a MemoryStore share the same files
+    #[derive(Debug, Clone, Default)]
+    pub struct MemoryStore {
+        files: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    }
+
+    impl MemoryStore {
+        fn files(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Vec<u8>>>> {
+            self.files
+                .lock()
+                .map_err(|_| cmd::Error::LockPoisoned.into())
+        }
+    }
+
+    impl ProjectStore for MemoryStore {
+        fn read(&self, name: &str) -> Result<Vec<u8>> {
+            self.files()?
+                .get(name)
+                .cloned()
+                .ok_or_else(|| cmd::Error::FileNotExists(name.to_string()).into())
+        }
+
+        fn write(&self, name: &str, data: &[u8]) -> Result<()> {
+            self.files()?.insert(name.to_string(), data.to_vec());
+            Ok(())
+        }
+
+        fn list(&self) -> Result<Vec<String>> {
+            let mut names: Vec<String> = self.files()?.keys().cloned().collect();
+            names.sort_unstable();
+            Ok(names)
+        }
+
+        fn delete(&self, name: &str) -> Result<()> {
+            self.files()?
+                .remove(name)
+                .map(|_| ())
+                .ok_or_else(|| cmd::Error::FileNotExists(name.to_string()).into())
+        }
+
+        fn exists(&self, name: &str) -> Result<bool> {
+            Ok(self.files()?.contains_key(name))
+        }
+    }
+
+    /// Store used by editor states unless another is provided, which keeps files in the current
+    /// working directory
+    pub fn default_store() -> Arc<dyn ProjectStore> {
+        Arc::new(FileStore::default())
+    }
+
+    /// Key of the backup settings in the settings file, next to the logging settings
+    pub const BACKUP_SETTINGS_KEY: &str = "backups";
+
+    /// Where backups and journals are written, and how many old backups are kept, as stored in
+    /// the settings file
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct BackupSettings {
+        /// Directory for backups and journals. None keeps them next to the project files
+        pub dir: Option<PathBuf>,
+        /// Number of older backups kept for each project, not counting the latest backup
+        pub keep: usize,
+        /// Journal unsaved changes, so they can be replayed after a crash
+        pub journal: bool,
+    }
+
+    impl Default for BackupSettings {
+        fn default() -> Self {
+            BackupSettings {
+                dir: None,
+                keep: 3,
+                journal: true,
+            }
+        }
+    }
+
+    impl BackupSettings {
+        /// Read the backup settings from a settings file. A missing file or a file without
+        /// backup settings gives the default settings
+        ///
+        /// # Errors
+        ///
+        /// Error if the file exists but cannot be read or parsed
+        pub fn load(path: &std::path::Path) -> Result<BackupSettings> {
+            let settings: serde_json::Map<String, serde_json::Value> =
+                match std::fs::read_to_string(path) {
+                    Ok(s) => serde_json::from_str(&s)?,
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                        return Ok(Self::default())
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+            match settings.get(BACKUP_SETTINGS_KEY) {
+                Some(backups) => Ok(serde_json::from_value(backups.clone())?),
+                None => Ok(Self::default()),
+            }
+        }
+
+        /// Create the backups described by the settings. The backup directory is created if it
+        /// does not exist
+        ///
+        /// # Errors
+        ///
+        /// Error if the backup directory cannot be created
+        pub fn backups(&self) -> Result<Backups> {
+            let store: Option<Arc<dyn ProjectStore>> = match &self.dir {
+                Some(dir) => {
+                    std::fs::create_dir_all(dir)?;
+                    Some(Arc::new(FileStore::new(dir)))
+                }
+                None => None,
+            };
+            Ok(Backups {
+                store,
+                keep: self.keep,
+                journal: self.journal,
+            })
+        }
+    }
+
+    /// Transient files kept for recovery rather than as part of a project: the backup written
+    /// before a rebuild, and the journal of unsaved changes. These can be kept in a store of
+    /// their own, so that folders of project files synced with other tools stay clean.
+    ///
+    /// Writing a backup moves the previous backups down a numbered suffix, so project.tree.bkp
+    /// is the latest backup and project.tree.bkp.1 the one before it, up to keep older backups
+    #[derive(Clone)]
+    pub struct Backups {
+        /// Store of the backups, None to keep them in whichever store the projects are in
+        pub store: Option<Arc<dyn ProjectStore>>,
+        /// Number of older backups kept for each project, not counting the latest backup
+        pub keep: usize,
+        /// Journal unsaved changes, see Journal
+        pub journal: bool,
+    }
+
+    impl Default for Backups {
+        /// Keep backups and journals in the same store as the projects, with the default number
+        /// of older backups
+        fn default() -> Self {
+            let settings = BackupSettings::default();
+            Backups {
+                store: None,
+                keep: settings.keep,
+                journal: settings.journal,
+            }
+        }
+    }
+
+    impl Backups {
+        /// Store the backups are written to, for projects in the given store
+        pub fn store(&self, projects: &Arc<dyn ProjectStore>) -> Arc<dyn ProjectStore> {
+            self.store.clone().unwrap_or_else(|| projects.clone())
+        }
+
+        /// Name of the latest backup of a project
+        pub fn path(name: &str) -> String {
+            name.to_string() + TREE_EXT + BACKUP_EXT
+        }
+
+        /// Name of an older backup of a project, 1 being the most recent
+        pub fn rotated_path(name: &str, n: usize) -> String {
+            format!("{}.{}", Backups::path(name), n)
+        }
+
+        /// Write the latest backup of a project in the given project store, moving the previous
+        /// backups down. The oldest backup is dropped once there are more than keep older backups
+        pub fn write(
+            &self,
+            projects: &Arc<dyn ProjectStore>,
+            data: &DialogueTreeData,
+        ) -> Result<()> {
+            let store = self.store(projects);
+            let store = store.as_ref();
+            let latest = Backups::path(&data.name);
+            if self.keep > 0 && store.exists(&latest)? {
+                for n in (1..self.keep).rev() {
+                    let from = Backups::rotated_path(&data.name, n);
+                    if store.exists(&from)? {
+                        store.write(
+                            &Backups::rotated_path(&data.name, n + 1),
+                            &store.read(&from)?,
+                        )?;
+                    }
+                }
+                store.write(&Backups::rotated_path(&data.name, 1), &store.read(&latest)?)?;
+            }
+            replace_data(store, &latest, data, Compression::None)
+        }
+
+        /// Delete older backups beyond the first keep of each project, or every backup including
+        /// the latest if all is set. Journals are never deleted, as they hold unsaved changes.
+        /// Returns the names of the deleted files, in sorted order
+        pub fn purge(
+            &self,
+            projects: &Arc<dyn ProjectStore>,
+            keep: usize,
+            all: bool,
+        ) -> Result<Vec<String>> {
+            let backup_end = TREE_EXT.to_string() + BACKUP_EXT;
+            let store = self.store(projects);
+            let mut deleted = Vec::new();
+            for name in store.list()? {
+                let purged = if name.ends_with(&backup_end) {
+                    all
+                } else {
+                    match name.rsplit_once('.') {
+                        Some((base, n)) if base.ends_with(&backup_end) => {
+                            match n.parse::<usize>() {
+                                Ok(n) => all || n > keep,
+                                // the previous contents kept when the latest backup was replaced
+                                Err(_) => all && OLD_EXT.strip_prefix('.') == Some(n),
+                            }
+                        }
+                        _ => false,
+                    }
+                };
+                if purged {
+                    store.delete(&name)?;
+                    deleted.push(name);
+                }
+            }
+            Ok(deleted)
+        }
+    }
+
+    /// Backups used by editor states unless others are provided, which are kept next to the
+    /// project files. Changes are not journaled, so that editor states which were not given a
+    /// store don't write journal files
+    pub fn default_backups() -> Backups {
+        Backups {
+            journal: false,
+            ..Backups::default()
+        }
+    }
+
+    /// Magic bytes at the start of a compressed dialogue tree file. Uncompressed files start with
+    /// migrate::VERSION_MAGIC instead, or the project uid if saved before trees were versioned
+    pub const COMPRESSED_MAGIC: &[u8; 4] = b"ARBZ";
+
+    /// Magic bytes at the start of an encrypted file, see crypt
+    pub const ENCRYPTED_MAGIC: &[u8; 4] = b"ARBE";
+
+    /// Check if the contents of a file are encrypted. Encrypted files can be detected even when
+    /// the encryption feature is disabled, so that opening them gives a clear error
+    pub fn is_encrypted(bytes: &[u8]) -> bool {
+        bytes.starts_with(ENCRYPTED_MAGIC)
+    }
+
+    /// Compression applied to dialogue tree files
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    pub enum Compression {
+        #[default]
+        None,
+        /// zlib wrapped deflate stream, prefixed by COMPRESSED_MAGIC
+        Deflate,
+    }
+
+    /// Serialize a dialogue tree, compressing it if requested
+    pub fn encode_data(data: &DialogueTreeData, compression: Compression) -> Result<Vec<u8>> {
+        let encoded = migrate::encode(data)?;
+        Ok(match compression {
+            Compression::None => encoded,
+            Compression::Deflate => {
+                let mut compressed = COMPRESSED_MAGIC.to_vec();
+                compressed.extend(miniz_oxide::deflate::compress_to_vec_zlib(&encoded, 6));
+                compressed
+            }
+        })
+    }
+
+    /// Decompress the contents of a dialogue tree file if it starts with COMPRESSED_MAGIC,
+    /// returning the serialized tree
+    pub fn decompress(bytes: Vec<u8>) -> Result<Vec<u8>> {
+        anyhow::ensure!(!is_encrypted(&bytes), cmd::Error::Encrypted);
+        match bytes.strip_prefix(COMPRESSED_MAGIC) {
+            Some(compressed) => miniz_oxide::inflate::decompress_to_vec_zlib(compressed)
+                .map_err(|e| cmd::Error::Decompress(format!("{:?}", e)).into()),
+            None => Ok(bytes),
+        }
+    }
+
+    /// Deserialize a dialogue tree, detecting compression from the magic bytes. Trees saved by
+    /// older versions of arbor are upgraded, see migrate
+    pub fn decode_data(bytes: Vec<u8>) -> Result<DialogueTreeData> {
+        migrate::decode(&decompress(bytes)?)
+    }
+
+    /// Serialize a dialogue tree and write it to a store
+    pub fn write_data(store: &dyn ProjectStore, name: &str, data: &DialogueTreeData) -> Result<()> {
+        store.write(name, &encode_data(data, Compression::None)?)
+    }
+
+    /// Serialize a dialogue tree and replace a file in a store with it, keeping the previous file
+    pub fn replace_data(
+        store: &dyn ProjectStore,
+        name: &str,
+        data: &DialogueTreeData,
+        compression: Compression,
+    ) -> Result<()> {
+        store.replace(name, &encode_data(data, compression)?)
+    }
+
+    /// Read and deserialize a dialogue tree from a store, which may be compressed or have its text
+    /// split into segments. Entries of the shared libraries used by the tree are merged into its
+    /// tables, see cmd::util::merge_libraries. The tree is not validated
+    ///
+    /// Editing needs the whole text buffer, so every segment is loaded. Use read_partial to read
+    /// single nodes and edges without loading the rest of the text
+    pub fn read_data(store: &dyn ProjectStore, name: &str) -> Result<DialogueTreeData> {
+        let mut data = read_partial(store, name)?.into_data()?;
+        cmd::util::merge_libraries(&mut data, store);
+        Ok(data)
+    }
+
+    /// Read a dialogue tree from a store without its text buffer, see PartialData. Entries of
+    /// shared libraries are not merged into its tables
+    pub fn read_partial<'s>(store: &'s dyn ProjectStore, name: &str) -> Result<PartialData<'s>> {
+        let (table, bytes) = split_segments(store.read(name)?)?;
+        Ok(PartialData {
+            store,
+            data: decode_data(bytes)?,
+            table,
+            loaded: HashMap::new(),
+        })
+    }
+
+    /// Read a shared library of names and values from a store, see Library. The name is given
+    /// without the library extension
+    pub fn read_library(store: &dyn ProjectStore, name: &str) -> Result<Library> {
+        let bytes = store.read(&(name.to_string() + LIBRARY_EXT))?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Write a shared library of names and values to a store, replacing any library with the
+    /// same name
+    pub fn replace_library(store: &dyn ProjectStore, name: &str, library: &Library) -> Result<()> {
+        store.replace(
+            &(name.to_string() + LIBRARY_EXT),
+            &serde_json::to_vec_pretty(library)?,
+        )
+    }
+
+    /// Magic bytes at the start of a dialogue tree file whose text buffer is stored in spillover
+    /// files. The magic is followed by the serialized SegmentTable, and then the tree file itself
+    /// with an empty text buffer
+    pub const SEGMENTED_MAGIC: &[u8; 4] = b"ARBS";
+
+    /// A piece of a text buffer, stored in its own file next to the project file
+    #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+    pub struct Segment {
+        /// Name of the spillover file in the store. Files are named after the hash of their text,
+        /// so a save never changes a file that an earlier save still refers to
+        pub file: String,
+        /// Start of the segment in the text buffer
+        pub start: usize,
+        /// End of the segment in the text buffer
+        pub end: usize,
+        /// Hash of the segment text, checked when the segment is read
+        pub hash: u64,
+    }
+
+    /// Where the text of a section is kept in a segmented text buffer: the index of the segment,
+    /// and the start and end of the text within that segment
+    #[derive(new, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+    pub struct SegmentSection {
+        pub segment: usize,
+        pub start: usize,
+        pub end: usize,
+    }
+
+    /// Table of the segments that a text buffer is split into, in text order
+    ///
+    /// Segments only break the text between sections, so every node and edge can be read from a
+    /// single segment without loading the rest of the text. The table keeps the segment section
+    /// of every node and edge, see PartialData for reading them.
+    #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+    pub struct SegmentTable {
+        /// Maximum length in bytes of a segment. A single section longer than this gets a segment
+        /// of its own
+        pub max_len: usize,
+        pub segments: Vec<Segment>,
+        /// Segment section of each node, by node index
+        pub nodes: Vec<SegmentSection>,
+        /// Segment section of each edge, by edge index
+        pub edges: Vec<SegmentSection>,
+    }
+
+    impl SegmentTable {
+        /// Plan the segments for the text buffer of a project saved as the file name. Segments
+        /// are at most max_len bytes long, unless a single section is longer
+        pub fn split(data: &DialogueTreeData, name: &str, max_len: usize) -> SegmentTable {
+            let text = &data.text;
+            let mut sections: Vec<[usize; 2]> = data
+                .tree
+                .nodes()
+                .iter()
+                .map(|n| n.section.text)
+                .chain(data.tree.edges().iter().map(|e| e.section.text))
+                .filter(|s| s[0] <= s[1] && s[1] <= text.len())
+                .collect();
+            sections.sort_unstable();
+
+            trace!("find the offsets that are not inside of any section");
+            let mut cuts = Vec::new();
+            let mut covered = 0;
+            for section in sections.iter() {
+                if section[0] >= covered && text.is_char_boundary(section[0]) {
+                    cuts.push(section[0]);
+                }
+                covered = covered.max(section[1]);
+            }
+            cuts.push(text.len());
+
+            let mut table = SegmentTable {
+                max_len,
+                ..Default::default()
+            };
+            let mut start = 0;
+            while start < text.len() {
+                // take the furthest cut that fits, or the nearest one if none do
+                let fits = cuts
+                    .iter()
+                    .rev()
+                    .find(|&&c| c > start && c - start <= max_len);
+                let end = fits
+                    .or_else(|| cuts.iter().find(|&&c| c > start))
+                    .copied()
+                    .unwrap_or(text.len());
+                let hash = hash(&text.as_bytes()[start..end]);
+                table.segments.push(Segment {
+                    file: format!("{}{}{:016x}", name, SEGMENT_EXT, hash),
+                    start,
+                    end,
+                    hash,
+                });
+                start = end;
+            }
+
+            let unplaced = SegmentSection::new(usize::MAX, 0, 0);
+            table.nodes = data
+                .tree
+                .nodes()
+                .iter()
+                .map(|n| table.locate(&n.section).unwrap_or(unplaced))
+                .collect();
+            table.edges = data
+                .tree
+                .edges()
+                .iter()
+                .map(|e| table.locate(&e.section).unwrap_or(unplaced))
+                .collect();
+            table
+        }
+
+        /// Find the segment section holding a section of the whole text buffer. None if no
+        /// segment holds the whole section
+        pub fn locate(&self, section: &Section) -> Option<SegmentSection> {
+            let index = self
+                .segments
+                .iter()
+                .position(|s| s.start <= section[0] && section[1] <= s.end)?;
+            let start = self.segments[index].start;
+            Some(SegmentSection::new(
+                index,
+                section[0] - start,
+                section[1] - start,
+            ))
+        }
+
+        /// Names of the spillover files the table refers to
+        pub fn files(&self) -> HashSet<&str> {
+            self.segments.iter().map(|s| s.file.as_str()).collect()
+        }
+
+        /// Read a single segment from the store
+        ///
+        /// # Errors
+        /// Error with SegmentMismatch if the segment file is missing or its text has changed
+        pub fn read_segment(&self, store: &dyn ProjectStore, index: usize) -> Result<String> {
+            let segment = self
+                .segments
+                .get(index)
+                .ok_or_else(|| cmd::Error::SegmentMismatch(index.to_string()))?;
+            let mismatch = || cmd::Error::SegmentMismatch(segment.file.clone());
+            let text = String::from_utf8(store.read(&segment.file).map_err(|_| mismatch())?)
+                .map_err(|_| mismatch())?;
+            anyhow::ensure!(
+                text.len() == segment.end - segment.start && hash(text.as_bytes()) == segment.hash,
+                mismatch()
+            );
+            Ok(text)
+        }
+
+        /// Read the text of a single section, loading only the segment that holds it
+        pub fn read_section(&self, store: &dyn ProjectStore, section: &Section) -> Result<String> {
+            let at = self.locate(section).ok_or(cmd::Error::InvalidSection)?;
+            Ok(self.read_segment(store, at.segment)?[at.start..at.end].to_string())
+        }
+    }
+
+    /// A dialogue tree read without its text buffer. If the tree was saved in segments, the text
+    /// of a node or edge is read on demand, and only the segments holding the text that was asked
+    /// for are loaded. Trees that are not segmented keep their whole text buffer
+    pub struct PartialData<'s> {
+        store: &'s dyn ProjectStore,
+        /// The tree, with an empty text buffer if it is segmented
+        pub data: DialogueTreeData,
+        /// Segment table of the tree, None if it is not segmented
+        pub table: Option<SegmentTable>,
+        /// Text of the segments loaded so far, by segment index
+        loaded: HashMap<usize, String>,
+    }
+
+    impl PartialData<'_> {
+        /// Text of a node, loading the segment that holds it if needed
+        ///
+        /// # Errors
+        /// Error if the node does not exist, or its segment cannot be read
+        pub fn node_text(&mut self, index: NodeIndex) -> Result<&str> {
+            let section = self.data.tree.get_node(index)?.section;
+            let at = self.table.as_ref().map(|t| t.nodes.get(index).copied());
+            self.section_text(section, at)
+        }
+
+        /// Text of an edge, loading the segment that holds it if needed
+        ///
+        /// # Errors
+        /// Error if the edge does not exist, or its segment cannot be read
+        pub fn edge_text(&mut self, index: EdgeIndex) -> Result<&str> {
+            let section = self.data.tree.get_edge(index)?.section;
+            let at = self.table.as_ref().map(|t| t.edges.get(index).copied());
+            self.section_text(section, at)
+        }
+
+        /// Number of segments loaded so far
+        pub fn loaded(&self) -> usize {
+            self.loaded.len()
+        }
+
+        fn section_text(
+            &mut self,
+            section: Section,
+            at: Option<Option<SegmentSection>>,
+        ) -> Result<&str> {
+            let (table, at) = match (self.table.as_ref(), at) {
+                (Some(table), Some(Some(at))) => (table, at),
+                (Some(_), _) => return Err(cmd::Error::InvalidSection.into()),
+                (None, _) => {
+                    return self
+                        .data
+                        .text
+                        .get(section[0]..section[1])
+                        .ok_or_else(|| cmd::Error::InvalidSection.into())
+                }
+            };
+            let text = match self.loaded.entry(at.segment) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => e.insert(table.read_segment(self.store, at.segment)?),
+            };
+            text.get(at.start..at.end)
+                .ok_or_else(|| cmd::Error::InvalidSection.into())
+        }
+
+        /// Load the segments not read yet and join them back into the whole text buffer
+        pub fn into_data(mut self) -> Result<DialogueTreeData> {
+            if let Some(table) = self.table.as_ref() {
+                let len = table.segments.last().map_or(0, |s| s.end);
+                let mut text = String::with_capacity(len);
+                for index in 0..table.segments.len() {
+                    match self.loaded.remove(&index) {
+                        Some(segment) => text.push_str(&segment),
+                        None => text.push_str(&table.read_segment(self.store, index)?),
+                    }
+                }
+                self.data.text = text;
+            }
+            Ok(self.data)
+        }
+    }
+
+    /// Separate the segment table from the contents of a dialogue tree file. Files that are not
+    /// segmented are returned as is, with no table
+    pub fn split_segments(bytes: Vec<u8>) -> Result<(Option<SegmentTable>, Vec<u8>)> {
+        match bytes.strip_prefix(SEGMENTED_MAGIC) {
+            Some(rest) => {
+                let mut reader = std::io::Cursor::new(rest);
+                let table: SegmentTable = bincode::deserialize_from(&mut reader)?;
+                let offset = reader.position() as usize;
+                Ok((Some(table), rest[offset..].to_vec()))
+            }
+            None => Ok((None, bytes)),
+        }
+    }
+
+    /// Read the segment table of a dialogue tree file, so that sections can be read without
+    /// loading the whole text buffer. Returns None if the file is not segmented
+    pub fn read_segments(store: &dyn ProjectStore, name: &str) -> Result<Option<SegmentTable>> {
+        Ok(split_segments(store.read(name)?)?.0)
+    }
+
+    /// The maximum text length a dialogue tree file was last saved with, so that later saves keep
+    /// splitting the text the same way. usize::MAX if the file is missing or not segmented
+    pub fn segment_limit(store: &dyn ProjectStore, name: &str) -> Result<usize> {
+        if !store.exists(name)? {
+            return Ok(usize::MAX);
+        }
+        Ok(read_segments(store, name)?.map_or(usize::MAX, |table| table.max_len))
+    }
+
+    /// Serialize a dialogue tree and replace a file in a store with it, like replace_data. If the
+    /// text buffer is longer than max_text_len, the text is written to spillover files instead,
+    /// see SegmentTable. Entries of shared libraries are not written, see
+    /// cmd::util::strip_libraries. Returns the number of segments written
+    ///
+    /// Segments are written before the project file is replaced, and spillover files are only
+    /// deleted once neither the new project file nor the previous one kept as name.old refers to
+    /// them, so an interrupted save always leaves both files readable
+    pub fn replace_data_segmented(
+        store: &dyn ProjectStore,
+        name: &str,
+        data: &DialogueTreeData,
+        compression: Compression,
+        max_text_len: usize,
+    ) -> Result<usize> {
+        let stripped;
+        let data = if data.libraries.is_empty() {
+            data
+        } else {
+            trace!("leave shared library entries out of the project file");
+            stripped = cmd::util::strip_libraries(data, store);
+            &stripped
+        };
+
+        let table = if data.text.len() > max_text_len {
+            SegmentTable::split(data, name, max_text_len)
+        } else {
+            SegmentTable::default()
+        };
+
+        if table.segments.is_empty() {
+            replace_data(store, name, data, compression)?;
+        } else {
+            trace!("write segments that are not in the store yet, leaving existing files as is");
+            for segment in table.segments.iter() {
+                if !store.exists(&segment.file)? {
+                    let text = &data.text[segment.start..segment.end];
+                    store.write(&segment.file, text.as_bytes())?;
+                }
+            }
+            let stripped = DialogueTreeData {
+                text: String::new(),
+                ..data.clone()
+            };
+            let mut encoded = SEGMENTED_MAGIC.to_vec();
+            bincode::serialize_into(&mut encoded, &table)?;
+            encoded.extend(encode_data(&stripped, compression)?);
+            store.replace(name, &encoded)?;
+        }
+
+        trace!("delete spillover files that neither this save nor the previous one refer to");
+        let old = name.to_string() + OLD_EXT;
+        let previous = if store.exists(&old)? {
+            split_segments(store.read(&old)?)
+                .ok()
+                .and_then(|(table, _)| table)
+        } else {
+            None
+        };
+        let mut keep = table.files();
+        if let Some(previous) = previous.as_ref() {
+            keep.extend(previous.files());
+        }
+        let prefix = name.to_string() + SEGMENT_EXT;
+        for file in store.list()? {
+            if file.starts_with(&prefix) && !keep.contains(file.as_str()) {
+                store.delete(&file)?;
+            }
+        }
+        Ok(table.segments.len())
+    }
+}
+
+/// Versioning of saved dialogue trees, and upgrading trees saved by older versions of arbor
+///
+/// Saved trees start with VERSION_MAGIC and the format version, followed by the tree serialized
+/// with bincode. Trees saved before the version header was added are detected by trying each
+/// historical layout in turn, newest first.
+///
+/// Any change to the serialized layout of DialogueTreeData or the types it contains must bump
+/// FORMAT_VERSION, add the previous layout as a struct here, and add a step to Save::upgrade that
+/// converts the previous version to the new one.
+pub mod migrate {
+    use super::*;
+    use bincode::Options;
+
+    /// Version of the layout written by this version of arbor
+    pub const FORMAT_VERSION: u32 = 16;
+
+    /// Magic bytes at the start of a versioned dialogue tree, followed by the format version as a
+    /// little endian u32
+    pub const VERSION_MAGIC: &[u8; 4] = b"ARBV";
+
+    /// Node layout of versions 0 to 2, before timed choices
+    #[derive(Serialize, Deserialize)]
+    pub struct DialogueV0 {
+        pub section: Section,
+        pub pos: Position,
+    }
+
+    /// Edge layout of versions 0 to 2, before timed choices
+    #[derive(Serialize, Deserialize)]
+    pub struct ChoiceV0 {
+        pub section: Section,
+        pub requirement: ReqKind,
+        pub effect: EffectKind,
+    }
+
+    /// Tree layout of versions 0 to 2, before timed choices
+    #[derive(Serialize, Deserialize)]
+    pub struct TreeV0 {
+        pub nodes: Vec<DialogueV0>,
+        pub edges: Vec<ChoiceV0>,
+        pub node_links: Vec<EdgeIndex>,
+        pub edge_links: Vec<EdgeIndex>,
+        pub edge_sources: Vec<NodeIndex>,
+        pub edge_targets: Vec<NodeIndex>,
+    }
+
+    /// Node layout of versions 3 to 6, before content flags
+    #[derive(Serialize, Deserialize)]
+    pub struct DialogueV3 {
+        pub section: Section,
+        pub pos: Position,
+        pub timeout: Option<u32>,
+    }
+
+    /// Edge layout of versions 3 to 5, before call edges
+    #[derive(Serialize, Deserialize)]
+    pub struct ChoiceV3 {
+        pub section: Section,
+        pub requirement: ReqKind,
+        pub effect: EffectKind,
+        pub default: bool,
+    }
+
+    /// Tree layout of versions 3 to 5, before call edges
+    #[derive(Serialize, Deserialize)]
+    pub struct TreeV3 {
+        pub nodes: Vec<DialogueV3>,
+        pub edges: Vec<ChoiceV3>,
+        pub node_links: Vec<EdgeIndex>,
+        pub edge_links: Vec<EdgeIndex>,
+        pub edge_sources: Vec<NodeIndex>,
+        pub edge_targets: Vec<NodeIndex>,
+    }
+
+    /// Edge layout of versions 6 to 10, before line ids
+    #[derive(Serialize, Deserialize)]
+    pub struct ChoiceV6 {
+        pub section: Section,
+        pub requirement: ReqKind,
+        pub effect: EffectKind,
+        pub default: bool,
+        pub call: bool,
+    }
+
+    /// Tree layout of version 6, before content flags
+    #[derive(Serialize, Deserialize)]
+    pub struct TreeV6 {
+        pub nodes: Vec<DialogueV3>,
+        pub edges: Vec<ChoiceV6>,
+        pub node_links: Vec<EdgeIndex>,
+        pub edge_links: Vec<EdgeIndex>,
+        pub edge_sources: Vec<NodeIndex>,
+        pub edge_targets: Vec<NodeIndex>,
+    }
+
+    /// Node layout of versions 7 and 8, before positions moved to the layout table
+    #[derive(Serialize, Deserialize)]
+    pub struct DialogueV7 {
+        pub section: Section,
+        pub pos: Position,
+        pub timeout: Option<u32>,
+        pub flags: ContentFlags,
+    }
+
+    /// Tree layout of versions 7 and 8, before positions moved to the layout table
+    #[derive(Serialize, Deserialize)]
+    pub struct TreeV7 {
+        pub nodes: Vec<DialogueV7>,
+        pub edges: Vec<ChoiceV6>,
+        pub node_links: Vec<EdgeIndex>,
+        pub edge_links: Vec<EdgeIndex>,
+        pub edge_sources: Vec<NodeIndex>,
+        pub edge_targets: Vec<NodeIndex>,
+    }
+
+    /// Node layout of versions 9 and 10, before line ids
+    #[derive(Serialize, Deserialize)]
+    pub struct DialogueV9 {
+        pub section: Section,
+        pub timeout: Option<u32>,
+        pub flags: ContentFlags,
+    }
+
+    /// Tree layout of versions 9 and 10, before line ids
+    #[derive(Serialize, Deserialize)]
+    pub struct TreeV9 {
+        pub nodes: Vec<DialogueV9>,
+        pub edges: Vec<ChoiceV6>,
+        pub node_links: Vec<EdgeIndex>,
+        pub edge_links: Vec<EdgeIndex>,
+        pub edge_sources: Vec<NodeIndex>,
+        pub edge_targets: Vec<NodeIndex>,
+    }
+
+    impl TreeV9 {
+        /// Give every node and edge a line id, nodes first and then edges, in index order.
+        /// Returns the tree and the last line id given
+        pub fn with_lines(self) -> (Tree, u32) {
+            let mut next_line = 0;
+            let mut line = || {
+                next_line += 1;
+                LineId {
+                    id: next_line,
+                    revision: 0,
+                }
+            };
+            let nodes = self
+                .nodes
+                .into_iter()
+                .map(|n| Dialogue {
+                    timeout: n.timeout,
+                    flags: n.flags,
+                    line: line(),
+                    ..Dialogue::new(n.section)
+                })
+                .collect();
+            let edges = self
+                .edges
+                .into_iter()
+                .map(|e| Choice {
+                    default: e.default,
+                    call: e.call,
+                    line: line(),
+                    ..Choice::new(e.section, e.requirement, e.effect)
+                })
+                .collect();
+            let tree = Tree {
+                nodes,
+                edges,
+                node_links: self.node_links,
+                edge_links: self.edge_links,
+                edge_sources: self.edge_sources,
+                edge_targets: self.edge_targets,
+            };
+            (tree, next_line)
+        }
+    }
+
+    impl TreeV7 {
+        /// Split the node positions out of the tree. Nodes at the origin are left out of the
+        /// layout table
+        pub fn split(self) -> (TreeV9, LayoutTableV9) {
+            let layout = self
+                .nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, n)| n.pos != Position::default())
+                .map(|(index, n)| (index, n.pos))
+                .collect();
+            let tree = TreeV9 {
+                nodes: self
+                    .nodes
+                    .into_iter()
+                    .map(|n| DialogueV9 {
+                        section: n.section,
+                        timeout: n.timeout,
+                        flags: n.flags,
+                    })
+                    .collect(),
+                edges: self.edges,
+                node_links: self.node_links,
+                edge_links: self.edge_links,
+                edge_sources: self.edge_sources,
+                edge_targets: self.edge_targets,
+            };
+            (tree, layout)
+        }
+    }
+
+    impl From<TreeV6> for TreeV7 {
+        fn from(tree: TreeV6) -> Self {
+            TreeV7 {
+                nodes: tree
+                    .nodes
+                    .into_iter()
+                    .map(|n| DialogueV7 {
+                        section: n.section,
+                        pos: n.pos,
+                        timeout: n.timeout,
+                        flags: ContentFlags::default(),
+                    })
+                    .collect(),
+                edges: tree.edges,
+                node_links: tree.node_links,
+                edge_links: tree.edge_links,
+                edge_sources: tree.edge_sources,
+                edge_targets: tree.edge_targets,
+            }
+        }
+    }
+
+    impl From<TreeV3> for TreeV6 {
+        fn from(tree: TreeV3) -> Self {
+            TreeV6 {
+                nodes: tree.nodes,
+                edges: tree
+                    .edges
+                    .into_iter()
+                    .map(|e| ChoiceV6 {
+                        section: e.section,
+                        requirement: e.requirement,
+                        effect: e.effect,
+                        default: e.default,
+                        call: false,
+                    })
+                    .collect(),
+                node_links: tree.node_links,
+                edge_links: tree.edge_links,
+                edge_sources: tree.edge_sources,
+                edge_targets: tree.edge_targets,
+            }
+        }
+    }
+
+    impl From<TreeV0> for TreeV3 {
+        fn from(tree: TreeV0) -> Self {
+            let nodes = tree.nodes.into_iter();
+            let edges = tree.edges.into_iter();
+            TreeV3 {
+                nodes: nodes
+                    .map(|n| DialogueV3 {
+                        section: n.section,
+                        pos: n.pos,
+                        timeout: None,
+                    })
+                    .collect(),
+                edges: edges
+                    .map(|e| ChoiceV3 {
+                        section: e.section,
+                        requirement: e.requirement,
+                        effect: e.effect,
+                        default: false,
+                    })
+                    .collect(),
+                node_links: tree.node_links,
+                edge_links: tree.edge_links,
+                edge_sources: tree.edge_sources,
+                edge_targets: tree.edge_targets,
+            }
+        }
+    }
+
+    /// Version 0, the original layout. The uid was a usize
+    #[derive(Serialize, Deserialize)]
+    pub struct V0 {
+        pub uid: u64,
+        pub tree: TreeV0,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+    }
+
+    /// Version 1, added the bookmark table
+    #[derive(Serialize, Deserialize)]
+    pub struct V1 {
+        pub uid: u64,
+        pub tree: TreeV0,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+    }
+
+    /// Version 2, widened the uid to 128 bits
+    #[derive(Serialize, Deserialize)]
+    pub struct V2 {
+        pub uid: Uid,
+        pub tree: TreeV0,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+    }
+
+    /// Version 3, added choice timeouts to nodes and the default choice marker to edges
+    #[derive(Serialize, Deserialize)]
+    pub struct V3 {
+        pub uid: Uid,
+        pub tree: TreeV3,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+    }
+
+    /// Version 4, added the direction table
+    #[derive(Serialize, Deserialize)]
+    pub struct V4 {
+        pub uid: Uid,
+        pub tree: TreeV3,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+    }
+
+    /// Version 5, added interrupts
+    #[derive(Serialize, Deserialize)]
+    pub struct V5 {
+        pub uid: Uid,
+        pub tree: TreeV3,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+        pub interrupts: Vec<Interrupt>,
+    }
+
+    /// Version 6, added call edges
+    #[derive(Serialize, Deserialize)]
+    pub struct V6 {
+        pub uid: Uid,
+        pub tree: TreeV6,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+        pub interrupts: Vec<Interrupt>,
+    }
+
+    /// Version 7, added content flags
+    #[derive(Serialize, Deserialize)]
+    pub struct V7 {
+        pub uid: Uid,
+        pub tree: TreeV7,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+        pub interrupts: Vec<Interrupt>,
+    }
+
+    /// Version 8, added the asset table and asset root
+    #[derive(Serialize, Deserialize)]
+    pub struct V8 {
+        pub uid: Uid,
+        pub tree: TreeV7,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+        pub interrupts: Vec<Interrupt>,
+        pub asset_root: String,
+        pub assets: AssetTable,
+    }
+
+    /// Version 9, moved node positions out of the tree into the layout table
+    #[derive(Serialize, Deserialize)]
+    pub struct V9 {
+        pub uid: Uid,
+        pub tree: TreeV9,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+        pub layout: LayoutTableV9,
+        pub interrupts: Vec<Interrupt>,
+        pub asset_root: String,
+        pub assets: AssetTable,
+    }
+
+    /// Version 10, added shared libraries
+    #[derive(Serialize, Deserialize)]
+    pub struct V10 {
+        pub uid: Uid,
+        pub tree: TreeV9,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+        pub layout: LayoutTableV9,
+        pub interrupts: Vec<Interrupt>,
+        pub asset_root: String,
+        pub assets: AssetTable,
+        pub libraries: Vec<String>,
+    }
+
+    /// Version 11, added line ids
+    #[derive(Serialize, Deserialize)]
+    pub struct V11 {
+        pub uid: Uid,
+        pub tree: Tree,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+        pub layout: LayoutTableV9,
+        pub interrupts: Vec<Interrupt>,
+        pub asset_root: String,
+        pub assets: AssetTable,
+        pub libraries: Vec<String>,
+        pub next_line: u32,
+    }
+
+    /// Version 12, added the choice policy
+    #[derive(Serialize, Deserialize)]
+    pub struct V12 {
+        pub uid: Uid,
+        pub tree: Tree,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+        pub layout: LayoutTableV9,
+        pub interrupts: Vec<Interrupt>,
+        pub asset_root: String,
+        pub assets: AssetTable,
+        pub libraries: Vec<String>,
+        pub next_line: u32,
+        pub choice_policy: ChoicePolicy,
+    }
+
+    /// Version 13, added starting value presets
+    #[derive(Serialize, Deserialize)]
+    pub struct V13 {
+        pub uid: Uid,
+        pub tree: Tree,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+        pub layout: LayoutTableV9,
+        pub interrupts: Vec<Interrupt>,
+        pub asset_root: String,
+        pub assets: AssetTable,
+        pub libraries: Vec<String>,
+        pub next_line: u32,
+        pub choice_policy: ChoicePolicy,
+        pub presets: PresetTable,
+    }
+
+    /// Layout table of versions 9 to 15, keyed by node index rather than line id
+    pub type LayoutTableV9 = HashMap<NodeIndex, Position>;
+
+    /// Version 14, added on-enter effects
+    #[derive(Serialize, Deserialize)]
+    pub struct V14 {
+        pub uid: Uid,
+        pub tree: Tree,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+        pub layout: LayoutTableV9,
+        pub interrupts: Vec<Interrupt>,
+        pub asset_root: String,
+        pub assets: AssetTable,
+        pub libraries: Vec<String>,
+        pub next_line: u32,
+        pub choice_policy: ChoicePolicy,
+        pub presets: PresetTable,
+        pub on_enter: OnEnterTable,
+    }
+
+    /// Version 15, added naming rules
+    #[derive(Serialize, Deserialize)]
+    pub struct V15 {
+        pub uid: Uid,
+        pub tree: Tree,
+        pub text: String,
+        pub name_table: NameTable,
+        pub val_table: ValTable,
+        pub name: String,
+        pub bookmarks: BookmarkTable,
+        pub directions: DirectionTable,
+        pub layout: LayoutTableV9,
+        pub interrupts: Vec<Interrupt>,
+        pub asset_root: String,
+        pub assets: AssetTable,
+        pub libraries: Vec<String>,
+        pub next_line: u32,
+        pub choice_policy: ChoicePolicy,
+        pub presets: PresetTable,
+        pub on_enter: OnEnterTable,
+        pub naming: NamingRules,
+    }
+
+    /// A dialogue tree in any version of the save format. Version 16 keyed the layout table by
+    /// line id, and is the current layout of DialogueTreeData, boxed to keep the enum small
+    pub enum Save {
+        V0(V0),
+        V1(V1),
+        V2(V2),
+        V3(V3),
+        V4(V4),
+        V5(V5),
+        V6(V6),
+        V7(V7),
+        V8(V8),
+        V9(V9),
+        V10(V10),
+        V11(V11),
+        V12(V12),
+        V13(V13),
+        V14(V14),
+        V15(V15),
+        V16(Box<DialogueTreeData>),
+    }
+
+    impl Save {
+        /// Version of the layout the tree was saved with
+        pub fn version(&self) -> u32 {
+            match self {
+                Save::V0(_) => 0,
+                Save::V1(_) => 1,
+                Save::V2(_) => 2,
+                Save::V3(_) => 3,
+                Save::V4(_) => 4,
+                Save::V5(_) => 5,
+                Save::V6(_) => 6,
+                Save::V7(_) => 7,
+                Save::V8(_) => 8,
+                Save::V9(_) => 9,
+                Save::V10(_) => 10,
+                Save::V11(_) => 11,
+                Save::V12(_) => 12,
+                Save::V13(_) => 13,
+                Save::V14(_) => 14,
+                Save::V15(_) => 15,
+                Save::V16(_) => 16,
+            }
+        }
+
+        /// Upgrade the tree by a single version. A tree in the current version is returned as is
+        pub fn upgrade(self) -> Save {
+            match self {
+                Save::V0(v0) => Save::V1(V1 {
+                    uid: v0.uid,
+                    tree: v0.tree,
+                    text: v0.text,
+                    name_table: v0.name_table,
+                    val_table: v0.val_table,
+                    name: v0.name,
+                    bookmarks: BookmarkTable::default(),
+                }),
+                Save::V1(v1) => Save::V2(V2 {
+                    uid: Uid(v1.uid as u128),
+                    tree: v1.tree,
+                    text: v1.text,
+                    name_table: v1.name_table,
+                    val_table: v1.val_table,
+                    name: v1.name,
+                    bookmarks: v1.bookmarks,
+                }),
+                Save::V2(v2) => Save::V3(V3 {
+                    uid: v2.uid,
+                    tree: v2.tree.into(),
+                    text: v2.text,
+                    name_table: v2.name_table,
+                    val_table: v2.val_table,
+                    name: v2.name,
+                    bookmarks: v2.bookmarks,
+                }),
+                Save::V3(v3) => Save::V4(V4 {
+                    uid: v3.uid,
+                    tree: v3.tree,
+                    text: v3.text,
+                    name_table: v3.name_table,
+                    val_table: v3.val_table,
+                    name: v3.name,
+                    bookmarks: v3.bookmarks,
+                    directions: DirectionTable::default(),
+                }),
+                Save::V4(v4) => Save::V5(V5 {
+                    uid: v4.uid,
+                    tree: v4.tree,
+                    text: v4.text,
+                    name_table: v4.name_table,
+                    val_table: v4.val_table,
+                    name: v4.name,
+                    bookmarks: v4.bookmarks,
+                    directions: v4.directions,
+                    interrupts: Vec::new(),
+                }),
+                Save::V5(v5) => Save::V6(V6 {
+                    uid: v5.uid,
+                    tree: v5.tree.into(),
+                    text: v5.text,
+                    name_table: v5.name_table,
+                    val_table: v5.val_table,
+                    name: v5.name,
+                    bookmarks: v5.bookmarks,
+                    directions: v5.directions,
+                    interrupts: v5.interrupts,
+                }),
+                Save::V6(v6) => Save::V7(V7 {
+                    uid: v6.uid,
+                    tree: v6.tree.into(),
+                    text: v6.text,
+                    name_table: v6.name_table,
+                    val_table: v6.val_table,
+                    name: v6.name,
+                    bookmarks: v6.bookmarks,
+                    directions: v6.directions,
+                    interrupts: v6.interrupts,
+                }),
+                Save::V7(v7) => Save::V8(V8 {
+                    uid: v7.uid,
+                    tree: v7.tree,
+                    text: v7.text,
+                    name_table: v7.name_table,
+                    val_table: v7.val_table,
+                    name: v7.name,
+                    bookmarks: v7.bookmarks,
+                    directions: v7.directions,
+                    interrupts: v7.interrupts,
+                    asset_root: String::new(),
+                    assets: AssetTable::default(),
+                }),
+                Save::V8(v8) => {
+                    let (tree, layout) = v8.tree.split();
+                    Save::V9(V9 {
+                        uid: v8.uid,
+                        tree,
+                        text: v8.text,
+                        name_table: v8.name_table,
+                        val_table: v8.val_table,
+                        name: v8.name,
+                        bookmarks: v8.bookmarks,
+                        directions: v8.directions,
+                        layout,
+                        interrupts: v8.interrupts,
+                        asset_root: v8.asset_root,
+                        assets: v8.assets,
+                    })
+                }
+                Save::V9(v9) => Save::V10(V10 {
+                    uid: v9.uid,
+                    tree: v9.tree,
+                    text: v9.text,
+                    name_table: v9.name_table,
+                    val_table: v9.val_table,
+                    name: v9.name,
+                    bookmarks: v9.bookmarks,
+                    directions: v9.directions,
+                    layout: v9.layout,
+                    interrupts: v9.interrupts,
+                    asset_root: v9.asset_root,
+                    assets: v9.assets,
+                    libraries: Vec::new(),
+                }),
+                Save::V10(v10) => {
+                    let (tree, next_line) = v10.tree.with_lines();
+                    Save::V11(V11 {
+                        uid: v10.uid,
+                        tree,
+                        text: v10.text,
+                        name_table: v10.name_table,
+                        val_table: v10.val_table,
+                        name: v10.name,
+                        bookmarks: v10.bookmarks,
+                        directions: v10.directions,
+                        layout: v10.layout,
+                        interrupts: v10.interrupts,
+                        asset_root: v10.asset_root,
+                        assets: v10.assets,
+                        libraries: v10.libraries,
+                        next_line,
+                    })
+                }
+                Save::V11(v11) => Save::V12(V12 {
+                    uid: v11.uid,
+                    tree: v11.tree,
+                    text: v11.text,
+                    name_table: v11.name_table,
+                    val_table: v11.val_table,
+                    name: v11.name,
+                    bookmarks: v11.bookmarks,
+                    directions: v11.directions,
+                    layout: v11.layout,
+                    interrupts: v11.interrupts,
+                    asset_root: v11.asset_root,
+                    assets: v11.assets,
+                    libraries: v11.libraries,
+                    next_line: v11.next_line,
+                    choice_policy: ChoicePolicy::default(),
+                }),
+                Save::V12(v12) => Save::V13(V13 {
+                    uid: v12.uid,
+                    tree: v12.tree,
+                    text: v12.text,
+                    name_table: v12.name_table,
+                    val_table: v12.val_table,
+                    name: v12.name,
+                    bookmarks: v12.bookmarks,
+                    directions: v12.directions,
+                    layout: v12.layout,
+                    interrupts: v12.interrupts,
+                    asset_root: v12.asset_root,
+                    assets: v12.assets,
+                    libraries: v12.libraries,
+                    next_line: v12.next_line,
+                    choice_policy: v12.choice_policy,
+                    presets: PresetTable::new(),
+                }),
+                Save::V13(v13) => Save::V14(V14 {
+                    uid: v13.uid,
+                    tree: v13.tree,
+                    text: v13.text,
+                    name_table: v13.name_table,
+                    val_table: v13.val_table,
+                    name: v13.name,
+                    bookmarks: v13.bookmarks,
+                    directions: v13.directions,
+                    layout: v13.layout,
+                    interrupts: v13.interrupts,
+                    asset_root: v13.asset_root,
+                    assets: v13.assets,
+                    libraries: v13.libraries,
+                    next_line: v13.next_line,
+                    choice_policy: v13.choice_policy,
+                    presets: v13.presets,
+                    on_enter: OnEnterTable::default(),
+                }),
+                Save::V14(v14) => Save::V15(V15 {
+                    uid: v14.uid,
+                    tree: v14.tree,
+                    text: v14.text,
+                    name_table: v14.name_table,
+                    val_table: v14.val_table,
+                    name: v14.name,
+                    bookmarks: v14.bookmarks,
+                    directions: v14.directions,
+                    layout: v14.layout,
+                    interrupts: v14.interrupts,
+                    asset_root: v14.asset_root,
+                    assets: v14.assets,
+                    libraries: v14.libraries,
+                    next_line: v14.next_line,
+                    choice_policy: v14.choice_policy,
+                    presets: v14.presets,
+                    on_enter: v14.on_enter,
+                    naming: NamingRules::default(),
+                }),
+                Save::V15(v15) => {
+                    let mut data = DialogueTreeData::new(&v15.name);
+                    // positions of nodes that no longer exist are dropped
+                    data.layout = v15
+                        .layout
+                        .iter()
+                        .filter_map(|(&index, &pos)| {
+                            v15.tree.nodes().get(index).map(|n| (n.line.id, pos))
+                        })
+                        .collect();
+                    data.uid = v15.uid;
+                    data.tree = v15.tree;
+                    data.text = v15.text;
+                    data.name_table = v15.name_table;
+                    data.val_table = v15.val_table;
+                    data.bookmarks = v15.bookmarks;
+                    data.directions = v15.directions;
+                    data.interrupts = v15.interrupts;
+                    data.asset_root = v15.asset_root;
+                    data.assets = v15.assets;
+                    data.libraries = v15.libraries;
+                    data.next_line = v15.next_line;
+                    data.choice_policy = v15.choice_policy;
+                    data.presets = v15.presets;
+                    data.on_enter = v15.on_enter;
+                    data.naming = v15.naming;
+                    Save::V16(Box::new(data))
+                }
+                current @ Save::V16(_) => current,
+            }
+        }
+
+        /// Upgrade the tree one version at a time until it is in the current version
+        pub fn into_current(mut self) -> DialogueTreeData {
+            loop {
+                match self {
+                    Save::V16(data) => return *data,
+                    old => {
+                        debug!("upgrade saved tree from version {}", old.version());
+                        self = old.upgrade();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Serialize a dialogue tree in the current version, with the version header
+    pub fn encode(data: &DialogueTreeData) -> Result<Vec<u8>> {
+        let mut encoded = VERSION_MAGIC.to_vec();
+        encoded.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut encoded, data)?;
+        Ok(encoded)
+    }
+
+    /// Deserialize a dialogue tree saved in any version, upgrading it to the current version
+    ///
+    /// # Errors
+    /// Error with UnsupportedVersion if the tree was saved by a newer version of arbor, or
+    /// UnrecognizedFormat if an unversioned tree does not match any historical layout
+    pub fn decode(bytes: &[u8]) -> Result<DialogueTreeData> {
+        let save = match split_header(bytes) {
+            Some((version, body)) => decode_version(version, body)?,
+            None => decode_unversioned(bytes)?,
+        };
+        Ok(save.into_current())
+    }
+
+    /// Read the uid of a serialized dialogue tree. Only the start of a versioned tree is decoded
+    pub fn read_uid(bytes: &[u8]) -> Result<Uid> {
+        match split_header(bytes) {
+            Some((version, body)) if version >= 2 => Ok(bincode::deserialize(body)?),
+            Some((version, body)) if version <= FORMAT_VERSION => {
+                Ok(Uid(bincode::deserialize::<u64>(body)? as u128))
+            }
+            Some((version, _)) => Err(unsupported(version)),
+            None => Ok(decode_unversioned(bytes)?.into_current().uid),
+        }
+    }
+
+    /// Split a versioned tree into its format version and body. Returns None if the tree has no
+    /// version header
+    fn split_header(bytes: &[u8]) -> Option<(u32, &[u8])> {
+        let rest = bytes.strip_prefix(&VERSION_MAGIC[..])?;
+        if rest.len() < 4 {
+            return None;
+        }
+        let (version, body) = rest.split_at(4);
+        Some((
+            u32::from_le_bytes([version[0], version[1], version[2], version[3]]),
+            body,
+        ))
+    }
+
+    fn unsupported(version: u32) -> anyhow::Error {
+        cmd::Error::UnsupportedVersion(version, FORMAT_VERSION).into()
+    }
+
+    /// Deserialize the body of a tree in a known version
+    fn decode_version(version: u32, body: &[u8]) -> Result<Save> {
+        Ok(match version {
+            0 => Save::V0(bincode::deserialize(body)?),
+            1 => Save::V1(bincode::deserialize(body)?),
+            2 => Save::V2(bincode::deserialize(body)?),
+            3 => Save::V3(bincode::deserialize(body)?),
+            4 => Save::V4(bincode::deserialize(body)?),
+            5 => Save::V5(bincode::deserialize(body)?),
+            6 => Save::V6(bincode::deserialize(body)?),
+            7 => Save::V7(bincode::deserialize(body)?),
+            8 => Save::V8(bincode::deserialize(body)?),
+            9 => Save::V9(bincode::deserialize(body)?),
+            10 => Save::V10(bincode::deserialize(body)?),
+            11 => Save::V11(bincode::deserialize(body)?),
+            12 => Save::V12(bincode::deserialize(body)?),
+            13 => Save::V13(bincode::deserialize(body)?),
+            14 => Save::V14(bincode::deserialize(body)?),
+            15 => Save::V15(bincode::deserialize(body)?),
+            16 => Save::V16(bincode::deserialize(body)?),
+            _ => return Err(unsupported(version)),
+        })
+    }
+
+    /// Deserialize a tree saved before the version header was added, by trying each layout from
+    /// newest to oldest. A layout only matches if it uses every byte of the tree
+    fn decode_unversioned(bytes: &[u8]) -> Result<Save> {
+        let options = || {
+            bincode::DefaultOptions::new()
+                .with_fixint_encoding()
+                .reject_trailing_bytes()
+        };
+        if let Ok(v2) = options().deserialize(bytes) {
+            return Ok(Save::V2(v2));
+        }
+        if let Ok(v1) = options().deserialize(bytes) {
+            return Ok(Save::V1(v1));
+        }
+        if let Ok(v0) = options().deserialize(bytes) {
+            return Ok(Save::V0(v0));
+        }
+        Err(cmd::Error::UnrecognizedFormat.into())
+    }
+}
+
+/// Persistent id of a line of text in a node or edge, so that recorded voiceover can be matched
+/// to its line across edits
+///
+/// The id is given to a node or edge when it is created and kept for its lifetime, even when its
+/// index changes as other nodes and edges are removed. The revision counts the edits to the text
+/// of the line, see Tree::edit_node. An id of 0 marks a line that was never given an id, such as a
+/// node added to a Tree directly. Written as L<id>.<revision>, e.g. L00012.3
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default, Hash)]
+pub struct LineId {
+    pub id: u32,
+    pub revision: u32,
+}
+
+impl std::fmt::Display for LineId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "L{:05}.{}", self.id, self.revision)
+    }
+}
+
+/// Order that choices leaving a node are offered in, see ChoicePolicy
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChoiceOrder {
+    /// The order the edges were linked in while authoring
+    #[default]
+    Authored,
+    /// By the text of the choice with names substituted, ignoring case
+    Alphabetical,
+    /// Authored order, but choices without a requirement before those with one
+    RequirementsLast,
+}
+
+impl std::fmt::Display for ChoiceOrder {
+    /// Format the order in the same format that is accepted by from_str
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChoiceOrder::Authored => write!(f, "authored"),
+            ChoiceOrder::Alphabetical => write!(f, "alphabetical"),
+            ChoiceOrder::RequirementsLast => write!(f, "requirements-last"),
+        }
+    }
+}
+
+impl std::str::FromStr for ChoiceOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "authored" => Ok(ChoiceOrder::Authored),
+            "alphabetical" => Ok(ChoiceOrder::Alphabetical),
+            "requirements-last" => Ok(ChoiceOrder::RequirementsLast),
+            _ => Err(anyhow::anyhow!(
+                "expected authored, alphabetical, or requirements-last, found {}",
+                s
+            )),
+        }
+    }
+}
+
+/// How choices whose requirement is not met are shown to the player, see ChoicePolicy
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnmetChoices {
+    /// Shown, but can't be selected
+    #[default]
+    Disable,
+    /// Left out of the choices
+    Hide,
+}
+
+impl std::fmt::Display for UnmetChoices {
+    /// Format the setting in the same format that is accepted by from_str
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnmetChoices::Disable => write!(f, "disable"),
+            UnmetChoices::Hide => write!(f, "hide"),
+        }
+    }
+}
+
+impl std::str::FromStr for UnmetChoices {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(UnmetChoices::Disable),
+            "hide" => Ok(UnmetChoices::Hide),
+            _ => Err(anyhow::anyhow!("expected disable or hide, found {}", s)),
+        }
+    }
+}
+
+/// Project settings for how runners and readers should order and show the choices leaving a
+/// node. Followed by the runner, the list command, and exports, so that every view of the project
+/// offers choices the same way the game will, see runner::choice_states
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChoicePolicy {
+    pub order: ChoiceOrder,
+    pub unmet: UnmetChoices,
+}
+
+impl ChoicePolicy {
+    /// Sort choices into the order of the policy. The sorts are stable, so choices that compare
+    /// equal keep their authored order
+    pub fn sort(&self, choices: &mut [runner::ChoiceState]) {
+        match self.order {
+            ChoiceOrder::Authored => {}
+            ChoiceOrder::Alphabetical => {
+                choices.sort_by_cached_key(|choice| choice.text.to_lowercase())
+            }
+            ChoiceOrder::RequirementsLast => {
+                choices.sort_by_key(|choice| choice.requirement != ReqKind::No)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ChoicePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} order, {} unmet", self.order, self.unmet)
+    }
+}
+
+/// Casing that name and val keys must follow, see NamingRules
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyCasing {
+    /// Keys may be written in any case
+    #[default]
+    Any,
+    /// Lowercase words joined by underscores, e.g. rus_lit
+    Snake,
+    /// Lowercase first word, with later words capitalized and no separators, e.g. rusLit
+    Camel,
+    /// Uppercase words joined by underscores, e.g. RUS_LIT
+    UpperSnake,
+}
+
+impl KeyCasing {
+    /// Check if a key follows the casing. The scope prefix of a val key is not part of the key,
+    /// see ValScope::unscoped
+    pub fn matches(&self, key: &str) -> bool {
+        let words_ok = |upper: bool| {
+            key.split('_').all(|word| {
+                !word.is_empty()
+                    && word.chars().all(|c| {
+                        c.is_ascii_digit()
+                            || (upper && c.is_ascii_uppercase())
+                            || (!upper && c.is_ascii_lowercase())
+                    })
+            })
+        };
+        match self {
+            KeyCasing::Any => true,
+            KeyCasing::Snake => words_ok(false),
+            KeyCasing::UpperSnake => words_ok(true),
+            KeyCasing::Camel => {
+                key.starts_with(|c: char| c.is_ascii_lowercase())
+                    && key.chars().all(|c| c.is_ascii_alphanumeric())
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for KeyCasing {
+    /// Format the casing in the same format that is accepted by from_str
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyCasing::Any => write!(f, "any"),
+            KeyCasing::Snake => write!(f, "snake"),
+            KeyCasing::Camel => write!(f, "camel"),
+            KeyCasing::UpperSnake => write!(f, "upper-snake"),
+        }
+    }
+}
+
+impl std::str::FromStr for KeyCasing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(KeyCasing::Any),
+            "snake" => Ok(KeyCasing::Snake),
+            "camel" => Ok(KeyCasing::Camel),
+            "upper-snake" => Ok(KeyCasing::UpperSnake),
+            _ => Err(anyhow::anyhow!(
+                "expected any, snake, camel, or upper-snake, found {}",
+                s
+            )),
+        }
+    }
+}
+
+/// Project conventions for naming keys and tagging nodes, so that teams sharing a large project
+/// keep it consistent. Breaking a rule is not an error, the check command reports it as a
+/// warning, see cmd::util::lint_names. The default rules allow everything
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct NamingRules {
+    /// Casing of every name and val key
+    pub casing: KeyCasing,
+    /// Prefix that the keys of speakers must start with, empty for no rule. The narrator is
+    /// exempt
+    pub speaker_prefix: String,
+    /// Keys longer than this are reported, to keep keys shorter than the hard limit of
+    /// KEY_MAX_LEN. None for no limit
+    pub max_key_len: Option<usize>,
+    /// Content flags that nodes may be tagged with, None to allow every flag
+    pub tags: Option<ContentFlags>,
+}
+
+impl std::fmt::Display for NamingRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} case", self.casing)?;
+        if !self.speaker_prefix.is_empty() {
+            write!(f, ", speaker prefix {}", self.speaker_prefix)?;
+        }
+        if let Some(max) = self.max_key_len {
+            write!(f, ", keys up to {} characters", max)?;
+        }
+        match self.tags {
+            Some(tags) => write!(f, ", tags {}", tags),
+            None => write!(f, ", any tags"),
+        }
+    }
+}
+
+/// Struct storing the information for a player choice. Stored in the edges of a dialogue tree
+#[derive(new, Debug, Serialize, Deserialize, Clone)]
+pub struct Choice {
+    pub section: Section,
+    pub requirement: ReqKind,
+    pub effect: EffectKind,
+    /// Taken automatically when the timeout of the source node runs out, see Dialogue::timeout
+    #[new(default)]
+    pub default: bool,
+    /// Traversed like a subroutine call. Once the conversation at the target reaches a node with
+    /// no choices, the playthrough returns to the source node, see Runner::return_from_call
+    #[new(default)]
+    pub call: bool,
+    /// Persistent id of the choice text, see DialogueTreeData::new_line
+    #[new(default)]
+    pub line: LineId,
+}
+
+/// Struct for storing the information for a line of dialogue. Stored in the nodes of a dialogue
+/// tree
+#[derive(new, Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct Dialogue {
+    pub section: Section,
+    /// Time in milliseconds the player has to pick a choice, after which the default choice is
+    /// taken. None if the choices are not timed
+    #[new(default)]
+    pub timeout: Option<u32>,
+    /// Content warnings for the dialogue, used to strip or replace the node in censored exports
+    #[new(default)]
+    pub flags: ContentFlags,
+    /// Persistent id of the dialogue text, see DialogueTreeData::new_line
+    #[new(default)]
+    pub line: LineId,
+}
+
+/// Set of content warnings attached to a node, such as violence or spoilers
+///
+/// Flags are written as a comma separated list of names, for example "violence,spoiler", or
+/// "none" for the empty set
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Default)]
+pub struct ContentFlags(u8);
+
+impl ContentFlags {
+    pub const VIOLENCE: ContentFlags = ContentFlags(1);
+    pub const PROFANITY: ContentFlags = ContentFlags(1 << 1);
+    pub const SPOILER: ContentFlags = ContentFlags(1 << 2);
+
+    /// Name of each flag, in the format accepted by from_str
+    pub const NAMES: [(&'static str, ContentFlags); 3] = [
+        ("violence", ContentFlags::VIOLENCE),
+        ("profanity", ContentFlags::PROFANITY),
+        ("spoiler", ContentFlags::SPOILER),
+    ];
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Check if any flag is in both sets
+    pub fn intersects(&self, other: ContentFlags) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Flags in this set that are not in the other
+    pub fn difference(&self, other: ContentFlags) -> ContentFlags {
+        ContentFlags(self.0 & !other.0)
+    }
+
+    /// Names of the flags in the set
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        ContentFlags::NAMES
+            .iter()
+            .filter(move |(_, flag)| self.intersects(*flag))
+            .map(|(name, _)| *name)
+    }
+}
+
+impl std::ops::BitOr for ContentFlags {
+    type Output = ContentFlags;
+
+    fn bitor(self, rhs: ContentFlags) -> ContentFlags {
+        ContentFlags(self.0 | rhs.0)
+    }
+}
+
+impl std::str::FromStr for ContentFlags {
+    type Err = cmd::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.trim() == "none" {
+            return Ok(ContentFlags::default());
+        }
+        s.split(',')
+            .try_fold(ContentFlags::default(), |flags, name| {
+                let name = name.trim();
+                ContentFlags::NAMES
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .map(|(_, flag)| flags | *flag)
+                    .ok_or_else(|| cmd::Error::UnknownContentFlag(name.to_string()))
+            })
+    }
+}
+
+impl std::fmt::Display for ContentFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let names: Vec<&str> = self.names().collect();
+        write!(f, "{}", names.join(","))
+    }
+}
+
+/// A stage direction attached to a node, such as a character entering or an animation playing.
+/// Directions are not part of the dialogue text, they are passed to the game engine so that it can
+/// present the dialogue
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub enum Direction {
+    /// A character enters the scene
+    Enter(KeyString),
+    /// A character leaves the scene
+    Exit(KeyString),
+    /// A character plays the named animation
+    Animate(KeyString, NameString),
+    /// The background changes to the named scene
+    Background(NameString),
+}
+
+impl Direction {
+    /// Names of each kind of direction, in the format accepted by from_str
+    pub const KINDS: [&'static str; 4] = ["Enter", "Exit", "Animate", "Background"];
+
+    /// The name table key of the character the direction applies to, if any
+    pub fn character(&self) -> Option<&KeyString> {
+        match self {
+            Direction::Enter(key) | Direction::Exit(key) | Direction::Animate(key, _) => Some(key),
+            Direction::Background(_) => None,
+        }
+    }
+}
+
+impl std::str::FromStr for Direction {
+    type Err = anyhow::Error;
+
+    /// Parse a direction in the same format as it is declared, e.g. 'Animate(cat,wave)'
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        info!("Parsing Direction from string");
+        let (kind, args) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or(cmd::Error::Generic)?;
+        let mut args = args.split(',');
+        let mut next = || args.next().ok_or(cmd::Error::Generic);
+        let direction = match kind {
+            "Enter" => Direction::Enter(KeyString::from(next()?).map_err(|e| e.simplify())?),
+            "Exit" => Direction::Exit(KeyString::from(next()?).map_err(|e| e.simplify())?),
+            "Animate" => Direction::Animate(
+                KeyString::from(next()?).map_err(|e| e.simplify())?,
+                NameString::from(next()?).map_err(|e| e.simplify())?,
+            ),
+            "Background" => {
+                Direction::Background(NameString::from(next()?).map_err(|e| e.simplify())?)
+            }
+            _ => return Err(cmd::Error::Generic.into()),
+        };
+        anyhow::ensure!(args.next().is_none(), cmd::Error::Generic);
+        Ok(direction)
+    }
+}
+
+impl std::fmt::Display for Direction {
+    /// Format the direction in the same format that is accepted by from_str
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Enter(key) => write!(f, "Enter({})", key),
+            Direction::Exit(key) => write!(f, "Exit({})", key),
+            Direction::Animate(key, anim) => write!(f, "Animate({},{})", key, anim),
+            Direction::Background(scene) => write!(f, "Background({})", scene),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub enum ReqKind {
+    /// No requirement
+    No,
+    /// Must be greater than num
+    Greater(KeyString, u32),
+    /// Must be less than num
+    Less(KeyString, u32),
+    /// Must be equal to num
+    Equal(KeyString, u32),
+    /// Must match name string
+    Cmp(KeyString, NameString),
+    /// Must not match name string
+    NotCmp(KeyString, NameString),
+    /// Must match one of the names, written as a list separated by '|', e.g. 'mage|sorcerer'
+    OneOf(KeyString, Vec<NameString>),
+}
+
+impl ReqKind {
+    /// Names of each kind of requirement, in the format accepted by from_str
+    pub const KINDS: [&'static str; 7] =
+        ["No", "Greater", "Less", "Equal", "Cmp", "NotCmp", "OneOf"];
+
+    /// Separator between the names of a OneOf requirement
+    pub const NAME_SEPARATOR: char = '|';
+
+    /// Check if a kind of requirement is tested against a name rather than a value
+    pub fn uses_name(kind: &str) -> bool {
+        matches!(kind, "Cmp" | "NotCmp" | "OneOf")
+    }
+}
+
+impl std::str::FromStr for ReqKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        info!("Parsing ReqKind from string");
+        if s == "No" {
+            return Ok(ReqKind::No);
+        }
+        // Implementation notes:
+        // The enum string format is set up to directly map to how the enum is declared in rust:
+        // e.g. 'GreaterThan(my_key,10)'
+        // This is tokenized on the presence of '(' ',' and ')' special characters. In reverse
+        // order:
+        // e.g. ['', '10', 'my_key', 'GreaterThan']
+        //
+        // This is done in reverse order so that the required key and val can be built up before
+        // converting the enum itself, (since the key and val are required to declare the enum
+        //
+        // Importantly, the 'val' that is tested against can be a string or a u32. This is handled
+        // by waiting to unwrap the val parameter until building the Enum
+        let mut split = s.rsplit(&['(', ',', ')'][..]);
+        debug!("{}", s);
+
+        trace!("Check that first item is ''");
+        anyhow::ensure!(split.next().ok_or(cmd::Error::Generic)?.is_empty());
+
+        trace!(
+            "second item should be number or string, check for valid length, wait to check if int"
+        );
+        let val = match NameString::from(split.next().ok_or(cmd::Error::Generic)?) {
+            Ok(v) => Ok(v),
+            Err(e) => Err(e.simplify()),
+        }?;
+
+        trace!("third item should be key, check that the key is a valid length");
+        // match required due to lifetime limitations on CapacityError
+        let key = match KeyString::from(split.next().ok_or(cmd::Error::Generic)?) {
+            Ok(v) => Ok(v),
+            Err(e) => Err(e.simplify()),
+        }?;
+
+        trace!("fourth item should be Enum type, build it!, and also try to resolve the val");
+        match split.next().ok_or(cmd::Error::Generic)? {
+            "Greater" => Ok(ReqKind::Greater(key, val.parse::<u32>()?)),
+            "Less" => Ok(ReqKind::Less(key, val.parse::<u32>()?)),
+            "Equal" => Ok(ReqKind::Equal(key, val.parse::<u32>()?)),
+            "Cmp" => Ok(ReqKind::Cmp(key, val)),
+            "NotCmp" => Ok(ReqKind::NotCmp(key, val)),
+            "OneOf" => {
+                let names = val
+                    .split(ReqKind::NAME_SEPARATOR)
+                    .map(|name| {
+                        anyhow::ensure!(!name.is_empty(), cmd::Error::Generic);
+                        Ok(NameString::from(name).map_err(|e| e.simplify())?)
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(ReqKind::OneOf(key, names))
+            }
+            _ => Err(cmd::Error::Generic.into()),
+        }
+    }
+}
+
+impl std::fmt::Display for ReqKind {
+    /// Format the requirement in the same format that is accepted by from_str
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReqKind::No => write!(f, "No"),
+            ReqKind::Greater(key, val) => write!(f, "Greater({},{})", key, val),
+            ReqKind::Less(key, val) => write!(f, "Less({},{})", key, val),
+            ReqKind::Equal(key, val) => write!(f, "Equal({},{})", key, val),
+            ReqKind::Cmp(key, val) => write!(f, "Cmp({},{})", key, val),
+            ReqKind::NotCmp(key, val) => write!(f, "NotCmp({},{})", key, val),
+            ReqKind::OneOf(key, names) => {
+                write!(f, "OneOf({},", key)?;
+                for (i, name) in names.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, "{}", ReqKind::NAME_SEPARATOR)?;
+                    }
+                    write!(f, "{}", name)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Represents an effect that occurs when a choice is made.
+///
+/// Name length strings are stored as a heap allocated String rather than a static NameString as
+/// that would bloat enum size by 32 bytes, when Cmp will rarely be used compared to val based
+/// requirements
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum EffectKind {
+    /// No effect
+    No,
+    Add(KeyString, u32),
+    Sub(KeyString, u32),
+    Set(KeyString, u32),
+    Assign(KeyString, NameString),
+    /// Ask the player to enter a name, such as their character's name, and store it under the
+    /// key. The runner waits for the answer, see runner::Runner::answer
+    Prompt(KeyString),
+}
+
+impl EffectKind {
+    /// Names of each kind of effect, in the format accepted by from_str
+    pub const KINDS: [&'static str; 6] = ["No", "Add", "Sub", "Set", "Assign", "Prompt"];
+
+    /// Stands in for the name entered for a Prompt effect wherever it is shown before it is
+    /// known
+    pub const PLAYER_INPUT: &'static str = "(player input)";
+
+    /// Check if a kind of effect modifies a name rather than a value
+    pub fn uses_name(kind: &str) -> bool {
+        matches!(kind, "Assign" | "Prompt")
+    }
+
+    /// Check if a kind of effect is written with a value after its key. Every kind of
+    /// requirement is, so this can be used for both
+    pub fn takes_value(kind: &str) -> bool {
+        kind != "Prompt"
+    }
+}
+
+impl std::str::FromStr for EffectKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        info!("Parsing EffectKind from string");
+        if s == "No" {
+            return Ok(EffectKind::No);
+        }
+        // prompts have no value, e.g. 'Prompt(player)'
+        if let Some(key) = s.strip_prefix("Prompt(").and_then(|s| s.strip_suffix(')')) {
+            anyhow::ensure!(!key.contains(&['(', ',', ')'][..]), cmd::Error::Generic);
+            let key = match KeyString::from(key) {
+                Ok(v) => Ok(v),
+                Err(e) => Err(e.simplify()),
+            }?;
+            return Ok(EffectKind::Prompt(key));
+        }
+        // Implementation notes:
+        // The enum string format is set up to directly map to how the enum is declared in rust:
+        // e.g. 'Add(my_key,10)'
+        // This is tokenized on the presence of '(' ',' and ')' special characters. In reverse
+        // order:
+        // e.g. ['', '10', 'my_key', 'Add']
+        //
+        // This is done in reverse order so that the required key and val can be built up before
+        // converting the enum itself, (since the key and val are required to declare the enum.
+        //
+        // Importantly, the 'val' that is tested against can be a string or a u32. This is handled
+        // by waiting to unwrap the val parameter until building the Enum
+        let mut split = s.rsplit(&['(', ',', ')'][..]);
+        debug!("{}", s);
+
+        trace!("First item should be ''");
+        anyhow::ensure!(split.next().ok_or(cmd::Error::Generic)?.is_empty());
+
+        trace!("Second item should be number or string, don't check for validity yet");
+        let val = split.next().ok_or(cmd::Error::Generic)?;
+
+        trace!("Third item should be key, check that the key and name are of a valid length");
+        // match required due to lifetime limitations on CapacityError
+        let key = match KeyString::from(split.next().ok_or(cmd::Error::Generic)?) {
             Ok(v) => Ok(v),
             Err(e) => Err(e.simplify()),
         }?;
 
-        trace!("fourth item should be Enum type, build it!, and also try to resolve the val");
-        match split.next().ok_or(cmd::Error::Generic)? {
-            "Greater" => Ok(ReqKind::Greater(key, val.parse::<u32>()?)),
-            "Less" => Ok(ReqKind::Less(key, val.parse::<u32>()?)),
-            "Equal" => Ok(ReqKind::Equal(key, val.parse::<u32>()?)),
-            "Cmp" => Ok(ReqKind::Cmp(key, val)),
-            _ => Err(cmd::Error::Generic.into()),
+        trace!("fourth item should be Enum type, build it!, and also try to resolve the val");
+        match split.next().ok_or(cmd::Error::Generic)? {
+            "Add" => Ok(EffectKind::Add(key, val.parse::<u32>()?)),
+            "Sub" => Ok(EffectKind::Sub(key, val.parse::<u32>()?)),
+            "Set" => Ok(EffectKind::Set(key, val.parse::<u32>()?)),
+            "Assign" => {
+                // match required due to lifetime limitations on CapacityError
+                let name = match NameString::from(val) {
+                    Ok(v) => Ok(v),
+                    Err(e) => Err(e.simplify()),
+                }?;
+                Ok(EffectKind::Assign(key, name))
+            }
+            _ => Err(cmd::Error::Generic.into()),
+        }
+    }
+}
+
+impl std::fmt::Display for EffectKind {
+    /// Format the effect in the same format that is accepted by from_str
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EffectKind::No => write!(f, "No"),
+            EffectKind::Add(key, val) => write!(f, "Add({},{})", key, val),
+            EffectKind::Sub(key, val) => write!(f, "Sub({},{})", key, val),
+            EffectKind::Set(key, val) => write!(f, "Set({},{})", key, val),
+            EffectKind::Assign(key, val) => write!(f, "Assign({},{})", key, val),
+            EffectKind::Prompt(key) => write!(f, "Prompt({})", key),
+        }
+    }
+}
+
+/// Top level module for all arbor commands. These commands rely heavily on the structopt
+/// derive feature to easily implement a command line interface along with command structs for
+/// input through other methods (UI, test code, etc.). In any structopt derived structure or enum,
+/// the doc comments are displayed to the user through the CLI.
+///
+/// All commands also implement the generic Executable trait. This trait uses enum_dispatch to
+/// propagate through to all types contained in the Parse enums. This executable method is where
+/// the core logic of any command happens.
+pub mod cmd {
+    use super::*;
+
+    /// Error types for different commands
+    ///
+    /// Uses thiserror to generate messages for common situations. This does not
+    /// attempt to implement From trait on any lower level error types, but relies
+    /// on anyhow for unification and printing a stack trace
+    #[derive(Error, Debug)]
+    pub enum Error {
+        #[error("An unspecified error occured...")]
+        Generic,
+        #[error("Node parsing failed")]
+        NodeParse,
+        #[error("Edge parsing failed")]
+        EdgeParse,
+        #[error("The name already exists")]
+        NameExists,
+        #[error("The name does not exist")]
+        NameNotExists,
+        #[error("The name is in use")]
+        NameInUse,
+        #[error("The value already exists")]
+        ValExists,
+        #[error("The value does not exist")]
+        ValNotExists,
+        #[error("The value is in use")]
+        ValInUse,
+        #[error("Attempted to access an invalid section of the text")]
+        InvalidSection,
+        #[error("Hash does not match text section")]
+        InvalidHash,
+        #[error("The event history is empty, undo not possible")]
+        EventHistoryEmpty,
+        #[error("The event future queue is empty, redo not possible")]
+        EventFuturesEmpty,
+        #[error("The undo operation failed")]
+        UndoFailed,
+        #[error("The redo operation failed")]
+        RedoFailed,
+        #[error("Screenplay parsing failed on line {0}")]
+        ScreenplayParse(usize),
+        #[error("The label {0} does not exist")]
+        LabelNotExists(String),
+        #[error("The package does not contain {0}")]
+        PackageEntryNotExists(String),
+        #[error("File uid {0} does not match project uid {1}, use --force to override")]
+        UidMismatch(Uid, Uid),
+        #[error("Edge {0} is not an available choice")]
+        ChoiceUnavailable(usize),
+        #[error("The repaired project must be written to a new file")]
+        RepairOverwrite,
+        #[error("There is no journal of unsaved changes to replay")]
+        NothingToReplay,
+        #[error("The journal was not started from the saved copy of the project")]
+        JournalMismatch,
+        #[error("Invalid uid {0}, expected up to 32 hex digits")]
+        InvalidUid(String),
+        #[error("Rebuild failed, {} problems found in the tree", .0.len())]
+        RebuildFailed(Vec<util::Corruption>),
+        #[error("The editor is busy with a background task")]
+        Busy,
+        #[error("The editor state lock was poisoned by a panicked thread")]
+        LockPoisoned,
+        #[error("Query parsing failed: {0}")]
+        QueryParse(String),
+        #[error("The bookmark already exists")]
+        BookmarkExists,
+        #[error("The bookmark does not exist")]
+        BookmarkNotExists,
+        #[error("The node is bookmarked as {0}, remove the bookmark first")]
+        NodeBookmarked(KeyString),
+        #[error("The editor cursor is not set, use goto to select a node")]
+        NoCursor,
+        #[error("The prompt was cancelled")]
+        PromptCancelled,
+        #[error("Table parsing failed on line {0}, expected key,value")]
+        TableParse(usize),
+        #[error("The value of {0} must be a string or number")]
+        InvalidTableValue(String),
+        #[error("Unknown file format {0:?}")]
+        UnknownFormat(String),
+        #[error("The file {0} does not exist")]
+        FileNotExists(String),
+        #[error("Failed to decompress file: {0}")]
+        Decompress(String),
+        #[error("The text segment {0} is missing or does not match the project file")]
+        SegmentMismatch(String),
+        #[error("The file is encrypted, a key or password is needed to open it")]
+        Encrypted,
+        #[error("The file was saved in format version {0}, this version of arbor reads up to {1}")]
+        UnsupportedVersion(u32, u32),
+        #[error("The file is not a dialogue tree saved by any version of arbor")]
+        UnrecognizedFormat,
+        #[error("Node {0} has timed choices, but no default choice")]
+        TimeoutWithoutDefault(usize),
+        #[error("Node {0} has more than one default choice")]
+        MultipleDefaults(usize),
+        #[error("Edge {0} is a default choice, so it cannot have a requirement")]
+        DefaultHasRequirement(usize),
+        #[error("Node {0} has directions, remove them first")]
+        NodeHasDirections(usize),
+        #[error("A direction on node {0} references the missing character {1}")]
+        DirectionKeyNotExists(usize, KeyString),
+        #[error("Node {0} has no direction at position {1}")]
+        InvalidDirectionPosition(usize, usize),
+        #[error("Node {0} has on-enter effects, remove them first")]
+        NodeHasOnEnter(usize),
+        #[error("Node {0} has no on-enter effect at position {1}")]
+        InvalidOnEnterPosition(usize, usize),
+        #[error("The node is the target of interrupt {0}, remove the interrupt first")]
+        NodeInterruptTarget(usize),
+        #[error("There is no interrupt at position {0}")]
+        InvalidInterruptPosition(usize),
+        #[error("Interrupt {0} has no requirement, so it would always be taken")]
+        InterruptWithoutRequirement(usize),
+        #[error(
+            "Call edge {0} never returns, no node without choices is reachable from its target"
+        )]
+        CallNeverReturns(usize),
+        #[error("There is no call to return from, or the current node still has choices")]
+        NoCallToReturn,
+        #[error(
+            "Unknown scope in val key {0}, use c. for conversation or t. for temporary values"
+        )]
+        UnknownValScope(String),
+        #[error("Failed to encrypt file")]
+        EncryptFailed,
+        #[error("Failed to decrypt file, the key or password is wrong or the file is corrupted")]
+        DecryptFailed,
+        #[error("Encryption is not supported, rebuild with the encryption feature enabled")]
+        EncryptionDisabled,
+        #[error("There is no change {0} in the history")]
+        HistoryOutOfRange(usize),
+        #[error("Change {0} can't be undone on its own: {1}")]
+        RevertConflict(usize, String),
+        #[error("Invalid assumption {0:?}, expected key=value")]
+        InvalidAssumption(String),
+        #[error("Edge {0} does not lead to node {1}")]
+        EdgeNotIncoming(usize, usize),
+        #[error("The CSV file has no {0} column")]
+        ColumnNotExists(String),
+        #[error("Invalid text id {0:?}, expected node:<index> or edge:<index>")]
+        InvalidTextId(String),
+        #[error("Unknown content flag {0:?}, expected violence, profanity, spoiler, or none")]
+        UnknownContentFlag(String),
+        #[error("Censoring needs either a fallback node or replacement text")]
+        CensorWithoutFallback,
+        #[error("The fallback node {0} is censored itself")]
+        CensoredFallback(usize),
+        #[error("The asset {0} already exists")]
+        AssetExists(KeyString),
+        #[error("The asset {0} does not exist")]
+        AssetNotExists(KeyString),
+        #[error("The project {0} is already open")]
+        ProjectOpen(String),
+        #[error("The project {0} is not open")]
+        ProjectNotOpen(String),
+        #[error("The project {0} has unsaved changes, save it first or close with --force")]
+        ProjectModified(String),
+        #[error("The project already uses the library {0}")]
+        LibraryExists(String),
+        #[error("The project does not use the library {0}")]
+        LibraryNotExists(String),
+        #[error("Unknown grouping {0:?}, expected bookmark, flags, or speaker")]
+        UnknownGrouping(String),
+        #[error("The search pattern must not be empty")]
+        EmptyPattern,
+        #[error("Assertion parsing failed on line {0}")]
+        AssertionParse(usize),
+        #[error("The thumbnail is not an image written by arbor")]
+        InvalidThumbnail,
+        #[error("The clipboard does not hold nodes copied from arbor")]
+        InvalidClipboard,
+        #[error("Invalid key mapping {0:?}, expected old=new")]
+        InvalidKeyMap(String),
+        #[error("The preset {0} does not exist")]
+        PresetNotExists(String),
+        #[error("The preset sets {0}, which is not a name or value of the project")]
+        PresetKeyNotExists(KeyString),
+        #[error("The player has not been asked for a name")]
+        NoPrompt,
+        #[error(
+            "Invalid answer {0:?}, expected a name of 1 to {} characters",
+            NAME_MAX_LEN
+        )]
+        InvalidAnswer(String),
+        #[error("There is no alias named {0}")]
+        AliasNotExists(String),
+        #[error("{0} is a built in alias, only user defined aliases can be removed")]
+        BuiltinAlias(String),
+        #[error("{0} is already a command and cannot be used as an alias")]
+        AliasIsCommand(String),
+    }
+
+    /// Stable classification of errors, used as the process exit status so that scripts can
+    /// branch on the kind of failure. The numeric values must not change between versions
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[repr(i32)]
+    pub enum ErrorCode {
+        /// The command succeeded
+        Success = 0,
+        /// A failure that does not fit any other category
+        Generic = 1,
+        /// A command, argument, or input file could not be parsed
+        Parse = 2,
+        /// The command was rejected because the project or its arguments are invalid, or the
+        /// check command found problems
+        Validation = 3,
+        /// Reading or writing a file failed
+        Io = 4,
+        /// Project data is corrupted or in an unrecognized format
+        Corruption = 5,
+    }
+
+    impl ErrorCode {
+        /// Classify an error by the first error in its chain that arbor recognizes
+        pub fn of(e: &anyhow::Error) -> ErrorCode {
+            e.chain()
+                .find_map(|cause| {
+                    if let Some(e) = cause.downcast_ref::<Error>() {
+                        Some(ErrorCode::from(e))
+                    } else if let Some(e) = cause.downcast_ref::<tree::Error>() {
+                        Some(ErrorCode::from(e))
+                    } else if cause.is::<io::Error>() {
+                        Some(ErrorCode::Io)
+                    } else if let Some(e) = cause.downcast_ref::<serde_json::Error>() {
+                        Some(if e.is_io() {
+                            ErrorCode::Io
+                        } else {
+                            ErrorCode::Parse
+                        })
+                    } else if cause.is::<csv::Error>() {
+                        Some(ErrorCode::Parse)
+                    } else if cause.is::<bincode::Error>() {
+                        Some(ErrorCode::Corruption)
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or(ErrorCode::Generic)
+        }
+
+        /// Exit status of the process
+        pub fn code(self) -> i32 {
+            self as i32
+        }
+    }
+
+    impl std::fmt::Display for ErrorCode {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let name = match self {
+                ErrorCode::Success => "success",
+                ErrorCode::Generic => "generic",
+                ErrorCode::Parse => "parse",
+                ErrorCode::Validation => "validation",
+                ErrorCode::Io => "io",
+                ErrorCode::Corruption => "corruption",
+            };
+            write!(f, "{}", name)
+        }
+    }
+
+    impl From<&Error> for ErrorCode {
+        fn from(e: &Error) -> Self {
+            // every variant is listed so that new errors must be given a code
+            match e {
+                Error::NodeParse
+                | Error::EdgeParse
+                | Error::ScreenplayParse(_)
+                | Error::QueryParse(_)
+                | Error::TableParse(_)
+                | Error::InvalidTableValue(_)
+                | Error::InvalidUid(_)
+                | Error::UnknownFormat(_)
+                | Error::UnknownValScope(_)
+                | Error::InvalidAssumption(_)
+                | Error::InvalidTextId(_)
+                | Error::UnknownContentFlag(_)
+                | Error::UnknownGrouping(_)
+                | Error::EmptyPattern
+                | Error::AssertionParse(_)
+                | Error::InvalidClipboard
+                | Error::InvalidKeyMap(_)
+                | Error::ColumnNotExists(_) => ErrorCode::Parse,
+                Error::NameExists
+                | Error::NameNotExists
+                | Error::NameInUse
+                | Error::ValExists
+                | Error::ValNotExists
+                | Error::ValInUse
+                | Error::EventHistoryEmpty
+                | Error::EventFuturesEmpty
+                | Error::LabelNotExists(_)
+                | Error::PackageEntryNotExists(_)
+                | Error::ChoiceUnavailable(_)
+                | Error::RepairOverwrite
+                | Error::NothingToReplay
+                | Error::BookmarkExists
+                | Error::BookmarkNotExists
+                | Error::NodeBookmarked(_)
+                | Error::NoCursor
+                | Error::TimeoutWithoutDefault(_)
+                | Error::MultipleDefaults(_)
+                | Error::DefaultHasRequirement(_)
+                | Error::NodeHasDirections(_)
+                | Error::DirectionKeyNotExists(_, _)
+                | Error::InvalidDirectionPosition(_, _)
+                | Error::NodeHasOnEnter(_)
+                | Error::InvalidOnEnterPosition(_, _)
+                | Error::NodeInterruptTarget(_)
+                | Error::InvalidInterruptPosition(_)
+                | Error::InterruptWithoutRequirement(_)
+                | Error::CallNeverReturns(_)
+                | Error::NoCallToReturn
+                | Error::HistoryOutOfRange(_)
+                | Error::RevertConflict(_, _)
+                | Error::EdgeNotIncoming(_, _)
+                | Error::CensorWithoutFallback
+                | Error::CensoredFallback(_)
+                | Error::AssetExists(_)
+                | Error::AssetNotExists(_)
+                | Error::ProjectOpen(_)
+                | Error::ProjectNotOpen(_)
+                | Error::ProjectModified(_)
+                | Error::LibraryExists(_)
+                | Error::LibraryNotExists(_)
+                | Error::PresetNotExists(_)
+                | Error::PresetKeyNotExists(_)
+                | Error::NoPrompt
+                | Error::InvalidAnswer(_)
+                | Error::AliasNotExists(_)
+                | Error::BuiltinAlias(_)
+                | Error::AliasIsCommand(_) => ErrorCode::Validation,
+                Error::FileNotExists(_) => ErrorCode::Io,
+                Error::InvalidSection
+                | Error::InvalidHash
+                | Error::UidMismatch(_, _)
+                | Error::JournalMismatch
+                | Error::RebuildFailed(_)
+                | Error::Decompress(_)
+                | Error::SegmentMismatch(_)
+                | Error::UnsupportedVersion(_, _)
+                | Error::UnrecognizedFormat
+                | Error::DecryptFailed
+                | Error::InvalidThumbnail => ErrorCode::Corruption,
+                Error::Generic
+                | Error::UndoFailed
+                | Error::RedoFailed
+                | Error::Busy
+                | Error::LockPoisoned
+                | Error::PromptCancelled
+                | Error::Encrypted
+                | Error::EncryptFailed
+                | Error::EncryptionDisabled => ErrorCode::Generic,
+            }
+        }
+    }
+
+    impl From<&tree::Error> for ErrorCode {
+        fn from(e: &tree::Error) -> Self {
+            match e {
+                tree::Error::InvalidEdgeLinks => ErrorCode::Corruption,
+                tree::Error::InvalidNodeIndex
+                | tree::Error::InvalidEdgeIndex
+                | tree::Error::NodeInUse
+                | tree::Error::NodesFull
+                | tree::Error::WouldCreateCycle => ErrorCode::Validation,
+            }
+        }
+    }
+
+    /// Trait to allow structopt generated
+    #[enum_dispatch]
+    pub trait Executable {
+        fn execute(&self, state: &mut EditorState) -> Result<usize>;
+    }
+
+    /// A tree based dialogue editor
+    // NoBinaryName is set so that the first arg is not parsed as binary name when using
+    // StructOpt::from_iter_safe
+    // name is set as "" to prevent usage help from recommending to start commands with "arbor"
+    #[enum_dispatch(Executable)]
+    #[derive(StructOpt)]
+    #[structopt(name="", setting = AppSettings::NoBinaryName)]
+    pub enum Parse {
+        New(new::Parse),
+        Edit(edit::Parse),
+        Remove(remove::Parse),
+        Rename(rename::Parse),
+        Clone(clone::Parse),
+        Import(import::Parse),
+        Export(export::Parse),
+        Save(Save),
+        Package(Package),
+        Load(Load),
+        Recover(Recover),
+        Purge(Purge),
+        Replay(Replay),
+        Repair(Repair),
+        Rebuild(Rebuild),
+        Swap(Swap),
+        Meta(Meta),
+        Stats(Stats),
+        Analyze(Analyze),
+        List(List),
+        Undo(Undo),
+        Redo(Redo),
+        History(history::Parse),
+        Project(project::Parse),
+        Bookmark(bookmark::Parse),
+        Direction(direction::Parse),
+        OnEnter(on_enter::Parse),
+        Interrupt(interrupt::Parse),
+        Asset(asset::Parse),
+        Library(library::Parse),
+        Preset(preset::Parse),
+        Report(report::Parse),
+        Check(Check),
+        Goto(Goto),
+        Children(Children),
+        Parent(Parent),
+        Reparent(Reparent),
+        WhereUsed(WhereUsed),
+        Find(Find),
+        Replace(Replace),
+        Test(Test),
+        Simulate(Simulate),
+    }
+
+    pub mod new {
+        use super::*;
+
+        /// Create new things
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Project(new::Project),
+            Node(new::Node),
+            Edge(new::Edge),
+            Branch(new::Branch),
+            Name(new::Name),
+            Val(new::Val),
+        }
+
+        /// Create a new project
+        ///
+        /// A project is made up of a text rope storing all dialogue text, a hashtable storing
+        /// variable or user defined values, and a graph representing the narrative. Nodes of the
+        /// graph represent dialogues from characters in the story, and nodes represent the
+        /// actions of the player.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Project {
+            /// The name of the project
+            name: String,
+
+            /// Determine if the project should be loaded as the active project after creation. If
+            /// any unsaved changes in the current project will be discarded.
+            #[structopt(short, long)]
+            set_active: bool,
+        }
+
+        impl Executable for Project {
+            /// New Project
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                let new_project = DialogueTreeData::new(self.name.as_str());
+
+                let _res = store::write_data(
+                    state.store.as_ref(),
+                    &(self.name.clone() + TREE_EXT),
+                    &new_project,
+                );
+
+                if self.set_active {
+                    let open = std::mem::take(&mut state.open);
+                    *state = EditorState::with_backups(
+                        new_project,
+                        state.store.clone(),
+                        state.backups.clone(),
+                    );
+                    state.open = open;
+                }
+                Ok(state.active.uid.low())
+            }
+        }
+
+        /// Create a new node in the dialogue tree
+        ///
+        /// A node represents a text a segment of dialogue from a character.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Node {
+            /// The speaker for this node. The speaker name must be a key in the name table
+            speaker: String,
+            /// The text or action for this node
+            dialogue: String,
+        }
+
+        impl Executable for Node {
+            /// New Node
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Creating new node");
+
+                trace!("verify the speaker name is valid");
+                util::speaker_name(&self.speaker, &state.active.name_table)
+                    .ok_or(cmd::Error::NameNotExists)?;
+
+                trace!("push dialogue to text buffer");
+                let start = state.active.text.len();
+                state.active.text.push_str(&format!(
+                    "{}{}{}{}",
+                    TOKEN_SEP, self.speaker, TOKEN_SEP, self.dialogue
+                ));
+                let end = state.active.text.len();
+                debug!("start: {}, end: {}", start, end);
+
+                trace!("compute hash from text section");
+                let hash = hash(&state.active.text[start..end].as_bytes());
+                debug!("hash {}", hash);
+
+                let dialogue = Dialogue {
+                    line: state.active.new_line(),
+                    ..Dialogue::new(Section::new([start, end], hash))
+                };
+
+                trace!("add new node to tree");
+                let event = state.active.tree.add_node(dialogue)?;
+                let idx = event.index;
+                state.history.push(event.into(), &mut state.active);
+
+                Ok(idx)
+            }
+        }
+
+        /// Create a new edge in the dialogue tree
+        ///
+        /// An edge represents an action from the player that connects two nodes
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Edge {
+            /// Source node index, target node index, and action text or dialogue. If the source
+            /// is omitted, the edge originates from the node at the editor cursor
+            #[structopt(required = true, min_values = 2, max_values = 3)]
+            args: Vec<String>,
+            /// Requirement for accessing this edge
+            #[structopt(short = "r")]
+            requirement: Option<ReqKind>,
+
+            /// Effect caused by accessing this edge
+            #[structopt(short = "e")]
+            effect: Option<EffectKind>,
+
+            /// Prompt step by step for any requirement or effect not given on the command line
+            #[structopt(short, long)]
+            interactive: bool,
+        }
+
+        impl Edge {
+            pub fn new(
+                source: usize,
+                target: usize,
+                text: String,
+                requirement: Option<ReqKind>,
+                effect: Option<EffectKind>,
+            ) -> Self {
+                Edge {
+                    args: vec![source.to_string(), target.to_string(), text],
+                    requirement,
+                    effect,
+                    interactive: false,
+                }
+            }
+
+            /// Create an edge that originates from the node at the editor cursor
+            pub fn from_cursor(
+                target: usize,
+                text: String,
+                requirement: Option<ReqKind>,
+                effect: Option<EffectKind>,
+            ) -> Self {
+                Edge {
+                    args: vec![target.to_string(), text],
+                    requirement,
+                    effect,
+                    interactive: false,
+                }
+            }
+
+            /// Resolve the source, target, and text of the edge from the arguments
+            fn endpoints<'a>(&'a self, state: &EditorState) -> Result<(usize, usize, &'a str)> {
+                match self.args.as_slice() {
+                    [source, target, text] => Ok((source.parse()?, target.parse()?, text)),
+                    [target, text] => Ok((
+                        state.active.cursor.ok_or(cmd::Error::NoCursor)?,
+                        target.parse()?,
+                        text,
+                    )),
+                    _ => Err(cmd::Error::Generic.into()),
+                }
+            }
+        }
+
+        impl Executable for Edge {
+            /// New Edge
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Creating new edge");
+                let (source, target, text) = self.endpoints(state)?;
+                let mut requirement = self.requirement.clone();
+                let mut effect = self.effect;
+
+                if self.interactive {
+                    trace!("prompt for requirement and effect");
+                    let mut ask = |prompt: &str| {
+                        let mut buf = String::new();
+                        println!("{}", prompt);
+                        util::prompt_input(&mut buf);
+                        buf.trim().to_string()
+                    };
+                    if requirement.is_none() {
+                        requirement = Some(util::prompt_requirement(
+                            &mut ask,
+                            &state.active.name_table,
+                            &state.active.val_table,
+                        )?);
+                    }
+                    if effect.is_none() {
+                        effect = Some(util::prompt_effect(
+                            &mut ask,
+                            &state.active.name_table,
+                            &state.active.val_table,
+                        )?);
+                    }
+                }
+
+                trace!("push choice text buffer");
+                let start = state.active.text.len();
+                state.active.text.push_str(text);
+                let end = state.active.text.len();
+                debug!("start: {}, end: {}", start, end);
+
+                trace!("Compute hash from text section");
+                let hash = hash(&state.active.text[start..end].as_bytes());
+                debug!("hash {}", hash);
+
+                trace!("Validate that any requirements/effects reference valid hashmap keys");
+                if requirement.is_some() {
+                    util::validate_requirement(
+                        requirement.as_ref().ok_or(cmd::Error::Generic)?,
+                        &state.active.name_table,
+                        &state.active.val_table,
+                    )?;
+                }
+                if effect.is_some() {
+                    util::validate_effect(
+                        effect.as_ref().ok_or(cmd::Error::Generic)?,
+                        &state.active.name_table,
+                        &state.active.val_table,
+                    )?;
+                }
+
+                let choice = Choice {
+                    line: state.active.new_line(),
+                    ..Choice::new(
+                        Section::new([start, end], hash),
+                        requirement.unwrap_or(ReqKind::No),
+                        effect.unwrap_or(EffectKind::No),
+                    )
+                };
+
+                trace!("Adding new edge to tree");
+                let event = state.active.tree.add_edge(source, target, choice)?;
+                let idx = event.index;
+
+                state.history.push(event.into(), &mut state.active);
+                Ok(idx)
+            }
+        }
+
+        /// Create a new node along with a choice leading to it
+        ///
+        /// The node and edge are recorded as a single event, so they can be removed with one
+        /// undo. The indices of both are printed to the editor scratchpad, and the index of the
+        /// new node is returned.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Branch {
+            /// Index of the node the choice leads from
+            source: usize,
+            /// Action text or dialogue of the choice
+            choice: String,
+            /// The speaker for the new node. The speaker name must be a key in the name table
+            speaker: String,
+            /// The text or action for the new node
+            dialogue: String,
+            /// Requirement for accessing the choice
+            #[structopt(short = "r")]
+            #[new(default)]
+            requirement: Option<ReqKind>,
+            /// Effect caused by making the choice
+            #[structopt(short = "e")]
+            #[new(default)]
+            effect: Option<EffectKind>,
+        }
+
+        impl Executable for Branch {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Create new branch from node {}", self.source);
+
+                trace!("validate the speaker, requirement, and effect before changing anything");
+                let data = &mut state.active;
+                data.tree.get_node(self.source)?;
+                util::speaker_name(&self.speaker, &data.name_table)
+                    .ok_or(cmd::Error::NameNotExists)?;
+                if let Some(requirement) = self.requirement.as_ref() {
+                    util::validate_requirement(requirement, &data.name_table, &data.val_table)?;
+                }
+                if let Some(effect) = self.effect.as_ref() {
+                    util::validate_effect(effect, &data.name_table, &data.val_table)?;
+                }
+
+                trace!("add the node, then the edge leading to it");
+                let section = util::push_section(
+                    &mut data.text,
+                    &format!(
+                        "{}{}{}{}",
+                        TOKEN_SEP, self.speaker, TOKEN_SEP, self.dialogue
+                    ),
+                );
+                let node = Dialogue {
+                    line: data.new_line(),
+                    ..Dialogue::new(section)
+                };
+                let node_event = data.tree.add_node(node)?;
+                let node_index = node_event.index;
+                let section = util::push_section(&mut data.text, &self.choice);
+                let choice = Choice {
+                    line: data.new_line(),
+                    ..Choice::new(
+                        section,
+                        self.requirement.clone().unwrap_or(ReqKind::No),
+                        self.effect.unwrap_or(EffectKind::No),
+                    )
+                };
+                let edge_event = match data.tree.add_edge(self.source, node_index, choice) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        trace!("edge failed, remove the node that was already added");
+                        node_event.undo(data)?;
+                        return Err(e);
+                    }
+                };
+                let edge_index = edge_event.index;
+
+                state.history.push(
+                    EventGroup {
+                        events: vec![node_event.into(), edge_event.into()],
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+                state
+                    .scratchpad
+                    .push_str(&format!("node: {}\r\nedge: {}\r\n", node_index, edge_index));
+                Ok(node_index)
+            }
+        }
+
+        /// Create a new name for use in dialogue nodes and actions
+        ///
+        /// A name represents some variable that may be substituted into the text. Examples
+        /// include player names, pronouns, and character traits
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Name {
+            /// The keyword to reference the name with in the text. Maximum length of 8 characters
+            key: KeyString,
+            /// The name to store, able be updated by player actions. Maximum length of 32
+            /// characters
+            name: NameString,
+        }
+        impl Executable for Name {
+            /// New Name
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Create new name");
+
+                trace!("check that key does not already exist");
+                if state.active.name_table.get(self.key.as_str()).is_none() {
+                    trace!("add key and name to table");
+                    state.active.name_table.insert(self.key, self.name);
+
+                    state.history.push(
+                        NameTableInsert {
+                            key: self.key,
+                            name: self.name,
+                        }
+                        .into(),
+                        &mut state.active,
+                    );
+
+                    Ok(0)
+                } else {
+                    Err(cmd::Error::NameExists.into())
+                }
+            }
+        }
+
+        /// Create a new value for use in dialogue nodes and actions
+        ///
+        /// A value represents some variable number that is used as requirements and effects for
+        /// choices. Examples include player skill levels, relationship stats, and presence of an item.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Val {
+            /// The keyword to reference the value with in the dialogue tree. Max length of 8
+            /// characters
+            key: KeyString,
+            /// Value to store, able be updated by player actions
+            value: u32,
+        }
+        impl Executable for Val {
+            /// New Val
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Create new val");
+                ValScope::of(&self.key)?;
+
+                trace!("check that key does not already exist");
+                if state.active.val_table.get(self.key.as_str()).is_none() {
+                    trace!("add key and val to table");
+                    state.active.val_table.insert(self.key, self.value);
+
+                    state.history.push(
+                        ValTableInsert {
+                            key: self.key,
+                            value: self.value,
+                        }
+                        .into(),
+                        &mut state.active,
+                    );
+
+                    Ok(self.value as usize)
+                } else {
+                    Err(cmd::Error::ValExists.into())
+                }
+            }
+        }
+    }
+
+    pub mod edit {
+        use super::*;
+
+        /// Edit existing things
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Node(edit::Node),
+            Edge(edit::Edge),
+            Name(edit::Name),
+            Val(edit::Val),
+            Timeout(edit::Timeout),
+            #[structopt(name = "default")]
+            DefaultChoice(edit::DefaultChoice),
+            Call(edit::Call),
+            Flags(edit::Flags),
+            Tag(edit::Tag),
+            Move(edit::Move),
+            Align(edit::Align),
+            Distribute(edit::Distribute),
+            Snap(edit::Snap),
+            Choices(edit::Choices),
+            Naming(edit::Naming),
+        }
+
+        /// Edit the contents of a node in the dialogue tree
+        ///
+        /// A node represents a text a segment of dialogue from a character.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Node {
+            /// Index of the node to edit
+            node_index: usize,
+            /// The speaker for this node
+            speaker: KeyString,
+            /// The text or action for this node
+            dialogue: String,
+        }
+        impl Executable for Node {
+            /// Edit Node
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Edit node {}", self.node_index);
+
+                trace!("get node weight from tree");
+                let old_node = *state.active.tree.get_node(self.node_index)?;
+
+                trace!("write new dialogue to text buffer, in place if it fits");
+                let (section, rewrite) = util::rewrite_section(
+                    &mut state.active,
+                    &old_node.section,
+                    &format!(
+                        "{}{}{}{}",
+                        TOKEN_SEP, self.speaker, TOKEN_SEP, self.dialogue
+                    ),
+                );
+                let new_node = Dialogue {
+                    section,
+                    ..old_node
+                };
+
+                trace!("update node weight in tree");
+                let event = state.active.tree.edit_node(self.node_index, new_node)?;
+                state
+                    .history
+                    .push(util::with_rewrite(event, rewrite), &mut state.active);
+
+                Ok(self.node_index)
+            }
+        }
+
+        /// Edit the contents of an edge in the dialogue tree
+        ///
+        /// The source and target node of an edge may not be edited, you must remove the edge and
+        /// then create a new one to do this.
+        #[derive(new, StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Edge {
+            /// Id of the edge to edit
+            edge_index: usize,
+            /// Action text or dialogue
+            text: String,
+            /// Requirement for accessing this edge
+            #[structopt(short = "r")]
+            requirement: Option<ReqKind>,
+            /// Effect caused by accessing this edge
+            #[structopt(short = "e")]
+            effect: Option<EffectKind>,
+        }
+
+        impl Executable for Edge {
+            /// Edit Edge
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Edit edge {}", self.edge_index);
+                let old_weight = state.active.tree.get_edge(self.edge_index)?.clone();
+                anyhow::ensure!(
+                    !old_weight.default || matches!(self.requirement, None | Some(ReqKind::No)),
+                    cmd::Error::DefaultHasRequirement(self.edge_index)
+                );
+
+                trace!("validate that any requirements/effects reference valid hashmap keys");
+                if self.requirement.is_some() {
+                    util::validate_requirement(
+                        self.requirement.as_ref().ok_or(cmd::Error::Generic)?,
+                        &state.active.name_table,
+                        &state.active.val_table,
+                    )?;
+                }
+                if self.effect.is_some() {
+                    util::validate_effect(
+                        self.effect.as_ref().ok_or(cmd::Error::Generic)?,
+                        &state.active.name_table,
+                        &state.active.val_table,
+                    )?;
+                }
+
+                trace!("write choice to text buffer, in place if it fits");
+                let (section, rewrite) =
+                    util::rewrite_section(&mut state.active, &old_weight.section, &self.text);
+
+                trace!("update edge weight in tree");
+                let new_weight = Choice {
+                    section,
+                    requirement: self.requirement.clone().unwrap_or(ReqKind::No),
+                    effect: self.effect.unwrap_or(EffectKind::No),
+                    ..old_weight
+                };
+                let event = state.active.tree.edit_edge(self.edge_index, new_weight)?;
+
+                state
+                    .history
+                    .push(util::with_rewrite(event, rewrite), &mut state.active);
+                Ok(self.edge_index)
+            }
+        }
+
+        /// Edit the value of an existing name
+        ///
+        /// A name represents some variable that may be substituted into the text. Examples
+        /// include player names, pronouns, and character traits
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Name {
+            /// The keyword to reference the name with in the text
+            key: KeyString,
+            /// Value of the name to store
+            name: NameString,
+        }
+
+        impl Executable for Name {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Edit name {}", self.key);
+
+                trace!("check that key exists before editing");
+                if state.active.name_table.get(&self.key).is_some() {
+                    let name = state
+                        .active
+                        .name_table
+                        .get_mut(&self.key)
+                        .ok_or(cmd::Error::Generic)?;
+                    let old_name = *name;
+                    debug!("old name: {}, new name: {}", old_name, self.name);
+
+                    trace!("update key-value in name table");
+                    *name = self.name;
+
+                    state.history.push(
+                        NameTableEdit {
+                            key: self.key,
+                            from: old_name,
+                            to: self.name,
+                        }
+                        .into(),
+                        &mut state.active,
+                    );
+
+                    Ok(0)
+                } else {
+                    Err(cmd::Error::NameNotExists.into())
+                }
+            }
+        }
+
+        /// Edit an existing value
+        ///
+        /// A value represents some variable number that is used as requirements and effects for
+        /// choices. Examples include player skill levels, relationship stats, and presence of an item.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Val {
+            /// The keyword to reference the name with in the text
+            key: KeyString,
+            /// Value to store to the name
+            value: u32,
+        }
+
+        impl Executable for Val {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Edit val {}", self.key);
+
+                trace!("check that key exists before editing");
+                if state.active.name_table.get(&self.key).is_some() {
+                    let value = state
+                        .active
+                        .val_table
+                        .get_mut(&self.key)
+                        .ok_or(cmd::Error::Generic)?;
+                    let old_value = *value;
+                    debug!("old val: {}, new val: {}", old_value, self.value);
+
+                    trace!("update key-value in value table");
+                    *value = self.value;
+
+                    state.history.push(
+                        ValTableEdit {
+                            key: self.key,
+                            from: old_value,
+                            to: self.value,
+                        }
+                        .into(),
+                        &mut state.active,
+                    );
+
+                    Ok(self.value as usize)
+                } else {
+                    Err(cmd::Error::ValNotExists.into())
+                }
+            }
+        }
+
+        /// Set a time limit for the choices leaving a node
+        ///
+        /// When the time runs out the game takes the default choice, so the node must have exactly
+        /// one outgoing edge marked as default before a timeout can be set.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Timeout {
+            /// Index of the node with the timed choices
+            node_index: usize,
+            /// Time limit for making a choice, in milliseconds
+            milliseconds: u32,
+        }
+
+        impl Executable for Timeout {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Edit timeout of node {}", self.node_index);
+
+                trace!("check that the node has a single default choice");
+                let defaults = util::default_choices(&state.active.tree, self.node_index)?;
+                anyhow::ensure!(
+                    !defaults.is_empty(),
+                    cmd::Error::TimeoutWithoutDefault(self.node_index)
+                );
+                anyhow::ensure!(
+                    defaults.len() == 1,
+                    cmd::Error::MultipleDefaults(self.node_index)
+                );
+
+                let new_node = Dialogue {
+                    timeout: Some(self.milliseconds),
+                    ..*state.active.tree.get_node(self.node_index)?
+                };
+                let event = state.active.tree.edit_node(self.node_index, new_node)?;
+                state.history.push(event.into(), &mut state.active);
+
+                Ok(self.milliseconds as usize)
+            }
+        }
+
+        /// Set the content flags of a node, replacing any it already has
+        ///
+        /// Flags mark nodes that may be stripped or replaced when packaging or exporting a
+        /// censored build, see the package command.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Flags {
+            /// Index of the node to flag
+            node_index: usize,
+            /// Comma separated list of violence, profanity, and spoiler
+            flags: ContentFlags,
+        }
+
+        impl Executable for Flags {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Edit flags of node {}", self.node_index);
+
+                let new_node = Dialogue {
+                    flags: self.flags,
+                    ..*state.active.tree.get_node(self.node_index)?
+                };
+                let event = state.active.tree.edit_node(self.node_index, new_node)?;
+                state.history.push(event.into(), &mut state.active);
+
+                Ok(self.node_index)
+            }
+        }
+
+        /// Add content flags to several nodes as a single change in the history, keeping any
+        /// flags they already have. Returns the number of nodes tagged
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Tag {
+            /// Comma separated list of violence, profanity, and spoiler
+            flags: ContentFlags,
+            /// Indices of the nodes to tag
+            #[structopt(required_unless = "query")]
+            node_indices: Vec<usize>,
+            /// Also tag the nodes selected by a query, e.g. "select nodes where speaker == cat"
+            #[structopt(short, long)]
+            #[new(default)]
+            query: Option<String>,
+        }
+
+        impl Executable for Tag {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Tag nodes {:?} with {}", self.node_indices, self.flags);
+                let mut node_indices = self.node_indices.clone();
+                if let Some(query) = &self.query {
+                    let query = query.parse::<query::Query>()?;
+                    node_indices.extend(query.select_nodes(&state.active)?);
+                }
+                edit_nodes(state, &node_indices, |_, node| Dialogue {
+                    flags: node.flags | self.flags,
+                    ..node
+                })
+            }
+        }
+
+        /// Move several nodes in the graph view by the same offset, as a single change in the
+        /// history. Returns the number of nodes moved
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(
+            setting = AppSettings::NoBinaryName,
+            setting = AppSettings::AllowNegativeNumbers
+        )]
+        pub struct Move {
+            /// Horizontal offset
+            dx: f32,
+            /// Vertical offset
+            dy: f32,
+            /// Indices of the nodes to move
+            #[structopt(required = true)]
+            node_indices: Vec<usize>,
+        }
+
+        impl Executable for Move {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!(
+                    "Move nodes {:?} by ({}, {})",
+                    self.node_indices, self.dx, self.dy
+                );
+                move_nodes(state, &self.node_indices, |_, pos| {
+                    Position::new(pos.x + self.dx, pos.y + self.dy)
+                })
+            }
+        }
+
+        /// Side of a set of nodes to line them up along
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum Side {
+            Left,
+            Top,
+        }
+
+        impl std::str::FromStr for Side {
+            type Err = anyhow::Error;
+
+            fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+                match s {
+                    "left" => Ok(Side::Left),
+                    "top" => Ok(Side::Top),
+                    _ => Err(anyhow::anyhow!("expected left or top, found {}", s)),
+                }
+            }
+        }
+
+        /// Line up several nodes in the graph view along their left or top edge, as a single
+        /// change in the history. Returns the number of nodes aligned
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Align {
+            /// Side to align, either left or top
+            side: Side,
+            /// Indices of the nodes to align
+            #[structopt(required = true)]
+            node_indices: Vec<usize>,
+        }
+
+        impl Executable for Align {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Align nodes {:?} {:?}", self.node_indices, self.side);
+                let mut min = Position::new(f32::INFINITY, f32::INFINITY);
+                for &node_index in self.node_indices.iter() {
+                    state.active.tree.get_node(node_index)?;
+                    let pos = state.active.position(node_index);
+                    min = Position::new(min.x.min(pos.x), min.y.min(pos.y));
+                }
+                let side = self.side;
+                move_nodes(state, &self.node_indices, |_, pos| match side {
+                    Side::Left => Position::new(min.x, pos.y),
+                    Side::Top => Position::new(pos.x, min.y),
+                })
+            }
+        }
+
+        /// Space several nodes in the graph view evenly between the two outermost nodes, as a
+        /// single change in the history. Nodes keep their order. Returns the number of nodes
+        /// distributed
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Distribute {
+            /// Distribute vertically instead of horizontally
+            #[structopt(short, long)]
+            vertical: bool,
+            /// Indices of the nodes to distribute
+            #[structopt(required = true)]
+            node_indices: Vec<usize>,
+        }
+
+        impl Executable for Distribute {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Distribute nodes {:?}", self.node_indices);
+                let vertical = self.vertical;
+                let coord = |pos: Position| if vertical { pos.y } else { pos.x };
+
+                trace!("order the nodes along the axis");
+                let mut nodes = self.node_indices.clone();
+                nodes.sort_unstable();
+                nodes.dedup();
+                let mut order = Vec::with_capacity(nodes.len());
+                for &node_index in nodes.iter() {
+                    state.active.tree.get_node(node_index)?;
+                    order.push((coord(state.active.position(node_index)), node_index));
+                }
+                order.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+                let (first, last) = match (order.first(), order.last()) {
+                    (Some(first), Some(last)) => (first.0, last.0),
+                    _ => return Ok(0),
+                };
+                let step = (last - first) / (order.len().max(2) - 1) as f32;
+                let targets: HashMap<NodeIndex, f32> = order
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (_, node_index))| (*node_index, first + step * i as f32))
+                    .collect();
+                move_nodes(state, &nodes, |node_index, pos| {
+                    if vertical {
+                        Position::new(pos.x, targets[&node_index])
+                    } else {
+                        Position::new(targets[&node_index], pos.y)
+                    }
+                })
+            }
+        }
+
+        /// Move several nodes in the graph view to the nearest point on a grid, as a single
+        /// change in the history. Returns the number of nodes snapped
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Snap {
+            /// Spacing of the grid
+            grid: f32,
+            /// Indices of the nodes to snap
+            #[structopt(required = true)]
+            node_indices: Vec<usize>,
+        }
+
+        impl Executable for Snap {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Snap nodes {:?} to {}", self.node_indices, self.grid);
+                anyhow::ensure!(
+                    self.grid > 0.0,
+                    "grid spacing must be positive, found {}",
+                    self.grid
+                );
+                let grid = self.grid;
+                move_nodes(state, &self.node_indices, |_, pos| util::snap(pos, grid))
+            }
+        }
+
+        /// Set how runners and readers order and show the choices leaving a node, or print the
+        /// current setting if no option is given
+        ///
+        /// Choices are offered in authored order, alphabetically by their text, or with the
+        /// choices that have requirements after those without. Choices with unmet requirements
+        /// are either shown disabled or hidden. The setting is saved with the project and followed
+        /// by play, list, and the json export.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Choices {
+            /// Order of the choices, one of authored, alphabetical, or requirements-last
+            #[structopt(short, long)]
+            #[new(default)]
+            order: Option<ChoiceOrder>,
+            /// How to show choices with unmet requirements, either disable or hide
+            #[structopt(short, long)]
+            #[new(default)]
+            unmet: Option<UnmetChoices>,
+        }
+
+        impl Executable for Choices {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                let from = state.active.choice_policy;
+                let to = ChoicePolicy {
+                    order: self.order.unwrap_or(from.order),
+                    unmet: self.unmet.unwrap_or(from.unmet),
+                };
+                if to != from {
+                    info!("Set choice policy to {}", to);
+                    state.active.choice_policy = to;
+                    state
+                        .history
+                        .push(ChoicePolicyEdit { from, to }.into(), &mut state.active);
+                }
+                state
+                    .scratchpad
+                    .push_str(&format!("choices: {}\r\n", state.active.choice_policy));
+                println!("{}", state.scratchpad);
+                Ok(0)
+            }
+        }
+
+        /// Set the naming conventions of the project, or print the current rules if no option is
+        /// given
+        ///
+        /// Keys can be required to follow a casing, speaker keys to start with a prefix, and keys
+        /// to stay under a length below the hard limit of 8 characters. Nodes can be limited to a
+        /// vocabulary of content flags. Breaking a rule is not an error, check reports each
+        /// break as a warning. The rules are saved with the project but are not packaged.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Naming {
+            /// Casing of name and val keys, one of any, snake, camel, or upper-snake
+            #[structopt(short, long)]
+            #[new(default)]
+            casing: Option<KeyCasing>,
+            /// Prefix that speaker keys must start with, or "" for no prefix
+            #[structopt(short, long)]
+            #[new(default)]
+            speaker_prefix: Option<String>,
+            /// Longest allowed key length, or 0 for the hard limit
+            #[structopt(short, long)]
+            #[new(default)]
+            max_key_len: Option<usize>,
+            /// Comma separated content flags that nodes may be tagged with, or "any" to allow
+            /// every flag
+            #[structopt(short, long)]
+            #[new(default)]
+            tags: Option<String>,
+        }
+
+        impl Executable for Naming {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                let from = state.active.naming.clone();
+                let mut to = from.clone();
+                if let Some(casing) = self.casing {
+                    to.casing = casing;
+                }
+                if let Some(prefix) = &self.speaker_prefix {
+                    anyhow::ensure!(
+                        prefix.len() < KEY_MAX_LEN,
+                        "speaker prefix must be shorter than {} bytes, found {}",
+                        KEY_MAX_LEN,
+                        prefix
+                    );
+                    to.speaker_prefix = prefix.clone();
+                }
+                if let Some(max) = self.max_key_len {
+                    anyhow::ensure!(
+                        max <= KEY_MAX_LEN,
+                        "key length limit cannot be over {}, found {}",
+                        KEY_MAX_LEN,
+                        max
+                    );
+                    to.max_key_len = if max == 0 { None } else { Some(max) };
+                }
+                if let Some(tags) = &self.tags {
+                    to.tags = match tags.trim() {
+                        "any" => None,
+                        tags => Some(tags.parse::<ContentFlags>()?),
+                    };
+                }
+                if to != from {
+                    info!("Set naming rules to {}", to);
+                    state.active.naming = to.clone();
+                    state
+                        .history
+                        .push(NamingRulesEdit { from, to }.into(), &mut state.active);
+                }
+                state
+                    .scratchpad
+                    .push_str(&format!("naming: {}\r\n", state.active.naming));
+                println!("{}", state.scratchpad);
+                Ok(0)
+            }
+        }
+
+        /// Replace each of a set of nodes with the result of edit, recording the edits as one
+        /// event group. Duplicate indices are edited once. Returns the number of nodes edited
+        fn edit_nodes<F>(state: &mut EditorState, node_indices: &[usize], edit: F) -> Result<usize>
+        where
+            F: Fn(NodeIndex, Dialogue) -> Dialogue,
+        {
+            let mut nodes = node_indices.to_vec();
+            nodes.sort_unstable();
+            nodes.dedup();
+            for &node_index in nodes.iter() {
+                state.active.tree.get_node(node_index)?;
+            }
+            let mut events: Vec<DialogueTreeEvent> = Vec::with_capacity(nodes.len());
+            for &node_index in nodes.iter() {
+                let new_node = edit(node_index, *state.active.tree.get_node(node_index)?);
+                events.push(state.active.tree.edit_node(node_index, new_node)?.into());
+            }
+            state
+                .history
+                .push(EventGroup { events }.into(), &mut state.active);
+            Ok(nodes.len())
+        }
+
+        /// Replace the position of each of a set of nodes with the result of place, recording the
+        /// moves as one event group. Duplicate indices are moved once. Returns the number of nodes
+        /// moved
+        fn move_nodes<F>(state: &mut EditorState, node_indices: &[usize], place: F) -> Result<usize>
+        where
+            F: Fn(NodeIndex, Position) -> Position,
+        {
+            let mut nodes = node_indices.to_vec();
+            nodes.sort_unstable();
+            nodes.dedup();
+            for &node_index in nodes.iter() {
+                state.active.tree.get_node(node_index)?;
+            }
+            let mut events: Vec<DialogueTreeEvent> = Vec::with_capacity(nodes.len());
+            for &node_index in nodes.iter() {
+                let pos = place(node_index, state.active.position(node_index));
+                events.push(state.active.set_position(node_index, Some(pos))?.into());
+            }
+            state
+                .history
+                .push(EventGroup { events }.into(), &mut state.active);
+            Ok(nodes.len())
+        }
+
+        /// Mark an edge as the default choice of its source node
+        ///
+        /// The default choice is taken when a timed choice runs out. Any other default choice from
+        /// the same node is unmarked.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct DefaultChoice {
+            /// Id of the edge to mark as default
+            edge_index: usize,
+        }
+
+        impl Executable for DefaultChoice {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Edit default choice {}", self.edge_index);
+
+                trace!("check that the edge is always available");
+                let choice = state.active.tree.get_edge(self.edge_index)?.clone();
+                anyhow::ensure!(
+                    choice.requirement == ReqKind::No,
+                    cmd::Error::DefaultHasRequirement(self.edge_index)
+                );
+
+                let source = state.active.tree.source_of(self.edge_index)?;
+                let mut events: Vec<DialogueTreeEvent> = Vec::new();
+
+                trace!("unmark other default choices from the same node");
+                for index in util::default_choices(&state.active.tree, source)? {
+                    if index != self.edge_index {
+                        let old = state.active.tree.get_edge(index)?.clone();
+                        let event = state.active.tree.edit_edge(
+                            index,
+                            Choice {
+                                default: false,
+                                ..old
+                            },
+                        )?;
+                        events.push(event.into());
+                    }
+                }
+
+                let event = state.active.tree.edit_edge(
+                    self.edge_index,
+                    Choice {
+                        default: true,
+                        ..choice
+                    },
+                )?;
+                events.push(event.into());
+
+                state
+                    .history
+                    .push(EventGroup { events }.into(), &mut state.active);
+                Ok(self.edge_index)
+            }
+        }
+
+        /// Make an edge a call edge, which returns to its source node once the conversation at
+        /// its target reaches a node with no choices
+        ///
+        /// Calls allow a shared conversation to be reused from many places without duplicating it.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Call {
+            /// Id of the edge to make a call
+            edge_index: usize,
+        }
+
+        impl Executable for Call {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Edit call {}", self.edge_index);
+
+                trace!("check that the call can return");
+                anyhow::ensure!(
+                    util::call_returns(&state.active.tree, self.edge_index)?,
+                    cmd::Error::CallNeverReturns(self.edge_index)
+                );
+
+                let new_choice = Choice {
+                    call: true,
+                    ..state.active.tree.get_edge(self.edge_index)?.clone()
+                };
+                let event = state.active.tree.edit_edge(self.edge_index, new_choice)?;
+                state.history.push(event.into(), &mut state.active);
+
+                Ok(self.edge_index)
+            }
+        }
+    }
+
+    pub mod remove {
+        use super::*;
+
+        /// Remove existing things
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Node(remove::Node),
+            Nodes(remove::Nodes),
+            Edge(remove::Edge),
+            Name(remove::Name),
+            Val(remove::Val),
+            Timeout(remove::Timeout),
+            #[structopt(name = "default")]
+            DefaultChoice(remove::DefaultChoice),
+            Call(remove::Call),
+            Flags(remove::Flags),
+        }
+
+        /// Remove the contents of a node in the dialogue tree and return the hash of the removed
+        /// node's text section
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Node {
+            /// Index of the node to remove
+            node_index: usize,
+        }
+        impl Executable for Node {
+            /// Remove Node
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove node {}", self.node_index);
+
+                trace!("remove directions, on-enter effects, and position along with the node");
+                let mut events: Vec<DialogueTreeEvent> = Vec::new();
+                if state.active.directions.contains_key(&self.node_index) {
+                    let event = state.active.set_directions(self.node_index, Vec::new())?;
+                    events.push(event.into());
+                }
+                if state.active.on_enter.contains_key(&self.node_index) {
+                    let event = state.active.set_on_enter(self.node_index, Vec::new())?;
+                    events.push(event.into());
+                }
+                if state.active.placement(self.node_index).is_some() {
+                    let event = state.active.set_position(self.node_index, None)?;
+                    events.push(event.into());
+                }
+
+                let event = match state.active.remove_node(self.node_index) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        trace!("remove failed, restore the directions, effects, and position");
+                        EventGroup { events }.undo(&mut state.active)?;
+                        return Err(e);
+                    }
+                };
+                let hash = event.node.section.hash;
+
+                if events.is_empty() {
+                    state.history.push(event.into(), &mut state.active);
+                } else {
+                    events.push(event.into());
+                    state
+                        .history
+                        .push(EventGroup { events }.into(), &mut state.active);
+                }
+                Ok(hash as usize)
+            }
+        }
+
+        /// Remove several nodes, along with every edge leading to or from them, as a single
+        /// change in the history. Returns the number of nodes removed
+        ///
+        /// Nodes are removed from the highest index down, so that the indices given are not
+        /// invalidated by earlier removals. Nothing is removed if any node cannot be removed.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Nodes {
+            /// Indices of the nodes to remove
+            #[structopt(required = true)]
+            node_indices: Vec<usize>,
+        }
+
+        impl Executable for Nodes {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove nodes {:?}", self.node_indices);
+                let mut nodes = self.node_indices.clone();
+                nodes.sort_unstable();
+                nodes.dedup();
+                for &node_index in nodes.iter() {
+                    state.active.tree.get_node(node_index)?;
+                }
+
+                trace!("find edges leading to or from the nodes");
+                let tree = &state.active.tree;
+                let mut edges = Vec::new();
+                for edge_index in 0..tree.edges().len() {
+                    let source = tree.source_of(edge_index)?;
+                    let target = tree.target_of(edge_index)?;
+                    let source_removed = nodes.binary_search(&source).is_ok();
+                    if source_removed || nodes.binary_search(&target).is_ok() {
+                        // a timed node that is kept must not lose its default choice
+                        anyhow::ensure!(
+                            source_removed
+                                || !tree.get_edge(edge_index)?.default
+                                || tree.get_node(source)?.timeout.is_none(),
+                            cmd::Error::TimeoutWithoutDefault(source)
+                        );
+                        edges.push(edge_index);
+                    }
+                }
+
+                let mut events: Vec<DialogueTreeEvent> = Vec::new();
+                let cursor = state.active.cursor;
+                if let Err(e) = remove_all(&mut state.active, &edges, &nodes, &mut events) {
+                    trace!("remove failed, restore the removed edges, nodes, and cursor");
+                    EventGroup { events }.undo(&mut state.active)?;
+                    state.active.cursor = cursor;
+                    return Err(e);
+                }
+
+                state
+                    .history
+                    .push(EventGroup { events }.into(), &mut state.active);
+                Ok(nodes.len())
+            }
+        }
+
+        /// Remove edges and then nodes, each from the highest index down, recording an event for
+        /// every change
+        fn remove_all(
+            data: &mut DialogueTreeData,
+            edges: &[EdgeIndex],
+            nodes: &[NodeIndex],
+            events: &mut Vec<DialogueTreeEvent>,
+        ) -> Result<()> {
+            for &edge_index in edges.iter().rev() {
+                events.push(data.tree.remove_edge(edge_index)?.into());
+            }
+            for &node_index in nodes.iter().rev() {
+                if data.directions.contains_key(&node_index) {
+                    events.push(data.set_directions(node_index, Vec::new())?.into());
+                }
+                if data.on_enter.contains_key(&node_index) {
+                    events.push(data.set_on_enter(node_index, Vec::new())?.into());
+                }
+                if data.placement(node_index).is_some() {
+                    events.push(data.set_position(node_index, None)?.into());
+                }
+                events.push(data.remove_node(node_index)?.into());
+            }
+            Ok(())
+        }
+
+        /// Remove an edge from the dialogue tree and return the hash of the removed edge's text
+        /// section
+        #[derive(new, StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Edge {
+            /// Id of the edge to remove
+            edge_index: usize,
+        }
+
+        impl Executable for Edge {
+            /// Remove Edge
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove Edge {}", self.edge_index);
+
+                trace!("check that a timed node is not left without a default choice");
+                let source = state.active.tree.source_of(self.edge_index)?;
+                anyhow::ensure!(
+                    !state.active.tree.get_edge(self.edge_index)?.default
+                        || state.active.tree.get_node(source)?.timeout.is_none(),
+                    cmd::Error::TimeoutWithoutDefault(source)
+                );
+
+                trace!("remove edge from tree");
+                let event = state.active.tree.remove_edge(self.edge_index)?;
+                let hash = event.edge.section.hash;
+
+                state.history.push(event.into(), &mut state.active);
+                Ok(hash as usize)
+            }
+        }
+
+        /// Remove a name, only allowed if the name is not used anywhere
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Name {
+            /// The keyword to reference the name with in the text
+            key: KeyString,
+        }
+
+        impl Executable for Name {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove Name {}", self.key);
+
+                let name = *state
+                    .active
+                    .name_table
+                    .get(&self.key)
+                    .ok_or(cmd::Error::NameNotExists)?;
+
+                trace!("check if the key is referenced anywhere in the tree");
+                if !util::key_references(&state.active, util::KeyKind::Name, &self.key).is_empty() {
+                    return Err(cmd::Error::NameInUse.into());
+                }
+
+                trace!("remove key-value pair from name table");
+                state
+                    .active
+                    .name_table
+                    .remove(self.key.as_str())
+                    .ok_or(cmd::Error::NameNotExists)?;
+
+                state.history.push(
+                    NameTableRemove {
+                        key: self.key,
+                        name,
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+
+                Ok(0)
+            }
+        }
+
+        /// Remove a value, only allowed if the value is not used anywhere
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Val {
+            /// The keyword to reference the name with in the text
+            key: KeyString,
+        }
+
+        impl Executable for Val {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("remove value {}", self.key);
+
+                let value = *state
+                    .active
+                    .val_table
+                    .get(&self.key)
+                    .ok_or(cmd::Error::ValNotExists)?;
+
+                trace!("check if the key is referenced in any requirements or effects");
+                if !util::key_references(&state.active, util::KeyKind::Val, &self.key).is_empty() {
+                    return Err(cmd::Error::ValInUse.into());
+                }
+
+                trace!("remove key-value pair from value table");
+                state
+                    .active
+                    .val_table
+                    .remove(self.key.as_str())
+                    .ok_or(cmd::Error::NameNotExists)?;
+
+                state.history.push(
+                    ValTableRemove {
+                        key: self.key,
+                        val: value,
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+
+                Ok(0)
+            }
+        }
+
+        /// Remove the time limit from the choices leaving a node
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Timeout {
+            /// Index of the node with the timed choices
+            node_index: usize,
+        }
+
+        impl Executable for Timeout {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove timeout of node {}", self.node_index);
+
+                let new_node = Dialogue {
+                    timeout: None,
+                    ..*state.active.tree.get_node(self.node_index)?
+                };
+                let event = state.active.tree.edit_node(self.node_index, new_node)?;
+                state.history.push(event.into(), &mut state.active);
+
+                Ok(self.node_index)
+            }
+        }
+
+        /// Remove all content flags from a node
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Flags {
+            /// Index of the flagged node
+            node_index: usize,
+        }
+
+        impl Executable for Flags {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove flags of node {}", self.node_index);
+
+                let new_node = Dialogue {
+                    flags: ContentFlags::default(),
+                    ..*state.active.tree.get_node(self.node_index)?
+                };
+                let event = state.active.tree.edit_node(self.node_index, new_node)?;
+                state.history.push(event.into(), &mut state.active);
+
+                Ok(self.node_index)
+            }
+        }
+
+        /// Unmark the default choice, only allowed if its source node has no timeout
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct DefaultChoice {
+            /// Id of the default edge
+            edge_index: usize,
+        }
+
+        impl Executable for DefaultChoice {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove default choice {}", self.edge_index);
+
+                let source = state.active.tree.source_of(self.edge_index)?;
+                anyhow::ensure!(
+                    state.active.tree.get_node(source)?.timeout.is_none(),
+                    cmd::Error::TimeoutWithoutDefault(source)
+                );
+
+                let new_choice = Choice {
+                    default: false,
+                    ..state.active.tree.get_edge(self.edge_index)?.clone()
+                };
+                let event = state.active.tree.edit_edge(self.edge_index, new_choice)?;
+                state.history.push(event.into(), &mut state.active);
+
+                Ok(self.edge_index)
+            }
+        }
+
+        /// Make a call edge an ordinary edge again
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Call {
+            /// Id of the call edge
+            edge_index: usize,
+        }
+
+        impl Executable for Call {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove call {}", self.edge_index);
+
+                let new_choice = Choice {
+                    call: false,
+                    ..state.active.tree.get_edge(self.edge_index)?.clone()
+                };
+                let event = state.active.tree.edit_edge(self.edge_index, new_choice)?;
+                state.history.push(event.into(), &mut state.active);
+
+                Ok(self.edge_index)
+            }
+        }
+    }
+
+    pub mod clone {
+        use super::*;
+
+        /// Duplicate existing things
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Edge(clone::Edge),
+        }
+
+        /// Duplicate a choice onto a new target, and return the index of the new edge
+        ///
+        /// The clone has the same text, requirement, and effect as the original, and leads from
+        /// the same node unless another source is given. Any of these can be replaced while
+        /// cloning, for example to build persuade, intimidate, and bribe variants of a choice. The
+        /// clone is never the default choice of its node.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Edge {
+            /// Index of the edge to clone
+            edge_index: usize,
+            /// Index of the node the clone leads to
+            #[structopt(short, long)]
+            target: usize,
+            /// Index of the node the clone leads from, the source of the original by default
+            #[structopt(short, long)]
+            #[new(default)]
+            source: Option<usize>,
+            /// Text of the clone, in place of the original text
+            #[structopt(long)]
+            #[new(default)]
+            text: Option<String>,
+            /// Requirement of the clone, in place of the original requirement
+            #[structopt(short = "r")]
+            #[new(default)]
+            requirement: Option<ReqKind>,
+            /// Effect of the clone, in place of the original effect
+            #[structopt(short = "e")]
+            #[new(default)]
+            effect: Option<EffectKind>,
+            /// Prompt to edit the text, requirement, and effect of the clone before it is added.
+            /// An empty answer keeps the value shown
+            #[structopt(short, long)]
+            #[new(default)]
+            interactive: bool,
+        }
+
+        impl Executable for Edge {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Clone edge {} to node {}", self.edge_index, self.target);
+                let data = &mut state.active;
+                let original = data.tree.get_edge(self.edge_index)?.clone();
+                let source = match self.source {
+                    Some(source) => source,
+                    None => data.tree.source_of(self.edge_index)?,
+                };
+                let mut text = match &self.text {
+                    Some(text) => text.clone(),
+                    None => data
+                        .text
+                        .get(original.section[0]..original.section[1])
+                        .ok_or(cmd::Error::InvalidSection)?
+                        .to_string(),
+                };
+                let mut requirement = self.requirement.clone().unwrap_or(original.requirement);
+                let mut effect = self.effect.unwrap_or(original.effect);
+
+                if self.interactive {
+                    trace!("prompt for changes to the clone");
+                    let ask = |prompt: String| {
+                        let mut buf = String::new();
+                        println!("{}", prompt);
+                        util::prompt_input(&mut buf);
+                        buf.trim().to_string()
+                    };
+                    let answer = ask(format!("text [{}]:", text));
+                    if !answer.is_empty() {
+                        text = answer;
+                    }
+                    requirement = loop {
+                        let answer = ask(format!("requirement [{}]:", requirement));
+                        if answer.is_empty() {
+                            break requirement;
+                        }
+                        match answer.parse() {
+                            Ok(parsed) => break parsed,
+                            Err(e) => println!("invalid requirement {}: {}", answer, e),
+                        }
+                    };
+                    effect = loop {
+                        let answer = ask(format!("effect [{}]:", effect));
+                        if answer.is_empty() {
+                            break effect;
+                        }
+                        match answer.parse() {
+                            Ok(parsed) => break parsed,
+                            Err(e) => println!("invalid effect {}: {}", answer, e),
+                        }
+                    };
+                }
+
+                trace!("validate that the requirement and effect reference valid keys");
+                util::validate_requirement(&requirement, &data.name_table, &data.val_table)?;
+                util::validate_effect(&effect, &data.name_table, &data.val_table)?;
+
+                let section = util::push_section(&mut data.text, &text);
+                let choice = Choice {
+                    section,
+                    requirement,
+                    effect,
+                    default: false,
+                    line: data.new_line(),
+                    ..original
+                };
+                let event = data.tree.add_edge(source, self.target, choice)?;
+                let idx = event.index;
+                state.history.push(event.into(), &mut state.active);
+                Ok(idx)
+            }
+        }
+    }
+
+    pub mod rename {
+        use super::*;
+
+        /// Rename existing things
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Name(rename::Name),
+            Val(rename::Val),
+        }
+
+        /// Rename the key of an existing name
+        ///
+        /// Every reference to the key in node text, edge text, requirements, and effects is
+        /// updated to the new key. The entire rename is recorded as a single event, so it can be
+        /// reverted with one undo
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Name {
+            /// The current keyword of the name
+            key: KeyString,
+            /// The new keyword for the name. Maximum length of 8 characters
+            new_key: KeyString,
+        }
+
+        impl Executable for Name {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Rename name {} to {}", self.key, self.new_key);
+
+                let name = *state
+                    .active
+                    .name_table
+                    .get(&self.key)
+                    .ok_or(cmd::Error::NameNotExists)?;
+
+                trace!("check that the new key does not already exist");
+                if state.active.name_table.contains_key(&self.new_key) {
+                    return Err(cmd::Error::NameExists.into());
+                }
+
+                let mut events: Vec<DialogueTreeEvent> = Vec::new();
+                let res = rename_references(
+                    &mut state.active,
+                    &mut events,
+                    util::KeyKind::Name,
+                    self.key,
+                    self.new_key,
+                );
+                if let Err(e) = res {
+                    trace!("rename failed, revert any changes that were already made");
+                    EventGroup { events }.undo(&mut state.active)?;
+                    return Err(e);
+                }
+
+                trace!("move name to the new key in the name table");
+                state.active.name_table.remove(&self.key);
+                state.active.name_table.insert(self.new_key, name);
+                events.push(
+                    NameTableRemove {
+                        key: self.key,
+                        name,
+                    }
+                    .into(),
+                );
+                events.push(
+                    NameTableInsert {
+                        key: self.new_key,
+                        name,
+                    }
+                    .into(),
+                );
+
+                state
+                    .history
+                    .push(EventGroup { events }.into(), &mut state.active);
+                Ok(0)
+            }
+        }
+
+        /// Rename the key of an existing value
+        ///
+        /// Every reference to the key in requirements and effects is updated to the new key. The
+        /// entire rename is recorded as a single event, so it can be reverted with one undo
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Val {
+            /// The current keyword of the value
+            key: KeyString,
+            /// The new keyword for the value. Maximum length of 8 characters
+            new_key: KeyString,
+        }
+
+        impl Executable for Val {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Rename val {} to {}", self.key, self.new_key);
+                ValScope::of(&self.new_key)?;
+
+                let value = *state
+                    .active
+                    .val_table
+                    .get(&self.key)
+                    .ok_or(cmd::Error::ValNotExists)?;
+
+                trace!("check that the new key does not already exist");
+                if state.active.val_table.contains_key(&self.new_key) {
+                    return Err(cmd::Error::ValExists.into());
+                }
+
+                let mut events: Vec<DialogueTreeEvent> = Vec::new();
+                let res = rename_references(
+                    &mut state.active,
+                    &mut events,
+                    util::KeyKind::Val,
+                    self.key,
+                    self.new_key,
+                );
+                if let Err(e) = res {
+                    trace!("rename failed, revert any changes that were already made");
+                    EventGroup { events }.undo(&mut state.active)?;
+                    return Err(e);
+                }
+
+                trace!("move value to the new key in the value table");
+                state.active.val_table.remove(&self.key);
+                state.active.val_table.insert(self.new_key, value);
+                events.push(
+                    ValTableRemove {
+                        key: self.key,
+                        val: value,
+                    }
+                    .into(),
+                );
+                events.push(
+                    ValTableInsert {
+                        key: self.new_key,
+                        value,
+                    }
+                    .into(),
+                );
+
+                state
+                    .history
+                    .push(EventGroup { events }.into(), &mut state.active);
+                Ok(0)
+            }
+        }
+
+        /// Helper to rewrite every reference to a key in the tree. Text sections that reference
+        /// the key are pushed to the end of the text buffer with the key replaced, the same way
+        /// edits are performed. Each modification is pushed to the events list as it is applied.
+        fn rename_references(
+            data: &mut DialogueTreeData,
+            events: &mut Vec<DialogueTreeEvent>,
+            kind: util::KeyKind,
+            key: KeyString,
+            new_key: KeyString,
+        ) -> Result<()> {
+            if kind == util::KeyKind::Name {
+                trace!("rewrite node text that references the key");
+                for node_index in 0..data.tree.nodes().len() {
+                    let node = *data.tree.get_node(node_index)?;
+                    let text = data
+                        .text
+                        .get(node.section[0]..node.section[1])
+                        .ok_or(cmd::Error::InvalidSection)?;
+                    if let Some(new_text) = util::rename_text_key(text, &key, &new_key) {
+                        let section = util::push_section(&mut data.text, &new_text);
+                        let new_node = Dialogue { section, ..node };
+                        let event = data.tree.edit_node(node_index, new_node)?;
+                        events.push(event.into());
+                    }
+                }
+            }
+
+            trace!("rewrite edge text, requirements, and effects that reference the key");
+            for edge_index in 0..data.tree.edges().len() {
+                let edge = data.tree.get_edge(edge_index)?.clone();
+                let mut new_edge = edge.clone();
+                new_edge.requirement =
+                    util::rename_requirement(edge.requirement.clone(), kind, key, new_key);
+                new_edge.effect = util::rename_effect(edge.effect, kind, key, new_key);
+                if kind == util::KeyKind::Name {
+                    let text = data
+                        .text
+                        .get(edge.section[0]..edge.section[1])
+                        .ok_or(cmd::Error::InvalidSection)?;
+                    if let Some(new_text) = util::rename_text_key(text, &key, &new_key) {
+                        new_edge.section = util::push_section(&mut data.text, &new_text);
+                    }
+                }
+
+                let changed = new_edge.section.hash != edge.section.hash
+                    || new_edge.requirement != edge.requirement
+                    || new_edge.effect != edge.effect;
+                if changed {
+                    let event = data.tree.edit_edge(edge_index, new_edge)?;
+                    events.push(event.into());
+                }
+            }
+
+            trace!("rewrite on-enter effects that reference the key");
+            let mut entered: Vec<NodeIndex> = data.on_enter.keys().copied().collect();
+            entered.sort_unstable();
+            for node_index in entered {
+                let effects = util::on_enter_of(data, node_index)?;
+                let renamed: Vec<EffectKind> = effects
+                    .iter()
+                    .map(|effect| util::rename_effect(*effect, kind, key, new_key))
+                    .collect();
+                if renamed != effects {
+                    let event = data.set_on_enter(node_index, renamed)?;
+                    events.push(event.into());
+                }
+            }
+            Ok(())
+        }
+    }
+
+    pub mod import {
+        use super::*;
+
+        /// Import things from other file formats
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Screenplay(import::Screenplay),
+            Names(import::Names),
+            Vals(import::Vals),
+            Csv(import::Csv),
+        }
+
+        /// Bulk import names into the name table from a CSV or JSON file
+        ///
+        /// CSV files contain one key,name pair per line. JSON files contain a single object
+        /// mapping keys to names. The format is chosen based on the file extension.
+        ///
+        /// ```text
+        /// cat,Behemoth
+        /// kot,Kot
+        /// ```
+        ///
+        /// Keys must not already exist in the name table, and are checked before any are
+        /// imported. The whole import is recorded as a single event. Returns the number of names
+        /// imported.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Names {
+            /// Path to the CSV or JSON file
+            file: String,
+        }
+
+        impl Executable for Names {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Import names from {}", self.file);
+                let entries = util::read_table_file(&self.file)?;
+
+                trace!("validate all keys and names before importing");
+                let mut names: Vec<(KeyString, NameString)> = Vec::with_capacity(entries.len());
+                for (key, name) in entries.iter() {
+                    let key = KeyString::from(key).map_err(|e| e.simplify())?;
+                    let name = NameString::from(name).map_err(|e| e.simplify())?;
+                    if state.active.name_table.contains_key(&key)
+                        || names.iter().any(|(k, _)| *k == key)
+                    {
+                        return Err(cmd::Error::NameExists.into());
+                    }
+                    names.push((key, name));
+                }
+
+                trace!("add names to the table");
+                let mut events: Vec<DialogueTreeEvent> = Vec::with_capacity(names.len());
+                for (key, name) in names.iter() {
+                    state.active.name_table.insert(*key, *name);
+                    events.push(
+                        NameTableInsert {
+                            key: *key,
+                            name: *name,
+                        }
+                        .into(),
+                    );
+                }
+
+                state
+                    .history
+                    .push(EventGroup { events }.into(), &mut state.active);
+                Ok(names.len())
+            }
+        }
+
+        /// Bulk import values into the value table from a CSV or JSON file
+        ///
+        /// CSV files contain one key,value pair per line. JSON files contain a single object
+        /// mapping keys to values. The format is chosen based on the file extension.
+        ///
+        /// ```text
+        /// {"rus_lit": 100, "rum": 1}
+        /// ```
+        ///
+        /// Keys must not already exist in the value table, and are checked before any are
+        /// imported. The whole import is recorded as a single event. Returns the number of
+        /// values imported.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Vals {
+            /// Path to the CSV or JSON file
+            file: String,
+        }
+
+        impl Executable for Vals {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Import values from {}", self.file);
+                let entries = util::read_table_file(&self.file)?;
+
+                trace!("validate all keys and values before importing");
+                let mut vals: Vec<(KeyString, u32)> = Vec::with_capacity(entries.len());
+                for (key, value) in entries.iter() {
+                    let key = KeyString::from(key).map_err(|e| e.simplify())?;
+                    ValScope::of(&key)?;
+                    let value = value.parse::<u32>()?;
+                    if state.active.val_table.contains_key(&key)
+                        || vals.iter().any(|(k, _)| *k == key)
+                    {
+                        return Err(cmd::Error::ValExists.into());
+                    }
+                    vals.push((key, value));
+                }
+
+                trace!("add values to the table");
+                let mut events: Vec<DialogueTreeEvent> = Vec::with_capacity(vals.len());
+                for (key, value) in vals.iter() {
+                    state.active.val_table.insert(*key, *value);
+                    events.push(
+                        ValTableInsert {
+                            key: *key,
+                            value: *value,
+                        }
+                        .into(),
+                    );
+                }
+
+                state
+                    .history
+                    .push(EventGroup { events }.into(), &mut state.active);
+                Ok(vals.len())
+            }
+        }
+
+        /// Import nodes and edges from a plain text screenplay file into the active project
+        ///
+        /// The screenplay format allows drafting dialogue linearly in any text editor. Each node is
+        /// a line starting with a speaker key, followed by a colon and the dialogue text. Indented
+        /// lines following a node starting with '>' are choices leading to the node with the given
+        /// label. A choice may optionally end with a requirement and effect using the same syntax as
+        /// the new edge command. A line starting with '#' sets the label of the next node. Other
+        /// indented lines continue the dialogue text of the previous node on a new line.
+        ///
+        /// ```text
+        /// # start
+        /// cat: Well, who knows, who knows
+        ///     > Dostoevsky's dead -> protest -r Less(rus_lit,51) -e Sub(rus_lit,1)
+        /// # protest
+        /// cat: 'I protest!' ::cat:: exclaimed hotly.
+        /// ```
+        ///
+        /// All speakers and names must already exist in the name table. The whole import is
+        /// recorded as a single event. Returns the number of nodes imported.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Screenplay {
+            /// Path to the screenplay file
+            file: String,
+        }
+
+        impl Executable for Screenplay {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Import screenplay {}", self.file);
+                let text = std::fs::read_to_string(&self.file)?;
+                let nodes = util::parse_screenplay(&text)?;
+
+                trace!("validate all text, labels, requirements, and effects before importing");
+                let first_index = state.active.tree.nodes().len();
+                let mut labels: HashMap<&str, usize> = HashMap::new();
+                for (i, node) in nodes.iter().enumerate() {
+                    if let Some(label) = node.label.as_ref() {
+                        labels.insert(label.as_str(), first_index + i);
+                    }
+                }
+                for node in nodes.iter() {
+                    util::validate_node(&node.text, &state.active.name_table)?;
+                    for choice in node.choices.iter() {
+                        labels
+                            .get(choice.target.as_str())
+                            .ok_or_else(|| cmd::Error::LabelNotExists(choice.target.clone()))?;
+                        util::validate_edge(&choice.text, &state.active.name_table)?;
+                        util::validate_requirement(
+                            &choice.requirement,
+                            &state.active.name_table,
+                            &state.active.val_table,
+                        )?;
+                        util::validate_effect(
+                            &choice.effect,
+                            &state.active.name_table,
+                            &state.active.val_table,
+                        )?;
+                    }
+                }
+
+                trace!("add nodes to the tree");
+                let mut events: Vec<DialogueTreeEvent> = Vec::new();
+                for node in nodes.iter() {
+                    let section = util::push_section(&mut state.active.text, &node.text);
+                    let node = Dialogue {
+                        line: state.active.new_line(),
+                        ..Dialogue::new(section)
+                    };
+                    let event = state.active.tree.add_node(node)?;
+                    events.push(event.into());
+                }
+
+                trace!("add edges to the tree");
+                for (i, node) in nodes.iter().enumerate() {
+                    for choice in node.choices.iter() {
+                        let section = util::push_section(&mut state.active.text, &choice.text);
+                        let target = *labels
+                            .get(choice.target.as_str())
+                            .ok_or_else(|| cmd::Error::LabelNotExists(choice.target.clone()))?;
+                        let choice = Choice {
+                            line: state.active.new_line(),
+                            ..Choice::new(section, choice.requirement.clone(), choice.effect)
+                        };
+                        let event = state
+                            .active
+                            .tree
+                            .add_edge(first_index + i, target, choice)?;
+                        events.push(event.into());
+                    }
+                }
+
+                state
+                    .history
+                    .push(EventGroup { events }.into(), &mut state.active);
+                Ok(nodes.len())
+            }
+        }
+
+        /// Replace the text of nodes and edges with a column of a localization CSV file
+        ///
+        /// The file must have a header row with an id column, as written by the export csv
+        /// command, and the column to import. Rows are matched to nodes and edges by id, and node
+        /// speakers are kept. Rows with an empty cell in the column are skipped as untranslated.
+        ///
+        /// Rows that do not match a node or edge, or whose text references a missing name, are
+        /// listed in the scratchpad and left out of the import. The rest of the import is recorded
+        /// as a single event. Returns the number of rows imported.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Csv {
+            /// Path to the CSV file
+            file: String,
+            /// Header of the column to import, such as a locale
+            #[structopt(short, long)]
+            column: String,
+        }
+
+        impl Executable for Csv {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Import csv column {} from {}", self.column, self.file);
+                let mut reader = csv::Reader::from_path(&self.file)?;
+                let headers = reader.headers()?.clone();
+                let column_of = |name: &str| {
+                    headers
+                        .iter()
+                        .position(|h| h.trim() == name)
+                        .ok_or_else(|| cmd::Error::ColumnNotExists(name.to_string()))
+                };
+                let id_column = column_of("id")?;
+                let text_column = column_of(&self.column)?;
+
+                let mut events: Vec<DialogueTreeEvent> = Vec::new();
+                let mut failed: Vec<(u64, String)> = Vec::new();
+                for record in reader.records() {
+                    let record = record?;
+                    let line = record.position().map_or(0, |p| p.line());
+                    let text = record.get(text_column).unwrap_or_default();
+                    if text.is_empty() {
+                        continue;
+                    }
+                    let id = record.get(id_column).unwrap_or_default();
+                    match util::import_text(&mut state.active, id, text) {
+                        Ok(event) => events.push(event),
+                        Err(e) => failed.push((line, e.to_string())),
+                    }
+                }
+
+                let imported = events.len();
+                if !events.is_empty() {
+                    state
+                        .history
+                        .push(EventGroup { events }.into(), &mut state.active);
+                }
+                state.scratchpad.push_str(&format!(
+                    "imported {} rows from column {}\r\n",
+                    imported, self.column
+                ));
+                for (line, error) in failed.iter() {
+                    state
+                        .scratchpad
+                        .push_str(&format!("line {}: {}\r\n", line, error));
+                }
+                println!("{}", state.scratchpad);
+                Ok(imported)
+            }
+        }
+    }
+
+    pub mod export {
+        use super::*;
+
+        /// Export things to other file formats
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Screenplay(export::Screenplay),
+            Json(export::Json),
+            Csv(export::Csv),
+            Subtree(export::Subtree),
+            Machine(export::Machine),
+        }
+
+        /// Export the active project to a plain text screenplay file
+        ///
+        /// Each node is labeled with its index, so the exported file may be edited and imported
+        /// into a new project. See the import screenplay command for a description of the format.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Screenplay {
+            /// Path to write the screenplay file to
+            file: String,
+        }
+
+        impl Executable for Screenplay {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Export screenplay {}", self.file);
+                let screenplay = util::write_screenplay(&state.active)?;
+                std::fs::write(&self.file, screenplay)?;
+                Ok(state.active.uid.low())
+            }
+        }
+
+        /// Export the active project to a JSON file for web based players
+        ///
+        /// The file has a documented structure that does not depend on arbor's internal layout,
+        /// see the json module for a description of the format. Censored builds are exported in
+        /// the same way as with the package command.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Json {
+            /// Path to write the JSON file to
+            file: String,
+            /// Strip or replace nodes with any of these content flags, for example violence,spoiler
+            #[structopt(long)]
+            censor: Option<ContentFlags>,
+            /// Node that edges and interrupts leading to stripped nodes are rerouted to
+            #[structopt(long)]
+            fallback: Option<usize>,
+            /// Replace the dialogue of censored nodes with this text instead of stripping them
+            #[structopt(long)]
+            replace: Option<String>,
+        }
+
+        impl Executable for Json {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Export json {}", self.file);
+                let censored = match self.censor {
+                    Some(flags) => {
+                        let censor =
+                            util::Censor::from_options(self.fallback, self.replace.as_deref())?;
+                        Some(util::censor(&state.active, flags, &censor)?)
+                    }
+                    None => None,
+                };
+                let project = crate::json::export(censored.as_ref().unwrap_or(&state.active))?;
+                std::fs::write(&self.file, serde_json::to_string_pretty(&project)?)?;
+                Ok(state.active.uid.low())
+            }
+        }
+
+        /// Export the text of every node and edge to a CSV file for localization and voiceover
+        ///
+        /// Each row has the id of the node or edge, its line id, the speaker key for nodes, and
+        /// the text with name keys written the same way as in the new node and new edge commands.
+        /// Translations may be added as new columns and imported with the import csv command.
+        ///
+        /// ```text
+        /// id,line,speaker,text,fr
+        /// node:0,L00001.0,cat,"I am ::cat::, the cat","Je suis ::cat::, le chat"
+        /// edge:0,L00002.1,,Ask ::cat::,Demander à ::cat::
+        /// ```
+        ///
+        /// Line ids stay with a line as the tree is edited, and their revision counts the edits
+        /// to the text, see LineId. A manifest of the exported lines can be written along with the
+        /// CSV file, to find the lines that changed since with the report stale-lines command.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Csv {
+            /// Path to write the CSV file to
+            file: String,
+            /// Path to write a manifest of the line ids and text hashes to
+            #[structopt(short, long)]
+            #[new(default)]
+            manifest: Option<String>,
+        }
+
+        impl Executable for Csv {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Export csv {}", self.file);
+                let csv = util::write_text_csv(&state.active)?;
+                std::fs::write(&self.file, csv)?;
+                if let Some(manifest) = self.manifest.as_ref() {
+                    let lines = util::LineManifest::new(&state.active);
+                    std::fs::write(manifest, serde_json::to_string_pretty(&lines)?)?;
+                }
+                Ok(state.active.uid.low())
+            }
+        }
+
+        /// Export a set of nodes, and the edges between them, as a new project in the project
+        /// store
+        ///
+        /// The new project can be loaded like any other. See util::extract for what is copied
+        /// along with the nodes. Returns the low bits of the new project's uid.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Subtree {
+            /// Name of the new project
+            name: String,
+            /// Indices of the nodes to export
+            #[structopt(required = true)]
+            node_indices: Vec<usize>,
+        }
+
+        impl Executable for Subtree {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Export subtree {}", self.name);
+                let data = util::extract(&state.active, &self.node_indices, &self.name)?;
+                store::write_data(state.store.as_ref(), &(self.name.clone() + TREE_EXT), &data)?;
+                Ok(data.uid.low())
+            }
+        }
+
+        /// Export an overview of the active project as a state machine, for quest designers
+        ///
+        /// Nodes are collapsed into states by bookmark, content flags, or speaker, and the choices
+        /// between states become transitions. States and transitions list the effects of their
+        /// choices, see analysis::state_machine. The file is written as JSON or as graphviz DOT,
+        /// chosen by the file extension. Returns the number of states.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Machine {
+            /// Path to write the .json or .dot file to
+            file: String,
+            /// Group nodes by bookmark, flags, or speaker
+            #[structopt(short, long, default_value = "bookmark")]
+            by: analysis::GroupBy,
+        }
+
+        impl Executable for Machine {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Export state machine {}", self.file);
+                let machine = analysis::state_machine(&state.active, self.by)?;
+                let extension = std::path::Path::new(&self.file)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or_default()
+                    .to_lowercase();
+                let contents = match extension.as_str() {
+                    "json" => serde_json::to_string_pretty(&machine)?,
+                    "dot" => machine.to_dot(),
+                    _ => return Err(cmd::Error::UnknownFormat(extension).into()),
+                };
+                std::fs::write(&self.file, contents)?;
+                Ok(machine.states.len())
+            }
+        }
+    }
+
+    /// Undo the last event that modified the dialogue tree, or a single earlier event
+    ///
+    /// Rebuilding the tree removes the entire undo/redo history. Undo does not interact with file
+    /// level operations such as saving or loading projects
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Undo {
+        #[structopt(subcommand)]
+        #[new(default)]
+        target: Option<UndoTarget>,
+    }
+
+    /// Earlier event to undo instead of the last one
+    #[derive(StructOpt, Debug, Clone, Copy, PartialEq)]
+    pub enum UndoTarget {
+        /// Undo a single earlier change, keeping the changes made after it. The change is
+        /// reverted by a new change in the history, which can be undone like any other. The
+        /// revert is refused if a later change depends on the change or edits the same things,
+        /// and the reason is printed. Returns the new position in the history
+        Event {
+            /// Number of the change to undo, as printed by history list
+            change: usize,
+        },
+    }
+
+    impl Executable for Undo {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            match self.target {
+                None => {
+                    info!("Undo");
+                    state.history.undo(&mut state.active)?;
+                    Ok(0)
+                }
+                Some(UndoTarget::Event { change }) => {
+                    info!("Undo change {}", change);
+                    state.history.revert(&mut state.active, change)?;
+                    Ok(state.history.position)
+                }
+            }
+        }
+    }
+
+    /// Redo the last undo event that modified the dialogue tree
+    ///
+    /// Rebuilding the tree removes the entire undo/redo history. Redo does not interact with file
+    /// level operations such as saving or loading projects
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Redo {}
+
+    impl Executable for Redo {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Redo");
+            state.history.redo(&mut state.active)?;
+            Ok(0)
+        }
+    }
+
+    pub mod history {
+        use super::*;
+
+        /// Inspect the undo/redo history, and jump to any point in it
+        ///
+        /// Changes are numbered from 1, oldest first. Jumping to a change undoes or redoes every
+        /// change needed to reach it, jumping to 0 undoes all changes.
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            List(history::List),
+            Jump(history::Jump),
+        }
+
+        /// Print the undo/redo history to the editor scratchpad, oldest change first
+        ///
+        /// The most recent change that has not been undone is marked with '>', and undone changes
+        /// that can still be redone are marked as undone. Edits of node and edge text are shown as
+        /// a word diff, see util::render_diff, colored when printed to a terminal
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct List {
+            /// Only print the most recent changes
+            #[structopt(short = "n", long)]
+            count: Option<usize>,
+        }
+
+        impl Executable for List {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("List history");
+                let history = &state.history;
+                let skip = self
+                    .count
+                    .map_or(0, |count| history.record.len().saturating_sub(count));
+                let color = util::use_color();
+                for (i, event) in history.record.iter().enumerate().skip(skip) {
+                    let marker = if i + 1 == history.position { '>' } else { ' ' };
+                    let undone = if i >= history.position {
+                        "(undone) "
+                    } else {
+                        ""
+                    };
+                    let description = match event.text_change(&state.active) {
+                        Some(change) => format!(
+                            "{}: {}",
+                            change.title,
+                            util::render_diff(&util::word_diff(&change.old, &change.new), color)
+                        ),
+                        None => event.describe(&state.active),
+                    };
+                    state.scratchpad.push_str(&format!(
+                        "{} {}: {}{}\r\n",
+                        marker,
+                        i + 1,
+                        undone,
+                        description
+                    ));
+                }
+                if history.record.is_empty() {
+                    state.scratchpad.push_str("no changes\r\n");
+                }
+                Ok(history.position)
+            }
+        }
+
+        /// Undo or redo changes until the given change is the most recent one. 0 undoes all
+        /// changes
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Jump {
+            /// Number of the change to jump to, as printed by history list
+            position: usize,
+        }
+
+        impl Executable for Jump {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Jump to history position {}", self.position);
+                anyhow::ensure!(
+                    self.position <= state.history.record.len(),
+                    cmd::Error::HistoryOutOfRange(self.position)
+                );
+                while state.history.position > self.position {
+                    state.history.undo(&mut state.active)?;
+                }
+                while state.history.position < self.position {
+                    state.history.redo(&mut state.active)?;
+                }
+                Ok(state.history.position)
+            }
+        }
+    }
+
+    pub mod project {
+        use super::*;
+
+        /// Keep several projects open in one editor, and copy nodes between them
+        ///
+        /// Only the active project is edited by other commands. Every open project keeps its own
+        /// backup copy and undo/redo history, which come back when the project is made active
+        /// again.
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Open(project::Open),
+            Switch(project::Switch),
+            List(project::List),
+            Close(project::Close),
+            Copy(project::Copy),
+        }
+
+        /// Load a project from disk and make it active, keeping the previously active project
+        /// open
+        ///
+        /// The loaded project is validated before it is opened, like with the load command.
+        /// Returns the low bits of the opened project's uid.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Open {
+            /// Name of the project to open
+            name: String,
+        }
+
+        impl Executable for Open {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Open project {}", self.name);
+                anyhow::ensure!(
+                    self.name != state.active.name && state.open_position(&self.name).is_none(),
+                    cmd::Error::ProjectOpen(self.name.clone())
+                );
+                let loaded =
+                    store::read_data(state.store.as_ref(), &(self.name.clone() + TREE_EXT))?;
+                util::validate_tree(&loaded)?;
+
+                trace!("open the project and switch to it");
+                state
+                    .open
+                    .push(OpenProject::new(loaded, state.journal_store()));
+                state.switch_project(state.open.len() - 1);
+                Ok(state.active.uid.low())
+            }
+        }
+
+        /// Make another open project active. The previously active project stays open
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Switch {
+            /// Name of the project to switch to
+            name: String,
+        }
+
+        impl Executable for Switch {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Switch to project {}", self.name);
+                if self.name != state.active.name {
+                    let position = state
+                        .open_position(&self.name)
+                        .ok_or_else(|| cmd::Error::ProjectNotOpen(self.name.clone()))?;
+                    state.switch_project(position);
+                }
+                Ok(state.active.uid.low())
+            }
+        }
+
+        /// Print the open projects to the editor scratchpad
+        ///
+        /// The active project is marked with '*', and projects with unsaved changes are marked as
+        /// modified. Returns the number of open projects, including the active one.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct List {}
+
+        impl Executable for List {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("List open projects");
+                let projects = std::iter::once((&state.active, &state.history, '*')).chain(
+                    state
+                        .open
+                        .iter()
+                        .map(|project| (&project.active, &project.history, ' ')),
+                );
+                for (data, history, marker) in projects {
+                    let modified = if history.is_modified() {
+                        " (modified)"
+                    } else {
+                        ""
+                    };
+                    state.scratchpad.push_str(&format!(
+                        "{} {}: {} nodes{}\r\n",
+                        marker,
+                        data.name,
+                        data.tree.nodes().len(),
+                        modified
+                    ));
+                }
+                Ok(state.open.len() + 1)
+            }
+        }
+
+        /// Close an open project other than the active one
+        ///
+        /// Closing is refused if the project has unsaved changes, unless forced. The journal of a
+        /// forced close is kept, so the changes can still be recovered with replay after loading
+        /// the project again.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Close {
+            /// Name of the project to close
+            name: String,
+            /// Discard unsaved changes in the project
+            #[structopt(short, long)]
+            #[new(default)]
+            force: bool,
+        }
+
+        impl Executable for Close {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Close project {}", self.name);
+                let position = state
+                    .open_position(&self.name)
+                    .ok_or_else(|| cmd::Error::ProjectNotOpen(self.name.clone()))?;
+                anyhow::ensure!(
+                    self.force || !state.open[position].history.is_modified(),
+                    cmd::Error::ProjectModified(self.name.clone())
+                );
+                state.open.remove(position);
+                Ok(state.open.len() + 1)
+            }
+        }
+
+        /// Copy a set of nodes, and the edges between them, from the active project into another
+        /// open project
+        ///
+        /// The copied nodes are added after the existing nodes of the other project, see
+        /// util::extract and util::graft for what is copied along with them. The copy is recorded
+        /// as a single change in the other project's history. Returns the index of the first
+        /// copied node in the other project.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Copy {
+            /// Name of the open project to copy the nodes into
+            project: String,
+            /// Indices of the nodes to copy
+            #[structopt(required = true)]
+            node_indices: Vec<usize>,
+        }
+
+        impl Executable for Copy {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!(
+                    "Copy nodes {:?} to project {}",
+                    self.node_indices, self.project
+                );
+                let position = state
+                    .open_position(&self.project)
+                    .ok_or_else(|| cmd::Error::ProjectNotOpen(self.project.clone()))?;
+                let subtree = util::extract(&state.active, &self.node_indices, &self.project)?;
+
+                let target = &mut state.open[position];
+                let first = target.active.tree.nodes().len();
+                let events = util::graft(&mut target.active, &subtree)?;
+                target
+                    .history
+                    .push(EventGroup { events }.into(), &mut target.active);
+                state.scratchpad.push_str(&format!(
+                    "copied {} nodes to {} starting at node {}\r\n",
+                    subtree.tree.nodes().len(),
+                    self.project,
+                    first
+                ));
+                Ok(first)
+            }
+        }
+    }
+
+    /// Save the current project
+    ///
+    /// If a file for the project already exists on disk, its uid is checked against the uid of
+    /// the active project. Saving is refused if they differ, to prevent a different project with
+    /// the same name from being overwritten.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Save {
+        /// Overwrite the file even if its uid does not match the active project
+        #[structopt(short, long)]
+        force: bool,
+        /// Compress the saved file. Compressed files are detected automatically on load
+        #[structopt(short, long)]
+        compress: bool,
+        /// Store the text in spillover files of at most this many bytes next to the project file.
+        /// Later saves keep splitting the text the same way, 0 stores it in the project file again
+        #[structopt(short, long)]
+        #[new(default)]
+        max_text: Option<usize>,
+    }
+
+    impl Executable for Save {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Save project");
+
+            trace!("report any unused or missing name/val keys before saving");
+            let report = util::project_key_usage(&state.active, state.store.as_ref());
+            if !report.is_empty() {
+                warn!("{}", report);
+                state.scratchpad.push_str(&report.to_string());
+                println!("{}", state.scratchpad);
+            }
+
+            let path = state.active.name.clone() + TREE_EXT;
+            trace!("check that the file in the store belongs to the active project");
+            if !self.force {
+                util::check_uid(state.store.as_ref(), &path, state.active.uid)?;
+            }
+
+            let compression = if self.compress {
+                store::Compression::Deflate
+            } else {
+                store::Compression::None
+            };
+            let max_text_len = match self.max_text {
+                Some(0) => usize::MAX,
+                Some(max_text_len) => max_text_len,
+                None => store::segment_limit(state.store.as_ref(), &path)?,
+            };
+            let segments = store::replace_data_segmented(
+                state.store.as_ref(),
+                &path,
+                &state.active,
+                compression,
+                max_text_len,
+            )?;
+            debug!("saved text in {} spillover files", segments);
+
+            trace!("save successful, sync backup with active copy");
+            state.backup = state.active.clone();
+            state.history.mark_saved();
+
+            if let Some(journals) = state.journal_store() {
+                trace!("discard the journal, its changes are now saved");
+                let mut journal = Journal::new(journals, &state.active);
+                journal.discard()?;
+                state.history.journal = Some(journal);
+            }
+
+            Ok(state.active.uid.low())
+        }
+    }
+
+    /// Package the current project into a single archive for distribution
+    ///
+    /// The package contains the project's dialogue tree, the files in its asset table, any locale
+    /// files provided, and a manifest describing the contents. Files are read through the project
+    /// store, and the package is written to it as project_name.arbor
+    ///
+    /// A censored build is packaged by passing the content flags to censor, along with either a
+    /// fallback node to reroute to in place of stripped nodes, or text to replace their dialogue
+    /// with. The project itself is not modified.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Package {
+        /// Locale files to bundle, such as CSV files written by the export csv command. Files in
+        /// the asset table are always bundled
+        #[structopt(short, long = "locale")]
+        locales: Vec<String>,
+        /// Encrypt the package with a password. Requires the encryption feature
+        #[structopt(long)]
+        password: Option<String>,
+        /// Strip or replace nodes with any of these content flags, for example violence,spoiler
+        #[structopt(long)]
+        censor: Option<ContentFlags>,
+        /// Node that edges and interrupts leading to stripped nodes are rerouted to
+        #[structopt(long)]
+        fallback: Option<usize>,
+        /// Replace the dialogue of censored nodes with this text instead of stripping them
+        #[structopt(long)]
+        replace: Option<String>,
+    }
+
+    impl Executable for Package {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Package project");
+
+            trace!("check that the tree is valid before packaging");
+            util::validate_tree_incremental(&mut state.active)?;
+
+            let censored = match self.censor {
+                Some(flags) => {
+                    let censor =
+                        util::Censor::from_options(self.fallback, self.replace.as_deref())?;
+                    Some(util::censor(&state.active, flags, &censor)?)
+                }
+                None => None,
+            };
+            let data = censored.as_ref().unwrap_or(&state.active);
+            let package = crate::Package::new(data, state.store.as_ref(), &self.locales)?;
+            let name = state.active.name.clone() + PACKAGE_EXT;
+            match &self.password {
+                None => package.write_to(state.store.as_ref(), &name)?,
+                #[cfg(feature = "encryption")]
+                Some(password) => {
+                    trace!("encrypt package");
+                    let secret = crate::crypt::Secret::Password(password.clone());
+                    package.write_encrypted(state.store.as_ref(), &name, &secret)?
+                }
+                #[cfg(not(feature = "encryption"))]
+                Some(_) => return Err(Error::EncryptionDisabled.into()),
+            }
+            Ok(state.active.uid.low())
+        }
+    }
+
+    /// Rebuild the tree and text buffer for efficient access and memory use. Rebuilding the tree
+    /// erases the undo/redo history.
+    ///
+    /// Rebuilding the tree is used to remove unused sections of text from the buffer. It performs
+    /// a DFS search through the tree, and creates a new tree and text buffer where the text sections
+    /// of a node and its outgoing edges are next to each other. This rebuilding process has a risk
+    /// of corrupting the tree, so a backup copy is is saved before hand. The backup is stored both
+    /// in memory and copied to disk as project_name.tree.bkp, in the backup directory if one is
+    /// configured (see store::BackupSettings). Previous backups are kept as project_name.tree.bkp.1
+    /// and so on. To use the backup copy, either call the swap subcommand to load from memory, or
+    /// use the recover subcommand to load it from disk.
+    ///
+    /// Since the rebuild tree cleans out any artifacts from edits/removals, the undo/redo
+    ///
+    /// If any corruption is found while rebuilding, the active tree is restored from the backup
+    /// and a list of the problems found is printed to the editor scratchpad.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Rebuild {}
+
+    impl Executable for Rebuild {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            // save states to backup buffer
+            state.backup = state.active.clone();
+
+            // save backup to disk, keeping the previous backups
+            state.backups.write(&state.store, &state.active)?;
+
+            // attempt rebuild tree on active buffer, backup buffer is used as source. Confirm
+            // that that rebuilt tree is valid, and restore from the backup if anything failed
+            let res = util::rebuild_tree(
+                &state.backup.text,
+                &state.backup.tree,
+                &mut state.active.text,
+                &mut state.active.tree,
+            )
+            .and_then(|_| {
+                // the text buffer was rewritten in place, every section must be hashed again
+                state.active.hash_cache.clear();
+                state.active.search_index.clear();
+                util::validate_tree_incremental(&mut state.active)
+            });
+            if let Err(e) = res {
+                warn!("rebuild failed, restoring active tree from backup");
+                state.active = state.backup.clone();
+                if let Some(cmd::Error::RebuildFailed(problems)) = e.downcast_ref::<cmd::Error>() {
+                    for problem in problems.iter() {
+                        state.scratchpad.push_str(&format!("{}\r\n", problem));
+                    }
+                    println!("{}", state.scratchpad);
+                }
+                return Err(e);
+            }
+
+            // Clear the undo/redo history
+            state.history.clear();
+            state.history.journal_rebuild(&state.active.text);
+
+            Ok(state.active.uid.low())
+        }
+    }
+
+    /// Load a project from disk, will overwrite unsaved changes
+    ///
+    /// The loaded project is validated before replacing the active project, so a failed load
+    /// leaves the editor state untouched. On success, the previous project is kept in the backup
+    /// buffer and can be restored with the swap subcommand. Other open projects are not affected,
+    /// use project open to keep the active project open alongside the loaded one.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Load {
+        name: String,
+    }
+
+    impl Executable for Load {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Load project");
+            let loaded = store::read_data(state.store.as_ref(), &(self.name.clone() + TREE_EXT))?;
+
+            trace!("check that the loaded tree is valid before loading into main state");
+            util::validate_tree(&loaded)?;
+
+            trace!("replace editor state, keeping previous project as backup");
+            let new_state =
+                EditorState::with_backups(loaded, state.store.clone(), state.backups.clone());
+            let previous = std::mem::replace(state, new_state);
+            state.backup = previous.active;
+            state.open = previous.open;
+
+            trace!("check for unsaved changes left behind by a previous session");
+            let journal = Journal::read(state.backup_store().as_ref(), &state.active.name)?;
+            if let Some((uid, _, entries)) = journal {
+                if uid == state.active.uid && !entries.is_empty() {
+                    warn!("found {} unsaved changes in the journal", entries.len());
+                    state.scratchpad.push_str(&format!(
+                        "found {} unsaved changes from a previous session, use replay to recover them\r\n",
+                        entries.len()
+                    ));
+                    println!("{}", state.scratchpad);
+                }
+            }
+
+            trace!("check that shared libraries can be read and agree with the project");
+            let library_problems = util::library_problems(&state.active, state.store.as_ref());
+            if !library_problems.is_empty() {
+                warn!("{} problems with shared libraries", library_problems.len());
+                for problem in library_problems.iter() {
+                    state
+                        .scratchpad
+                        .push_str(&format!("warning: {}\r\n", problem));
+                }
+                println!("{}", state.scratchpad);
+            }
+
+            trace!("check that asset files exist");
+            let missing = util::missing_assets(&state.active, state.store.root());
+            if !missing.is_empty() {
+                warn!("{} assets are missing", missing.len());
+                for (key, path) in missing.iter() {
+                    state.scratchpad.push_str(&format!(
+                        "warning: asset {} is missing at {}\r\n",
+                        key,
+                        path.display()
+                    ));
+                }
+                println!("{}", state.scratchpad);
+            }
+            Ok(state.active.uid.low())
+        }
+    }
+
+    /// Recover the active project from the backup file saved to disk before a rebuild
+    ///
+    /// The backup file project_name.tree.bkp is only loaded if its uid matches the uid of the
+    /// active project. Any unsaved changes in the active project are moved to the backup buffer,
+    /// and can be restored with the swap subcommand.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Recover {
+        /// Load the backup file even if its uid does not match the active project
+        #[structopt(short, long)]
+        force: bool,
+    }
+
+    impl Executable for Recover {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Recover project from backup file");
+            let path = store::Backups::path(&state.active.name);
+
+            trace!("check that the backup file belongs to the active project");
+            if !self.force {
+                util::check_uid(state.backup_store().as_ref(), &path, state.active.uid)?;
+            }
+
+            let recovered = store::read_data(state.backup_store().as_ref(), &path)?;
+            util::validate_tree(&recovered)?;
+
+            trace!("move active project to backup buffer and clear history");
+            state.backup = std::mem::replace(&mut state.active, recovered);
+            state.history.clear();
+            // the recovered tree doesn't follow from the saved file, so stop journaling until the
+            // next save. The journal so far still holds the changes made before recovering
+            state.history.journal = None;
+            Ok(state.active.uid.low())
+        }
+    }
+
+    /// Delete old backups of every project in the backup directory, and return the number of
+    /// files deleted
+    ///
+    /// Older backups beyond the number kept by the backup settings are deleted, see
+    /// store::BackupSettings. The latest backup of each project is only deleted with --all.
+    /// Journals of unsaved changes are never deleted.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Purge {
+        /// Number of older backups to keep for each project, instead of the number from the
+        /// backup settings
+        #[new(default)]
+        #[structopt(short, long)]
+        keep: Option<usize>,
+        /// Delete every backup, including the latest backup of each project
+        #[new(default)]
+        #[structopt(short, long)]
+        all: bool,
+    }
+
+    impl Executable for Purge {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Purge backups");
+            let keep = self.keep.unwrap_or(state.backups.keep);
+            let deleted = state.backups.purge(&state.store, keep, self.all)?;
+            for name in deleted.iter() {
+                state.scratchpad.push_str(&format!("deleted {}\r\n", name));
+            }
+            println!("{}", state.scratchpad);
+            Ok(deleted.len())
+        }
+    }
+
+    /// Repair common corruption in a project, and write the result to a new project file
+    ///
+    /// The project file is read without validation, so projects that fail to load can still be
+    /// repaired. If no name is given the active project is repaired. See util::repair_tree for
+    /// the problems that are fixed. The repaired project is written to project_name_repaired.tree
+    /// (or the given output name), the original is never modified. A report of the problems found
+    /// is printed to the scratchpad, followed by any problems that could not be repaired.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Repair {
+        /// Name of the project file to repair, defaults to the active project
+        name: Option<String>,
+        /// Name of the repaired project
+        #[structopt(short, long)]
+        output: Option<String>,
+    }
+
+    impl Executable for Repair {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Repair project");
+            let data = match &self.name {
+                Some(name) => store::read_data(state.store.as_ref(), &(name.clone() + TREE_EXT))?,
+                None => state.active.clone(),
+            };
+            let output = self
+                .output
+                .clone()
+                .unwrap_or_else(|| data.name.clone() + REPAIR_SUFFIX);
+            anyhow::ensure!(output != data.name, Error::RepairOverwrite);
+
+            trace!("repair tree");
+            let (mut repaired, problems) = util::repair_tree(&data);
+            repaired.name = output;
+            for problem in problems.iter() {
+                state
+                    .scratchpad
+                    .push_str(&format!("repaired {}\r\n", problem));
+            }
+            if let Err(e) = util::validate_tree(&repaired) {
+                warn!("repaired tree is still invalid: {}", e);
+                state
+                    .scratchpad
+                    .push_str(&format!("could not repair: {}\r\n", e));
+            }
+            state.scratchpad.push_str(&format!(
+                "{} problems repaired, written to {}{}\r\n",
+                problems.len(),
+                repaired.name,
+                TREE_EXT
+            ));
+            println!("{}", state.scratchpad);
+
+            trace!("write repaired project to a new file");
+            store::write_data(
+                state.store.as_ref(),
+                &(repaired.name.clone() + TREE_EXT),
+                &repaired,
+            )?;
+            Ok(problems.len())
+        }
+    }
+
+    /// Replay unsaved changes from the journal left behind by a previous session
+    ///
+    /// When journaling is enabled (see store::BackupSettings), every change to a project is
+    /// recorded in project_name.tree.journal until the project is saved. If the editor exits
+    /// without saving, for example after a crash, the journal is left behind and load reports it.
+    /// Replaying the journal onto the freshly loaded project restores the unsaved changes,
+    /// including the undo/redo history. The journal is only replayed if its uid matches the active
+    /// project, and if no changes have been made since loading.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Replay {}
+
+    impl Executable for Replay {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Replay journal");
+            trace!("the journal must not have been written to since loading");
+            let started = state.history.journal.as_ref().map(Journal::is_started);
+            anyhow::ensure!(started != Some(true), Error::NothingToReplay);
+            let (uid, base_len, entries) =
+                Journal::read(state.backup_store().as_ref(), &state.active.name)?
+                    .ok_or(Error::NothingToReplay)?;
+            anyhow::ensure!(
+                uid == state.active.uid,
+                Error::UidMismatch(uid, state.active.uid)
+            );
+            anyhow::ensure!(base_len == state.active.text.len(), Error::JournalMismatch);
+
+            let count = entries.len();
+            let journal = match state.journal_store() {
+                Some(journals) => {
+                    trace!("rewrite the journal without any damaged entry at the end");
+                    let mut journal = Journal::new(journals, &state.active);
+                    journal.rewrite(&entries)?;
+                    Some(journal)
+                }
+                None => None,
+            };
+
+            trace!("replay entries onto a scratch copy of the project");
+            let scratch_store = std::sync::Arc::new(store::MemoryStore::default());
+            let mut scratch = EditorState::with_store(state.active.clone(), scratch_store);
+            scratch.history.journal = None;
+            for entry in entries.into_iter() {
+                match entry {
+                    JournalEntry::Push { text, event } => {
+                        scratch.active.text.push_str(&text);
+                        event.redo(&mut scratch.active)?;
+                        scratch.history.push(event, &mut scratch.active);
+                    }
+                    JournalEntry::Undo => scratch.history.undo(&mut scratch.active)?,
+                    JournalEntry::Redo => scratch.history.redo(&mut scratch.active)?,
+                    JournalEntry::Rebuild => {
+                        Rebuild::new().execute(&mut scratch)?;
+                    }
+                }
+            }
+            util::validate_tree(&scratch.active)?;
+
+            trace!("replay succeeded, apply the result and continue the journal");
+            state.backup = std::mem::replace(&mut state.active, scratch.active);
+            state.history.record = scratch.history.record;
+            state.history.position = scratch.history.position;
+            state.history.saved = None;
+            state.history.journal = journal;
+
+            state
+                .scratchpad
+                .push_str(&format!("replayed {} changes from the journal\r\n", count));
+            println!("{}", state.scratchpad);
+            Ok(count)
+        }
+    }
+
+    /// Swap the backup and active trees.
+    ///
+    /// The backup tree stores the state from the last new or save, the previous project before a
+    /// load, or the state just before a rebuild is attempted. This is mainly useful as a recovery
+    /// option if the active tree gets corrupted.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Swap {}
+
+    impl Executable for Swap {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            std::mem::swap(&mut state.active, &mut state.backup);
+            // the swapped in tree doesn't follow from the saved file, stop journaling until the
+            // next save
+            state.history.journal = None;
+            Ok(state.active.uid.low())
+        }
+    }
+
+    /// Move the editor cursor to a node, and return the node index
+    ///
+    /// The cursor is used as the default node for node-relative commands, such as children,
+    /// parent, and new edge.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Goto {
+        /// Index of the node to move to
+        node_index: usize,
+    }
+
+    impl Executable for Goto {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Go to node {}", self.node_index);
+            state.active.tree.get_node(self.node_index)?;
+            state.active.cursor = Some(self.node_index);
+            Ok(self.node_index)
+        }
+    }
+
+    /// Move a node and everything below it under a different parent node
+    ///
+    /// The edge connecting the node to its old parent is moved so that it leaves the new parent
+    /// instead, keeping its text, requirement, and effect. The edge is placed after any existing
+    /// choices of the new parent. Moves that would create a cycle, with the new parent below the
+    /// node, are refused. The move is recorded as a single event, so it can be reverted with one
+    /// undo
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Reparent {
+        /// Index of the node to move
+        node_index: usize,
+        /// Index of the edge from the old parent to the node
+        #[structopt(long)]
+        from: usize,
+        /// Index of the new parent node
+        #[structopt(long)]
+        to: usize,
+    }
+
+    impl Executable for Reparent {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Reparent node {} under node {}", self.node_index, self.to);
+            anyhow::ensure!(
+                state.active.tree.target_of(self.from)? == self.node_index,
+                cmd::Error::EdgeNotIncoming(self.from, self.node_index)
+            );
+            if state.active.tree.get_edge(self.from)?.default {
+                let source = state.active.tree.source_of(self.from)?;
+                anyhow::ensure!(
+                    state.active.tree.get_node(source)?.timeout.is_none(),
+                    cmd::Error::TimeoutWithoutDefault(source)
+                );
+                anyhow::ensure!(
+                    util::default_choices(&state.active.tree, self.to)?.is_empty(),
+                    cmd::Error::MultipleDefaults(self.to)
+                );
+            }
+            let (removed, inserted) = state.active.tree.reparent_edge(self.from, self.to)?;
+            let events = vec![removed.into(), inserted.into()];
+            state
+                .history
+                .push(EventGroup { events }.into(), &mut state.active);
+            Ok(self.from)
+        }
+    }
+
+    /// Print the outgoing edges of the node at the editor cursor, and the nodes they lead to.
+    /// Returns the number of outgoing edges
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Children {}
+
+    impl Executable for Children {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("List children of cursor");
+            let cursor = state.active.cursor.ok_or(cmd::Error::NoCursor)?;
+            let data = &state.active;
+            let tree = &data.tree;
+            let mut renderer = util::TextRenderer::default();
+            let mut count = 0;
+            for edge_index in tree.outgoing_from_index(cursor)? {
+                let text = renderer.edge(data, &data.name_table, edge_index)?;
+                state.scratchpad.push_str(&format!(
+                    "--> edge {} to node {}: \"{}\"\r\n",
+                    edge_index,
+                    tree.target_of(edge_index)?,
+                    text
+                ));
+                count += 1;
+            }
+            println!("{}", state.scratchpad);
+            Ok(count)
+        }
+    }
+
+    /// Print the incoming edges of the node at the editor cursor, and the nodes they come from.
+    /// Returns the number of incoming edges
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Parent {}
+
+    impl Executable for Parent {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("List parents of cursor");
+            let cursor = state.active.cursor.ok_or(cmd::Error::NoCursor)?;
+            let data = &state.active;
+            let tree = &data.tree;
+            tree.get_node(cursor)?;
+            let mut renderer = util::TextRenderer::default();
+            let mut count = 0;
+            for edge_index in 0..tree.edges().len() {
+                if tree.target_of(edge_index)? != cursor {
+                    continue;
+                }
+                let text = renderer.edge(data, &data.name_table, edge_index)?;
+                state.scratchpad.push_str(&format!(
+                    "<-- edge {} from node {}: \"{}\"\r\n",
+                    edge_index,
+                    tree.source_of(edge_index)?,
+                    text
+                ));
+                count += 1;
+            }
+            println!("{}", state.scratchpad);
+            Ok(count)
+        }
+    }
+
+    /// Print every reference to a name or val key to the editor scratchpad, and return the number
+    /// of references found
+    ///
+    /// Names are searched for in node speakers, node text, edge text, requirements, and effects.
+    /// Vals are searched for in requirements and effects.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct WhereUsed {
+        /// The key to search for
+        key: KeyString,
+        /// Maximum number of characters of text to preview for each reference
+        #[structopt(short, long, default_value = "40")]
+        width: usize,
+    }
+
+    impl Executable for WhereUsed {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Find references to {}", self.key);
+            let data = &state.active;
+            let mut renderer = util::TextRenderer::default();
+            let mut count = 0;
+
+            for (kind, label) in [(util::KeyKind::Name, "name"), (util::KeyKind::Val, "val")] {
+                for reference in util::key_references(data, kind, &self.key) {
+                    let preview = match reference {
+                        util::Reference::Speaker(i) | util::Reference::NodeText(i) => {
+                            let (_, text) = renderer.node(data, &data.name_table, i)?;
+                            format!("\"{}\"", util::truncate(text, self.width))
+                        }
+                        util::Reference::EdgeText(i) => {
+                            let text = renderer.edge(data, &data.name_table, i)?;
+                            format!("\"{}\"", util::truncate(text, self.width))
+                        }
+                        util::Reference::Requirement(i) => {
+                            data.tree.get_edge(i)?.requirement.to_string()
+                        }
+                        util::Reference::Effect(i) => data.tree.get_edge(i)?.effect.to_string(),
+                        util::Reference::Direction(i) => util::directions_of(data, i)?
+                            .iter()
+                            .map(Direction::to_string)
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        util::Reference::OnEnter(i) => util::on_enter_of(data, i)?
+                            .iter()
+                            .map(EffectKind::to_string)
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        util::Reference::Interrupt(i) => {
+                            let interrupt = data
+                                .interrupts
+                                .get(i)
+                                .ok_or(cmd::Error::InvalidInterruptPosition(i))?;
+                            format!("{} -> node {}", interrupt.requirement, interrupt.target)
+                        }
+                    };
+                    state.scratchpad.push_str(&format!(
+                        "{} {}: {}: {}\r\n",
+                        label, self.key, reference, preview
+                    ));
+                    count += 1;
+                }
+            }
+            println!("{}", state.scratchpad);
+            Ok(count)
+        }
+    }
+
+    /// Print every node and edge whose text contains a pattern to the editor scratchpad, and
+    /// return the number found
+    ///
+    /// Only the prose is matched, the keys of speakers and name substitutions are skipped like
+    /// they are by replace. Text is indexed the first time it is searched, so repeated searches of
+    /// a large project stay fast. See util::SearchIndex for details.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Find {
+        /// The text to search for
+        pattern: String,
+        /// Ignore ASCII case when matching
+        #[structopt(short, long)]
+        ignore_case: bool,
+        /// Maximum number of characters of text to preview for each match
+        #[structopt(short, long, default_value = "40")]
+        width: usize,
+    }
+
+    impl Executable for Find {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Find {:?}", self.pattern);
+            let found = util::search(&mut state.active, &self.pattern, self.ignore_case)?;
+            let data = &state.active;
+            let mut renderer = util::TextRenderer::default();
+            for reference in found.iter() {
+                let text = match *reference {
+                    util::Reference::NodeText(i) => renderer.node(data, &data.name_table, i)?.1,
+                    util::Reference::EdgeText(i) => renderer.edge(data, &data.name_table, i)?,
+                    _ => continue,
+                };
+                state.scratchpad.push_str(&format!(
+                    "{}: \"{}\"\r\n",
+                    reference,
+                    util::truncate(text, self.width)
+                ));
+            }
+            println!("{}", state.scratchpad);
+            Ok(found.len())
+        }
+    }
+
+    /// Run the story logic assertions in a file against the active project, and return the
+    /// number of assertions that failed
+    ///
+    /// Each line of the file plays a path of choices and checks where it ends, and the names and
+    /// values at the end, e.g. "path 0,1,2 ends at ending_good with gold >= 50". See
+    /// runner::Assertion for the format. A pass or failure is printed for each assertion, so the
+    /// file can be run as a test suite.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Test {
+        /// Path of the assertion file
+        file: String,
+        /// Start every path from the starting values of a preset, see preset set
+        #[structopt(short, long)]
+        #[new(default)]
+        preset: Option<String>,
+    }
+
+    impl Executable for Test {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Test assertions in {}", self.file);
+            let assertions = runner::Assertion::parse_all(&std::fs::read_to_string(&self.file)?)?;
+            let assumptions = match &self.preset {
+                Some(preset) => state.active.preset(preset)?.clone(),
+                None => runner::Assumptions::default(),
+            };
+            let mut failed = 0;
+            for assertion in assertions.iter() {
+                match assertion.failure_with(&state.active, &assumptions) {
+                    None => state.scratchpad.push_str(&format!(
+                        "pass line {}: {}\r\n",
+                        assertion.line, assertion.text
+                    )),
+                    Some(failure) => {
+                        failed += 1;
+                        state.scratchpad.push_str(&format!(
+                            "FAIL line {}: {}\r\n    {}\r\n",
+                            assertion.line, assertion.text, failure
+                        ));
+                    }
+                }
+            }
+            state.scratchpad.push_str(&format!(
+                "{} passed, {} failed\r\n",
+                assertions.len() - failed,
+                failed
+            ));
+            println!("{}", state.scratchpad);
+            Ok(failed)
+        }
+    }
+
+    /// Play a batch of random playthroughs and print how they end, and return the number of runs
+    /// that reached an ending
+    ///
+    /// Each run picks at random among the available choices. Runs are seeded from the seed, so
+    /// simulating again with the same seed gives the same results, see runner::simulate. Without
+    /// a seed one is drawn at random, and printed so that the runs can be reproduced. Prints how
+    /// often each ending was reached and each choice was made, and the spread of the values at
+    /// the end of the runs.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Simulate {
+        /// Seed of the first run, run i is seeded with seed + i. Random if not given
+        #[structopt(short, long)]
+        seed: Option<u64>,
+        /// Number of runs
+        #[structopt(short, long, default_value = "100")]
+        runs: usize,
+        /// Node every run starts from
+        #[structopt(long, default_value = "0")]
+        start: usize,
+        /// Stop a run after this many choices, for trees that loop
+        #[structopt(short, long, default_value = "1000")]
+        max_steps: usize,
+        /// Start every run from the starting values of a preset, see preset set
+        #[structopt(short, long)]
+        #[new(default)]
+        preset: Option<String>,
+    }
+
+    impl Executable for Simulate {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Simulate {} runs from node {}", self.runs, self.start);
+            let assumptions = match &self.preset {
+                Some(preset) => state.active.preset(preset)?.clone(),
+                None => runner::Assumptions::default(),
+            };
+            let seed = self.seed.unwrap_or_else(rand::random);
+            let simulation = runner::simulate(
+                &state.active,
+                self.start,
+                &assumptions,
+                Some(seed),
+                self.runs,
+                self.max_steps,
+            )?;
+            state.scratchpad.push_str(&simulation.to_string());
+            println!("{}", state.scratchpad);
+            Ok(simulation.runs - simulation.unfinished)
+        }
+    }
+
+    /// Replace a pattern in the text of every node and edge, and return the number of nodes and
+    /// edges changed
+    ///
+    /// Only dialogue and choice text is replaced, speakers and name substitutions are left as
+    /// they are. The replacement can be limited to the nodes or edges selected by a query. The
+    /// entire replacement is recorded as a single event, so it can be reverted with one undo
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Replace {
+        /// The text to search for
+        pattern: String,
+        /// The text to replace it with
+        replacement: String,
+        /// Ignore ASCII case when matching
+        #[structopt(short, long)]
+        ignore_case: bool,
+        /// Only replace text in the nodes or edges selected by a query, e.g. "select edges where
+        /// text contains gold", see list --query
+        #[structopt(short, long)]
+        #[new(default)]
+        query: Option<String>,
+    }
+
+    impl Executable for Replace {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Replace {:?} with {:?}", self.pattern, self.replacement);
+            let selection = match &self.query {
+                Some(query) => {
+                    let query = query.parse::<query::Query>()?;
+                    let selected: HashSet<usize> =
+                        query.select(&state.active)?.into_iter().collect();
+                    Some((query.target, selected))
+                }
+                None => None,
+            };
+            let found = util::search(&mut state.active, &self.pattern, self.ignore_case)?;
+            let data = &mut state.active;
+            let mut events: Vec<DialogueTreeEvent> = Vec::new();
+            for reference in found {
+                let (section, target, i) = match reference {
+                    util::Reference::NodeText(i) => {
+                        (data.tree.get_node(i)?.section, query::Target::Nodes, i)
+                    }
+                    util::Reference::EdgeText(i) => {
+                        (data.tree.get_edge(i)?.section, query::Target::Edges, i)
+                    }
+                    _ => continue,
+                };
+                if let Some((selected_target, selected)) = &selection {
+                    if *selected_target != target || !selected.contains(&i) {
+                        continue;
+                    }
+                }
+                let text = data
+                    .text
+                    .get(section[0]..section[1])
+                    .ok_or(cmd::Error::InvalidSection)?;
+                let new_text = match util::replace_text(
+                    text,
+                    &self.pattern,
+                    &self.replacement,
+                    self.ignore_case,
+                ) {
+                    Some(new_text) => new_text,
+                    None => continue,
+                };
+                let section = util::push_section(&mut data.text, &new_text);
+                let event: DialogueTreeEvent = match reference {
+                    util::Reference::NodeText(i) => {
+                        let node = *data.tree.get_node(i)?;
+                        data.tree.edit_node(i, Dialogue { section, ..node })?.into()
+                    }
+                    _ => {
+                        let i = reference.edge().ok_or(cmd::Error::Generic)?;
+                        let edge = data.tree.get_edge(i)?.clone();
+                        data.tree.edit_edge(i, Choice { section, ..edge })?.into()
+                    }
+                };
+                events.push(event);
+            }
+
+            let count = events.len();
+            if count > 0 {
+                state
+                    .history
+                    .push(EventGroup { events }.into(), &mut state.active);
+            }
+            Ok(count)
+        }
+    }
+
+    /// Check the active project for problems without modifying it
+    ///
+    /// Reports structural problems found by validating the tree, name and val keys that are
+    /// unused or missing, assets whose files do not exist, shared libraries that are missing or
+    /// conflict with the project, nodes with duplicate or too many choices, and breaks of the
+    /// project's naming rules, see edit naming. Each problem is printed to the editor scratchpad.
+    /// Returns the number of problems found.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Check {
+        /// Maximum number of choices a node may have before it is reported
+        #[structopt(short, long, default_value = "6")]
+        max_choices: usize,
+    }
+
+    impl Executable for Check {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Check project");
+            let mut problems = 0;
+
+            trace!("validate the tree");
+            if let Err(e) = util::validate_tree(&state.active) {
+                problems += 1;
+                state.scratchpad.push_str(&format!("error: {}\r\n", e));
+            }
+
+            trace!("check for unused and missing keys");
+            let report = util::project_key_usage(&state.active, state.store.as_ref());
+            problems += report.unused_names.len()
+                + report.unused_vals.len()
+                + report.missing_names.len()
+                + report.missing_vals.len();
+            state.scratchpad.push_str(&report.to_string());
+
+            trace!("check that asset files exist");
+            let missing = util::missing_assets(&state.active, state.store.root());
+            problems += missing.len();
+            for (key, path) in missing.iter() {
+                state.scratchpad.push_str(&format!(
+                    "warning: asset {} is missing at {}\r\n",
+                    key,
+                    path.display()
+                ));
+            }
+
+            trace!("check that shared libraries can be read and agree with the project");
+            let library_problems = util::library_problems(&state.active, state.store.as_ref());
+            problems += library_problems.len();
+            for problem in library_problems.iter() {
+                state
+                    .scratchpad
+                    .push_str(&format!("warning: {}\r\n", problem));
+            }
+
+            trace!("check for duplicate choices and nodes with too many choices");
+            let lints = util::lint_choices(&state.active, self.max_choices)?;
+            problems += lints.len();
+            for lint in lints.iter() {
+                state.scratchpad.push_str(&format!("{}\r\n", lint));
+            }
+
+            trace!("check naming conventions");
+            let lints = util::lint_names(&state.active)?;
+            problems += lints.len();
+            for lint in lints.iter() {
+                state.scratchpad.push_str(&format!("{}\r\n", lint));
+            }
+
+            if problems == 0 {
+                state.scratchpad.push_str("no problems found\r\n");
+            }
+            println!("{}", state.scratchpad);
+            Ok(problems)
+        }
+    }
+
+    /// Print metadata about the active project to the editor scratchpad
+    ///
+    /// Includes the project name and uid, which is used to check that files on disk belong to
+    /// the active project, along with the size of the tree and tables.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Meta {}
+
+    impl Executable for Meta {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            state.scratchpad.push_str(&format!(
+                "project: {}\r\nuid: {}\r\nnodes: {}\r\nedges: {}\r\nnames: {}\r\nvals: {}\r\nchoices: {}\r\n",
+                state.active.name,
+                state.active.uid,
+                state.active.tree.nodes().len(),
+                state.active.tree.edges().len(),
+                state.active.name_table.len(),
+                state.active.val_table.len(),
+                state.active.choice_policy,
+            ));
+            println!("{}", state.scratchpad);
+            Ok(state.active.uid.low())
+        }
+    }
+
+    /// Print statistics about the active project to the editor scratchpad
+    ///
+    /// By default prints the number of nodes, edges, table entries and history events. With
+    /// --memory, prints the approximate memory allocated by each part of the project instead,
+    /// which shows how much space a rebuild could free.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Stats {
+        /// Print the memory used by the text, tree, tables and history
+        #[structopt(short, long)]
+        memory: bool,
+    }
+
+    impl Executable for Stats {
+        /// Returns the total bytes used if printing memory stats, otherwise the number of nodes
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            let res = if self.memory {
+                let stats = state.memory_stats();
+                let parts = [
+                    ("text", stats.text),
+                    ("nodes", stats.nodes),
+                    ("edges", stats.edges),
+                    ("links", stats.links),
+                    ("tables", stats.tables),
+                    ("cache", stats.cache),
+                    ("history", stats.history),
+                    ("total", stats.total()),
+                ];
+                for (part, bytes) in parts.iter() {
+                    state.scratchpad.push_str(&format!(
+                        "{}: {}\r\n",
+                        part,
+                        util::format_bytes(*bytes)
+                    ));
+                }
+                stats.total()
+            } else {
+                let data = &state.active;
+                state.scratchpad.push_str(&format!(
+                    "nodes: {}\r\nedges: {}\r\nnames: {}\r\nvals: {}\r\nbookmarks: {}\r\n\
+                    text: {} bytes\r\nhistory: {} events\r\n",
+                    data.tree.nodes().len(),
+                    data.tree.edges().len(),
+                    data.name_table.len(),
+                    data.val_table.len(),
+                    data.bookmarks.len(),
+                    data.text.len(),
+                    state.history.record.len(),
+                ));
+                data.tree.nodes().len()
+            };
+            println!("{}", state.scratchpad);
+            Ok(res)
+        }
+    }
+
+    /// Print structural metrics of the active project to the editor scratchpad
+    ///
+    /// Prints a histogram of node depths, the fewest choices needed to reach each node from the
+    /// root, and of the number of choices at each node, along with the branching factor and the
+    /// number of nodes where paths converge. See the analysis module for details.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct Analyze {
+        /// Print the metrics as JSON
+        #[structopt(short, long)]
+        json: bool,
+    }
+
+    impl Executable for Analyze {
+        /// Returns the depth of the deepest node reachable from the root
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            info!("Analyze tree structure");
+            let metrics = analysis::analyze(&state.active.tree)?;
+            if self.json {
+                state
+                    .scratchpad
+                    .push_str(&serde_json::to_string_pretty(&metrics)?);
+                state.scratchpad.push_str("\r\n");
+            } else {
+                state.scratchpad.push_str(&metrics.to_string());
+            }
+            println!("{}", state.scratchpad);
+            Ok(metrics.depths.len().saturating_sub(1))
+        }
+    }
+
+    /// Print all nodes, edges, and associated text to the editor scratchpad
+    ///
+    /// Prints all nodes in index order (not necessarily the order they would appear when
+    /// traversing the dialogue tree). Under each node definiton, a list of the outgoing edges from
+    /// that node will be listed, in the order set by the project's choice policy. This will show
+    /// the path to the next dialogue option from any node, and the choice/action text associated
+    /// with that edge.
+    ///
+    /// By default the output is a table with aligned columns, where long text is truncated to a
+    /// short preview. Requirements and effects are colorized when printing to a terminal. Node
+    /// rows also show the project uid, to tell apart projects with the same name. The raw output
+    /// prints the full text of every node and edge in a free-form format.
+    ///
+    /// Note that edge and node indices will not remain stable if nodes/edges are removed from the
+    /// graph.
+    #[derive(new, StructOpt, Debug)]
+    #[structopt(setting = AppSettings::NoBinaryName)]
+    pub struct List {
+        /// Print the full text of every node and edge without formatting
+        #[structopt(short, long)]
+        raw: bool,
+        /// Maximum number of characters of text to preview in the table and endings
+        #[structopt(short, long, default_value = "40")]
+        width: usize,
+        /// Only list the nodes or edges selected by a query, e.g. "select nodes where
+        /// out_degree == 0". Selected nodes are listed with all outgoing edges, and selected
+        /// edges are listed under their source node
+        #[structopt(short, long)]
+        query: Option<String>,
+        /// Check each edge's requirement against hypothetical names and values, e.g.
+        /// "gold=20,flag=1", and show whether it passes. Keys that aren't assumed keep their
+        /// value from the project
+        #[structopt(short, long)]
+        assume: Option<runner::Assumptions>,
+        /// Check each edge's requirement against the starting values of a preset, see preset
+        /// set. Assumptions given with --assume are applied on top of the preset
+        #[structopt(short, long)]
+        #[new(default)]
+        preset: Option<String>,
+        #[structopt(subcommand)]
+        #[new(default)]
+        listing: Option<Listing>,
+    }
+
+    /// Part of the tree to list on its own
+    #[derive(StructOpt, Debug, Clone, Copy, PartialEq)]
+    pub enum Listing {
+        /// List the nodes where the dialogue ends, meaning nodes with no outgoing edges, along
+        /// with the text of the last choice on each path leading to them. Returns the number of
+        /// endings
+        Endings,
+    }
+
+    impl Executable for List {
+        fn execute(&self, state: &mut EditorState) -> Result<usize> {
+            if let Some(Listing::Endings) = self.listing {
+                let count = self.list_endings(state)?;
+                println!("{}", state.scratchpad);
+                return Ok(count);
+            }
+            if self.raw {
+                self.list_raw(state)?;
+            } else {
+                self.list_table(state)?;
+            }
+            println!("{}", state.scratchpad);
+            Ok(state.active.uid.low())
+        }
+    }
+
+    impl List {
+        /// Get the preset and assumptions to check requirements against, if any were given
+        fn assumptions(&self, data: &DialogueTreeData) -> Result<Option<runner::Assumptions>> {
+            let mut assumptions = match (&self.preset, &self.assume) {
+                (None, None) => return Ok(None),
+                (Some(preset), _) => data.preset(preset)?.clone(),
+                (None, Some(_)) => runner::Assumptions::default(),
+            };
+            if let Some(assume) = &self.assume {
+                assumptions.merge(assume);
+            }
+            Ok(Some(assumptions))
+        }
+
+        /// Find the nodes and edges to list, based on the query if one was provided
+        fn visible(&self, data: &DialogueTreeData) -> Result<(FixedBitSet, FixedBitSet)> {
+            let mut nodes = FixedBitSet::with_capacity(data.tree.nodes().len());
+            let mut edges = FixedBitSet::with_capacity(data.tree.edges().len());
+            let query = match &self.query {
+                Some(query) => query.parse::<query::Query>()?,
+                None => {
+                    nodes.set_range(.., true);
+                    edges.set_range(.., true);
+                    return Ok((nodes, edges));
+                }
+            };
+
+            let selection = query.select(data)?;
+            match query.target {
+                query::Target::Nodes => {
+                    for node_index in selection {
+                        nodes.insert(node_index);
+                        data.tree
+                            .outgoing_from_index(node_index)?
+                            .for_each(|edge_index| edges.insert(edge_index));
+                    }
+                }
+                query::Target::Edges => {
+                    for edge_index in selection {
+                        edges.insert(edge_index);
+                        nodes.insert(data.tree.source_of(edge_index)?);
+                    }
+                }
+            }
+            Ok((nodes, edges))
+        }
+
+        /// Describe a node's line. Narration without a narrator label is shown without a speaker
+        fn node_line(idx: NodeIndex, name: &str, text: &str) -> String {
+            if name.is_empty() {
+                format!("node {}: \"{}\"", idx, text)
+            } else {
+                format!("node {}: {} says \"{}\"", idx, name, text)
+            }
+        }
+
+        /// Check an edge's requirement against the assumed names and values, if any were given
+        fn assumed(
+            tables: Option<&(NameTable, ValTable)>,
+            requirement: &ReqKind,
+        ) -> Option<&'static str> {
+            let (names, vals) = tables?;
+            Some(if runner::requirement_met(requirement, names, vals) {
+                "pass"
+            } else {
+                "fail"
+            })
+        }
+
+        fn list_endings(&self, state: &mut EditorState) -> Result<usize> {
+            let data = &state.active;
+            let mut renderer = util::TextRenderer::default();
+            let (visible_nodes, _) = self.visible(data)?;
+            let mut incoming = vec![Vec::new(); data.tree.nodes().len()];
+            for edge_index in 0..data.tree.edges().len() {
+                incoming[data.tree.target_of(edge_index)?].push(edge_index);
+            }
+
+            let mut count = 0;
+            for idx in data.tree.terminal_nodes() {
+                if !visible_nodes.contains(idx) {
+                    continue;
+                }
+                let (name, text) = renderer.node(data, &data.name_table, idx)?;
+                let line = List::node_line(idx, name, &util::truncate(text, self.width));
+                state.scratchpad.push_str(&line);
+                state.scratchpad.push_str("\r\n");
+                for edge_index in incoming[idx].iter().copied() {
+                    let text = renderer.edge(data, &data.name_table, edge_index)?;
+                    state.scratchpad.push_str(&format!(
+                        "<-- edge {} from node {}: \"{}\"\r\n",
+                        edge_index,
+                        data.tree.source_of(edge_index)?,
+                        util::truncate(text, self.width)
+                    ));
+                }
+                count += 1;
+            }
+            Ok(count)
+        }
+
+        fn list_raw(&self, state: &mut EditorState) -> Result<()> {
+            let mut renderer = util::TextRenderer::default();
+            let (visible_nodes, visible_edges) = self.visible(&state.active)?;
+            let assumed = self
+                .assumptions(&state.active)?
+                .map(|a| a.apply(&state.active));
+            let node_iter = state.active.tree.nodes().iter().enumerate();
+
+            for (idx, node) in node_iter.filter(|(idx, _)| visible_nodes.contains(*idx)) {
+                let (name, text) = renderer.node(&state.active, &state.active.name_table, idx)?;
+                state.scratchpad.push_str(&List::node_line(idx, name, text));
+                if let Some(timeout) = node.timeout {
+                    state
+                        .scratchpad
+                        .push_str(&format!(", timeout: {}ms", timeout));
+                }
+                if !node.flags.is_empty() {
+                    state
+                        .scratchpad
+                        .push_str(&format!(", flags: {}", node.flags));
+                }
+                if let Some(directions) = state.active.directions.get(&idx) {
+                    let directions: Vec<String> =
+                        directions.iter().map(Direction::to_string).collect();
+                    state
+                        .scratchpad
+                        .push_str(&format!(", directions: {}", directions.join(" ")));
+                }
+                if let Some(effects) = state.active.on_enter.get(&idx) {
+                    let effects: Vec<String> = effects.iter().map(EffectKind::to_string).collect();
+                    state
+                        .scratchpad
+                        .push_str(&format!(", on enter: {}", effects.join(" ")));
+                }
+                state.scratchpad.push_str("\r\n");
+                let outgoing_edges = runner::ordered_edges(&state.active, idx)?;
+                for edge_index in outgoing_edges
+                    .into_iter()
+                    .filter(|e| visible_edges.contains(*e))
+                {
+                    let choice = state.active.tree.get_edge(edge_index)?;
+                    let text =
+                        renderer.edge(&state.active, &state.active.name_table, edge_index)?;
+                    state.scratchpad.push_str(&format!(
+                        "--> edge {} to node {}: \"{}\"\r\n    requirements: {:?}, effects: {:?}",
+                        edge_index,
+                        state.active.tree.target_of(edge_index)?,
+                        text,
+                        choice.requirement,
+                        choice.effect,
+                    ));
+                    if let Some(check) = List::assumed(assumed.as_ref(), &choice.requirement) {
+                        state.scratchpad.push_str(&format!(", assumed: {}", check));
+                    }
+                    if choice.default {
+                        state.scratchpad.push_str(", default");
+                    }
+                    if choice.call {
+                        state.scratchpad.push_str(", call");
+                    }
+                    state.scratchpad.push_str("\r\n");
+                }
+            }
+            Ok(())
+        }
+
+        fn list_table(&self, state: &mut EditorState) -> Result<()> {
+            use std::io::IsTerminal;
+
+            let mut renderer = util::TextRenderer::default();
+            let mut rows = Vec::with_capacity(state.active.tree.nodes().len());
+            let data = &state.active;
+            let (visible_nodes, visible_edges) = self.visible(data)?;
+            let assumed = self.assumptions(data)?.map(|a| a.apply(data));
+
+            for idx in 0..data.tree.nodes().len() {
+                if !visible_nodes.contains(idx) {
+                    continue;
+                }
+                let (name, text) = renderer.node(data, &data.name_table, idx)?;
+                rows.push(vec![
+                    idx.to_string(),
+                    String::new(),
+                    String::new(),
+                    name.to_string(),
+                    util::truncate(text, self.width),
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    data.uid.to_string(),
+                ]);
+                for edge_index in runner::ordered_edges(data, idx)? {
+                    if !visible_edges.contains(edge_index) {
+                        continue;
+                    }
+                    let choice = data.tree.get_edge(edge_index)?;
+                    let text = renderer.edge(data, &data.name_table, edge_index)?;
+                    rows.push(vec![
+                        String::new(),
+                        edge_index.to_string(),
+                        data.tree.target_of(edge_index)?.to_string(),
+                        String::new(),
+                        util::truncate(text, self.width),
+                        match &choice.requirement {
+                            ReqKind::No => String::new(),
+                            req => req.to_string(),
+                        },
+                        match choice.effect {
+                            EffectKind::No => String::new(),
+                            effect => effect.to_string(),
+                        },
+                        List::assumed(assumed.as_ref(), &choice.requirement)
+                            .unwrap_or_default()
+                            .to_string(),
+                        String::new(),
+                    ]);
+                }
+            }
+
+            let mut columns = vec![
+                util::Column::new("NODE", None),
+                util::Column::new("EDGE", None),
+                util::Column::new("TARGET", None),
+                util::Column::new("SPEAKER", None),
+                util::Column::new("TEXT", None),
+                util::Column::new("REQUIREMENT", Some("33")),
+                util::Column::new("EFFECT", Some("32")),
+                util::Column::new("ASSUMED", Some("36")),
+                util::Column::new("UID", None),
+            ];
+            // the assumed column is only shown when checking against assumptions
+            if assumed.is_none() {
+                columns.remove(7);
+                for row in rows.iter_mut() {
+                    row.remove(7);
+                }
+            }
+            // only colorize when a person is reading the output
+            state.scratchpad.push_str(&util::render_table(
+                &columns,
+                &rows,
+                std::io::stdout().is_terminal(),
+            ));
+            Ok(())
+        }
+    }
+
+    pub mod bookmark {
+        use super::*;
+
+        /// Bookmark nodes to find them again by name
+        ///
+        /// Node indices change as nodes are removed, while bookmarks follow the node they point
+        /// at. Bookmarked nodes cannot be removed until the bookmark is removed.
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Add(bookmark::Add),
+            Remove(bookmark::Remove),
+            List(bookmark::List),
+            Goto(bookmark::Goto),
+        }
+
+        /// Add a bookmark to a node
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Add {
+            /// Name of the bookmark. Maximum length of 8 characters
+            key: KeyString,
+            /// Index of the node to bookmark
+            node_index: usize,
+        }
+
+        impl Executable for Add {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Add bookmark {} to node {}", self.key, self.node_index);
+
+                trace!("check that node exists and key is not already used");
+                state.active.tree.get_node(self.node_index)?;
+                if state.active.bookmarks.contains_key(&self.key) {
+                    return Err(cmd::Error::BookmarkExists.into());
+                }
+
+                state.active.bookmarks.insert(self.key, self.node_index);
+                state.history.push(
+                    BookmarkInsert {
+                        key: self.key,
+                        index: self.node_index,
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+                Ok(self.node_index)
+            }
+        }
+
+        /// Remove a bookmark, the bookmarked node is not modified
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Remove {
+            /// Name of the bookmark
+            key: KeyString,
+        }
+
+        impl Executable for Remove {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove bookmark {}", self.key);
+
+                let index = state
+                    .active
+                    .bookmarks
+                    .remove(&self.key)
+                    .ok_or(cmd::Error::BookmarkNotExists)?;
+                state.history.push(
+                    BookmarkRemove {
+                        key: self.key,
+                        index,
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+                Ok(index)
+            }
+        }
+
+        /// Print all bookmarks and the node they point at to the editor scratchpad
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct List {}
+
+        impl Executable for List {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("List bookmarks");
+
+                let mut bookmarks: Vec<_> = state.active.bookmarks.iter().collect();
+                bookmarks.sort_unstable();
+                for (key, index) in bookmarks.iter() {
+                    state
+                        .scratchpad
+                        .push_str(&format!("{}: node {}\r\n", key, index));
+                }
+                println!("{}", state.scratchpad);
+                Ok(bookmarks.len())
+            }
+        }
+
+        /// Move the editor cursor to a bookmarked node, and return the node index
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Goto {
+            /// Name of the bookmark
+            key: KeyString,
+        }
+
+        impl Executable for Goto {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Go to bookmark {}", self.key);
+
+                let index = *state
+                    .active
+                    .bookmarks
+                    .get(&self.key)
+                    .ok_or(cmd::Error::BookmarkNotExists)?;
+                state.active.cursor = Some(index);
+                Ok(index)
+            }
+        }
+    }
+
+    pub mod asset {
+        use super::*;
+
+        /// Register portraits, voice lines, locale files and other assets used by the project
+        ///
+        /// Asset paths are stored relative to the asset root, which is itself relative to the
+        /// directory of the project file, so that projects can be moved between machines. Paths
+        /// are resolved when the project is loaded, and missing files are reported by load and
+        /// check.
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Add(asset::Add),
+            Remove(asset::Remove),
+            Root(asset::Root),
+            List(asset::List),
+        }
+
+        /// Add an asset to the project. The file does not need to exist yet
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Add {
+            /// Name of the asset. Maximum length of 8 characters
+            key: KeyString,
+            /// Path to the asset, relative to the asset root
+            path: String,
+        }
+
+        impl Executable for Add {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Add asset {} at {}", self.key, self.path);
+                anyhow::ensure!(
+                    !state.active.assets.contains_key(&self.key),
+                    cmd::Error::AssetExists(self.key)
+                );
+
+                state.active.assets.insert(self.key, self.path.clone());
+                state.history.push(
+                    AssetInsert {
+                        key: self.key,
+                        path: self.path.clone(),
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+                Ok(state.active.assets.len())
+            }
+        }
+
+        /// Remove an asset from the project, the file itself is not deleted
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Remove {
+            /// Name of the asset
+            key: KeyString,
+        }
+
+        impl Executable for Remove {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove asset {}", self.key);
+
+                let path = state
+                    .active
+                    .assets
+                    .remove(&self.key)
+                    .ok_or(cmd::Error::AssetNotExists(self.key))?;
+                state.history.push(
+                    AssetRemove {
+                        key: self.key,
+                        path,
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+                Ok(state.active.assets.len())
+            }
+        }
+
+        /// Set the directory that asset paths are relative to
+        ///
+        /// A relative root is relative to the directory of the project file, and an empty root is
+        /// the project directory itself.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Root {
+            /// The asset root directory
+            dir: String,
+        }
+
+        impl Executable for Root {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Set asset root to {}", self.dir);
+
+                let from = std::mem::replace(&mut state.active.asset_root, self.dir.clone());
+                state.history.push(
+                    AssetRootEdit {
+                        from,
+                        to: self.dir.clone(),
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+                Ok(0)
+            }
+        }
+
+        /// Print all assets and their resolved paths to the editor scratchpad, marking any that
+        /// are missing
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct List {}
+
+        impl Executable for List {
+            /// Returns the number of missing assets
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("List assets");
+
+                let mut assets: Vec<_> = state.active.assets.keys().copied().collect();
+                assets.sort_unstable();
+                let mut missing = 0;
+                for key in assets.iter() {
+                    let path = state.resolve_asset(key)?;
+                    let marker = if path.exists() {
+                        ""
+                    } else {
+                        missing += 1;
+                        " (missing)"
+                    };
+                    state.scratchpad.push_str(&format!(
+                        "{}: {}{}\r\n",
+                        key,
+                        path.display(),
+                        marker
+                    ));
+                }
+                println!("{}", state.scratchpad);
+                Ok(missing)
+            }
+        }
+    }
+
+    pub mod library {
+        use super::*;
+
+        /// Share names and values between projects through library files
+        ///
+        /// A library is a JSON file of names and values in the project store, see Library.
+        /// Entries of the libraries a project uses are merged into its tables when it is loaded,
+        /// and are not written to the project file when it is saved, so the library stays the
+        /// only copy. Projects can change a library entry, the changed entry is saved with the
+        /// project and reported as a conflict by load and check.
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Add(library::Add),
+            Remove(library::Remove),
+            Create(library::Create),
+            List(library::List),
+        }
+
+        /// Use a library in the active project, merging its entries into the name and val tables
+        ///
+        /// Keys the project already has keep their entry, any that differ from the library are
+        /// reported as conflicts. Returns the number of entries merged.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Add {
+            /// Name of the library, without the library extension
+            name: String,
+        }
+
+        impl Executable for Add {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Add library {}", self.name);
+                anyhow::ensure!(
+                    !state.active.libraries.contains(&self.name),
+                    cmd::Error::LibraryExists(self.name.clone())
+                );
+                let library = store::read_library(state.store.as_ref(), &self.name)?;
+
+                let position = state.active.libraries.len();
+                state.active.libraries.push(self.name.clone());
+                let mut events: Vec<DialogueTreeEvent> = vec![LibraryInsert {
+                    position,
+                    name: self.name.clone(),
+                }
+                .into()];
+                for (key, name) in library.names.iter() {
+                    if !state.active.name_table.contains_key(key) {
+                        state.active.name_table.insert(*key, *name);
+                        events.push(
+                            NameTableInsert {
+                                key: *key,
+                                name: *name,
+                            }
+                            .into(),
+                        );
+                    }
+                }
+                for (key, value) in library.vals.iter() {
+                    if !state.active.val_table.contains_key(key) {
+                        state.active.val_table.insert(*key, *value);
+                        events.push(
+                            ValTableInsert {
+                                key: *key,
+                                value: *value,
+                            }
+                            .into(),
+                        );
+                    }
+                }
+                let merged = events.len() - 1;
+                state
+                    .history
+                    .push(EventGroup { events }.into(), &mut state.active);
+
+                let problems = util::library_problems(&state.active, state.store.as_ref());
+                for problem in problems.iter().filter(|problem| match problem {
+                    util::LibraryProblem::NameConflict(library, _, _, _)
+                    | util::LibraryProblem::ValConflict(library, _, _, _) => *library == self.name,
+                    util::LibraryProblem::Unreadable(_, _) => false,
+                }) {
+                    state
+                        .scratchpad
+                        .push_str(&format!("warning: {}\r\n", problem));
+                }
+                Ok(merged)
+            }
+        }
+
+        /// Stop using a library in the active project
+        ///
+        /// Entries merged from the library are kept in the project's tables, and are saved with
+        /// the project from now on. Returns the number of libraries still used.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Remove {
+            /// Name of the library
+            name: String,
+        }
+
+        impl Executable for Remove {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove library {}", self.name);
+                let position = state
+                    .active
+                    .libraries
+                    .iter()
+                    .position(|name| *name == self.name)
+                    .ok_or_else(|| cmd::Error::LibraryNotExists(self.name.clone()))?;
+                let name = state.active.libraries.remove(position);
+                state
+                    .history
+                    .push(LibraryRemove { position, name }.into(), &mut state.active);
+                Ok(state.active.libraries.len())
+            }
+        }
+
+        /// Write the name and val tables of the active project to a new library file
+        ///
+        /// Used to start a library from an existing project, the project does not use the
+        /// library until it is added. Returns the number of entries written.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Create {
+            /// Name of the library, without the library extension
+            name: String,
+            /// Overwrite the library if it already exists
+            #[structopt(short, long)]
+            #[new(default)]
+            force: bool,
+        }
+
+        impl Executable for Create {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Create library {}", self.name);
+                let path = self.name.clone() + LIBRARY_EXT;
+                anyhow::ensure!(
+                    self.force || !state.store.exists(&path)?,
+                    cmd::Error::LibraryExists(self.name.clone())
+                );
+                let library = Library {
+                    names: state.active.name_table.clone(),
+                    vals: state.active.val_table.clone(),
+                };
+                store::replace_library(state.store.as_ref(), &self.name, &library)?;
+                Ok(library.names.len() + library.vals.len())
+            }
+        }
+
+        /// Print the libraries used by the active project to the editor scratchpad, along with
+        /// any problems found with them
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct List {}
+
+        impl Executable for List {
+            /// Returns the number of problems found
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("List libraries");
+                for name in state.active.libraries.iter() {
+                    if let Ok(library) = store::read_library(state.store.as_ref(), name) {
+                        state.scratchpad.push_str(&format!(
+                            "{}: {} names, {} vals\r\n",
+                            name,
+                            library.names.len(),
+                            library.vals.len()
+                        ));
+                    }
+                }
+                let problems = util::library_problems(&state.active, state.store.as_ref());
+                for problem in problems.iter() {
+                    state
+                        .scratchpad
+                        .push_str(&format!("warning: {}\r\n", problem));
+                }
+                println!("{}", state.scratchpad);
+                Ok(problems.len())
+            }
+        }
+    }
+
+    pub mod preset {
+        use super::*;
+
+        /// Name starting values for playthroughs, such as "new game", "chapter 3 save", or "max
+        /// stats"
+        ///
+        /// A preset replaces some of the project's names and values when a playthrough starts,
+        /// keys it doesn't set keep their value from the project. Presets are saved with the
+        /// project and can be selected by list --preset, test --preset, the editor's playthrough
+        /// preview, and the reader's --preset option.
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Set(preset::Set),
+            Remove(preset::Remove),
+            List(preset::List),
+        }
+
+        /// Create a preset, or replace the values of an existing one
+        ///
+        /// Values are given as a comma separated list of assignments, e.g.
+        /// "gold=100,chapter=3,cat=Leo". Numbers are assigned to vals and anything else to names.
+        /// Every key must already be in the project's name or val table. Returns the number of
+        /// presets.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Set {
+            /// Name of the preset
+            name: String,
+            /// Starting names and values, e.g. "gold=100,cat=Leo"
+            values: runner::Assumptions,
+        }
+
+        impl Executable for Set {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Set preset {}", self.name);
+                if let Some(key) = self.values.missing_keys(&state.active).first() {
+                    return Err(cmd::Error::PresetKeyNotExists(*key).into());
+                }
+                let from = state
+                    .active
+                    .presets
+                    .insert(self.name.clone(), self.values.clone());
+                state.history.push(
+                    PresetEdit {
+                        name: self.name.clone(),
+                        from,
+                        to: Some(self.values.clone()),
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+                Ok(state.active.presets.len())
+            }
+        }
+
+        /// Remove a preset. Returns the number of presets left
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Remove {
+            /// Name of the preset
+            name: String,
+        }
+
+        impl Executable for Remove {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove preset {}", self.name);
+                let from = state
+                    .active
+                    .presets
+                    .remove(&self.name)
+                    .ok_or_else(|| cmd::Error::PresetNotExists(self.name.clone()))?;
+                state.history.push(
+                    PresetEdit {
+                        name: self.name.clone(),
+                        from: Some(from),
+                        to: None,
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+                Ok(state.active.presets.len())
+            }
+        }
+
+        /// Print the presets of the active project to the editor scratchpad, with the values
+        /// each one sets
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct List {}
+
+        impl Executable for List {
+            /// Returns the number of presets
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("List presets");
+                for (name, preset) in state.active.presets.iter() {
+                    state
+                        .scratchpad
+                        .push_str(&format!("{}: {}\r\n", name, preset));
+                }
+                println!("{}", state.scratchpad);
+                Ok(state.active.presets.len())
+            }
+        }
+    }
+
+    pub mod direction {
+        use super::*;
+
+        /// Stage directions attached to nodes, passed to the game engine along with the dialogue
+        ///
+        /// Directions are written in the same format as they are declared, e.g. Enter(cat),
+        /// Exit(cat), Animate(cat,wave), or Background(forest). Character keys must exist in the
+        /// name table.
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Add(direction::Add),
+            Edit(direction::Edit),
+            Remove(direction::Remove),
+            List(direction::List),
+        }
+
+        /// Add a direction to the end of a node's directions
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Add {
+            /// Index of the node
+            node_index: usize,
+            /// The direction, e.g. Enter(cat)
+            direction: Direction,
+        }
+
+        impl Executable for Add {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!(
+                    "Add direction {} to node {}",
+                    self.direction, self.node_index
+                );
+
+                util::validate_direction(
+                    self.node_index,
+                    &self.direction,
+                    &state.active.name_table,
+                )?;
+                let mut directions = util::directions_of(&state.active, self.node_index)?.to_vec();
+                directions.push(self.direction);
+                let position = directions.len() - 1;
+
+                let event = state.active.set_directions(self.node_index, directions)?;
+                state.history.push(event.into(), &mut state.active);
+                Ok(position)
+            }
+        }
+
+        /// Replace the direction at a position in a node's directions
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Edit {
+            /// Index of the node
+            node_index: usize,
+            /// Position of the direction in the node's directions, starting from 0
+            position: usize,
+            /// The new direction, e.g. Exit(cat)
+            direction: Direction,
+        }
+
+        impl Executable for Edit {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!(
+                    "Edit direction {} of node {}",
+                    self.position, self.node_index
+                );
+
+                util::validate_direction(
+                    self.node_index,
+                    &self.direction,
+                    &state.active.name_table,
+                )?;
+                let mut directions = util::directions_of(&state.active, self.node_index)?.to_vec();
+                *directions.get_mut(self.position).ok_or(
+                    cmd::Error::InvalidDirectionPosition(self.node_index, self.position),
+                )? = self.direction;
+
+                let event = state.active.set_directions(self.node_index, directions)?;
+                state.history.push(event.into(), &mut state.active);
+                Ok(self.position)
+            }
+        }
+
+        /// Remove the direction at a position in a node's directions
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Remove {
+            /// Index of the node
+            node_index: usize,
+            /// Position of the direction in the node's directions, starting from 0
+            position: usize,
+        }
+
+        impl Executable for Remove {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!(
+                    "Remove direction {} of node {}",
+                    self.position, self.node_index
+                );
+
+                let mut directions = util::directions_of(&state.active, self.node_index)?.to_vec();
+                anyhow::ensure!(
+                    self.position < directions.len(),
+                    cmd::Error::InvalidDirectionPosition(self.node_index, self.position)
+                );
+                directions.remove(self.position);
+
+                let event = state.active.set_directions(self.node_index, directions)?;
+                state.history.push(event.into(), &mut state.active);
+                Ok(self.position)
+            }
+        }
+
+        /// Print the directions of a node to the editor scratchpad, and return how many there are
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct List {
+            /// Index of the node
+            node_index: usize,
+        }
+
+        impl Executable for List {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("List directions of node {}", self.node_index);
+
+                let directions = util::directions_of(&state.active, self.node_index)?;
+                for (position, direction) in directions.iter().enumerate() {
+                    state
+                        .scratchpad
+                        .push_str(&format!("{}: {}\r\n", position, direction));
+                }
+                println!("{}", state.scratchpad);
+                Ok(directions.len())
+            }
+        }
+    }
+
+    pub mod on_enter {
+        use super::*;
+
+        /// Effects attached to nodes, applied whenever the node is shown regardless of which
+        /// choice led there
+        ///
+        /// Effects are written in the same format as edge effects, e.g. Add(gold,5) or
+        /// Prompt(hero). Keys must exist in the name or val table. On-enter effects are applied in
+        /// order, before the node's choices are offered.
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Add(on_enter::Add),
+            Edit(on_enter::Edit),
+            Remove(on_enter::Remove),
+            List(on_enter::List),
+        }
+
+        /// Add an effect to the end of a node's on-enter effects
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Add {
+            /// Index of the node
+            node_index: usize,
+            /// The effect, e.g. Add(gold,5)
+            effect: EffectKind,
+        }
+
+        impl Executable for Add {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!(
+                    "Add on-enter effect {} to node {}",
+                    self.effect, self.node_index
+                );
+
+                util::validate_effect(
+                    &self.effect,
+                    &state.active.name_table,
+                    &state.active.val_table,
+                )?;
+                let mut effects = util::on_enter_of(&state.active, self.node_index)?.to_vec();
+                effects.push(self.effect);
+                let position = effects.len() - 1;
+
+                let event = state.active.set_on_enter(self.node_index, effects)?;
+                state.history.push(event.into(), &mut state.active);
+                Ok(position)
+            }
+        }
+
+        /// Replace the effect at a position in a node's on-enter effects
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Edit {
+            /// Index of the node
+            node_index: usize,
+            /// Position of the effect in the node's on-enter effects, starting from 0
+            position: usize,
+            /// The new effect, e.g. Set(gold,0)
+            effect: EffectKind,
+        }
+
+        impl Executable for Edit {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!(
+                    "Edit on-enter effect {} of node {}",
+                    self.position, self.node_index
+                );
+
+                util::validate_effect(
+                    &self.effect,
+                    &state.active.name_table,
+                    &state.active.val_table,
+                )?;
+                let mut effects = util::on_enter_of(&state.active, self.node_index)?.to_vec();
+                *effects
+                    .get_mut(self.position)
+                    .ok_or(cmd::Error::InvalidOnEnterPosition(
+                        self.node_index,
+                        self.position,
+                    ))? = self.effect;
+
+                let event = state.active.set_on_enter(self.node_index, effects)?;
+                state.history.push(event.into(), &mut state.active);
+                Ok(self.position)
+            }
+        }
+
+        /// Remove the effect at a position in a node's on-enter effects
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Remove {
+            /// Index of the node
+            node_index: usize,
+            /// Position of the effect in the node's on-enter effects, starting from 0
+            position: usize,
+        }
+
+        impl Executable for Remove {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!(
+                    "Remove on-enter effect {} of node {}",
+                    self.position, self.node_index
+                );
+
+                let mut effects = util::on_enter_of(&state.active, self.node_index)?.to_vec();
+                anyhow::ensure!(
+                    self.position < effects.len(),
+                    cmd::Error::InvalidOnEnterPosition(self.node_index, self.position)
+                );
+                effects.remove(self.position);
+
+                let event = state.active.set_on_enter(self.node_index, effects)?;
+                state.history.push(event.into(), &mut state.active);
+                Ok(self.position)
+            }
+        }
+
+        /// Print the on-enter effects of a node to the editor scratchpad, and return how many
+        /// there are
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct List {
+            /// Index of the node
+            node_index: usize,
+        }
+
+        impl Executable for List {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("List on-enter effects of node {}", self.node_index);
+
+                let effects = util::on_enter_of(&state.active, self.node_index)?;
+                for (position, effect) in effects.iter().enumerate() {
+                    state
+                        .scratchpad
+                        .push_str(&format!("{}: {}\r\n", position, effect));
+                }
+                println!("{}", state.scratchpad);
+                Ok(effects.len())
+            }
+        }
+    }
+
+    pub mod interrupt {
+        use super::*;
+
+        /// Project level interrupts, which move a playthrough to a node as soon as their
+        /// requirement is met
+        ///
+        /// Interrupts are checked in order at every node before the choices are presented, and the
+        /// first one with a met requirement is taken. Interrupts are not checked again at the node
+        /// they jump to.
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Add(interrupt::Add),
+            Remove(interrupt::Remove),
+            List(interrupt::List),
+        }
+
+        /// Add an interrupt, and return its position
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Add {
+            /// Requirement that triggers the interrupt, e.g. Greater(suspicion,10)
+            requirement: ReqKind,
+            /// Index of the node to jump to
+            target: usize,
+            /// Position to insert the interrupt at, interrupts are added to the end by default
+            #[structopt(long)]
+            at: Option<usize>,
+        }
+
+        impl Executable for Add {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Add interrupt {} to node {}", self.requirement, self.target);
+
+                let position = self.at.unwrap_or(state.active.interrupts.len());
+                anyhow::ensure!(
+                    position <= state.active.interrupts.len(),
+                    cmd::Error::InvalidInterruptPosition(position)
+                );
+                let interrupt = Interrupt::new(self.requirement.clone(), self.target);
+                util::validate_interrupt(position, &interrupt, &state.active)?;
+
+                state.active.interrupts.insert(position, interrupt.clone());
+                state.history.push(
+                    InterruptInsert {
+                        position,
+                        interrupt,
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+                Ok(position)
+            }
+        }
+
+        /// Remove the interrupt at a position
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Remove {
+            /// Position of the interrupt, starting from 0
+            position: usize,
+        }
+
+        impl Executable for Remove {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Remove interrupt {}", self.position);
+
+                anyhow::ensure!(
+                    self.position < state.active.interrupts.len(),
+                    cmd::Error::InvalidInterruptPosition(self.position)
+                );
+                let interrupt = state.active.interrupts.remove(self.position);
+                state.history.push(
+                    InterruptRemove {
+                        position: self.position,
+                        interrupt,
+                    }
+                    .into(),
+                    &mut state.active,
+                );
+                Ok(self.position)
+            }
+        }
+
+        /// Print all interrupts in order to the editor scratchpad
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct List {}
+
+        impl Executable for List {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("List interrupts");
+
+                for (position, interrupt) in state.active.interrupts.iter().enumerate() {
+                    state.scratchpad.push_str(&format!(
+                        "{}: {} -> node {}\r\n",
+                        position, interrupt.requirement, interrupt.target
+                    ));
+                }
+                println!("{}", state.scratchpad);
+                Ok(state.active.interrupts.len())
+            }
+        }
+    }
+
+    /// Utility methods used internally for various useful tasks. These cannot be called directly
+    /// from the command line, but are useful for working with dialogue_trees in other programs
+    pub mod report {
+        use super::*;
+
+        /// Print reports that summarize the active project
+        #[enum_dispatch(Executable)]
+        #[derive(StructOpt)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub enum Parse {
+            Vars(report::Vars),
+            StaleLines(report::StaleLines),
+        }
+
+        /// Print the symbol table of every name and val key to the editor scratchpad, and return
+        /// the number of keys
+        ///
+        /// Lists the initial value of each key, every effect that modifies it and every
+        /// requirement that reads it along with where they are, and the range of values it can
+        /// reach. See analysis::symbol_table for how the range is found.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct Vars {
+            /// Print the symbol table as JSON
+            #[structopt(short, long)]
+            json: bool,
+        }
+
+        impl Executable for Vars {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Report name and val usage");
+                let table = analysis::symbol_table(&state.active)?;
+                if self.json {
+                    state
+                        .scratchpad
+                        .push_str(&serde_json::to_string_pretty(&table)?);
+                    state.scratchpad.push_str("\r\n");
+                } else {
+                    state.scratchpad.push_str(&table.to_string());
+                }
+                println!("{}", state.scratchpad);
+                Ok(table.names.len() + table.vals.len())
+            }
+        }
+
+        /// Compare the lines of the active project against a manifest written by export csv, and
+        /// return the number of lines that need to be recorded again or for the first time
+        ///
+        /// Lines are matched by line id, so they are found even after their node or edge moved to
+        /// another index. Each stale line, whose text changed since the export, is listed with
+        /// its current and exported revision, followed by removed lines and new lines.
+        #[derive(new, StructOpt, Debug)]
+        #[structopt(setting = AppSettings::NoBinaryName)]
+        pub struct StaleLines {
+            /// Path of the manifest written by export csv --manifest
+            #[structopt(long)]
+            since: String,
+        }
+
+        impl Executable for StaleLines {
+            fn execute(&self, state: &mut EditorState) -> Result<usize> {
+                info!("Report stale lines since {}", self.since);
+                let manifest: util::LineManifest =
+                    serde_json::from_str(&std::fs::read_to_string(&self.since)?)?;
+                let changes = util::stale_lines(&state.active, &manifest);
+                let count =
+                    |f: fn(&util::LineChange) -> bool| changes.iter().filter(|c| f(c)).count();
+                let stale = count(|c| matches!(c, util::LineChange::Stale { .. }));
+                let new = count(|c| matches!(c, util::LineChange::New { .. }));
+                let removed = count(|c| matches!(c, util::LineChange::Removed(_)));
+                for change in changes.iter() {
+                    state.scratchpad.push_str(&format!("{}\r\n", change));
+                }
+                state.scratchpad.push_str(&format!(
+                    "{} stale, {} new, {} removed since the export of {}\r\n",
+                    stale, new, removed, manifest.name
+                ));
+                println!("{}", state.scratchpad);
+                Ok(stale + new)
+            }
+        }
+    }
+
+    pub mod util {
+        use super::*;
+        use std::collections::hash_map::Entry;
+        use std::collections::HashSet;
+        use tree::End;
+
+        /// Generate UID.
+        ///
+        /// UID is a 128 bit unique identifier for the project. This is stored in the dialogue
+        /// tree, and is useful for associating other metadata or resources with the correct tree
+        /// in the case that multiple files exist with the same name (likely if multiple users are
+        /// sharing files)
+        ///
+        /// Uids are drawn from the OS random number generator, unless seed_uids has been called on
+        /// the current thread
+        pub fn gen_uid() -> Uid {
+            UID_RNG.with(|rng| match rng.borrow_mut().as_mut() {
+                Some(rng) => Uid(rand::Rng::gen(rng)),
+                None => Uid(rand::random()),
+            })
+        }
+
+        /// Make uids generated on the current thread deterministic, for reproducible tests. Pass
+        /// None to go back to random uids
+        pub fn seed_uids(seed: Option<u64>) {
+            use rand::SeedableRng;
+            UID_RNG.with(|rng| *rng.borrow_mut() = seed.map(rand::rngs::StdRng::seed_from_u64));
+        }
+
+        thread_local! {
+            static UID_RNG: std::cell::RefCell<Option<rand::rngs::StdRng>> =
+                const { std::cell::RefCell::new(None) };
+        }
+
+        /// Read the uid of a dialogue tree file without deserializing the whole tree
+        ///
+        /// The uid is the first field of a serialized dialogue tree, so only the start of the file
+        /// is decoded, unless it was saved before trees were versioned. Returns None if the file
+        /// does not exist.
+        pub fn read_uid(store: &dyn store::ProjectStore, name: &str) -> Result<Option<Uid>> {
+            if !store.exists(name)? {
+                return Ok(None);
+            }
+            let (_, bytes) = store::split_segments(store.read(name)?)?;
+            let bytes = store::decompress(bytes)?;
+            Ok(Some(migrate::read_uid(&bytes)?))
+        }
+
+        /// Check that a dialogue tree file in a store belongs to the project with the given uid
+        ///
+        /// # Errors
+        /// Error if the file exists and its uid does not match
+        pub fn check_uid(store: &dyn store::ProjectStore, name: &str, uid: Uid) -> Result<()> {
+            match read_uid(store, name)? {
+                Some(file_uid) if file_uid != uid => {
+                    Err(cmd::Error::UidMismatch(file_uid, uid).into())
+                }
+                _ => Ok(()),
+            }
+        }
+
+        /// Split node or edge text into tokens on TOKEN_SEP. Separators escaped as TOKEN_ESCAPE
+        /// don't split the text, and are left in the tokens as written. Use unescape to get the
+        /// text of a token as it should be shown
+        pub fn split_tokens(text: &str) -> Tokens<'_> {
+            Tokens { rest: Some(text) }
+        }
+
+        /// Iterator over the tokens of node or edge text, see split_tokens
+        pub struct Tokens<'a> {
+            rest: Option<&'a str>,
+        }
+
+        impl<'a> Iterator for Tokens<'a> {
+            type Item = &'a str;
+
+            fn next(&mut self) -> Option<Self::Item> {
+                let rest = self.rest?;
+                let mut from = 0;
+                while let Some(found) = rest[from..].find(TOKEN_SEP) {
+                    let at = from + found;
+                    if rest[..at].ends_with('\\') {
+                        // escaped separator, keep searching after it
+                        from = at + TOKEN_SEP.len();
+                    } else {
+                        self.rest = Some(&rest[at + TOKEN_SEP.len()..]);
+                        return Some(&rest[..at]);
+                    }
+                }
+                self.rest = None;
+                Some(rest)
+            }
+        }
+
+        /// Replace escaped separators in a token with a literal TOKEN_SEP
+        pub fn unescape(token: &str) -> std::borrow::Cow<'_, str> {
+            if token.contains(TOKEN_ESCAPE) {
+                token.replace(TOKEN_ESCAPE, TOKEN_SEP).into()
+            } else {
+                token.into()
+            }
+        }
+
+        /// Escape every TOKEN_SEP in text, so that it is shown as written rather than parsed as
+        /// names. Use this when adding prose that should not contain name substitutions
+        pub fn escape(text: &str) -> String {
+            text.replace(TOKEN_SEP, TOKEN_ESCAPE)
+        }
+
+        /// Helper method to parse a dialogue node's section of the text and fill in any name
+        /// variables.
+        ///
+        /// The input text rope section should have the following format
+        ///     ::name::text ::name:: more text
+        ///
+        /// The first name is the speaker. This name must be a valid key to the name_table
+        /// Inside the text, additional names may be inserted inside a pair of :: symbols. The
+        /// entire area inside the :: symbols must be a valid key to the name_table.
+        ///
+        /// Both the name and text buf are cleared at the beginning of this method.
+        pub fn parse_node(
+            text: &str,
+            name_table: &NameTable,
+            name_buf: &mut String,
+            text_buf: &mut String,
+        ) -> Result<()> {
+            // Implementation notes:
+            //  0. The first iterator element should always be '', if not something is wrong
+            //  1. The second iterator element is always the speaker name and should be the only
+            //     thing written to the name buffer
+            //  2. Since only a simple flow of ::speaker_name::text::name:::text ... etc is
+            //     allowed, only every 'other' token (indices 1,3,5...) need to be looked up in the
+            //     hashtable
+            //  3. The above is only true because split() will return an empty strings on sides of
+            //     the separator with no text. For instance name::::name:: would split to ['name,
+            //     '', name, '']
+            name_buf.clear();
+            text_buf.clear();
+            let mut text_iter = split_tokens(text).enumerate();
+            let _ = text_iter.next(); // skip first token, it is '' for any correct string
+            let speaker_key = text_iter.next().ok_or(cmd::Error::Generic)?.1;
+            let speaker_name =
+                speaker_name(speaker_key, name_table).ok_or(cmd::Error::NodeParse)?;
+            name_buf.push_str(speaker_name);
+            text_iter.try_for_each(|(i, n)| -> std::result::Result<(), cmd::Error> {
+                if (i & 0x1) == 1 {
+                    // token is a name (index 1, 3, 5 ...)
+                    let value = name_table.get(n).ok_or(cmd::Error::NodeParse)?;
+                    text_buf.push_str(value);
+                    Ok(())
+                } else {
+                    // token cannot be a name
+                    text_buf.push_str(&unescape(n));
+                    Ok(())
+                }
+            })?;
+
+            Ok(())
+        }
+
+        /// Truncate text to a maximum number of characters for previewing. Truncated text ends in
+        /// an ellipsis if the width leaves room for some text before it, and line breaks are
+        /// replaced with spaces to keep the preview on one line
+        pub fn truncate(text: &str, width: usize) -> String {
+            let text = text.replace(['\r', '\n'], " ");
+            if text.chars().count() <= width {
+                text
+            } else if width <= 3 {
+                text.chars().take(width).collect()
+            } else {
+                let mut truncated: String = text.chars().take(width.saturating_sub(3)).collect();
+                truncated.push_str("...");
+                truncated
+            }
+        }
+
+        /// Cut text down to about width characters around a match at the given byte range, for
+        /// listing search results with context. Cut ends are marked with an ellipsis, and line
+        /// breaks are replaced with spaces to keep the excerpt on one line
+        pub fn excerpt(text: &str, range: std::ops::Range<usize>, width: usize) -> String {
+            let before = text[..range.start].chars().count();
+            let matched = text[range].chars().count();
+            let total = text.chars().count();
+            let context = width.saturating_sub(matched) / 2;
+            let start = before.saturating_sub(context);
+            let end = (before + matched + context).max(start + width).min(total);
+            let mut excerpt = String::with_capacity(width + 6);
+            if start > 0 {
+                excerpt.push_str("...");
+            }
+            excerpt.extend(text.chars().skip(start).take(end - start));
+            if end < total {
+                excerpt.push_str("...");
+            }
+            excerpt.replace(['\r', '\n'], " ")
+        }
+
+        /// A run of words in a diff of two texts, see word_diff
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum WordDiff {
+            Same(String),
+            Removed(String),
+            Added(String),
+        }
+
+        /// Most token pairs compared by word_diff, texts with more are shown as replaced whole
+        const MAX_DIFF_CELLS: usize = 1 << 20;
+
+        /// Split text into the tokens compared by word_diff: runs of whitespace, runs of
+        /// letters, digits, underscores, and apostrophes, TOKEN_SEP, and any other single
+        /// character
+        fn diff_tokens(text: &str) -> Vec<&str> {
+            let kind = |c: char| {
+                if c.is_whitespace() {
+                    0
+                } else if c.is_alphanumeric() || c == '_' || c == '\'' {
+                    1
+                } else {
+                    2
+                }
+            };
+            let mut tokens = Vec::new();
+            let mut rest = text;
+            while let Some(first) = rest.chars().next() {
+                let len = if rest.starts_with(TOKEN_SEP) {
+                    TOKEN_SEP.len()
+                } else if kind(first) == 2 {
+                    first.len_utf8()
+                } else {
+                    rest.find(|c: char| kind(c) != kind(first))
+                        .unwrap_or(rest.len())
+                };
+                tokens.push(&rest[..len]);
+                rest = &rest[len..];
+            }
+            tokens
+        }
+
+        /// Compare two texts word by word, returning the runs of words that are the same in both,
+        /// only in the old text, and only in the new text, in the order they appear. Removed runs
+        /// come before added runs where text was replaced. Joining the Same and Removed runs gives
+        /// the old text, and joining the Same and Added runs gives the new text
+        pub fn word_diff(old: &str, new: &str) -> Vec<WordDiff> {
+            let old = diff_tokens(old);
+            let new = diff_tokens(new);
+            let (n, m) = (old.len(), new.len());
+
+            trace!("longest common subsequence of the tokens, from the end");
+            let mut common = if n.saturating_mul(m) <= MAX_DIFF_CELLS {
+                vec![0usize; (n + 1) * (m + 1)]
+            } else {
+                Vec::new()
+            };
+            let at = |i: usize, j: usize| i * (m + 1) + j;
+            if !common.is_empty() {
+                for i in (0..n).rev() {
+                    for j in (0..m).rev() {
+                        common[at(i, j)] = if old[i] == new[j] {
+                            common[at(i + 1, j + 1)] + 1
+                        } else {
+                            common[at(i + 1, j)].max(common[at(i, j + 1)])
+                        };
+                    }
+                }
+            }
+
+            let mut diff: Vec<WordDiff> = Vec::new();
+            let mut push = |span: WordDiff| {
+                match (diff.last_mut(), &span) {
+                    (Some(WordDiff::Same(last)), WordDiff::Same(s))
+                    | (Some(WordDiff::Removed(last)), WordDiff::Removed(s))
+                    | (Some(WordDiff::Added(last)), WordDiff::Added(s)) => {
+                        last.push_str(s);
+                        return;
+                    }
+                    _ => {}
+                }
+                diff.push(span);
+            };
+            let (mut i, mut j) = (0, 0);
+            let (mut removed, mut added) = (String::new(), String::new());
+            while i < n || j < m {
+                if i < n && j < m && old[i] == new[j] && !common.is_empty() {
+                    if !removed.is_empty() {
+                        push(WordDiff::Removed(std::mem::take(&mut removed)));
+                    }
+                    if !added.is_empty() {
+                        push(WordDiff::Added(std::mem::take(&mut added)));
+                    }
+                    push(WordDiff::Same(old[i].to_string()));
+                    i += 1;
+                    j += 1;
+                } else if j == m || (i < n && common.get(at(i + 1, j)) >= common.get(at(i, j + 1)))
+                {
+                    removed.push_str(old[i]);
+                    i += 1;
+                } else {
+                    added.push_str(new[j]);
+                    j += 1;
+                }
+            }
+            if !removed.is_empty() {
+                push(WordDiff::Removed(removed));
+            }
+            if !added.is_empty() {
+                push(WordDiff::Added(added));
+            }
+            diff
+        }
+
+        /// Write a word diff on one line. Removed and added runs are marked as [-removed-] and
+        /// {+added+}, or shown in red and green with ANSI escape codes if color is set
+        pub fn render_diff(diff: &[WordDiff], color: bool) -> String {
+            let mut rendered = String::new();
+            for span in diff {
+                let (text, open, close) = match span {
+                    WordDiff::Same(text) => (text, "", ""),
+                    WordDiff::Removed(text) if color => (text, "\u{1b}[9;31m", "\u{1b}[0m"),
+                    WordDiff::Removed(text) => (text, "[-", "-]"),
+                    WordDiff::Added(text) if color => (text, "\u{1b}[32m", "\u{1b}[0m"),
+                    WordDiff::Added(text) => (text, "{+", "+}"),
+                };
+                rendered.push_str(open);
+                rendered.push_str(&text.replace(['\r', '\n'], " "));
+                rendered.push_str(close);
+            }
+            rendered
+        }
+
+        /// Check if output printed to the terminal should be colored with ANSI escape codes.
+        /// Color is left out when stdout is not a terminal, or the NO_COLOR environment variable
+        /// is set
+        pub fn use_color() -> bool {
+            use std::io::IsTerminal;
+            std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+        }
+
+        /// Format a number of bytes for display, using the largest binary unit that keeps the
+        /// number above one (e.g. "512 B", "1.5 KiB")
+        pub fn format_bytes(bytes: usize) -> String {
+            const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+            if bytes < 1024 {
+                return format!("{} B", bytes);
+            }
+            let mut size = bytes as f64 / 1024.0;
+            let mut unit = 0;
+            while size >= 1024.0 && unit < UNITS.len() - 1 {
+                size /= 1024.0;
+                unit += 1;
+            }
+            format!("{:.1} {}", size, UNITS[unit])
+        }
+
+        /// A column of a table rendered with render_table
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub struct Column<'a> {
+            pub header: &'a str,
+            /// ANSI SGR code that cells in this column are wrapped in when colorized (e.g. "33"
+            /// for yellow)
+            pub color: Option<&'a str>,
+        }
+
+        impl<'a> Column<'a> {
+            pub fn new(header: &'a str, color: Option<&'a str>) -> Self {
+                Column { header, color }
+            }
+        }
+
+        /// Render rows of cells as a table with a header and aligned columns
+        ///
+        /// If colorize is set, cells are wrapped in the color of their column, and the header is
+        /// printed in bold. Rows should have one cell per column
+        pub fn render_table(columns: &[Column], rows: &[Vec<String>], colorize: bool) -> String {
+            let mut widths: Vec<usize> = columns.iter().map(|c| c.header.chars().count()).collect();
+            for row in rows.iter() {
+                for (width, cell) in widths.iter_mut().zip(row.iter()) {
+                    *width = std::cmp::max(*width, cell.chars().count());
+                }
+            }
+
+            let mut out = String::new();
+            let mut push_row = |cells: &mut dyn Iterator<Item = &str>, header: bool| {
+                let mut line = String::new();
+                for ((cell, width), column) in cells.zip(widths.iter()).zip(columns.iter()) {
+                    let padded = format!("{:<width$}", cell, width = width);
+                    let code = if !colorize {
+                        None
+                    } else if header {
+                        Some("1")
+                    } else {
+                        column.color
+                    };
+                    match code {
+                        Some(code) if !cell.is_empty() => {
+                            line.push_str(&format!("\u{1b}[{}m{}\u{1b}[0m", code, padded))
+                        }
+                        _ => line.push_str(&padded),
+                    }
+                    line.push_str("  ");
+                }
+                out.push_str(line.trim_end());
+                out.push_str("\r\n");
+            };
+            push_row(&mut columns.iter().map(|c| c.header), true);
+            for row in rows.iter() {
+                push_row(&mut row.iter().map(String::as_str), false);
+            }
+            out
+        }
+
+        /// Look up the display name of a node's speaker
+        ///
+        /// The reserved NARRATOR_KEY is always valid, and displays as its entry in the name table
+        /// if one exists, or as an empty name otherwise. Returns None if any other key is not in
+        /// the name table
+        pub fn speaker_name<'a>(key: &str, name_table: &'a NameTable) -> Option<&'a str> {
+            match name_table.get(key) {
+                Some(name) => Some(name.as_str()),
+                None if key == NARRATOR_KEY => Some(""),
+                None => None,
+            }
+        }
+
+        /// Same routine as parse node, except the results are not actually written to a
+        /// thread. This is used for validating that the section of text is valid
+        pub fn validate_node(text: &str, name_table: &NameTable) -> Result<()> {
+            let mut text_iter = split_tokens(text).enumerate();
+            text_iter.next(); // discard first empty string
+            let speaker_key = text_iter.next().ok_or(cmd::Error::EdgeParse)?.1;
+            speaker_name(speaker_key, name_table).ok_or(cmd::Error::EdgeParse)?;
+            text_iter.try_for_each(|(i, n)| -> std::result::Result<(), cmd::Error> {
+                if (i & 0x1) == 1 {
+                    // token is a name (index 1, 3, 5 ...)
+                    name_table.get(n).ok_or(cmd::Error::EdgeParse)?;
+                    Ok(())
+                } else {
+                    // token cannot be a name
+                    Ok(())
+                }
+            })?;
+            Ok(())
+        }
+
+        /// Helper method to parse a player action (edge's) section of the text and fill in any
+        /// name variables.
+        ///
+        /// The input text section should have the following format
+        ///     'action text ::name:: more action text'
+        ///
+        /// Both the name and text buf are cleared at the beginning of this method
+        pub fn parse_edge(text: &str, name_table: &NameTable, text_buf: &mut String) -> Result<()> {
+            // Implementation notes
+            //  1. Due to the format, only even iterator elements are names that need to be looked
+            //     up in the name table. This is true because split() will return an empty strings
+            //     on sides of the separator with no text. For instance name::::name:: would split
+            //     to ['name', '', 'name', '']
+            text_buf.clear();
+            let mut text_iter = split_tokens(text).enumerate();
+            text_iter.try_for_each(|(i, n)| -> std::result::Result<(), cmd::Error> {
+                if (i & 0x1) == 0 {
+                    // token cannot be a name
+                    text_buf.push_str(&unescape(n));
+                    Ok(())
+                } else {
+                    let value = name_table.get(n).ok_or(cmd::Error::EdgeParse)?;
+                    text_buf.push_str(value);
+                    Ok(())
+                }
+            })?;
+            Ok(())
+        }
+
+        /// Reusable buffers for rendering the text of nodes and edges with all names substituted
+        ///
+        /// Wraps parse_node and parse_edge so that callers rendering many nodes or edges don't
+        /// need to manage their own buffers. The returned text borrows the renderer, and is only
+        /// valid until the next call
+        #[derive(Debug, Default, Clone)]
+        pub struct TextRenderer {
+            name_buf: String,
+            text_buf: String,
+        }
+
+        impl TextRenderer {
+            /// Render the speaker name and text of a node, substituting names from name_table
+            pub fn node(
+                &mut self,
+                data: &DialogueTreeData,
+                name_table: &NameTable,
+                index: NodeIndex,
+            ) -> Result<(&str, &str)> {
+                let section = data.tree.get_node(index)?.section;
+                let text = data
+                    .text
+                    .get(section[0]..section[1])
+                    .ok_or(cmd::Error::InvalidSection)?;
+                parse_node(text, name_table, &mut self.name_buf, &mut self.text_buf)?;
+                Ok((&self.name_buf, &self.text_buf))
+            }
+
+            /// Render the text of an edge, substituting names from name_table
+            pub fn edge(
+                &mut self,
+                data: &DialogueTreeData,
+                name_table: &NameTable,
+                index: EdgeIndex,
+            ) -> Result<&str> {
+                let section = data.tree.get_edge(index)?.section;
+                let text = data
+                    .text
+                    .get(section[0]..section[1])
+                    .ok_or(cmd::Error::InvalidSection)?;
+                parse_edge(text, name_table, &mut self.text_buf)?;
+                Ok(&self.text_buf)
+            }
+        }
+
+        /// Same routine as parse_edge, but does not write to an output string buffer. Useful for
+        /// validating a section of text in an edge
+        pub fn validate_edge(text: &str, name_table: &NameTable) -> Result<()> {
+            let mut text_iter = split_tokens(text).enumerate();
+            text_iter.try_for_each(|(i, n)| -> std::result::Result<(), cmd::Error> {
+                if (i & 0x1) == 0 {
+                    Ok(())
+                } else {
+                    name_table.get(n).ok_or(cmd::Error::Generic)?;
+                    Ok(())
+                }
+            })?;
+            Ok(())
+        }
+
+        /// Helper method to prompt the user for input
+        ///
+        /// User input is stored into the provided buffer
+        pub fn prompt_input(buf: &mut String) {
+            // Print input prompt
+            print!(">> ");
+
+            // get next command from the user
+            io::stdout().flush().unwrap();
+            io::stdin().read_line(buf).expect("Failed to read line");
+        }
+
+        /// Rebuilds the text of a dialogue tree, removing unused sections and reordering text
+        /// sections for improved caching of nearby nodes. The rebuilt string is then stored in
+        /// the new_buf string buffer.
+        ///
+        /// When editing nodes/edges, currently new text is pushed to the end of the text buffer,
+        /// and the indices of the node/edge are updated to point to the new text. This leaves the
+        /// old section of text in the buffer, and over time many edits will bloat the string. The
+        /// solution to this, without leaving gaps in the string, is to rebuild the text buffer
+        /// based on the order that the text section is referenced in the tree. The order is
+        /// determined by DFS order that the nodes occur, with all edges colocated immediately
+        /// after their source node. This should provide good cache hitrate in most cases, as users
+        /// are likely to follow DFS-like path through the tree as they make choices and advance
+        /// through the dialogue.
+        ///
+        /// Note that the new_buf and new_tree are cleared at the beginning of this method.
+        /// Make sure it is safe to do so before calling.
+        pub fn rebuild_tree(
+            text: &str,
+            tree: &Tree,
+            new_text: &mut String,
+            new_tree: &mut Tree,
+        ) -> Result<()> {
+            new_text.clear();
+            new_tree.clear();
+            // Clone the old tree into the new one such that the nodes and edge indices and layout
+            // are identical. This makes it much easier to rebuild as only the node weights need to
+            // be updated to point to the proper sections of the next text buffer
+            *new_tree = tree.clone();
+            if tree.nodes().is_empty() {
+                return Ok(());
+            }
+
+            // Collect every problem found rather than stopping at the first, so that the full
+            // extent of any corruption can be reported
+            let mut problems = Vec::new();
+            // Nodes that are not reachable from the root are visited afterwards by starting a new
+            // walk from each unvisited node, so that no node is left pointing into the old text
+            // buffer
+            let mut rebuild_node = |node_index: NodeIndex| -> Result<()> {
+                // Rebuild node
+                let dialogue = tree.get_node(node_index)?;
+                let slice = match text.get(dialogue.section[0]..dialogue.section[1]) {
+                    Some(slice) => slice,
+                    None => {
+                        problems.push(Corruption::NodeSection(node_index));
+                        return Ok(());
+                    }
+                };
+                let start = new_text.len();
+                new_text.push_str(slice);
+                let end = new_text.len();
+                let new_dialogue = new_tree.get_node_mut(node_index)?;
+                // verify new and old hash match
+                let new_hash = hash(&new_text.as_bytes()[start..end]);
+                if dialogue.section.hash != new_hash {
+                    problems.push(Corruption::NodeHash {
+                        index: node_index,
+                        expected: dialogue.section.hash,
+                        actual: new_hash,
+                    });
+                }
+                *new_dialogue = Dialogue {
+                    section: Section::new([start, end], new_hash),
+                    ..*dialogue
+                };
+
+                // Rebuild all edges sourced from this node
+                let edge_iter = tree.outgoing_from_index(node_index)?;
+                for edge_index in edge_iter {
+                    let edge = tree.get_edge(edge_index)?;
+
+                    // Verify that edge and new_edge match, they should be identical since we
+                    // started by cloning the tree to new_tree
+                    if tree.target_of(edge_index)? != new_tree.target_of(edge_index)? {
+                        problems.push(Corruption::EdgeTarget(edge_index));
+                    }
+
+                    let slice = match text.get(edge.section[0]..edge.section[1]) {
+                        Some(slice) => slice,
+                        None => {
+                            problems.push(Corruption::EdgeSection(edge_index));
+                            continue;
+                        }
+                    };
+                    let start = new_text.len();
+                    new_text.push_str(slice);
+                    let end = new_text.len();
+                    // verify new and old hash match
+                    let new_hash = hash(&new_text.as_bytes()[start..end]);
+                    if edge.section.hash != new_hash {
+                        problems.push(Corruption::EdgeHash {
+                            index: edge_index,
+                            expected: edge.section.hash,
+                            actual: new_hash,
+                        });
+                    }
+                    let new_choice = new_tree.get_edge_mut(edge_index)?;
+                    new_choice.section = Section::new([start, end], new_hash);
+                }
+                Ok(())
+            };
+            let failed =
+                tree::walk_from(
+                    tree,
+                    0..tree.nodes().len(),
+                    |node_index, _, _| match rebuild_node(node_index) {
+                        Ok(()) => ControlFlow::Continue(tree::Visit::Children),
+                        Err(e) => ControlFlow::Break(e),
+                    },
+                )?;
+            if let Some(e) = failed {
+                return Err(e);
+            }
+
+            if problems.is_empty() {
+                Ok(())
+            } else {
+                Err(cmd::Error::RebuildFailed(problems).into())
+            }
+        }
+
+        /// A problem with a node or edge found while rebuilding a tree
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum Corruption {
+            /// The text section of the node is outside the text buffer
+            NodeSection(usize),
+            /// The text section of the edge is outside the text buffer
+            EdgeSection(usize),
+            /// The hash of the node's text does not match the stored hash
+            NodeHash {
+                index: usize,
+                expected: u64,
+                actual: u64,
+            },
+            /// The hash of the edge's text does not match the stored hash
+            EdgeHash {
+                index: usize,
+                expected: u64,
+                actual: u64,
+            },
+            /// The target of the edge changed during the rebuild
+            EdgeTarget(usize),
+            /// The source or target of the edge is not a node in the tree
+            EdgeEndpoint(usize),
+            /// The outgoing edge list of the node is broken or out of bounds
+            LinkList(usize),
+            /// The requirement of the edge references a missing key
+            Requirement(usize),
+            /// The effect of the edge references a missing key
+            Effect(usize),
+            /// The bookmark points to a node that is not in the tree
+            Bookmark(KeyString),
+            /// The directions belong to a node that is not in the tree, or reference a missing
+            /// character
+            Directions(usize),
+            /// The on-enter effects belong to a node that is not in the tree, or reference a
+            /// missing key
+            OnEnter(usize),
+            /// The interrupt targets a node that is not in the tree, or has an invalid requirement
+            Interrupt(usize),
+        }
+
+        impl std::fmt::Display for Corruption {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    Corruption::NodeSection(idx) => {
+                        write!(f, "node {}: text section is out of bounds", idx)
+                    }
+                    Corruption::EdgeSection(idx) => {
+                        write!(f, "edge {}: text section is out of bounds", idx)
+                    }
+                    Corruption::NodeHash {
+                        index,
+                        expected,
+                        actual,
+                    } => write!(
+                        f,
+                        "node {}: hash mismatch, expected {:#x} found {:#x}",
+                        index, expected, actual
+                    ),
+                    Corruption::EdgeHash {
+                        index,
+                        expected,
+                        actual,
+                    } => write!(
+                        f,
+                        "edge {}: hash mismatch, expected {:#x} found {:#x}",
+                        index, expected, actual
+                    ),
+                    Corruption::EdgeTarget(idx) => write!(f, "edge {}: target changed", idx),
+                    Corruption::EdgeEndpoint(idx) => {
+                        write!(f, "edge {}: source or target node does not exist", idx)
+                    }
+                    Corruption::LinkList(idx) => {
+                        write!(f, "node {}: outgoing edge list is broken", idx)
+                    }
+                    Corruption::Requirement(idx) => {
+                        write!(f, "edge {}: requirement references a missing key", idx)
+                    }
+                    Corruption::Effect(idx) => {
+                        write!(f, "edge {}: effect references a missing key", idx)
+                    }
+                    Corruption::Bookmark(key) => {
+                        write!(f, "bookmark {}: node does not exist", key)
+                    }
+                    Corruption::Directions(idx) => write!(
+                        f,
+                        "node {}: directions reference a missing node or character",
+                        idx
+                    ),
+                    Corruption::OnEnter(idx) => write!(
+                        f,
+                        "node {}: on-enter effects reference a missing node or key",
+                        idx
+                    ),
+                    Corruption::Interrupt(idx) => write!(
+                        f,
+                        "interrupt {}: target node is missing or requirement is invalid",
+                        idx
+                    ),
+                }
+            }
+        }
+
+        /// Validate that the contents of a requirement enum are valid
+        ///
+        /// This is mainly used when taking a requirement from CLI and checking that the key
+        /// is present in the val_table for u32 types, and the name_table for String types
+        pub fn validate_requirement(
+            req: &ReqKind,
+            name_table: &NameTable,
+            val_table: &ValTable,
+        ) -> Result<()> {
+            // this match will stop compiling any time a new reqKind is added
+            match req {
+                ReqKind::No => {}
+                ReqKind::Greater(key, _val) => {
+                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
+                }
+                ReqKind::Less(key, _val) => {
+                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
+                }
+                ReqKind::Equal(key, _val) => {
+                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
+                }
+                ReqKind::Cmp(key, _val) => {
+                    name_table.get(key).ok_or(cmd::Error::NameNotExists)?;
+                }
+                ReqKind::NotCmp(key, _val) => {
+                    name_table.get(key).ok_or(cmd::Error::NameNotExists)?;
+                }
+                ReqKind::OneOf(key, _val) => {
+                    name_table.get(key).ok_or(cmd::Error::NameNotExists)?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Find all keys that start with the given partial key, with or without their val scope
+        /// prefix. An exact match is always returned alone, so that keys which are a prefix of
+        /// other keys can still be selected
+        pub fn complete_key<'a, I>(partial: &str, keys: I) -> Vec<KeyString>
+        where
+            I: Iterator<Item = &'a KeyString>,
+        {
+            let mut matches: Vec<KeyString> = keys
+                .filter(|k| k.starts_with(partial) || ValScope::unscoped(k).starts_with(partial))
+                .copied()
+                .collect();
+            if let Some(exact) = matches.iter().find(|k| k.as_str() == partial) {
+                return vec![*exact];
+            }
+            matches.sort_unstable();
+            matches
+        }
+
+        /// Build a requirement step by step from answers to a series of prompts
+        ///
+        /// ask is called with the text of each prompt and returns the answer. Kinds and keys may
+        /// be abbreviated to any unique prefix. Invalid answers are asked again. An empty answer
+        /// to the first prompt selects no requirement, and an empty answer to any later prompt
+        /// cancels.
+        pub fn prompt_requirement<F>(
+            ask: &mut F,
+            name_table: &NameTable,
+            val_table: &ValTable,
+        ) -> Result<ReqKind>
+        where
+            F: FnMut(&str) -> String,
+        {
+            let built = prompt_kind(
+                ask,
+                "requirement",
+                &ReqKind::KINDS,
+                ReqKind::uses_name,
+                name_table,
+                val_table,
+            )?;
+            built.parse()
+        }
+
+        /// Build an effect step by step from answers to a series of prompts, see
+        /// prompt_requirement
+        pub fn prompt_effect<F>(
+            ask: &mut F,
+            name_table: &NameTable,
+            val_table: &ValTable,
+        ) -> Result<EffectKind>
+        where
+            F: FnMut(&str) -> String,
+        {
+            let built = prompt_kind(
+                ask,
+                "effect",
+                &EffectKind::KINDS,
+                EffectKind::uses_name,
+                name_table,
+                val_table,
+            )?;
+            built.parse()
+        }
+
+        /// Prompt for the kind, key, and value of a requirement or effect and return it in the
+        /// format accepted by from_str
+        fn prompt_kind<F>(
+            ask: &mut F,
+            label: &str,
+            kinds: &[&str],
+            uses_name: fn(&str) -> bool,
+            name_table: &NameTable,
+            val_table: &ValTable,
+        ) -> Result<String>
+        where
+            F: FnMut(&str) -> String,
+        {
+            let kind = loop {
+                let answer = ask(&format!("{} type [{}]:", label, kinds.join(", ")));
+                if answer.is_empty() {
+                    return Ok(String::from("No"));
+                }
+                let matches: Vec<&str> = kinds
+                    .iter()
+                    .copied()
+                    .filter(|k| k.to_lowercase().starts_with(&answer.to_lowercase()))
+                    .collect();
+                match matches.as_slice() {
+                    [kind] => break *kind,
+                    _ => println!("unknown {} type {}", label, answer),
+                }
+            };
+            if kind == "No" {
+                return Ok(String::from("No"));
+            }
+
+            let keys: Vec<&KeyString> = if uses_name(kind) {
+                name_table.keys().collect()
+            } else {
+                val_table.keys().collect()
+            };
+            let mut key_list: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+            key_list.sort_unstable();
+            let key = loop {
+                let answer = ask(&format!("key [{}]:", key_list.join(", ")));
+                if answer.is_empty() {
+                    return Err(cmd::Error::PromptCancelled.into());
+                }
+                match complete_key(&answer, keys.iter().copied()).as_slice() {
+                    [key] => break *key,
+                    [] => println!("no key matches {}", answer),
+                    matches => println!("{} matches {:?}", answer, matches),
+                }
+            };
+
+            if !EffectKind::takes_value(kind) {
+                return Ok(format!("{}({})", kind, key));
+            }
+            let value = loop {
+                let answer = if uses_name(kind) {
+                    ask("name:")
+                } else {
+                    ask("value:")
+                };
+                if answer.is_empty() {
+                    return Err(cmd::Error::PromptCancelled.into());
+                }
+                let valid = if uses_name(kind) {
+                    NameString::from(&answer).is_ok()
+                } else {
+                    answer.parse::<u32>().is_ok()
+                };
+                if valid && !answer.contains(&['(', ',', ')'][..]) {
+                    break answer;
+                }
+                println!("invalid value {}", answer);
+            };
+
+            Ok(format!("{}({},{})", kind, key, value))
+        }
+
+        /// Validate that the contents of a effect enum are valid
+        ///
+        /// This is mainly used when taking a effect from CLI and checking that the key
+        /// is present in the val_table for u32 types, and the name_table for String types
+        pub fn validate_effect(
+            effect: &EffectKind,
+            name_table: &NameTable,
+            val_table: &ValTable,
+        ) -> Result<()> {
+            // this match will stop compiling any time a new EffectKind is added
+            // NOTE: remember, if val is a u32, check the val_table, if val is a String, check the
+            // name table
+            match effect {
+                EffectKind::No => {}
+                EffectKind::Add(key, _val) => {
+                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
+                }
+                EffectKind::Sub(key, _val) => {
+                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
+                }
+                EffectKind::Set(key, _val) => {
+                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
+                }
+                EffectKind::Assign(key, _val) => {
+                    name_table.get(key).ok_or(cmd::Error::NameNotExists)?;
+                }
+                EffectKind::Prompt(key) => {
+                    name_table.get(key).ok_or(cmd::Error::NameNotExists)?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Copy a section of text into a new text buffer, fixing the section if it is corrupt
+        ///
+        /// Sections outside the text buffer are replaced with REPAIR_PLACEHOLDER, and sections with
+        /// a mismatched hash are given the hash of their current text. Returns the new section, and
+        /// the problem found if any.
+        fn repair_section(
+            text: &str,
+            section: &Section,
+            new_text: &mut String,
+        ) -> (Section, Option<Option<u64>>) {
+            match text.get(section[0]..section[1]) {
+                Some(slice) => {
+                    let new_section = push_section(new_text, slice);
+                    let mismatch = new_section.hash != section.hash;
+                    (new_section, mismatch.then_some(Some(new_section.hash)))
+                }
+                None => (push_section(new_text, REPAIR_PLACEHOLDER), Some(None)),
+            }
+        }
+
+        /// Attempt to fix common corruption in a dialogue tree, returning a repaired copy of the
+        /// tree and a list of the problems that were fixed
+        ///
+        /// The repaired tree is built from scratch with a compacted text buffer. Node indices are
+        /// kept, while edges are renumbered in order of their source node.
+        /// - Text sections outside the buffer are replaced with placeholder text
+        /// - Hashes that don't match the text are recomputed from the current text
+        /// - Edges whose source or target node is missing are dropped
+        /// - Outgoing edge lists are rebuilt from the edge sources, keeping the original order of
+        ///   the edges where the old list is intact
+        /// - Requirements and effects that reference missing keys are replaced with No
+        /// - Bookmarks pointing to missing nodes are dropped
+        /// - Directions of missing nodes, and directions referencing missing characters, are
+        ///   dropped
+        /// - On-enter effects of missing nodes, and on-enter effects referencing missing keys,
+        ///   are dropped
+        /// - Positions of missing nodes are dropped. Positions are only used by the editor, so
+        ///   this is not reported as a problem
+        /// - Interrupts targeting missing nodes or with invalid requirements are dropped
+        pub fn repair_tree(data: &DialogueTreeData) -> (DialogueTreeData, Vec<Corruption>) {
+            let tree = &data.tree;
+            let mut problems = Vec::new();
+            let mut repaired = DialogueTreeData::new(&data.name);
+            repaired.uid = data.uid;
+            repaired.next_line = data.next_line;
+            repaired.choice_policy = data.choice_policy;
+            repaired.presets = data.presets.clone();
+            repaired.naming = data.naming.clone();
+            repaired.name_table = data.name_table.clone();
+            repaired.val_table = data.val_table.clone();
+
+            trace!("copy nodes, repairing text sections");
+            for (i, node) in tree.nodes().iter().enumerate() {
+                let (section, problem) =
+                    repair_section(&data.text, &node.section, &mut repaired.text);
+                match problem {
+                    Some(None) => problems.push(Corruption::NodeSection(i)),
+                    Some(Some(actual)) => problems.push(Corruption::NodeHash {
+                        index: i,
+                        expected: node.section.hash,
+                        actual,
+                    }),
+                    None => {}
+                }
+                // node indices are sequential, so adding to an empty tree can't fail
+                let _res = repaired.tree.add_node(Dialogue { section, ..*node });
+            }
+
+            trace!("drop edges with missing endpoints");
+            let node_count = tree.nodes().len();
+            let mut outgoing: Vec<Vec<EdgeIndex>> = vec![Vec::new(); node_count];
+            let valid: Vec<bool> = (0..tree.edges().len())
+                .map(|i| {
+                    let source = tree.edge_sources.get(i).filter(|&&s| s < node_count);
+                    let target = tree.edge_targets.get(i).filter(|&&t| t < node_count);
+                    let valid = source.is_some() && target.is_some();
+                    if !valid {
+                        problems.push(Corruption::EdgeEndpoint(i));
+                    }
+                    valid
+                })
+                .collect();
+
+            trace!("rebuild outgoing edge lists from edge sources");
+            for (source, edges) in outgoing.iter_mut().enumerate() {
+                // follow the old linked list while it stays consistent
+                let mut next = tree.node_links.get(source).copied();
+                let mut broken = next.is_none();
+                while let Some(index) = next.filter(|&i| i != EdgeIndex::end()) {
+                    let linked = valid.get(index) == Some(&true)
+                        && tree.edge_sources[index] == source
+                        && !edges.contains(&index);
+                    if !linked {
+                        broken = true;
+                        break;
+                    }
+                    edges.push(index);
+                    next = tree.edge_links.get(index).copied();
+                    broken |= next.is_none();
+                }
+                // append any edges from this source that the old list missed
+                for index in
+                    (0..tree.edges().len()).filter(|&i| valid[i] && tree.edge_sources[i] == source)
+                {
+                    if !edges.contains(&index) {
+                        broken = true;
+                        edges.push(index);
+                    }
+                }
+                if broken {
+                    problems.push(Corruption::LinkList(source));
+                }
+            }
+
+            trace!("copy edges, repairing text sections and actions");
+            for (source, edges) in outgoing.iter().enumerate() {
+                for &i in edges.iter() {
+                    let edge = &tree.edges()[i];
+                    let (section, problem) =
+                        repair_section(&data.text, &edge.section, &mut repaired.text);
+                    match problem {
+                        Some(None) => problems.push(Corruption::EdgeSection(i)),
+                        Some(Some(actual)) => problems.push(Corruption::EdgeHash {
+                            index: i,
+                            expected: edge.section.hash,
+                            actual,
+                        }),
+                        None => {}
+                    }
+                    let mut choice = Choice {
+                        section,
+                        ..edge.clone()
+                    };
+                    if validate_requirement(&choice.requirement, &data.name_table, &data.val_table)
+                        .is_err()
+                    {
+                        problems.push(Corruption::Requirement(i));
+                        choice.requirement = ReqKind::No;
+                    }
+                    if validate_effect(&choice.effect, &data.name_table, &data.val_table).is_err() {
+                        problems.push(Corruption::Effect(i));
+                        choice.effect = EffectKind::No;
+                    }
+                    // endpoints were checked above
+                    let _res = repaired.tree.add_edge(source, tree.edge_targets[i], choice);
+                }
+            }
+
+            trace!("copy bookmarks that point to existing nodes");
+            for (key, &index) in data.bookmarks.iter() {
+                if index < node_count {
+                    repaired.bookmarks.insert(*key, index);
+                } else {
+                    problems.push(Corruption::Bookmark(*key));
+                }
+            }
+
+            trace!("copy directions of existing nodes, dropping those with missing characters");
+            let mut directed: Vec<_> = data.directions.iter().collect();
+            directed.sort_unstable_by_key(|(&index, _)| index);
+            for (&index, directions) in directed {
+                let kept: Vec<Direction> = directions
+                    .iter()
+                    .filter(|d| validate_direction(index, d, &data.name_table).is_ok())
+                    .copied()
+                    .collect();
+                if index >= node_count || kept.len() != directions.len() {
+                    problems.push(Corruption::Directions(index));
+                }
+                if index < node_count && !kept.is_empty() {
+                    repaired.directions.insert(index, kept);
+                }
+            }
+
+            trace!("copy on-enter effects of existing nodes, dropping those with missing keys");
+            let mut entered: Vec<_> = data.on_enter.iter().collect();
+            entered.sort_unstable_by_key(|(&index, _)| index);
+            for (&index, effects) in entered {
+                let kept: Vec<EffectKind> = effects
+                    .iter()
+                    .filter(|e| validate_effect(e, &data.name_table, &data.val_table).is_ok())
+                    .copied()
+                    .collect();
+                if index >= node_count || kept.len() != effects.len() {
+                    problems.push(Corruption::OnEnter(index));
+                }
+                if index < node_count && !kept.is_empty() {
+                    repaired.on_enter.insert(index, kept);
+                }
+            }
+
+            trace!("copy positions of existing nodes");
+            for node in data.tree.nodes().iter() {
+                if let Some(&pos) = data.layout.get(&node.line.id) {
+                    repaired.layout.insert(node.line.id, pos);
+                }
+            }
+
+            trace!("copy valid interrupts");
+            for (position, interrupt) in data.interrupts.iter().enumerate() {
+                if interrupt.target < node_count
+                    && validate_interrupt(position, interrupt, data).is_ok()
+                {
+                    repaired.interrupts.push(interrupt.clone());
+                } else {
+                    problems.push(Corruption::Interrupt(position));
+                }
+            }
+            (repaired, problems)
+        }
+
+        /// Validate that a given dialogue tree data structure contains all valid sections of text
+        /// that all edges point to valid nodes in the tree, all have valid action enums, and have
+        /// have correct hashes for all nodes and edges
+        ///
+        /// Returns a result with the error type if the tree was invalid, returns Ok(()) if valid
+        pub fn validate_tree(data: &DialogueTreeData) -> Result<()> {
+            validate_tree_with(data, None)
+        }
+
+        /// Validate a dialogue tree the same way as [validate_tree], but skip nodes and edges that
+        /// have not changed since the last validation. Changed sections are hashed again, and are
+        /// parsed again along with the rest of the tree if any key was added to or removed from
+        /// the name or val table.
+        ///
+        /// The result of the validation is recorded in the tree's hash cache. Edited nodes and
+        /// edges point to new sections, or to a shorter section with a new hash when their text
+        /// was rewritten in place, so they are checked again the next time around. If the buffer
+        /// is ever rewritten in place by anything other than rewrite_section the cache must be
+        /// cleared.
+        pub fn validate_tree_incremental(data: &mut DialogueTreeData) -> Result<()> {
+            let mut cache = std::mem::take(&mut data.hash_cache);
+            if data.text.len() < cache.text_len {
+                debug!("text buffer shrank since last validation, clear the hash cache");
+                cache.clear();
+            }
+            let res = validate_tree_with(data, Some(&cache));
+            if res.is_ok() {
+                cache.update(data);
+            }
+            data.hash_cache = cache;
+            res
+        }
+
+        /// Nodes and edges that passed the last call to [validate_tree_incremental], used to skip
+        /// checking them again.
+        ///
+        /// Sections are stored by node and edge index. A node or edge is dirty if it differs from
+        /// the one verified at that index, so any edit, insertion or removal since the last
+        /// validation causes the affected nodes and edges to be checked again
+        #[derive(Default, Clone)]
+        pub struct HashCache {
+            nodes: Vec<(usize, usize, u64)>,
+            edges: Vec<(usize, usize, u64, ReqKind, EffectKind)>,
+            /// Keys of the name table when the cache was last updated
+            names: HashSet<KeyString>,
+            /// Keys of the val table when the cache was last updated
+            vals: HashSet<KeyString>,
+            /// Length of the text buffer when the cache was last updated
+            text_len: usize,
+        }
+
+        impl HashCache {
+            /// Forget every verified node and edge, so the next validation checks the whole tree
+            pub fn clear(&mut self) {
+                self.nodes.clear();
+                self.edges.clear();
+                self.names.clear();
+                self.vals.clear();
+                self.text_len = 0;
+            }
+
+            /// Number of verified nodes and edges
+            pub fn len(&self) -> usize {
+                self.nodes.len() + self.edges.len()
+            }
+
+            pub fn is_empty(&self) -> bool {
+                self.nodes.is_empty() && self.edges.is_empty()
+            }
+
+            /// Approximate number of bytes allocated by the cache
+            pub fn memory_usage(&self) -> usize {
+                vec_bytes(&self.nodes)
+                    + vec_bytes(&self.edges)
+                    + (self.names.capacity() + self.vals.capacity())
+                        * std::mem::size_of::<KeyString>()
+            }
+
+            /// Check if the node at an index has already been verified
+            pub fn node_verified(&self, index: NodeIndex, node: &Dialogue) -> bool {
+                let section = &node.section;
+                self.nodes.get(index) == Some(&(section[0], section[1], section.hash))
+            }
+
+            /// Check if the edge at an index has already been verified
+            pub fn edge_verified(&self, index: EdgeIndex, edge: &Choice) -> bool {
+                let section = &edge.section;
+                let entry = (
+                    section[0],
+                    section[1],
+                    section.hash,
+                    edge.requirement.clone(),
+                    edge.effect,
+                );
+                self.edges.get(index) == Some(&entry)
+            }
+
+            /// Check if the name and val tables have the same keys as when the cache was updated
+            fn same_keys(&self, name_table: &NameTable, val_table: &ValTable) -> bool {
+                self.names.len() == name_table.len()
+                    && self.vals.len() == val_table.len()
+                    && name_table.keys().all(|k| self.names.contains(k))
+                    && val_table.keys().all(|k| self.vals.contains(k))
+            }
+
+            /// Record every node and edge of a tree that was just validated
+            fn update(&mut self, data: &DialogueTreeData) {
+                self.nodes.clear();
+                self.nodes.extend(data.tree.nodes().iter().map(|n| {
+                    let section = &n.section;
+                    (section[0], section[1], section.hash)
+                }));
+                self.edges.clear();
+                self.edges.extend(data.tree.edges().iter().map(|e| {
+                    let section = &e.section;
+                    (
+                        section[0],
+                        section[1],
+                        section.hash,
+                        e.requirement.clone(),
+                        e.effect,
+                    )
+                }));
+                self.names.clear();
+                self.names.extend(data.name_table.keys().copied());
+                self.vals.clear();
+                self.vals.extend(data.val_table.keys().copied());
+                self.text_len = data.text.len();
+            }
+        }
+
+        /// The verified nodes and edges are left out, they are not part of the project and there
+        /// can be tens of thousands of them
+        impl std::fmt::Debug for HashCache {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("HashCache")
+            }
+        }
+
+        /// Trigram index over the prose of every node and edge, used by find and replace to skip
+        /// sections that cannot contain the pattern instead of scanning the whole tree.
+        ///
+        /// Only prose tokens are indexed, speakers and name keys are left out like they are by
+        /// replace_text. The index is built lazily by [search], and is invalidated by the history
+        /// whenever an event that edits text is pushed, undone, or redone, see
+        /// DialogueTreeEvent::edits_text. Trigrams are ASCII lowercase so that the same index
+        /// serves case sensitive and insensitive searches. Like the hash cache, it must be cleared
+        /// if the text buffer is ever rewritten outside of the history.
+        #[derive(Default, Clone)]
+        pub struct SearchIndex {
+            built: bool,
+            node_count: usize,
+            edge_count: usize,
+            /// Positions of the entries containing each trigram, in ascending order. Nodes are
+            /// numbered first, followed by edges
+            trigrams: HashMap<[u8; 3], Vec<u32>>,
+        }
+
+        impl SearchIndex {
+            /// Forget the index, so the next search builds it again
+            pub fn clear(&mut self) {
+                self.built = false;
+                self.node_count = 0;
+                self.edge_count = 0;
+                self.trigrams.clear();
+            }
+
+            /// Forget the index if an event edited the text of any node or edge
+            pub fn invalidate(&mut self, event: &DialogueTreeEvent) {
+                if self.built && event.edits_text() {
+                    trace!("text edited, invalidate the search index");
+                    self.clear();
+                }
+            }
+
+            pub fn is_empty(&self) -> bool {
+                !self.built
+            }
+
+            /// Approximate number of bytes allocated by the index
+            pub fn memory_usage(&self) -> usize {
+                map_bytes(&self.trigrams) + self.trigrams.values().map(vec_bytes).sum::<usize>()
+            }
+
+            /// Index the prose of every node and edge of a tree. Sections that do not point to a
+            /// valid section of the text are indexed as empty
+            fn rebuild(&mut self, data: &DialogueTreeData) {
+                self.clear();
+                let sections = data
+                    .tree
+                    .nodes()
+                    .iter()
+                    .map(|n| &n.section)
+                    .chain(data.tree.edges().iter().map(|e| &e.section));
+                for (position, section) in sections.enumerate() {
+                    let text = data.text.get(section[0]..section[1]).unwrap_or_default();
+                    for token in split_tokens(text).step_by(2) {
+                        let lower = token.as_bytes().to_ascii_lowercase();
+                        for window in lower.windows(3) {
+                            let postings = self
+                                .trigrams
+                                .entry([window[0], window[1], window[2]])
+                                .or_default();
+                            if postings.last() != Some(&(position as u32)) {
+                                postings.push(position as u32);
+                            }
+                        }
+                    }
+                }
+                self.node_count = data.tree.nodes().len();
+                self.edge_count = data.tree.edges().len();
+                self.built = true;
+            }
+
+            /// Positions of the entries that may contain a pattern. Patterns shorter than a
+            /// trigram may be anywhere
+            fn candidates(&self, pattern: &str) -> Vec<u32> {
+                let lower = pattern.as_bytes().to_ascii_lowercase();
+                if lower.len() < 3 {
+                    return (0..(self.node_count + self.edge_count) as u32).collect();
+                }
+                let mut postings = Vec::new();
+                for window in lower.windows(3) {
+                    match self.trigrams.get(&[window[0], window[1], window[2]]) {
+                        Some(list) => postings.push(list),
+                        None => return Vec::new(),
+                    }
+                }
+                postings.sort_unstable_by_key(|list| list.len());
+                let mut found = postings[0].clone();
+                for list in postings[1..].iter() {
+                    found.retain(|position| list.binary_search(position).is_ok());
+                }
+                found
+            }
+        }
+
+        /// The indexed trigrams are left out, there can be tens of thousands of them
+        impl std::fmt::Debug for SearchIndex {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("SearchIndex")
+            }
+        }
+
+        /// Find every node and edge whose prose contains a pattern, in index order. Speakers and
+        /// name keys are not searched, so find matches the same text that replace changes, see
+        /// replace_text. Nodes are listed as Reference::NodeText and edges as
+        /// Reference::EdgeText. The search index of the tree is built first if it was
+        /// invalidated, see SearchIndex
+        pub fn search(
+            data: &mut DialogueTreeData,
+            pattern: &str,
+            ignore_case: bool,
+        ) -> Result<Vec<Reference>> {
+            anyhow::ensure!(!pattern.is_empty(), cmd::Error::EmptyPattern);
+            let mut index = std::mem::take(&mut data.search_index);
+            if !index.built {
+                debug!("build the search index");
+                index.rebuild(data);
+            }
+            let mut found = Vec::new();
+            for position in index.candidates(pattern) {
+                let position = position as usize;
+                let section = if position < index.node_count {
+                    data.tree.get_node(position).map(|n| n.section)
+                } else {
+                    data.tree
+                        .get_edge(position - index.node_count)
+                        .map(|e| e.section)
+                };
+                let text = section
+                    .ok()
+                    .and_then(|s| data.text.get(s[0]..s[1]))
+                    .unwrap_or_default();
+                if find_prose(text, pattern, ignore_case).is_some() {
+                    found.push(if position < index.node_count {
+                        Reference::NodeText(position)
+                    } else {
+                        Reference::EdgeText(position - index.node_count)
+                    });
+                }
+            }
+            data.search_index = index;
+            Ok(found)
+        }
+
+        /// Find the byte offset of the first occurrence of a pattern in the prose of node or edge
+        /// text, optionally ignoring ASCII case. Tokens in key positions are skipped, see
+        /// replace_text
+        pub fn find_prose(text: &str, pattern: &str, ignore_case: bool) -> Option<usize> {
+            split_tokens(text).step_by(2).find_map(|token| {
+                let start = token.as_ptr() as usize - text.as_ptr() as usize;
+                find_text(token, pattern, ignore_case).map(|at| start + at)
+            })
+        }
+
+        /// Find the byte offset of the first occurrence of a pattern in text, optionally
+        /// ignoring ASCII case
+        pub fn find_text(text: &str, pattern: &str, ignore_case: bool) -> Option<usize> {
+            if ignore_case {
+                text.to_ascii_lowercase()
+                    .find(&pattern.to_ascii_lowercase())
+            } else {
+                text.find(pattern)
+            }
+        }
+
+        /// Replace every occurrence of a pattern in node or edge text. Returns None if the
+        /// pattern was not found.
+        ///
+        /// Only tokens outside of key positions are searched, so speakers and name substitutions
+        /// are never changed. Separators in the replacement are escaped, see escape
+        pub fn replace_text(
+            text: &str,
+            pattern: &str,
+            replacement: &str,
+            ignore_case: bool,
+        ) -> Option<String> {
+            let replacement = escape(replacement);
+            let mut found = false;
+            let mut new_text = String::with_capacity(text.len());
+            for (i, token) in split_tokens(text).enumerate() {
+                if i > 0 {
+                    new_text.push_str(TOKEN_SEP);
+                }
+                let mut rest = token;
+                if (i & 0x1) == 0 {
+                    while let Some(at) = find_text(rest, pattern, ignore_case) {
+                        found = true;
+                        new_text.push_str(&rest[..at]);
+                        new_text.push_str(&replacement);
+                        rest = &rest[at + pattern.len()..];
+                    }
+                }
+                new_text.push_str(rest);
+            }
+            if found {
+                Some(new_text)
+            } else {
+                None
+            }
+        }
+
+        /// Validate a dialogue tree, skipping any node or edge the cache has already verified
+        fn validate_tree_with(data: &DialogueTreeData, cache: Option<&HashCache>) -> Result<()> {
+            // verified sections only need to be parsed again if the keys they use could be gone
+            let same_keys = cache.is_some_and(|c| c.same_keys(&data.name_table, &data.val_table));
+
+            // check nodes first, use parallel iterator in case of very large graph
+            let nodes_iter = data.tree.nodes().par_iter().enumerate();
+            nodes_iter.try_for_each(|(i, node)| -> Result<()> {
+                let verified = cache.is_some_and(|c| c.node_verified(i, node));
+                if verified && same_keys {
+                    return Ok(());
+                }
+                // try to grab the text section as a slice, and return an error if the get() failed
+                let slice = data.text[..]
+                    .get(node.section[0]..node.section[1])
+                    .ok_or(cmd::Error::InvalidSection)?;
+                // if the slice was successful, check its hash
+                anyhow::ensure!(
+                    verified || seahash::hash(slice.as_bytes()) == node.section.hash,
+                    cmd::Error::InvalidHash
+                );
+                // Check that the section of text parses successfully (all names present in the
+                // name_table)
+                validate_node(slice, &data.name_table)?;
+                Ok(())
+            })?;
+
+            // check edges, will check that they point to nodes that exist, and validate the actionenums
+            let edges_iter = data.tree.edges().par_iter().enumerate();
+            edges_iter.try_for_each(|(i, edge)| -> Result<()> {
+                let verified = cache.is_some_and(|c| c.edge_verified(i, edge));
+                if verified && same_keys {
+                    return Ok(());
+                }
+                // try to grab the text section as a slice, and return an error if the get() failed
+                let slice = data.text[..]
+                    .get(edge.section[0]..edge.section[1])
+                    .ok_or(cmd::Error::InvalidSection)?;
+                // if the slice was successful, check its hash
+                anyhow::ensure!(
+                    verified || seahash::hash(slice.as_bytes()) == edge.section.hash,
+                    cmd::Error::InvalidHash
+                );
+                // Check that the section of text parses successfully (all names present in the
+                // name_table)
+                validate_edge(slice, &data.name_table)?;
+                validate_requirement(&edge.requirement, &data.name_table, &data.val_table)?;
+                validate_effect(&edge.effect, &data.name_table, &data.val_table)?;
+                Ok(())
+            })?;
+
+            // check timed choices. Nodes may have at most one default choice, which must always be
+            // available, and every node with a timeout must have one
+            let mut defaults = vec![0usize; data.tree.nodes().len()];
+            for (i, edge) in data.tree.edges().iter().enumerate() {
+                if edge.default {
+                    anyhow::ensure!(
+                        edge.requirement == ReqKind::No,
+                        cmd::Error::DefaultHasRequirement(i)
+                    );
+                    let source = data.tree.source_of(i)?;
+                    *defaults
+                        .get_mut(source)
+                        .ok_or(tree::Error::InvalidNodeIndex)? += 1;
+                }
+            }
+            for (i, node) in data.tree.nodes().iter().enumerate() {
+                anyhow::ensure!(defaults[i] <= 1, cmd::Error::MultipleDefaults(i));
+                anyhow::ensure!(
+                    node.timeout.is_none() || defaults[i] == 1,
+                    cmd::Error::TimeoutWithoutDefault(i)
+                );
+            }
+
+            // check that directions belong to nodes in the tree and reference existing characters
+            for (&index, directions) in data.directions.iter() {
+                data.tree.get_node(index)?;
+                for direction in directions.iter() {
+                    validate_direction(index, direction, &data.name_table)?;
+                }
+            }
+
+            // check that on-enter effects belong to nodes in the tree and reference existing keys
+            for (&index, effects) in data.on_enter.iter() {
+                data.tree.get_node(index)?;
+                for effect in effects.iter() {
+                    validate_effect(effect, &data.name_table, &data.val_table)?;
+                }
+            }
+
+            for (position, interrupt) in data.interrupts.iter().enumerate() {
+                validate_interrupt(position, interrupt, data)?;
+            }
+
+            // check that every val key has a known scope
+            for key in data.val_table.keys() {
+                ValScope::of(key)?;
+            }
+
+            // check that every call can return to its caller
+            for (i, edge) in data.tree.edges().iter().enumerate() {
+                if edge.call {
+                    anyhow::ensure!(
+                        call_returns(&data.tree, i)?,
+                        cmd::Error::CallNeverReturns(i)
+                    );
+                }
+            }
+            Ok(())
+        }
+
+        /// The table that a key belongs to
+        #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+        pub enum KeyKind {
+            /// Key into the name table
+            Name,
+            /// Key into the val table
+            Val,
+        }
+
+        /// Get the directions of a node, which is empty if the node has none
+        pub fn directions_of(data: &DialogueTreeData, node: NodeIndex) -> Result<&[Direction]> {
+            data.tree.get_node(node)?;
+            Ok(data.directions.get(&node).map_or(&[], Vec::as_slice))
+        }
+
+        /// Get the on-enter effects of a node, which is empty if the node has none
+        pub fn on_enter_of(data: &DialogueTreeData, node: NodeIndex) -> Result<&[EffectKind]> {
+            data.tree.get_node(node)?;
+            Ok(data.on_enter.get(&node).map_or(&[], Vec::as_slice))
+        }
+
+        /// Validate that the character of a direction on a node is in the name table
+        pub fn validate_direction(
+            node: NodeIndex,
+            direction: &Direction,
+            name_table: &NameTable,
+        ) -> Result<()> {
+            if let Some(key) = direction.character() {
+                anyhow::ensure!(
+                    name_table.contains_key(key),
+                    cmd::Error::DirectionKeyNotExists(node, *key)
+                );
+            }
+            Ok(())
+        }
+
+        /// Validate that an interrupt targets a node in the tree, and that its requirement is set
+        /// and references existing keys
+        pub fn validate_interrupt(
+            position: usize,
+            interrupt: &Interrupt,
+            data: &DialogueTreeData,
+        ) -> Result<()> {
+            data.tree.get_node(interrupt.target)?;
+            anyhow::ensure!(
+                interrupt.requirement != ReqKind::No,
+                cmd::Error::InterruptWithoutRequirement(position)
+            );
+            validate_requirement(&interrupt.requirement, &data.name_table, &data.val_table)
+        }
+
+        /// Check if a call edge can return, i.e. if a node with no choices is reachable from its
+        /// target. Calls made within the called conversation return to nodes already visited, so
+        /// they are followed like ordinary edges
+        pub fn call_returns(tree: &Tree, edge: EdgeIndex) -> Result<bool> {
+            let leaf = tree::walk(tree, tree.target_of(edge)?, |node, _, _| {
+                if tree.is_leaf(node).unwrap_or_default() {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(tree::Visit::Children)
+                }
+            })?;
+            Ok(leaf.is_some())
+        }
+
+        /// Get the outgoing edges of a node that are marked as the default choice. A valid tree has
+        /// at most one
+        pub fn default_choices(tree: &Tree, node: NodeIndex) -> Result<Vec<EdgeIndex>> {
+            let mut defaults = Vec::new();
+            for index in tree.outgoing_from_index(node)? {
+                if tree.get_edge(index)?.default {
+                    defaults.push(index);
+                }
+            }
+            Ok(defaults)
+        }
+
+        /// Push a string onto the end of a text buffer and return a Section pointing to it
+        pub fn push_section(text: &mut String, s: &str) -> Section {
+            let start = text.len();
+            text.push_str(s);
+            let end = text.len();
+            let hash = hash(s.as_bytes());
+            debug!("start: {}, end: {}, hash: {}", start, end, hash);
+            Section::new([start, end], hash)
+        }
+
+        /// Write the new text of a node or edge that currently points to a section, and return the
+        /// section holding the new text
+        ///
+        /// Text that is no longer than the old text is written in place over the old section, so
+        /// that repeated edits don't grow the buffer and rebuilds are needed less often. Any bytes
+        /// left over at the end of the old section are filled with spaces, which are not part of
+        /// the new section. The overwrite is returned as a TextRewrite event, which must be
+        /// recorded along with the node or edge edit so that undo restores the old text. Longer
+        /// text, and text of a section that is shared with another node or edge, is appended to
+        /// the end of the buffer instead.
+        pub fn rewrite_section(
+            data: &mut DialogueTreeData,
+            old: &Section,
+            text: &str,
+        ) -> (Section, Option<TextRewrite>) {
+            let (start, end) = (old[0], old[1]);
+            let overlaps = |s: &Section| s[0] < end && start < s[1];
+            let shared = data
+                .tree
+                .nodes()
+                .iter()
+                .map(|n| &n.section)
+                .chain(data.tree.edges().iter().map(|e| &e.section))
+                .filter(|s| overlaps(s))
+                .count()
+                > 1;
+            let from = match data.text.get(start..end) {
+                Some(from) if text.len() <= from.len() && !shared => from.to_string(),
+                _ => return (push_section(&mut data.text, text), None),
+            };
+
+            let mut to = String::with_capacity(from.len());
+            to.push_str(text);
+            to.extend(std::iter::repeat_n(' ', from.len() - text.len()));
+            data.text.replace_range(start..end, &to);
+            let hash = hash(text.as_bytes());
+            debug!("rewrote start: {}, end: {}, hash: {}", start, end, hash);
+            (
+                Section::new([start, start + text.len()], hash),
+                Some(TextRewrite { start, from, to }),
+            )
+        }
+
+        /// Combine an edit with the text it rewrote in place, if any, into a single event. The
+        /// edit comes first so that the group is described by it
+        pub fn with_rewrite<E: Into<DialogueTreeEvent>>(
+            edit: E,
+            rewrite: Option<TextRewrite>,
+        ) -> DialogueTreeEvent {
+            match rewrite {
+                Some(rewrite) => EventGroup {
+                    events: vec![edit.into(), rewrite.into()],
+                }
+                .into(),
+                None => edit.into(),
+            }
+        }
+
+        /// Replace every name key in a section of node or edge text with a new key. Returns None
+        /// if the key was not referenced in the text.
+        ///
+        /// Only tokens in key positions are replaced, matching text elsewhere is left untouched
+        pub fn rename_text_key(text: &str, key: &str, new_key: &str) -> Option<String> {
+            let mut found = false;
+            let mut new_text = String::with_capacity(text.len());
+            for (i, token) in split_tokens(text).enumerate() {
+                if i > 0 {
+                    new_text.push_str(TOKEN_SEP);
+                }
+                if (i & 0x1) == 1 && token == key {
+                    found = true;
+                    new_text.push_str(new_key);
+                } else {
+                    new_text.push_str(token);
+                }
+            }
+            if found {
+                Some(new_text)
+            } else {
+                None
+            }
+        }
+
+        /// Return a requirement with any reference to a key of the given kind replaced
+        pub fn rename_requirement(
+            req: ReqKind,
+            kind: KeyKind,
+            key: KeyString,
+            new_key: KeyString,
+        ) -> ReqKind {
+            // this match will stop compiling any time a new ReqKind is added
+            match (req, kind) {
+                (ReqKind::Greater(k, v), KeyKind::Val) if k == key => ReqKind::Greater(new_key, v),
+                (ReqKind::Less(k, v), KeyKind::Val) if k == key => ReqKind::Less(new_key, v),
+                (ReqKind::Equal(k, v), KeyKind::Val) if k == key => ReqKind::Equal(new_key, v),
+                (ReqKind::Cmp(k, v), KeyKind::Name) if k == key => ReqKind::Cmp(new_key, v),
+                (ReqKind::NotCmp(k, v), KeyKind::Name) if k == key => ReqKind::NotCmp(new_key, v),
+                (ReqKind::OneOf(k, v), KeyKind::Name) if k == key => ReqKind::OneOf(new_key, v),
+                (
+                    req @ (ReqKind::No
+                    | ReqKind::Greater(_, _)
+                    | ReqKind::Less(_, _)
+                    | ReqKind::Equal(_, _)
+                    | ReqKind::Cmp(_, _)
+                    | ReqKind::NotCmp(_, _)
+                    | ReqKind::OneOf(_, _)),
+                    _,
+                ) => req,
+            }
+        }
+
+        /// Return an effect with any reference to a key of the given kind replaced
+        pub fn rename_effect(
+            effect: EffectKind,
+            kind: KeyKind,
+            key: KeyString,
+            new_key: KeyString,
+        ) -> EffectKind {
+            // this match will stop compiling any time a new EffectKind is added
+            match (effect, kind) {
+                (EffectKind::Add(k, v), KeyKind::Val) if k == key => EffectKind::Add(new_key, v),
+                (EffectKind::Sub(k, v), KeyKind::Val) if k == key => EffectKind::Sub(new_key, v),
+                (EffectKind::Set(k, v), KeyKind::Val) if k == key => EffectKind::Set(new_key, v),
+                (EffectKind::Assign(k, v), KeyKind::Name) if k == key => {
+                    EffectKind::Assign(new_key, v)
+                }
+                (EffectKind::Prompt(k), KeyKind::Name) if k == key => EffectKind::Prompt(new_key),
+                (EffectKind::No, _)
+                | (EffectKind::Add(_, _), _)
+                | (EffectKind::Sub(_, _), _)
+                | (EffectKind::Set(_, _), _)
+                | (EffectKind::Assign(_, _), _)
+                | (EffectKind::Prompt(_), _) => effect,
+            }
+        }
+
+        /// A node parsed from a screenplay, with the raw text that will be stored in the text
+        /// buffer
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct ScreenplayNode {
+            pub label: Option<String>,
+            pub text: String,
+            pub choices: Vec<ScreenplayChoice>,
+        }
+
+        /// A choice parsed from a screenplay, targeting the node with a given label
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct ScreenplayChoice {
+            pub text: String,
+            pub target: String,
+            pub requirement: ReqKind,
+            pub effect: EffectKind,
+        }
+
+        /// Parse the contents of a screenplay file. Text is not validated against the name table
+        ///
+        /// See cmd::import::Screenplay for a description of the format
+        pub fn parse_screenplay(screenplay: &str) -> Result<Vec<ScreenplayNode>> {
+            let mut nodes: Vec<ScreenplayNode> = Vec::new();
+            let mut label: Option<String> = None;
+
+            for (i, line) in screenplay.lines().enumerate() {
+                // line numbers are reported starting from 1
+                let line_number = i + 1;
+                let trimmed = line.trim();
+                let indented = line.starts_with(char::is_whitespace);
+
+                if trimmed.is_empty() {
+                    continue;
+                } else if let Some(l) = trimmed.strip_prefix('#') {
+                    label = Some(l.trim().to_string());
+                } else if indented {
+                    let node = nodes
+                        .last_mut()
+                        .ok_or(cmd::Error::ScreenplayParse(line_number))?;
+                    if let Some(choice) = trimmed.strip_prefix('>') {
+                        node.choices
+                            .push(parse_screenplay_choice(choice.trim(), line_number)?);
+                    } else {
+                        node.text.push('\n');
+                        node.text.push_str(trimmed);
+                    }
+                } else {
+                    let (speaker, dialogue) = trimmed
+                        .split_once(':')
+                        .ok_or(cmd::Error::ScreenplayParse(line_number))?;
+                    nodes.push(ScreenplayNode {
+                        label: label.take(),
+                        text: format!(
+                            "{}{}{}{}",
+                            TOKEN_SEP,
+                            speaker.trim(),
+                            TOKEN_SEP,
+                            dialogue.trim_start()
+                        ),
+                        choices: Vec::new(),
+                    });
+                }
+            }
+            Ok(nodes)
+        }
+
+        /// Helper to parse the contents of a choice line in a screenplay, with the leading '>'
+        /// removed
+        fn parse_screenplay_choice(choice: &str, line_number: usize) -> Result<ScreenplayChoice> {
+            let (text, target) = choice
+                .rsplit_once("->")
+                .ok_or(cmd::Error::ScreenplayParse(line_number))?;
+            let mut tokens = target.split_whitespace();
+            let target = tokens
+                .next()
+                .ok_or(cmd::Error::ScreenplayParse(line_number))?;
+
+            let mut requirement = ReqKind::No;
+            let mut effect = EffectKind::No;
+            while let Some(flag) = tokens.next() {
+                let value = tokens
+                    .next()
+                    .ok_or(cmd::Error::ScreenplayParse(line_number))?;
+                match flag {
+                    "-r" => requirement = value.parse::<ReqKind>()?,
+                    "-e" => effect = value.parse::<EffectKind>()?,
+                    _ => return Err(cmd::Error::ScreenplayParse(line_number).into()),
+                }
+            }
+
+            Ok(ScreenplayChoice {
+                text: text.trim().to_string(),
+                target: target.to_string(),
+                requirement,
+                effect,
+            })
+        }
+
+        /// Write a dialogue tree to the screenplay format, labeling each node with its index
+        ///
+        /// See cmd::import::Screenplay for a description of the format
+        pub fn write_screenplay(data: &DialogueTreeData) -> Result<String> {
+            let mut screenplay = String::with_capacity(data.text.len());
+            for (idx, node) in data.tree.nodes().iter().enumerate() {
+                let text = data
+                    .text
+                    .get(node.section[0]..node.section[1])
+                    .ok_or(cmd::Error::InvalidSection)?;
+                // node text has the format ::speaker::dialogue
+                let (speaker, dialogue) = text
+                    .strip_prefix(TOKEN_SEP)
+                    .and_then(|t| t.split_once(TOKEN_SEP))
+                    .ok_or(cmd::Error::NodeParse)?;
+
+                screenplay.push_str(&format!("# {}\n", idx));
+                let mut lines = dialogue.split('\n');
+                screenplay.push_str(&format!("{}: {}\n", speaker, lines.next().unwrap_or("")));
+                for line in lines {
+                    screenplay.push_str(&format!("    {}\n", line));
+                }
+
+                for edge_index in data.tree.outgoing_from_index(idx)? {
+                    let choice = data.tree.get_edge(edge_index)?;
+                    let text = data
+                        .text
+                        .get(choice.section[0]..choice.section[1])
+                        .ok_or(cmd::Error::InvalidSection)?;
+                    screenplay.push_str(&format!(
+                        "    > {} -> {}",
+                        text,
+                        data.tree.target_of(edge_index)?
+                    ));
+                    if choice.requirement != ReqKind::No {
+                        screenplay.push_str(&format!(" -r {}", choice.requirement));
+                    }
+                    if choice.effect != EffectKind::No {
+                        screenplay.push_str(&format!(" -e {}", choice.effect));
+                    }
+                    screenplay.push('\n');
+                }
+            }
+            Ok(screenplay)
+        }
+
+        /// Id of a node or edge in a localization CSV file, written as node:3 or edge:7
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum TextId {
+            Node(NodeIndex),
+            Edge(EdgeIndex),
+        }
+
+        impl std::str::FromStr for TextId {
+            type Err = cmd::Error;
+
+            fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+                let invalid = || cmd::Error::InvalidTextId(s.to_string());
+                let (kind, index) = s.trim().split_once(':').ok_or_else(invalid)?;
+                let index = index.parse::<usize>().map_err(|_| invalid())?;
+                match kind {
+                    "node" => Ok(TextId::Node(index)),
+                    "edge" => Ok(TextId::Edge(index)),
+                    _ => Err(invalid()),
+                }
+            }
+        }
+
+        impl std::fmt::Display for TextId {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    TextId::Node(index) => write!(f, "node:{}", index),
+                    TextId::Edge(index) => write!(f, "edge:{}", index),
+                }
+            }
+        }
+
+        /// Write the text of every node and edge to a CSV file with id, line, speaker, and text
+        /// columns
+        ///
+        /// See cmd::export::Csv for a description of the format
+        pub fn write_text_csv(data: &DialogueTreeData) -> Result<String> {
+            let mut writer = csv::Writer::from_writer(vec![]);
+            writer.write_record(["id", "line", "speaker", "text"])?;
+            for (idx, node) in data.tree.nodes().iter().enumerate() {
+                let text = data
+                    .text
+                    .get(node.section[0]..node.section[1])
+                    .ok_or(cmd::Error::InvalidSection)?;
+                let (speaker, dialogue) = text
+                    .strip_prefix(TOKEN_SEP)
+                    .and_then(|t| t.split_once(TOKEN_SEP))
+                    .ok_or(cmd::Error::NodeParse)?;
+                let id = TextId::Node(idx).to_string();
+                let line = node.line.to_string();
+                writer.write_record([id.as_str(), line.as_str(), speaker, dialogue])?;
+            }
+            for (idx, edge) in data.tree.edges().iter().enumerate() {
+                let text = data
+                    .text
+                    .get(edge.section[0]..edge.section[1])
+                    .ok_or(cmd::Error::InvalidSection)?;
+                let id = TextId::Edge(idx).to_string();
+                let line = edge.line.to_string();
+                writer.write_record([id.as_str(), line.as_str(), "", text])?;
+            }
+            Ok(String::from_utf8(writer.into_inner()?)?)
+        }
+
+        /// Line id and text hash of a node or edge at the time of an export
+        #[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+        pub struct ManifestLine {
+            pub line: LineId,
+            pub hash: u64,
+        }
+
+        /// Record of the lines of a project when its text was exported, used to find the lines
+        /// that changed since, see cmd::report::StaleLines
+        #[derive(Debug, Serialize, Deserialize, PartialEq, Default)]
+        pub struct LineManifest {
+            /// Name of the exported project
+            pub name: String,
+            /// Every node and then every edge, in index order
+            pub lines: Vec<ManifestLine>,
+        }
+
+        impl LineManifest {
+            pub fn new(data: &DialogueTreeData) -> Self {
+                let nodes = data.tree.nodes().iter().map(|n| (n.line, n.section));
+                let edges = data.tree.edges().iter().map(|e| (e.line, e.section));
+                LineManifest {
+                    name: data.name.clone(),
+                    lines: nodes
+                        .chain(edges)
+                        .map(|(line, section)| ManifestLine {
+                            line,
+                            hash: section.hash,
+                        })
+                        .collect(),
+                }
+            }
+        }
+
+        /// Difference of a line between an export manifest and the current project
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum LineChange {
+            /// The text of the line changed since it was exported
+            Stale {
+                id: TextId,
+                line: LineId,
+                exported: LineId,
+            },
+            /// The line was added since the export, or has no line id
+            New { id: TextId, line: LineId },
+            /// The line was removed since the export
+            Removed(LineId),
+        }
+
+        impl std::fmt::Display for LineChange {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    LineChange::Stale { id, line, exported } => {
+                        write!(f, "stale {} {}, exported as {}", line, id, exported)
+                    }
+                    LineChange::New { id, line } => write!(f, "new {} {}", line, id),
+                    LineChange::Removed(line) => write!(f, "removed {}", line),
+                }
+            }
+        }
+
+        /// Compare the lines of a project against an export manifest by line id. A line is stale
+        /// if the hash of its text differs from the exported hash, so a line whose text was
+        /// changed and then changed back is not stale. Changes to lines in the manifest come
+        /// first, in manifest order, followed by new lines in index order
+        pub fn stale_lines(data: &DialogueTreeData, manifest: &LineManifest) -> Vec<LineChange> {
+            let nodes = data.tree.nodes().iter().enumerate();
+            let edges = data.tree.edges().iter().enumerate();
+            let current: Vec<(TextId, LineId, u64)> = nodes
+                .map(|(i, n)| (TextId::Node(i), n.line, n.section.hash))
+                .chain(edges.map(|(i, e)| (TextId::Edge(i), e.line, e.section.hash)))
+                .collect();
+            let by_id: HashMap<u32, (TextId, LineId, u64)> = current
+                .iter()
+                .filter(|(_, line, _)| line.id != 0)
+                .map(|&(id, line, hash)| (line.id, (id, line, hash)))
+                .collect();
+
+            let mut changes = Vec::new();
+            for exported in manifest.lines.iter() {
+                match by_id.get(&exported.line.id) {
+                    Some(&(id, line, hash)) if hash != exported.hash => {
+                        changes.push(LineChange::Stale {
+                            id,
+                            line,
+                            exported: exported.line,
+                        })
+                    }
+                    Some(_) => {}
+                    None => changes.push(LineChange::Removed(exported.line)),
+                }
+            }
+            let exported: HashSet<u32> = manifest.lines.iter().map(|l| l.line.id).collect();
+            for &(id, line, _) in current.iter() {
+                if line.id == 0 || !exported.contains(&line.id) {
+                    changes.push(LineChange::New { id, line });
+                }
+            }
+            changes
+        }
+
+        /// Replace the text of the node or edge with the given id, keeping the speaker of nodes.
+        /// The text is validated before anything is written
+        pub fn import_text(
+            data: &mut DialogueTreeData,
+            id: &str,
+            text: &str,
+        ) -> Result<DialogueTreeEvent> {
+            match id.parse::<TextId>()? {
+                TextId::Node(index) => {
+                    let old_node = *data.tree.get_node(index)?;
+                    let old_text = data
+                        .text
+                        .get(old_node.section[0]..old_node.section[1])
+                        .ok_or(cmd::Error::InvalidSection)?;
+                    let speaker = old_text
+                        .strip_prefix(TOKEN_SEP)
+                        .and_then(|t| t.split_once(TOKEN_SEP))
+                        .ok_or(cmd::Error::NodeParse)?
+                        .0;
+                    let new_text = format!("{}{}{}{}", TOKEN_SEP, speaker, TOKEN_SEP, text);
+                    validate_node(&new_text, &data.name_table)?;
+                    let section = push_section(&mut data.text, &new_text);
+                    let new_node = Dialogue {
+                        section,
+                        ..old_node
+                    };
+                    Ok(data.tree.edit_node(index, new_node)?.into())
+                }
+                TextId::Edge(index) => {
+                    let old_edge = data.tree.get_edge(index)?.clone();
+                    validate_edge(text, &data.name_table)?;
+                    let section = push_section(&mut data.text, text);
+                    let new_edge = Choice {
+                        section,
+                        ..old_edge
+                    };
+                    Ok(data.tree.edit_edge(index, new_edge)?.into())
+                }
+            }
+        }
+
+        /// How flagged nodes are handled in a censored copy of a tree
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum Censor {
+            /// Remove flagged nodes and their choices, rerouting edges and interrupts that lead
+            /// to them to the fallback node
+            Strip(NodeIndex),
+            /// Keep flagged nodes and their speakers, but replace their dialogue with this text
+            Replace(String),
+        }
+
+        impl Censor {
+            /// Choose how to censor from the options of an export command, exactly one of a
+            /// fallback node or replacement text must be given
+            pub fn from_options(
+                fallback: Option<NodeIndex>,
+                replace: Option<&str>,
+            ) -> Result<Self> {
+                match (fallback, replace) {
+                    (Some(fallback), None) => Ok(Censor::Strip(fallback)),
+                    (None, Some(text)) => Ok(Censor::Replace(text.to_string())),
+                    _ => Err(cmd::Error::CensorWithoutFallback.into()),
+                }
+            }
+        }
+
+        /// Make a copy of a tree with every node that has any of the given flags stripped or
+        /// replaced. The text buffer of the copy is rebuilt, so no censored text is left in it
+        ///
+        /// Stripping swap removes nodes, so node indices in the copy may differ from the original
+        ///
+        /// # Errors
+        /// Error if the fallback node does not exist or is censored itself, or if replacement
+        /// text references a missing name
+        pub fn censor(
+            data: &DialogueTreeData,
+            flags: ContentFlags,
+            censor: &Censor,
+        ) -> Result<DialogueTreeData> {
+            let mut censored = data.clone();
+            let mut flagged: Vec<NodeIndex> = (0..data.tree.nodes().len())
+                .filter(|i| data.tree.nodes()[*i].flags.intersects(flags))
+                .collect();
+
+            match censor {
+                Censor::Replace(text) => {
+                    for index in flagged {
+                        let node = *censored.tree.get_node(index)?;
+                        let old_text = censored
+                            .text
+                            .get(node.section[0]..node.section[1])
+                            .ok_or(cmd::Error::InvalidSection)?;
+                        let speaker = old_text
+                            .strip_prefix(TOKEN_SEP)
+                            .and_then(|t| t.split_once(TOKEN_SEP))
+                            .ok_or(cmd::Error::NodeParse)?
+                            .0;
+                        let new_text = format!("{}{}{}{}", TOKEN_SEP, speaker, TOKEN_SEP, text);
+                        validate_node(&new_text, &censored.name_table)?;
+                        let section = push_section(&mut censored.text, &new_text);
+                        censored.tree.get_node_mut(index)?.section = section;
+                    }
+                }
+                Censor::Strip(fallback) => {
+                    let mut fallback = *fallback;
+                    anyhow::ensure!(
+                        !data.tree.get_node(fallback)?.flags.intersects(flags),
+                        cmd::Error::CensoredFallback(fallback)
+                    );
+                    // remove from the highest index down, so the node swapped into a removed
+                    // index is never one that is still waiting to be removed
+                    flagged.sort_unstable_by(|a, b| b.cmp(a));
+                    for index in flagged {
+                        trace!("remove the choices of node {}", index);
+                        while let Some(edge) = censored.tree.outgoing_from_index(index)?.next() {
+                            censored.tree.remove_edge(edge)?;
+                        }
+
+                        trace!("reroute edges to node {} to the fallback", index);
+                        let incoming: Vec<EdgeIndex> = (0..censored.tree.edges().len())
+                            .filter(|e| censored.tree.target_of(*e).ok() == Some(index))
+                            .collect();
+                        for edge in incoming {
+                            let source = censored.tree.source_of(edge)?;
+                            let placement = censored.tree.placement_of(source, edge)?;
+                            let removed = censored.tree.remove_edge(edge)?;
+                            censored.tree.insert_edge(
+                                source,
+                                fallback,
+                                removed.edge,
+                                edge,
+                                placement,
+                            )?;
+                        }
+                        for interrupt in censored.interrupts.iter_mut() {
+                            if interrupt.target == index {
+                                interrupt.target = fallback;
+                            }
+                        }
+                        for bookmark in censored.bookmarks.values_mut() {
+                            if *bookmark == index {
+                                *bookmark = fallback;
+                            }
+                        }
+                        censored.directions.remove(&index);
+                        censored.on_enter.remove(&index);
+
+                        let swapped_index = censored.tree.nodes().len() - 1;
+                        censored.remove_node(index)?;
+                        if fallback == swapped_index {
+                            fallback = index;
+                        }
+                    }
+                }
+            }
+
+            trace!("rebuild the text buffer to drop censored text");
+            let mut text = String::with_capacity(censored.text.len());
+            let mut tree = Tree::with_capacity(0, 0);
+            rebuild_tree(&censored.text, &censored.tree, &mut text, &mut tree)?;
+            censored.text = text;
+            censored.tree = tree;
+            Ok(censored)
+        }
+
+        /// Report of name and val keys that are never referenced by the tree, as well as keys
+        /// that are referenced by the tree but are missing from their table
+        ///
+        /// Missing keys are stored as Strings, since a key referenced in the text may be longer
+        /// than a valid KeyString
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct KeyUsageReport {
+            pub unused_names: Vec<KeyString>,
+            pub unused_vals: Vec<KeyString>,
+            pub missing_names: Vec<String>,
+            pub missing_vals: Vec<String>,
+        }
+
+        impl KeyUsageReport {
+            /// Returns true if there are no unused or missing keys in the report
+            pub fn is_empty(&self) -> bool {
+                self.unused_names.is_empty()
+                    && self.unused_vals.is_empty()
+                    && self.missing_names.is_empty()
+                    && self.missing_vals.is_empty()
+            }
+
+            /// Drop unused keys that come from a shared library. Libraries hold the entries of
+            /// many projects, so most of them are expected to be unused in any one project
+            pub fn ignore_library(&mut self, library: &Library) {
+                self.unused_names
+                    .retain(|key| !library.names.contains_key(key));
+                self.unused_vals
+                    .retain(|key| !library.vals.contains_key(key));
+            }
+        }
+
+        impl std::fmt::Display for KeyUsageReport {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                for key in self.unused_names.iter() {
+                    write!(f, "warning: name {} is never used\r\n", key)?;
+                }
+                for key in self.unused_vals.iter() {
+                    write!(f, "warning: val {} is never used\r\n", key)?;
+                }
+                for key in self.missing_names.iter() {
+                    write!(f, "warning: name {} is used but does not exist\r\n", key)?;
+                }
+                for key in self.missing_vals.iter() {
+                    write!(f, "warning: val {} is used but does not exist\r\n", key)?;
+                }
+                Ok(())
+            }
+        }
+
+        /// A likely authoring mistake in the choices leaving a node
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum ChoiceLint {
+            /// Two choices from the same node have the same text, ignoring case and whitespace
+            Duplicate {
+                node: NodeIndex,
+                first: EdgeIndex,
+                second: EdgeIndex,
+            },
+            /// A node has more choices than the maximum
+            TooMany {
+                node: NodeIndex,
+                count: usize,
+                max: usize,
+            },
+        }
+
+        impl std::fmt::Display for ChoiceLint {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    ChoiceLint::Duplicate {
+                        node,
+                        first,
+                        second,
+                    } => write!(
+                        f,
+                        "warning: edges {} and {} from node {} have the same text",
+                        first, second, node
+                    ),
+                    ChoiceLint::TooMany { node, count, max } => write!(
+                        f,
+                        "warning: node {} has {} choices, more than the maximum of {}",
+                        node, count, max
+                    ),
+                }
+            }
+        }
+
+        /// Find nodes whose choices are likely authoring mistakes: choices with the same text
+        /// after names are substituted, ignoring case and whitespace, and nodes with more than
+        /// max_choices choices. Lints are returned in node order
+        pub fn lint_choices(
+            data: &DialogueTreeData,
+            max_choices: usize,
+        ) -> Result<Vec<ChoiceLint>> {
+            let mut renderer = TextRenderer::default();
+            let mut seen: HashMap<String, EdgeIndex> = HashMap::new();
+            let mut lints = Vec::new();
+            for node in 0..data.tree.nodes().len() {
+                seen.clear();
+                let mut count = 0;
+                for edge in data.tree.outgoing_from_index(node)? {
+                    count += 1;
+                    let text = renderer.edge(data, &data.name_table, edge)?;
+                    let normalized = text
+                        .split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                        .to_lowercase();
+                    match seen.get(&normalized) {
+                        Some(first) => lints.push(ChoiceLint::Duplicate {
+                            node,
+                            first: *first,
+                            second: edge,
+                        }),
+                        None => {
+                            seen.insert(normalized, edge);
+                        }
+                    }
+                }
+                if count > max_choices {
+                    lints.push(ChoiceLint::TooMany {
+                        node,
+                        count,
+                        max: max_choices,
+                    });
+                }
+            }
+            Ok(lints)
+        }
+
+        /// A break of the project's naming rules, see NamingRules
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum NameLint {
+            /// A key does not follow the required casing
+            Casing {
+                kind: KeyKind,
+                key: KeyString,
+                casing: KeyCasing,
+            },
+            /// A node is spoken by a key without the speaker prefix. Reported once per key, at
+            /// the first node that uses it
+            SpeakerPrefix {
+                key: KeyString,
+                node: NodeIndex,
+                prefix: String,
+            },
+            /// A key is longer than the project limit
+            TooLong {
+                kind: KeyKind,
+                key: KeyString,
+                max: usize,
+            },
+            /// A node is tagged with content flags outside of the allowed vocabulary
+            Tag {
+                node: NodeIndex,
+                flags: ContentFlags,
+            },
+        }
+
+        impl std::fmt::Display for NameLint {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let table = |kind: &KeyKind| match kind {
+                    KeyKind::Name => "name",
+                    KeyKind::Val => "val",
+                };
+                match self {
+                    NameLint::Casing { kind, key, casing } => {
+                        write!(f, "warning: {} {} is not {} case", table(kind), key, casing)
+                    }
+                    NameLint::SpeakerPrefix { key, node, prefix } => write!(
+                        f,
+                        "warning: speaker {} of node {} does not start with {}",
+                        key, node, prefix
+                    ),
+                    NameLint::TooLong { kind, key, max } => write!(
+                        f,
+                        "warning: {} {} is longer than the limit of {} characters",
+                        table(kind),
+                        key,
+                        max
+                    ),
+                    NameLint::Tag { node, flags } => write!(
+                        f,
+                        "warning: node {} is tagged with {}, which is not in the allowed tags",
+                        node, flags
+                    ),
+                }
+            }
+        }
+
+        /// Find breaks of the project's naming rules. Name and val keys are checked for casing
+        /// and length in sorted order, the scope prefix of val keys is not checked for casing.
+        /// Speakers and tags are then checked in node order. The narrator is exempt from every
+        /// rule. Sections that do not point to a valid section of the text are skipped, use
+        /// validate_tree to detect these
+        pub fn lint_names(data: &DialogueTreeData) -> Result<Vec<NameLint>> {
+            let rules = &data.naming;
+            let mut lints = Vec::new();
+
+            let mut keys: Vec<(KeyKind, KeyString)> = data
+                .name_table
+                .keys()
+                .filter(|k| k.as_str() != NARRATOR_KEY)
+                .map(|k| (KeyKind::Name, *k))
+                .chain(data.val_table.keys().map(|k| (KeyKind::Val, *k)))
+                .collect();
+            keys.sort_by_key(|(kind, key)| (*kind == KeyKind::Val, *key));
+            for (kind, key) in keys {
+                let unscoped = match kind {
+                    KeyKind::Name => key.as_str(),
+                    KeyKind::Val => ValScope::unscoped(&key),
+                };
+                if !rules.casing.matches(unscoped) {
+                    lints.push(NameLint::Casing {
+                        kind,
+                        key,
+                        casing: rules.casing,
+                    });
+                }
+                match rules.max_key_len {
+                    Some(max) if key.len() > max => {
+                        lints.push(NameLint::TooLong { kind, key, max })
+                    }
+                    _ => (),
+                }
+            }
+
+            let mut speakers: HashSet<&str> = HashSet::new();
+            for (node_index, node) in data.tree.nodes().iter().enumerate() {
+                if !rules.speaker_prefix.is_empty() {
+                    let speaker = data
+                        .text
+                        .get(node.section[0]..node.section[1])
+                        .and_then(|text| split_tokens(text).nth(1));
+                    match speaker {
+                        Some(key)
+                            if key != NARRATOR_KEY
+                                && !key.starts_with(rules.speaker_prefix.as_str())
+                                && speakers.insert(key) =>
+                        {
+                            lints.push(NameLint::SpeakerPrefix {
+                                key: KeyString::from(key).map_err(|e| e.simplify())?,
+                                node: node_index,
+                                prefix: rules.speaker_prefix.clone(),
+                            })
+                        }
+                        _ => (),
+                    }
+                }
+                if let Some(tags) = rules.tags {
+                    let flags = node.flags.difference(tags);
+                    if !flags.is_empty() {
+                        lints.push(NameLint::Tag {
+                            node: node_index,
+                            flags,
+                        });
+                    }
+                }
+            }
+            Ok(lints)
+        }
+
+        /// Read key-value pairs from a CSV or JSON file, based on the file extension
+        ///
+        /// CSV files contain one key,value pair per line without a header. JSON files contain a
+        /// single object, where values may be strings or numbers. Leading and trailing whitespace
+        /// is trimmed from CSV fields. CSV pairs are returned in the order they appear in the file,
+        /// and JSON pairs are returned sorted by key.
+        pub fn read_table_file(path: &str) -> Result<Vec<(String, String)>> {
+            let extension = std::path::Path::new(path)
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default()
+                .to_lowercase();
+            match extension.as_str() {
+                "csv" => {
+                    let mut reader = csv::ReaderBuilder::new()
+                        .has_headers(false)
+                        .trim(csv::Trim::All)
+                        .from_path(path)?;
+                    let mut entries = Vec::new();
+                    for record in reader.records() {
+                        let record = record?;
+                        match (record.get(0), record.get(1), record.len()) {
+                            (Some(key), Some(value), 2) => {
+                                entries.push((key.to_string(), value.to_string()))
+                            }
+                            _ => {
+                                let line = record.position().map_or(0, |p| p.line());
+                                return Err(cmd::Error::TableParse(line as usize).into());
+                            }
+                        }
+                    }
+                    Ok(entries)
+                }
+                "json" => {
+                    let text = std::fs::read_to_string(path)?;
+                    let object: serde_json::Map<String, serde_json::Value> =
+                        serde_json::from_str(&text)?;
+                    object
+                        .into_iter()
+                        .map(|(key, value)| match value {
+                            serde_json::Value::String(s) => Ok((key, s)),
+                            serde_json::Value::Number(n) => Ok((key, n.to_string())),
+                            _ => Err(cmd::Error::InvalidTableValue(key).into()),
+                        })
+                        .collect()
+                }
+                _ => Err(cmd::Error::UnknownFormat(extension).into()),
+            }
+        }
+
+        /// Resolve the path of an asset into an absolute path
+        ///
+        /// Relative paths are resolved from the asset root, and a relative asset root from the
+        /// directory of the project store, or the working directory for stores that are not on
+        /// the filesystem. Absolute asset roots and paths are used as is.
+        pub fn resolve_asset(
+            store_root: Option<&std::path::Path>,
+            asset_root: &str,
+            path: &str,
+        ) -> std::path::PathBuf {
+            let mut resolved = std::env::current_dir().unwrap_or_default();
+            // pushing an absolute path replaces the path so far
+            if let Some(root) = store_root {
+                resolved.push(root);
+            }
+            for part in [asset_root, path].iter().filter(|p| !p.is_empty()) {
+                resolved.push(part);
+            }
+            resolved
+        }
+
+        /// Find the assets of a project whose files do not exist, sorted by key, along with the
+        /// path they were resolved to
+        pub fn missing_assets(
+            data: &DialogueTreeData,
+            store_root: Option<&std::path::Path>,
+        ) -> Vec<(KeyString, std::path::PathBuf)> {
+            let mut missing: Vec<_> = data
+                .assets
+                .iter()
+                .map(|(key, path)| (*key, resolve_asset(store_root, &data.asset_root, path)))
+                .filter(|(_, path)| !path.exists())
+                .collect();
+            missing.sort_unstable();
+            missing
+        }
+
+        /// A problem with one of the shared libraries used by a project
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum LibraryProblem {
+            /// The library file is missing or could not be parsed
+            Unreadable(String, String),
+            /// The library has a different name for a key than the project
+            NameConflict(String, KeyString, NameString, NameString),
+            /// The library has a different value for a key than the project
+            ValConflict(String, KeyString, u32, u32),
+        }
+
+        impl std::fmt::Display for LibraryProblem {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    LibraryProblem::Unreadable(library, e) => {
+                        write!(f, "library {} could not be read: {}", library, e)
+                    }
+                    LibraryProblem::NameConflict(library, key, shared, name) => write!(
+                        f,
+                        "library {} has name {} as {}, but the project has {}",
+                        library, key, shared, name
+                    ),
+                    LibraryProblem::ValConflict(library, key, shared, value) => write!(
+                        f,
+                        "library {} has val {} as {}, but the project has {}",
+                        library, key, shared, value
+                    ),
+                }
+            }
+        }
+
+        /// Read and combine the shared libraries used by a project. Where libraries have the same
+        /// key, the entry of the earlier library is kept. Libraries that cannot be read are
+        /// skipped, see library_problems
+        pub fn shared_tables(data: &DialogueTreeData, store: &dyn store::ProjectStore) -> Library {
+            let mut shared = Library::default();
+            for name in data.libraries.iter() {
+                if let Ok(library) = store::read_library(store, name) {
+                    for (key, name) in library.names {
+                        shared.names.entry(key).or_insert(name);
+                    }
+                    for (key, value) in library.vals {
+                        shared.vals.entry(key).or_insert(value);
+                    }
+                }
+            }
+            shared
+        }
+
+        /// Merge the entries of the shared libraries used by a project into its name and val
+        /// tables. Keys the project already has keep their entry in the project, conflicting
+        /// entries are reported by library_problems. Returns the number of entries merged
+        pub fn merge_libraries(
+            data: &mut DialogueTreeData,
+            store: &dyn store::ProjectStore,
+        ) -> usize {
+            let shared = shared_tables(data, store);
+            let mut merged = 0;
+            for (key, name) in shared.names {
+                if let Entry::Vacant(e) = data.name_table.entry(key) {
+                    e.insert(name);
+                    merged += 1;
+                }
+            }
+            for (key, value) in shared.vals {
+                if let Entry::Vacant(e) = data.val_table.entry(key) {
+                    e.insert(value);
+                    merged += 1;
+                }
+            }
+            merged
+        }
+
+        /// Copy a project without the name and val entries that match its shared libraries, so
+        /// that the libraries stay the only copy of those entries. Entries the project changed
+        /// are kept, and reported as conflicts by library_problems
+        pub fn strip_libraries(
+            data: &DialogueTreeData,
+            store: &dyn store::ProjectStore,
+        ) -> DialogueTreeData {
+            let shared = shared_tables(data, store);
+            let mut stripped = data.clone();
+            stripped
+                .name_table
+                .retain(|key, name| shared.names.get(key) != Some(name));
+            stripped
+                .val_table
+                .retain(|key, value| shared.vals.get(key) != Some(value));
+            stripped
+        }
+
+        /// Find shared libraries that cannot be read, and library entries that conflict with the
+        /// project's name and val tables. Each library is compared with the project on its own,
+        /// so two libraries that disagree are reported as well. Conflicts are sorted by library
+        /// and key
+        pub fn library_problems(
+            data: &DialogueTreeData,
+            store: &dyn store::ProjectStore,
+        ) -> Vec<LibraryProblem> {
+            let mut problems = Vec::new();
+            for name in data.libraries.iter() {
+                let library = match store::read_library(store, name) {
+                    Ok(library) => library,
+                    Err(e) => {
+                        problems.push(LibraryProblem::Unreadable(name.clone(), e.to_string()));
+                        continue;
+                    }
+                };
+                let mut conflicts = Vec::new();
+                for (key, shared) in library.names.iter() {
+                    match data.name_table.get(key) {
+                        Some(own) if own != shared => conflicts.push(LibraryProblem::NameConflict(
+                            name.clone(),
+                            *key,
+                            *shared,
+                            *own,
+                        )),
+                        _ => {}
+                    }
+                }
+                for (key, shared) in library.vals.iter() {
+                    match data.val_table.get(key) {
+                        Some(own) if own != shared => conflicts.push(LibraryProblem::ValConflict(
+                            name.clone(),
+                            *key,
+                            *shared,
+                            *own,
+                        )),
+                        _ => {}
+                    }
+                }
+                conflicts.sort_unstable_by_key(|problem| match problem {
+                    LibraryProblem::NameConflict(_, key, _, _) => (0, *key),
+                    LibraryProblem::ValConflict(_, key, _, _) => (1, *key),
+                    LibraryProblem::Unreadable(_, _) => (2, KeyString::new()),
+                });
+                problems.extend(conflicts);
+            }
+            problems
+        }
+
+        /// Analyze key usage like key_usage, leaving out unused keys that come from the
+        /// project's shared libraries
+        pub fn project_key_usage(
+            data: &DialogueTreeData,
+            store: &dyn store::ProjectStore,
+        ) -> KeyUsageReport {
+            let mut report = key_usage(data);
+            if !data.libraries.is_empty() {
+                report.ignore_library(&shared_tables(data, store));
+            }
+            report
+        }
+
+        /// Split the path to a tree file into the directory containing it and the project name
+        ///
+        /// Only files with the tree extension can be opened this way
+        pub fn split_tree_path(path: &std::path::Path) -> Result<(std::path::PathBuf, String)> {
+            let extension = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default();
+            if format!(".{}", extension) != TREE_EXT {
+                return Err(cmd::Error::UnknownFormat(extension.to_string()).into());
+            }
+            let name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or_else(|| cmd::Error::UnknownFormat(extension.to_string()))?;
+            let dir = path
+                .parent()
+                .filter(|p| !p.as_os_str().is_empty())
+                .unwrap_or_else(|| std::path::Path::new("."));
+            Ok((dir.to_path_buf(), name.to_string()))
+        }
+
+        /// Load a tree file from anywhere on disk into a new editor state. The state saves back
+        /// to the directory the file was opened from
+        pub fn open_tree_file(path: &std::path::Path) -> Result<EditorState> {
+            let (dir, name) = split_tree_path(path)?;
+            let store = std::sync::Arc::new(store::FileStore::new(dir));
+            let mut state = EditorState::with_store(DialogueTreeData::default(), store);
+            cmd::Load::new(name).execute(&mut state)?;
+            Ok(state)
+        }
+
+        /// Round a position to the nearest point on a grid with the given spacing
+        pub fn snap(pos: Position, grid: f32) -> Position {
+            Position::new((pos.x / grid).round() * grid, (pos.y / grid).round() * grid)
+        }
+
+        /// Copy a set of nodes, and the edges between them, into a new project. Nodes keep their
+        /// order and are renumbered from 0, and their text is copied into a compact text buffer.
+        /// The name, val, and asset tables and the choice policy are copied whole, along with the
+        /// directions, on-enter effects, positions, and bookmarks of the copied nodes. Interrupts
+        /// are left out, since they may lead to nodes that were not copied. Nodes and edges keep
+        /// their line ids, and the new project continues from the last line id of the old one so
+        /// that ids are not reused
+        ///
+        /// # Errors
+        ///
+        /// Error if any node index is invalid, or the text of a copied node or edge is corrupted
+        pub fn extract(
+            data: &DialogueTreeData,
+            node_indices: &[NodeIndex],
+            name: &str,
+        ) -> Result<DialogueTreeData> {
+            let mut nodes = node_indices.to_vec();
+            nodes.sort_unstable();
+            nodes.dedup();
+
+            let mut new_data = DialogueTreeData::new(name);
+            new_data.next_line = data.next_line;
+            new_data.choice_policy = data.choice_policy;
+            new_data.presets = data.presets.clone();
+            new_data.naming = data.naming.clone();
+            new_data.name_table = data.name_table.clone();
+            new_data.val_table = data.val_table.clone();
+            new_data.asset_root = data.asset_root.clone();
+            new_data.assets = data.assets.clone();
+
+            let mut copy_text = |section: Section| -> Result<Section> {
+                let slice = data
+                    .text
+                    .get(section[0]..section[1])
+                    .ok_or(cmd::Error::InvalidSection)?;
+                let start = new_data.text.len();
+                new_data.text.push_str(slice);
+                Ok(Section::new([start, new_data.text.len()], section.hash))
+            };
+            let mut sections = Vec::with_capacity(nodes.len());
+            for &node_index in nodes.iter() {
+                sections.push(copy_text(data.tree.get_node(node_index)?.section)?);
+            }
+            let mut edges = Vec::new();
+            for edge_index in 0..data.tree.edges().len() {
+                let source = nodes.binary_search(&data.tree.source_of(edge_index)?);
+                let target = nodes.binary_search(&data.tree.target_of(edge_index)?);
+                if let (Ok(source), Ok(target)) = (source, target) {
+                    let choice = data.tree.get_edge(edge_index)?;
+                    edges.push((source, target, copy_text(choice.section)?, choice.clone()));
+                }
+            }
+
+            for (&node_index, section) in nodes.iter().zip(sections) {
+                let node = Dialogue {
+                    section,
+                    ..*data.tree.get_node(node_index)?
+                };
+                new_data.tree.add_node(node)?;
+            }
+            for (source, target, section, choice) in edges {
+                new_data
+                    .tree
+                    .add_edge(source, target, Choice { section, ..choice })?;
+            }
+            for (new_index, node_index) in nodes.iter().enumerate() {
+                if let Some(directions) = data.directions.get(node_index) {
+                    new_data.directions.insert(new_index, directions.clone());
+                }
+                if let Some(effects) = data.on_enter.get(node_index) {
+                    new_data.on_enter.insert(new_index, effects.clone());
+                }
+                if let Some(pos) = data.placement(*node_index) {
+                    new_data.set_position(new_index, Some(pos))?;
+                }
+            }
+            for (key, node_index) in data.bookmarks.iter() {
+                if let Ok(new_index) = nodes.binary_search(node_index) {
+                    new_data.bookmarks.insert(*key, new_index);
+                }
+            }
+            Ok(new_data)
+        }
+
+        /// Append a copy of a whole project to another, such as a set of nodes copied with
+        /// extract. Nodes and edges are added after the existing ones and keep their order. Name
+        /// and val keys missing from the target are copied, keys the target already has keep
+        /// their entry in the target. Directions, on-enter effects, and positions of the nodes are
+        /// copied, while bookmarks, interrupts, and assets are not. The copies are new lines in the
+        /// target, so they are given new line ids. Returns the events that were applied, in order
+        ///
+        /// # Errors
+        ///
+        /// Error if the text of a node or edge is corrupted, no changes are made in this case
+        pub fn graft(
+            target: &mut DialogueTreeData,
+            source: &DialogueTreeData,
+        ) -> Result<Vec<DialogueTreeEvent>> {
+            let text_of = |section: Section| {
+                source
+                    .text
+                    .get(section[0]..section[1])
+                    .ok_or(cmd::Error::InvalidSection)
+            };
+            for node in source.tree.nodes().iter() {
+                text_of(node.section)?;
+            }
+            for edge in source.tree.edges().iter() {
+                text_of(edge.section)?;
+            }
+
+            let mut events: Vec<DialogueTreeEvent> = Vec::new();
+            for (key, name) in source.name_table.iter() {
+                if !target.name_table.contains_key(key) {
+                    target.name_table.insert(*key, *name);
+                    events.push(
+                        NameTableInsert {
+                            key: *key,
+                            name: *name,
+                        }
+                        .into(),
+                    );
+                }
+            }
+            for (key, value) in source.val_table.iter() {
+                if !target.val_table.contains_key(key) {
+                    target.val_table.insert(*key, *value);
+                    events.push(
+                        ValTableInsert {
+                            key: *key,
+                            value: *value,
+                        }
+                        .into(),
+                    );
+                }
+            }
+
+            let copy_text = |text: &mut String, section: Section| -> Result<Section> {
+                let start = text.len();
+                text.push_str(text_of(section)?);
+                Ok(Section::new([start, text.len()], section.hash))
+            };
+            let offset = target.tree.nodes().len();
+            for node in source.tree.nodes().iter() {
+                let section = copy_text(&mut target.text, node.section)?;
+                let line = target.new_line();
+                let event = target.tree.add_node(Dialogue {
+                    section,
+                    line,
+                    ..*node
+                })?;
+                events.push(event.into());
+            }
+            for (edge_index, edge) in source.tree.edges().iter().enumerate() {
+                let section = copy_text(&mut target.text, edge.section)?;
+                let line = target.new_line();
+                let event = target.tree.add_edge(
+                    offset + source.tree.source_of(edge_index)?,
+                    offset + source.tree.target_of(edge_index)?,
+                    Choice {
+                        section,
+                        line,
+                        ..edge.clone()
+                    },
+                )?;
+                events.push(event.into());
+            }
+            for (node_index, directions) in source.directions.iter() {
+                let event = target.set_directions(offset + node_index, directions.clone())?;
+                events.push(event.into());
+            }
+            for (node_index, effects) in source.on_enter.iter() {
+                let event = target.set_on_enter(offset + node_index, effects.clone())?;
+                events.push(event.into());
+            }
+            for node_index in 0..source.tree.nodes().len() {
+                if let Some(pos) = source.placement(node_index) {
+                    let event = target.set_position(offset + node_index, Some(pos))?;
+                    events.push(event.into());
+                }
+            }
+            Ok(events)
+        }
+
+        /// Get the key referenced by a requirement, and the table it belongs to
+        pub fn requirement_key(req: &ReqKind) -> Option<(KeyKind, &KeyString)> {
+            // this match will stop compiling any time a new ReqKind is added
+            match req {
+                ReqKind::No => None,
+                ReqKind::Greater(key, _) => Some((KeyKind::Val, key)),
+                ReqKind::Less(key, _) => Some((KeyKind::Val, key)),
+                ReqKind::Equal(key, _) => Some((KeyKind::Val, key)),
+                ReqKind::Cmp(key, _) => Some((KeyKind::Name, key)),
+                ReqKind::NotCmp(key, _) => Some((KeyKind::Name, key)),
+                ReqKind::OneOf(key, _) => Some((KeyKind::Name, key)),
+            }
+        }
+
+        /// Get the key referenced by an effect, and the table it belongs to
+        pub fn effect_key(effect: &EffectKind) -> Option<(KeyKind, &KeyString)> {
+            // this match will stop compiling any time a new EffectKind is added
+            match effect {
+                EffectKind::No => None,
+                EffectKind::Add(key, _) => Some((KeyKind::Val, key)),
+                EffectKind::Sub(key, _) => Some((KeyKind::Val, key)),
+                EffectKind::Set(key, _) => Some((KeyKind::Val, key)),
+                EffectKind::Assign(key, _) => Some((KeyKind::Name, key)),
+                EffectKind::Prompt(key) => Some((KeyKind::Name, key)),
+            }
+        }
+
+        /// A place in a dialogue tree where a key is referenced
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        pub enum Reference {
+            /// The key is the speaker of a node
+            Speaker(NodeIndex),
+            /// The key is substituted into the text of a node
+            NodeText(NodeIndex),
+            /// The key is substituted into the text of an edge
+            EdgeText(EdgeIndex),
+            /// The key is tested by the requirement of an edge
+            Requirement(EdgeIndex),
+            /// The key is modified by the effect of an edge
+            Effect(EdgeIndex),
+            /// The key is the character of a direction on a node
+            Direction(NodeIndex),
+            /// The key is modified by an on-enter effect of a node
+            OnEnter(NodeIndex),
+            /// The key is tested by the requirement of an interrupt, by position
+            Interrupt(usize),
+        }
+
+        impl Reference {
+            /// The node holding the reference, if it is in a node
+            pub fn node(&self) -> Option<NodeIndex> {
+                match *self {
+                    Reference::Speaker(i)
+                    | Reference::NodeText(i)
+                    | Reference::Direction(i)
+                    | Reference::OnEnter(i) => Some(i),
+                    _ => None,
+                }
+            }
+
+            /// The edge holding the reference, if it is in an edge
+            pub fn edge(&self) -> Option<EdgeIndex> {
+                match *self {
+                    Reference::EdgeText(i) | Reference::Requirement(i) | Reference::Effect(i) => {
+                        Some(i)
+                    }
+                    _ => None,
+                }
+            }
+        }
+
+        impl std::fmt::Display for Reference {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                    Reference::Speaker(i) => write!(f, "speaker of node {}", i),
+                    Reference::NodeText(i) => write!(f, "text of node {}", i),
+                    Reference::EdgeText(i) => write!(f, "text of edge {}", i),
+                    Reference::Requirement(i) => write!(f, "requirement of edge {}", i),
+                    Reference::Effect(i) => write!(f, "effect of edge {}", i),
+                    Reference::Direction(i) => write!(f, "directions of node {}", i),
+                    Reference::OnEnter(i) => write!(f, "on-enter effects of node {}", i),
+                    Reference::Interrupt(i) => write!(f, "interrupt {}", i),
+                }
+            }
+        }
+
+        /// Find every reference to a key in the node text, edge text, requirements, effects,
+        /// directions, on-enter effects, and interrupts of a dialogue tree, in index order. Names
+        /// may be referenced anywhere, while vals may only be referenced in requirements and
+        /// effects. Sections that do not point to a valid
+        /// section of the text are skipped, use validate_tree to detect these.
+        pub fn key_references(data: &DialogueTreeData, kind: KeyKind, key: &str) -> Vec<Reference> {
+            let mut references = Vec::new();
+            let in_text = |text: &str, skip: usize| {
+                split_tokens(text)
+                    .enumerate()
+                    .skip(skip)
+                    .any(|(i, k)| (i & 0x1) == 1 && k == key)
+            };
+            let is_key = |found: Option<(KeyKind, &KeyString)>| matches!(found, Some((k, found_key)) if k == kind && found_key.as_str() == key);
+
+            if kind == KeyKind::Name {
+                for (index, node) in data.tree.nodes().iter().enumerate() {
+                    if let Some(slice) = data.text.get(node.section[0]..node.section[1]) {
+                        if split_tokens(slice).nth(1) == Some(key) {
+                            references.push(Reference::Speaker(index));
+                        }
+                        // skip the speaker, it is handled above
+                        if in_text(slice, 2) {
+                            references.push(Reference::NodeText(index));
+                        }
+                    }
+                }
+            }
+
+            for (index, edge) in data.tree.edges().iter().enumerate() {
+                if kind == KeyKind::Name {
+                    if let Some(slice) = data.text.get(edge.section[0]..edge.section[1]) {
+                        if in_text(slice, 0) {
+                            references.push(Reference::EdgeText(index));
+                        }
+                    }
+                }
+                if is_key(requirement_key(&edge.requirement)) {
+                    references.push(Reference::Requirement(index));
+                }
+                if is_key(effect_key(&edge.effect)) {
+                    references.push(Reference::Effect(index));
+                }
+            }
+
+            if kind == KeyKind::Name {
+                let mut directed: Vec<NodeIndex> = data
+                    .directions
+                    .iter()
+                    .filter(|(_, directions)| {
+                        directions
+                            .iter()
+                            .any(|d| d.character().map(KeyString::as_str) == Some(key))
+                    })
+                    .map(|(&index, _)| index)
+                    .collect();
+                directed.sort_unstable();
+                references.extend(directed.into_iter().map(Reference::Direction));
+            }
+
+            let mut entered: Vec<NodeIndex> = data
+                .on_enter
+                .iter()
+                .filter(|(_, effects)| effects.iter().any(|e| is_key(effect_key(e))))
+                .map(|(&index, _)| index)
+                .collect();
+            entered.sort_unstable();
+            references.extend(entered.into_iter().map(Reference::OnEnter));
+
+            for (position, interrupt) in data.interrupts.iter().enumerate() {
+                if is_key(requirement_key(&interrupt.requirement)) {
+                    references.push(Reference::Interrupt(position));
+                }
+            }
+            references
+        }
+
+        /// Collect the name keys referenced in a section of node or edge text
+        ///
+        /// Both node and edge text place keys at the odd tokens when split on TOKEN_SEP. For
+        /// nodes, the first of these is the speaker.
+        fn text_keys<'a>(text: &'a str, keys: &mut HashSet<&'a str>) {
+            split_tokens(text)
+                .enumerate()
+                .filter(|(i, _)| (i & 0x1) == 1)
+                .for_each(|(_, key)| {
+                    keys.insert(key);
+                });
+        }
+
+        /// Analyze how the name and val tables are used throughout a dialogue tree
+        ///
+        /// All node text, edge text, requirements, and effects are scanned for key references.
+        /// Table keys that are never referenced, and referenced keys that are not present in the
+        /// tables, are returned in a sorted report. Sections that do not point to a valid
+        /// section of the text are skipped, use validate_tree to detect these.
+        pub fn key_usage(data: &DialogueTreeData) -> KeyUsageReport {
+            let mut used_names: HashSet<&str> = HashSet::new();
+            let mut used_vals: HashSet<&str> = HashSet::new();
+
+            for node in data.tree.nodes() {
+                if let Some(slice) = data.text.get(node.section[0]..node.section[1]) {
+                    text_keys(slice, &mut used_names);
+                }
+            }
+
+            for edge in data.tree.edges() {
+                if let Some(slice) = data.text.get(edge.section[0]..edge.section[1]) {
+                    text_keys(slice, &mut used_names);
+                }
+                for (kind, key) in requirement_key(&edge.requirement)
+                    .into_iter()
+                    .chain(effect_key(&edge.effect))
+                {
+                    match kind {
+                        KeyKind::Name => used_names.insert(key.as_str()),
+                        KeyKind::Val => used_vals.insert(key.as_str()),
+                    };
+                }
+            }
+
+            for direction in data.directions.values().flatten() {
+                if let Some(key) = direction.character() {
+                    used_names.insert(key.as_str());
+                }
+            }
+
+            for effect in data.on_enter.values().flatten() {
+                match effect_key(effect) {
+                    Some((KeyKind::Name, key)) => used_names.insert(key.as_str()),
+                    Some((KeyKind::Val, key)) => used_vals.insert(key.as_str()),
+                    None => false,
+                };
+            }
+
+            for interrupt in data.interrupts.iter() {
+                match requirement_key(&interrupt.requirement) {
+                    Some((KeyKind::Name, key)) => used_names.insert(key.as_str()),
+                    Some((KeyKind::Val, key)) => used_vals.insert(key.as_str()),
+                    None => false,
+                };
+            }
+
+            let mut report = KeyUsageReport {
+                unused_names: data
+                    .name_table
+                    .keys()
+                    .filter(|k| !used_names.contains(k.as_str()))
+                    .copied()
+                    .collect(),
+                unused_vals: data
+                    .val_table
+                    .keys()
+                    .filter(|k| !used_vals.contains(k.as_str()))
+                    .copied()
+                    .collect(),
+                missing_names: used_names
+                    .iter()
+                    .filter(|k| **k != NARRATOR_KEY && !data.name_table.contains_key(**k))
+                    .map(|k| k.to_string())
+                    .collect(),
+                missing_vals: used_vals
+                    .iter()
+                    .filter(|k| !data.val_table.contains_key(**k))
+                    .map(|k| k.to_string())
+                    .collect(),
+            };
+            report.unused_names.sort();
+            report.unused_vals.sort();
+            report.missing_names.sort();
+            report.missing_vals.sort();
+            report
+        }
+
+        /// The name and val keys referenced by a single node or edge
+        #[derive(Debug, Default, Clone, PartialEq)]
+        pub struct ElementKeys<'a> {
+            pub names: HashSet<&'a str>,
+            pub vals: HashSet<&'a str>,
+        }
+
+        impl<'a> ElementKeys<'a> {
+            /// Check if a key of the given kind is referenced
+            pub fn contains(&self, kind: KeyKind, key: &str) -> bool {
+                match kind {
+                    KeyKind::Name => self.names.contains(key),
+                    KeyKind::Val => self.vals.contains(key),
+                }
+            }
+        }
+
+        /// Collect the keys referenced by the speaker and text of a node. An invalid index or
+        /// text section yields no keys
+        pub fn node_keys(data: &DialogueTreeData, index: NodeIndex) -> ElementKeys<'_> {
+            let mut keys = ElementKeys::default();
+            if let Some(slice) = data
+                .tree
+                .nodes()
+                .get(index)
+                .and_then(|node| data.text.get(node.section[0]..node.section[1]))
+            {
+                text_keys(slice, &mut keys.names);
+            }
+            keys
+        }
+
+        /// Collect the keys referenced by the text, requirement, and effect of an edge. An
+        /// invalid index yields no keys
+        pub fn edge_keys(data: &DialogueTreeData, index: EdgeIndex) -> ElementKeys<'_> {
+            let mut keys = ElementKeys::default();
+            if let Some(edge) = data.tree.edges().get(index) {
+                if let Some(slice) = data.text.get(edge.section[0]..edge.section[1]) {
+                    text_keys(slice, &mut keys.names);
+                }
+                for (kind, key) in requirement_key(&edge.requirement)
+                    .into_iter()
+                    .chain(effect_key(&edge.effect))
+                {
+                    match kind {
+                        KeyKind::Name => keys.names.insert(key.as_str()),
+                        KeyKind::Val => keys.vals.insert(key.as_str()),
+                    };
+                }
+            }
+            keys
+        }
+    }
+}
+
+/// Queries select a subset of the nodes or edges in a dialogue tree, so that commands can operate
+/// on many elements at once. Queries are written in a small text format:
+///
+/// ```text
+/// select nodes where speaker == cat and out_degree == 0
+/// select edges where requirement ~ rus_lit or target == 3
+/// ```
+///
+/// A query selects either nodes or edges, optionally filtered by conditions joined with 'and' and
+/// 'or'. 'and' binds more tightly than 'or'. Each condition compares a field of the node or edge
+/// with a value using one of ==, !=, <, <=, >, >=, or ~ (contains). Values containing spaces may be
+/// quoted with ' or ".
+///
+/// Node fields: index, speaker, text, out_degree, in_degree
+///
+/// Edge fields: index, source, target, text, requirement, effect
+///
+/// Text fields are compared after all names are substituted. The speaker field is compared
+/// against the speaker key, and requirements/effects are compared against their display format,
+/// e.g. Less(rus_lit,51).
+pub mod query {
+    use super::*;
+    use tree::{EdgeIndex, NodeIndex};
+
+    /// Kind of element selected by a query
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Target {
+        Nodes,
+        Edges,
+    }
+
+    /// Property of a node or edge that can be tested in a condition
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Field {
+        Index,
+        Speaker,
+        Text,
+        OutDegree,
+        InDegree,
+        Source,
+        Target,
+        Requirement,
+        Effect,
+    }
+
+    impl Field {
+        /// Numeric fields are compared as numbers, all other fields are compared as strings
+        fn is_numeric(self) -> bool {
+            matches!(
+                self,
+                Field::Index | Field::OutDegree | Field::InDegree | Field::Source | Field::Target
+            )
+        }
+
+        fn applies_to(self, target: self::Target) -> bool {
+            match self {
+                Field::Index | Field::Text => true,
+                Field::Speaker | Field::OutDegree | Field::InDegree => {
+                    target == self::Target::Nodes
+                }
+                Field::Source | Field::Target | Field::Requirement | Field::Effect => {
+                    target == self::Target::Edges
+                }
+            }
+        }
+    }
+
+    /// Comparison operator used in a condition
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Op {
+        Eq,
+        Ne,
+        Lt,
+        Le,
+        Gt,
+        Ge,
+        Contains,
+    }
+
+    /// A single comparison between a field and a value
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Condition {
+        pub field: Field,
+        pub op: Op,
+        pub value: String,
+    }
+
+    /// A parsed query. Conditions are stored in disjunctive normal form, i.e. an element is
+    /// selected if all conditions in any one of the clauses match. A query with no clauses
+    /// selects every element
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Query {
+        pub target: Target,
+        pub clauses: Vec<Vec<Condition>>,
+    }
+
+    /// Split a query string into words, quoted strings, and operators
+    fn tokenize(s: &str) -> Result<Vec<String>> {
+        let mut tokens = Vec::new();
+        let mut chars = s.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+            } else if c == '"' || c == '\'' {
+                chars.next();
+                let mut token = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some(ch) => token.push(ch),
+                        None => return Err(cmd::Error::QueryParse("unclosed quote".into()).into()),
+                    }
+                }
+                tokens.push(token);
+            } else if "=!<>~".contains(c) {
+                let mut token = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if !"=!<>~".contains(ch) {
+                        break;
+                    }
+                    token.push(ch);
+                    chars.next();
+                }
+                tokens.push(token);
+            } else {
+                let mut token = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_whitespace() || "=!<>~".contains(ch) {
+                        break;
+                    }
+                    token.push(ch);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+        Ok(tokens)
+    }
+
+    fn parse_err(msg: String) -> anyhow::Error {
+        cmd::Error::QueryParse(msg).into()
+    }
+
+    impl std::str::FromStr for Query {
+        type Err = anyhow::Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            info!("Parsing query from string");
+            let tokens = tokenize(s)?;
+            let mut tokens = tokens.iter().map(String::as_str).peekable();
+
+            trace!("skip optional select keyword");
+            if tokens.peek() == Some(&"select") {
+                tokens.next();
+            }
+
+            trace!("parse query target");
+            let target = match tokens.next() {
+                Some("nodes") | Some("node") => Target::Nodes,
+                Some("edges") | Some("edge") => Target::Edges,
+                t => return Err(parse_err(format!("expected nodes or edges, found {:?}", t))),
+            };
+
+            let mut clauses = Vec::new();
+            match tokens.next() {
+                None => return Ok(Query { target, clauses }),
+                Some("where") => (),
+                Some(t) => return Err(parse_err(format!("expected where, found {}", t))),
+            }
+
+            trace!("parse conditions");
+            let mut clause = Vec::new();
+            loop {
+                let field = match tokens.next() {
+                    Some("index") => Field::Index,
+                    Some("speaker") => Field::Speaker,
+                    Some("text") => Field::Text,
+                    Some("out_degree") => Field::OutDegree,
+                    Some("in_degree") => Field::InDegree,
+                    Some("source") => Field::Source,
+                    Some("target") => Field::Target,
+                    Some("requirement") => Field::Requirement,
+                    Some("effect") => Field::Effect,
+                    t => return Err(parse_err(format!("expected field, found {:?}", t))),
+                };
+                if !field.applies_to(target) {
+                    return Err(parse_err(format!(
+                        "{:?} is not a field of {:?}",
+                        field, target
+                    )));
+                }
+                let op = match tokens.next() {
+                    Some("==") => Op::Eq,
+                    Some("!=") => Op::Ne,
+                    Some("<") => Op::Lt,
+                    Some("<=") => Op::Le,
+                    Some(">") => Op::Gt,
+                    Some(">=") => Op::Ge,
+                    Some("~") => Op::Contains,
+                    t => return Err(parse_err(format!("expected operator, found {:?}", t))),
+                };
+                let value = tokens
+                    .next()
+                    .ok_or_else(|| parse_err("expected value".into()))?;
+                if field.is_numeric() {
+                    if op == Op::Contains {
+                        return Err(parse_err(format!("~ cannot be used with {:?}", field)));
+                    }
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| parse_err(format!("{:?} must be a number", field)))?;
+                } else if !matches!(op, Op::Eq | Op::Ne | Op::Contains) {
+                    return Err(parse_err(format!(
+                        "{:?} can only be compared with ==, != or ~",
+                        field
+                    )));
+                }
+                clause.push(Condition {
+                    field,
+                    op,
+                    value: value.to_string(),
+                });
+
+                match tokens.next() {
+                    None => break,
+                    Some("and") => (),
+                    Some("or") => clauses.push(std::mem::take(&mut clause)),
+                    Some(t) => return Err(parse_err(format!("expected and/or, found {}", t))),
+                }
+            }
+            clauses.push(clause);
+            Ok(Query { target, clauses })
+        }
+    }
+
+    /// Values of every field for a single node or edge
+    struct Element {
+        index: usize,
+        speaker: String,
+        text: String,
+        out_degree: usize,
+        in_degree: usize,
+        source: usize,
+        target: usize,
+        requirement: String,
+        effect: String,
+    }
+
+    impl Condition {
+        fn matches(&self, element: &Element) -> bool {
+            let num = match self.field {
+                Field::Index => Some(element.index),
+                Field::OutDegree => Some(element.out_degree),
+                Field::InDegree => Some(element.in_degree),
+                Field::Source => Some(element.source),
+                Field::Target => Some(element.target),
+                _ => None,
+            };
+            if let Some(num) = num {
+                // numeric values are checked when the query is parsed
+                let value = self.value.parse::<usize>().unwrap_or_default();
+                return match self.op {
+                    Op::Eq => num == value,
+                    Op::Ne => num != value,
+                    Op::Lt => num < value,
+                    Op::Le => num <= value,
+                    Op::Gt => num > value,
+                    Op::Ge => num >= value,
+                    Op::Contains => false,
+                };
+            }
+
+            let s = match self.field {
+                Field::Speaker => &element.speaker,
+                Field::Text => &element.text,
+                Field::Requirement => &element.requirement,
+                _ => &element.effect,
+            };
+            match self.op {
+                Op::Eq => *s == self.value,
+                Op::Ne => *s != self.value,
+                Op::Contains => s.contains(self.value.as_str()),
+                _ => false,
+            }
+        }
+    }
+
+    impl Query {
+        /// Find the indices of all nodes matched by the query, in index order, for commands that
+        /// only act on nodes
+        ///
+        /// # Errors
+        /// Error if the query selects edges
+        pub fn select_nodes(&self, data: &DialogueTreeData) -> Result<Vec<NodeIndex>> {
+            anyhow::ensure!(
+                self.target == Target::Nodes,
+                cmd::Error::QueryParse("expected a query that selects nodes".to_string())
+            );
+            self.select(data)
+        }
+
+        /// Find the indices of all nodes or edges in the tree matched by the query, in index order
+        pub fn select(&self, data: &DialogueTreeData) -> Result<Vec<usize>> {
+            info!("Select {:?} matching query", self.target);
+            let tree = &data.tree;
+            let mut renderer = cmd::util::TextRenderer::default();
+            let mut selection = Vec::new();
+
+            trace!("count edges in and out of each node");
+            let mut out_degree = vec![0; tree.nodes().len()];
+            let mut in_degree = vec![0; tree.nodes().len()];
+            for edge_index in 0..tree.edges().len() {
+                out_degree[tree.source_of(edge_index)?] += 1;
+                in_degree[tree.target_of(edge_index)?] += 1;
+            }
+
+            trace!("test each element against the query");
+            let count = match self.target {
+                Target::Nodes => tree.nodes().len(),
+                Target::Edges => tree.edges().len(),
+            };
+            for index in 0..count {
+                let element = match self.target {
+                    Target::Nodes => {
+                        let node: NodeIndex = index;
+                        let section = tree.get_node(node)?.section;
+                        let text = &data.text[section[0]..section[1]];
+                        let (_, rendered) = renderer.node(data, &data.name_table, node)?;
+                        Element {
+                            index,
+                            speaker: cmd::util::split_tokens(text)
+                                .nth(1)
+                                .unwrap_or_default()
+                                .to_string(),
+                            text: rendered.to_string(),
+                            out_degree: out_degree[node],
+                            in_degree: in_degree[node],
+                            source: 0,
+                            target: 0,
+                            requirement: String::new(),
+                            effect: String::new(),
+                        }
+                    }
+                    Target::Edges => {
+                        let edge: EdgeIndex = index;
+                        let choice = tree.get_edge(edge)?;
+                        let rendered = renderer.edge(data, &data.name_table, edge)?;
+                        Element {
+                            index,
+                            speaker: String::new(),
+                            text: rendered.to_string(),
+                            out_degree: 0,
+                            in_degree: 0,
+                            source: tree.source_of(edge)?,
+                            target: tree.target_of(edge)?,
+                            requirement: choice.requirement.to_string(),
+                            effect: choice.effect.to_string(),
+                        }
+                    }
+                };
+                if self.clauses.is_empty()
+                    || self
+                        .clauses
+                        .iter()
+                        .any(|clause| clause.iter().all(|c| c.matches(&element)))
+                {
+                    selection.push(index);
+                }
+            }
+            Ok(selection)
+        }
+    }
+}
+
+/// Structural metrics of a dialogue tree, used for pacing analysis and for checking a tree
+/// against structural guidelines
+///
+/// Depth is the fewest choices needed to reach a node from the root node at index 0. Nodes that
+/// cannot be reached from the root have no depth, and are counted separately.
+pub mod analysis {
+    use super::*;
+    use std::collections::{BTreeMap, BTreeSet};
+    use tree::{EdgeIndex, NodeIndex};
+
+    /// Width in characters of the longest bar in a printed histogram
+    const BAR_WIDTH: usize = 40;
+
+    /// Structural metrics of a tree
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    pub struct Metrics {
+        pub nodes: usize,
+        pub edges: usize,
+        /// Number of nodes at each depth, indexed by depth
+        pub depths: Vec<usize>,
+        /// Number of nodes that cannot be reached from the root
+        pub unreachable: usize,
+        /// Number of nodes with each number of outgoing edges, indexed by out-degree
+        pub out_degrees: Vec<usize>,
+        /// Mean number of outgoing edges of the nodes that have any
+        pub branching_factor: f64,
+        /// Number of nodes with more than one incoming edge, where paths through the tree
+        /// converge
+        pub converging: usize,
+    }
+
+    /// Measure the structure of a tree
+    pub fn analyze(tree: &Tree) -> Result<Metrics> {
+        let node_count = tree.nodes().len();
+        let mut metrics = Metrics {
+            nodes: node_count,
+            edges: tree.edges().len(),
+            ..Default::default()
+        };
+
+        trace!("count edges in and out of each node");
+        let mut in_degree = vec![0usize; node_count];
+        for edge_index in 0..tree.edges().len() {
+            in_degree[tree.target_of(edge_index)?] += 1;
+        }
+        let mut branching = 0;
+        for node_index in 0..node_count {
+            let out_degree = tree.outgoing_from_index(node_index)?.count();
+            if metrics.out_degrees.len() <= out_degree {
+                metrics.out_degrees.resize(out_degree + 1, 0);
+            }
+            metrics.out_degrees[out_degree] += 1;
+            if out_degree > 0 {
+                branching += 1;
+            }
+        }
+        if branching > 0 {
+            metrics.branching_factor = metrics.edges as f64 / branching as f64;
+        }
+        metrics.converging = in_degree.iter().filter(|d| **d > 1).count();
+
+        trace!("find the depth of each node with a breadth first search from the root");
+        if node_count == 0 {
+            return Ok(metrics);
+        }
+        let mut depths = vec![None; node_count];
+        let mut queue = VecDeque::new();
+        depths[0] = Some(0);
+        queue.push_back(0);
+        while let Some(node_index) = queue.pop_front() {
+            let depth = depths[node_index].unwrap_or_default();
+            for edge_index in tree.outgoing_from_index(node_index)? {
+                let target = tree.target_of(edge_index)?;
+                if depths[target].is_none() {
+                    depths[target] = Some(depth + 1);
+                    queue.push_back(target);
+                }
+            }
+        }
+        for depth in depths.iter() {
+            match depth {
+                Some(depth) => {
+                    if metrics.depths.len() <= *depth {
+                        metrics.depths.resize(depth + 1, 0);
+                    }
+                    metrics.depths[*depth] += 1;
+                }
+                None => metrics.unreachable += 1,
+            }
+        }
+        Ok(metrics)
+    }
+
+    impl std::fmt::Display for Metrics {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "nodes: {}\r\nedges: {}\r\nbranching factor: {:.2}\r\nconverging nodes: {}\r\n\
+                unreachable nodes: {}\r\n",
+                self.nodes, self.edges, self.branching_factor, self.converging, self.unreachable
+            )?;
+            write!(f, "depth:\r\n{}", histogram(&self.depths))?;
+            write!(f, "out-degree:\r\n{}", histogram(&self.out_degrees))
+        }
+    }
+
+    /// How nodes are collapsed into the states of a state machine, see state_machine
+    #[derive(Debug, Clone, Copy, PartialEq, Default)]
+    pub enum GroupBy {
+        /// Nodes belong to the nearest bookmark they can be reached from. Nodes that no bookmark
+        /// reaches are grouped together
+        #[default]
+        Bookmark,
+        /// Nodes with the same content flags are grouped together
+        Flags,
+        /// Nodes with the same speaker are grouped together
+        Speaker,
+    }
+
+    impl GroupBy {
+        /// Every way of grouping nodes, in the order they are offered
+        pub const ALL: [GroupBy; 3] = [GroupBy::Bookmark, GroupBy::Flags, GroupBy::Speaker];
+    }
+
+    impl std::fmt::Display for GroupBy {
+        /// Format the grouping in the same format that is accepted by from_str
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                GroupBy::Bookmark => write!(f, "bookmark"),
+                GroupBy::Flags => write!(f, "flags"),
+                GroupBy::Speaker => write!(f, "speaker"),
+            }
+        }
+    }
+
+    impl std::str::FromStr for GroupBy {
+        type Err = cmd::Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "bookmark" => Ok(GroupBy::Bookmark),
+                "flags" => Ok(GroupBy::Flags),
+                "speaker" => Ok(GroupBy::Speaker),
+                _ => Err(cmd::Error::UnknownGrouping(s.to_string())),
+            }
+        }
+    }
+
+    /// Effects of a set of choices, as written for the effect option of new edge, by the key
+    /// they change
+    pub type EffectSummary = BTreeMap<String, BTreeSet<String>>;
+
+    /// A group of nodes collapsed into a single state
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    pub struct State {
+        pub id: usize,
+        pub label: String,
+        pub nodes: Vec<NodeIndex>,
+        /// Effects of the choices between nodes of the state, and on-enter effects of its nodes
+        pub effects: EffectSummary,
+    }
+
+    /// All choices leading from the nodes of one state to the nodes of another
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    pub struct Transition {
+        pub from: usize,
+        pub to: usize,
+        pub edges: Vec<EdgeIndex>,
+        /// Requirements of the choices, as written for the requirement option of new edge
+        pub requirements: BTreeSet<String>,
+        pub effects: EffectSummary,
+    }
+
+    /// A tree reduced to states and the transitions between them, giving an overview of which
+    /// keys each part of the tree can change
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    pub struct StateMachine {
+        pub states: Vec<State>,
+        pub transitions: Vec<Transition>,
+    }
+
+    /// Label of the state for nodes that no bookmark reaches
+    pub const UNGROUPED: &str = "ungrouped";
+
+    /// Label every node of a tree with the group it belongs to, see GroupBy. Labels are indexed
+    /// by node
+    pub fn group_labels(data: &DialogueTreeData, by: GroupBy) -> Result<Vec<String>> {
+        let tree = &data.tree;
+        let node_count = tree.nodes().len();
+        let mut labels: Vec<String> = Vec::with_capacity(node_count);
+        match by {
+            GroupBy::Bookmark => {
+                let mut bookmarks: Vec<_> = data.bookmarks.iter().collect();
+                bookmarks.sort_unstable();
+                let mut groups: Vec<Option<&KeyString>> = vec![None; node_count];
+                let mut queue = VecDeque::new();
+                for (key, node_index) in bookmarks {
+                    if *node_index < node_count && groups[*node_index].is_none() {
+                        groups[*node_index] = Some(key);
+                        queue.push_back(*node_index);
+                    }
+                }
+                while let Some(node_index) = queue.pop_front() {
+                    for edge_index in tree.outgoing_from_index(node_index)? {
+                        let target = tree.target_of(edge_index)?;
+                        if groups[target].is_none() {
+                            groups[target] = groups[node_index];
+                            queue.push_back(target);
+                        }
+                    }
+                }
+                labels.extend(
+                    groups
+                        .into_iter()
+                        .map(|key| key.map_or(UNGROUPED, |key| key.as_str()).to_string()),
+                );
+            }
+            GroupBy::Flags => {
+                for node in tree.nodes() {
+                    let names: Vec<_> = node.flags.names().collect();
+                    labels.push(if names.is_empty() {
+                        "none".to_string()
+                    } else {
+                        names.join(",")
+                    });
+                }
+            }
+            GroupBy::Speaker => {
+                for node in tree.nodes() {
+                    let text = data
+                        .text
+                        .get(node.section[0]..node.section[1])
+                        .ok_or(cmd::Error::InvalidSection)?;
+                    let speaker = cmd::util::split_tokens(text).nth(1).unwrap_or_default();
+                    labels.push(speaker.to_string());
+                }
+            }
+        }
+        Ok(labels)
+    }
+
+    /// Collapse the nodes of a tree into states, see GroupBy. States are sorted by label, and
+    /// transitions by the states they connect
+    pub fn state_machine(data: &DialogueTreeData, by: GroupBy) -> Result<StateMachine> {
+        let tree = &data.tree;
+
+        trace!("label every node with its group");
+        let labels = group_labels(data, by)?;
+
+        trace!("create a state for each label");
+        let mut sorted: Vec<&String> = labels.iter().collect();
+        sorted.sort_unstable();
+        sorted.dedup();
+        let mut machine = StateMachine::default();
+        for (id, label) in sorted.iter().enumerate() {
+            machine.states.push(State {
+                id,
+                label: label.to_string(),
+                ..Default::default()
+            });
+        }
+        let state_of: Vec<usize> = labels
+            .iter()
+            .map(|label| sorted.binary_search(&label).unwrap_or_default())
+            .collect();
+        for (node_index, state) in state_of.iter().enumerate() {
+            machine.states[*state].nodes.push(node_index);
+        }
+
+        trace!("collect the choices within and between states");
+        let mut transitions: BTreeMap<(usize, usize), Transition> = BTreeMap::new();
+        for (edge_index, edge) in tree.edges().iter().enumerate() {
+            let from = state_of[tree.source_of(edge_index)?];
+            let to = state_of[tree.target_of(edge_index)?];
+            let effects = if from == to {
+                &mut machine.states[from].effects
+            } else {
+                let transition = transitions.entry((from, to)).or_insert_with(|| Transition {
+                    from,
+                    to,
+                    ..Default::default()
+                });
+                transition.edges.push(edge_index);
+                if edge.requirement != ReqKind::No {
+                    transition.requirements.insert(edge.requirement.to_string());
+                }
+                &mut transition.effects
+            };
+            if let Some((_, key)) = cmd::util::effect_key(&edge.effect) {
+                effects
+                    .entry(key.to_string())
+                    .or_default()
+                    .insert(edge.effect.to_string());
+            }
+        }
+        for (&node_index, effects) in data.on_enter.iter() {
+            let state = match state_of.get(node_index) {
+                Some(&state) => &mut machine.states[state],
+                None => continue,
+            };
+            for effect in effects.iter() {
+                if let Some((_, key)) = cmd::util::effect_key(effect) {
+                    state
+                        .effects
+                        .entry(key.to_string())
+                        .or_default()
+                        .insert(effect.to_string());
+                }
+            }
+        }
+        machine.transitions = transitions.into_values().collect();
+        Ok(machine)
+    }
+
+    impl StateMachine {
+        /// Write the state machine in the graphviz DOT language. States are labelled with their
+        /// node count and the keys their choices change, and transitions with their effects
+        pub fn to_dot(&self) -> String {
+            let quote = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+            let changes = |effects: &EffectSummary| {
+                effects
+                    .values()
+                    .flatten()
+                    .map(|effect| format!("\\n{}", quote(effect)))
+                    .collect::<String>()
+            };
+            let mut dot = String::from("digraph {\n");
+            for state in self.states.iter() {
+                dot.push_str(&format!(
+                    "    s{} [label=\"{} ({} nodes){}\"];\n",
+                    state.id,
+                    quote(&state.label),
+                    state.nodes.len(),
+                    changes(&state.effects)
+                ));
+            }
+            for transition in self.transitions.iter() {
+                dot.push_str(&format!(
+                    "    s{} -> s{} [label=\"{} choices{}\"];\n",
+                    transition.from,
+                    transition.to,
+                    transition.edges.len(),
+                    changes(&transition.effects)
+                ));
+            }
+            dot.push_str("}\n");
+            dot
+        }
+    }
+
+    /// Where a key is modified or tested
+    #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+    pub enum Site {
+        /// The effect or requirement of a choice
+        Edge {
+            edge: EdgeIndex,
+            from: NodeIndex,
+            to: NodeIndex,
+        },
+        /// The requirement of an interrupt, by position
+        Interrupt { position: usize, target: NodeIndex },
+        /// An on-enter effect of a node
+        OnEnter { node: NodeIndex },
+    }
+
+    impl std::fmt::Display for Site {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Site::Edge { edge, from, to } => write!(f, "edge {} ({} -> {})", edge, from, to),
+                Site::Interrupt { position, target } => {
+                    write!(f, "interrupt {} (-> {})", position, target)
+                }
+                Site::OnEnter { node } => write!(f, "on entering node {}", node),
+            }
+        }
+    }
+
+    /// An effect or requirement, as written for new edge, and where it is
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    pub struct Usage {
+        pub site: Site,
+        pub rule: String,
+    }
+
+    /// The values a key can hold while playing through a tree
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    pub enum Bounds {
+        /// Lowest and highest value a val can reach. Vals saturate, so a val that can be added to
+        /// in a loop reaches u32::MAX
+        Val { min: u32, max: u32 },
+        /// Every name a name key can be assigned
+        Name(BTreeSet<String>),
+    }
+
+    /// Everything that reads and writes a single key
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    pub struct Symbol {
+        /// Value in the name or val table, or None if the key is referenced but missing
+        pub initial: Option<String>,
+        pub effects: Vec<Usage>,
+        pub requirements: Vec<Usage>,
+        pub bounds: Bounds,
+    }
+
+    /// Static data flow of every name and val key in a project, by key
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+    pub struct SymbolTable {
+        pub names: BTreeMap<String, Symbol>,
+        pub vals: BTreeMap<String, Symbol>,
+    }
+
+    /// Get the symbol of a key, adding an entry for keys that are referenced but not in a table
+    /// so that they still show up in the report
+    fn symbol<'t>(
+        table: &'t mut SymbolTable,
+        kind: cmd::util::KeyKind,
+        key: &KeyString,
+    ) -> &'t mut Symbol {
+        let (symbols, bounds) = match kind {
+            cmd::util::KeyKind::Name => (&mut table.names, Bounds::Name(BTreeSet::new())),
+            cmd::util::KeyKind::Val => (&mut table.vals, Bounds::Val { min: 0, max: 0 }),
+        };
+        symbols.entry(key.to_string()).or_insert_with(|| Symbol {
+            initial: None,
+            effects: Vec::new(),
+            requirements: Vec::new(),
+            bounds,
+        })
+    }
+
+    /// Build the symbol table of a project from its tables, the effects and requirements of its
+    /// choices, and its interrupts
+    ///
+    /// Bounds only follow the choices that can be reached from the root, or from the target of an
+    /// interrupt, and assume that any choice can be made. Requirements are not taken into account,
+    /// so the bounds may be wider than what a playthrough can actually reach.
+    pub fn symbol_table(data: &DialogueTreeData) -> Result<SymbolTable> {
+        let tree = &data.tree;
+        let mut table = SymbolTable::default();
+        for (key, name) in data.name_table.iter() {
+            table.names.insert(
+                key.to_string(),
+                Symbol {
+                    initial: Some(name.to_string()),
+                    effects: Vec::new(),
+                    requirements: Vec::new(),
+                    bounds: Bounds::Name(std::iter::once(name.to_string()).collect()),
+                },
+            );
+        }
+        for (key, val) in data.val_table.iter() {
+            table.vals.insert(
+                key.to_string(),
+                Symbol {
+                    initial: Some(val.to_string()),
+                    effects: Vec::new(),
+                    requirements: Vec::new(),
+                    bounds: Bounds::Val {
+                        min: *val,
+                        max: *val,
+                    },
+                },
+            );
+        }
+
+        trace!("collect the effects and requirements of every choice, node, and interrupt");
+        for (edge_index, edge) in tree.edges().iter().enumerate() {
+            let site = Site::Edge {
+                edge: edge_index,
+                from: tree.source_of(edge_index)?,
+                to: tree.target_of(edge_index)?,
+            };
+            if let Some((kind, key)) = cmd::util::effect_key(&edge.effect) {
+                symbol(&mut table, kind, key).effects.push(Usage {
+                    site,
+                    rule: edge.effect.to_string(),
+                });
+            }
+            if let Some((kind, key)) = cmd::util::requirement_key(&edge.requirement) {
+                symbol(&mut table, kind, key).requirements.push(Usage {
+                    site,
+                    rule: edge.requirement.to_string(),
+                });
+            }
+        }
+        let mut entered: Vec<_> = data.on_enter.iter().collect();
+        entered.sort_unstable_by_key(|(&node, _)| node);
+        for (&node, effects) in entered {
+            for effect in effects.iter() {
+                if let Some((kind, key)) = cmd::util::effect_key(effect) {
+                    symbol(&mut table, kind, key).effects.push(Usage {
+                        site: Site::OnEnter { node },
+                        rule: effect.to_string(),
+                    });
+                }
+            }
+        }
+        for (position, interrupt) in data.interrupts.iter().enumerate() {
+            if let Some((kind, key)) = cmd::util::requirement_key(&interrupt.requirement) {
+                symbol(&mut table, kind, key).requirements.push(Usage {
+                    site: Site::Interrupt {
+                        position,
+                        target: interrupt.target,
+                    },
+                    rule: interrupt.requirement.to_string(),
+                });
+            }
+        }
+
+        trace!("find the values each key can reach");
+        let reachable = reachable_nodes(data)?;
+        for (key, symbol) in table.names.iter_mut() {
+            if let Bounds::Name(names) = &mut symbol.bounds {
+                for usage in symbol.effects.iter() {
+                    let effects = match usage.site {
+                        Site::Edge { edge, from, .. } if reachable[from] => {
+                            std::slice::from_ref(&tree.get_edge(edge)?.effect)
+                        }
+                        Site::OnEnter { node } if reachable[node] => {
+                            cmd::util::on_enter_of(data, node)?
+                        }
+                        _ => &[],
+                    };
+                    for effect in effects.iter() {
+                        match effect {
+                            EffectKind::Assign(k, name) if k.as_str() == key.as_str() => {
+                                names.insert(name.to_string());
+                            }
+                            EffectKind::Prompt(k) if k.as_str() == key.as_str() => {
+                                names.insert(EffectKind::PLAYER_INPUT.to_string());
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+        for (key, symbol) in table.vals.iter_mut() {
+            if let Bounds::Val { min, max } = symbol.bounds {
+                let (min, max) = val_bounds(data, key, min, max)?;
+                symbol.bounds = Bounds::Val { min, max };
+            }
+        }
+        Ok(table)
+    }
+
+    /// Find the nodes that can be reached from the root, or from the target of an interrupt
+    fn reachable_nodes(data: &DialogueTreeData) -> Result<Vec<bool>> {
+        let tree = &data.tree;
+        let mut reachable = vec![false; tree.nodes().len()];
+        let mut stack: Vec<NodeIndex> = data.interrupts.iter().map(|i| i.target).collect();
+        if !reachable.is_empty() {
+            stack.push(0);
+        }
+        while let Some(node_index) = stack.pop() {
+            match reachable.get_mut(node_index) {
+                Some(seen) if !*seen => *seen = true,
+                _ => continue,
+            }
+            for edge_index in tree.outgoing_from_index(node_index)? {
+                stack.push(tree.target_of(edge_index)?);
+            }
+        }
+        Ok(reachable)
+    }
+
+    /// Find the lowest and highest value a val can hold at any node, starting from the initial
+    /// bounds at the root
+    ///
+    /// The bounds at each node are widened by each choice leading to it until nothing changes.
+    /// Bounds are kept as the node is arrived at, and its on-enter effects are applied on top.
+    /// Loops that keep moving a bound would never settle, so after as many passes as there are
+    /// nodes, any bound that still moves jumps straight to 0 or u32::MAX.
+    fn val_bounds(data: &DialogueTreeData, key: &str, min: u32, max: u32) -> Result<(u32, u32)> {
+        let tree = &data.tree;
+        let node_count = tree.nodes().len();
+        if node_count == 0 {
+            return Ok((min, max));
+        }
+        let apply = |effect: &EffectKind, (low, high): (u32, u32)| match effect {
+            EffectKind::Add(k, n) if k.as_str() == key => {
+                (low.saturating_add(*n), high.saturating_add(*n))
+            }
+            EffectKind::Sub(k, n) if k.as_str() == key => {
+                (low.saturating_sub(*n), high.saturating_sub(*n))
+            }
+            EffectKind::Set(k, n) if k.as_str() == key => (*n, *n),
+            _ => (low, high),
+        };
+        let join = |a: Option<(u32, u32)>, (low, high): (u32, u32)| match a {
+            Some((a_low, a_high)) => (a_low.min(low), a_high.max(high)),
+            None => (low, high),
+        };
+        let enter = |node: NodeIndex, bounds: (u32, u32)| {
+            cmd::util::on_enter_of(data, node)
+                .unwrap_or(&[])
+                .iter()
+                .fold(bounds, |bounds, effect| apply(effect, bounds))
+        };
+
+        let mut bounds: Vec<Option<(u32, u32)>> = vec![None; node_count];
+        bounds[0] = Some((min, max));
+        for pass in 0.. {
+            let widen = pass >= node_count;
+            let mut changed = false;
+            let mut update = |bounds: &mut Vec<Option<(u32, u32)>>, target: NodeIndex, new| {
+                let old = match bounds.get(target) {
+                    Some(old) => *old,
+                    None => return,
+                };
+                let mut joined = join(old, new);
+                if Some(joined) == old {
+                    return;
+                }
+                if let (true, Some((old_low, old_high))) = (widen, old) {
+                    if joined.0 < old_low {
+                        joined.0 = 0;
+                    }
+                    if joined.1 > old_high {
+                        joined.1 = u32::MAX;
+                    }
+                }
+                bounds[target] = Some(joined);
+                changed = true;
+            };
+            for (edge_index, edge) in tree.edges().iter().enumerate() {
+                let source_index = tree.source_of(edge_index)?;
+                if let Some(source) = bounds[source_index] {
+                    update(
+                        &mut bounds,
+                        tree.target_of(edge_index)?,
+                        apply(&edge.effect, enter(source_index, source)),
+                    );
+                }
+            }
+            // an interrupt can fire from any node, carrying whatever value the key has there
+            let anywhere = bounds
+                .iter()
+                .enumerate()
+                .filter_map(|(node, b)| b.map(|b| enter(node, b)))
+                .fold(None, |a, b| Some(join(a, b)));
+            if let Some(anywhere) = anywhere {
+                for interrupt in data.interrupts.iter() {
+                    update(&mut bounds, interrupt.target, anywhere);
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        Ok(bounds
+            .iter()
+            .enumerate()
+            .filter_map(|(node, b)| b.map(|b| join(Some(b), enter(node, b))))
+            .fold((min, max), |a, b| join(Some(a), b)))
+    }
+
+    impl std::fmt::Display for SymbolTable {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            for (label, symbols) in [("name", &self.names), ("val", &self.vals)] {
+                for (key, symbol) in symbols.iter() {
+                    let initial = symbol.initial.as_deref().unwrap_or("missing");
+                    write!(f, "{} {} = {}", label, key, initial)?;
+                    match &symbol.bounds {
+                        Bounds::Val { min, max } => write!(f, ", range {}..={}", min, max)?,
+                        Bounds::Name(names) => write!(
+                            f,
+                            ", values {}",
+                            names.iter().cloned().collect::<Vec<_>>().join(" | ")
+                        )?,
+                    }
+                    write!(f, "\r\n")?;
+                    for usage in symbol.effects.iter() {
+                        write!(f, "  set by {} at {}\r\n", usage.rule, usage.site)?;
+                    }
+                    for usage in symbol.requirements.iter() {
+                        write!(f, "  read by {} at {}\r\n", usage.rule, usage.site)?;
+                    }
+                }
+            }
+            Ok(())
         }
     }
+
+    /// Format counts as rows of bars scaled to the largest count, labelled with their index
+    fn histogram(counts: &[usize]) -> String {
+        let max = counts.iter().copied().max().unwrap_or_default().max(1);
+        let label_width = counts.len().saturating_sub(1).to_string().len();
+        counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                // any nonzero count shows at least one character
+                let bar = match count {
+                    0 => 0,
+                    _ => (count * BAR_WIDTH / max).max(1),
+                };
+                format!(
+                    "  {:>width$} | {} {}\r\n",
+                    i,
+                    "#".repeat(bar),
+                    count,
+                    width = label_width
+                )
+            })
+            .collect()
+    }
 }
 
-/// Represents an effect that occurs when a choice is made.
+/// JSON export of a project for web based players and other tools that don't understand arbor's
+/// internal layout
 ///
-/// Name length strings are stored as a heap allocated String rather than a static NameString as
-/// that would bloat enum size by 32 bytes, when Cmp will rarely be used compared to val based
-/// requirements
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
-pub enum EffectKind {
-    /// No effect
-    No,
-    Add(KeyString, u32),
-    Sub(KeyString, u32),
-    Set(KeyString, u32),
-    Assign(KeyString, NameString),
+/// Unlike the project file, the export contains no text sections, hashes, or linked lists. Nodes
+/// and edges are listed by index, each node lists its choices in order, and requirements and
+/// effects are objects tagged with an "op" field. Text contains substitution markers written as
+/// {key}, which players replace with the current name for the key. Literal braces in the text are
+/// doubled, as in {{ and }}. For example:
+///
+/// ```text
+/// {
+///   "schema": 1,
+///   "name": "demo",
+///   "nodes": [{"id": 0, "speaker_key": "cat", "speaker": "Behemoth",
+///              "text": "I am {cat}", "choices": [0], "timeout_ms": null, "directions": [],
+///              "flags": [], "on_enter": []}],
+///   "edges": [{"id": 0, "source": 0, "target": 1, "text": "Who?",
+///              "requirement": {"op": "greater", "key": "gold", "value": 5},
+///              "effect": null, "default": false, "call": false}],
+///   ...
+/// }
+/// ```
+pub mod json {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// Version of the exported structure, increased whenever a field is changed or removed
+    pub const SCHEMA_VERSION: u32 = 1;
+
+    /// An exported project
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    pub struct Project {
+        pub schema: u32,
+        pub name: String,
+        /// The project uid, as 32 hex digits
+        pub uid: String,
+        /// Initial name for each name key
+        pub names: BTreeMap<String, String>,
+        /// Initial value and scope for each val key
+        pub vals: BTreeMap<String, Val>,
+        pub nodes: Vec<Node>,
+        pub edges: Vec<Edge>,
+        /// Node id for each bookmark
+        pub bookmarks: BTreeMap<String, usize>,
+        /// Interrupts in the order they are checked
+        pub interrupts: Vec<Interrupt>,
+        /// How choices are ordered, one of "authored", "alphabetical", or "requirements-last".
+        /// The choices of each node are already listed in this order
+        #[serde(default)]
+        pub choice_order: String,
+        /// How choices with unmet requirements are shown, either "disable" or "hide"
+        #[serde(default)]
+        pub unmet_choices: String,
+    }
+
+    /// A value and how long changes to it last, see ValScope
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    pub struct Val {
+        pub initial: u32,
+        /// One of "global", "conversation", or "temporary"
+        pub scope: String,
+    }
+
+    /// A line of dialogue
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    pub struct Node {
+        pub id: usize,
+        pub speaker_key: String,
+        /// Initial name of the speaker
+        pub speaker: String,
+        /// Dialogue with substitution markers
+        pub text: String,
+        /// Ids of the edges leaving the node, in the order they are presented
+        pub choices: Vec<usize>,
+        /// Time limit for choosing, after which the default choice is taken
+        pub timeout_ms: Option<u32>,
+        pub directions: Vec<Direction>,
+        /// Content flags, any of "violence", "profanity", or "spoiler"
+        pub flags: Vec<String>,
+        /// Effects applied whenever the node is shown, in order
+        #[serde(default)]
+        pub on_enter: Vec<Effect>,
+    }
+
+    /// A player choice
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    pub struct Edge {
+        pub id: usize,
+        pub source: usize,
+        pub target: usize,
+        /// Choice text with substitution markers
+        pub text: String,
+        pub requirement: Option<Requirement>,
+        pub effect: Option<Effect>,
+        /// Taken when the time limit of the source node runs out
+        pub default: bool,
+        /// Returns to the source node once the target conversation ends
+        pub call: bool,
+    }
+
+    /// Condition for a choice or interrupt to be available
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    #[serde(tag = "op", rename_all = "snake_case")]
+    pub enum Requirement {
+        Greater {
+            key: String,
+            value: u32,
+        },
+        Less {
+            key: String,
+            value: u32,
+        },
+        Equal {
+            key: String,
+            value: u32,
+        },
+        /// The current name for the key must match
+        Cmp {
+            key: String,
+            name: String,
+        },
+        /// The current name for the key must not match
+        NotCmp {
+            key: String,
+            name: String,
+        },
+        /// The current name for the key must match one of the names
+        OneOf {
+            key: String,
+            names: Vec<String>,
+        },
+    }
+
+    /// Change made when a choice is taken
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    #[serde(tag = "op", rename_all = "snake_case")]
+    pub enum Effect {
+        Add {
+            key: String,
+            value: u32,
+        },
+        Sub {
+            key: String,
+            value: u32,
+        },
+        Set {
+            key: String,
+            value: u32,
+        },
+        /// Change the current name for the key
+        Assign {
+            key: String,
+            name: String,
+        },
+        /// Ask the player for a name, and store it as the current name for the key
+        Prompt {
+            key: String,
+        },
+    }
+
+    /// Stage direction for presenting a node
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    #[serde(tag = "kind", rename_all = "snake_case")]
+    pub enum Direction {
+        Enter {
+            character: String,
+        },
+        Exit {
+            character: String,
+        },
+        Animate {
+            character: String,
+            animation: String,
+        },
+        Background {
+            scene: String,
+        },
+    }
+
+    /// Jump to the target node as soon as the requirement is met
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    pub struct Interrupt {
+        pub requirement: Requirement,
+        pub target: usize,
+    }
+
+    impl Requirement {
+        fn from_req(req: &ReqKind) -> Option<Self> {
+            let key = |k: &KeyString| k.to_string();
+            Some(match req {
+                ReqKind::No => return None,
+                ReqKind::Greater(k, v) => Requirement::Greater {
+                    key: key(k),
+                    value: *v,
+                },
+                ReqKind::Less(k, v) => Requirement::Less {
+                    key: key(k),
+                    value: *v,
+                },
+                ReqKind::Equal(k, v) => Requirement::Equal {
+                    key: key(k),
+                    value: *v,
+                },
+                ReqKind::Cmp(k, n) => Requirement::Cmp {
+                    key: key(k),
+                    name: n.to_string(),
+                },
+                ReqKind::NotCmp(k, n) => Requirement::NotCmp {
+                    key: key(k),
+                    name: n.to_string(),
+                },
+                ReqKind::OneOf(k, n) => Requirement::OneOf {
+                    key: key(k),
+                    names: n.iter().map(|name| name.to_string()).collect(),
+                },
+            })
+        }
+    }
+
+    impl Effect {
+        fn from_effect(effect: &EffectKind) -> Option<Self> {
+            let key = |k: &KeyString| k.to_string();
+            Some(match effect {
+                EffectKind::No => return None,
+                EffectKind::Add(k, v) => Effect::Add {
+                    key: key(k),
+                    value: *v,
+                },
+                EffectKind::Sub(k, v) => Effect::Sub {
+                    key: key(k),
+                    value: *v,
+                },
+                EffectKind::Set(k, v) => Effect::Set {
+                    key: key(k),
+                    value: *v,
+                },
+                EffectKind::Assign(k, n) => Effect::Assign {
+                    key: key(k),
+                    name: n.to_string(),
+                },
+                EffectKind::Prompt(k) => Effect::Prompt { key: key(k) },
+            })
+        }
+    }
+
+    impl From<&crate::Direction> for Direction {
+        fn from(direction: &crate::Direction) -> Self {
+            match direction {
+                crate::Direction::Enter(k) => Direction::Enter {
+                    character: k.to_string(),
+                },
+                crate::Direction::Exit(k) => Direction::Exit {
+                    character: k.to_string(),
+                },
+                crate::Direction::Animate(k, a) => Direction::Animate {
+                    character: k.to_string(),
+                    animation: a.to_string(),
+                },
+                crate::Direction::Background(b) => Direction::Background {
+                    scene: b.to_string(),
+                },
+            }
+        }
+    }
+
+    /// Write the tokens of node or edge text with substitution markers. Tokens at odd indices are
+    /// keys
+    fn marked_text<'a>(tokens: impl Iterator<Item = (usize, &'a str)>) -> String {
+        let mut marked = String::new();
+        for (i, token) in tokens {
+            if (i & 0x1) == 1 {
+                marked.push('{');
+                marked.push_str(token);
+                marked.push('}');
+            } else {
+                let text = cmd::util::unescape(token);
+                marked.push_str(&text.replace('{', "{{").replace('}', "}}"));
+            }
+        }
+        marked
+    }
+
+    /// Get the text a section points to
+    fn section_text<'a>(data: &'a DialogueTreeData, section: &Section) -> Result<&'a str> {
+        Ok(data
+            .text
+            .get(section[0]..section[1])
+            .ok_or(cmd::Error::InvalidSection)?)
+    }
+
+    /// Build the exported structure of a project
+    ///
+    /// # Errors
+    /// Error if a text section is invalid or a speaker is missing from the name table, use
+    /// validate_tree to find these
+    pub fn export(data: &DialogueTreeData) -> Result<Project> {
+        let mut nodes = Vec::with_capacity(data.tree.nodes().len());
+        for (id, node) in data.tree.nodes().iter().enumerate() {
+            let mut tokens =
+                cmd::util::split_tokens(section_text(data, &node.section)?).enumerate();
+            let _ = tokens.next(); // node text starts with a separator
+            let speaker_key = tokens.next().ok_or(cmd::Error::NodeParse)?.1;
+            let speaker = cmd::util::speaker_name(speaker_key, &data.name_table)
+                .ok_or(cmd::Error::NodeParse)?;
+            nodes.push(Node {
+                id,
+                speaker_key: speaker_key.to_string(),
+                speaker: speaker.to_string(),
+                text: marked_text(tokens),
+                choices: runner::ordered_edges(data, id)?,
+                timeout_ms: node.timeout,
+                directions: cmd::util::directions_of(data, id)?
+                    .iter()
+                    .map(Direction::from)
+                    .collect(),
+                flags: node.flags.names().map(String::from).collect(),
+                on_enter: cmd::util::on_enter_of(data, id)?
+                    .iter()
+                    .filter_map(Effect::from_effect)
+                    .collect(),
+            });
+        }
+
+        let mut edges = Vec::with_capacity(data.tree.edges().len());
+        for (id, edge) in data.tree.edges().iter().enumerate() {
+            let tokens = cmd::util::split_tokens(section_text(data, &edge.section)?).enumerate();
+            edges.push(Edge {
+                id,
+                source: data.tree.source_of(id)?,
+                target: data.tree.target_of(id)?,
+                text: marked_text(tokens),
+                requirement: Requirement::from_req(&edge.requirement),
+                effect: Effect::from_effect(&edge.effect),
+                default: edge.default,
+                call: edge.call,
+            });
+        }
+
+        let mut vals = BTreeMap::new();
+        for (key, value) in data.val_table.iter() {
+            let scope = match ValScope::of(key)? {
+                ValScope::Global => "global",
+                ValScope::Conversation => "conversation",
+                ValScope::Temporary => "temporary",
+            };
+            vals.insert(
+                key.to_string(),
+                Val {
+                    initial: *value,
+                    scope: scope.to_string(),
+                },
+            );
+        }
+
+        let mut interrupts = Vec::with_capacity(data.interrupts.len());
+        for (position, interrupt) in data.interrupts.iter().enumerate() {
+            interrupts.push(Interrupt {
+                requirement: Requirement::from_req(&interrupt.requirement)
+                    .ok_or(cmd::Error::InterruptWithoutRequirement(position))?,
+                target: interrupt.target,
+            });
+        }
+
+        Ok(Project {
+            schema: SCHEMA_VERSION,
+            name: data.name.clone(),
+            uid: data.uid.to_string(),
+            names: data
+                .name_table
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            vals,
+            nodes,
+            edges,
+            bookmarks: data
+                .bookmarks
+                .iter()
+                .map(|(k, v)| (k.to_string(), *v))
+                .collect(),
+            interrupts,
+            choice_order: data.choice_policy.order.to_string(),
+            unmet_choices: data.choice_policy.unmet.to_string(),
+        })
+    }
 }
 
-impl std::str::FromStr for EffectKind {
-    type Err = anyhow::Error;
+/// Copying nodes between arbor instances through the system clipboard
+///
+/// Copied nodes are written as a JSON Payload, which holds everything needed to recreate the nodes
+/// and the edges between them in another project: the speaker and text of each node with its
+/// content flags and position, the text, requirement, and effect of each edge, and the entries of
+/// every name and val key they use. Text is stored the way it is typed into new node, with keys
+/// between TOKEN_SEP markers. The payload starts with FORMAT, so that pasting ordinary text is
+/// refused rather than misread.
+pub mod clipboard {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// Marker identifying a clipboard payload written by arbor
+    pub const FORMAT: &str = "arbor-nodes";
+
+    /// Version of the payload structure, increased whenever a field is changed or removed
+    pub const VERSION: u32 = 1;
+
+    /// Nodes and edges copied from a project
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    pub struct Payload {
+        pub format: String,
+        pub version: u32,
+        /// Name of the project the nodes were copied from
+        pub project: String,
+        /// Name for each name key used by the copied nodes and edges
+        pub names: BTreeMap<String, String>,
+        /// Initial value for each val key used by the copied edges
+        pub vals: BTreeMap<String, u32>,
+        pub nodes: Vec<Node>,
+        pub edges: Vec<Edge>,
+    }
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        info!("Parsing EffectKind from string");
-        // Implementation notes:
-        // The enum string format is set up to directly map to how the enum is declared in rust:
-        // e.g. 'Add(my_key,10)'
-        // This is tokenized on the presence of '(' ',' and ')' special characters. In reverse
-        // order:
-        // e.g. ['', '10', 'my_key', 'Add']
-        //
-        // This is done in reverse order so that the required key and val can be built up before
-        // converting the enum itself, (since the key and val are required to declare the enum.
-        //
-        // Importantly, the 'val' that is tested against can be a string or a u32. This is handled
-        // by waiting to unwrap the val parameter until building the Enum
-        let mut split = s.rsplit(&['(', ',', ')'][..]);
-        debug!("{}", s);
+    /// A copied line of dialogue
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    pub struct Node {
+        pub speaker: String,
+        pub text: String,
+        /// Content flags, in the format accepted by edit tag
+        pub tags: String,
+        pub timeout_ms: Option<u32>,
+        pub position: Option<(f32, f32)>,
+    }
 
-        trace!("First item should be ''");
-        anyhow::ensure!(split.next().ok_or(cmd::Error::Generic)?.is_empty());
+    /// A copied choice, between two of the copied nodes
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    pub struct Edge {
+        /// Position of the source node in the copied nodes
+        pub source: usize,
+        /// Position of the target node in the copied nodes
+        pub target: usize,
+        pub text: String,
+        /// Requirement in the format accepted by new edge, e.g. "Greater(rus_lit,50)"
+        pub requirement: String,
+        /// Effect in the format accepted by new edge, e.g. "Add(rus_lit,10)"
+        pub effect: String,
+        pub default: bool,
+        pub call: bool,
+    }
+
+    /// What a paste added to a project
+    #[derive(Default)]
+    pub struct Pasted {
+        /// Events that were applied, in order
+        pub events: Vec<DialogueTreeEvent>,
+        /// Index of the first pasted node, the rest follow in order
+        pub first: NodeIndex,
+        pub nodes: usize,
+        /// Name keys the project did not have, added with their copied names
+        pub missing_names: Vec<KeyString>,
+        /// Val keys the project did not have, added with their copied values
+        pub missing_vals: Vec<KeyString>,
+        /// Keys the project already had with a different entry. The entry of the project is kept
+        pub differing: Vec<KeyString>,
+    }
+
+    impl std::fmt::Display for Pasted {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let join = |keys: &[KeyString]| {
+                keys.iter()
+                    .map(KeyString::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            };
+            write!(
+                f,
+                "pasted {} nodes starting at node {}",
+                self.nodes, self.first
+            )?;
+            if !self.missing_names.is_empty() {
+                write!(f, "; added missing names: {}", join(&self.missing_names))?;
+            }
+            if !self.missing_vals.is_empty() {
+                write!(f, "; added missing vals: {}", join(&self.missing_vals))?;
+            }
+            if !self.differing.is_empty() {
+                write!(
+                    f,
+                    "; kept the project's entries for: {}",
+                    join(&self.differing)
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    impl Payload {
+        /// Read a payload from clipboard text
+        ///
+        /// # Errors
+        /// Error with InvalidClipboard if the text is not a payload written by arbor, or was
+        /// written by a newer version
+        pub fn parse(text: &str) -> Result<Payload> {
+            let payload: Payload =
+                serde_json::from_str(text).map_err(|_| cmd::Error::InvalidClipboard)?;
+            anyhow::ensure!(
+                payload.format == FORMAT && payload.version <= VERSION,
+                cmd::Error::InvalidClipboard
+            );
+            Ok(payload)
+        }
+
+        /// Write the payload as clipboard text
+        pub fn to_json(&self) -> Result<String> {
+            Ok(serde_json::to_string_pretty(self)?)
+        }
+    }
+
+    /// Copy a set of nodes, and the edges between them, see util::extract for which edges are
+    /// copied
+    ///
+    /// # Errors
+    /// Error if any node index is invalid, or the text of a copied node or edge is corrupted
+    pub fn copy(data: &DialogueTreeData, node_indices: &[NodeIndex]) -> Result<Payload> {
+        let subtree = cmd::util::extract(data, node_indices, &data.name)?;
+        let text_of = |section: &Section| -> Result<&str> {
+            Ok(subtree
+                .text
+                .get(section[0]..section[1])
+                .ok_or(cmd::Error::InvalidSection)?)
+        };
+
+        let mut names = BTreeMap::new();
+        let mut vals = BTreeMap::new();
+        let mut use_keys = |keys: cmd::util::ElementKeys<'_>| {
+            for key in keys.names {
+                if let Some(name) = subtree.name_table.get(key) {
+                    names.insert(key.to_string(), name.to_string());
+                }
+            }
+            for key in keys.vals {
+                if let Some(val) = subtree.val_table.get(key) {
+                    vals.insert(key.to_string(), *val);
+                }
+            }
+        };
+
+        let mut nodes = Vec::with_capacity(subtree.tree.nodes().len());
+        for (index, node) in subtree.tree.nodes().iter().enumerate() {
+            use_keys(cmd::util::node_keys(&subtree, index));
+            let text = text_of(&node.section)?;
+            let speaker = cmd::util::split_tokens(text)
+                .nth(1)
+                .ok_or(cmd::Error::NodeParse)?;
+            nodes.push(Node {
+                speaker: speaker.to_string(),
+                text: text
+                    .get(TOKEN_SEP.len() * 2 + speaker.len()..)
+                    .unwrap_or_default()
+                    .to_string(),
+                tags: node.flags.to_string(),
+                timeout_ms: node.timeout,
+                position: subtree.placement(index).map(|pos| (pos.x, pos.y)),
+            });
+        }
+        let mut edges = Vec::with_capacity(subtree.tree.edges().len());
+        for (index, edge) in subtree.tree.edges().iter().enumerate() {
+            use_keys(cmd::util::edge_keys(&subtree, index));
+            edges.push(Edge {
+                source: subtree.tree.source_of(index)?,
+                target: subtree.tree.target_of(index)?,
+                text: text_of(&edge.section)?.to_string(),
+                requirement: edge.requirement.to_string(),
+                effect: edge.effect.to_string(),
+                default: edge.default,
+                call: edge.call,
+            });
+        }
+
+        Ok(Payload {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            project: data.name.clone(),
+            names,
+            vals,
+            nodes,
+            edges,
+        })
+    }
+
+    /// Parse a list of key mappings written as old=new pairs separated by commas, for example
+    /// "cat=kot,gold=coins". An empty list maps nothing
+    ///
+    /// # Errors
+    /// Error with InvalidKeyMap if a pair is not written as old=new, or a key is too long
+    pub fn parse_remap(s: &str) -> Result<BTreeMap<KeyString, KeyString>> {
+        let mut remap = BTreeMap::new();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let invalid = || cmd::Error::InvalidKeyMap(pair.to_string());
+            let (old, new) = pair.split_once('=').ok_or_else(invalid)?;
+            let old = KeyString::from(old.trim()).map_err(|_| invalid())?;
+            let new = KeyString::from(new.trim()).map_err(|_| invalid())?;
+            remap.insert(old, new);
+        }
+        Ok(remap)
+    }
+
+    /// Add the copied nodes and edges to a project, after its existing nodes. Keys in the
+    /// payload are first renamed with the remap, so that copied text can use the keys of the
+    /// project it is pasted into. Keys still missing from the project are added with their
+    /// copied entries, and reported along with keys whose entry differs from the copied one. The
+    /// pasted nodes and edges are new lines, and get new line ids
+    ///
+    /// # Errors
+    /// Error if the payload is invalid, for example if an edge refers to a node that was not
+    /// copied or a speaker has no name. No changes are made in this case
+    pub fn paste(
+        target: &mut DialogueTreeData,
+        payload: &Payload,
+        remap: &BTreeMap<KeyString, KeyString>,
+    ) -> Result<Pasted> {
+        let key = |key: &str| -> Result<KeyString> {
+            let key = KeyString::from(key).map_err(|e| e.simplify())?;
+            Ok(*remap.get(&key).unwrap_or(&key))
+        };
+        let remap_text = |text: &str| -> String {
+            let tokens: Vec<&str> = cmd::util::split_tokens(text)
+                .enumerate()
+                .map(|(i, token)| match KeyString::from(token) {
+                    Ok(k) if (i & 0x1) == 1 => remap.get(&k).map_or(token, KeyString::as_str),
+                    _ => token,
+                })
+                .collect();
+            tokens.join(TOKEN_SEP)
+        };
+
+        trace!("rebuild the copied nodes as a project of their own");
+        let mut source = DialogueTreeData::new(&payload.project);
+        for (k, name) in payload.names.iter() {
+            let name = NameString::from(name).map_err(|e| e.simplify())?;
+            source.name_table.insert(key(k)?, name);
+        }
+        for (k, val) in payload.vals.iter() {
+            let k = key(k)?;
+            ValScope::of(&k)?;
+            source.val_table.insert(k, *val);
+        }
+        for (index, node) in payload.nodes.iter().enumerate() {
+            let text = format!(
+                "{}{}{}{}",
+                TOKEN_SEP,
+                key(&node.speaker)?,
+                TOKEN_SEP,
+                remap_text(&node.text)
+            );
+            let section = cmd::util::push_section(&mut source.text, &text);
+            let line = source.new_line();
+            source.tree.add_node(Dialogue {
+                timeout: node.timeout_ms,
+                flags: node.tags.parse()?,
+                line,
+                ..Dialogue::new(section)
+            })?;
+            if let Some((x, y)) = node.position {
+                source.set_position(index, Some(Position::new(x, y)))?;
+            }
+        }
+        for edge in payload.edges.iter() {
+            let section = cmd::util::push_section(&mut source.text, &remap_text(&edge.text));
+            let mut requirement: ReqKind = edge.requirement.parse()?;
+            if let Some((kind, k)) = cmd::util::requirement_key(&requirement) {
+                let (k, renamed) = (*k, key(k)?);
+                requirement = cmd::util::rename_requirement(requirement, kind, k, renamed);
+            }
+            let mut effect: EffectKind = edge.effect.parse()?;
+            if let Some((kind, k)) = cmd::util::effect_key(&effect) {
+                effect = cmd::util::rename_effect(effect, kind, *k, key(k)?);
+            }
+            source.tree.add_edge(
+                edge.source,
+                edge.target,
+                Choice {
+                    default: edge.default,
+                    call: edge.call,
+                    ..Choice::new(section, requirement, effect)
+                },
+            )?;
+        }
+        cmd::util::validate_tree(&source)?;
+
+        trace!("compare the keys with the project before adding the nodes");
+        // keys mapped onto a key of the project are meant to take its entry
+        let remapped = |k: &KeyString| remap.values().any(|to| to == k);
+        let mut pasted = Pasted {
+            first: target.tree.nodes().len(),
+            nodes: source.tree.nodes().len(),
+            ..Default::default()
+        };
+        for (k, name) in source.name_table.iter() {
+            match target.name_table.get(k) {
+                None => pasted.missing_names.push(*k),
+                Some(existing) if existing != name && !remapped(k) => pasted.differing.push(*k),
+                Some(_) => {}
+            }
+        }
+        for (k, val) in source.val_table.iter() {
+            match target.val_table.get(k) {
+                None => pasted.missing_vals.push(*k),
+                Some(existing) if existing != val && !remapped(k) => pasted.differing.push(*k),
+                Some(_) => {}
+            }
+        }
+        pasted.missing_names.sort_unstable();
+        pasted.missing_vals.sort_unstable();
+        pasted.differing.sort_unstable();
+        pasted.differing.dedup();
+
+        pasted.events = cmd::util::graft(target, &source)?;
+        Ok(pasted)
+    }
+}
+
+/// Small images of a project's graph, shown next to each project when choosing one to open
+///
+/// Thumbnails are drawn without a window or GPU, straight from the node positions in the layout
+/// table, so they can be made for any project in a store. They are cached next to the project
+/// file as a PNG named after the project with THUMBNAIL_EXT, which records the modification time
+/// of the project file it was drawn from. The thumbnail is drawn again once the project file
+/// changes, see cached.
+pub mod thumbnail {
+    use super::*;
+
+    /// Width and height of cached thumbnails in pixels
+    pub const SIZE: usize = 96;
+
+    /// Pixels left empty around the graph
+    const MARGIN: usize = 4;
+
+    const BACKGROUND: [u8; 4] = [0x1b, 0x1b, 0x1b, 0xff];
+    const EDGE_COLOR: [u8; 4] = [0x5a, 0x5a, 0x5a, 0xff];
+    const NODE_COLOR: [u8; 4] = [0xe0, 0xa0, 0x40, 0xff];
+    /// Color of nodes with no choices, where the dialogue ends
+    const END_COLOR: [u8; 4] = [0x60, 0xb0, 0xe0, 0xff];
+
+    const PNG_MAGIC: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+    /// Keyword of the PNG text chunk holding the modification time of the project file
+    const SOURCE_KEYWORD: &[u8] = b"arbor:source";
+
+    /// An image in 8 bit RGBA, with rows from top to bottom
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Thumbnail {
+        pub width: usize,
+        pub height: usize,
+        pub pixels: Vec<u8>,
+    }
+
+    impl Thumbnail {
+        /// Create an image filled with the background color
+        fn blank(width: usize, height: usize) -> Self {
+            Thumbnail {
+                width,
+                height,
+                pixels: BACKGROUND.repeat(width * height),
+            }
+        }
+
+        /// Get the color of a pixel
+        pub fn pixel(&self, x: usize, y: usize) -> [u8; 4] {
+            let i = (y * self.width + x) * 4;
+            [
+                self.pixels[i],
+                self.pixels[i + 1],
+                self.pixels[i + 2],
+                self.pixels[i + 3],
+            ]
+        }
+
+        /// Set the color of a pixel, pixels outside the image are ignored
+        fn set(&mut self, x: i64, y: i64, color: [u8; 4]) {
+            if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+                return;
+            }
+            let i = (y as usize * self.width + x as usize) * 4;
+            self.pixels[i..i + 4].copy_from_slice(&color);
+        }
+
+        /// Draw a one pixel wide line between two points
+        fn line(&mut self, from: (i64, i64), to: (i64, i64), color: [u8; 4]) {
+            let (mut x, mut y) = from;
+            let dx = (to.0 - x).abs();
+            let dy = -(to.1 - y).abs();
+            let step_x = if x < to.0 { 1 } else { -1 };
+            let step_y = if y < to.1 { 1 } else { -1 };
+            let mut err = dx + dy;
+            loop {
+                self.set(x, y, color);
+                if (x, y) == to {
+                    break;
+                }
+                let err2 = 2 * err;
+                if err2 >= dy {
+                    err += dy;
+                    x += step_x;
+                }
+                if err2 <= dx {
+                    err += dx;
+                    y += step_y;
+                }
+            }
+        }
+
+        /// Draw a square centered on a point
+        fn dot(&mut self, center: (i64, i64), radius: i64, color: [u8; 4]) {
+            for y in center.1 - radius..=center.1 + radius {
+                for x in center.0 - radius..=center.0 + radius {
+                    self.set(x, y, color);
+                }
+            }
+        }
+    }
+
+    /// Draw the graph of a project, scaled to fit the image while keeping its aspect ratio. Edges
+    /// are drawn as lines between the positions of their nodes, and nodes as dots on top, with
+    /// the nodes where the dialogue ends in a different color
+    ///
+    /// # Errors
+    ///
+    /// Error if an edge of the tree has no source or target node
+    pub fn render(data: &DialogueTreeData, width: usize, height: usize) -> Result<Thumbnail> {
+        let mut image = Thumbnail::blank(width, height);
+        let count = data.tree.nodes().len();
+        if count == 0 {
+            return Ok(image);
+        }
+
+        let positions: Vec<Position> = (0..count).map(|i| data.position(i)).collect();
+        let (mut min, mut max) = (positions[0], positions[0]);
+        for pos in positions.iter() {
+            min = Position::new(min.x.min(pos.x), min.y.min(pos.y));
+            max = Position::new(max.x.max(pos.x), max.y.max(pos.y));
+        }
+        let inner_width = width.saturating_sub(2 * MARGIN).max(1) as f32;
+        let inner_height = height.saturating_sub(2 * MARGIN).max(1) as f32;
+        let span_x = (max.x - min.x).max(1.0);
+        let span_y = (max.y - min.y).max(1.0);
+        let scale = (inner_width / span_x).min(inner_height / span_y);
+        // center the graph along the axis it doesn't fill
+        let offset_x = MARGIN as f32 + (inner_width - span_x * scale) / 2.0;
+        let offset_y = MARGIN as f32 + (inner_height - span_y * scale) / 2.0;
+        let points: Vec<(i64, i64)> = positions
+            .iter()
+            .map(|pos| {
+                (
+                    (offset_x + (pos.x - min.x) * scale).round() as i64,
+                    (offset_y + (pos.y - min.y) * scale).round() as i64,
+                )
+            })
+            .collect();
+
+        for edge_index in 0..data.tree.edges().len() {
+            let source = data.tree.source_of(edge_index)?;
+            let target = data.tree.target_of(edge_index)?;
+            image.line(points[source], points[target], EDGE_COLOR);
+        }
+        // large graphs would be covered by overlapping dots
+        let radius = if count > 200 { 0 } else { 1 };
+        for (node_index, point) in points.iter().enumerate() {
+            let color = if data.tree.is_leaf(node_index)? {
+                END_COLOR
+            } else {
+                NODE_COLOR
+            };
+            image.dot(*point, radius, color);
+        }
+        Ok(image)
+    }
+
+    /// Name of the cached thumbnail of a project file, e.g. demo.tree is cached as
+    /// demo.thumb.png
+    pub fn cache_name(file: &str) -> String {
+        file.strip_suffix(TREE_EXT).unwrap_or(file).to_string() + THUMBNAIL_EXT
+    }
+
+    /// Get the thumbnail of a project file in a store, drawing it again if the project file has
+    /// changed since the cached thumbnail was drawn. Stores that don't track modification times
+    /// draw the thumbnail every time, and nothing is cached
+    ///
+    /// # Errors
+    ///
+    /// Error if the project file cannot be read, or the thumbnail cannot be cached
+    pub fn cached(store: &dyn store::ProjectStore, file: &str) -> Result<Thumbnail> {
+        let name = cache_name(file);
+        let source = store.modified(file)?.map(|time| {
+            let since_epoch = time
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default();
+            format!(
+                "{}.{:09}",
+                since_epoch.as_secs(),
+                since_epoch.subsec_nanos()
+            )
+        });
+        if let Some(source) = source.as_ref() {
+            if let Ok(bytes) = store.read(&name) {
+                match decode_png(&bytes) {
+                    Ok((thumbnail, Some(drawn_from))) if drawn_from == *source => {
+                        return Ok(thumbnail)
+                    }
+                    Ok(_) => debug!("thumbnail {} is out of date", name),
+                    Err(e) => debug!("thumbnail {} could not be read: {}", name, e),
+                }
+            }
+        }
+
+        let thumbnail = render(&store::read_data(store, file)?, SIZE, SIZE)?;
+        if let Some(source) = source {
+            store.write(&name, &encode_png(&thumbnail, Some(&source)))?;
+        }
+        Ok(thumbnail)
+    }
+
+    /// Encode an image as a PNG, with an optional note of the project file it was drawn from
+    pub fn encode_png(image: &Thumbnail, source: Option<&str>) -> Vec<u8> {
+        let mut png = PNG_MAGIC.to_vec();
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&(image.width as u32).to_be_bytes());
+        header.extend_from_slice(&(image.height as u32).to_be_bytes());
+        // 8 bits per channel, RGBA, default compression and filtering, not interlaced
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+        write_chunk(&mut png, b"IHDR", &header);
+        if let Some(source) = source {
+            let mut text = SOURCE_KEYWORD.to_vec();
+            text.push(0);
+            text.extend_from_slice(source.as_bytes());
+            write_chunk(&mut png, b"tEXt", &text);
+        }
+        // each row starts with its filter type, which is always none
+        let row_len = image.width * 4;
+        let mut rows = Vec::with_capacity((row_len + 1) * image.height);
+        for row in image.pixels.chunks(row_len.max(1)) {
+            rows.push(0);
+            rows.extend_from_slice(row);
+        }
+        write_chunk(
+            &mut png,
+            b"IDAT",
+            &miniz_oxide::deflate::compress_to_vec_zlib(&rows, 6),
+        );
+        write_chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    /// Decode a PNG written by encode_png, along with the note of the project file it was drawn
+    /// from if it has one. Only the subset of PNG written by encode_png is supported
+    ///
+    /// # Errors
+    ///
+    /// Error with InvalidThumbnail if the image is not a PNG written by encode_png
+    pub fn decode_png(bytes: &[u8]) -> Result<(Thumbnail, Option<String>)> {
+        let mut rest = bytes
+            .strip_prefix(&PNG_MAGIC[..])
+            .ok_or(cmd::Error::InvalidThumbnail)?;
+        let mut size = None;
+        let mut source = None;
+        let mut compressed = Vec::new();
+        while rest.len() >= 12 {
+            let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            anyhow::ensure!(rest.len() >= 12 + len, cmd::Error::InvalidThumbnail);
+            let (kind, body) = (&rest[4..8], &rest[8..8 + len]);
+            let crc = &rest[8 + len..12 + len];
+            anyhow::ensure!(
+                crc == crc32(&rest[4..8 + len]).to_be_bytes(),
+                cmd::Error::InvalidThumbnail
+            );
+            match kind {
+                b"IHDR" => {
+                    anyhow::ensure!(
+                        body.len() == 13 && body[8..] == [8, 6, 0, 0, 0],
+                        cmd::Error::InvalidThumbnail
+                    );
+                    let width = u32::from_be_bytes([body[0], body[1], body[2], body[3]]);
+                    let height = u32::from_be_bytes([body[4], body[5], body[6], body[7]]);
+                    size = Some((width as usize, height as usize));
+                }
+                b"tEXt" => {
+                    if let Some(text) = body.strip_prefix(SOURCE_KEYWORD) {
+                        let text = text
+                            .strip_prefix(&[0])
+                            .ok_or(cmd::Error::InvalidThumbnail)?;
+                        source = Some(String::from_utf8_lossy(text).into_owned());
+                    }
+                }
+                b"IDAT" => compressed.extend_from_slice(body),
+                _ => {}
+            }
+            rest = &rest[12 + len..];
+        }
 
-        trace!("Second item should be number or string, don't check for validity yet");
-        let val = split.next().ok_or(cmd::Error::Generic)?;
+        let (width, height) = size.ok_or(cmd::Error::InvalidThumbnail)?;
+        let rows = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed)
+            .map_err(|_| cmd::Error::InvalidThumbnail)?;
+        let row_len = width * 4;
+        anyhow::ensure!(
+            rows.len() == (row_len + 1) * height,
+            cmd::Error::InvalidThumbnail
+        );
+        let mut pixels = Vec::with_capacity(row_len * height);
+        for row in rows.chunks(row_len + 1) {
+            anyhow::ensure!(row[0] == 0, cmd::Error::InvalidThumbnail);
+            pixels.extend_from_slice(&row[1..]);
+        }
+        Ok((
+            Thumbnail {
+                width,
+                height,
+                pixels,
+            },
+            source,
+        ))
+    }
 
-        trace!("Third item should be key, check that the key and name are of a valid length");
-        // match required due to lifetime limitations on CapacityError
-        let key = match KeyString::from(split.next().ok_or(cmd::Error::Generic)?) {
-            Ok(v) => Ok(v),
-            Err(e) => Err(e.simplify()),
-        }?;
+    /// Append a PNG chunk, with its length and checksum
+    fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+        png.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(body);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
 
-        trace!("fourth item should be Enum type, build it!, and also try to resolve the val");
-        match split.next().ok_or(cmd::Error::Generic)? {
-            "Add" => Ok(EffectKind::Add(key, val.parse::<u32>()?)),
-            "Sub" => Ok(EffectKind::Sub(key, val.parse::<u32>()?)),
-            "Set" => Ok(EffectKind::Set(key, val.parse::<u32>()?)),
-            "Assign" => {
-                // match required due to lifetime limitations on CapacityError
-                let name = match NameString::from(val) {
-                    Ok(v) => Ok(v),
-                    Err(e) => Err(e.simplify()),
-                }?;
-                Ok(EffectKind::Assign(key, name))
+    /// CRC-32 checksum of a PNG chunk's type and body
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = 0xffff_ffff_u32;
+        for byte in bytes {
+            crc ^= u32::from(*byte);
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
             }
-            _ => Err(cmd::Error::Generic.into()),
         }
+        !crc
     }
 }
 
-/// Top level module for all arbor commands. These commands rely heavily on the structopt
-/// derive feature to easily implement a command line interface along with command structs for
-/// input through other methods (UI, test code, etc.). In any structopt derived structure or enum,
-/// the doc comments are displayed to the user through the CLI.
+/// Playthrough of a dialogue tree, in the same way a game would present it. The runner keeps its
+/// own copy of the name and val tables so that the effects of choices can be applied without
+/// modifying the project.
 ///
-/// All commands also implement the generic Executable trait. This trait uses enum_dispatch to
-/// propagate through to all types contained in the Parse enums. This executable method is where
-/// the core logic of any command happens.
-pub mod cmd {
+/// This is used to preview the player's view of a section of the tree while editing it.
+pub mod runner {
     use super::*;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+    use std::sync::mpsc;
+    use tree::{EdgeIndex, NodeIndex};
+
+    /// Check if a requirement is met by the given names and values. Requirements on missing keys
+    /// are never met
+    pub fn requirement_met(req: &ReqKind, names: &NameTable, vals: &ValTable) -> bool {
+        match req {
+            ReqKind::No => true,
+            ReqKind::Greater(key, num) => vals.get(key).is_some_and(|v| v > num),
+            ReqKind::Less(key, num) => vals.get(key).is_some_and(|v| v < num),
+            ReqKind::Equal(key, num) => vals.get(key) == Some(num),
+            ReqKind::Cmp(key, name) => names.get(key) == Some(name),
+            ReqKind::NotCmp(key, name) => names.get(key).is_some_and(|n| n != name),
+            ReqKind::OneOf(key, options) => names.get(key).is_some_and(|n| options.contains(n)),
+        }
+    }
 
-    /// Error types for different commands
-    ///
-    /// Uses thiserror to generate messages for common situations. This does not
-    /// attempt to implement From trait on any lower level error types, but relies
-    /// on anyhow for unification and printing a stack trace
-    #[derive(Error, Debug)]
-    pub enum Error {
-        #[error("An unspecified error occured...")]
-        Generic,
-        #[error("Node parsing failed")]
-        NodeParse,
-        #[error("Edge parsing failed")]
-        EdgeParse,
-        #[error("The name already exists")]
-        NameExists,
-        #[error("The name does not exist")]
-        NameNotExists,
-        #[error("The name is in use")]
-        NameInUse,
-        #[error("The value already exists")]
-        ValExists,
-        #[error("The value does not exist")]
-        ValNotExists,
-        #[error("The value is in use")]
-        ValInUse,
-        #[error("Attempted to access an invalid section of the text")]
-        InvalidSection,
-        #[error("Hash does not match text section")]
-        InvalidHash,
-        #[error("The event history is empty, undo not possible")]
-        EventHistoryEmpty,
-        #[error("The event future queue is empty, redo not possible")]
-        EventFuturesEmpty,
-        #[error("The undo operation failed")]
-        UndoFailed,
-        #[error("The redo operation failed")]
-        RedoFailed,
+    /// Apply an effect to the given names and values. Arithmetic saturates rather than overflows.
+    /// Prompts change nothing until they are answered, see Runner::answer
+    pub fn apply_effect(effect: &EffectKind, names: &mut NameTable, vals: &mut ValTable) {
+        match effect {
+            EffectKind::No => {}
+            EffectKind::Add(key, num) => {
+                let val = vals.entry(*key).or_default();
+                *val = val.saturating_add(*num);
+            }
+            EffectKind::Sub(key, num) => {
+                let val = vals.entry(*key).or_default();
+                *val = val.saturating_sub(*num);
+            }
+            EffectKind::Set(key, num) => {
+                vals.insert(*key, *num);
+            }
+            EffectKind::Assign(key, name) => {
+                names.insert(*key, *name);
+            }
+            EffectKind::Prompt(_) => {}
+        }
     }
 
-    /// Trait to allow structopt generated
-    #[enum_dispatch]
-    pub trait Executable {
-        fn execute(&self, state: &mut EditorState) -> Result<usize>;
+    /// The key changed by an effect, with its value before and after the effect is applied
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct EffectPreview {
+        pub key: KeyString,
+        /// Value before the effect, None if the key is missing
+        pub before: Option<String>,
+        pub after: String,
     }
 
-    /// A tree based dialogue editor
-    // NoBinaryName is set so that the first arg is not parsed as binary name when using
-    // StructOpt::from_iter_safe
-    // name is set as "" to prevent usage help from recommending to start commands with "arbor"
-    #[enum_dispatch(Executable)]
-    #[derive(StructOpt)]
-    #[structopt(name="", setting = AppSettings::NoBinaryName)]
-    pub enum Parse {
-        New(new::Parse),
-        Edit(edit::Parse),
-        Remove(remove::Parse),
-        Save(Save),
-        Load(Load),
-        Rebuild(Rebuild),
-        Swap(Swap),
-        List(List),
+    /// Preview the change an effect would make to the given names and values, without applying
+    /// it. None if the effect changes nothing
+    pub fn preview_effect(
+        effect: &EffectKind,
+        names: &NameTable,
+        vals: &ValTable,
+    ) -> Option<EffectPreview> {
+        let mut after_names = names.clone();
+        let mut after_vals = vals.clone();
+        apply_effect(effect, &mut after_names, &mut after_vals);
+        match effect {
+            EffectKind::No => None,
+            EffectKind::Add(key, _) | EffectKind::Sub(key, _) | EffectKind::Set(key, _) => {
+                Some(EffectPreview {
+                    key: *key,
+                    before: vals.get(key).map(|val| val.to_string()),
+                    after: after_vals.get(key).map(|val| val.to_string())?,
+                })
+            }
+            EffectKind::Assign(key, _) => Some(EffectPreview {
+                key: *key,
+                before: names.get(key).map(|name| name.to_string()),
+                after: after_names.get(key).map(|name| name.to_string())?,
+            }),
+            EffectKind::Prompt(key) => Some(EffectPreview {
+                key: *key,
+                before: names.get(key).map(|name| name.to_string()),
+                after: EffectKind::PLAYER_INPUT.to_string(),
+            }),
+        }
     }
 
-    pub mod new {
-        use super::*;
+    /// Explain a requirement in plain words, along with the current value of its key, for example
+    /// "requires rus_lit < 51, currently 40"
+    pub fn describe_requirement(req: &ReqKind, names: &NameTable, vals: &ValTable) -> String {
+        let current_val = |key: &KeyString| match vals.get(key) {
+            Some(val) => val.to_string(),
+            None => "missing".to_string(),
+        };
+        match req {
+            ReqKind::No => "no requirement".to_string(),
+            ReqKind::Greater(key, num) => {
+                format!("requires {} > {}, currently {}", key, num, current_val(key))
+            }
+            ReqKind::Less(key, num) => {
+                format!("requires {} < {}, currently {}", key, num, current_val(key))
+            }
+            ReqKind::Equal(key, num) => {
+                format!("requires {} = {}, currently {}", key, num, current_val(key))
+            }
+            ReqKind::Cmp(key, name) => format!(
+                "requires {} to be {}, currently {}",
+                key,
+                name,
+                names.get(key).map_or("missing", |n| n.as_str())
+            ),
+            ReqKind::NotCmp(key, name) => format!(
+                "requires {} not to be {}, currently {}",
+                key,
+                name,
+                names.get(key).map_or("missing", |n| n.as_str())
+            ),
+            ReqKind::OneOf(key, options) => format!(
+                "requires {} to be one of {}, currently {}",
+                key,
+                options
+                    .iter()
+                    .map(|name| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                names.get(key).map_or("missing", |n| n.as_str())
+            ),
+        }
+    }
 
-        /// Create new things
-        #[enum_dispatch(Executable)]
-        #[derive(StructOpt)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub enum Parse {
-            Project(new::Project),
-            Node(new::Node),
-            Edge(new::Edge),
-            Name(new::Name),
-            Val(new::Val),
+    /// A choice leaving a node, along with whether its requirement is met
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct ChoiceState {
+        /// Index of the edge for the choice
+        pub index: EdgeIndex,
+        /// Text of the choice, with names substituted
+        pub text: String,
+        pub requirement: ReqKind,
+        /// True if the requirement is met by the names and values the choices were checked with
+        pub met: bool,
+        /// True if this is the choice taken when a timed decision runs out
+        pub default: bool,
+    }
+
+    /// Get every choice leaving a node in the order of the project's choice policy, checking each
+    /// requirement against the given names and values. Choices with unmet requirements are
+    /// included whatever the policy, see Runner::shown_choices
+    pub fn choice_states(
+        data: &DialogueTreeData,
+        node: NodeIndex,
+        names: &NameTable,
+        vals: &ValTable,
+    ) -> Result<Vec<ChoiceState>> {
+        let mut renderer = cmd::util::TextRenderer::default();
+        let mut choices = Vec::new();
+        for index in data.tree.outgoing_from_index(node)? {
+            let edge = data.tree.get_edge(index)?;
+            choices.push(ChoiceState {
+                index,
+                text: renderer.edge(data, names, index)?.to_string(),
+                requirement: edge.requirement.clone(),
+                met: requirement_met(&edge.requirement, names, vals),
+                default: edge.default,
+            });
         }
+        data.choice_policy.sort(&mut choices);
+        Ok(choices)
+    }
 
-        /// Create a new project
-        ///
-        /// A project is made up of a text rope storing all dialogue text, a hashtable storing
-        /// variable or user defined values, and a graph representing the narrative. Nodes of the
-        /// graph represent dialogues from characters in the story, and nodes represent the
-        /// actions of the player.
-        #[derive(new, StructOpt, Debug)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Project {
-            /// The name of the project
-            name: String,
+    /// Get the edges leaving a node in the order of the project's choice policy, with
+    /// requirements and text checked against the project's own names and values
+    pub fn ordered_edges(data: &DialogueTreeData, node: NodeIndex) -> Result<Vec<EdgeIndex>> {
+        Ok(
+            choice_states(data, node, &data.name_table, &data.val_table)?
+                .into_iter()
+                .map(|choice| choice.index)
+                .collect(),
+        )
+    }
 
-            /// Determine if the project should be loaded as the active project after creation. If
-            /// any unsaved changes in the current project will be discarded.
-            #[structopt(short, long)]
-            set_active: bool,
+    /// Hypothetical names and values, used to check requirements without playing through the
+    /// tree. Parsed from a comma separated list of assignments, e.g. "gold=20,flag=1,cat=Leo".
+    /// Numbers are assigned to vals and anything else is assigned to names, matching the rules
+    /// used when importing tables. Also used to store the starting values of presets, see
+    /// DialogueTreeData::presets
+    #[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+    pub struct Assumptions {
+        pub names: NameTable,
+        pub vals: ValTable,
+    }
+
+    impl Assumptions {
+        /// Get the names and values of a project with the assumptions applied on top. Keys
+        /// that aren't assumed keep their value from the project
+        pub fn apply(&self, data: &DialogueTreeData) -> (NameTable, ValTable) {
+            let mut names = data.name_table.clone();
+            let mut vals = data.val_table.clone();
+            names.extend(self.names.iter().map(|(k, v)| (*k, *v)));
+            vals.extend(self.vals.iter().map(|(k, v)| (*k, *v)));
+            (names, vals)
         }
 
-        impl Executable for Project {
-            /// New Project
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                let new_project = DialogueTreeData::new(self.name.as_str());
+        /// Assume the names and values of another set of assumptions on top of these
+        pub fn merge(&mut self, other: &Assumptions) {
+            self.names.extend(other.names.iter().map(|(k, v)| (*k, *v)));
+            self.vals.extend(other.vals.iter().map(|(k, v)| (*k, *v)));
+        }
+
+        /// Get the assumed names and values that aren't in the project's tables
+        pub fn missing_keys(&self, data: &DialogueTreeData) -> Vec<KeyString> {
+            let mut missing: Vec<KeyString> = self
+                .names
+                .keys()
+                .filter(|key| !data.name_table.contains_key(*key))
+                .chain(
+                    self.vals
+                        .keys()
+                        .filter(|key| !data.val_table.contains_key(*key)),
+                )
+                .copied()
+                .collect();
+            missing.sort();
+            missing
+        }
+    }
 
-                let encoded = bincode::serialize(&new_project)?;
-                let _res = std::fs::write(self.name.clone() + TREE_EXT, encoded);
+    /// Written as the comma separated list of assignments it is parsed from, sorted by key
+    impl std::fmt::Display for Assumptions {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let mut assignments: Vec<String> = self
+                .names
+                .iter()
+                .map(|(key, name)| format!("{}={}", key, name))
+                .chain(
+                    self.vals
+                        .iter()
+                        .map(|(key, value)| format!("{}={}", key, value)),
+                )
+                .collect();
+            assignments.sort();
+            write!(f, "{}", assignments.join(","))
+        }
+    }
 
-                if self.set_active {
-                    *state = EditorState::new(new_project);
+    impl std::str::FromStr for Assumptions {
+        type Err = anyhow::Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let mut assumptions = Assumptions::default();
+            for assignment in s.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+                let invalid = || cmd::Error::InvalidAssumption(assignment.to_string());
+                let (key, value) = assignment.split_once('=').ok_or_else(invalid)?;
+                let key = KeyString::from(key.trim()).map_err(|_| invalid())?;
+                let value = value.trim();
+                match value.parse::<u32>() {
+                    Ok(num) => {
+                        assumptions.vals.insert(key, num);
+                    }
+                    Err(_) => {
+                        let name = NameString::from(value).map_err(|_| invalid())?;
+                        assumptions.names.insert(key, name);
+                    }
                 }
-                Ok(state.active.uid)
             }
+            Ok(assumptions)
         }
+    }
 
-        /// Create a new node in the dialogue tree
-        ///
-        /// A node represents a text a segment of dialogue from a character.
-        #[derive(new, StructOpt, Debug)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Node {
-            /// The speaker for this node. The speaker name must be a key in the name table
-            speaker: String,
-            /// The text or action for this node
-            dialogue: String,
-        }
+    /// Comparison made by the condition of an assertion
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Compare {
+        Greater,
+        GreaterEq,
+        Less,
+        LessEq,
+        Equal,
+        NotEqual,
+    }
 
-        impl Executable for Node {
-            /// New Node
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("Creating new node");
+    impl Compare {
+        /// Operators in the order they are searched for, so that >= is found before >
+        const OPERATORS: [(&'static str, Compare); 6] = [
+            (">=", Compare::GreaterEq),
+            ("<=", Compare::LessEq),
+            ("!=", Compare::NotEqual),
+            (">", Compare::Greater),
+            ("<", Compare::Less),
+            ("=", Compare::Equal),
+        ];
+
+        fn test<T: PartialOrd>(&self, current: &T, expected: &T) -> bool {
+            match self {
+                Compare::Greater => current > expected,
+                Compare::GreaterEq => current >= expected,
+                Compare::Less => current < expected,
+                Compare::LessEq => current <= expected,
+                Compare::Equal => current == expected,
+                Compare::NotEqual => current != expected,
+            }
+        }
+    }
 
-                trace!("verify the speaker name is valid");
-                state
-                    .active
-                    .name_table
-                    .get(self.speaker.as_str())
-                    .ok_or(cmd::Error::NameNotExists)?;
+    impl std::fmt::Display for Compare {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let (op, _) = Compare::OPERATORS
+                .iter()
+                .find(|(_, compare)| compare == self)
+                .ok_or(std::fmt::Error)?;
+            f.write_str(op)
+        }
+    }
 
-                trace!("push dialogue to text buffer");
-                let start = state.active.text.len();
-                state.active.text.push_str(&format!(
-                    "{}{}{}{}",
-                    TOKEN_SEP, self.speaker, TOKEN_SEP, self.dialogue
-                ));
-                let end = state.active.text.len();
-                debug!("start: {}, end: {}", start, end);
+    /// Condition on a name or value at the end of an assertion's path, e.g. gold >= 50 or
+    /// cat = Kot. Numbers are compared against vals and anything else against names, which can
+    /// only be tested with = and !=
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Condition {
+        pub key: KeyString,
+        pub compare: Compare,
+        pub value: String,
+    }
 
-                trace!("compute hash from text section");
-                let hash = hash(&state.active.text[start..end].as_bytes());
-                debug!("hash {}", hash);
+    impl std::str::FromStr for Condition {
+        type Err = anyhow::Error;
+
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            let (at, op, compare) = Compare::OPERATORS
+                .iter()
+                .filter_map(|(op, compare)| s.find(op).map(|at| (at, *op, *compare)))
+                .min_by_key(|(at, _, _)| *at)
+                .ok_or(cmd::Error::Generic)?;
+            let key = KeyString::from(s[..at].trim()).map_err(|e| e.simplify())?;
+            let value = s[at + op.len()..].trim().to_string();
+            let is_name = value.parse::<u32>().is_err();
+            anyhow::ensure!(
+                !key.is_empty()
+                    && !value.is_empty()
+                    && (!is_name || matches!(compare, Compare::Equal | Compare::NotEqual)),
+                cmd::Error::Generic
+            );
+            Ok(Condition {
+                key,
+                compare,
+                value,
+            })
+        }
+    }
 
-                let dialogue =
-                    Dialogue::new(Section::new([start, end], hash), Position::new(0.0, 0.0));
+    impl Condition {
+        /// Check the condition against the names and values of a runner. Returns a description
+        /// of the mismatch if the condition does not hold
+        fn failure(&self, names: &NameTable, vals: &ValTable) -> Option<String> {
+            let current = match self.value.parse::<u32>() {
+                Ok(expected) => match vals.get(&self.key) {
+                    Some(current) if self.compare.test(current, &expected) => return None,
+                    Some(current) => current.to_string(),
+                    None => "missing".to_string(),
+                },
+                Err(_) => match names.get(&self.key) {
+                    Some(current) if self.compare.test(&current.as_str(), &self.value.as_str()) => {
+                        return None
+                    }
+                    Some(current) => current.to_string(),
+                    None => "missing".to_string(),
+                },
+            };
+            Some(format!(
+                "expected {} {} {}, found {}",
+                self.key, self.compare, self.value, current
+            ))
+        }
+    }
 
-                trace!("add new node to tree");
-                let event = state.active.tree.add_node(dialogue)?;
-                let idx = event.index;
-                state.history.push(event.into());
+    /// A check of story logic: playing a path of choices from a start node must end at a given
+    /// node, with names and values meeting the given conditions. Written one per line as
+    ///
+    /// `[from <node>] path <edge>,<edge>,... [ends at <node>] [with <condition>, ...]`
+    ///
+    /// for example `path 0,1,2 ends at ending_good with gold >= 50, cat = Kot`. Nodes are given
+    /// by index or by bookmark, and the playthrough starts at node 0 unless a start is given.
+    /// Calls return automatically, as in Runner::replay
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Assertion {
+        /// Line of the assertion in its file, starting from 1
+        pub line: usize,
+        /// The assertion as written
+        pub text: String,
+        pub start: Option<String>,
+        pub path: Vec<EdgeIndex>,
+        pub end: Option<String>,
+        pub conditions: Vec<Condition>,
+    }
 
-                Ok(idx)
+    impl Assertion {
+        /// Parse every assertion in a file. Blank lines and lines starting with # are skipped
+        ///
+        /// # Errors
+        /// Error with AssertionParse if any line is not a valid assertion
+        pub fn parse_all(s: &str) -> Result<Vec<Assertion>> {
+            let mut assertions = Vec::new();
+            for (i, line) in s.lines().enumerate() {
+                let text = line.trim();
+                if text.is_empty() || text.starts_with('#') {
+                    continue;
+                }
+                let assertion =
+                    Assertion::parse(i + 1, text).map_err(|_| cmd::Error::AssertionParse(i + 1))?;
+                assertions.push(assertion);
             }
+            Ok(assertions)
         }
 
-        /// Create a new edge in the dialogue tree
-        ///
-        /// An edge represents an action from the player that connects two nodes
-        #[derive(new, StructOpt)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Edge {
-            /// dialogue node index that this action originates from
-            source: usize,
-            /// dialogue node index that this action will lead to
-            target: usize,
-            /// Action text or dialogue
-            text: String,
-            /// Requirement for accessing this edge
-            #[structopt(short = "r")]
-            requirement: Option<ReqKind>,
-
-            /// Effect caused by accessing this edge
-            #[structopt(short = "e")]
-            effect: Option<EffectKind>,
+        fn parse(line: usize, text: &str) -> Result<Assertion> {
+            let (rule, conditions) = match text.split_once(" with ") {
+                Some((rule, conditions)) => (rule, conditions.split(',').collect()),
+                None => (text, Vec::new()),
+            };
+            let mut words = rule.split_whitespace().peekable();
+            let mut start = None;
+            if words.peek() == Some(&"from") {
+                words.next();
+                start = Some(words.next().ok_or(cmd::Error::Generic)?.to_string());
+            }
+            anyhow::ensure!(words.next() == Some("path"), cmd::Error::Generic);
+            let path = words
+                .next()
+                .ok_or(cmd::Error::Generic)?
+                .split(',')
+                .map(|edge| edge.trim().parse::<EdgeIndex>())
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut end = None;
+            if let Some(word) = words.next() {
+                anyhow::ensure!(
+                    word == "ends" && words.next() == Some("at"),
+                    cmd::Error::Generic
+                );
+                end = Some(words.next().ok_or(cmd::Error::Generic)?.to_string());
+            }
+            anyhow::ensure!(words.next().is_none(), cmd::Error::Generic);
+            Ok(Assertion {
+                line,
+                text: text.to_string(),
+                start,
+                path,
+                end,
+                conditions: conditions
+                    .into_iter()
+                    .map(str::parse::<Condition>)
+                    .collect::<Result<_>>()?,
+            })
         }
 
-        impl Executable for Edge {
-            /// New Edge
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("Creating new edge");
-
-                trace!("push choice text buffer");
-                let start = state.active.text.len();
-                state.active.text.push_str(&self.text);
-                let end = state.active.text.len();
-                debug!("start: {}, end: {}", start, end);
-
-                trace!("Compute hash from text section");
-                let hash = hash(&state.active.text[start..end].as_bytes());
-                debug!("hash {}", hash);
+        /// Find a node by index or by bookmark
+        fn node(data: &DialogueTreeData, node: &str) -> Option<NodeIndex> {
+            let index = node
+                .parse::<NodeIndex>()
+                .ok()
+                .or_else(|| data.bookmarks.get(node).copied())?;
+            data.tree.get_node(index).ok().map(|_| index)
+        }
 
-                trace!("Validate that any requirements/effects reference valid hashmap keys");
-                if self.requirement.is_some() {
-                    util::validate_requirement(
-                        self.requirement.as_ref().ok_or(cmd::Error::Generic)?,
-                        &state.active.name_table,
-                        &state.active.val_table,
-                    )?;
+        /// Play the path of the assertion through a tree. Returns a description of the first
+        /// thing that did not go as asserted, or None if the assertion holds
+        pub fn failure(&self, data: &DialogueTreeData) -> Option<String> {
+            self.failure_with(data, &Assumptions::default())
+        }
+
+        /// Play the path of the assertion from the given starting names and values, see
+        /// Runner::with_assumptions and Assertion::failure
+        pub fn failure_with(
+            &self,
+            data: &DialogueTreeData,
+            assumptions: &Assumptions,
+        ) -> Option<String> {
+            let start = match &self.start {
+                Some(start) => match Assertion::node(data, start) {
+                    Some(start) => start,
+                    None => return Some(format!("start node {} does not exist", start)),
+                },
+                None => 0,
+            };
+            let mut runner = match Runner::with_assumptions(data, start, assumptions) {
+                Ok(runner) => runner,
+                Err(e) => return Some(e.to_string()),
+            };
+            for &edge in self.path.iter() {
+                if runner.can_return(data).unwrap_or(false) {
+                    let _res = runner.return_from_call(data);
                 }
-                if self.effect.is_some() {
-                    util::validate_effect(
-                        self.effect.as_ref().ok_or(cmd::Error::Generic)?,
-                        &state.active.name_table,
-                        &state.active.val_table,
-                    )?;
+                if runner.choose(data, edge).is_err() {
+                    return Some(format!(
+                        "edge {} is not an available choice at node {}",
+                        edge, runner.node
+                    ));
                 }
+            }
+            if let Some(end) = &self.end {
+                match Assertion::node(data, end) {
+                    Some(end) if end == runner.node => {}
+                    Some(_) => return Some(format!("ended at node {}, not {}", runner.node, end)),
+                    None => return Some(format!("end node {} does not exist", end)),
+                }
+            }
+            self.conditions
+                .iter()
+                .find_map(|condition| condition.failure(&runner.names, &runner.vals))
+        }
+    }
 
-                let choice = Choice::new(
-                    Section::new([start, end], hash),
-                    self.requirement.clone().unwrap_or(ReqKind::No),
-                    self.effect.clone().unwrap_or(EffectKind::No),
-                );
+    /// Lowest, highest, and total of a number recorded once per run, see Simulation
+    #[derive(Debug, Default, Clone, Copy, PartialEq)]
+    pub struct Spread {
+        pub min: u32,
+        pub max: u32,
+        pub total: u64,
+        /// Number of values recorded
+        pub count: usize,
+    }
 
-                trace!("Adding new edge to tree");
-                let event = state
-                    .active
-                    .tree
-                    .add_edge(self.source, self.target, choice)?;
-                let idx = event.index;
+    impl Spread {
+        fn record(&mut self, value: u32) {
+            if self.count == 0 {
+                self.min = value;
+                self.max = value;
+            } else {
+                self.min = self.min.min(value);
+                self.max = self.max.max(value);
+            }
+            self.total += value as u64;
+            self.count += 1;
+        }
 
-                state.history.push(event.into());
-                Ok(idx)
+        /// Mean of the recorded values, 0 if there are none
+        pub fn mean(&self) -> f64 {
+            match self.count {
+                0 => 0.0,
+                count => self.total as f64 / count as f64,
             }
         }
+    }
 
-        /// Create a new name for use in dialogue nodes and actions
-        ///
-        /// A name represents some variable that may be substituted into the text. Examples
-        /// include player names, pronouns, and character traits
-        #[derive(new, StructOpt, Debug)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Name {
-            /// The keyword to reference the name with in the text. Maximum length of 8 characters
-            key: KeyString,
-            /// The name to store, able be updated by player actions. Maximum length of 32
-            /// characters
-            name: NameString,
+    impl std::fmt::Display for Spread {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(
+                f,
+                "min {}, mean {:.1}, max {}",
+                self.min,
+                self.mean(),
+                self.max
+            )
         }
-        impl Executable for Name {
-            /// New Name
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("Create new name");
+    }
 
-                trace!("check that key does not already exist");
-                if state.active.name_table.get(self.key.as_str()).is_none() {
-                    trace!("add key and name to table");
-                    state.active.name_table.insert(self.key, self.name);
+    /// Outcome statistics of a batch of playthroughs, see simulate
+    #[derive(Debug, Default, Clone, PartialEq)]
+    pub struct Simulation {
+        /// Seed of the first run, None if the runs were deterministic
+        pub seed: Option<u64>,
+        pub runs: usize,
+        /// Number of runs that ended at each node
+        pub endings: BTreeMap<NodeIndex, usize>,
+        /// Number of runs stopped by the step limit before reaching an ending
+        pub unfinished: usize,
+        /// Number of times each choice was made, over all runs
+        pub choices: BTreeMap<EdgeIndex, usize>,
+        /// Number of choices made by each run
+        pub steps: Spread,
+        /// Final value of each val at the end of each run
+        pub vals: BTreeMap<String, Spread>,
+    }
 
-                    state.history.push(
-                        NameTableInsert {
-                            key: self.key,
-                            name: self.name,
-                        }
-                        .into(),
-                    );
+    impl std::fmt::Display for Simulation {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let share = |count: usize| 100.0 * count as f64 / self.runs.max(1) as f64;
+            match self.seed {
+                Some(seed) => write!(f, "{} runs from seed {}\r\n", self.runs, seed)?,
+                None => write!(f, "{} deterministic runs\r\n", self.runs)?,
+            }
+            for (node, count) in self.endings.iter() {
+                write!(
+                    f,
+                    "ended at node {}: {} ({:.1}%)\r\n",
+                    node,
+                    count,
+                    share(*count)
+                )?;
+            }
+            if self.unfinished > 0 {
+                write!(
+                    f,
+                    "unfinished: {} ({:.1}%)\r\n",
+                    self.unfinished,
+                    share(self.unfinished)
+                )?;
+            }
+            write!(f, "choices made: {}\r\n", self.steps)?;
+            for (edge, count) in self.choices.iter() {
+                write!(f, "edge {}: taken {} times\r\n", edge, count)?;
+            }
+            for (key, spread) in self.vals.iter() {
+                write!(f, "val {}: {}\r\n", key, spread)?;
+            }
+            Ok(())
+        }
+    }
 
-                    Ok(0)
-                } else {
-                    Err(cmd::Error::NameExists.into())
+    /// Make a batch of playthroughs from the given node and starting values, and collect where
+    /// they end
+    ///
+    /// Each run makes choices with Runner::choose_random, returning from calls as they end and
+    /// answering prompts with EffectKind::PLAYER_INPUT. Run i is seeded with seed + i, so the
+    /// batch is the same every time for the same seed, and any one run can be reproduced by
+    /// seeding a runner with its seed. Without a seed every run takes the first available
+    /// choice. A run ends at a node without available choices, or is stopped after max_steps
+    /// choices
+    ///
+    /// # Errors
+    /// Error if the start node does not exist
+    pub fn simulate(
+        data: &DialogueTreeData,
+        start: NodeIndex,
+        assumptions: &Assumptions,
+        seed: Option<u64>,
+        runs: usize,
+        max_steps: usize,
+    ) -> Result<Simulation> {
+        let mut simulation = Simulation {
+            seed,
+            runs,
+            ..Default::default()
+        };
+        for run in 0..runs {
+            let mut runner = Runner::with_assumptions(data, start, assumptions)?;
+            runner.seed(seed.map(|seed| seed.wrapping_add(run as u64)));
+            let mut steps = 0;
+            let ended = loop {
+                if runner.prompt.is_some() {
+                    runner.answer(data, EffectKind::PLAYER_INPUT)?;
+                }
+                if runner.can_return(data)? {
+                    runner.return_from_call(data)?;
                 }
+                if steps == max_steps {
+                    break false;
+                }
+                match runner.choose_random(data)? {
+                    Some(edge) => *simulation.choices.entry(edge).or_default() += 1,
+                    None => break true,
+                }
+                steps += 1;
+            };
+            if ended {
+                *simulation.endings.entry(runner.node).or_default() += 1;
+            } else {
+                simulation.unfinished += 1;
+            }
+            simulation.steps.record(steps as u32);
+            for (key, value) in runner.vals.iter() {
+                simulation
+                    .vals
+                    .entry(key.to_string())
+                    .or_default()
+                    .record(*value);
             }
         }
+        Ok(simulation)
+    }
 
-        /// Create a new value for use in dialogue nodes and actions
-        ///
-        /// A value represents some variable number that is used as requirements and effects for
-        /// choices. Examples include player skill levels, relationship stats, and presence of an item.
-        #[derive(new, StructOpt, Debug)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Val {
-            /// The keyword to reference the value with in the dialogue tree. Max length of 8
-            /// characters
-            key: KeyString,
-            /// Value to store, able be updated by player actions
-            value: u32,
+    /// Where a runner ended up after its tree was reloaded, see Runner::hot_reload
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Reload {
+        /// The current node was found in the reloaded tree, possibly at another index
+        Found(NodeIndex),
+        /// The current node was found in the reloaded tree, and its text was edited
+        Edited(NodeIndex),
+        /// The current node was deleted, the runner moved back to the most recent node on its
+        /// path that still exists
+        Rewound(NodeIndex),
+        /// Nothing on the path still exists, the runner restarted at the start node
+        Restarted(NodeIndex),
+    }
+
+    /// Finds the nodes and edges of one version of a tree in a later version of it. Indices are
+    /// not stable across edits, since removals move the last node or edge into the removed
+    /// index, so nodes and edges are identified by their line id instead, see LineId
+    struct Remapper<'a> {
+        old: &'a DialogueTreeData,
+        new: &'a DialogueTreeData,
+        /// Index of the node in the new tree with each line id
+        nodes: HashMap<u32, NodeIndex>,
+        /// Index of the edge in the new tree with each line id
+        edges: HashMap<u32, EdgeIndex>,
+    }
+
+    impl<'a> Remapper<'a> {
+        fn new(old: &'a DialogueTreeData, new: &'a DialogueTreeData) -> Self {
+            let nodes = new.tree.nodes().iter().enumerate();
+            let edges = new.tree.edges().iter().enumerate();
+            Remapper {
+                old,
+                new,
+                nodes: nodes.map(|(index, node)| (node.line.id, index)).collect(),
+                edges: edges.map(|(index, edge)| (edge.line.id, index)).collect(),
+            }
         }
-        impl Executable for Val {
-            /// New Val
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("Create new val");
 
-                trace!("check that key does not already exist");
-                if state.active.val_table.get(self.key.as_str()).is_none() {
-                    trace!("add key and val to table");
-                    state.active.val_table.insert(self.key, self.value);
+        /// Find a node by its line id. Nodes without a line id are only found at the same
+        /// index, and only if no nodes were added or removed, since the index would otherwise be
+        /// taken by another node. Returns None if the node no longer exists
+        fn node(&self, index: NodeIndex) -> Option<NodeIndex> {
+            let line = self.old.tree.get_node(index).ok()?.line;
+            if line.id != 0 {
+                return self.nodes.get(&line.id).copied();
+            }
+            let same_len = self.old.tree.nodes().len() == self.new.tree.nodes().len();
+            Some(index).filter(|_| same_len && self.new.tree.get_node(index).is_ok())
+        }
 
-                    state.history.push(
-                        ValTableInsert {
-                            key: self.key,
-                            value: self.value,
-                        }
-                        .into(),
-                    );
+        /// Check if the text of a node was edited between the versions, by its line revision
+        fn edited(&self, old: NodeIndex, new: NodeIndex) -> bool {
+            let old = self.old.tree.get_node(old).map(|node| node.line);
+            let new = self.new.tree.get_node(new).map(|node| node.line);
+            old.ok() != new.ok()
+        }
 
-                    Ok(self.value as usize)
-                } else {
-                    Err(cmd::Error::ValExists.into())
-                }
+        /// Find an edge by its line id. Edges without a line id are found if they are the only
+        /// edge between the remapped source and target nodes
+        fn edge(&self, index: EdgeIndex) -> Option<EdgeIndex> {
+            let line = self.old.tree.get_edge(index).ok()?.line;
+            if line.id != 0 {
+                return self.edges.get(&line.id).copied();
+            }
+            let source = self.node(self.old.tree.source_of(index).ok()?)?;
+            let target = self.node(self.old.tree.target_of(index).ok()?)?;
+            let candidates: Vec<EdgeIndex> = self
+                .new
+                .tree
+                .outgoing_from_index(source)
+                .ok()?
+                .filter(|&edge| self.new.tree.target_of(edge).ok() == Some(target))
+                .collect();
+            match candidates.as_slice() {
+                [edge] => Some(*edge),
+                _ => None,
             }
         }
     }
 
-    mod edit {
-        use super::*;
+    /// Something that happened during a playthrough, sent to the receiver returned by
+    /// Runner::subscribe. Lets a game drive subtitles, analytics or achievements without polling
+    /// the runner after every call
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum RunnerEvent {
+        /// The runner moved to a node, with the speaker name and text of the node
+        LineStarted {
+            node: NodeIndex,
+            speaker: String,
+            text: String,
+        },
+        /// The choices available from the current node, in order. Not sent if there are none
+        ChoicePresented {
+            node: NodeIndex,
+            choices: Vec<(EdgeIndex, String)>,
+        },
+        /// A choice was made, with its text as it was shown
+        ChoiceTaken { edge: EdgeIndex, text: String },
+        /// The effect of a choice was applied. Not sent for choices without an effect
+        EffectApplied { edge: EdgeIndex, effect: EffectKind },
+        /// An on-enter effect of a node was applied as the node was shown, before its line
+        EntryEffectApplied { node: NodeIndex, effect: EffectKind },
+        /// The runner reached a node with no choices outside of a call
+        ConversationEnded { node: NodeIndex },
+    }
 
-        /// Edit existing things
-        #[enum_dispatch(Executable)]
-        #[derive(StructOpt)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub enum Parse {
-            Node(edit::Node),
-            Edge(edit::Edge),
-            Name(edit::Name),
-            Val(edit::Val),
-        }
+    /// State of a playthrough of a dialogue tree
+    #[derive(Debug, Clone)]
+    pub struct Runner {
+        /// The node currently being shown
+        pub node: NodeIndex,
+        /// Names as modified by the choices made so far
+        pub names: NameTable,
+        /// Values as modified by the choices made so far
+        pub vals: ValTable,
+        /// Edges chosen so far, in order
+        pub path: Vec<EdgeIndex>,
+        /// Position of the interrupt that moved the runner to the current node, if any
+        pub interrupt: Option<usize>,
+        /// Call edges taken that have not returned yet, the innermost call is last
+        pub calls: Vec<EdgeIndex>,
+        /// Conversation scoped values of the caller of each call, restored when the call returns
+        pub saved: Vec<ValTable>,
+        /// Name key the player was asked to enter by the effect of the last choice or an on-enter
+        /// effect of the current node, until the answer is given with Runner::answer
+        pub prompt: Option<KeyString>,
+        /// Where events are sent, if the runner has a subscriber
+        events: Option<mpsc::Sender<RunnerEvent>>,
+        /// Picks the choices made by Runner::choose_random, None in deterministic mode
+        rng: Option<rand::rngs::StdRng>,
+    }
 
-        /// Edit the contents of a node in the dialogue tree
-        ///
-        /// A node represents a text a segment of dialogue from a character.
-        #[derive(new, StructOpt, Debug)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Node {
-            /// Index of the node to edit
-            node_index: usize,
-            /// The speaker for this node
-            speaker: KeyString,
-            /// The text or action for this node
-            dialogue: String,
+    impl Runner {
+        /// Start a playthrough at the given node, with the project's initial names and values.
+        /// Interrupts are checked and on-enter effects applied before the first choices are
+        /// presented
+        pub fn new(data: &DialogueTreeData, start: NodeIndex) -> Result<Self> {
+            Runner::with_assumptions(data, start, &Assumptions::default())
         }
-        impl Executable for Node {
-            /// Edit Node
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("Edit node {}", self.node_index);
 
-                trace!("push new dialogue to text buffer");
-                let start = state.active.text.len();
-                state.active.text.push_str(&format!(
-                    "{}{}{}{}",
-                    TOKEN_SEP, self.speaker, TOKEN_SEP, self.dialogue
-                ));
-                let end = state.active.text.len();
+        /// Start a playthrough at the given node, with the assumed names and values applied on
+        /// top of the project's initial ones, such as a preset from DialogueTreeData::presets.
+        /// Values reset by a new conversation go back to the project's initial values
+        pub fn with_assumptions(
+            data: &DialogueTreeData,
+            start: NodeIndex,
+            assumptions: &Assumptions,
+        ) -> Result<Self> {
+            data.tree.get_node(start)?;
+            let (names, vals) = assumptions.apply(data);
+            let mut runner = Runner {
+                node: start,
+                names,
+                vals,
+                path: Vec::new(),
+                interrupt: None,
+                calls: Vec::new(),
+                saved: Vec::new(),
+                prompt: None,
+                events: None,
+                rng: None,
+            };
+            runner.arrive(data);
+            Ok(runner)
+        }
 
-                trace!("get node weight from tree");
-                let old_node = state.active.tree.get_node(self.node_index)?;
+        /// Send events for everything that happens from now on to the returned receiver. The
+        /// current line and its choices are sent straight away, so the receiver always starts
+        /// with a LineStarted event. Subscribing again replaces the previous receiver, and clones
+        /// of the runner send to the same receiver
+        pub fn subscribe(&mut self, data: &DialogueTreeData) -> mpsc::Receiver<RunnerEvent> {
+            let (sender, receiver) = mpsc::channel();
+            self.events = Some(sender);
+            self.announce(data);
+            receiver
+        }
 
-                trace!("recalculate hash");
-                let hash = hash(state.active.text[start..end].as_bytes());
-                debug!("hash {}", hash);
+        /// Send an event to the subscriber, if there is one. The subscriber is dropped once its
+        /// receiver is
+        fn emit(&mut self, event: RunnerEvent) {
+            if let Some(sender) = &self.events {
+                if sender.send(event).is_err() {
+                    self.events = None;
+                }
+            }
+        }
 
-                let new_node = Dialogue::new(Section::new([start, end], hash), old_node.pos);
+        /// Send the events for arriving at the current node: its line, then either its choices
+        /// or the end of the conversation. Nodes that fail to render are skipped
+        fn announce(&mut self, data: &DialogueTreeData) {
+            if self.events.is_none() {
+                return;
+            }
+            let node = self.node;
+            if let Ok((speaker, text)) = self.dialogue(data) {
+                self.emit(RunnerEvent::LineStarted {
+                    node,
+                    speaker,
+                    text,
+                });
+            }
+            let has_choices = match data.tree.outgoing_from_index(node) {
+                Ok(mut outgoing) => outgoing.next().is_some(),
+                Err(_) => return,
+            };
+            if has_choices {
+                if let Ok(choices) = self.choices(data) {
+                    self.emit(RunnerEvent::ChoicePresented { node, choices });
+                }
+            } else if self.calls.is_empty() {
+                self.emit(RunnerEvent::ConversationEnded { node });
+            }
+        }
 
-                trace!("update node weight in tree");
-                let event = state.active.tree.edit_node(self.node_index, new_node)?;
-                state.history.push(event.into());
+        /// Start a new conversation at the given node, keeping the names and global values from
+        /// the playthrough so far. Conversation and temporary values are reset, and any calls in
+        /// progress are abandoned
+        pub fn start_conversation(
+            &mut self,
+            data: &DialogueTreeData,
+            start: NodeIndex,
+        ) -> Result<()> {
+            data.tree.get_node(start)?;
+            self.reset(data, ValScope::Conversation);
+            self.reset(data, ValScope::Temporary);
+            self.node = start;
+            self.calls.clear();
+            self.saved.clear();
+            self.interrupt = None;
+            self.prompt = None;
+            self.arrive(data);
+            self.announce(data);
+            Ok(())
+        }
 
-                Ok(self.node_index)
+        /// Reset every value in a scope to its initial value in the project
+        fn reset(&mut self, data: &DialogueTreeData, scope: ValScope) {
+            for (key, value) in data.val_table.iter() {
+                if ValScope::of(key).ok() == Some(scope) {
+                    self.vals.insert(*key, *value);
+                }
             }
         }
 
-        /// Edit the contents of an edge in the dialogue tree
-        ///
-        /// The source and target node of an edge may not be edited, you must remove the edge and
-        /// then create a new one to do this.
-        #[derive(new, StructOpt)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Edge {
-            /// Id of the edge to edit
-            edge_index: usize,
-            /// Action text or dialogue
-            text: String,
-            /// Requirement for accessing this edge
-            #[structopt(short = "r")]
-            requirement: Option<ReqKind>,
-            /// Effect caused by accessing this edge
-            #[structopt(short = "e")]
-            effect: Option<EffectKind>,
+        /// Copy the current values in a scope
+        fn scoped(&self, scope: ValScope) -> ValTable {
+            self.vals
+                .iter()
+                .filter(|(key, _)| ValScope::of(key).ok() == Some(scope))
+                .map(|(key, value)| (*key, *value))
+                .collect()
         }
 
-        impl Executable for Edge {
-            /// Edit Edge
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("Edit edge {}", self.edge_index);
+        /// Take the first interrupt whose requirement is met by the current names and values, and
+        /// move to its target node. Nothing happens if the runner is already at the target.
+        /// Returns the position of the interrupt taken
+        ///
+        /// This is called after every choice, call it again if the names or values are modified
+        /// outside of the runner
+        pub fn check_interrupts(&mut self, data: &DialogueTreeData) -> Option<usize> {
+            let position = self.take_interrupt(data)?;
+            self.enter(data);
+            self.announce(data);
+            Some(position)
+        }
 
-                trace!("push choice to text buffer");
-                let start = state.active.text.len();
-                state.active.text.push_str(&self.text);
-                let end = state.active.text.len();
+        /// Take the first interrupt whose requirement is met, without sending any events
+        fn take_interrupt(&mut self, data: &DialogueTreeData) -> Option<usize> {
+            let (position, interrupt) = data
+                .interrupts
+                .iter()
+                .enumerate()
+                .find(|(_, i)| requirement_met(&i.requirement, &self.names, &self.vals))?;
+            if interrupt.target == self.node {
+                return None;
+            }
+            self.node = interrupt.target;
+            self.interrupt = Some(position);
+            Some(position)
+        }
 
-                trace!("recalculate hash");
-                let hash = hash(state.active.text[start..end].as_bytes());
-                debug!("hash {}", hash);
+        /// Take any interrupt from the node just moved to, then apply the on-enter effects of the
+        /// node that is shown
+        fn arrive(&mut self, data: &DialogueTreeData) {
+            self.take_interrupt(data);
+            self.enter(data);
+        }
 
-                trace!("validate that any requirements/effects reference valid hashmap keys");
-                if self.requirement.is_some() {
-                    util::validate_requirement(
-                        self.requirement.as_ref().ok_or(cmd::Error::Generic)?,
-                        &state.active.name_table,
-                        &state.active.val_table,
-                    )?;
-                }
-                if self.effect.is_some() {
-                    util::validate_effect(
-                        self.effect.as_ref().ok_or(cmd::Error::Generic)?,
-                        &state.active.name_table,
-                        &state.active.val_table,
-                    )?;
+        /// Apply the on-enter effects of the current node in order. A Prompt effect replaces any
+        /// pending prompt
+        fn enter(&mut self, data: &DialogueTreeData) {
+            let node = self.node;
+            let effects = cmd::util::on_enter_of(data, node).unwrap_or(&[]);
+            for &effect in effects.iter() {
+                apply_effect(&effect, &mut self.names, &mut self.vals);
+                if let EffectKind::Prompt(key) = effect {
+                    self.prompt = Some(key);
                 }
+                self.emit(RunnerEvent::EntryEffectApplied { node, effect });
+            }
+        }
 
-                trace!("update edge weight in tree");
-                let new_weight = Choice::new(
-                    Section::new([start, end], hash),
-                    self.requirement.clone().unwrap_or(ReqKind::No),
-                    self.effect.clone().unwrap_or(EffectKind::No),
-                );
-                let event = state.active.tree.edit_edge(self.edge_index, new_weight)?;
+        /// Get the speaker name and text of the current node, with names substituted from the
+        /// runner's name table
+        pub fn dialogue(&self, data: &DialogueTreeData) -> Result<(String, String)> {
+            let mut renderer = cmd::util::TextRenderer::default();
+            let (name, text) = renderer.node(data, &self.names, self.node)?;
+            Ok((name.to_string(), text.to_string()))
+        }
 
-                state.history.push(event.into());
-                Ok(self.edge_index)
+        /// Get the choices available from the current node in the order of the choice policy,
+        /// along with their text. Choices with unmet requirements are left out
+        pub fn choices(&self, data: &DialogueTreeData) -> Result<Vec<(EdgeIndex, String)>> {
+            Ok(self
+                .all_choices(data)?
+                .into_iter()
+                .filter(|choice| choice.met)
+                .map(|choice| (choice.index, choice.text))
+                .collect())
+        }
+
+        /// Get every choice from the current node, including those with unmet requirements
+        pub fn all_choices(&self, data: &DialogueTreeData) -> Result<Vec<ChoiceState>> {
+            choice_states(data, self.node, &self.names, &self.vals)
+        }
+
+        /// Get the choices from the current node that should be shown to the player. Choices with
+        /// unmet requirements are included to be shown disabled, unless the choice policy hides
+        /// them
+        pub fn shown_choices(&self, data: &DialogueTreeData) -> Result<Vec<ChoiceState>> {
+            let mut choices = self.all_choices(data)?;
+            if data.choice_policy.unmet == UnmetChoices::Hide {
+                choices.retain(|choice| choice.met);
             }
+            Ok(choices)
+        }
+
+        /// Make a choice, applying its effect and moving to its target node, or to the target of an
+        /// interrupt if one is triggered. The on-enter effects of the node moved to are applied
+        /// after the choice's effect. Temporary values are reset before the effect is applied.
+        /// Reaching a node with no choices outside of a call ends the conversation, which resets
+        /// conversation and temporary values
+        ///
+        /// A Prompt effect sets Runner::prompt, and the host should ask the player for the name
+        /// before presenting the next choices. Any earlier prompt that was not answered is dropped
+        ///
+        /// # Errors
+        /// Error if the edge does not leave the current node, or its requirement is not met
+        pub fn choose(&mut self, data: &DialogueTreeData, index: EdgeIndex) -> Result<()> {
+            let edge = data.tree.get_edge(index)?;
+            anyhow::ensure!(
+                data.tree.source_of(index)? == self.node
+                    && requirement_met(&edge.requirement, &self.names, &self.vals),
+                cmd::Error::ChoiceUnavailable(index)
+            );
+            if self.events.is_some() {
+                let mut renderer = cmd::util::TextRenderer::default();
+                let text = renderer.edge(data, &self.names, index)?.to_string();
+                self.emit(RunnerEvent::ChoiceTaken { edge: index, text });
+            }
+            self.reset(data, ValScope::Temporary);
+            apply_effect(&edge.effect, &mut self.names, &mut self.vals);
+            self.prompt = match edge.effect {
+                EffectKind::Prompt(key) => Some(key),
+                _ => None,
+            };
+            if edge.effect != EffectKind::No {
+                self.emit(RunnerEvent::EffectApplied {
+                    edge: index,
+                    effect: edge.effect,
+                });
+            }
+            self.node = data.tree.target_of(index)?;
+            self.path.push(index);
+            if edge.call {
+                self.saved.push(self.scoped(ValScope::Conversation));
+                self.reset(data, ValScope::Conversation);
+                self.calls.push(index);
+            }
+            self.interrupt = None;
+            self.arrive(data);
+            if self.calls.is_empty() && data.tree.outgoing_from_index(self.node)?.next().is_none() {
+                self.reset(data, ValScope::Conversation);
+                self.reset(data, ValScope::Temporary);
+            }
+            self.announce(data);
+            Ok(())
+        }
+
+        /// Seed the random choices made by Runner::choose_random, so that a playthrough can be
+        /// reproduced. None switches to deterministic mode, where the first available choice is
+        /// always taken. Runners start in deterministic mode
+        pub fn seed(&mut self, seed: Option<u64>) {
+            self.rng = seed.map(rand::rngs::StdRng::seed_from_u64);
+        }
+
+        /// Make one of the available choices from the current node, picked at random if the
+        /// runner is seeded, or the first one in deterministic mode. Returns the choice made, or
+        /// None if there are no available choices
+        pub fn choose_random(&mut self, data: &DialogueTreeData) -> Result<Option<EdgeIndex>> {
+            let choices = self.choices(data)?;
+            let picked = match &mut self.rng {
+                _ if choices.is_empty() => return Ok(None),
+                Some(rng) => rng.gen_range(0..choices.len()),
+                None => 0,
+            };
+            let index = choices[picked].0;
+            self.choose(data, index)?;
+            Ok(Some(index))
         }
 
-        /// Edit the value of an existing name
+        /// Store the name the player entered for the pending prompt. The current line is sent to
+        /// the subscriber again, since it may show the name, unless the name triggers an interrupt
         ///
-        /// A name represents some variable that may be substituted into the text. Examples
-        /// include player names, pronouns, and character traits
-        #[derive(new, StructOpt, Debug)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Name {
-            /// The keyword to reference the name with in the text
-            key: KeyString,
-            /// Value of the name to store
-            name: NameString,
+        /// # Errors
+        /// Error if there is no pending prompt, or the answer is empty or too long for a name
+        pub fn answer(&mut self, data: &DialogueTreeData, answer: &str) -> Result<()> {
+            let key = self.prompt.ok_or(cmd::Error::NoPrompt)?;
+            let answer = answer.trim();
+            let name = NameString::from(answer)
+                .ok()
+                .filter(|name| !name.is_empty())
+                .ok_or_else(|| cmd::Error::InvalidAnswer(answer.to_string()))?;
+            self.names.insert(key, name);
+            self.prompt = None;
+            if self.take_interrupt(data).is_some() {
+                self.enter(data);
+            }
+            self.announce(data);
+            Ok(())
         }
 
-        impl Executable for Name {
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("Edit name {}", self.key);
+        /// Check if the current node ends a called conversation, in which case the playthrough
+        /// should continue with return_from_call rather than ending
+        pub fn can_return(&self, data: &DialogueTreeData) -> Result<bool> {
+            Ok(
+                !self.calls.is_empty()
+                    && data.tree.outgoing_from_index(self.node)?.next().is_none(),
+            )
+        }
 
-                trace!("check that key exists before editing");
-                if state.active.name_table.get(&self.key).is_some() {
-                    let name = state
-                        .active
-                        .name_table
-                        .get_mut(&self.key)
-                        .ok_or(cmd::Error::Generic)?;
-                    let old_name = *name;
-                    debug!("old name: {}, new name: {}", old_name, self.name);
+        /// Return from the innermost call to the node the call was made from, whose choices are
+        /// presented again. The on-enter effects of the node are not applied again. Returns the
+        /// node returned to
+        ///
+        /// # Errors
+        /// Error if there is no call to return from, or the current node still has choices
+        pub fn return_from_call(&mut self, data: &DialogueTreeData) -> Result<NodeIndex> {
+            anyhow::ensure!(self.can_return(data)?, cmd::Error::NoCallToReturn);
+            let call = self.calls.pop().ok_or(cmd::Error::NoCallToReturn)?;
+            self.node = data.tree.source_of(call)?;
+            self.reset(data, ValScope::Temporary);
+            if let Some(saved) = self.saved.pop() {
+                self.vals.extend(saved);
+            }
+            self.interrupt = None;
+            if self.take_interrupt(data).is_some() {
+                self.enter(data);
+            }
+            self.announce(data);
+            Ok(self.node)
+        }
 
-                    trace!("update key-value in name table");
-                    *name = self.name;
+        /// Get the stage directions of the current node, in order
+        pub fn directions<'a>(&self, data: &'a DialogueTreeData) -> Result<&'a [Direction]> {
+            cmd::util::directions_of(data, self.node)
+        }
 
-                    state.history.push(
-                        NameTableEdit {
-                            key: self.key,
-                            from: old_name,
-                            to: self.name,
-                        }
-                        .into(),
-                    );
+        /// Get the time limit for choosing from the current node, or None if the choice is untimed
+        pub fn timeout(&self, data: &DialogueTreeData) -> Result<Option<std::time::Duration>> {
+            Ok(data
+                .tree
+                .get_node(self.node)?
+                .timeout
+                .map(|ms| std::time::Duration::from_millis(ms as u64)))
+        }
 
-                    Ok(0)
-                } else {
-                    Err(cmd::Error::NameNotExists.into())
-                }
-            }
+        /// Get the choice to take from the current node when the time limit runs out
+        pub fn default_choice(&self, data: &DialogueTreeData) -> Result<Option<EdgeIndex>> {
+            Ok(cmd::util::default_choices(&data.tree, self.node)?
+                .first()
+                .copied())
         }
 
-        /// Edit an existing value
+        /// Take the default choice from the current node, as when a timed decision runs out
         ///
-        /// A value represents some variable number that is used as requirements and effects for
-        /// choices. Examples include player skill levels, relationship stats, and presence of an item.
-        #[derive(new, StructOpt, Debug)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Val {
-            /// The keyword to reference the name with in the text
-            key: KeyString,
-            /// Value to store to the name
-            value: u32,
+        /// # Errors
+        /// Error if the current node has no default choice
+        pub fn choose_default(&mut self, data: &DialogueTreeData) -> Result<EdgeIndex> {
+            let index = self
+                .default_choice(data)?
+                .ok_or(cmd::Error::TimeoutWithoutDefault(self.node))?;
+            self.choose(data, index)?;
+            Ok(index)
         }
 
-        impl Executable for Val {
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("Edit val {}", self.key);
+        /// Move the playthrough from one version of a tree onto a reloaded version of it, keeping
+        /// its names and values, so that dialogue can be edited while a game is running
+        ///
+        /// Nodes and edges are found in the new tree by their line id, since indices change as
+        /// the tree is edited. If the current node was deleted, the runner moves back along its
+        /// path to the most recent node that still exists, or to the start node if none do.
+        /// Choices and calls that no longer exist are dropped from the path and call stack. Keys
+        /// added to the new tree's tables are given their initial values, and interrupts are not
+        /// checked until the next choice
+        ///
+        /// # Errors
+        /// Error if the start node is not in the new tree
+        pub fn hot_reload(
+            &mut self,
+            old: &DialogueTreeData,
+            new: &DialogueTreeData,
+            start: NodeIndex,
+        ) -> Result<Reload> {
+            new.tree.get_node(start)?;
+            let remap = Remapper::new(old, new);
+
+            let reload = match remap.node(self.node) {
+                Some(node) if remap.edited(self.node, node) => Reload::Edited(node),
+                Some(node) => Reload::Found(node),
+                None => self
+                    .path
+                    .iter()
+                    .rev()
+                    .find_map(|&edge| remap.node(old.tree.source_of(edge).ok()?))
+                    .map_or(Reload::Restarted(start), Reload::Rewound),
+            };
 
-                trace!("check that key exists before editing");
-                if state.active.name_table.get(&self.key).is_some() {
-                    let value = state
-                        .active
-                        .val_table
-                        .get_mut(&self.key)
-                        .ok_or(cmd::Error::Generic)?;
-                    let old_value = *value;
-                    debug!("old val: {}, new val: {}", old_value, self.value);
+            let mut calls = Vec::with_capacity(self.calls.len());
+            let mut saved = Vec::with_capacity(self.saved.len());
+            for (&call, vals) in self.calls.iter().zip(self.saved.iter()) {
+                if let Some(call) = remap.edge(call) {
+                    calls.push(call);
+                    saved.push(vals.clone());
+                }
+            }
+            self.calls = calls;
+            self.saved = saved;
+            self.path = self
+                .path
+                .iter()
+                .filter_map(|&edge| remap.edge(edge))
+                .collect();
+            if let Reload::Restarted(_) = reload {
+                self.calls.clear();
+                self.saved.clear();
+            }
 
-                    trace!("update key-value in value table");
-                    *value = self.value;
+            for (key, name) in new.name_table.iter() {
+                self.names.entry(*key).or_insert(*name);
+            }
+            for (key, value) in new.val_table.iter() {
+                self.vals.entry(*key).or_insert(*value);
+            }
 
-                    state.history.push(
-                        ValTableEdit {
-                            key: self.key,
-                            from: old_value,
-                            to: self.value,
-                        }
-                        .into(),
-                    );
+            self.node = match reload {
+                Reload::Found(node)
+                | Reload::Edited(node)
+                | Reload::Rewound(node)
+                | Reload::Restarted(node) => node,
+            };
+            self.interrupt = self.interrupt.filter(|&position| {
+                new.interrupts.get(position).map(|i| i.target) == Some(self.node)
+            });
+            Ok(reload)
+        }
 
-                    Ok(self.value as usize)
-                } else {
-                    Err(cmd::Error::ValNotExists.into())
+        /// Start a playthrough at the given node and make a series of choices. Replay stops at the
+        /// first choice that is no longer available, for example because the tree was edited
+        pub fn replay(
+            data: &DialogueTreeData,
+            start: NodeIndex,
+            path: &[EdgeIndex],
+        ) -> Result<Self> {
+            Runner::replay_with(data, start, &Assumptions::default(), path, &[])
+        }
+
+        /// Replay a series of choices from the given starting names and values, see
+        /// Runner::with_assumptions and Runner::replay. Prompts are answered in order from the
+        /// answers, prompts without an answer leave the name as it was
+        pub fn replay_with(
+            data: &DialogueTreeData,
+            start: NodeIndex,
+            assumptions: &Assumptions,
+            path: &[EdgeIndex],
+            answers: &[String],
+        ) -> Result<Self> {
+            let mut answers = answers.iter();
+            let mut path = path.iter();
+            let mut runner = Runner::with_assumptions(data, start, assumptions)?;
+            loop {
+                // the start node may prompt before the first choice
+                if runner.prompt.is_some() {
+                    if let Some(answer) = answers.next() {
+                        if runner.answer(data, answer).is_err() {
+                            break;
+                        }
+                    }
+                }
+                let index = match path.next() {
+                    Some(&index) => index,
+                    None => break,
+                };
+                // returns are not recorded in the path, as they are made at every node that ends a
+                // called conversation
+                if runner.can_return(data)? {
+                    runner.return_from_call(data)?;
+                }
+                if runner.choose(data, index).is_err() {
+                    break;
                 }
             }
+            Ok(runner)
         }
     }
+}
 
-    pub mod remove {
-        use super::*;
+/// High level editor API for frontends
+///
+/// The Editor wraps an EditorState and exposes every editing operation as a plain method, so
+/// GUI and other frontends never need to construct cmd structs or modify the EditorState
+/// directly. All modifications to the dialogue tree are recorded in the undo/redo history in the
+/// same way as the equivalent cmd.
+pub mod editor {
+    use super::*;
+    use cmd::{bookmark, direction, edit, interrupt, new, on_enter, remove, rename};
+    use std::sync::{mpsc, Arc, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+    use tree::{EdgeIndex, NodeIndex};
+
+    /// Operations that can be run on a background thread
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum Task {
+        Save,
+        Load,
+        Rebuild,
+    }
 
-        /// Remove existing things
-        #[enum_dispatch(Executable)]
-        #[derive(StructOpt)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub enum Parse {
-            Node(remove::Node),
-            Edge(remove::Edge),
-            Name(remove::Name),
-            Val(remove::Val),
+    /// Notifications reported by background tasks, retrieved with Editor::poll
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Notification {
+        /// The task has completed the given fraction of its work
+        Progress(Task, f32),
+        /// The task has finished, and its result has been applied to the editor. Errors are
+        /// stored as their display string
+        Finished(Task, std::result::Result<(), String>),
+    }
+
+    /// Messages sent from a background thread to the editor
+    enum Message {
+        Progress(f32),
+        Done(Box<Result<Option<DialogueTreeData>>>),
+    }
+
+    /// A task running on a background thread
+    struct Job {
+        task: Task,
+        receiver: mpsc::Receiver<Message>,
+    }
+
+    /// Editor instance for a single project
+    pub struct Editor {
+        state: EditorState,
+        job: Option<Job>,
+        renderer: cmd::util::TextRenderer,
+    }
+
+    impl Editor {
+        /// Create an editor for an existing dialogue tree
+        pub fn new(data: DialogueTreeData) -> Self {
+            Editor {
+                state: EditorState::new(data),
+                job: None,
+                renderer: cmd::util::TextRenderer::default(),
+            }
         }
 
-        /// Remove the contents of a node in the dialogue tree and return the hash of the removed
-        /// node's text section
-        #[derive(new, StructOpt, Debug)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Node {
-            /// Index of the node to remove
-            node_index: usize,
+        /// Create an editor for an existing dialogue tree that saves and loads project files in the
+        /// given store
+        pub fn with_store(
+            data: DialogueTreeData,
+            store: std::sync::Arc<dyn store::ProjectStore>,
+        ) -> Self {
+            Editor {
+                state: EditorState::with_store(data, store),
+                job: None,
+                renderer: cmd::util::TextRenderer::default(),
+            }
         }
-        impl Executable for Node {
-            /// Remove Node
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("Remove node {}", self.node_index);
 
-                let event = state.active.tree.remove_node(self.node_index)?;
-                let hash = event.node.section.hash;
+        /// Consume the editor and return its state. Any background task is abandoned
+        pub fn into_state(self) -> EditorState {
+            self.state
+        }
 
-                state.history.push(event.into());
-                Ok(hash as usize)
-            }
+        /// Returns true if a background task is running
+        ///
+        /// The editor state cannot be modified while a background task is running, all methods
+        /// that modify the state will return an error
+        pub fn is_busy(&self) -> bool {
+            self.job.is_some()
         }
 
-        /// Remove an edge from the dialogue tree and return the hash of the removed edge's text
-        /// section
-        #[derive(new, StructOpt)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Edge {
-            /// Id of the edge to remove
-            edge_index: usize,
+        /// Return an error if a background task is running
+        fn check_idle(&self) -> Result<()> {
+            anyhow::ensure!(self.job.is_none(), cmd::Error::Busy);
+            Ok(())
         }
 
-        impl Executable for Edge {
-            /// Remove Edge
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("Remove Edge {}", self.edge_index);
+        /// Start running a task on a background thread. The closure is given a callback to
+        /// report progress
+        fn spawn<F>(&mut self, task: Task, f: F)
+        where
+            F: FnOnce(&dyn Fn(f32)) -> Result<Option<DialogueTreeData>> + Send + 'static,
+        {
+            let (sender, receiver) = mpsc::channel();
+            std::thread::spawn(move || {
+                let progress = |fraction| {
+                    let _ = sender.send(Message::Progress(fraction));
+                };
+                let res = f(&progress);
+                let _ = sender.send(Message::Done(Box::new(res)));
+            });
+            self.job = Some(Job { task, receiver });
+        }
 
-                trace!("remove edge from tree");
-                let event = state.active.tree.remove_edge(self.edge_index)?;
-                let hash = event.edge.section.hash;
+        /// Save the active project to disk on a background thread. See Editor::save
+        ///
+        /// The project is copied before saving, completion is reported by Editor::poll
+        pub fn save_async(&mut self, force: bool) -> Result<()> {
+            self.check_idle()?;
+            let path = self.state.active.name.clone() + TREE_EXT;
+            if !force {
+                cmd::util::check_uid(self.state.store.as_ref(), &path, self.state.active.uid)?;
+            }
 
-                state.history.push(event.into());
-                Ok(hash as usize)
+            let report =
+                cmd::util::project_key_usage(&self.state.active, self.state.store.as_ref());
+            if !report.is_empty() {
+                warn!("{}", report);
+                self.state.scratchpad.push_str(&report.to_string());
             }
+
+            let data = self.state.active.clone();
+            let store = self.state.store.clone();
+            self.spawn(Task::Save, move |progress| {
+                let max_text_len = store::segment_limit(store.as_ref(), &path)?;
+                progress(0.5);
+                store::replace_data_segmented(
+                    store.as_ref(),
+                    &path,
+                    &data,
+                    store::Compression::None,
+                    max_text_len,
+                )?;
+                progress(1.0);
+                Ok(Some(data))
+            });
+            Ok(())
         }
 
-        /// Remove a name, only allowed if the name is not used anywhere
-        #[derive(new, StructOpt, Debug)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Name {
-            /// The keyword to reference the name with in the text
-            key: KeyString,
+        /// Load a project from disk on a background thread. See Editor::load
+        ///
+        /// The active project is replaced when the load completes, in Editor::poll
+        pub fn load_async(&mut self, name: &str) -> Result<()> {
+            self.check_idle()?;
+            let path = name.to_string() + TREE_EXT;
+            let store = self.state.store.clone();
+            self.spawn(Task::Load, move |progress| {
+                let data = store::read_data(store.as_ref(), &path)?;
+                progress(0.5);
+                cmd::util::validate_tree(&data)?;
+                progress(1.0);
+                Ok(Some(data))
+            });
+            Ok(())
         }
 
-        impl Executable for Name {
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("Remove Name {}", self.key);
+        /// Rebuild the tree and text buffer on a background thread. See Editor::rebuild
+        ///
+        /// The rebuild is performed on a copy of the active project, which replaces the active
+        /// project when the rebuild completes, in Editor::poll
+        pub fn rebuild_async(&mut self) -> Result<()> {
+            self.check_idle()?;
+            let source = self.state.active.clone();
+            let backups = self.state.backups.clone();
+            let projects = self.state.store.clone();
+            self.spawn(Task::Rebuild, move |progress| {
+                backups.write(&projects, &source)?;
+                progress(0.25);
+                let mut rebuilt = source.clone();
+                cmd::util::rebuild_tree(
+                    &source.text,
+                    &source.tree,
+                    &mut rebuilt.text,
+                    &mut rebuilt.tree,
+                )?;
+                progress(0.75);
+                rebuilt.hash_cache.clear();
+                rebuilt.search_index.clear();
+                cmd::util::validate_tree_incremental(&mut rebuilt)?;
+                progress(1.0);
+                Ok(Some(rebuilt))
+            });
+            Ok(())
+        }
 
-                let name = *state
-                    .active
-                    .name_table
-                    .get(&self.key)
-                    .ok_or(cmd::Error::NameNotExists)?;
+        /// Check on the progress of any background task, and apply its result to the editor
+        /// state if it has finished. Returns all notifications since the last poll
+        pub fn poll(&mut self) -> Vec<Notification> {
+            let mut notifications = Vec::new();
+            let job = match self.job.as_ref() {
+                Some(job) => job,
+                None => return notifications,
+            };
+            let task = job.task;
 
-                trace!("check if the key is referenced anywhere in the text");
-                if let Some(_found) = state
-                    .active
-                    .text
-                    .find(format!("{}{}{}", TOKEN_SEP, self.key, TOKEN_SEP).as_str())
-                {
-                    return Err(cmd::Error::NameInUse.into());
+            let res = loop {
+                match job.receiver.try_recv() {
+                    Ok(Message::Progress(fraction)) => {
+                        notifications.push(Notification::Progress(task, fraction))
+                    }
+                    Ok(Message::Done(res)) => break *res,
+                    Err(mpsc::TryRecvError::Empty) => return notifications,
+                    Err(mpsc::TryRecvError::Disconnected) => break Err(cmd::Error::Generic.into()),
                 }
+            };
+            self.job = None;
 
-                trace!("check if the key is referenced in any requirements or effects");
-                for choice in state.active.tree.edges() {
-                    // this match will stop compiling any time a new reqKind is added
-                    match &choice.requirement {
-                        ReqKind::No => Ok(()),
-                        ReqKind::Greater(_, _) => Ok(()),
-                        ReqKind::Less(_, _) => Ok(()),
-                        ReqKind::Equal(_, _) => Ok(()),
-                        ReqKind::Cmp(key, _) => {
-                            if key.eq(self.key.as_str()) {
-                                Err(cmd::Error::NameInUse)
-                            } else {
-                                Ok(())
-                            }
-                        }
-                    }?;
-                    match &choice.effect {
-                        EffectKind::No => Ok(()),
-                        EffectKind::Add(_, _) => Ok(()),
-                        EffectKind::Sub(_, _) => Ok(()),
-                        EffectKind::Set(_, _) => Ok(()),
-                        EffectKind::Assign(key, _) => {
-                            if key.eq(self.key.as_str()) {
-                                Err(cmd::Error::NameInUse)
-                            } else {
-                                Ok(())
-                            }
-                        }
-                    }?;
+            let res = res.map(|data| self.apply(task, data));
+            if let Err(e) = res.as_ref() {
+                if let Some(cmd::Error::RebuildFailed(problems)) = e.downcast_ref::<cmd::Error>() {
+                    for problem in problems.iter() {
+                        self.state.scratchpad.push_str(&format!("{}\r\n", problem));
+                    }
                 }
+            }
+            notifications.push(Notification::Finished(task, res.map_err(|e| e.to_string())));
+            notifications
+        }
 
-                trace!("remove key-value pair from name table");
-                state
-                    .active
-                    .name_table
-                    .remove(self.key.as_str())
-                    .ok_or(cmd::Error::NameNotExists)?;
+        /// Block until any background task has finished, returning all notifications since the
+        /// last poll
+        pub fn wait(&mut self) -> Vec<Notification> {
+            let mut notifications = Vec::new();
+            while self.is_busy() {
+                notifications.append(&mut self.poll());
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            notifications
+        }
 
-                state.history.push(
-                    NameTableRemove {
-                        key: self.key,
-                        name,
+        /// Apply the result of a finished background task to the editor state
+        fn apply(&mut self, task: Task, data: Option<DialogueTreeData>) {
+            let data = match data {
+                Some(data) => data,
+                None => return,
+            };
+            match task {
+                Task::Save => {
+                    if let Some(journals) = self.state.journal_store() {
+                        let mut journal = Journal::new(journals, &data);
+                        if let Err(e) = journal.discard() {
+                            warn!("failed to discard journal: {}", e);
+                        }
+                        self.state.history.journal = Some(journal);
                     }
-                    .into(),
-                );
-
-                Ok(0)
+                    self.state.history.mark_saved();
+                    self.state.backup = data;
+                }
+                Task::Load => {
+                    let state = EditorState::with_backups(
+                        data,
+                        self.state.store.clone(),
+                        self.state.backups.clone(),
+                    );
+                    let previous = std::mem::replace(&mut self.state, state);
+                    self.state.backup = previous.active;
+                }
+                Task::Rebuild => {
+                    self.state.backup = std::mem::replace(&mut self.state.active, data);
+                    self.state.history.clear();
+                    self.state.history.journal_rebuild(&self.state.active.text);
+                }
             }
         }
 
-        /// Remove a value, only allowed if the value is not used anywhere
-        #[derive(new, StructOpt, Debug)]
-        #[structopt(setting = AppSettings::NoBinaryName)]
-        pub struct Val {
-            /// The keyword to reference the name with in the text
-            key: KeyString,
+        /// Create a new project on disk and open it in a new editor
+        pub fn create(name: &str) -> Result<Self> {
+            let mut editor = Editor::new(DialogueTreeData::default());
+            new::Project::new(name.to_string(), true).execute(&mut editor.state)?;
+            Ok(editor)
         }
 
-        impl Executable for Val {
-            fn execute(&self, state: &mut EditorState) -> Result<usize> {
-                info!("remove value {}", self.key);
+        /// Open an existing project from disk in a new editor
+        pub fn open(name: &str) -> Result<Self> {
+            let mut editor = Editor::new(DialogueTreeData::default());
+            editor.load(name)?;
+            Ok(editor)
+        }
 
-                let value = *state
-                    .active
-                    .val_table
-                    .get(&self.key)
-                    .ok_or(cmd::Error::ValNotExists)?;
+        /// Load a project from disk, replacing the active project. See cmd::Load
+        pub fn load(&mut self, name: &str) -> Result<()> {
+            self.check_idle()?;
+            cmd::Load::new(name.to_string()).execute(&mut self.state)?;
+            Ok(())
+        }
 
-                trace!("check if the key is referenced in any requirements or effects");
-                for choice in state.active.tree.edges() {
-                    // this match will stop compiling any time a new reqKind is added
-                    match &choice.requirement {
-                        ReqKind::No => Ok(()),
-                        ReqKind::Greater(key, _) => {
-                            if key.eq(self.key.as_str()) {
-                                Err(cmd::Error::NameInUse)
-                            } else {
-                                Ok(())
-                            }
-                        }
-                        ReqKind::Less(key, _) => {
-                            if key.eq(self.key.as_str()) {
-                                Err(cmd::Error::NameInUse)
-                            } else {
-                                Ok(())
-                            }
-                        }
-                        ReqKind::Equal(key, _) => {
-                            if key.eq(self.key.as_str()) {
-                                Err(cmd::Error::NameInUse)
-                            } else {
-                                Ok(())
-                            }
-                        }
-                        ReqKind::Cmp(_, _) => Ok(()),
-                    }?;
-                    match &choice.effect {
-                        EffectKind::No => Ok(()),
-                        EffectKind::Add(key, _) => {
-                            if key.eq(self.key.as_str()) {
-                                Err(cmd::Error::NameInUse)
-                            } else {
-                                Ok(())
-                            }
-                        }
-                        EffectKind::Sub(key, _) => {
-                            if key.eq(self.key.as_str()) {
-                                Err(cmd::Error::NameInUse)
-                            } else {
-                                Ok(())
-                            }
-                        }
-                        EffectKind::Set(key, _) => {
-                            if key.eq(self.key.as_str()) {
-                                Err(cmd::Error::NameInUse)
-                            } else {
-                                Ok(())
-                            }
-                        }
-                        EffectKind::Assign(_, _) => Ok(()),
-                    }?;
-                }
+        /// Save the active project to disk. See cmd::Save
+        ///
+        /// If force is set, the file is overwritten even if it belongs to a different project
+        pub fn save(&mut self, force: bool) -> Result<()> {
+            self.check_idle()?;
+            cmd::Save::new(force, false).execute(&mut self.state)?;
+            Ok(())
+        }
 
-                trace!("remove key-value pair from value table");
-                state
-                    .active
-                    .val_table
-                    .remove(self.key.as_str())
-                    .ok_or(cmd::Error::NameNotExists)?;
+        /// Rebuild the tree and text buffer. See cmd::Rebuild
+        pub fn rebuild(&mut self) -> Result<()> {
+            self.check_idle()?;
+            cmd::Rebuild::new().execute(&mut self.state)?;
+            Ok(())
+        }
 
-                state.history.push(
-                    ValTableRemove {
-                        key: self.key,
-                        val: value,
-                    }
-                    .into(),
-                );
+        /// Swap the active and backup trees. See cmd::Swap
+        pub fn swap(&mut self) -> Result<()> {
+            self.check_idle()?;
+            self.state.swap();
+            Ok(())
+        }
 
-                Ok(0)
-            }
+        /// Undo the most recent change to the tree
+        pub fn undo(&mut self) -> Result<()> {
+            self.check_idle()?;
+            cmd::Undo::new().execute(&mut self.state)?;
+            Ok(())
         }
-    }
 
-    /// Undo the last event that modified the dialogue tree
-    ///
-    /// Rebuilding the tree removes the entire undo/redo history. Undo does not interact with file
-    /// level operations such as saving or loading projects
-    #[derive(new, StructOpt, Debug)]
-    #[structopt(setting = AppSettings::NoBinaryName)]
-    pub struct Undo {}
+        /// Redo the most recently undone change to the tree
+        pub fn redo(&mut self) -> Result<()> {
+            self.check_idle()?;
+            cmd::Redo::new().execute(&mut self.state)?;
+            Ok(())
+        }
 
-    impl Executable for Undo {
-        fn execute(&self, state: &mut EditorState) -> Result<usize> {
-            info!("Undo");
-            state.history.undo(&mut state.active)?;
-            Ok(0)
+        /// Add a node and return its index
+        pub fn new_node(&mut self, speaker: &str, dialogue: &str) -> Result<NodeIndex> {
+            self.check_idle()?;
+            new::Node::new(speaker.to_string(), dialogue.to_string()).execute(&mut self.state)
         }
-    }
 
-    /// Redo the last undo event that modified the dialogue tree
-    ///
-    /// Rebuilding the tree removes the entire undo/redo history. Redo does not interact with file
-    /// level operations such as saving or loading projects
-    #[derive(new, StructOpt, Debug)]
-    #[structopt(setting = AppSettings::NoBinaryName)]
-    pub struct Redo {}
+        /// Add an edge between two nodes and return its index
+        pub fn new_edge(
+            &mut self,
+            source: NodeIndex,
+            target: NodeIndex,
+            text: &str,
+            requirement: ReqKind,
+            effect: EffectKind,
+        ) -> Result<EdgeIndex> {
+            self.check_idle()?;
+            new::Edge::new(
+                source,
+                target,
+                text.to_string(),
+                Some(requirement),
+                Some(effect),
+            )
+            .execute(&mut self.state)
+        }
 
-    impl Executable for Redo {
-        fn execute(&self, state: &mut EditorState) -> Result<usize> {
-            info!("Redo");
-            state.history.redo(&mut state.active)?;
-            Ok(0)
+        /// Add a name to the name table
+        pub fn new_name(&mut self, key: KeyString, name: NameString) -> Result<()> {
+            self.check_idle()?;
+            new::Name::new(key, name).execute(&mut self.state)?;
+            Ok(())
         }
-    }
-    /// Save the current project
-    #[derive(new, StructOpt, Debug)]
-    #[structopt(setting = AppSettings::NoBinaryName)]
-    pub struct Save {}
 
-    impl Executable for Save {
-        fn execute(&self, state: &mut EditorState) -> Result<usize> {
-            info!("Save project");
-            let encoded = bincode::serialize(&state.active)?;
-            std::fs::write(state.active.name.clone() + TREE_EXT, encoded)?;
+        /// Add a value to the value table
+        pub fn new_val(&mut self, key: KeyString, value: u32) -> Result<()> {
+            self.check_idle()?;
+            new::Val::new(key, value).execute(&mut self.state)?;
+            Ok(())
+        }
 
-            trace!("save successful, sync backup with active copy");
-            state.backup = state.active.clone();
+        /// Replace the speaker and text of a node
+        pub fn edit_node(
+            &mut self,
+            index: NodeIndex,
+            speaker: KeyString,
+            dialogue: &str,
+        ) -> Result<()> {
+            self.check_idle()?;
+            edit::Node::new(index, speaker, dialogue.to_string()).execute(&mut self.state)?;
+            Ok(())
+        }
 
-            Ok(state.active.uid)
+        /// Replace the text, requirement, and effect of an edge
+        pub fn edit_edge(
+            &mut self,
+            index: EdgeIndex,
+            text: &str,
+            requirement: ReqKind,
+            effect: EffectKind,
+        ) -> Result<()> {
+            self.check_idle()?;
+            edit::Edge::new(index, text.to_string(), Some(requirement), Some(effect))
+                .execute(&mut self.state)?;
+            Ok(())
         }
-    }
 
-    /// Rebuild the tree and text buffer for efficient access and memory use. Rebuilding the tree
-    /// erases the undo/redo history.
-    ///
-    /// Rebuilding the tree is used to remove unused sections of text from the buffer. It performs
-    /// a DFS search through the tree, and creates a new tree and text buffer where the text sections
-    /// of a node and its outgoing edges are next to each other. This rebuilding process has a risk
-    /// of corrupting the tree, so a backup copy is is saved before hand. The backup is stored both
-    /// in memory and copied to disk as project_name.tree.bkp. To use the backup copy, either call
-    /// the swap subcommand to load from memory, or remove the .bkp tag from the end of the file
-    /// and then load it.
-    ///
-    /// Since the rebuild tree cleans out any artifacts from edits/removals, the undo/redo
-    ///
-    #[derive(new, StructOpt, Debug)]
-    #[structopt(setting = AppSettings::NoBinaryName)]
-    pub struct Rebuild {}
+        /// Change the name stored for a key in the name table
+        pub fn edit_name(&mut self, key: KeyString, name: NameString) -> Result<()> {
+            self.check_idle()?;
+            edit::Name::new(key, name).execute(&mut self.state)?;
+            Ok(())
+        }
 
-    impl Executable for Rebuild {
-        fn execute(&self, state: &mut EditorState) -> Result<usize> {
-            // save states to backup buffer
-            state.backup = state.active.clone();
+        /// Change the value stored for a key in the value table
+        pub fn edit_val(&mut self, key: KeyString, value: u32) -> Result<()> {
+            self.check_idle()?;
+            edit::Val::new(key, value).execute(&mut self.state)?;
+            Ok(())
+        }
 
-            // save backup to filesystem
-            let encoded = bincode::serialize(&state.active)?;
-            std::fs::write(state.active.name.clone() + TREE_EXT + BACKUP_EXT, encoded)?;
+        /// Set a time limit in milliseconds for the choices leaving a node. The node must have a
+        /// default choice
+        pub fn set_timeout(&mut self, index: NodeIndex, milliseconds: u32) -> Result<()> {
+            self.check_idle()?;
+            edit::Timeout::new(index, milliseconds).execute(&mut self.state)?;
+            Ok(())
+        }
 
-            // attempt rebuild tree on active buffer, backup buffer is used as source
-            util::rebuild_tree(
-                &state.backup.text,
-                &state.backup.tree,
-                &mut state.active.text,
-                &mut state.active.tree,
-            )?;
+        /// Mark an edge as the default choice of its source node, unmarking any other
+        pub fn set_default_choice(&mut self, index: EdgeIndex) -> Result<()> {
+            self.check_idle()?;
+            edit::DefaultChoice::new(index).execute(&mut self.state)?;
+            Ok(())
+        }
 
-            // Confirm that that rebuilt tree is valid
-            util::validate_tree(&state.active)?;
+        /// Move a node to a new position. The move is recorded in the undo/redo history
+        pub fn move_node(&mut self, index: NodeIndex, pos: Position) -> Result<()> {
+            self.check_idle()?;
+            let event = self.state.active.set_position(index, Some(pos))?;
+            self.state
+                .history
+                .push(event.into(), &mut self.state.active);
+            Ok(())
+        }
 
-            // Clear the undo/redo history
-            state.history.clear();
+        /// Remove a node, only allowed if no edges use the node
+        pub fn remove_node(&mut self, index: NodeIndex) -> Result<()> {
+            self.check_idle()?;
+            remove::Node::new(index).execute(&mut self.state)?;
+            Ok(())
+        }
 
-            Ok(state.active.uid)
+        /// Remove an edge. Removing edges invalidates edge indices
+        pub fn remove_edge(&mut self, index: EdgeIndex) -> Result<()> {
+            self.check_idle()?;
+            remove::Edge::new(index).execute(&mut self.state)?;
+            Ok(())
         }
-    }
 
-    /// Load a project from disk, will overwrite unsaved changes
-    #[derive(new, StructOpt, Debug)]
-    #[structopt(setting = AppSettings::NoBinaryName)]
-    pub struct Load {
-        name: String,
-    }
+        /// Remove a name, only allowed if the name is not used anywhere
+        pub fn remove_name(&mut self, key: KeyString) -> Result<()> {
+            self.check_idle()?;
+            remove::Name::new(key).execute(&mut self.state)?;
+            Ok(())
+        }
 
-    impl Executable for Load {
-        fn execute(&self, state: &mut EditorState) -> Result<usize> {
-            let new_state = EditorState::new(bincode::deserialize_from(std::io::BufReader::new(
-                std::fs::File::open(self.name.clone() + TREE_EXT)?,
-            ))?);
-            // check that the loaded tree is valid before loading into main state
-            util::validate_tree(&state.active)?;
-            *state = new_state;
-            Ok(state.active.uid)
+        /// Remove a value, only allowed if the value is not used anywhere
+        pub fn remove_val(&mut self, key: KeyString) -> Result<()> {
+            self.check_idle()?;
+            remove::Val::new(key).execute(&mut self.state)?;
+            Ok(())
         }
-    }
 
-    /// Swap the backup and active trees.
-    ///
-    /// The backup tree stores the state from the last new, load, save, or just before a rebuild
-    /// is attempted. This is mainly useful as a recovery option if the active tree gets corrupted.
-    #[derive(new, StructOpt, Debug)]
-    #[structopt(setting = AppSettings::NoBinaryName)]
-    pub struct Swap {}
+        /// Remove the time limit from the choices leaving a node
+        pub fn remove_timeout(&mut self, index: NodeIndex) -> Result<()> {
+            self.check_idle()?;
+            remove::Timeout::new(index).execute(&mut self.state)?;
+            Ok(())
+        }
 
-    impl Executable for Swap {
-        fn execute(&self, state: &mut EditorState) -> Result<usize> {
-            std::mem::swap(&mut state.active, &mut state.backup);
-            Ok(state.active.uid)
+        /// Unmark a default choice, only allowed if its source node has no timeout
+        pub fn remove_default_choice(&mut self, index: EdgeIndex) -> Result<()> {
+            self.check_idle()?;
+            remove::DefaultChoice::new(index).execute(&mut self.state)?;
+            Ok(())
         }
-    }
 
-    /// Print all nodes, edges, and associated text to the editor scratchpad
-    ///
-    /// Prints all nodes in index order (not necessarily the order they would appear when
-    /// traversing the dialogue tree). Under each node definiton, a list of the outgoing edges from
-    /// that node will be listed. This will show the path to the next dialogue option from any
-    /// node, and the choice/action text associated with that edge.
-    ///
-    /// Note that edge and node indices will not remain stable if nodes/edges are removed from the
-    /// graph.
-    #[derive(new, StructOpt, Debug)]
-    #[structopt(setting = AppSettings::NoBinaryName)]
-    pub struct List {}
+        /// Add a stage direction to the end of a node's directions
+        pub fn add_direction(&mut self, index: NodeIndex, direction: Direction) -> Result<()> {
+            self.check_idle()?;
+            direction::Add::new(index, direction).execute(&mut self.state)?;
+            Ok(())
+        }
 
-    impl Executable for List {
-        fn execute(&self, state: &mut EditorState) -> Result<usize> {
-            let mut name_buf = String::with_capacity(64);
-            let mut text_buf = String::with_capacity(256);
-            let node_iter = state.active.tree.nodes().iter().enumerate();
+        /// Replace the stage direction at a position in a node's directions
+        pub fn edit_direction(
+            &mut self,
+            index: NodeIndex,
+            position: usize,
+            direction: Direction,
+        ) -> Result<()> {
+            self.check_idle()?;
+            direction::Edit::new(index, position, direction).execute(&mut self.state)?;
+            Ok(())
+        }
 
-            for (idx, node) in node_iter {
-                let text = &state.active.text[node.section[0]..node.section[1]];
-                util::parse_node(text, &state.active.name_table, &mut name_buf, &mut text_buf)?;
-                state.scratchpad.push_str(&format!(
-                    "node {}: {} says \"{}\"\r\n",
-                    idx, name_buf, text_buf
-                ));
-                let outgoing_edges_iter = state.active.tree.outgoing_from_index(idx)?;
-                for edge_index in outgoing_edges_iter {
-                    let choice = state.active.tree.get_edge(edge_index)?;
-                    util::parse_edge(
-                        &state.active.text[choice.section[0]..choice.section[1]],
-                        &state.active.name_table,
-                        &mut text_buf,
-                    )?;
-                    state.scratchpad.push_str(&format!(
-                        "--> edge {} to node {}: \"{}\"\r\n    requirements: {:?}, effects: {:?}\r\n",
-                        edge_index,
-                        state.active.tree.target_of(edge_index)?,
-                        text_buf,
-                        choice.requirement,
-                        choice.effect,
-                    ));
-                }
+        /// Make an edge a call edge, or an ordinary edge again
+        pub fn set_call(&mut self, index: EdgeIndex, call: bool) -> Result<()> {
+            self.check_idle()?;
+            if call {
+                edit::Call::new(index).execute(&mut self.state)?;
+            } else {
+                remove::Call::new(index).execute(&mut self.state)?;
             }
-            println!("{}", state.scratchpad);
-            Ok(state.active.uid)
+            Ok(())
         }
-    }
 
-    /// Utility methods used internally for various useful tasks. These cannot be called directly
-    /// from the command line, but are useful for working with dialogue_trees in other programs
-    pub mod util {
-        use super::*;
+        /// Set the content flags of a node, an empty set removes them
+        pub fn set_flags(&mut self, index: NodeIndex, flags: ContentFlags) -> Result<()> {
+            self.check_idle()?;
+            edit::Flags::new(index, flags).execute(&mut self.state)?;
+            Ok(())
+        }
 
-        /// Generate UID.
-        ///
-        /// UID is a 64 bit unique identifier for the project. This is stored in the dialogue
-        /// tree, and is useful for associating other metadata or resources with the correct tree
-        /// in the case that multiple files exist with the same name (likely if multiple users are
-        /// sharing files)
-        pub fn gen_uid() -> usize {
-            rand::random::<usize>()
+        /// Add an interrupt at a position in the interrupt list, or at the end if no position is
+        /// given. Returns the position of the interrupt
+        pub fn add_interrupt(
+            &mut self,
+            requirement: ReqKind,
+            target: NodeIndex,
+            position: Option<usize>,
+        ) -> Result<usize> {
+            self.check_idle()?;
+            interrupt::Add::new(requirement, target, position).execute(&mut self.state)
         }
 
-        /// Helper method to parse a dialogue node's section of the text and fill in any name
-        /// variables.
-        ///
-        /// The input text rope section should have the following format
-        ///     ::name::text ::name:: more text
-        ///
-        /// The first name is the speaker. This name must be a valid key to the name_table
-        /// Inside the text, additional names may be inserted inside a pair of :: symbols. The
-        /// entire area inside the :: symbols must be a valid key to the name_table.
-        ///
-        /// Both the name and text buf are cleared at the beginning of this method.
-        pub fn parse_node(
-            text: &str,
-            name_table: &NameTable,
-            name_buf: &mut String,
-            text_buf: &mut String,
+        /// Remove the interrupt at a position in the interrupt list
+        pub fn remove_interrupt(&mut self, position: usize) -> Result<()> {
+            self.check_idle()?;
+            interrupt::Remove::new(position).execute(&mut self.state)?;
+            Ok(())
+        }
+
+        /// Remove the stage direction at a position in a node's directions
+        pub fn remove_direction(&mut self, index: NodeIndex, position: usize) -> Result<()> {
+            self.check_idle()?;
+            direction::Remove::new(index, position).execute(&mut self.state)?;
+            Ok(())
+        }
+
+        /// Add an effect to the end of a node's on-enter effects
+        pub fn add_on_enter(&mut self, index: NodeIndex, effect: EffectKind) -> Result<()> {
+            self.check_idle()?;
+            on_enter::Add::new(index, effect).execute(&mut self.state)?;
+            Ok(())
+        }
+
+        /// Replace the effect at a position in a node's on-enter effects
+        pub fn edit_on_enter(
+            &mut self,
+            index: NodeIndex,
+            position: usize,
+            effect: EffectKind,
         ) -> Result<()> {
-            // Implementation notes:
-            //  0. The first iterator element should always be '', if not something is wrong
-            //  1. The second iterator element is always the speaker name and should be the only
-            //     thing written to the name buffer
-            //  2. Since only a simple flow of ::speaker_name::text::name:::text ... etc is
-            //     allowed, only every 'other' token (indices 1,3,5...) need to be looked up in the
-            //     hashtable
-            //  3. The above is only true because split() will return an empty strings on sides of
-            //     the separator with no text. For instance name::::name:: would split to ['name,
-            //     '', name, '']
-            name_buf.clear();
-            text_buf.clear();
-            let mut text_iter = text.split(TOKEN_SEP).enumerate();
-            let _ = text_iter.next(); // skip first token, it is '' for any correct string
-            let speaker_key = text_iter.next().ok_or(cmd::Error::Generic)?.1;
-            let speaker_name = name_table.get(speaker_key).ok_or(cmd::Error::NodeParse)?;
-            name_buf.push_str(speaker_name);
-            text_iter.try_for_each(|(i, n)| -> std::result::Result<(), cmd::Error> {
-                if (i & 0x1) == 1 {
-                    // token is a name (index 1, 3, 5 ...)
-                    let value = name_table.get(n).ok_or(cmd::Error::NodeParse)?;
-                    text_buf.push_str(value);
-                    Ok(())
-                } else {
-                    // token cannot be a name
-                    text_buf.push_str(n);
-                    Ok(())
-                }
-            })?;
+            self.check_idle()?;
+            on_enter::Edit::new(index, position, effect).execute(&mut self.state)?;
+            Ok(())
+        }
+
+        /// Remove the effect at a position in a node's on-enter effects
+        pub fn remove_on_enter(&mut self, index: NodeIndex, position: usize) -> Result<()> {
+            self.check_idle()?;
+            on_enter::Remove::new(index, position).execute(&mut self.state)?;
+            Ok(())
+        }
+
+        /// Rename a name key and update all references to it
+        pub fn rename_name(&mut self, key: KeyString, new_key: KeyString) -> Result<()> {
+            self.check_idle()?;
+            rename::Name::new(key, new_key).execute(&mut self.state)?;
+            Ok(())
+        }
+
+        /// Rename a value key and update all references to it
+        pub fn rename_val(&mut self, key: KeyString, new_key: KeyString) -> Result<()> {
+            self.check_idle()?;
+            rename::Val::new(key, new_key).execute(&mut self.state)?;
+            Ok(())
+        }
 
+        /// Bookmark a node. Bookmarks follow the node as other nodes are removed
+        pub fn add_bookmark(&mut self, key: KeyString, index: NodeIndex) -> Result<()> {
+            self.check_idle()?;
+            bookmark::Add::new(key, index).execute(&mut self.state)?;
             Ok(())
         }
 
-        /// Same routine as parse node, except the results are not actually written to a
-        /// thread. This is used for validating that the section of text is valid
-        pub fn validate_node(text: &str, name_table: &NameTable) -> Result<()> {
-            let mut text_iter = text.split(TOKEN_SEP).enumerate();
-            text_iter.next(); // discard first empty string
-            let speaker_key = text_iter.next().ok_or(cmd::Error::EdgeParse)?.1;
-            name_table.get(speaker_key).ok_or(cmd::Error::EdgeParse)?;
-            text_iter.try_for_each(|(i, n)| -> std::result::Result<(), cmd::Error> {
-                if (i & 0x1) == 1 {
-                    // token is a name (index 1, 3, 5 ...)
-                    name_table.get(n).ok_or(cmd::Error::EdgeParse)?;
-                    Ok(())
-                } else {
-                    // token cannot be a name
-                    Ok(())
-                }
-            })?;
+        /// Remove a bookmark
+        pub fn remove_bookmark(&mut self, key: KeyString) -> Result<()> {
+            self.check_idle()?;
+            bookmark::Remove::new(key).execute(&mut self.state)?;
             Ok(())
         }
 
-        /// Helper method to parse a player action (edge's) section of the text and fill in any
-        /// name variables.
-        ///
-        /// The input text section should have the following format
-        ///     'action text ::name:: more action text'
-        ///
-        /// Both the name and text buf are cleared at the beginning of this method
-        pub fn parse_edge(text: &str, name_table: &NameTable, text_buf: &mut String) -> Result<()> {
-            // Implementation notes
-            //  1. Due to the format, only even iterator elements are names that need to be looked
-            //     up in the name table. This is true because split() will return an empty strings
-            //     on sides of the separator with no text. For instance name::::name:: would split
-            //     to ['name', '', 'name', '']
-            text_buf.clear();
-            let mut text_iter = text.split(TOKEN_SEP).enumerate();
-            text_iter.try_for_each(|(i, n)| -> std::result::Result<(), cmd::Error> {
-                if (i & 0x1) == 0 {
-                    // token cannot be a name
-                    text_buf.push_str(n);
-                    Ok(())
-                } else {
-                    let value = name_table.get(n).ok_or(cmd::Error::EdgeParse)?;
-                    text_buf.push_str(value);
-                    Ok(())
-                }
-            })?;
+        /// Move the cursor to a node
+        pub fn goto(&mut self, index: NodeIndex) -> Result<()> {
+            self.check_idle()?;
+            cmd::Goto::new(index).execute(&mut self.state)?;
             Ok(())
         }
 
-        /// Same routine as parse_edge, but does not write to an output string buffer. Useful for
-        /// validating a section of text in an edge
-        pub fn validate_edge(text: &str, name_table: &NameTable) -> Result<()> {
-            let mut text_iter = text.split(TOKEN_SEP).enumerate();
-            text_iter.try_for_each(|(i, n)| -> std::result::Result<(), cmd::Error> {
-                if (i & 0x1) == 0 {
-                    Ok(())
-                } else {
-                    name_table.get(n).ok_or(cmd::Error::Generic)?;
-                    Ok(())
-                }
-            })?;
-            Ok(())
+        /// Get the node at the cursor, if it is set
+        pub fn cursor(&self) -> Option<NodeIndex> {
+            self.state.active.cursor
         }
 
-        /// Helper method to prompt the user for input
-        ///
-        /// User input is stored into the provided buffer
-        pub fn prompt_input(buf: &mut String) {
-            // Print input prompt
-            print!(">> ");
+        /// Move the cursor to a bookmarked node and return the node index
+        pub fn goto_bookmark(&mut self, key: KeyString) -> Result<NodeIndex> {
+            self.check_idle()?;
+            bookmark::Goto::new(key).execute(&mut self.state)
+        }
 
-            // get next command from the user
-            io::stdout().flush().unwrap();
-            io::stdin().read_line(buf).expect("Failed to read line");
+        /// Get the active dialogue tree
+        pub fn data(&self) -> &DialogueTreeData {
+            &self.state.active
         }
 
-        /// Rebuilds the text of a dialogue tree, removing unused sections and reordering text
-        /// sections for improved caching of nearby nodes. The rebuilt string is then stored in
-        /// the new_buf string buffer.
+        /// Get the editor state, useful for queries that have no equivalent method
+        pub fn state(&self) -> &EditorState {
+            &self.state
+        }
+
+        /// Execute a command that has no equivalent method, such as one parsed from user input
         ///
-        /// When editing nodes/edges, currently new text is pushed to the end of the text buffer,
-        /// and the indices of the node/edge are updated to point to the new text. This leaves the
-        /// old section of text in the buffer, and over time many edits will bloat the string. The
-        /// solution to this, without leaving gaps in the string, is to rebuild the text buffer
-        /// based on the order that the text section is referenced in the tree. The order is
-        /// determined by DFS order that the nodes occur, with all edges colocated immediately
-        /// after their source node. This should provide good cache hitrate in most cases, as users
-        /// are likely to follow DFS-like path through the tree as they make choices and advance
-        /// through the dialogue.
+        /// # Errors
+        /// Error if a background task is running, or if the command fails
+        pub fn execute<T: Executable>(&mut self, cmd: &T) -> Result<usize> {
+            self.check_idle()?;
+            cmd.execute(&mut self.state)
+        }
+
+        /// Get the speaker name and text of a node with all names substituted
+        pub fn node_text(&self, index: NodeIndex) -> Result<(String, String)> {
+            let data = &self.state.active;
+            let mut renderer = cmd::util::TextRenderer::default();
+            let (name, text) = renderer.node(data, &data.name_table, index)?;
+            Ok((name.to_string(), text.to_string()))
+        }
+
+        /// Get the text of an edge with all names substituted
+        pub fn edge_text(&self, index: EdgeIndex) -> Result<String> {
+            let data = &self.state.active;
+            let mut renderer = cmd::util::TextRenderer::default();
+            Ok(renderer.edge(data, &data.name_table, index)?.to_string())
+        }
+
+        /// Render the speaker name and text of a node with all names substituted
         ///
-        /// Note that the new_buf and new_tree are cleared at the beginning of this method.
-        /// Make sure it is safe to do so before calling.
-        pub fn rebuild_tree(
-            text: &str,
-            tree: &Tree,
-            new_text: &mut String,
-            new_tree: &mut Tree,
-        ) -> Result<()> {
-            new_text.clear();
-            new_tree.clear();
-            // Clone the old tree into the new one such that the nodes and edge indices and layout
-            // are identical. This makes it much easier to rebuild as only the node weights need to
-            // be updated to point to the proper sections of the next text buffer
-            *new_tree = tree.clone();
+        /// Unlike node_text, the editor's internal buffers are reused between calls, so the
+        /// returned text is only valid until the next render
+        pub fn render_node(&mut self, index: NodeIndex) -> Result<(&str, &str)> {
+            let data = &self.state.active;
+            self.renderer.node(data, &data.name_table, index)
+        }
 
-            let root_index: usize = 0;
-            let mut dfs = Dfs::new(&tree, root_index);
-            while let Some(node_index) = dfs.next(&tree)? {
-                // Rebuild node
-                let dialogue = tree.get_node(node_index)?;
-                let slice: &str = &text[dialogue.section[0]..dialogue.section[1]];
-                let start = new_text.len();
-                new_text.push_str(slice);
-                let end = new_text.len();
-                let new_dialogue = new_tree.get_node_mut(node_index)?;
-                // verify new and old hash match
-                let new_hash = hash(new_text[start..end].as_bytes());
-                assert!(dialogue.section.hash == new_hash);
-                *new_dialogue = Dialogue::new(Section::new([start, end], new_hash), dialogue.pos);
+        /// Render the text of an edge with all names substituted, reusing the editor's internal
+        /// buffers
+        pub fn render_edge(&mut self, index: EdgeIndex) -> Result<&str> {
+            let data = &self.state.active;
+            self.renderer.edge(data, &data.name_table, index)
+        }
 
-                // Rebuild all edges sourced from this node
-                let edge_iter = tree.outgoing_from_index(node_index)?;
-                for edge_index in edge_iter {
-                    let edge = tree.get_edge(edge_index)?;
-                    let slice: &str = &text[edge.section[0]..edge.section[1]];
+        /// Get the outgoing edges of a node, in order
+        pub fn outgoing_edges(&self, index: NodeIndex) -> Result<Vec<EdgeIndex>> {
+            Ok(self.state.active.tree.outgoing_from_index(index)?.collect())
+        }
 
-                    // Verify that edge and new_edge match, they should be identical since we
-                    // started by cloning the tree to new_tree
-                    assert!(tree.target_of(edge_index)? == new_tree.target_of(edge_index)?);
+        /// Get the source and target nodes of an edge
+        pub fn endpoints(&self, index: EdgeIndex) -> Result<(NodeIndex, NodeIndex)> {
+            let tree = &self.state.active.tree;
+            Ok((tree.source_of(index)?, tree.target_of(index)?))
+        }
 
-                    let start = new_text.len();
-                    new_text.push_str(slice);
-                    let end = new_text.len();
-                    // verify new and old hash match
-                    let new_hash = hash(new_text[start..end].as_bytes());
-                    assert!(edge.section.hash == new_hash);
-                    let new_choice = new_tree.get_edge_mut(edge_index)?;
-                    new_choice.section = Section::new([start, end], new_hash);
-                }
+        /// Find every reference to a name or val key in the tree
+        pub fn references(&self, kind: cmd::util::KeyKind, key: &str) -> Vec<cmd::util::Reference> {
+            cmd::util::key_references(&self.state.active, kind, key)
+        }
+
+        /// Get the indices of the nodes or edges selected by a query, see the query module for
+        /// the query format
+        pub fn select(&self, query: &str) -> Result<Vec<usize>> {
+            query.parse::<query::Query>()?.select(&self.state.active)
+        }
+
+        /// Get the listing of the tree printed by the list cmd
+        pub fn list(&mut self) -> Result<String> {
+            self.check_idle()?;
+            self.state.scratchpad.clear();
+            cmd::List::new(true, 0, None, None).execute(&mut self.state)?;
+            Ok(std::mem::take(&mut self.state.scratchpad))
+        }
+    }
+
+    /// Thread-safe handle to an editor state, shared between threads
+    ///
+    /// Cloning the handle is cheap, and all clones refer to the same state. Threads that only
+    /// need to look at the project (rendering, autosave, analysis) take a read lock, and commands
+    /// may either be executed immediately under a write lock, or queued from any thread and
+    /// executed together by the thread that owns the editing loop.
+    #[derive(Clone)]
+    pub struct SharedEditor {
+        state: Arc<RwLock<EditorState>>,
+        queue: Arc<Mutex<VecDeque<cmd::Parse>>>,
+    }
+
+    impl SharedEditor {
+        /// Create a shared handle that takes ownership of an editor state
+        pub fn new(state: EditorState) -> Self {
+            SharedEditor {
+                state: Arc::new(RwLock::new(state)),
+                queue: Arc::new(Mutex::new(VecDeque::new())),
             }
+        }
+
+        /// Lock the editor state for reading, blocking until no writer holds the lock
+        pub fn read(&self) -> Result<RwLockReadGuard<'_, EditorState>> {
+            self.state
+                .read()
+                .map_err(|_| cmd::Error::LockPoisoned.into())
+        }
+
+        /// Lock the editor state for writing, blocking until no other thread holds the lock
+        pub fn write(&self) -> Result<RwLockWriteGuard<'_, EditorState>> {
+            self.state
+                .write()
+                .map_err(|_| cmd::Error::LockPoisoned.into())
+        }
+
+        /// Execute a command immediately under a write lock
+        pub fn execute<T: Executable>(&self, cmd: &T) -> Result<usize> {
+            cmd.execute(&mut *self.write()?)
+        }
+
+        /// Copy the active dialogue tree, useful for work that should not hold the lock such as
+        /// autosaving
+        pub fn snapshot(&self) -> Result<DialogueTreeData> {
+            Ok(self.read()?.active.clone())
+        }
 
+        /// Queue a command to be executed by the next call to execute_queued
+        pub fn queue(&self, cmd: cmd::Parse) -> Result<()> {
+            self.queue
+                .lock()
+                .map_err(|_| cmd::Error::LockPoisoned)?
+                .push_back(cmd);
             Ok(())
         }
 
-        /// Validate that the contents of a requirement enum are valid
-        ///
-        /// This is mainly used when taking a requirement from CLI and checking that the key
-        /// is present in the val_table for u32 types, and the name_table for String types
-        pub fn validate_requirement(
-            req: &ReqKind,
-            name_table: &NameTable,
-            val_table: &ValTable,
-        ) -> Result<()> {
-            // this match will stop compiling any time a new reqKind is added
-            match req {
-                ReqKind::No => {}
-                ReqKind::Greater(key, _val) => {
-                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
-                }
-                ReqKind::Less(key, _val) => {
-                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
-                }
-                ReqKind::Equal(key, _val) => {
-                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
-                }
-                ReqKind::Cmp(key, _val) => {
-                    name_table.get(key).ok_or(cmd::Error::NameNotExists)?;
-                }
+        /// Execute all queued commands in the order they were queued, under a single write lock.
+        /// Returns the result of each command
+        pub fn execute_queued(&self) -> Result<Vec<Result<usize>>> {
+            let cmds: Vec<cmd::Parse> = self
+                .queue
+                .lock()
+                .map_err(|_| cmd::Error::LockPoisoned)?
+                .drain(..)
+                .collect();
+            let mut state = self.write()?;
+            Ok(cmds.iter().map(|c| c.execute(&mut state)).collect())
+        }
+    }
+
+    impl From<EditorState> for Editor {
+        fn from(state: EditorState) -> Self {
+            Editor {
+                state,
+                job: None,
+                renderer: cmd::util::TextRenderer::default(),
             }
-            Ok(())
         }
+    }
+
+    impl From<Editor> for SharedEditor {
+        fn from(editor: Editor) -> Self {
+            SharedEditor::new(editor.state)
+        }
+    }
+}
 
-        /// Validate that the contents of a effect enum are valid
+pub mod logging {
+    //! Logging settings shared by the arbor frontends. Verbosity is read per module from a
+    //! settings file. The library only emits log records, each frontend installs its own logger
+    use super::*;
+    use log::LevelFilter;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+
+    /// Name of the settings file, looked for in the working directory
+    pub static SETTINGS_FILE: &str = "arbor_settings.json";
+    /// Environment variable that overrides the path of the settings file
+    pub static SETTINGS_ENV: &str = "ARBOR_SETTINGS";
+
+    /// Level filters from least to most verbose
+    const LEVELS: [LevelFilter; 6] = [
+        LevelFilter::Off,
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+        LevelFilter::Trace,
+    ];
+
+    /// Logging settings, as stored in the settings file
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct Settings {
+        /// Level used for modules that are not listed in modules
+        pub level: String,
+        /// Level of each module, the longest module path that prefixes a log target is used
+        pub modules: HashMap<String, String>,
+        /// File that log output is copied to
+        pub file: Option<PathBuf>,
+        /// Size in bytes the log file may grow to before it is rotated
+        pub max_file_size: u64,
+        /// Number of rotated log files kept, not counting the file being written to
+        pub max_files: usize,
+    }
+
+    impl Default for Settings {
+        fn default() -> Self {
+            Settings {
+                level: "warn".to_string(),
+                modules: HashMap::new(),
+                file: None,
+                max_file_size: 1 << 20,
+                max_files: 3,
+            }
+        }
+    }
+
+    impl Settings {
+        /// Path of the settings file, from SETTINGS_ENV if it is set
+        pub fn path() -> PathBuf {
+            std::env::var_os(SETTINGS_ENV)
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(SETTINGS_FILE))
+        }
+
+        /// Read settings from a file. A missing file gives the default settings
         ///
-        /// This is mainly used when taking a effect from CLI and checking that the key
-        /// is present in the val_table for u32 types, and the name_table for String types
-        pub fn validate_effect(
-            effect: &EffectKind,
-            name_table: &NameTable,
-            val_table: &ValTable,
-        ) -> Result<()> {
-            // this match will stop compiling any time a new EffectKind is added
-            // NOTE: remember, if val is a u32, check the val_table, if val is a String, check the
-            // name table
-            match effect {
-                EffectKind::No => {}
-                EffectKind::Add(key, _val) => {
-                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
-                }
-                EffectKind::Sub(key, _val) => {
-                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
-                }
-                EffectKind::Set(key, _val) => {
-                    val_table.get(key).ok_or(cmd::Error::ValNotExists)?;
-                }
-                EffectKind::Assign(key, _val) => {
-                    name_table.get(key).ok_or(cmd::Error::NameNotExists)?;
-                }
+        /// # Errors
+        ///
+        /// Error if the file exists but cannot be read or parsed
+        pub fn load(path: &Path) -> Result<Settings> {
+            match fs::read_to_string(path) {
+                Ok(s) => Ok(serde_json::from_str(&s)?),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Settings::default()),
+                Err(e) => Err(e.into()),
             }
-            Ok(())
         }
 
-        /// Validate that a given dialogue tree data structure contains all valid sections of text
-        /// that all edges point to valid nodes in the tree, all have valid action enums, and have
-        /// have correct hashes for all nodes and edges
+        /// Parse the level names into a Filter. Each verbose step raises the default level and
+        /// every module level by one, up to Trace
         ///
-        /// Returns a result with the error type if the tree was invalid, returns Ok(()) if valid
-        pub fn validate_tree(data: &DialogueTreeData) -> Result<()> {
-            // check nodes first, use parallel iterator in case of very large graph
-            let nodes_iter = data.tree.nodes().par_iter();
-            nodes_iter.try_for_each(|node| -> Result<()> {
-                // try to grab the text section as a slice, and return an error if the get() failed
-                let slice = data.text[..]
-                    .get(node.section[0]..node.section[1])
-                    .ok_or(cmd::Error::InvalidSection)?;
-                // if the slice was successful, check its hash
-                anyhow::ensure!(
-                    seahash::hash(slice.as_bytes()) == node.section.hash,
-                    cmd::Error::InvalidHash
-                );
-                // Check that the section of text parses successfully (all names present in the
-                // name_table)
-                validate_node(slice, &data.name_table)?;
-                Ok(())
-            })?;
+        /// # Errors
+        ///
+        /// Error if any level name is not a valid log level
+        pub fn filter(&self, verbose: usize) -> Result<Filter> {
+            let raise = |level: LevelFilter| {
+                LEVELS
+                    .get(level as usize + verbose)
+                    .copied()
+                    .unwrap_or(LevelFilter::Trace)
+            };
+            let parse = |name: &str| {
+                LevelFilter::from_str(name)
+                    .map_err(|_| anyhow::anyhow!("invalid log level: {}", name))
+            };
+            let mut modules = self
+                .modules
+                .iter()
+                .map(|(module, level)| Ok((module.clone(), raise(parse(level)?))))
+                .collect::<Result<Vec<_>>>()?;
+            // longest module paths are checked first so that the most specific setting is used
+            modules.sort_by_key(|m| std::cmp::Reverse(m.0.len()));
+            Ok(Filter {
+                default: raise(parse(&self.level)?),
+                modules,
+            })
+        }
+    }
 
-            // check edges, will check that they point to nodes that exist, and validate the actionenums
-            let edges_iter = data.tree.edges().par_iter();
-            edges_iter.try_for_each(|edge| -> Result<()> {
-                // try to grab the text section as a slice, and return an error if the get() failed
-                let slice = data.text[..]
-                    .get(edge.section[0]..edge.section[1])
-                    .ok_or(cmd::Error::InvalidSection)?;
-                // if the slice was successful, check its hash
-                anyhow::ensure!(
-                    seahash::hash(slice.as_bytes()) == edge.section.hash,
-                    cmd::Error::InvalidHash
-                );
-                // Check that the section of text parses successfully (all names present in the
-                // name_table)
-                validate_edge(slice, &data.name_table)?;
-                validate_requirement(&edge.requirement, &data.name_table, &data.val_table)?;
-                validate_effect(&edge.effect, &data.name_table, &data.val_table)?;
-                Ok(())
-            })?;
-            Ok(())
+    /// Log level of each module, parsed from Settings
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct Filter {
+        pub default: LevelFilter,
+        /// Module paths and their levels, sorted from longest to shortest path
+        pub modules: Vec<(String, LevelFilter)>,
+    }
+
+    impl Filter {
+        /// Level that records from a log target are kept at
+        pub fn level(&self, target: &str) -> LevelFilter {
+            self.modules
+                .iter()
+                .find(|(module, _)| {
+                    target.starts_with(module.as_str())
+                        && target[module.len()..]
+                            .chars()
+                            .next()
+                            .is_none_or(|c| c == ':')
+                })
+                .map_or(self.default, |(_, level)| *level)
+        }
+
+        /// Most verbose level of any module, used as the global max level of the log crate
+        pub fn max(&self) -> LevelFilter {
+            self.modules
+                .iter()
+                .map(|(_, level)| *level)
+                .fold(self.default, std::cmp::max)
         }
     }
 }
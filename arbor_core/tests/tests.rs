@@ -1,4 +1,5 @@
 use arbor_core::*;
+use store::ProjectStore;
 
 #[allow(dead_code)]
 fn setup_logger() {
@@ -15,13 +16,23 @@ fn run_cmd(cmd_buf: &str, state: &mut EditorState) -> Result<usize> {
     v.execute(state)
 }
 
+/// helper function to create an editor state that keeps its project files in memory
+#[allow(dead_code)]
+fn memory_state(data: DialogueTreeData) -> (EditorState, store::MemoryStore) {
+    let store = store::MemoryStore::default();
+    (
+        EditorState::with_store(data, std::sync::Arc::new(store.clone())),
+        store,
+    )
+}
+
 #[test]
 /// Test basic use case of the editor, new project, add a few nodes and names, list the output,
 /// save the project, reload, list the output again
 fn simple() {
     setup_logger();
     let mut cmd_buf = String::with_capacity(1000);
-    let mut state = EditorState::new(DialogueTreeData::default());
+    let (mut state, store) = memory_state(DialogueTreeData::default());
     cmd_buf.push_str("new project \"simple_test\" -s");
     run_cmd(&cmd_buf, &mut state).unwrap();
     cmd_buf.clear();
@@ -49,7 +60,7 @@ fn simple() {
     run_cmd(&cmd_buf, &mut state).unwrap();
     cmd_buf.clear();
 
-    cmd_buf.push_str("list");
+    cmd_buf.push_str("list --raw");
     run_cmd(&cmd_buf, &mut state).unwrap();
     cmd_buf.clear();
 
@@ -74,15 +85,1201 @@ fn simple() {
     run_cmd(&cmd_buf, &mut state).unwrap();
     cmd_buf.clear();
 
-    cmd_buf.push_str("list");
+    cmd_buf.push_str("list --raw");
     run_cmd(&cmd_buf, &mut state).unwrap();
     cmd_buf.clear();
 
     assert_eq!(state.scratchpad, expected_list);
     state.scratchpad.clear();
 
-    std::fs::remove_file("simple_test.tree").unwrap();
-    std::fs::remove_file("simple_test.tree.bkp").unwrap();
+    assert_eq!(
+        store.list().unwrap(),
+        vec![
+            "simple_test.tree",
+            "simple_test.tree.bkp",
+            "simple_test.tree.journal",
+            "simple_test.tree.old"
+        ]
+    );
+}
+
+#[test]
+/// Test that unused and missing name/val keys are found by the key usage report
+fn key_usage() {
+    let mut state = EditorState::new(DialogueTreeData::default());
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new name dog Sharik", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    run_cmd("new val gold 10", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    run_cmd("new edge -e Add(gold,1) 0 0 \"Again\"", &mut state).unwrap();
+
+    let report = cmd::util::key_usage(&state.active);
+    assert_eq!(report.unused_names, vec![KeyString::from("dog").unwrap()]);
+    assert_eq!(
+        report.unused_vals,
+        vec![KeyString::from("rus_lit").unwrap()]
+    );
+    assert!(report.missing_names.is_empty());
+    assert!(report.missing_vals.is_empty());
+
+    // remove the name table entry behind the tables back to simulate a missing key
+    state.active.name_table.remove("cat");
+    let report = cmd::util::key_usage(&state.active);
+    assert_eq!(report.missing_names, vec!["cat".to_string()]);
+}
+
+#[test]
+/// Test renaming a name key updates text and effects, and that the rename is a single undo
+fn rename_name() {
+    let mut state = EditorState::new(DialogueTreeData::default());
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"I am ::cat::, the cat\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -e Assign(cat,Begemot) 0 0 \"Pet ::cat::\"",
+        &mut state,
+    )
+    .unwrap();
+    let before = format!("{:?}", state.active.tree);
+
+    run_cmd("rename name cat kot", &mut state).unwrap();
+    assert!(!state.active.name_table.contains_key("cat"));
+    assert_eq!(state.active.name_table.get("kot").unwrap(), "Behemoth");
+    cmd::util::validate_tree(&state.active).unwrap();
+
+    let node = state.active.tree.get_node(0).unwrap();
+    assert_eq!(
+        &state.active.text[node.section[0]..node.section[1]],
+        "::kot::I am ::kot::, the cat"
+    );
+    let edge = state.active.tree.get_edge(0).unwrap();
+    assert_eq!(
+        &state.active.text[edge.section[0]..edge.section[1]],
+        "Pet ::kot::"
+    );
+    assert_eq!(
+        edge.effect,
+        EffectKind::Assign(
+            KeyString::from("kot").unwrap(),
+            NameString::from("Begemot").unwrap()
+        )
+    );
+
+    cmd::Undo::new().execute(&mut state).unwrap();
+    assert_eq!(state.active.name_table.get("cat").unwrap(), "Behemoth");
+    assert!(!state.active.name_table.contains_key("kot"));
+    assert_eq!(format!("{:?}", state.active.tree), before);
+}
+
+#[test]
+/// Test importing a screenplay, then exporting it and importing it into a new project
+fn screenplay_round_trip() {
+    let screenplay = concat!(
+        "# start\n",
+        "cat: Well, who knows, who knows\n",
+        "    > Dostoevsky's dead -> protest -r Less(rus_lit,51) -e Sub(rus_lit,1)\n",
+        "    > Leave -> end\n",
+        "\n",
+        "# protest\n",
+        "cat: 'I protest!' ::cat:: exclaimed hotly.\n",
+        "    'Dostoevsky is immortal'\n",
+        "# end\n",
+        "cat: Goodbye\n",
+    );
+    std::fs::write("screenplay_test.txt", screenplay).unwrap();
+
+    let mut state = EditorState::new(DialogueTreeData::default());
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    assert_eq!(
+        run_cmd("import screenplay screenplay_test.txt", &mut state).unwrap(),
+        3
+    );
+    cmd::util::validate_tree(&state.active).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 3);
+    assert_eq!(state.active.tree.edges().len(), 2);
+    assert_eq!(state.active.tree.target_of(1).unwrap(), 2);
+
+    run_cmd("list --raw", &mut state).unwrap();
+    let expected_list = state.scratchpad.clone();
+    state.scratchpad.clear();
+
+    // the whole import is undone at once
+    cmd::Undo::new().execute(&mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 0);
+    cmd::Redo::new().execute(&mut state).unwrap();
+
+    run_cmd("export screenplay screenplay_test.txt", &mut state).unwrap();
+    let mut new_state = EditorState::new(DialogueTreeData::default());
+    run_cmd("new name cat Behemoth", &mut new_state).unwrap();
+    run_cmd("new val rus_lit 50", &mut new_state).unwrap();
+    run_cmd("import screenplay screenplay_test.txt", &mut new_state).unwrap();
+    run_cmd("list --raw", &mut new_state).unwrap();
+    assert_eq!(new_state.scratchpad, expected_list);
+
+    std::fs::remove_file("screenplay_test.txt").unwrap();
+}
+
+#[test]
+/// Test packaging a project with its assets and a locale file, and reading the runtime data back
+/// out of the package
+fn package() {
+    let (mut state, store) = memory_state(DialogueTreeData::default());
+    run_cmd("new project package_test", &mut state).unwrap();
+    run_cmd("load package_test", &mut state).unwrap();
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    run_cmd("asset root art", &mut state).unwrap();
+    run_cmd("asset add cat cat.png", &mut state).unwrap();
+    run_cmd("asset add voice voice/cat.ogg", &mut state).unwrap();
+
+    // assets are read through the store, resolved against the asset root
+    assert!(run_cmd("package", &mut state).is_err());
+    store.write("art/cat.png", b"portrait").unwrap();
+    store.write("art/voice/cat.ogg", b"meow").unwrap();
+    assert!(run_cmd("package -l fr.csv", &mut state).is_err());
+    store.write("fr.csv", b"id,fr").unwrap();
+
+    run_cmd("package -l fr.csv", &mut state).unwrap();
+    let package = Package::read_from(&store, "package_test.arbor").unwrap();
+    assert_eq!(package.manifest.name, "package_test");
+    assert_eq!(package.manifest.uid, state.active.uid);
+    assert_eq!(
+        package.manifest.assets,
+        vec!["cat.png", "voice/cat.ogg", "fr.csv"]
+    );
+    assert_eq!(package.entry("cat.png").unwrap(), "portrait".as_bytes());
+    assert_eq!(package.entry("voice/cat.ogg").unwrap(), "meow".as_bytes());
+    assert_eq!(package.entry("fr.csv").unwrap(), "id,fr".as_bytes());
+    assert!(package.entry("missing.txt").is_err());
+    assert_eq!(
+        format!("{:?}", package.tree().unwrap()),
+        format!("{:?}", state.active)
+    );
+}
+
+#[test]
+#[cfg(feature = "encryption")]
+/// Test encrypting a package with a password and a raw key, and that reading it without the right
+/// secret fails cleanly
+fn encrypted_package() {
+    use crypt::Secret;
+    let (mut state, store) = memory_state(DialogueTreeData::new("encrypt_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Dostoevsky is immortal\"", &mut state).unwrap();
+    run_cmd("package --password manuscripts", &mut state).unwrap();
+
+    let bytes = store.read("encrypt_test.arbor").unwrap();
+    assert!(!String::from_utf8_lossy(&bytes).contains("Dostoevsky"));
+    let err = Package::read_from(&store, "encrypt_test.arbor").unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<cmd::Error>(),
+        Some(cmd::Error::Encrypted)
+    ));
+    let wrong = Secret::Password("burn".to_string());
+    let err = Package::read_encrypted(&store, "encrypt_test.arbor", &wrong).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<cmd::Error>(),
+        Some(cmd::Error::DecryptFailed)
+    ));
+    let secret = Secret::Password("manuscripts".to_string());
+    let package = Package::read_encrypted(&store, "encrypt_test.arbor", &secret).unwrap();
+    assert_eq!(package.manifest.uid, state.active.uid);
+
+    let key = Secret::Key([7; crypt::KEY_LEN]);
+    package
+        .write_encrypted(&store, "key_test.arbor", &key)
+        .unwrap();
+    let decrypted = Package::read_encrypted(&store, "key_test.arbor", &key).unwrap();
+    assert_eq!(
+        format!("{:?}", decrypted.tree().unwrap()),
+        format!("{:?}", state.active)
+    );
+    assert!(Package::read_encrypted(&store, "key_test.arbor", &secret).is_err());
+}
+
+#[test]
+/// Test that saving and recovering refuse to touch files belonging to a different project
+fn uid_checks() {
+    let (mut state, store) = memory_state(DialogueTreeData::default());
+    run_cmd("new project uid_test", &mut state).unwrap();
+    run_cmd("load uid_test", &mut state).unwrap();
+    let uid = state.active.uid;
+    assert_eq!(uid.to_string().parse::<Uid>().unwrap(), uid);
+    assert_eq!(uid.to_string().len(), 32);
+    assert!("not hex".parse::<Uid>().is_err());
+    assert_eq!(
+        cmd::util::read_uid(&store, "uid_test.tree").unwrap(),
+        Some(uid)
+    );
+    assert_eq!(
+        cmd::util::read_uid(&store, "uid_test_missing.tree").unwrap(),
+        None
+    );
+
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    run_cmd("save", &mut state).unwrap();
+    run_cmd("rebuild", &mut state).unwrap();
+    run_cmd("new node cat \"Dostoevsky's dead\"", &mut state).unwrap();
+    run_cmd("recover", &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 1);
+    assert_eq!(state.backup.tree.nodes().len(), 2);
+
+    run_cmd("meta", &mut state).unwrap();
+    assert!(state.scratchpad.contains(&format!("uid: {}\r\n", uid)));
+    state.scratchpad.clear();
+
+    // a different project with the same name must not be clobbered
+    let mut other = EditorState::with_store(DialogueTreeData::new("uid_test"), state.store.clone());
+    assert!(run_cmd("save", &mut other).is_err());
+    assert!(run_cmd("recover", &mut other).is_err());
+    assert_eq!(
+        cmd::util::read_uid(&store, "uid_test.tree").unwrap(),
+        Some(uid)
+    );
+    run_cmd("save --force", &mut other).unwrap();
+    assert_eq!(
+        cmd::util::read_uid(&store, "uid_test.tree").unwrap(),
+        Some(other.active.uid)
+    );
+
+    // seeded uids are reproducible
+    cmd::util::seed_uids(Some(42));
+    let seeded = DialogueTreeData::new("uid_test").uid;
+    cmd::util::seed_uids(Some(42));
+    assert_eq!(DialogueTreeData::new("uid_test").uid, seeded);
+    cmd::util::seed_uids(None);
+    assert_ne!(DialogueTreeData::new("uid_test").uid, seeded);
+}
+
+#[test]
+/// Test that a failed load leaves the editor state untouched, and that a successful load keeps
+/// the previous project as a backup
+fn load_safety() {
+    let (mut state, _store) = memory_state(DialogueTreeData::default());
+    run_cmd("new project load_test", &mut state).unwrap();
+    run_cmd("load load_test", &mut state).unwrap();
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    let uid = state.active.uid;
+
+    // corrupt tree with a node section outside the text buffer
+    let mut corrupt = EditorState::with_store(
+        DialogueTreeData::new("load_test_corrupt"),
+        state.store.clone(),
+    );
+    corrupt
+        .active
+        .tree
+        .add_node(Dialogue::new(Section::new([0, 32], 0)))
+        .unwrap();
+    run_cmd("save", &mut corrupt).unwrap();
+
+    assert!(run_cmd("load load_test_corrupt", &mut state).is_err());
+    assert!(run_cmd("load load_test_missing", &mut state).is_err());
+    assert_eq!(state.active.uid, uid);
+    assert_eq!(state.active.tree.nodes().len(), 1);
+    cmd::Undo::new().execute(&mut state).unwrap();
+    cmd::Redo::new().execute(&mut state).unwrap();
+
+    run_cmd("load load_test", &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 0);
+    assert!(state.history.record.is_empty());
+    run_cmd("swap", &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 1);
+}
+
+#[test]
+/// Test that rebuilding a corrupted tree reports the problems and restores the active tree
+fn rebuild_corruption() {
+    let (mut state, store) = memory_state(DialogueTreeData::new("rebuild_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    run_cmd("new node cat \"Dostoevsky is immortal\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Dostoevsky's dead\"", &mut state).unwrap();
+    let expected = state.active.tree.get_node(1).unwrap().section.hash;
+    state.active.tree.get_node_mut(1).unwrap().section.hash = 0;
+    state.active.tree.get_edge_mut(0).unwrap().section.text = [0, 4096];
+    let text = state.active.text.clone();
+
+    let err = run_cmd("rebuild", &mut state).unwrap_err();
+    match err.downcast_ref::<cmd::Error>() {
+        Some(cmd::Error::RebuildFailed(problems)) => assert_eq!(
+            problems,
+            &vec![
+                cmd::util::Corruption::EdgeSection(0),
+                cmd::util::Corruption::NodeHash {
+                    index: 1,
+                    expected: 0,
+                    actual: expected
+                },
+            ]
+        ),
+        _ => panic!("unexpected error {}", err),
+    }
+    assert_eq!(state.scratchpad.lines().count(), 2);
+    assert_eq!(state.active.text, text);
+    assert!(store.exists("rebuild_test.tree.bkp").unwrap());
+}
+
+#[test]
+/// Test reading, writing, replacing, listing, and deleting files in the project stores
+fn project_store() {
+    let dir = std::env::temp_dir().join("arbor_store_test");
+    std::fs::create_dir_all(&dir).unwrap();
+    let stores: Vec<Box<dyn store::ProjectStore>> = vec![
+        Box::new(store::MemoryStore::default()),
+        Box::new(store::FileStore::new(&dir)),
+    ];
+    for store in stores {
+        store.write("b.tree", b"second").unwrap();
+        store.write("a.tree", b"first").unwrap();
+        store.write("a.tree", b"replaced").unwrap();
+        assert_eq!(store.read("a.tree").unwrap(), b"replaced");
+        assert_eq!(store.list().unwrap(), vec!["a.tree", "b.tree"]);
+        assert!(store.exists("b.tree").unwrap());
+
+        store.delete("b.tree").unwrap();
+        assert!(!store.exists("b.tree").unwrap());
+        let err = store.read("b.tree").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<cmd::Error>(),
+            Some(cmd::Error::FileNotExists(_))
+        ));
+        assert!(store.delete("b.tree").is_err());
+        store.replace("a.tree", b"atomic").unwrap();
+        assert_eq!(store.read("a.tree").unwrap(), b"atomic");
+        assert_eq!(store.read("a.tree.old").unwrap(), b"replaced");
+        assert_eq!(store.list().unwrap(), vec!["a.tree", "a.tree.old"]);
+
+        store.delete("a.tree").unwrap();
+        store.delete("a.tree.old").unwrap();
+        assert!(store.list().unwrap().is_empty());
+    }
+
+    let options = store::WriteOptions {
+        sync: false,
+        keep_old: false,
+    };
+    let store = store::FileStore::with_options(&dir, options);
+    store.replace("c.tree", b"first").unwrap();
+    store.replace("c.tree", b"second").unwrap();
+    assert_eq!(store.list().unwrap(), vec!["c.tree"]);
+    store.delete("c.tree").unwrap();
+    std::fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+/// Test that compressed saves are smaller and are detected automatically on load
+fn compression() {
+    let (mut state, store) = memory_state(DialogueTreeData::new("compress_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for _ in 0..20 {
+        run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    }
+    run_cmd("save", &mut state).unwrap();
+    let plain = store.read("compress_test.tree").unwrap();
+    run_cmd("save --compress", &mut state).unwrap();
+    let compressed = store.read("compress_test.tree").unwrap();
+    assert!(compressed.starts_with(store::COMPRESSED_MAGIC));
+    assert!(compressed.len() < plain.len());
+    assert_eq!(
+        cmd::util::read_uid(&store, "compress_test.tree").unwrap(),
+        Some(state.active.uid)
+    );
+
+    run_cmd("load compress_test", &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 20);
+    assert_eq!(format!("{:?}", state.active), format!("{:?}", state.backup));
+
+    store
+        .write("compress_test.tree", &compressed[..compressed.len() / 2])
+        .unwrap();
+    assert!(run_cmd("load compress_test", &mut state).is_err());
+}
+
+#[test]
+/// Test that changes made after the last save are journaled and can be replayed onto the saved
+/// project after a crash
+fn journal_replay() {
+    let (mut state, store) = memory_state(DialogueTreeData::new("journal_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    run_cmd("save", &mut state).unwrap();
+    assert!(!store.exists("journal_test.tree.journal").unwrap());
+
+    run_cmd("new node cat \"Dostoevsky is immortal\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Dostoevsky's dead\"", &mut state).unwrap();
+    run_cmd("new node cat \"Manuscripts don't burn\"", &mut state).unwrap();
+    cmd::Undo::new().execute(&mut state).unwrap();
+    run_cmd("rebuild", &mut state).unwrap();
+    run_cmd("edit node 1 cat \"Dostoevsky is immortal!\"", &mut state).unwrap();
+    let expected = format!("{:?}", state.active);
+    run_cmd("list --raw", &mut state).unwrap();
+    let expected_list = std::mem::take(&mut state.scratchpad);
+
+    // simulate a crash by dropping the state without saving, and append a partial entry
+    drop(state);
+    store
+        .append("journal_test.tree.journal", &[0, 0, 0])
+        .unwrap();
+    let (mut state, _) = memory_state(DialogueTreeData::default());
+    state.store = std::sync::Arc::new(store.clone());
+    run_cmd("load journal_test", &mut state).unwrap();
+    assert!(state.scratchpad.contains("found 6 unsaved changes"));
+    assert_eq!(state.active.tree.nodes().len(), 1);
+
+    assert_eq!(run_cmd("replay", &mut state).unwrap(), 6);
+    assert_eq!(format!("{:?}", state.active), expected);
+    state.scratchpad.clear();
+    run_cmd("list --raw", &mut state).unwrap();
+    assert_eq!(state.scratchpad, expected_list);
+    assert!(run_cmd("replay", &mut state).is_err());
+
+    // the journal continues after replaying, and undo history is restored
+    cmd::Undo::new().execute(&mut state).unwrap();
+    let expected = format!("{:?}", state.active);
+    run_cmd("load journal_test", &mut state).unwrap();
+    assert_eq!(run_cmd("replay", &mut state).unwrap(), 7);
+    assert_eq!(format!("{:?}", state.active), expected);
+    cmd::Redo::new().execute(&mut state).unwrap();
+    run_cmd("save", &mut state).unwrap();
+    assert!(!store.exists("journal_test.tree.journal").unwrap());
+
+    // journaling is opt in, states created without a store never write a journal
+    let state = EditorState::new(DialogueTreeData::new("journal_test"));
+    assert!(state.history.journal.is_none());
+    let mut state = EditorState::with_backups(
+        DialogueTreeData::new("journal_off_test"),
+        std::sync::Arc::new(store.clone()),
+        store::default_backups(),
+    );
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("save", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    run_cmd("load journal_test", &mut state).unwrap();
+    run_cmd("new node cat \"Dostoevsky is immortal\"", &mut state).unwrap();
+    assert!(state.history.journal.is_none());
+    assert!(!store.exists("journal_off_test.tree.journal").unwrap());
+    assert!(!store.exists("journal_test.tree.journal").unwrap());
+}
+
+#[test]
+/// Test tracking of unsaved changes through undo/redo and saves, and opening tree files by path
+fn unsaved_changes() {
+    let (mut state, _) = memory_state(DialogueTreeData::new("unsaved_test"));
+    assert!(!state.history.is_modified());
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    assert!(state.history.is_modified());
+    run_cmd("save", &mut state).unwrap();
+    assert!(!state.history.is_modified());
+
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    assert!(state.history.is_modified());
+    cmd::Undo::new().execute(&mut state).unwrap();
+    assert!(!state.history.is_modified());
+    cmd::Redo::new().execute(&mut state).unwrap();
+    run_cmd("save", &mut state).unwrap();
+    cmd::Undo::new().execute(&mut state).unwrap();
+    assert!(state.history.is_modified());
+    // the saved copy is lost once the undone event is replaced
+    run_cmd("new node cat \"Manuscripts don't burn\"", &mut state).unwrap();
+    cmd::Undo::new().execute(&mut state).unwrap();
+    assert!(state.history.is_modified());
+    run_cmd("rebuild", &mut state).unwrap();
+    assert!(state.history.is_modified());
+
+    let path = std::path::Path::new("projects/unsaved_test.tree");
+    let (dir, name) = cmd::util::split_tree_path(path).unwrap();
+    assert_eq!(dir, std::path::Path::new("projects"));
+    assert_eq!(name, "unsaved_test");
+    let (dir, _) = cmd::util::split_tree_path(std::path::Path::new("a.tree")).unwrap();
+    assert_eq!(dir, std::path::Path::new("."));
+    assert!(cmd::util::split_tree_path(std::path::Path::new("story.yarn")).is_err());
+    assert!(cmd::util::split_tree_path(std::path::Path::new("tree")).is_err());
+}
+
+#[test]
+/// Test that repair fixes a corrupted project file that fails to load, writing a new file
+fn repair() {
+    let (mut state, store) = memory_state(DialogueTreeData::new("repair_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    run_cmd("new node cat \"Dostoevsky is immortal\"", &mut state).unwrap();
+    run_cmd("new node cat \"Manuscripts don't burn\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Dostoevsky's dead\"", &mut state).unwrap();
+    run_cmd("new edge -r Less(rus_lit,51) 0 2 \"Read on\"", &mut state).unwrap();
+    run_cmd("new edge 1 2 \"Who knows\"", &mut state).unwrap();
+    run_cmd("bookmark add start 0", &mut state).unwrap();
+    run_cmd("save", &mut state).unwrap();
+    assert_eq!(run_cmd("repair", &mut state).unwrap(), 0);
+
+    let data = &mut state.active;
+    data.tree.get_node_mut(1).unwrap().section.hash = 0;
+    data.tree.get_edge_mut(0).unwrap().section.text = [0, 4096];
+    data.tree.edge_targets[2] = 99;
+    data.tree.node_links[0] = 2;
+    data.val_table.remove("rus_lit");
+    data.bookmarks.insert(KeyString::from("lost").unwrap(), 9);
+    run_cmd("save", &mut state).unwrap();
+    assert!(run_cmd("load repair_test", &mut state).is_err());
+
+    state.scratchpad.clear();
+    assert_eq!(
+        run_cmd("repair repair_test -o fixed", &mut state).unwrap(),
+        7
+    );
+    assert!(state
+        .scratchpad
+        .starts_with("repaired node 1: hash mismatch, expected 0x0 found 0x"));
+    assert!(state
+        .scratchpad
+        .contains("repaired edge 2: source or target node does not exist\r\n"));
+    assert!(state
+        .scratchpad
+        .contains("repaired node 0: outgoing edge list is broken\r\n"));
+    // the list of node 1 only held the dropped edge
+    assert!(state
+        .scratchpad
+        .contains("repaired node 1: outgoing edge list is broken\r\n"));
+    assert!(state
+        .scratchpad
+        .contains("repaired edge 0: text section is out of bounds\r\n"));
+    assert!(state
+        .scratchpad
+        .contains("repaired edge 1: requirement references a missing key\r\n"));
+    assert!(state
+        .scratchpad
+        .contains("repaired bookmark lost: node does not exist\r\n"));
+    assert!(state
+        .scratchpad
+        .ends_with("7 problems repaired, written to fixed.tree\r\n"));
+
+    run_cmd("load fixed", &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 3);
+    assert_eq!(state.active.tree.edges().len(), 2);
+    assert_eq!(state.active.bookmarks.len(), 1);
+    state.scratchpad.clear();
+    run_cmd("list --raw", &mut state).unwrap();
+    let expected_list = concat!(
+        "node 0: Behemoth says \"Well, who knows, who knows\"\r\n",
+        "--> edge 0 to node 1: \"[missing text]\"\r\n",
+        "    requirements: No, effects: No\r\n",
+        "--> edge 1 to node 2: \"Read on\"\r\n",
+        "    requirements: No, effects: No\r\n",
+        "node 1: Behemoth says \"Dostoevsky is immortal\"\r\n",
+        "node 2: Behemoth says \"Manuscripts don't burn\"\r\n",
+    );
+    assert_eq!(state.scratchpad, expected_list);
+    assert!(store.exists("repair_test.tree").unwrap());
+    assert!(run_cmd("repair -o fixed", &mut state).is_err());
+}
+
+#[test]
+/// Test playing through a tree, with requirements hiding choices and effects changing the names
+/// and values the runner sees
+fn runner() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("runner_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    run_cmd("new node cat \"I am ::cat::\"", &mut state).unwrap();
+    run_cmd("new node cat \"Dostoevsky is immortal\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -r Less(rus_lit,51) -e Sub(rus_lit,10) 0 1 \"Dostoevsky's dead\"",
+        &mut state,
+    )
+    .unwrap();
+    run_cmd(
+        "new edge -e Assign(cat,Kot) 1 0 \"Who are you?\"",
+        &mut state,
+    )
+    .unwrap();
+    run_cmd(
+        "new edge -r Greater(rus_lit,45) 1 0 \"Once more\"",
+        &mut state,
+    )
+    .unwrap();
+    let data = &state.active;
+
+    let mut runner = runner::Runner::new(data, 0).unwrap();
+    assert_eq!(
+        runner.dialogue(data).unwrap(),
+        ("Behemoth".to_string(), "I am Behemoth".to_string())
+    );
+    assert_eq!(
+        runner.choices(data).unwrap(),
+        vec![(0, "Dostoevsky's dead".to_string())]
+    );
+    runner.choose(data, 0).unwrap();
+    assert_eq!(runner.vals.get("rus_lit"), Some(&40));
+    // rus_lit is no longer greater than 45
+    assert_eq!(runner.choices(data).unwrap().len(), 1);
+    let all = runner.all_choices(data).unwrap();
+    assert_eq!(all.len(), 2);
+    assert!(all[0].met);
+    assert!(!all[1].met);
+    assert_eq!(
+        runner::describe_requirement(&all[1].requirement, &runner.names, &runner.vals),
+        "requires rus_lit > 45, currently 40"
+    );
+    assert!(runner.choose(data, 2).is_err());
+    runner.choose(data, 1).unwrap();
+    assert_eq!(runner.dialogue(data).unwrap().1, "I am Kot");
+    assert_eq!(runner.path, vec![0, 1]);
+    // the project itself is untouched
+    assert_eq!(data.name_table.get("cat").unwrap(), "Behemoth");
+
+    let replayed = runner::Runner::replay(data, 0, &[0, 2, 1]).unwrap();
+    assert_eq!(replayed.path, vec![0]);
+    assert_eq!(replayed.node, 1);
+    assert!(runner::Runner::new(data, 5).is_err());
+}
+
+#[test]
+/// Test moving a playthrough onto an edited tree, following moved and edited nodes and rewinding
+/// when the current node is deleted
+fn runner_hot_reload() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("reload_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for text in ["Hm", "Duel?", "Fire!", "Farewell"].iter() {
+        run_cmd(&format!("new node cat \"{}\"", text), &mut state).unwrap();
+    }
+    run_cmd("new edge 0 1 \"Challenge\"", &mut state).unwrap();
+    run_cmd("new edge 1 2 \"Accept\"", &mut state).unwrap();
+    run_cmd("new edge 0 3 \"Leave\"", &mut state).unwrap();
+    let mut runner = runner::Runner::new(&state.active, 0).unwrap();
+    runner.choose(&state.active, 2).unwrap();
+
+    // removing node 1 moves the current node into its index
+    let old = state.active.clone();
+    run_cmd("remove nodes 1", &mut state).unwrap();
+    run_cmd("new val gold 5", &mut state).unwrap();
+    let reload = runner.hot_reload(&old, &state.active, 0).unwrap();
+    assert_eq!(reload, runner::Reload::Found(1));
+    assert_eq!(runner.node, 1);
+    assert_eq!(runner.dialogue(&state.active).unwrap().1, "Farewell");
+    assert_eq!(runner.path.len(), 1);
+    assert_eq!(state.active.tree.target_of(runner.path[0]).unwrap(), 1);
+    assert_eq!(runner.vals.get("gold"), Some(&5));
+
+    let old = state.active.clone();
+    run_cmd("edit node 1 cat \"So long\"", &mut state).unwrap();
+    let reload = runner.hot_reload(&old, &state.active, 0).unwrap();
+    assert_eq!(reload, runner::Reload::Edited(1));
+    assert_eq!(runner.dialogue(&state.active).unwrap().1, "So long");
+
+    // a deleted node rewinds to the node the playthrough came from
+    let old = state.active.clone();
+    run_cmd("remove nodes 1", &mut state).unwrap();
+    let reload = runner.hot_reload(&old, &state.active, 0).unwrap();
+    assert_eq!(reload, runner::Reload::Rewound(0));
+    assert!(runner.path.is_empty());
+    assert!(runner.hot_reload(&old, &state.active, 9).is_err());
+}
+
+#[test]
+/// Test building and querying a tree through the editor facade
+fn editor() {
+    let mut editor = editor::Editor::with_store(
+        DialogueTreeData::new("editor_test"),
+        std::sync::Arc::new(store::MemoryStore::default()),
+    );
+    let cat = KeyString::from("cat").unwrap();
+    let rus_lit = KeyString::from("rus_lit").unwrap();
+    editor
+        .new_name(cat, NameString::from("Behemoth").unwrap())
+        .unwrap();
+    editor.new_val(rus_lit, 50).unwrap();
+    let start = editor
+        .new_node("cat", "Well, who knows, who knows")
+        .unwrap();
+    let end = editor.new_node("cat", "Dostoevsky is immortal").unwrap();
+    let edge = editor
+        .new_edge(
+            start,
+            end,
+            "Dostoevsky's dead",
+            ReqKind::Less(rus_lit, 51),
+            EffectKind::Sub(rus_lit, 1),
+        )
+        .unwrap();
+    assert!(editor.new_node("dog", "Woof").is_err());
+
+    assert_eq!(
+        editor.node_text(start).unwrap(),
+        (
+            "Behemoth".to_string(),
+            "Well, who knows, who knows".to_string()
+        )
+    );
+    assert_eq!(editor.edge_text(edge).unwrap(), "Dostoevsky's dead");
+    assert_eq!(editor.outgoing_edges(start).unwrap(), vec![edge]);
+    assert_eq!(editor.endpoints(edge).unwrap(), (start, end));
+
+    editor
+        .edit_name(cat, NameString::from("Kot").unwrap())
+        .unwrap();
+    editor.move_node(end, Position::new(10.0, 5.0)).unwrap();
+    assert_eq!(editor.node_text(end).unwrap().0, "Kot");
+    assert_eq!(
+        editor.render_node(start).unwrap(),
+        ("Kot", "Well, who knows, who knows")
+    );
+    assert_eq!(editor.render_edge(edge).unwrap(), "Dostoevsky's dead");
+    assert!(editor.render_edge(edge + 1).is_err());
+    assert!(editor.remove_node(end).is_err());
+    editor.remove_edge(edge).unwrap();
+    editor.remove_node(end).unwrap();
+    assert_eq!(editor.data().tree.nodes().len(), 1);
+
+    editor.undo().unwrap();
+    editor.undo().unwrap();
+    editor.undo().unwrap();
+    assert_eq!(editor.data().position(end).x, 0.0);
+    editor.redo().unwrap();
+    assert_eq!(editor.data().position(end).x, 10.0);
+    editor.execute(&cmd::Undo::new()).unwrap();
+    assert_eq!(editor.data().position(end).x, 0.0);
+    assert!(editor.list().unwrap().starts_with("node 0: Kot says"));
+}
+
+#[test]
+/// Test that background save, load, and rebuild apply their results and block modification of
+/// the editor state while running
+fn editor_async() {
+    use editor::{Notification, Task};
+    let store = store::MemoryStore::default();
+    let mut editor = editor::Editor::with_store(
+        DialogueTreeData::new("editor_async_test"),
+        std::sync::Arc::new(store.clone()),
+    );
+    editor
+        .new_name(
+            KeyString::from("cat").unwrap(),
+            NameString::from("Behemoth").unwrap(),
+        )
+        .unwrap();
+    editor
+        .new_node("cat", "Well, who knows, who knows")
+        .unwrap();
+
+    editor.save_async(false).unwrap();
+    assert!(editor.is_busy());
+    assert!(editor.new_node("cat", "Dostoevsky is immortal").is_err());
+    assert!(editor.save_async(false).is_err());
+    assert!(editor.execute(&cmd::Undo::new()).is_err());
+    let notifications = editor.wait();
+    assert_eq!(
+        notifications.last(),
+        Some(&Notification::Finished(Task::Save, Ok(())))
+    );
+    assert!(!editor.is_busy());
+
+    editor.new_node("cat", "Dostoevsky is immortal").unwrap();
+    editor.rebuild_async().unwrap();
+    editor.wait();
+    assert_eq!(editor.data().tree.nodes().len(), 2);
+    assert!(editor.undo().is_err());
+
+    editor.load_async("editor_async_test").unwrap();
+    editor.wait();
+    assert_eq!(editor.data().tree.nodes().len(), 1);
+    editor.load_async("editor_async_missing").unwrap();
+    match editor.wait().last() {
+        Some(Notification::Finished(Task::Load, Err(_))) => {}
+        n => panic!("unexpected notification {:?}", n),
+    }
+    assert_eq!(editor.data().tree.nodes().len(), 1);
+    assert_eq!(store.list().unwrap().len(), 3);
+}
+
+#[test]
+/// Test reading and modifying a shared editor from multiple threads
+fn shared_editor() {
+    let (state, _store) = memory_state(DialogueTreeData::new("shared_editor_test"));
+    let shared = editor::SharedEditor::new(state);
+    run_cmd("new name cat Behemoth", &mut shared.write().unwrap()).unwrap();
+
+    let workers: Vec<_> = (0..4)
+        .map(|i| {
+            let shared = shared.clone();
+            std::thread::spawn(move || {
+                let cmd = format!("new node cat \"line {}\"", i);
+                let cmds = shellwords::split(&cmd).unwrap();
+                shared
+                    .queue(cmd::Parse::from_iter_safe(cmds).unwrap())
+                    .unwrap();
+                shared.snapshot().unwrap().name_table.len()
+            })
+        })
+        .collect();
+    for worker in workers {
+        assert_eq!(worker.join().unwrap(), 1);
+    }
+
+    let results = shared.execute_queued().unwrap();
+    assert_eq!(results.len(), 4);
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert!(shared.execute_queued().unwrap().is_empty());
+    assert_eq!(shared.read().unwrap().active.tree.nodes().len(), 4);
+
+    shared.execute(&cmd::Undo::new()).unwrap();
+    assert_eq!(shared.read().unwrap().active.tree.nodes().len(), 3);
+}
+
+#[test]
+/// Test that narrator nodes don't need a speaker in the name table, and use the narrator label
+/// if one is defined
+fn narrator() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("narrator_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node _ \"The ::cat:: sighs\"", &mut state).unwrap();
+    assert!(run_cmd("new node dog \"Woof\"", &mut state).is_err());
+    cmd::util::validate_tree(&state.active).unwrap();
+    assert!(cmd::util::key_usage(&state.active).is_empty());
+
+    run_cmd("list --raw", &mut state).unwrap();
+    assert_eq!(state.scratchpad, "node 0: \"The Behemoth sighs\"\r\n");
+    state.scratchpad.clear();
+
+    run_cmd("new name _ Narrator", &mut state).unwrap();
+    run_cmd("list --raw", &mut state).unwrap();
+    assert_eq!(
+        state.scratchpad,
+        "node 0: Narrator says \"The Behemoth sighs\"\r\n"
+    );
+}
+
+#[test]
+/// Test that the default list output is an aligned table with truncated text previews
+fn list_table() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("list_table_test"));
+    state.active.uid = Uid(0xc47);
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rus_lit 100", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    run_cmd("new node cat \"Dostoevsky is immortal\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -r Less(rus_lit,51) 0 1 \"Dostoevsky's dead\"",
+        &mut state,
+    )
+    .unwrap();
+
+    run_cmd("list -w 12", &mut state).unwrap();
+    let expected_list = format!(
+        concat!(
+            "NODE  EDGE  TARGET  SPEAKER   TEXT          REQUIREMENT       EFFECT  UID\r\n",
+            "0                   Behemoth  Well, who...                            {uid}\r\n",
+            "      0     1                 Dostoevsk...  Less(rus_lit,51)\r\n",
+            "1                   Behemoth  Dostoevsk...                            {uid}\r\n",
+        ),
+        uid = "00000000000000000000000000000c47"
+    );
+    assert_eq!(state.scratchpad, expected_list);
+    assert_eq!(cmd::util::truncate("short", 12), "short");
+    assert_eq!(cmd::util::truncate("short", 4), "s...");
+    assert_eq!(cmd::util::truncate("short", 3), "sho");
+    assert_eq!(cmd::util::truncate("short", 2), "sh");
+    assert_eq!(cmd::util::truncate("short", 0), "");
+
+    // requirements are checked against assumed values, other keys keep their project value
+    state.scratchpad.clear();
+    run_cmd("list -w 12 --assume rus_lit=40,cat=Leo", &mut state).unwrap();
+    let expected_list = format!(
+        concat!(
+            "NODE  EDGE  TARGET  SPEAKER   TEXT          REQUIREMENT       EFFECT  ",
+            "ASSUMED  UID\r\n",
+            "0                   Behemoth  Well, who...",
+            "                                     {uid}\r\n",
+            "      0     1                 Dostoevsk...  Less(rus_lit,51)          pass\r\n",
+            "1                   Behemoth  Dostoevsk...",
+            "                                     {uid}\r\n",
+        ),
+        uid = "00000000000000000000000000000c47"
+    );
+    assert_eq!(state.scratchpad, expected_list);
+    state.scratchpad.clear();
+    run_cmd("list -r --assume gold=20", &mut state).unwrap();
+    assert!(state.scratchpad.contains("effects: No, assumed: fail\r\n"));
+    let cmds = shellwords::split("list --assume rus_lit").unwrap();
+    assert!(cmd::Parse::from_iter_safe(cmds).is_err());
+}
+
+#[test]
+/// Test that queries select the expected nodes and edges, and filter the list output
+fn query() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("query_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new name kot Kot", &mut state).unwrap();
+    run_cmd("new val rus_lit 100", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    run_cmd("new node kot \"Dostoevsky is immortal\"", &mut state).unwrap();
+    run_cmd("new node cat \"'I protest!'\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -r Less(rus_lit,51) 0 1 \"Dostoevsky's dead\"",
+        &mut state,
+    )
+    .unwrap();
+    run_cmd("new edge 0 2 \"He is immortal\"", &mut state).unwrap();
+    let data = &state.active;
+
+    let select = |s: &str| s.parse::<query::Query>().unwrap().select(data).unwrap();
+    assert_eq!(select("select nodes"), vec![0, 1, 2]);
+    assert_eq!(select("select nodes where speaker == cat"), vec![0, 2]);
+    assert_eq!(
+        select("select nodes where speaker == cat and out_degree == 0"),
+        vec![2]
+    );
+    assert_eq!(
+        select("select nodes where speaker == kot or out_degree>1"),
+        vec![0, 1]
+    );
+    assert_eq!(select("select nodes where text ~ 'who knows'"), vec![0]);
+    assert_eq!(select("select edges where requirement ~ rus_lit"), vec![0]);
+    assert_eq!(select("edges where target >= 2"), vec![1]);
+
+    assert!("select nodes where target == 1"
+        .parse::<query::Query>()
+        .is_err());
+    assert!("select nodes where index ~ 1"
+        .parse::<query::Query>()
+        .is_err());
+    assert!("select nodes where speaker < cat"
+        .parse::<query::Query>()
+        .is_err());
+    assert!("select nodes where text ~ 'unclosed"
+        .parse::<query::Query>()
+        .is_err());
+
+    run_cmd(
+        "list --raw -q \"select edges where index == 1\"",
+        &mut state,
+    )
+    .unwrap();
+    assert_eq!(
+        state.scratchpad,
+        concat!(
+            "node 0: Behemoth says \"Well, who knows, who knows\"\r\n",
+            "--> edge 1 to node 2: \"He is immortal\"\r\n",
+            "    requirements: No, effects: No\r\n",
+        )
+    );
+
+    // replace and tag only change the selected nodes or edges
+    let line = "replace -q \"select nodes where speaker == kot\" immortal eternal";
+    assert_eq!(run_cmd(line, &mut state).unwrap(), 1);
+    assert_eq!(run_cmd("replace immortal eternal", &mut state).unwrap(), 1);
+    let line = "edit tag spoiler -q \"select nodes where speaker == cat\"";
+    assert_eq!(run_cmd(line, &mut state).unwrap(), 2);
+    assert!(state.active.tree.get_node(1).unwrap().flags.is_empty());
+    assert!(run_cmd("edit tag spoiler -q \"select edges\"", &mut state).is_err());
+}
+
+#[test]
+/// Test that bookmarks follow their node as other nodes are removed, and block removal of the
+/// bookmarked node
+fn bookmarks() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("bookmark_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for text in ["first", "second", "third"].iter() {
+        run_cmd(&format!("new node cat {}", text), &mut state).unwrap();
+    }
+    run_cmd("bookmark add end 2", &mut state).unwrap();
+    assert!(run_cmd("bookmark add end 1", &mut state).is_err());
+    assert!(run_cmd("bookmark add start 5", &mut state).is_err());
+    assert!(run_cmd("remove node 2", &mut state).is_err());
+
+    // removing node 0 swaps the bookmarked node into index 0
+    run_cmd("remove node 0", &mut state).unwrap();
+    assert_eq!(run_cmd("bookmark goto end", &mut state).unwrap(), 0);
+    assert_eq!(state.active.cursor, Some(0));
+    cmd::Undo::new().execute(&mut state).unwrap();
+    assert_eq!(state.active.bookmarks["end"], 2);
+    cmd::Redo::new().execute(&mut state).unwrap();
+    assert_eq!(state.active.bookmarks["end"], 0);
+
+    run_cmd("bookmark list", &mut state).unwrap();
+    assert_eq!(state.scratchpad, "end: node 0\r\n");
+    run_cmd("bookmark remove end", &mut state).unwrap();
+    assert!(run_cmd("bookmark goto end", &mut state).is_err());
+    run_cmd("remove node 0", &mut state).unwrap();
+}
+
+#[test]
+/// Test navigating the tree with the editor cursor, and creating edges from the cursor
+fn cursor() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("cursor_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for text in ["first", "second", "third"].iter() {
+        run_cmd(&format!("new node cat {}", text), &mut state).unwrap();
+    }
+    assert!(run_cmd("new edge 1 \"Next\"", &mut state).is_err());
+    assert!(run_cmd("children", &mut state).is_err());
+    assert!(run_cmd("goto 3", &mut state).is_err());
+
+    run_cmd("goto 0", &mut state).unwrap();
+    run_cmd("new edge 1 \"Next\"", &mut state).unwrap();
+    run_cmd("new edge 2 0 \"Back\"", &mut state).unwrap();
+    assert_eq!(run_cmd("children", &mut state).unwrap(), 1);
+    assert_eq!(state.scratchpad, "--> edge 0 to node 1: \"Next\"\r\n");
+    state.scratchpad.clear();
+    assert_eq!(run_cmd("parent", &mut state).unwrap(), 1);
+    assert_eq!(state.scratchpad, "<-- edge 1 from node 2: \"Back\"\r\n");
+    state.scratchpad.clear();
+
+    // the cursor follows its node when another node is removed
+    run_cmd("new node cat fourth", &mut state).unwrap();
+    run_cmd("goto 3", &mut state).unwrap();
+    run_cmd("remove edge 0", &mut state).unwrap();
+    run_cmd("remove node 1", &mut state).unwrap();
+    assert_eq!(state.active.cursor, Some(1));
+
+    // undo and redo move the cursor along with its node as well
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.cursor, Some(3));
+    run_cmd("redo", &mut state).unwrap();
+    assert_eq!(state.active.cursor, Some(1));
+
+    run_cmd("remove node 1", &mut state).unwrap();
+    assert_eq!(state.active.cursor, None);
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.cursor, None);
+}
+
+#[test]
+/// Test building requirements and effects from prompt answers, with abbreviated kinds and keys
+fn prompt_builder() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("prompt_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rus_lit 100", &mut state).unwrap();
+    run_cmd("new val rum 1", &mut state).unwrap();
+    let data = &state.active;
+
+    let answers = |list: &'static [&'static str]| {
+        let mut iter = list.iter();
+        move |_: &str| iter.next().unwrap().to_string()
+    };
+
+    // an ambiguous key prefix and an invalid value are asked again
+    let mut ask = answers(&["gr", "ru", "rus", "lots", "51"]);
+    let req = cmd::util::prompt_requirement(&mut ask, &data.name_table, &data.val_table);
+    assert_eq!(req.unwrap().to_string(), "Greater(rus_lit,51)");
+
+    let mut ask = answers(&["assign", "c", "Woland"]);
+    let effect = cmd::util::prompt_effect(&mut ask, &data.name_table, &data.val_table);
+    assert_eq!(effect.unwrap().to_string(), "Assign(cat,Woland)");
+
+    let mut ask = answers(&[""]);
+    let req = cmd::util::prompt_requirement(&mut ask, &data.name_table, &data.val_table);
+    assert_eq!(req.unwrap(), ReqKind::No);
+
+    let mut ask = answers(&["sub", ""]);
+    assert!(cmd::util::prompt_effect(&mut ask, &data.name_table, &data.val_table).is_err());
+}
+
+#[test]
+/// Test that every kind of reference to a key is found, and that keys in use can't be removed
+fn where_used() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("where_used_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new name kot Kot", &mut state).unwrap();
+    run_cmd("new val rus_lit 100", &mut state).unwrap();
+    run_cmd(
+        "new node cat \"'I protest!' ::cat:: exclaimed\"",
+        &mut state,
+    )
+    .unwrap();
+    run_cmd("new node kot \"Dostoevsky is immortal\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -r Less(rus_lit,51) -e Assign(cat,Woland) 0 1 \"Ask ::cat::\"",
+        &mut state,
+    )
+    .unwrap();
+
+    let refs = cmd::util::key_references(&state.active, cmd::util::KeyKind::Name, "cat");
+    use cmd::util::Reference::*;
+    assert_eq!(refs, vec![Speaker(0), NodeText(0), EdgeText(0), Effect(0)]);
+    let refs = cmd::util::key_references(&state.active, cmd::util::KeyKind::Val, "rus_lit");
+    assert_eq!(refs, vec![Requirement(0)]);
+    assert_eq!(Speaker(0).node(), Some(0));
+    assert_eq!(Speaker(0).edge(), None);
+    assert_eq!(Effect(2).edge(), Some(2));
+    assert_eq!(Interrupt(1).node().or(Interrupt(1).edge()), None);
+    assert_eq!(Effect(2).to_string(), "effect of edge 2");
+
+    let keys = cmd::util::node_keys(&state.active, 0);
+    assert!(keys.contains(cmd::util::KeyKind::Name, "cat"));
+    assert!(keys.vals.is_empty());
+    let keys = cmd::util::edge_keys(&state.active, 0);
+    assert!(keys.contains(cmd::util::KeyKind::Name, "cat"));
+    assert!(keys.contains(cmd::util::KeyKind::Val, "rus_lit"));
+    assert!(!keys.contains(cmd::util::KeyKind::Name, "kot"));
+    assert_eq!(cmd::util::node_keys(&state.active, 7), Default::default());
+
+    assert_eq!(run_cmd("where-used rus_lit", &mut state).unwrap(), 1);
+    assert_eq!(
+        state.scratchpad,
+        "val rus_lit: requirement of edge 0: Less(rus_lit,51)\r\n"
+    );
+    state.scratchpad.clear();
+    assert_eq!(run_cmd("where-used kot -w 8", &mut state).unwrap(), 1);
+    assert_eq!(
+        state.scratchpad,
+        "name kot: speaker of node 1: \"Dosto...\"\r\n"
+    );
+
+    assert!(run_cmd("remove val rus_lit", &mut state).is_err());
+    assert!(run_cmd("remove name kot", &mut state).is_err());
+    run_cmd("remove node 1", &mut state).unwrap_err();
+    run_cmd("remove edge 0", &mut state).unwrap();
+    run_cmd("remove val rus_lit", &mut state).unwrap();
+    run_cmd("remove node 1", &mut state).unwrap();
+    run_cmd("remove name kot", &mut state).unwrap();
+}
+
+#[test]
+/// Test importing names and values from CSV and JSON files
+fn import_tables() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("import_tables_test"));
+    std::fs::write("import_tables_test.csv", "cat, Behemoth\nkot,Kot\n").unwrap();
+    std::fs::write("import_tables_test.json", r#"{"rus_lit": 100, "rum": "1"}"#).unwrap();
+    std::fs::write("import_tables_dup.csv", "dog,Sharik\ndog,Sharikov\n").unwrap();
+    std::fs::write("import_tables_bad.json", r#"{"rum": [1]}"#).unwrap();
+
+    assert_eq!(
+        run_cmd("import names import_tables_test.csv", &mut state).unwrap(),
+        2
+    );
+    assert_eq!(
+        run_cmd("import vals import_tables_test.json", &mut state).unwrap(),
+        2
+    );
+    assert_eq!(state.active.name_table["cat"].as_str(), "Behemoth");
+    assert_eq!(state.active.val_table["rus_lit"], 100);
+    assert_eq!(state.active.val_table["rum"], 1);
+
+    // imports fail as a whole, and are undone as a whole
+    assert!(run_cmd("import names import_tables_test.csv", &mut state).is_err());
+    assert!(run_cmd("import names import_tables_dup.csv", &mut state).is_err());
+    assert!(run_cmd("import vals import_tables_bad.json", &mut state).is_err());
+    assert!(run_cmd("import vals import_tables_test.csv", &mut state).is_err());
+    assert!(!state.active.name_table.contains_key("dog"));
+    cmd::Undo::new().execute(&mut state).unwrap();
+    assert!(state.active.val_table.is_empty());
+    assert_eq!(state.active.name_table.len(), 2);
+
+    for file in [
+        "import_tables_test.csv",
+        "import_tables_test.json",
+        "import_tables_dup.csv",
+        "import_tables_bad.json",
+    ]
+    .iter()
+    {
+        std::fs::remove_file(file).unwrap();
+    }
 }
 
 mod tree_tests {
@@ -91,7 +1288,7 @@ mod tree_tests {
     fn outgoing_edges() {
         let mut tree = tree::Tree::with_capacity(10, 10);
         //dummy dialogue for creating nodes
-        let dia = Dialogue::new(Section::new([0, 0], 0), Position::default());
+        let dia = Dialogue::new(Section::new([0, 0], 0));
         let choice = Choice::new(Section::new([0, 0], 0), ReqKind::No, EffectKind::No);
 
         for _ in 0..10 {
@@ -100,7 +1297,7 @@ mod tree_tests {
 
         // add edges such that all edges are an outgoing edge of node 0
         for i in 0..10 {
-            tree.add_edge(0, i, choice).unwrap();
+            tree.add_edge(0, i, choice.clone()).unwrap();
         }
 
         // iterate over all outgoing edges of node 0 and verify they are correct
@@ -114,7 +1311,7 @@ mod tree_tests {
     fn add_remove_node() {
         let mut tree = tree::Tree::with_capacity(10, 10);
         //dummy dialogue for creating nodes
-        let dia = Dialogue::new(Section::new([0, 0], 0), Position::default());
+        let dia = Dialogue::new(Section::new([0, 0], 0));
 
         for _ in 0..10 {
             tree.add_node(dia).unwrap();
@@ -137,6 +1334,12 @@ mod tree_tests {
         assert_eq!(format!("{:?}", tree), format!("{:?}", tree_full));
     }
 
+    /// Test that events stay small, large payloads should be boxed to keep the history compact
+    #[test]
+    fn event_size() {
+        assert!(std::mem::size_of::<DialogueTreeEvent>() <= EVENT_SIZE_BUDGET);
+    }
+
     /// Test top level undo-redo capability of EditorState
     #[test]
     fn undo_redo() {
@@ -175,7 +1378,7 @@ mod tree_tests {
     fn add_remove_edge() {
         let mut tree = tree::Tree::with_capacity(10, 10);
         //dummy dialogue for creating nodes
-        let dia = Dialogue::new(Section::new([0, 0], 0), Position::default());
+        let dia = Dialogue::new(Section::new([0, 0], 0));
         let choice = Choice::new(Section::new([0, 0], 0), ReqKind::No, EffectKind::No);
 
         for _ in 0..10 {
@@ -184,7 +1387,7 @@ mod tree_tests {
 
         // add edges such that all edges are an outgoing edge of node 0
         for i in 0..10 {
-            tree.add_edge(0, i, choice).unwrap();
+            tree.add_edge(0, i, choice.clone()).unwrap();
         }
         let tree_full = tree.clone();
 
@@ -254,3 +1457,2759 @@ mod tree_tests {
         assert_eq!(format!("{:?}", tree), format!("{:?}", tree_full));
     }
 }
+
+#[test]
+/// Test describing history events in plain words, and printing the history
+fn history_describe() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("history_test"));
+    run_cmd("history list", &mut state).unwrap();
+    assert_eq!(state.scratchpad, "no changes\r\n");
+    state.scratchpad.clear();
+
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Hello there\"", &mut state).unwrap();
+    run_cmd("edit node 0 cat \"Hi there\"", &mut state).unwrap();
+    run_cmd("new val rus_lit 40", &mut state).unwrap();
+    run_cmd("edit name cat Leviathan", &mut state).unwrap();
+    cmd::Undo::new().execute(&mut state).unwrap();
+
+    let record = &state.history.record;
+    assert_eq!(
+        record[0].describe(&state.active),
+        "Added name cat: 'Behemoth'"
+    );
+    assert_eq!(
+        record[2].describe(&state.active),
+        "Edited node 0: '::cat::Hello there' → '::cat::Hi there'"
+    );
+    assert_eq!(run_cmd("history list -n 2", &mut state).unwrap(), 4);
+    assert_eq!(
+        state.scratchpad,
+        "> 4: Added val rus_lit: 40\r\n  5: (undone) Edited name cat: 'Behemoth' → 'Leviathan'\r\n"
+    );
+}
+
+#[test]
+/// Test showing edits of node and edge text as word diffs
+fn word_diff() {
+    use cmd::util::WordDiff;
+    let diff = cmd::util::word_diff("Don't go, please.", "Do not go please!");
+    assert_eq!(
+        cmd::util::render_diff(&diff, false),
+        "[-Don't-]{+Do+} {+not +}go[-,-] please[-.-]{+!+}"
+    );
+    assert_eq!(
+        cmd::util::word_diff("a b", "a b"),
+        vec![WordDiff::Same("a b".to_string())]
+    );
+    assert_eq!(
+        cmd::util::word_diff("", "new words"),
+        vec![WordDiff::Added("new words".to_string())]
+    );
+    assert_eq!(
+        cmd::util::render_diff(&cmd::util::word_diff("cat", "kot"), true),
+        "\u{1b}[9;31mcat\u{1b}[0m\u{1b}[32mkot\u{1b}[0m"
+    );
+
+    let (mut state, _store) = memory_state(DialogueTreeData::new("diff_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Hello there\"", &mut state).unwrap();
+    run_cmd("new edge 0 0 \"Who knows\"", &mut state).unwrap();
+    // the shorter text is rewritten in place, the longer one is appended to the buffer
+    run_cmd("edit node 0 cat \"Hi there\"", &mut state).unwrap();
+    run_cmd("edit edge 0 \"Well, who knows\"", &mut state).unwrap();
+
+    let change = state.history.record[3].text_change(&state.active).unwrap();
+    assert_eq!(change.title, "Edited node 0");
+    assert_eq!(change.old, "::cat::Hello there");
+    assert_eq!(change.new, "::cat::Hi there");
+    assert!(state.history.record[1].text_change(&state.active).is_none());
+
+    // the texts are the same whether the edit is applied or undone
+    cmd::Undo::new().execute(&mut state).unwrap();
+    cmd::Undo::new().execute(&mut state).unwrap();
+    assert_eq!(
+        state.history.record[3].text_change(&state.active),
+        Some(change)
+    );
+    run_cmd("history jump 5", &mut state).unwrap();
+
+    // the test harness may print to a terminal, where the history would be colored
+    std::env::set_var("NO_COLOR", "1");
+    run_cmd("history list -n 2", &mut state).unwrap();
+    assert_eq!(
+        state.scratchpad,
+        "  4: Edited node 0: ::cat::[-Hello-]{+Hi+} there\r\n\
+         > 5: Edited edge 0: [-Who-]{+Well,+} {+who +}knows\r\n"
+    );
+}
+
+#[test]
+/// Test jumping back and forth through the history with the history command
+fn history_jump() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("jump_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for text in ["first", "second", "third"].iter() {
+        run_cmd(&format!("new node cat {}", text), &mut state).unwrap();
+    }
+    assert!(run_cmd("history jump 5", &mut state).is_err());
+    assert_eq!(state.history.position, 4);
+
+    assert_eq!(run_cmd("history jump 1", &mut state).unwrap(), 1);
+    assert_eq!(state.active.tree.nodes().len(), 0);
+    assert!(state.active.name_table.contains_key("cat"));
+    assert_eq!(run_cmd("history jump 3", &mut state).unwrap(), 3);
+    assert_eq!(state.active.tree.nodes().len(), 2);
+    assert_eq!(run_cmd("history jump 0", &mut state).unwrap(), 0);
+    assert!(state.active.name_table.is_empty());
+    assert_eq!(run_cmd("history jump 4", &mut state).unwrap(), 4);
+    assert_eq!(state.active.tree.nodes().len(), 3);
+}
+
+#[test]
+/// Test undoing a single earlier change while keeping the changes made after it
+fn history_revert() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("revert_test"));
+    let node_text = |state: &EditorState, index: usize| -> String {
+        cmd::util::TextRenderer::default()
+            .node(&state.active, &state.active.name_table, index)
+            .map(|(_, text)| text.to_string())
+            .unwrap()
+    };
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Hello there\"", &mut state).unwrap();
+    run_cmd("new node cat \"Who knows\"", &mut state).unwrap();
+    run_cmd("edit node 0 cat \"Hi there\"", &mut state).unwrap();
+    run_cmd("new val rus_lit 40", &mut state).unwrap();
+    run_cmd("edit node 1 cat \"Well, who knows\"", &mut state).unwrap();
+
+    // the edit of node 0 is reverted, and the later changes are kept
+    assert_eq!(run_cmd("undo event 4", &mut state).unwrap(), 7);
+    assert_eq!(node_text(&state, 0), "Hello there");
+    assert_eq!(node_text(&state, 1), "Well, who knows");
+    assert!(state.active.val_table.contains_key("rus_lit"));
+    assert!(state.history.record[6]
+        .describe(&state.active)
+        .starts_with("Reverted change 4: Edited node 0"));
+
+    // the revert is a change like any other
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(node_text(&state, 0), "Hi there");
+    run_cmd("redo", &mut state).unwrap();
+    assert_eq!(node_text(&state, 0), "Hello there");
+
+    // node 1 was edited after it was added, so adding it can't be undone on its own
+    let err = run_cmd("undo event 3", &mut state).unwrap_err();
+    match err.downcast_ref::<cmd::Error>() {
+        Some(cmd::Error::RevertConflict(3, reason)) => assert!(reason.contains("change 6")),
+        other => panic!("expected a revert conflict, found {:?}", other),
+    }
+    // the nodes use the name, so adding the name can't be undone either
+    let err = run_cmd("undo event 1", &mut state).unwrap_err();
+    match err.downcast_ref::<cmd::Error>() {
+        Some(cmd::Error::RevertConflict(1, reason)) => {
+            assert!(reason.starts_with("it is needed by change 2"))
+        }
+        other => panic!("expected a revert conflict, found {:?}", other),
+    }
+    assert_eq!(state.active.tree.nodes().len(), 2);
+    assert_eq!(state.history.position, 7);
+    assert!(run_cmd("undo event 0", &mut state).is_err());
+    assert!(run_cmd("undo event 8", &mut state).is_err());
+
+    cmd::util::validate_tree(&state.active).unwrap();
+}
+
+#[test]
+/// Test moving a subtree under a different parent, refusing moves that create cycles
+fn reparent() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("reparent_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for text in ["root", "left", "right", "leaf"].iter() {
+        run_cmd(&format!("new node cat {}", text), &mut state).unwrap();
+    }
+    run_cmd("new edge 0 1 \"Left\"", &mut state).unwrap();
+    run_cmd("new edge 0 2 \"Right\"", &mut state).unwrap();
+    run_cmd("new edge 1 3 \"Down\"", &mut state).unwrap();
+
+    // edge 2 leads to node 3, not node 2
+    assert!(run_cmd("reparent 2 --from 2 --to 1", &mut state).is_err());
+    // node 3 is below node 1, so node 1 can't be moved under it
+    assert!(run_cmd("reparent 1 --from 0 --to 3", &mut state).is_err());
+    assert!(run_cmd("reparent 1 --from 0 --to 1", &mut state).is_err());
+
+    // move the leaf from the left branch to the right branch, keeping its edge index
+    assert_eq!(
+        run_cmd("reparent 3 --from 2 --to 2", &mut state).unwrap(),
+        2
+    );
+    let tree = &state.active.tree;
+    assert_eq!(tree.source_of(2).unwrap(), 2);
+    assert_eq!(tree.target_of(2).unwrap(), 3);
+    assert_eq!(tree.outgoing_from_index(1).unwrap().count(), 0);
+    assert_eq!(
+        tree.outgoing_from_index(2).unwrap().collect::<Vec<_>>(),
+        [2]
+    );
+    cmd::util::validate_tree(&state.active).unwrap();
+
+    // the move is undone in a single step
+    cmd::Undo::new().execute(&mut state).unwrap();
+    let tree = &state.active.tree;
+    assert_eq!(tree.source_of(2).unwrap(), 1);
+    assert_eq!(
+        tree.outgoing_from_index(1).unwrap().collect::<Vec<_>>(),
+        [2]
+    );
+    assert_eq!(tree.outgoing_from_index(2).unwrap().count(), 0);
+    cmd::Redo::new().execute(&mut state).unwrap();
+    assert_eq!(state.active.tree.source_of(2).unwrap(), 2);
+}
+
+#[test]
+/// Test that escaped separators are shown as a literal "::" instead of starting a name
+fn escaped_separator() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("escape_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd(r#"new node cat "Ratio 2\::1, says ::cat::""#, &mut state).unwrap();
+    run_cmd(r#"new node cat "std\::mem""#, &mut state).unwrap();
+    run_cmd(r#"new edge 0 1 "Ask ::cat:: about a\::b""#, &mut state).unwrap();
+
+    let mut name_buf = String::new();
+    let mut text_buf = String::new();
+    let data = &state.active;
+    let node = data.tree.get_node(0).unwrap().section;
+    cmd::util::parse_node(
+        &data.text[node[0]..node[1]],
+        &data.name_table,
+        &mut name_buf,
+        &mut text_buf,
+    )
+    .unwrap();
+    assert_eq!(text_buf, "Ratio 2::1, says Behemoth");
+    let edge = data.tree.get_edge(0).unwrap().section;
+    cmd::util::parse_edge(
+        &data.text[edge[0]..edge[1]],
+        &data.name_table,
+        &mut text_buf,
+    )
+    .unwrap();
+    assert_eq!(text_buf, "Ask Behemoth about a::b");
+    cmd::util::validate_tree(data).unwrap();
+
+    // escaped separators are not mistaken for keys
+    assert_eq!(
+        cmd::util::split_tokens(r"::cat::a\::b::cat::").collect::<Vec<_>>(),
+        ["", "cat", r"a\::b", "cat", ""]
+    );
+    assert_eq!(cmd::util::escape("a::b"), r"a\::b");
+    assert_eq!(cmd::util::unescape(&cmd::util::escape("a::b")), "a::b");
+    assert_eq!(
+        cmd::util::rename_text_key(r"::cat::a\::cat::", "cat", "dog").unwrap(),
+        r"::dog::a\::cat::"
+    );
+}
+
+#[test]
+/// Test that incremental validation only checks nodes and edges changed since the last validation
+fn incremental_validation() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("hash_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    run_cmd("new node cat \"Dostoevsky is immortal\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Dostoevsky's dead\"", &mut state).unwrap();
+    assert!(state.active.hash_cache.is_empty());
+    cmd::util::validate_tree_incremental(&mut state.active).unwrap();
+    assert_eq!(state.active.hash_cache.len(), 3);
+
+    // an edited node points to a new section, which is checked on the next validation
+    run_cmd("edit node 0 cat \"Who knows\"", &mut state).unwrap();
+    let node = *state.active.tree.get_node(0).unwrap();
+    assert!(!state.active.hash_cache.node_verified(0, &node));
+    cmd::util::validate_tree_incremental(&mut state.active).unwrap();
+    assert!(state.active.hash_cache.node_verified(0, &node));
+
+    // a changed hash is never skipped
+    let hash = state.active.tree.get_node(1).unwrap().section.hash;
+    state.active.tree.get_node_mut(1).unwrap().section.hash = 0;
+    assert!(cmd::util::validate_tree_incremental(&mut state.active).is_err());
+    state.active.tree.get_node_mut(1).unwrap().section.hash = hash;
+
+    // text rewritten in place is only caught once the cache is cleared
+    state
+        .active
+        .text
+        .replace_range(node.section[1] - 1..node.section[1], "z");
+    cmd::util::validate_tree_incremental(&mut state.active).unwrap();
+    assert!(cmd::util::validate_tree(&state.active).is_err());
+    state.active.hash_cache.clear();
+    assert!(cmd::util::validate_tree_incremental(&mut state.active).is_err());
+
+    // the cache is not saved with the project
+    let data: DialogueTreeData =
+        serde_json::from_str(&serde_json::to_string(&state.active).unwrap()).unwrap();
+    assert!(data.hash_cache.is_empty());
+}
+
+#[test]
+/// Test that memory stats account for each part of the project and the history
+fn memory_stats() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("memory_test"));
+    let empty = state.memory_stats();
+    assert_eq!(empty.history, state.history.memory_usage());
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for i in 0..600 {
+        run_cmd(&format!("new node cat \"line {}\"", i), &mut state).unwrap();
+    }
+    run_cmd("new edge 0 1 \"Next\"", &mut state).unwrap();
+
+    let stats = state.memory_stats();
+    assert!(stats.text >= state.active.text.len());
+    assert!(stats.nodes >= 600 * std::mem::size_of::<Dialogue>());
+    assert!(stats.nodes > empty.nodes);
+    assert!(stats.edges >= std::mem::size_of::<Choice>());
+    assert!(stats.history > 0);
+    assert_eq!(stats.cache, 0);
+    assert_eq!(
+        stats.total(),
+        stats.text
+            + stats.nodes
+            + stats.edges
+            + stats.links
+            + stats.tables
+            + stats.cache
+            + stats.history
+    );
+    assert_eq!(state.active.memory_stats().history, 0);
+
+    assert_eq!(
+        run_cmd("stats --memory", &mut state).unwrap(),
+        stats.total()
+    );
+    assert!(state.scratchpad.contains("history: "));
+    assert!(state.scratchpad.contains("total: "));
+    state.scratchpad.clear();
+    assert_eq!(run_cmd("stats", &mut state).unwrap(), 600);
+    assert!(state.scratchpad.contains("history: 602 events"));
+
+    assert_eq!(cmd::util::format_bytes(512), "512 B");
+    assert_eq!(cmd::util::format_bytes(1536), "1.5 KiB");
+    assert_eq!(cmd::util::format_bytes(3 * 1024 * 1024), "3.0 MiB");
+}
+
+#[test]
+/// Test that trees saved in each historical version of the save format are upgraded on load, and
+/// saved again in the current version
+fn migrate_fixtures() {
+    let fixtures = [
+        ("v0", Uid(15821614298881042295), 0),
+        ("v1", Uid(15809100678136881607), 1),
+        ("v2", Uid(188244946464281175948289615415094916496), 1),
+        ("v3", Uid(235793822704802159464852835857896479546), 1),
+        ("v4", Uid(158404027526517897266207146990814981726), 1),
+        ("v5", Uid(36181319970833689220035546955815014628), 1),
+        ("v6", Uid(238063346909737294365123840507092837929), 1),
+        ("v7", Uid(23293914608498829132719860673303090483), 1),
+        ("v8", Uid(23293914608498829132719860673303090483), 1),
+        ("v9", Uid(23293914608498829132719860673303090483), 1),
+        ("v10", Uid(23293914608498829132719860673303090483), 1),
+        ("v11", Uid(23293914608498829132719860673303090483), 1),
+        ("v12", Uid(23293914608498829132719860673303090483), 1),
+        ("v13", Uid(23293914608498829132719860673303090483), 1),
+        ("v14", Uid(23293914608498829132719860673303090483), 1),
+        ("v15", Uid(23293914608498829132719860673303090483), 1),
+    ];
+    for (version, uid, bookmarks) in fixtures.iter() {
+        let path = format!(
+            "{}/tests/fixtures/{}.tree",
+            env!("CARGO_MANIFEST_DIR"),
+            version
+        );
+        let bytes = std::fs::read(path).unwrap();
+        assert_eq!(migrate::read_uid(&bytes).unwrap(), *uid);
+
+        let (mut state, store) = memory_state(DialogueTreeData::default());
+        store.write("fixture.tree", &bytes).unwrap();
+        run_cmd("load fixture", &mut state).unwrap();
+        let data = &state.active;
+        assert_eq!(data.uid, *uid);
+        assert_eq!(data.name, "fixture");
+        assert_eq!(data.tree.nodes().len(), 2);
+        assert_eq!(data.tree.edges().len(), 1);
+        assert_eq!(data.val_table[&KeyString::from("rus_lit").unwrap()], 50);
+        assert_eq!(data.bookmarks.len(), *bookmarks);
+
+        run_cmd("save", &mut state).unwrap();
+        let saved = store.read("fixture.tree").unwrap();
+        assert!(saved.starts_with(migrate::VERSION_MAGIC));
+        assert_eq!(saved[4..8], migrate::FORMAT_VERSION.to_le_bytes());
+        assert_eq!(migrate::decode(&saved).unwrap().uid, *uid);
+    }
+
+    // trees from newer versions and files that are not trees are refused
+    let mut future = migrate::encode(&DialogueTreeData::default()).unwrap();
+    future[4..8].copy_from_slice(&99u32.to_le_bytes());
+    let err = migrate::decode(&future).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<cmd::Error>(),
+        Some(cmd::Error::UnsupportedVersion(99, _))
+    ));
+    let err = migrate::decode(b"not a dialogue tree").unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<cmd::Error>(),
+        Some(cmd::Error::UnrecognizedFormat)
+    ));
+}
+
+#[test]
+/// Test timed choices, a node timeout requires exactly one always available default choice
+fn timed_choices() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("timed_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    run_cmd("new node cat \"Quick, choose!\"", &mut state).unwrap();
+    run_cmd("new node cat \"Too slow\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Stay silent\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -r Greater(rus_lit,10) 0 1 \"Quote Dostoevsky\"",
+        &mut state,
+    )
+    .unwrap();
+
+    // a timeout needs a default, and a default must always be available
+    assert!(run_cmd("edit timeout 0 5000", &mut state).is_err());
+    assert!(run_cmd("edit default 1", &mut state).is_err());
+    run_cmd("edit default 0", &mut state).unwrap();
+    run_cmd("edit timeout 0 5000", &mut state).unwrap();
+    assert!(run_cmd(
+        "edit edge 0 \"Stay silent\" -r Greater(rus_lit,10)",
+        &mut state
+    )
+    .is_err());
+    assert!(run_cmd("remove edge 0", &mut state).is_err());
+    assert!(run_cmd("remove default 0", &mut state).is_err());
+    // editing the node text keeps the timeout
+    run_cmd("edit node 0 cat \"Quick, choose now!\"", &mut state).unwrap();
+    assert_eq!(state.active.tree.get_node(0).unwrap().timeout, Some(5000));
+    cmd::util::validate_tree(&state.active).unwrap();
+
+    let data = &state.active;
+    let mut runner = runner::Runner::new(data, 0).unwrap();
+    assert_eq!(
+        runner.timeout(data).unwrap(),
+        Some(std::time::Duration::from_millis(5000))
+    );
+    assert_eq!(runner.default_choice(data).unwrap(), Some(0));
+    assert!(runner.all_choices(data).unwrap()[0].default);
+    assert_eq!(runner.choose_default(data).unwrap(), 0);
+    assert_eq!(runner.timeout(data).unwrap(), None);
+    assert!(runner.choose_default(data).is_err());
+
+    // a second default from the same node is caught by validation
+    state.active.tree.get_edge_mut(1).unwrap().default = true;
+    assert!(cmd::util::validate_tree(&state.active).is_err());
+    state.active.tree.get_edge_mut(1).unwrap().default = false;
+
+    run_cmd("remove timeout 0", &mut state).unwrap();
+    run_cmd("remove default 0", &mut state).unwrap();
+    assert!(!state.active.tree.get_edge(0).unwrap().default);
+    run_cmd("undo", &mut state).unwrap();
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.tree.get_node(0).unwrap().timeout, Some(5000));
+    assert!(state.active.tree.get_edge(0).unwrap().default);
+}
+
+#[test]
+/// Test stage directions, which follow their node and reach the runtime export
+fn directions() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("directions_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new name mar Margarita", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows, who knows\"", &mut state).unwrap();
+    run_cmd("new node mar \"Dostoevsky is immortal\"", &mut state).unwrap();
+    run_cmd("new node cat \"Manuscripts don't burn\"", &mut state).unwrap();
+
+    // character keys must be in the name table
+    assert!(run_cmd("direction add 2 Enter(woland)", &mut state).is_err());
+    assert!("Dance(cat)".parse::<Direction>().is_err());
+    assert!("Enter(cat,bow)".parse::<Direction>().is_err());
+    run_cmd("direction add 2 Background(ponds)", &mut state).unwrap();
+    run_cmd("direction add 2 Enter(cat)", &mut state).unwrap();
+    assert_eq!(
+        run_cmd("direction add 2 Animate(cat,bow)", &mut state).unwrap(),
+        2
+    );
+    run_cmd("direction edit 2 1 Enter(mar)", &mut state).unwrap();
+    assert!(run_cmd("direction remove 2 3", &mut state).is_err());
+    assert_eq!(run_cmd("direction list 2", &mut state).unwrap(), 3);
+    assert_eq!(
+        state.active.directions[&2],
+        vec![
+            Direction::Background(NameString::from("ponds").unwrap()),
+            Direction::Enter(KeyString::from("mar").unwrap()),
+            Direction::Animate(
+                KeyString::from("cat").unwrap(),
+                NameString::from("bow").unwrap()
+            ),
+        ]
+    );
+    assert_eq!(
+        cmd::util::key_references(&state.active, cmd::util::KeyKind::Name, "mar"),
+        vec![
+            cmd::util::Reference::Speaker(1),
+            cmd::util::Reference::Direction(2)
+        ]
+    );
+
+    // directions follow their node as it is swapped into a removed index, and are restored with
+    // the node they belong to
+    run_cmd("remove node 1", &mut state).unwrap();
+    assert_eq!(state.active.directions[&1].len(), 3);
+    run_cmd("remove node 1", &mut state).unwrap();
+    assert!(state.active.directions.is_empty());
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.directions[&1].len(), 3);
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.directions[&2].len(), 3);
+    // a node that can't be removed keeps its directions
+    run_cmd("new edge 0 2 \"Burn it\"", &mut state).unwrap();
+    assert!(run_cmd("remove node 2", &mut state).is_err());
+    assert_eq!(state.active.directions[&2].len(), 3);
+    cmd::util::validate_tree(&state.active).unwrap();
+
+    // the runner reports directions from the packaged runtime data
+    let package = Package::new(&state.active, state.store.as_ref(), &[]).unwrap();
+    let data = package.tree().unwrap();
+    let mut runner = runner::Runner::new(&data, 0).unwrap();
+    assert!(runner.directions(&data).unwrap().is_empty());
+    runner.choose(&data, 0).unwrap();
+    assert_eq!(
+        runner.directions(&data).unwrap(),
+        &state.active.directions[&2][..]
+    );
+
+    // directions referencing a removed character are caught by validation and dropped on repair
+    state
+        .active
+        .name_table
+        .remove(&KeyString::from("mar").unwrap());
+    assert!(cmd::util::validate_tree(&state.active).is_err());
+    let (repaired, problems) = cmd::util::repair_tree(&state.active);
+    assert_eq!(problems.len(), 1);
+    assert_eq!(repaired.directions[&2].len(), 2);
+}
+
+#[test]
+/// Test interrupts, which move a playthrough to their target as soon as their requirement is met
+fn interrupts() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("interrupt_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val suspect 5", &mut state).unwrap();
+    run_cmd("new node cat \"Nice primus\"", &mut state).unwrap();
+    run_cmd("new node cat \"I'm not misbehaving\"", &mut state).unwrap();
+    run_cmd("new node cat \"You're under arrest\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -e Add(suspect,10) 0 1 \"Fix the primus\"",
+        &mut state,
+    )
+    .unwrap();
+    run_cmd("new edge 1 0 \"Sit quietly\"", &mut state).unwrap();
+
+    // interrupts need a requirement on an existing key, and an existing target
+    assert!(run_cmd("interrupt add No 2", &mut state).is_err());
+    assert!(run_cmd("interrupt add Greater(woland,10) 2", &mut state).is_err());
+    assert!(run_cmd("interrupt add Greater(suspect,10) 3", &mut state).is_err());
+    assert!(run_cmd("interrupt add Greater(suspect,10) 2 --at 1", &mut state).is_err());
+    assert_eq!(
+        run_cmd("interrupt add Greater(suspect,10) 2", &mut state).unwrap(),
+        0
+    );
+    assert_eq!(
+        run_cmd("interrupt add Cmp(cat,Kot) 1 --at 0", &mut state).unwrap(),
+        0
+    );
+    assert_eq!(run_cmd("interrupt list", &mut state).unwrap(), 2);
+    assert!(run_cmd("remove node 2", &mut state).is_err());
+    assert_eq!(
+        cmd::util::key_references(&state.active, cmd::util::KeyKind::Val, "suspect"),
+        vec![
+            cmd::util::Reference::Effect(0),
+            cmd::util::Reference::Interrupt(1)
+        ]
+    );
+    cmd::util::validate_tree(&state.active).unwrap();
+
+    // the effect raises suspect, so the runner ends up at the arrest scene instead of node 1
+    let data = &state.active;
+    let mut runner = runner::Runner::new(data, 0).unwrap();
+    assert_eq!(runner.interrupt, None);
+    runner.choose(data, 0).unwrap();
+    assert_eq!(runner.node, 2);
+    assert_eq!(runner.interrupt, Some(1));
+    assert_eq!(runner.path, vec![0]);
+    // already at the target, so the interrupt is not taken again
+    assert_eq!(runner.check_interrupts(data), None);
+    assert_eq!(runner::Runner::replay(data, 0, &[0]).unwrap().node, 2);
+
+    // interrupts are checked at the start node too
+    runner.vals.insert(KeyString::from("suspect").unwrap(), 0);
+    runner.names.insert(
+        KeyString::from("cat").unwrap(),
+        NameString::from("Kot").unwrap(),
+    );
+    assert_eq!(runner.check_interrupts(data), Some(0));
+    assert_eq!(runner.node, 1);
+
+    // interrupt targets follow their node when other nodes are removed
+    run_cmd("remove edge 1", &mut state).unwrap();
+    run_cmd("remove edge 0", &mut state).unwrap();
+    run_cmd("interrupt remove 0", &mut state).unwrap();
+    run_cmd("remove node 1", &mut state).unwrap();
+    assert_eq!(state.active.interrupts[0].target, 1);
+    run_cmd("undo", &mut state).unwrap();
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.interrupts.len(), 2);
+    assert_eq!(state.active.interrupts[1].target, 2);
+}
+
+#[test]
+/// Test call edges, which return to their source node at the end of the called conversation
+fn call_edges() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("call_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val ponds 0", &mut state).unwrap();
+    run_cmd("new node cat \"What would you like to know?\"", &mut state).unwrap();
+    run_cmd("new node cat \"Never talk to strangers\"", &mut state).unwrap();
+    run_cmd("new node cat \"Especially at the ponds\"", &mut state).unwrap();
+    run_cmd("new node cat \"Farewell\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Any advice?\"", &mut state).unwrap();
+    run_cmd("new edge -e Add(ponds,1) 1 2 \"Go on\"", &mut state).unwrap();
+    run_cmd("new edge 0 3 \"Goodbye\"", &mut state).unwrap();
+    run_cmd("new edge 3 0 \"Wait\"", &mut state).unwrap();
+
+    run_cmd("edit call 0", &mut state).unwrap();
+    assert!(state.active.tree.get_edge(0).unwrap().call);
+    cmd::util::validate_tree(&state.active).unwrap();
+
+    let data = &state.active;
+    let mut runner = runner::Runner::new(data, 0).unwrap();
+    assert!(runner.return_from_call(data).is_err());
+    runner.choose(data, 0).unwrap();
+    assert_eq!(runner.calls, vec![0]);
+    assert!(!runner.can_return(data).unwrap());
+    runner.choose(data, 1).unwrap();
+    // the last line of the called conversation is presented before returning
+    assert_eq!(runner.dialogue(data).unwrap().1, "Especially at the ponds");
+    assert!(runner.can_return(data).unwrap());
+    assert_eq!(runner.return_from_call(data).unwrap(), 0);
+    assert!(runner.calls.is_empty());
+    assert_eq!(runner.vals.get("ponds"), Some(&1));
+    // calls can be made again, and replay returns in the same places
+    let replayed = runner::Runner::replay(data, 0, &[0, 1, 0, 1, 2]).unwrap();
+    assert_eq!(replayed.node, 3);
+    assert_eq!(replayed.vals.get("ponds"), Some(&2));
+
+    // a call into a loop with no way out can never return
+    run_cmd("new node cat \"Round and round\"", &mut state).unwrap();
+    run_cmd("new edge 0 4 \"Spin\"", &mut state).unwrap();
+    run_cmd("new edge 4 4 \"Again\"", &mut state).unwrap();
+    assert!(run_cmd("edit call 4", &mut state).is_err());
+
+    // closing the way out of a called conversation is caught by validation
+    run_cmd("new edge 2 1 \"Tell me again\"", &mut state).unwrap();
+    assert!(cmd::util::validate_tree(&state.active).is_err());
+    run_cmd("undo", &mut state).unwrap();
+    cmd::util::validate_tree(&state.active).unwrap();
+    run_cmd("remove call 0", &mut state).unwrap();
+    assert!(!state.active.tree.get_edge(0).unwrap().call);
+    run_cmd("undo", &mut state).unwrap();
+    assert!(state.active.tree.get_edge(0).unwrap().call);
+}
+
+#[test]
+/// Test scoped values, which the runner resets as conversations start and end and choices are made
+fn val_scopes() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("scope_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val gold 0", &mut state).unwrap();
+    run_cmd("new val c.trust 0", &mut state).unwrap();
+    run_cmd("new val t.roll 0", &mut state).unwrap();
+    assert!(run_cmd("new val x.bad 0", &mut state).is_err());
+    assert!(run_cmd("new val c. 0", &mut state).is_err());
+    assert!(run_cmd("rename val gold g.gold", &mut state).is_err());
+    assert_eq!(ValScope::of("c.trust").unwrap(), ValScope::Conversation);
+    assert_eq!(ValScope::unscoped("c.trust"), "trust");
+
+    run_cmd("new node cat \"Shall we play chess?\"", &mut state).unwrap();
+    run_cmd("new node cat \"Check\"", &mut state).unwrap();
+    run_cmd("new node cat \"Tell me about the ball\"", &mut state).unwrap();
+    run_cmd("new node cat \"Mate\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -e Add(c.trust,2) 0 1 \"Move the bishop\"",
+        &mut state,
+    )
+    .unwrap();
+    run_cmd(
+        "new edge -e Set(t.roll,6) 1 0 \"Roll the dice\"",
+        &mut state,
+    )
+    .unwrap();
+    run_cmd(
+        "new edge -e Add(c.trust,5) 0 2 \"Ask about the ball\"",
+        &mut state,
+    )
+    .unwrap();
+    run_cmd("new edge -e Add(gold,1) 0 3 \"Give up\"", &mut state).unwrap();
+    run_cmd("edit call 2", &mut state).unwrap();
+    cmd::util::validate_tree(&state.active).unwrap();
+
+    let data = &state.active;
+    let mut runner = runner::Runner::new(data, 0).unwrap();
+    runner.choose(data, 0).unwrap();
+    runner.choose(data, 1).unwrap();
+    assert_eq!(runner.vals.get("c.trust"), Some(&2));
+    assert_eq!(runner.vals.get("t.roll"), Some(&6));
+    // temporaries only last until the next choice, and calls start a new conversation after the
+    // effect of the call is applied to the caller's conversation
+    runner.choose(data, 2).unwrap();
+    assert_eq!(runner.vals.get("t.roll"), Some(&0));
+    assert_eq!(runner.vals.get("c.trust"), Some(&0));
+    runner.vals.insert(KeyString::from("c.trust").unwrap(), 1);
+    // the caller's conversation values are restored on return
+    runner.return_from_call(data).unwrap();
+    assert_eq!(runner.vals.get("c.trust"), Some(&7));
+    // ending the conversation resets it, while global values are kept
+    runner.choose(data, 3).unwrap();
+    assert_eq!(runner.vals.get("c.trust"), Some(&0));
+    assert_eq!(runner.vals.get("gold"), Some(&1));
+    runner.start_conversation(data, 0).unwrap();
+    assert_eq!(runner.vals.get("gold"), Some(&1));
+
+    // completion matches keys with or without their scope prefix
+    let keys = [
+        KeyString::from("c.trust").unwrap(),
+        KeyString::from("t.roll").unwrap(),
+        KeyString::from("gold").unwrap(),
+    ];
+    assert_eq!(
+        cmd::util::complete_key("tr", keys.iter()),
+        vec![KeyString::from("c.trust").unwrap()]
+    );
+    assert_eq!(cmd::util::complete_key("t", keys.iter()).len(), 2);
+
+    // keys with unknown scopes are caught by validation
+    state
+        .active
+        .val_table
+        .insert(KeyString::from("x.bad").unwrap(), 0);
+    assert!(cmd::util::validate_tree(&state.active).is_err());
+}
+
+#[test]
+/// Test exporting a project to the json structure used by web players
+fn json_export() {
+    let (mut state, _store) = memory_state(DialogueTreeData::default());
+    run_cmd("new project json_test -s", &mut state).unwrap();
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    run_cmd("new val c.trust 0", &mut state).unwrap();
+    run_cmd("new node cat \"I am ::cat::, the {cat}\"", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -r Greater(rus_lit,10) -e Add(c.trust,2) 0 1 \"Ask ::cat::\"",
+        &mut state,
+    )
+    .unwrap();
+    run_cmd("direction add 1 Enter(cat)", &mut state).unwrap();
+    run_cmd("interrupt add Less(rus_lit,5) 1", &mut state).unwrap();
+
+    let project = json::export(&state.active).unwrap();
+    assert_eq!(project.schema, json::SCHEMA_VERSION);
+    assert_eq!(project.name, "json_test");
+    assert_eq!(project.names.get("cat").unwrap(), "Behemoth");
+    assert_eq!(project.vals.get("c.trust").unwrap().scope, "conversation");
+    assert_eq!(project.vals.get("rus_lit").unwrap().scope, "global");
+
+    let node = &project.nodes[0];
+    assert_eq!(node.speaker_key, "cat");
+    assert_eq!(node.speaker, "Behemoth");
+    assert_eq!(node.text, "I am {cat}, the {{cat}}");
+    assert_eq!(node.choices, vec![0]);
+    assert_eq!(
+        project.nodes[1].directions,
+        vec![json::Direction::Enter {
+            character: "cat".to_string()
+        }]
+    );
+
+    let edge = &project.edges[0];
+    assert_eq!((edge.source, edge.target), (0, 1));
+    assert_eq!(edge.text, "Ask {cat}");
+    assert_eq!(
+        edge.requirement,
+        Some(json::Requirement::Greater {
+            key: "rus_lit".to_string(),
+            value: 10
+        })
+    );
+    assert_eq!(project.interrupts[0].target, 1);
+
+    // requirements and effects are tagged by their operation
+    let value = serde_json::to_value(&project).unwrap();
+    assert_eq!(value["edges"][0]["requirement"]["op"], "greater");
+    assert_eq!(value["edges"][0]["effect"]["op"], "add");
+    assert_eq!(value["nodes"][1]["directions"][0]["kind"], "enter");
+
+    run_cmd("export json json_test.json", &mut state).unwrap();
+    let file = std::fs::read_to_string("json_test.json").unwrap();
+    let read: json::Project = serde_json::from_str(&file).unwrap();
+    assert_eq!(read, project);
+    std::fs::remove_file("json_test.json").unwrap();
+}
+
+#[test]
+/// Test ordering and hiding choices with the project's choice policy
+fn choice_policy() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("policy_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    run_cmd("new node cat \"Who are you?\"", &mut state).unwrap();
+    run_cmd("new node cat \"Behemoth\"", &mut state).unwrap();
+    run_cmd("new edge -r Greater(rus_lit,10) 0 1 \"Read\"", &mut state).unwrap();
+    run_cmd("new edge -r Greater(rus_lit,60) 0 1 \"Argue\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"leave\"", &mut state).unwrap();
+    let order =
+        |state: &EditorState| -> Vec<usize> { runner::ordered_edges(&state.active, 0).unwrap() };
+    assert_eq!(state.active.choice_policy, ChoicePolicy::default());
+    assert_eq!(order(&state), vec![0, 1, 2]);
+
+    // alphabetical order ignores case
+    run_cmd("edit choices --order alphabetical", &mut state).unwrap();
+    assert_eq!(order(&state), vec![1, 2, 0]);
+    // choices with requirements keep their authored order after those without
+    run_cmd("edit choices --order requirements-last", &mut state).unwrap();
+    assert_eq!(order(&state), vec![2, 0, 1]);
+    let cmds = shellwords::split("edit choices --order random").unwrap();
+    assert!(cmd::Parse::from_iter_safe(cmds).is_err());
+
+    // the runner offers choices in policy order, and only shows unmet choices if they are disabled
+    let runner = runner::Runner::new(&state.active, 0).unwrap();
+    let edges = |choices: Vec<runner::ChoiceState>| -> Vec<usize> {
+        choices.iter().map(|c| c.index).collect()
+    };
+    assert_eq!(
+        runner.choices(&state.active).unwrap(),
+        vec![(2, "leave".to_string()), (0, "Read".to_string())]
+    );
+    assert_eq!(
+        edges(runner.shown_choices(&state.active).unwrap()),
+        vec![2, 0, 1]
+    );
+    run_cmd("edit choices --unmet hide", &mut state).unwrap();
+    assert_eq!(
+        edges(runner.shown_choices(&state.active).unwrap()),
+        vec![2, 0]
+    );
+
+    // list and the json export follow the policy
+    run_cmd("list --raw", &mut state).unwrap();
+    let leave = state.scratchpad.find("edge 2").unwrap();
+    assert!(leave < state.scratchpad.find("edge 0").unwrap());
+    state.scratchpad.clear();
+    let project = json::export(&state.active).unwrap();
+    assert_eq!(project.nodes[0].choices, vec![2, 0, 1]);
+    assert_eq!(project.choice_order, "requirements-last");
+    assert_eq!(project.unmet_choices, "hide");
+
+    // the policy is saved with the project, and changes to it can be undone
+    let data = migrate::decode(&migrate::encode(&state.active).unwrap()).unwrap();
+    assert_eq!(data.choice_policy, state.active.choice_policy);
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.choice_policy.unmet, UnmetChoices::Disable);
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.choice_policy.order, ChoiceOrder::Alphabetical);
+    run_cmd("redo", &mut state).unwrap();
+    assert_eq!(
+        state.active.choice_policy.order,
+        ChoiceOrder::RequirementsLast
+    );
+}
+
+#[test]
+/// Test exporting text to a localization CSV file and importing a translated column back
+fn localization_csv() {
+    let mut state = EditorState::new(DialogueTreeData::default());
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"I am ::cat::, the cat\"", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Ask ::cat::\"", &mut state).unwrap();
+
+    run_cmd("export csv localization_test.csv", &mut state).unwrap();
+    let exported = std::fs::read_to_string("localization_test.csv").unwrap();
+    assert_eq!(
+        exported,
+        "id,line,speaker,text\n\
+         node:0,L00001.0,cat,\"I am ::cat::, the cat\"\n\
+         node:1,L00002.0,cat,\"Well, who knows\"\n\
+         edge:0,L00003.0,,Ask ::cat::\n"
+    );
+
+    std::fs::write(
+        "localization_test.csv",
+        "id,text,fr\n\
+         node:0,,\"Je suis ::cat::, le chat\"\n\
+         node:1,,\n\
+         edge:0,,Demander à ::cat::\n\
+         edge:9,,Partir\n\
+         node:1,,Qui sait ::woland::\n",
+    )
+    .unwrap();
+    let section_text = |state: &EditorState, section: Section| {
+        state.active.text[section[0]..section[1]].to_string()
+    };
+    assert!(run_cmd("import csv localization_test.csv -c de", &mut state).is_err());
+    assert_eq!(
+        run_cmd("import csv localization_test.csv --column fr", &mut state).unwrap(),
+        2
+    );
+    let node = *state.active.tree.get_node(0).unwrap();
+    assert_eq!(
+        section_text(&state, node.section),
+        "::cat::Je suis ::cat::, le chat"
+    );
+    let edge = state.active.tree.get_edge(0).unwrap().clone();
+    assert_eq!(section_text(&state, edge.section), "Demander à ::cat::");
+    // untranslated rows are skipped, and unmatched rows are reported
+    assert_eq!(
+        section_text(&state, state.active.tree.get_node(1).unwrap().section),
+        "::cat::Well, who knows"
+    );
+    assert!(state.scratchpad.contains("line 5: "));
+    assert!(state.scratchpad.contains("line 6: "));
+    cmd::util::validate_tree(&state.active).unwrap();
+
+    // the import is undone as a single change
+    cmd::Undo::new().execute(&mut state).unwrap();
+    assert_eq!(
+        section_text(&state, state.active.tree.get_node(0).unwrap().section),
+        "::cat::I am ::cat::, the cat"
+    );
+    std::fs::remove_file("localization_test.csv").unwrap();
+}
+
+#[test]
+/// Test that line ids stay with their node or edge as the tree is edited, and that lines changed
+/// since an export are reported as stale
+fn line_ids() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("line_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"I am ::cat::\"", &mut state).unwrap();
+    run_cmd("new node cat \"Well, who knows\"", &mut state).unwrap();
+    run_cmd("new node cat \"Manuscripts don't burn\"", &mut state).unwrap();
+    run_cmd("new edge 0 2 \"Ask ::cat::\"", &mut state).unwrap();
+    let line = |state: &EditorState, node: usize| state.active.tree.get_node(node).unwrap().line;
+    assert_eq!(line(&state, 2), LineId { id: 3, revision: 0 });
+    assert_eq!(
+        state.active.tree.get_edge(0).unwrap().line.to_string(),
+        "L00004.0"
+    );
+
+    let manifest = "line_test.json";
+    run_cmd(
+        &format!("export csv line_test.csv --manifest {}", manifest),
+        &mut state,
+    )
+    .unwrap();
+
+    // editing the text moves the line to its next revision, and undo moves it back
+    run_cmd("edit node 2 cat \"Manuscripts burn\"", &mut state).unwrap();
+    assert_eq!(line(&state, 2), LineId { id: 3, revision: 1 });
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(line(&state, 2), LineId { id: 3, revision: 0 });
+    run_cmd("redo", &mut state).unwrap();
+    assert_eq!(line(&state, 2), LineId { id: 3, revision: 1 });
+
+    // removing a node moves the last node into its index, along with its line id
+    run_cmd("remove node 1", &mut state).unwrap();
+    assert_eq!(line(&state, 1), LineId { id: 3, revision: 1 });
+    run_cmd("new node cat \"Annushka spilled the oil\"", &mut state).unwrap();
+    assert_eq!(line(&state, 2), LineId { id: 5, revision: 0 });
+
+    let changes = state.active.clone();
+    let manifest_data: cmd::util::LineManifest =
+        serde_json::from_str(&std::fs::read_to_string(manifest).unwrap()).unwrap();
+    assert_eq!(manifest_data.lines.len(), 4);
+    assert_eq!(
+        cmd::util::stale_lines(&changes, &manifest_data),
+        vec![
+            cmd::util::LineChange::Removed(LineId { id: 2, revision: 0 }),
+            cmd::util::LineChange::Stale {
+                id: cmd::util::TextId::Node(1),
+                line: LineId { id: 3, revision: 1 },
+                exported: LineId { id: 3, revision: 0 },
+            },
+            cmd::util::LineChange::New {
+                id: cmd::util::TextId::Node(2),
+                line: LineId { id: 5, revision: 0 },
+            },
+        ]
+    );
+    state.scratchpad.clear();
+    let count = run_cmd(
+        &format!("report stale-lines --since {}", manifest),
+        &mut state,
+    )
+    .unwrap();
+    assert_eq!(count, 2);
+    assert!(state
+        .scratchpad
+        .contains("stale L00003.1 node:1, exported as L00003.0"));
+    assert!(state
+        .scratchpad
+        .contains("1 stale, 1 new, 1 removed since the export of line_test"));
+
+    // changing the text back makes the line current again
+    run_cmd("edit node 1 cat \"Manuscripts don't burn\"", &mut state).unwrap();
+    assert_eq!(line(&state, 1), LineId { id: 3, revision: 2 });
+    assert_eq!(
+        run_cmd(
+            &format!("report stale-lines --since {}", manifest),
+            &mut state
+        )
+        .unwrap(),
+        1
+    );
+    std::fs::remove_file("line_test.csv").unwrap();
+    std::fs::remove_file(manifest).unwrap();
+
+    // trees saved before line ids are given ids in index order when loaded
+    let path = format!("{}/tests/fixtures/v9.tree", env!("CARGO_MANIFEST_DIR"));
+    let data = migrate::decode(&std::fs::read(path).unwrap()).unwrap();
+    assert_eq!(
+        data.tree.get_node(1).unwrap().line,
+        LineId { id: 2, revision: 0 }
+    );
+    assert_eq!(
+        data.tree.get_edge(0).unwrap().line,
+        LineId { id: 3, revision: 0 }
+    );
+    assert_eq!(data.next_line, 3);
+}
+
+#[test]
+/// Test flagging nodes and packaging censored builds that strip or replace the flagged nodes
+fn content_flags() {
+    let (mut state, store) = memory_state(DialogueTreeData::new("flags_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Shall we duel?\"", &mut state).unwrap();
+    run_cmd("new node cat \"The pistol fires\"", &mut state).unwrap();
+    run_cmd("new node cat \"Let us play chess instead\"", &mut state).unwrap();
+    run_cmd("new node cat \"The primus survives\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Duel\"", &mut state).unwrap();
+    run_cmd("new edge 1 3 \"Look around\"", &mut state).unwrap();
+    run_cmd("new edge 0 2 \"Decline\"", &mut state).unwrap();
+    run_cmd("bookmark add duel 1", &mut state).unwrap();
+
+    assert!("gore".parse::<ContentFlags>().is_err());
+    assert_eq!(
+        "violence, spoiler".parse::<ContentFlags>().unwrap(),
+        ContentFlags::VIOLENCE | ContentFlags::SPOILER
+    );
+    run_cmd("edit flags 1 violence,profanity", &mut state).unwrap();
+    run_cmd("edit flags 3 spoiler", &mut state).unwrap();
+    run_cmd("list --raw", &mut state).unwrap();
+    assert!(state
+        .scratchpad
+        .contains("node 1: Behemoth says \"The pistol fires\", flags: violence,profanity"));
+    run_cmd("remove flags 3", &mut state).unwrap();
+    assert!(state.active.tree.get_node(3).unwrap().flags.is_empty());
+
+    // stripping reroutes edges to flagged nodes to the fallback and drops their text
+    let strip = cmd::util::Censor::Strip(2);
+    let censored = cmd::util::censor(&state.active, ContentFlags::VIOLENCE, &strip).unwrap();
+    assert_eq!(censored.tree.nodes().len(), 3);
+    assert_eq!(censored.tree.edges().len(), 2);
+    assert_eq!(censored.tree.target_of(0).unwrap(), 2);
+    assert_eq!(censored.bookmarks[&KeyString::from("duel").unwrap()], 2);
+    assert!(!censored.text.contains("pistol"));
+    cmd::util::validate_tree(&censored).unwrap();
+    // unflagged content is left alone, and the fallback cannot be censored itself
+    let censored = cmd::util::censor(&state.active, ContentFlags::SPOILER, &strip).unwrap();
+    assert_eq!(censored.tree.nodes().len(), 4);
+    assert!(cmd::util::censor(
+        &state.active,
+        ContentFlags::VIOLENCE,
+        &cmd::util::Censor::Strip(1)
+    )
+    .is_err());
+
+    // replacing keeps the node and its choices
+    let replace = cmd::util::Censor::Replace("*static*".to_string());
+    let censored = cmd::util::censor(&state.active, ContentFlags::PROFANITY, &replace).unwrap();
+    assert_eq!(censored.tree.nodes().len(), 4);
+    assert_eq!(censored.tree.target_of(1).unwrap(), 3);
+    assert!(censored.text.contains("::cat::*static*"));
+    assert!(!censored.text.contains("pistol"));
+
+    // packaging needs exactly one of a fallback or replacement, and leaves the project alone
+    assert!(run_cmd("package --censor violence", &mut state).is_err());
+    assert!(run_cmd(
+        "package --censor violence --fallback 2 --replace x",
+        &mut state
+    )
+    .is_err());
+    run_cmd("package --censor violence --fallback 2", &mut state).unwrap();
+    let package = Package::read_from(&store, "flags_test.arbor").unwrap();
+    assert_eq!(package.tree().unwrap().tree.nodes().len(), 3);
+    assert_eq!(state.active.tree.nodes().len(), 4);
+}
+
+#[test]
+/// Test resolving asset paths from the project directory and reporting missing assets
+fn assets() {
+    let dir = std::env::temp_dir().join("arbor_assets_test");
+    std::fs::create_dir_all(dir.join("art")).unwrap();
+    std::fs::write(dir.join("art").join("cat.png"), "portrait").unwrap();
+    let mut state = EditorState::with_store(
+        DialogueTreeData::new("assets_test"),
+        std::sync::Arc::new(store::FileStore::new(&dir)),
+    );
+
+    run_cmd("asset add cat cat.png", &mut state).unwrap();
+    assert!(run_cmd("asset add cat other.png", &mut state).is_err());
+    assert!(run_cmd("asset remove woland", &mut state).is_err());
+    run_cmd("asset root art", &mut state).unwrap();
+    assert_eq!(
+        state.resolve_asset("cat").unwrap(),
+        dir.join("art").join("cat.png")
+    );
+    assert!(state.resolve_asset("woland").is_err());
+    assert_eq!(
+        cmd::util::resolve_asset(Some(&dir), "art", "/voice/cat.ogg"),
+        std::path::PathBuf::from("/voice/cat.ogg")
+    );
+
+    // missing assets are reported by check and when loading
+    run_cmd("asset add voice voice.ogg", &mut state).unwrap();
+    assert_eq!(run_cmd("check", &mut state).unwrap(), 1);
+    assert!(state.scratchpad.contains("warning: asset voice is missing"));
+    run_cmd("save", &mut state).unwrap();
+    state.scratchpad.clear();
+    run_cmd("load assets_test", &mut state).unwrap();
+    assert!(state.scratchpad.contains("warning: asset voice is missing"));
+    assert_eq!(state.active.asset_root, "art");
+
+    run_cmd("asset remove voice", &mut state).unwrap();
+    state.scratchpad.clear();
+    assert_eq!(run_cmd("check", &mut state).unwrap(), 0);
+    assert_eq!(state.scratchpad, "no problems found\r\n");
+    assert_eq!(run_cmd("asset list", &mut state).unwrap(), 0);
+
+    // asset changes are undone like any other change
+    cmd::Undo::new().execute(&mut state).unwrap();
+    assert!(state.active.assets.contains_key("voice"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+/// Test drawing thumbnails of project graphs and caching them next to the project files
+fn thumbnails() {
+    let dir = std::env::temp_dir().join("arbor_thumbnail_test");
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let store = std::sync::Arc::new(store::FileStore::new(&dir));
+    let mut state = EditorState::with_store(DialogueTreeData::new("thumb_test"), store.clone());
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Who are you?\"", &mut state).unwrap();
+    run_cmd("new node cat \"Behemoth\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Who?\"", &mut state).unwrap();
+    state
+        .active
+        .set_position(1, Some(Position::new(100.0, 50.0)))
+        .unwrap();
+
+    // the graph is scaled to fit, and centered vertically since it is wider than it is tall
+    let image = thumbnail::render(&state.active, 96, 96).unwrap();
+    let background = image.pixel(0, 0);
+    assert_ne!(image.pixel(4, 26), background);
+    assert_ne!(image.pixel(92, 70), background);
+    assert_ne!(image.pixel(48, 48), background);
+    assert_eq!(image.pixel(48, 20), background);
+    // the ending node is drawn in a different color
+    assert_ne!(image.pixel(4, 26), image.pixel(92, 70));
+
+    let png = thumbnail::encode_png(&image, Some("source"));
+    assert_eq!(
+        thumbnail::decode_png(&png).unwrap(),
+        (image, Some("source".to_string()))
+    );
+    assert!(thumbnail::decode_png(b"not a png").is_err());
+
+    // thumbnails are cached, and drawn again once the project file has changed
+    run_cmd("save", &mut state).unwrap();
+    let drawn = thumbnail::render(&state.active, thumbnail::SIZE, thumbnail::SIZE).unwrap();
+    assert_eq!(
+        thumbnail::cached(store.as_ref(), "thumb_test.tree").unwrap(),
+        drawn
+    );
+    assert_eq!(
+        thumbnail::cache_name("thumb_test.tree"),
+        "thumb_test.thumb.png"
+    );
+    let cache = store.read("thumb_test.thumb.png").unwrap();
+    assert!(thumbnail::decode_png(&cache).unwrap().1.is_some());
+    assert_eq!(
+        thumbnail::cached(store.as_ref(), "thumb_test.tree").unwrap(),
+        drawn
+    );
+    let stale = thumbnail::Thumbnail {
+        width: 1,
+        height: 1,
+        pixels: vec![0; 4],
+    };
+    store
+        .write(
+            "thumb_test.thumb.png",
+            &thumbnail::encode_png(&stale, Some("0.000000000")),
+        )
+        .unwrap();
+    assert_eq!(
+        thumbnail::cached(store.as_ref(), "thumb_test.tree").unwrap(),
+        drawn
+    );
+    let (_, source) = thumbnail::decode_png(&store.read("thumb_test.thumb.png").unwrap()).unwrap();
+    assert_ne!(source.unwrap(), "0.000000000");
+    assert!(thumbnail::cached(store.as_ref(), "missing.tree").is_err());
+
+    // stores without modification times draw the thumbnail without caching it
+    let (mut memory, memory_store) = memory_state(DialogueTreeData::new("memory_test"));
+    run_cmd("save", &mut memory).unwrap();
+    thumbnail::cached(&memory_store, "memory_test.tree").unwrap();
+    assert!(!memory_store.exists("memory_test.thumb.png").unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+/// Test finding the nodes where the dialogue ends, and the choices that lead to them
+fn endings() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("endings_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Shall we duel?\"", &mut state).unwrap();
+    run_cmd("new node cat \"The pistol fires\"", &mut state).unwrap();
+    run_cmd("new node cat \"Let us play chess instead\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Duel\"", &mut state).unwrap();
+    run_cmd("new edge 0 2 \"Decline\"", &mut state).unwrap();
+    run_cmd("new edge 2 1 \"Cheat at chess\"", &mut state).unwrap();
+
+    let tree = &state.active.tree;
+    assert!(!tree.is_leaf(0).unwrap());
+    assert!(tree.is_leaf(1).unwrap());
+    assert!(tree.is_leaf(3).is_err());
+    assert_eq!(tree.terminal_nodes().collect::<Vec<_>>(), [1]);
+
+    state.scratchpad.clear();
+    assert_eq!(run_cmd("list endings", &mut state).unwrap(), 1);
+    assert_eq!(
+        state.scratchpad,
+        "node 1: Behemoth says \"The pistol fires\"\r\n\
+         <-- edge 0 from node 0: \"Duel\"\r\n\
+         <-- edge 2 from node 2: \"Cheat at chess\"\r\n"
+    );
+    state.scratchpad.clear();
+    assert_eq!(
+        run_cmd(
+            "list -q \"select nodes where index != 1\" endings",
+            &mut state
+        )
+        .unwrap(),
+        0
+    );
+}
+
+#[test]
+/// Test the depth and out-degree histograms and convergence counts of a tree
+fn analysis() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("analysis_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for _ in 0..5 {
+        run_cmd("new node cat \"Hm\"", &mut state).unwrap();
+    }
+    run_cmd("new edge 0 1 \"Duel\"", &mut state).unwrap();
+    run_cmd("new edge 0 2 \"Decline\"", &mut state).unwrap();
+    run_cmd("new edge 0 3 \"Leave\"", &mut state).unwrap();
+    run_cmd("new edge 1 3 \"Fire\"", &mut state).unwrap();
+    run_cmd("new edge 2 3 \"Fire anyway\"", &mut state).unwrap();
+
+    let metrics = analysis::analyze(&state.active.tree).unwrap();
+    assert_eq!(metrics.depths, [1, 3]);
+    assert_eq!(metrics.unreachable, 1);
+    assert_eq!(metrics.out_degrees, [2, 2, 0, 1]);
+    assert_eq!(metrics.branching_factor, 5.0 / 3.0);
+    assert_eq!(metrics.converging, 1);
+    let empty = analysis::analyze(&DialogueTreeData::new("empty").tree).unwrap();
+    assert!(empty.depths.is_empty());
+    assert_eq!(empty.branching_factor, 0.0);
+
+    state.scratchpad.clear();
+    assert_eq!(run_cmd("analyze", &mut state).unwrap(), 1);
+    assert!(state.scratchpad.contains("branching factor: 1.67\r\n"));
+    assert!(state
+        .scratchpad
+        .contains("depth:\r\n  0 | ############# 1\r\n"));
+    state.scratchpad.clear();
+    run_cmd("analyze --json", &mut state).unwrap();
+    let parsed: analysis::Metrics = serde_json::from_str(&state.scratchpad).unwrap();
+    assert_eq!(parsed, metrics);
+}
+
+#[test]
+/// Test collapsing a tree into states by bookmark, with the effects of choices summarized on the
+/// states and transitions
+fn state_machine() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("machine_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new name kor Koroviev", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    run_cmd("new node cat \"Hm\"", &mut state).unwrap();
+    run_cmd("new node cat \"Duel?\"", &mut state).unwrap();
+    run_cmd("new node kor \"Tickets!\"", &mut state).unwrap();
+    run_cmd("new node kor \"Lost\"", &mut state).unwrap();
+    run_cmd("new edge -e Sub(rus_lit,10) 0 1 \"Challenge\"", &mut state).unwrap();
+    run_cmd("new edge -e Sub(rus_lit,5) 0 1 \"Insult\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -r Greater(rus_lit,20) -e Assign(cat,Kot) 1 2 \"Go to the theater\"",
+        &mut state,
+    )
+    .unwrap();
+    run_cmd("bookmark add duel 0", &mut state).unwrap();
+    run_cmd("bookmark add show 2", &mut state).unwrap();
+
+    let machine = analysis::state_machine(&state.active, analysis::GroupBy::Bookmark).unwrap();
+    let labels: Vec<_> = machine.states.iter().map(|s| s.label.as_str()).collect();
+    assert_eq!(labels, ["duel", "show", analysis::UNGROUPED]);
+    assert_eq!(machine.states[0].nodes, [0, 1]);
+    assert_eq!(machine.states[2].nodes, [3]);
+    assert_eq!(
+        machine.states[0].effects["rus_lit"].len(),
+        2,
+        "both effects on rus_lit are listed"
+    );
+    assert_eq!(machine.transitions.len(), 1);
+    let transition = &machine.transitions[0];
+    assert_eq!((transition.from, transition.to), (0, 1));
+    assert_eq!(transition.edges, [2]);
+    assert!(transition.requirements.contains("Greater(rus_lit,20)"));
+    assert!(transition.effects["cat"].contains("Assign(cat,Kot)"));
+    assert!(machine.to_dot().contains("s0 -> s1"));
+
+    let by_speaker = analysis::state_machine(&state.active, analysis::GroupBy::Speaker).unwrap();
+    assert_eq!(by_speaker.states.len(), 2);
+    assert_eq!(
+        analysis::group_labels(&state.active, analysis::GroupBy::Speaker).unwrap(),
+        ["cat", "cat", "kor", "kor"]
+    );
+    for by in analysis::GroupBy::ALL.iter() {
+        assert_eq!(by.to_string().parse::<analysis::GroupBy>().unwrap(), *by);
+    }
+    let cmds = shellwords::split("export machine -b color out.json").unwrap();
+    assert!(cmd::Parse::from_iter_safe(cmds).is_err());
+    assert!(run_cmd("export machine out.txt", &mut state).is_err());
+}
+
+#[test]
+/// Test the symbol table lists effects and requirements by key, and finds the range of each val
+fn symbol_table() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("vars_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    run_cmd("new val gold 0", &mut state).unwrap();
+    run_cmd("new node cat \"Hm\"", &mut state).unwrap();
+    run_cmd("new node cat \"Again?\"", &mut state).unwrap();
+    run_cmd("new node cat \"Bye\"", &mut state).unwrap();
+    run_cmd("new edge -e Sub(rus_lit,60) 0 1 \"Burn it\"", &mut state).unwrap();
+    run_cmd("new edge -e Add(rus_lit,10) 0 1 \"Write it\"", &mut state).unwrap();
+    run_cmd("new edge -e Assign(cat,Kot) 1 2 \"Rename\"", &mut state).unwrap();
+    run_cmd("new edge -e Add(gold,1) 1 1 \"Again\"", &mut state).unwrap();
+    run_cmd("new edge -r Less(gold,5) 1 2 \"Leave\"", &mut state).unwrap();
+    run_cmd("interrupt add Cmp(cat,Kot) 2", &mut state).unwrap();
+
+    let table = analysis::symbol_table(&state.active).unwrap();
+    let rus_lit = &table.vals["rus_lit"];
+    assert_eq!(rus_lit.initial.as_deref(), Some("50"));
+    assert_eq!(rus_lit.effects.len(), 2);
+    assert_eq!(rus_lit.bounds, analysis::Bounds::Val { min: 0, max: 60 });
+    assert_eq!(
+        table.vals["gold"].bounds,
+        analysis::Bounds::Val {
+            min: 0,
+            max: u32::MAX
+        },
+        "a loop that adds to a val has no upper bound"
+    );
+    assert_eq!(table.vals["gold"].requirements.len(), 1);
+    match &table.names["cat"].bounds {
+        analysis::Bounds::Name(names) => assert_eq!(names.len(), 2),
+        bounds => panic!("unexpected bounds {:?}", bounds),
+    }
+    assert_eq!(
+        table.names["cat"].requirements[0].site,
+        analysis::Site::Interrupt {
+            position: 0,
+            target: 2
+        }
+    );
+
+    assert_eq!(run_cmd("report vars", &mut state).unwrap(), 3);
+    assert!(state
+        .scratchpad
+        .contains("set by Sub(rus_lit,60) at edge 0 (0 -> 1)"));
+}
+
+#[test]
+/// Test find and replace, and that the search index follows edits to the text
+fn find_replace() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("find_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Manuscripts don't burn\"", &mut state).unwrap();
+    run_cmd("new node cat \"Burn the ::cat:: manuscript\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"What about the cat?\"", &mut state).unwrap();
+
+    assert_eq!(run_cmd("find burn", &mut state).unwrap(), 1);
+    assert!(!state.active.search_index.is_empty());
+    assert_eq!(run_cmd("find -i BURN", &mut state).unwrap(), 2);
+    assert_eq!(
+        cmd::util::search(&mut state.active, "cat", false).unwrap(),
+        [cmd::util::Reference::EdgeText(0)],
+        "speakers and substitutions are not matched"
+    );
+    assert_eq!(run_cmd("find no", &mut state).unwrap(), 0);
+    assert!(run_cmd("find \"\"", &mut state).is_err());
+
+    run_cmd("edit node 0 cat \"Manuscripts do not burn\"", &mut state).unwrap();
+    assert_eq!(
+        cmd::util::search(&mut state.active, "do not", false).unwrap(),
+        [cmd::util::Reference::NodeText(0)],
+        "edited text is indexed again"
+    );
+    run_cmd("undo", &mut state).unwrap();
+    assert!(
+        state.active.search_index.is_empty(),
+        "undo invalidates the index"
+    );
+    assert_eq!(run_cmd("find \"do not\"", &mut state).unwrap(), 0);
+    run_cmd("redo", &mut state).unwrap();
+    assert_eq!(run_cmd("find \"do not\"", &mut state).unwrap(), 1);
+    run_cmd("bookmark add start 0", &mut state).unwrap();
+    assert!(
+        !state.active.search_index.is_empty(),
+        "only text edits invalidate"
+    );
+    let text = "Manuscripts do not burn, they are immortal";
+    let at = cmd::util::find_text(text, "BURN", true).unwrap();
+    assert_eq!(
+        cmd::util::excerpt(text, at..at + 4, 14),
+        "... not burn, the..."
+    );
+    assert_eq!(cmd::util::excerpt(text, 0..11, 14), "Manuscripts do...");
+
+    assert_eq!(run_cmd("replace cat kot", &mut state).unwrap(), 1);
+    assert_eq!(run_cmd("find \"the kot\"", &mut state).unwrap(), 1);
+    assert_eq!(run_cmd("find cat", &mut state).unwrap(), 0);
+    assert!(
+        state
+            .active
+            .text
+            .contains("::cat::Burn the ::cat:: manuscript"),
+        "speakers and substitutions are not replaced"
+    );
+    assert_eq!(run_cmd("replace -i burn ash", &mut state).unwrap(), 2);
+    assert_eq!(run_cmd("find burn -i", &mut state).unwrap(), 0);
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(run_cmd("find burn -i", &mut state).unwrap(), 2);
+    assert_eq!(run_cmd("replace wolf dog", &mut state).unwrap(), 0);
+}
+
+#[test]
+/// Test creating a node and the choice leading to it in one command, and undoing both at once
+fn new_branch() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("branch_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    run_cmd("new node cat \"Shall we?\"", &mut state).unwrap();
+
+    let node = run_cmd(
+        "new branch -e Sub(rus_lit,1) 0 \"Yes\" cat \"Then to the ball\"",
+        &mut state,
+    )
+    .unwrap();
+    assert_eq!(node, 1);
+    assert_eq!(state.scratchpad, "node: 1\r\nedge: 0\r\n");
+    assert_eq!(state.active.tree.target_of(0).unwrap(), 1);
+    assert!(matches!(
+        state.active.tree.get_edge(0).unwrap().effect,
+        EffectKind::Sub(_, 1)
+    ));
+    cmd::util::validate_tree(&state.active).unwrap();
+
+    assert!(run_cmd("new branch 0 \"No\" kot \"Pity\"", &mut state).is_err());
+    assert!(run_cmd("new branch 5 \"No\" cat \"Pity\"", &mut state).is_err());
+    assert!(run_cmd(
+        "new branch -r Less(gold,1) 0 \"No\" cat \"Pity\"",
+        &mut state
+    )
+    .is_err());
+    assert_eq!(
+        state.active.tree.nodes().len(),
+        2,
+        "failed branches add nothing"
+    );
+
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 1);
+    assert_eq!(state.active.tree.edges().len(), 0);
+    run_cmd("redo", &mut state).unwrap();
+    assert_eq!(state.active.tree.edges().len(), 1);
+}
+
+#[test]
+/// Test cloning a choice onto a new target, replacing parts of it while cloning
+fn clone_edge() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("clone_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    for text in ["Who are you?", "Fine", "Never", "Again"].iter() {
+        run_cmd(&format!("new node cat \"{}\"", text), &mut state).unwrap();
+    }
+    run_cmd(
+        "new edge -r Greater(rus_lit,10) -e Sub(rus_lit,10) 0 1 \"Persuade ::cat::\"",
+        &mut state,
+    )
+    .unwrap();
+
+    assert_eq!(run_cmd("clone edge 0 -t 2", &mut state).unwrap(), 1);
+    let original = state.active.tree.get_edge(0).unwrap().clone();
+    let clone = state.active.tree.get_edge(1).unwrap().clone();
+    assert_eq!(state.active.tree.source_of(1).unwrap(), 0);
+    assert_eq!(state.active.tree.target_of(1).unwrap(), 2);
+    assert_eq!(clone.requirement, original.requirement);
+    assert_eq!(clone.effect, original.effect);
+    assert_eq!(
+        &state.active.text[clone.section[0]..clone.section[1]],
+        "Persuade ::cat::"
+    );
+
+    run_cmd(
+        "clone edge 0 --target 3 --source 1 --text \"Bribe ::cat::\" -e Sub(rus_lit,20)",
+        &mut state,
+    )
+    .unwrap();
+    let bribe = state.active.tree.get_edge(2).unwrap().clone();
+    assert_eq!(state.active.tree.source_of(2).unwrap(), 1);
+    assert_eq!(bribe.requirement, original.requirement);
+    assert_ne!(bribe.effect, original.effect);
+    assert_eq!(
+        &state.active.text[bribe.section[0]..bribe.section[1]],
+        "Bribe ::cat::"
+    );
+    cmd::util::validate_tree(&state.active).unwrap();
+
+    assert!(run_cmd("clone edge 9 -t 1", &mut state).is_err());
+    assert!(run_cmd("clone edge 0 -t 9", &mut state).is_err());
+    assert!(run_cmd("clone edge 0 -t 1 -e Add(gold,1)", &mut state).is_err());
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.tree.edges().len(), 2);
+}
+
+#[test]
+/// Test that edits which don't grow the text are written in place, and that undo restores the
+/// overwritten text
+fn edit_in_place() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("in_place_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"I am not playing pranks\"", &mut state).unwrap();
+    run_cmd("new node cat \"I am fixing the primus\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"What are you doing?\"", &mut state).unwrap();
+    let text_len = state.active.text.len();
+    let section = state.active.tree.get_node(0).unwrap().section;
+
+    for _ in 0..10 {
+        run_cmd("edit node 0 cat \"Not playing pranks\"", &mut state).unwrap();
+        run_cmd("edit edge 0 \"What now?\"", &mut state).unwrap();
+    }
+    assert_eq!(
+        state.active.text.len(),
+        text_len,
+        "shorter text does not grow the buffer"
+    );
+    let node = state.active.tree.get_node(0).unwrap().section;
+    assert_eq!(node[0], section[0]);
+    let (_, text) = cmd::util::TextRenderer::default()
+        .node(&state.active, &state.active.name_table, 0)
+        .map(|(name, text)| (name.to_string(), text.to_string()))
+        .unwrap();
+    assert_eq!(text, "Not playing pranks");
+    cmd::util::validate_tree_incremental(&mut state.active).unwrap();
+
+    run_cmd(
+        "edit node 0 cat \"I am not playing pranks, I am fixing the primus\"",
+        &mut state,
+    )
+    .unwrap();
+    assert!(
+        state.active.text.len() > text_len,
+        "longer text is appended"
+    );
+    cmd::util::validate_tree_incremental(&mut state.active).unwrap();
+
+    for _ in 0..21 {
+        run_cmd("undo", &mut state).unwrap();
+    }
+    assert_eq!(
+        &state.active.text[section[0]..section[1]],
+        "::cat::I am not playing pranks"
+    );
+    cmd::util::validate_tree(&state.active).unwrap();
+    for _ in 0..20 {
+        run_cmd("redo", &mut state).unwrap();
+    }
+    let edge = state.active.tree.get_edge(0).unwrap().section;
+    assert_eq!(&state.active.text[edge[0]..edge[1]], "What now?");
+    cmd::util::validate_tree(&state.active).unwrap();
+}
+
+#[test]
+/// Test previewing the change an effect makes without applying it
+fn effect_preview() {
+    let mut names = NameTable::default();
+    names.insert(
+        KeyString::from("cat").unwrap(),
+        NameString::from("Kot").unwrap(),
+    );
+    let mut vals = ValTable::default();
+    vals.insert(KeyString::from("rus_lit").unwrap(), 40);
+    let preview = |effect: &str| runner::preview_effect(&effect.parse().unwrap(), &names, &vals);
+
+    let added = preview("Add(rus_lit,10)").unwrap();
+    assert_eq!(added.key.as_str(), "rus_lit");
+    assert_eq!(added.before.as_deref(), Some("40"));
+    assert_eq!(added.after, "50");
+    // arithmetic saturates like it does in a playthrough
+    assert_eq!(preview("Sub(rus_lit,50)").unwrap().after, "0");
+    let missing = preview("Add(gold,5)").unwrap();
+    assert_eq!(missing.before, None);
+    assert_eq!(missing.after, "5");
+    let assigned = preview("Assign(cat,Behemoth)").unwrap();
+    assert_eq!(assigned.before.as_deref(), Some("Kot"));
+    assert_eq!(assigned.after, "Behemoth");
+    assert_eq!(preview("No"), None);
+    // the tables themselves are untouched
+    assert_eq!(vals[&KeyString::from("rus_lit").unwrap()], 40);
+}
+
+#[test]
+/// Test prompt effects, which ask the player for a name during a playthrough
+fn prompt_effect() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("prompt_test"));
+    run_cmd("new name player Stranger", &mut state).unwrap();
+    run_cmd("new node player \"What is your name?\"", &mut state).unwrap();
+    run_cmd(
+        "new node player \"Nice to meet you, ::player::\"",
+        &mut state,
+    )
+    .unwrap();
+    run_cmd("new node player \"Goodbye\"", &mut state).unwrap();
+    run_cmd("new edge -e Prompt(player) 0 1 \"Tell them\"", &mut state).unwrap();
+    run_cmd("new edge 1 2 \"Leave\"", &mut state).unwrap();
+    // prompts store into the name table, so the key must be a name
+    assert!(run_cmd("new edge -e Prompt(gold) 0 2 \"Refuse\"", &mut state).is_err());
+
+    let effect: EffectKind = "Prompt(player)".parse().unwrap();
+    assert_eq!(effect.to_string(), "Prompt(player)");
+    assert!("Prompt(cat,Leo)".parse::<EffectKind>().is_err());
+
+    let mut runner = runner::Runner::new(&state.active, 0).unwrap();
+    let events = runner.subscribe(&state.active);
+    runner.choose(&state.active, 0).unwrap();
+    assert_eq!(runner.prompt, Some(KeyString::from("player").unwrap()));
+    assert!(runner.answer(&state.active, "   ").is_err());
+    assert!(runner.answer(&state.active, &"a".repeat(40)).is_err());
+    runner.answer(&state.active, " Margarita ").unwrap();
+    assert_eq!(runner.prompt, None);
+    assert_eq!(
+        runner.dialogue(&state.active).unwrap().1,
+        "Nice to meet you, Margarita"
+    );
+    assert!(runner.answer(&state.active, "Woland").is_err());
+    // the line is sent again once the name is known
+    let lines: Vec<String> = events
+        .try_iter()
+        .filter_map(|event| match event {
+            runner::RunnerEvent::LineStarted { text, .. } => Some(text),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(lines.last().unwrap(), "Nice to meet you, Margarita");
+
+    // replays answer prompts in order, unanswered prompts keep the name
+    let answers = vec![String::from("Woland")];
+    let replayed =
+        runner::Runner::replay_with(&state.active, 0, &Default::default(), &[0, 1], &answers)
+            .unwrap();
+    assert_eq!(replayed.node, 2);
+    assert_eq!(
+        replayed.names[&KeyString::from("player").unwrap()].as_str(),
+        "Woland"
+    );
+    let unanswered = runner::Runner::replay(&state.active, 0, &[0, 1]).unwrap();
+    assert_eq!(unanswered.node, 2);
+    assert_eq!(unanswered.prompt, None);
+    assert_eq!(
+        unanswered.names[&KeyString::from("player").unwrap()].as_str(),
+        "Stranger"
+    );
+
+    let preview =
+        runner::preview_effect(&effect, &state.active.name_table, &state.active.val_table).unwrap();
+    assert_eq!(preview.after, EffectKind::PLAYER_INPUT);
+    run_cmd("rename name player hero", &mut state).unwrap();
+    assert_eq!(
+        state.active.tree.get_edge(0).unwrap().effect.to_string(),
+        "Prompt(hero)"
+    );
+}
+
+#[test]
+/// Test on-enter effects, which are applied whenever their node is shown
+fn on_enter_effects() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("on_enter_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val visits 0", &mut state).unwrap();
+    run_cmd("new node cat \"Welcome to the show\"", &mut state).unwrap();
+    run_cmd("new node cat \"Ein, zwei, drei!\"", &mut state).unwrap();
+    run_cmd("new node cat \"The show is over\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Watch the trick\"", &mut state).unwrap();
+    run_cmd("new edge -e Add(visits,10) 0 1 \"Applaud\"", &mut state).unwrap();
+    run_cmd("new edge 1 2 \"Leave\"", &mut state).unwrap();
+
+    // keys must be in the name or val table
+    assert!(run_cmd("on-enter add 1 Add(gold,1)", &mut state).is_err());
+    run_cmd("on-enter add 1 Add(visits,1)", &mut state).unwrap();
+    assert_eq!(
+        run_cmd("on-enter add 1 Assign(cat,Fagot)", &mut state).unwrap(),
+        1
+    );
+    run_cmd("on-enter edit 1 1 Assign(cat,Koroviev)", &mut state).unwrap();
+    assert!(run_cmd("on-enter remove 1 2", &mut state).is_err());
+    assert_eq!(run_cmd("on-enter list 1", &mut state).unwrap(), 2);
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(
+        state.active.on_enter[&1][1].to_string(),
+        "Assign(cat,Fagot)"
+    );
+    run_cmd("redo", &mut state).unwrap();
+    assert_eq!(
+        cmd::util::key_references(&state.active, cmd::util::KeyKind::Val, "visits"),
+        vec![
+            cmd::util::Reference::Effect(1),
+            cmd::util::Reference::OnEnter(1)
+        ]
+    );
+
+    // effects apply whichever choice leads to the node, after the effect of the choice
+    let cat = KeyString::from("cat").unwrap();
+    let visits = KeyString::from("visits").unwrap();
+    for (edge, expected) in [(0, 1), (1, 11)] {
+        let mut runner = runner::Runner::new(&state.active, 0).unwrap();
+        assert_eq!(runner.vals[&visits], 0);
+        let events = runner.subscribe(&state.active);
+        runner.choose(&state.active, edge).unwrap();
+        assert_eq!(runner.vals[&visits], expected);
+        assert_eq!(runner.names[&cat].as_str(), "Koroviev");
+        let entered = events
+            .try_iter()
+            .filter(|event| {
+                matches!(
+                    event,
+                    runner::RunnerEvent::EntryEffectApplied { node: 1, .. }
+                )
+            })
+            .count();
+        assert_eq!(entered, 2);
+    }
+    // starting at the node shows it, so its effects apply as well
+    let runner = runner::Runner::new(&state.active, 1).unwrap();
+    assert_eq!(runner.vals[&visits], 1);
+
+    // the effects follow the key when it is renamed, and keep the node from being removed
+    run_cmd("rename val visits shows", &mut state).unwrap();
+    assert_eq!(state.active.on_enter[&1][0].to_string(), "Add(shows,1)");
+    assert!(state.active.remove_node(1).is_err());
+    cmd::util::validate_tree(&state.active).unwrap();
+
+    // on-enter effects are kept in packages and exported with their node
+    let package = Package::new(&state.active, state.store.as_ref(), &[]).unwrap();
+    assert_eq!(package.tree().unwrap().on_enter, state.active.on_enter);
+    let project = json::export(&state.active).unwrap();
+    assert_eq!(project.nodes[1].on_enter.len(), 2);
+    let value = serde_json::to_value(&project).unwrap();
+    assert_eq!(value["nodes"][1]["on_enter"][0]["op"], "add");
+
+    // effects referencing a removed key are caught by validation and dropped on repair
+    state
+        .active
+        .val_table
+        .remove(&KeyString::from("shows").unwrap());
+    assert!(cmd::util::validate_tree(&state.active).is_err());
+    let (repaired, problems) = cmd::util::repair_tree(&state.active);
+    assert!(problems.contains(&cmd::util::Corruption::OnEnter(1)));
+    assert_eq!(repaired.on_enter[&1].len(), 1);
+}
+
+#[test]
+/// Test seeded random playthroughs, and batches of them simulated for outcome statistics
+fn simulation() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("simulation_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val gold 0", &mut state).unwrap();
+    run_cmd("new node cat \"Heads or tails?\"", &mut state).unwrap();
+    run_cmd("new node cat \"Heads, you win\"", &mut state).unwrap();
+    run_cmd("new node cat \"Tails, try again\"", &mut state).unwrap();
+    run_cmd("new edge -e Add(gold,5) 0 1 \"Heads\"", &mut state).unwrap();
+    run_cmd("new edge -e Add(gold,1) 0 2 \"Tails\"", &mut state).unwrap();
+    run_cmd("new edge 2 0 \"Flip again\"", &mut state).unwrap();
+
+    // runners take the first choice until seeded, and a seed always makes the same choices
+    let mut runner = runner::Runner::new(&state.active, 0).unwrap();
+    assert_eq!(runner.choose_random(&state.active).unwrap(), Some(0));
+    assert_eq!(runner.choose_random(&state.active).unwrap(), None);
+    let play = |seed| {
+        let mut runner = runner::Runner::new(&state.active, 0).unwrap();
+        runner.seed(Some(seed));
+        for _ in 0..20 {
+            runner.choose_random(&state.active).unwrap();
+        }
+        runner.path
+    };
+    assert_eq!(play(3), play(3));
+
+    let simulate = |seed, runs, max_steps| {
+        runner::simulate(&state.active, 0, &Default::default(), seed, runs, max_steps).unwrap()
+    };
+    let simulation = simulate(Some(3), 200, 6);
+    assert_eq!(simulation, simulate(Some(3), 200, 6));
+    let ended: usize = simulation.endings.values().sum();
+    assert_eq!(ended + simulation.unfinished, 200);
+    assert_eq!(simulation.endings.keys().collect::<Vec<_>>(), vec![&1]);
+    assert_eq!(simulation.choices[&0], ended);
+    // runs stopped after three tails have the least gold, runs that win after two the most
+    assert!(simulation.unfinished > 0);
+    assert_eq!(simulation.vals["gold"].min, 3);
+    assert_eq!(simulation.vals["gold"].max, 7);
+    assert_eq!(simulation.steps.count, 200);
+
+    // without a seed every run is the same playthrough
+    let deterministic = simulate(None, 5, 10);
+    assert_eq!(deterministic.endings[&1], 5);
+    assert_eq!(deterministic.choices[&0], 5);
+    assert_eq!((deterministic.steps.min, deterministic.steps.max), (1, 1));
+
+    assert_eq!(
+        run_cmd("simulate --seed 3 --runs 200 --max-steps 6", &mut state).unwrap(),
+        ended
+    );
+    assert!(run_cmd("simulate --start 9", &mut state).is_err());
+
+    // the command draws a seed when none is given, and prints it
+    state.scratchpad.clear();
+    run_cmd("simulate --runs 5", &mut state).unwrap();
+    assert!(state.scratchpad.starts_with("5 runs from seed "));
+}
+
+#[test]
+/// Test naming rules, whose breaks are reported as warnings by check
+fn naming_lints() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("naming_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new name npc_dog Sharik", &mut state).unwrap();
+    run_cmd("new val c.gold 0", &mut state).unwrap();
+    run_cmd("new val goldBag 1", &mut state).unwrap();
+    run_cmd("new node cat \"Who is there?\"", &mut state).unwrap();
+    run_cmd("new node npc_dog \"Woof\"", &mut state).unwrap();
+    run_cmd("new node cat \"Only a dog\"", &mut state).unwrap();
+    run_cmd("edit flags 1 violence,spoiler", &mut state).unwrap();
+
+    // the default rules allow everything
+    assert!(cmd::util::lint_names(&state.active).unwrap().is_empty());
+
+    run_cmd(
+        "edit naming --casing snake --speaker-prefix npc_ --max-key-len 6 --tags violence",
+        &mut state,
+    )
+    .unwrap();
+    let lints: Vec<String> = cmd::util::lint_names(&state.active)
+        .unwrap()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        lints,
+        [
+            "warning: name npc_dog is longer than the limit of 6 characters",
+            "warning: val goldBag is not snake case",
+            "warning: val goldBag is longer than the limit of 6 characters",
+            "warning: speaker cat of node 0 does not start with npc_",
+            "warning: node 1 is tagged with spoiler, which is not in the allowed tags",
+        ]
+    );
+    state.scratchpad.clear();
+    run_cmd("check", &mut state).unwrap();
+    assert!(state
+        .scratchpad
+        .contains("warning: val goldBag is not snake case\r\n"));
+
+    // limits over the hard key length are rejected, and rules are undone as one change
+    assert!(run_cmd("edit naming --max-key-len 9", &mut state).is_err());
+    let cmds = shellwords::split("edit naming --casing kebab").unwrap();
+    assert!(cmd::Parse::from_iter_safe(cmds).is_err());
+    run_cmd("edit naming --max-key-len 0 --tags any", &mut state).unwrap();
+    assert_eq!(state.active.naming.max_key_len, None);
+    assert_eq!(cmd::util::lint_names(&state.active).unwrap().len(), 2);
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.naming.max_key_len, Some(6));
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.naming, NamingRules::default());
+}
+
+#[test]
+/// Test name requirements that compare against more than a single name
+fn name_requirements() {
+    let names = {
+        let mut names = NameTable::default();
+        names.insert(
+            KeyString::from("cat").unwrap(),
+            NameString::from("Kot").unwrap(),
+        );
+        names
+    };
+    let vals = ValTable::default();
+    let met = |req: &str| runner::requirement_met(&req.parse().unwrap(), &names, &vals);
+
+    assert!(met("NotCmp(cat,Cat)"));
+    assert!(!met("NotCmp(cat,Kot)"));
+    assert!(met("OneOf(cat,Cat|Kot|Gato)"));
+    assert!(!met("OneOf(cat,Cat|Gato)"));
+    // requirements on missing keys are never met
+    assert!(!met("NotCmp(dog,Kot)"));
+    assert!(!met("OneOf(dog,Kot)"));
+
+    let req: ReqKind = "OneOf(cat,Cat|Gato)".parse().unwrap();
+    assert_eq!(req.to_string(), "OneOf(cat,Cat|Gato)");
+    assert_eq!(
+        runner::describe_requirement(&req, &names, &vals),
+        "requires cat to be one of Cat, Gato, currently Kot"
+    );
+    assert!("OneOf(cat,Cat||Gato)".parse::<ReqKind>().is_err());
+    assert!("OneOf(cat,)".parse::<ReqKind>().is_err());
+
+    let mut state = EditorState::new(DialogueTreeData::default());
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"I am ::cat::, the cat\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -r OneOf(cat,Behemoth|Begemot) 0 0 \"Pet ::cat::\"",
+        &mut state,
+    )
+    .unwrap();
+    assert!(run_cmd(
+        "new edge -r NotCmp(dog,Sharik) 0 0 \"Pet the dog\"",
+        &mut state
+    )
+    .is_err());
+    run_cmd("rename name cat kot", &mut state).unwrap();
+    let edge = state.active.tree.get_edge(0).unwrap();
+    assert_eq!(edge.requirement.to_string(), "OneOf(kot,Behemoth|Begemot)");
+}
+
+#[test]
+/// Test the events a runner sends to its subscriber over a short playthrough
+fn runner_events() {
+    use runner::RunnerEvent;
+    let (mut state, _store) = memory_state(DialogueTreeData::new("events_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"I am ::cat::\"", &mut state).unwrap();
+    run_cmd("new node cat \"Manuscripts don't burn\"", &mut state).unwrap();
+    run_cmd(
+        "new edge -e Assign(cat,Kot) 0 1 \"Who are you, ::cat::?\"",
+        &mut state,
+    )
+    .unwrap();
+    let data = &state.active;
+
+    let mut runner = runner::Runner::new(data, 0).unwrap();
+    let events = runner.subscribe(data);
+    runner.choose(data, 0).unwrap();
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        vec![
+            RunnerEvent::LineStarted {
+                node: 0,
+                speaker: "Behemoth".to_string(),
+                text: "I am Behemoth".to_string(),
+            },
+            RunnerEvent::ChoicePresented {
+                node: 0,
+                choices: vec![(0, "Who are you, Behemoth?".to_string())],
+            },
+            RunnerEvent::ChoiceTaken {
+                edge: 0,
+                text: "Who are you, Behemoth?".to_string(),
+            },
+            RunnerEvent::EffectApplied {
+                edge: 0,
+                effect: "Assign(cat,Kot)".parse().unwrap(),
+            },
+            RunnerEvent::LineStarted {
+                node: 1,
+                speaker: "Kot".to_string(),
+                text: "Manuscripts don't burn".to_string(),
+            },
+            RunnerEvent::ConversationEnded { node: 1 },
+        ]
+    );
+
+    // a dropped receiver stops the events without affecting the playthrough
+    drop(events);
+    runner.start_conversation(data, 0).unwrap();
+    runner.choose(data, 0).unwrap();
+    assert_eq!(runner.node, 1);
+}
+
+#[test]
+/// Test keeping backups and journals in a store apart from the project files, with older backups
+/// rotated and purged
+fn backup_store() {
+    let projects = store::MemoryStore::default();
+    let transient = store::MemoryStore::default();
+    let backups = store::Backups {
+        store: Some(std::sync::Arc::new(transient.clone())),
+        keep: 2,
+        journal: true,
+    };
+    let mut state = EditorState::with_backups(
+        DialogueTreeData::new("backup_test"),
+        std::sync::Arc::new(projects.clone()),
+        backups,
+    );
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Hm\"", &mut state).unwrap();
+    assert!(transient.exists("backup_test.tree.journal").unwrap());
+    run_cmd("save", &mut state).unwrap();
+    for _ in 0..4 {
+        run_cmd("rebuild", &mut state).unwrap();
+    }
+    assert_eq!(
+        projects.list().unwrap(),
+        ["backup_test.tree"],
+        "no transient files next to the project"
+    );
+    assert!(transient.exists("backup_test.tree.bkp").unwrap());
+    assert!(transient.exists("backup_test.tree.bkp.2").unwrap());
+    assert!(!transient.exists("backup_test.tree.bkp.3").unwrap());
+    run_cmd("recover", &mut state).unwrap();
+
+    assert_eq!(run_cmd("purge -k 1", &mut state).unwrap(), 1);
+    assert!(transient.exists("backup_test.tree.bkp.1").unwrap());
+    assert_eq!(run_cmd("purge --all", &mut state).unwrap(), 3);
+    assert!(!transient.exists("backup_test.tree.bkp").unwrap());
+    assert!(run_cmd("recover", &mut state).is_err());
+}
+
+#[test]
+/// Test parsing assertion files and checking them by playing their paths through a tree
+fn assertions() {
+    use runner::Assertion;
+    let (mut state, _store) = memory_state(DialogueTreeData::new("assertion_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val gold 0", &mut state).unwrap();
+    run_cmd("new node cat \"I am ::cat::\"", &mut state).unwrap();
+    run_cmd("new node cat \"Manuscripts don't burn\"", &mut state).unwrap();
+    run_cmd("new node cat \"Goodbye\"", &mut state).unwrap();
+    run_cmd("new edge -e Add(gold,50) 0 1 \"Pay\"", &mut state).unwrap();
+    run_cmd("new edge -e Assign(cat,Kot) 0 2 \"Leave\"", &mut state).unwrap();
+    run_cmd("bookmark add good_end 1", &mut state).unwrap();
+    // bookmark keys longer than the key capacity are rejected when parsing
+    let cmds = shellwords::split("bookmark add ending_good 1").unwrap();
+    assert!(cmd::Parse::from_iter_safe(cmds).is_err());
+
+    let assertions = Assertion::parse_all(
+        "# endings\n\
+         path 0 ends at good_end with gold >= 50, cat = Behemoth\n\
+         \n\
+         from 0 path 1 ends at 2 with cat != Kot\n\
+         path 1,0\n",
+    )
+    .unwrap();
+    assert_eq!(assertions.len(), 3);
+    assert_eq!(assertions[0].line, 2);
+    assert_eq!(assertions[0].path, vec![0]);
+    assert_eq!(assertions[0].end.as_deref(), Some("good_end"));
+    assert_eq!(assertions[0].conditions.len(), 2);
+    assert_eq!(
+        assertions[0].conditions[0].compare,
+        runner::Compare::GreaterEq
+    );
+    assert_eq!(assertions[1].start.as_deref(), Some("0"));
+    assert_eq!(assertions[0].failure(&state.active), None);
+    assert_eq!(
+        assertions[1].failure(&state.active).unwrap(),
+        "expected cat != Kot, found Kot"
+    );
+    assert_eq!(
+        assertions[2].failure(&state.active).unwrap(),
+        "edge 0 is not an available choice at node 2"
+    );
+
+    // names can only be tested for equality, and parse errors give the line
+    assert!("cat > Kot".parse::<runner::Condition>().is_err());
+    assert!(Assertion::parse_all("path 0\npath 0 ends 1").is_err());
+    assert!(Assertion::parse_all("path a,b").is_err());
+
+    let path = std::env::temp_dir().join("arbor_assertion_test.txt");
+    std::fs::write(&path, "path 0 ends at 1\npath 1 ends at 1\n").unwrap();
+    let failed = run_cmd(&format!("test {}", path.display()), &mut state).unwrap();
+    assert_eq!(failed, 1);
+    assert!(state.scratchpad.contains("pass line 1"));
+    assert!(state.scratchpad.contains("FAIL line 2"));
+    assert!(state.scratchpad.contains("1 passed, 1 failed"));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+/// Test starting value presets, and selecting them for list, test, and the runner
+fn presets() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("preset_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val gold 0", &mut state).unwrap();
+    run_cmd("new node cat \"A coin for the ferry\"", &mut state).unwrap();
+    run_cmd("new node cat \"Welcome aboard\"", &mut state).unwrap();
+    run_cmd("new edge -r Greater(gold,9) 0 1 \"Pay\"", &mut state).unwrap();
+
+    assert_eq!(
+        run_cmd("preset set rich gold=100,cat=Kot", &mut state).unwrap(),
+        1
+    );
+    assert_eq!(run_cmd("preset set poor gold=1", &mut state).unwrap(), 2);
+    // presets can only set keys the project already has
+    let err = run_cmd("preset set broke silver=0", &mut state).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<cmd::Error>(),
+        Some(cmd::Error::PresetKeyNotExists(_))
+    ));
+    state.scratchpad.clear();
+    assert_eq!(run_cmd("preset list", &mut state).unwrap(), 2);
+    assert_eq!(
+        state.scratchpad,
+        "poor: gold=1\r\nrich: cat=Kot,gold=100\r\n"
+    );
+
+    let rich = state.active.preset("rich").unwrap().clone();
+    let mut runner = runner::Runner::with_assumptions(&state.active, 0, &rich).unwrap();
+    assert_eq!(runner.vals[&KeyString::from("gold").unwrap()], 100);
+    assert_eq!(runner.dialogue(&state.active).unwrap().0, "Kot");
+    assert!(runner.choose(&state.active, 0).is_ok());
+    let runner = runner::Runner::new(&state.active, 0).unwrap();
+    assert!(runner.clone().choose(&state.active, 0).is_err());
+
+    // assumptions are applied on top of the preset
+    state.scratchpad.clear();
+    run_cmd("list -r --preset rich", &mut state).unwrap();
+    assert!(state.scratchpad.contains("assumed: pass"));
+    state.scratchpad.clear();
+    run_cmd("list -r --preset rich --assume gold=2", &mut state).unwrap();
+    assert!(state.scratchpad.contains("assumed: fail"));
+    assert!(run_cmd("list --preset missing", &mut state).is_err());
+
+    let path = std::env::temp_dir().join("arbor_preset_test.txt");
+    std::fs::write(&path, "path 0 ends at 1 with gold = 100\n").unwrap();
+    let test = format!("test {}", path.display());
+    assert_eq!(run_cmd(&test, &mut state).unwrap(), 1);
+    assert_eq!(
+        run_cmd(&format!("{} --preset rich", test), &mut state).unwrap(),
+        0
+    );
+    std::fs::remove_file(&path).unwrap();
+
+    // presets are saved with the project, and changes to them can be undone
+    let saved = migrate::decode(&migrate::encode(&state.active).unwrap()).unwrap();
+    assert_eq!(saved.presets, state.active.presets);
+    assert_eq!(run_cmd("preset remove rich", &mut state).unwrap(), 1);
+    assert!(run_cmd("preset remove rich", &mut state).is_err());
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.presets.len(), 2);
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.presets.len(), 1);
+    run_cmd("redo", &mut state).unwrap();
+    assert_eq!(state.active.preset("poor").unwrap().to_string(), "gold=1");
+}
+
+#[test]
+/// Test walking a tree with a visitor that prunes branches and stops early
+fn walk() {
+    use std::ops::ControlFlow;
+    use tree::Visit;
+
+    let (mut state, _store) = memory_state(DialogueTreeData::new("walk_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for _ in 0..5 {
+        run_cmd("new node cat \"Hm\"", &mut state).unwrap();
+    }
+    run_cmd("new edge 0 1 \"Duel\"", &mut state).unwrap();
+    run_cmd("new edge 0 2 \"Decline\"", &mut state).unwrap();
+    run_cmd("new edge 1 3 \"Fire\"", &mut state).unwrap();
+    let tree = &state.active.tree;
+
+    let mut visited = Vec::new();
+    let stopped = tree::walk(tree, 0, |node, depth, via| {
+        visited.push((node, depth, via));
+        ControlFlow::<(), _>::Continue(Visit::Children)
+    })
+    .unwrap();
+    assert!(stopped.is_none());
+    assert_eq!(
+        visited,
+        [
+            (0, 0, None),
+            (2, 1, Some(1)),
+            (1, 1, Some(0)),
+            (3, 2, Some(2))
+        ]
+    );
+
+    // pruned nodes are visited, but their children are not
+    let mut visited = Vec::new();
+    tree::walk(tree, 0, |node, _, _| {
+        visited.push(node);
+        match node {
+            1 => ControlFlow::<(), _>::Continue(Visit::Prune),
+            _ => ControlFlow::Continue(Visit::Children),
+        }
+    })
+    .unwrap();
+    assert_eq!(visited, [0, 2, 1]);
+
+    // walking from every node reaches nodes unreachable from the root
+    let mut count = 0;
+    let found = tree::walk_from(tree, 0..tree.nodes().len(), |node, _, via| {
+        count += 1;
+        match node {
+            4 => ControlFlow::Break(via),
+            _ => ControlFlow::Continue(Visit::Children),
+        }
+    })
+    .unwrap();
+    assert_eq!(found, Some(None));
+    assert_eq!(count, 5);
+    assert!(tree::walk(tree, 5, |_, _, _| ControlFlow::<(), _>::Break(())).is_err());
+}
+
+#[test]
+/// Test reporting choices with the same text and nodes with too many choices
+fn choice_lints() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("lint_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new node cat \"Shall we duel?\"", &mut state).unwrap();
+    run_cmd("new node cat \"The pistol fires\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Ask ::cat::\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Decline\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"ask  behemoth \"", &mut state).unwrap();
+    run_cmd("new edge 1 0 \"Decline\"", &mut state).unwrap();
+
+    let lints = cmd::util::lint_choices(&state.active, 6).unwrap();
+    assert_eq!(
+        lints,
+        [cmd::util::ChoiceLint::Duplicate {
+            node: 0,
+            first: 0,
+            second: 2
+        }]
+    );
+    let lints = cmd::util::lint_choices(&state.active, 2).unwrap();
+    assert_eq!(lints.len(), 2);
+    assert_eq!(
+        lints[1].to_string(),
+        "warning: node 0 has 3 choices, more than the maximum of 2"
+    );
+
+    state.scratchpad.clear();
+    assert_eq!(run_cmd("check", &mut state).unwrap(), 1);
+    assert_eq!(
+        state.scratchpad,
+        "warning: edges 0 and 2 from node 0 have the same text\r\n"
+    );
+    assert_eq!(run_cmd("check --max-choices 2", &mut state).unwrap(), 2);
+}
+
+#[test]
+/// Test per module log levels read from the settings file
+fn logging() {
+    use log::LevelFilter;
+
+    let settings: logging::Settings = serde_json::from_str(
+        r#"{"level": "warn", "modules": {"arbor_core": "info", "arbor_core::tree": "trace"}}"#,
+    )
+    .unwrap();
+    let filter = settings.filter(0).unwrap();
+    assert_eq!(filter.level("arbor_cli"), LevelFilter::Warn);
+    assert_eq!(filter.level("arbor_core::cmd"), LevelFilter::Info);
+    assert_eq!(filter.level("arbor_core::tree"), LevelFilter::Trace);
+    // module paths only match whole path segments
+    assert_eq!(filter.level("arbor_core_extra"), LevelFilter::Warn);
+    assert_eq!(filter.max(), LevelFilter::Trace);
+
+    let filter = settings.filter(2).unwrap();
+    assert_eq!(filter.level("arbor_cli"), LevelFilter::Debug);
+    assert_eq!(filter.level("arbor_core::cmd"), LevelFilter::Trace);
+
+    let bad = logging::Settings {
+        level: "loud".to_string(),
+        ..Default::default()
+    };
+    assert!(bad.filter(0).is_err());
+}
+
+#[test]
+/// Test removing, tagging, moving, and exporting several nodes at once, each as a single change
+/// in the history
+fn bulk_nodes() {
+    let (mut state, store) = memory_state(DialogueTreeData::new("bulk_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for text in ["Hm", "Duel?", "Fire!", "Farewell"].iter() {
+        run_cmd(&format!("new node cat \"{}\"", text), &mut state).unwrap();
+    }
+    run_cmd("new edge 0 1 \"Challenge\"", &mut state).unwrap();
+    run_cmd("new edge 1 2 \"Accept\"", &mut state).unwrap();
+    run_cmd("new edge 0 3 \"Leave\"", &mut state).unwrap();
+
+    assert_eq!(run_cmd("edit tag spoiler 1 2 2", &mut state).unwrap(), 2);
+    assert!(state
+        .active
+        .tree
+        .get_node(2)
+        .unwrap()
+        .flags
+        .intersects(ContentFlags::SPOILER));
+    assert_eq!(
+        state.active.tree.get_node(0).unwrap().flags,
+        ContentFlags::default()
+    );
+    assert_eq!(run_cmd("edit move -0.5 2 0 3", &mut state).unwrap(), 2);
+    assert_eq!(state.active.position(3), Position::new(-0.5, 2.0));
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.position(3), Position::default());
+
+    // the exported subtree keeps the edges between the exported nodes only
+    run_cmd("export subtree duel 1 2", &mut state).unwrap();
+    let duel = store::read_data(&store, "duel.tree").unwrap();
+    assert_eq!(duel.tree.nodes().len(), 2);
+    assert_eq!(duel.tree.edges().len(), 1);
+    assert_eq!(duel.tree.target_of(0).unwrap(), 1);
+    assert!(cmd::util::validate_tree(&duel).is_ok());
+
+    assert_eq!(run_cmd("remove nodes 2 1", &mut state).unwrap(), 2);
+    assert_eq!(state.active.tree.nodes().len(), 2);
+    assert_eq!(state.active.tree.edges().len(), 1);
+    assert_eq!(state.active.tree.target_of(0).unwrap(), 1);
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 4);
+    assert_eq!(state.active.tree.edges().len(), 3);
+
+    // nothing is removed if any node cannot be
+    run_cmd("bookmark add end 3", &mut state).unwrap();
+    assert!(run_cmd("remove nodes 1 3", &mut state).is_err());
+    assert_eq!(state.active.tree.nodes().len(), 4);
+    assert_eq!(state.active.tree.edges().len(), 3);
+}
+
+#[test]
+/// Test aligning, distributing, and snapping node positions, each undone as one change
+fn layout_tools() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("layout_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    let positions = [(0.3, 1.0), (2.0, 0.2), (0.9, 0.55)];
+    for (i, (x, y)) in positions.iter().enumerate() {
+        run_cmd("new node cat \"Hm\"", &mut state).unwrap();
+        state
+            .active
+            .set_position(i, Some(Position::new(*x, *y)))
+            .unwrap();
+    }
+    let pos = |state: &EditorState, i: usize| {
+        let p = state.active.position(i);
+        (p.x, p.y)
+    };
+
+    assert_eq!(run_cmd("edit align left 0 1 2", &mut state).unwrap(), 3);
+    assert_eq!(pos(&state, 1), (0.3, 0.2));
+    assert_eq!(pos(&state, 2), (0.3, 0.55));
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(pos(&state, 1), (2.0, 0.2));
+
+    run_cmd("edit align top 0 2", &mut state).unwrap();
+    assert_eq!(pos(&state, 0), (0.3, 0.55));
+    assert_eq!(pos(&state, 1), (2.0, 0.2));
+    run_cmd("undo", &mut state).unwrap();
+
+    // the middle node is moved halfway between the outer nodes
+    let close =
+        |(x, y): (f32, f32), (ex, ey): (f32, f32)| (x - ex).abs() < 1e-5 && (y - ey).abs() < 1e-5;
+    run_cmd("edit distribute 0 1 2", &mut state).unwrap();
+    assert!(close(pos(&state, 2), (1.15, 0.55)));
+    run_cmd("undo", &mut state).unwrap();
+    run_cmd("edit distribute --vertical 0 1 2", &mut state).unwrap();
+    assert!(close(pos(&state, 2), (0.9, 0.6)));
+    run_cmd("undo", &mut state).unwrap();
+
+    run_cmd("edit snap 0.5 0 1 2", &mut state).unwrap();
+    assert_eq!(pos(&state, 0), (0.5, 1.0));
+    assert_eq!(pos(&state, 2), (1.0, 0.5));
+    assert!(run_cmd("edit snap 0 0", &mut state).is_err());
+    let cmds = shellwords::split("edit align middle 0").unwrap();
+    assert!(cmd::Parse::from_iter_safe(cmds).is_err());
+}
+
+#[test]
+/// Test that node positions stay with their nodes in the layout table across removals and undo,
+/// are upgraded from trees saved with positions in the nodes, and are left out of packages
+fn layout_table() {
+    let (mut state, store) = memory_state(DialogueTreeData::new("layout_table_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for text in ["Hm", "Duel?", "Fire!"].iter() {
+        run_cmd(&format!("new node cat \"{}\"", text), &mut state).unwrap();
+    }
+    run_cmd("edit move 1 2 0", &mut state).unwrap();
+    run_cmd("edit move 3 4 2", &mut state).unwrap();
+    assert_eq!(state.active.position(1), Position::default());
+    assert!(state.active.placement(1).is_none());
+
+    // the last node is swapped into the removed index and keeps its position, since positions are
+    // keyed by line id rather than index
+    run_cmd("remove node 0", &mut state).unwrap();
+    assert_eq!(state.active.position(0), Position::new(3.0, 4.0));
+    assert_eq!(state.active.layout.len(), 1);
+    let line = state.active.tree.get_node(0).unwrap().line;
+    assert_eq!(state.active.layout[&line.id], Position::new(3.0, 4.0));
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.position(0), Position::new(1.0, 2.0));
+    assert_eq!(state.active.position(2), Position::new(3.0, 4.0));
+
+    let package = Package::new(&state.active, state.store.as_ref(), &[]).unwrap();
+    assert!(package.tree().unwrap().layout.is_empty());
+    run_cmd("save", &mut state).unwrap();
+    let saved = store::read_data(&store, "layout_table_test.tree").unwrap();
+    assert_eq!(saved.position(2), Position::new(3.0, 4.0));
+
+    let path = format!("{}/tests/fixtures/v8.tree", env!("CARGO_MANIFEST_DIR"));
+    let old = migrate::decode(&std::fs::read(path).unwrap()).unwrap();
+    assert_eq!(old.layout.len(), 1);
+    assert_eq!(old.position(1), Position::new(1.5, -2.0));
+}
+
+#[test]
+/// Test saving the text buffer in spillover files, reading single sections from them, and loading
+/// the whole project back
+fn spillover_segments() {
+    let (mut state, store) = memory_state(DialogueTreeData::new("segment_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    for text in ["Well, who knows, who knows", "Dostoevsky is immortal", "Hm"].iter() {
+        run_cmd(&format!("new node cat \"{}\"", text), &mut state).unwrap();
+    }
+    run_cmd("new edge 0 1 \"I protest!\"", &mut state).unwrap();
+    let text = state.active.text.clone();
+
+    run_cmd("save --max-text 40", &mut state).unwrap();
+    let table = store::read_segments(&store, "segment_test.tree")
+        .unwrap()
+        .unwrap();
+    assert!(table.segments.len() > 1);
+    assert!(store.exists(&table.segments[0].file).unwrap());
+    for node in state.active.tree.nodes() {
+        let at = table.locate(&node.section).unwrap();
+        assert!(at.end - at.start <= 40);
+        assert_eq!(
+            table.read_section(&store, &node.section).unwrap(),
+            text[node.section[0]..node.section[1]]
+        );
+    }
+
+    // single nodes and edges are read without loading every segment
+    let mut partial = store::read_partial(&store, "segment_test.tree").unwrap();
+    assert!(partial.data.text.is_empty());
+    assert_eq!(partial.node_text(2).unwrap(), "::cat::Hm");
+    assert_eq!(partial.loaded(), 1);
+    assert_eq!(partial.edge_text(0).unwrap(), "I protest!");
+    assert!(partial.loaded() < table.segments.len());
+    assert_eq!(partial.into_data().unwrap().text, text);
+
+    // a save that changes the text writes new segments, and keeps the ones the previous save
+    // refers to until a later save replaces it
+    run_cmd("edit node 2 cat \"Hm!\"", &mut state).unwrap();
+    run_cmd("save", &mut state).unwrap();
+    let edited = store::read_segments(&store, "segment_test.tree")
+        .unwrap()
+        .unwrap();
+    assert_ne!(edited.segments, table.segments);
+    for segment in table.segments.iter().chain(edited.segments.iter()) {
+        assert!(store.exists(&segment.file).unwrap());
+    }
+    let old = store::read_data(&store, "segment_test.tree.old").unwrap();
+    assert_eq!(old.text, text);
+    let text = state.active.text.clone();
+
+    run_cmd("new project other", &mut state).unwrap();
+    run_cmd("load segment_test", &mut state).unwrap();
+    assert_eq!(state.active.text, text);
+    assert_eq!(
+        cmd::util::read_uid(&store, "segment_test.tree").unwrap(),
+        Some(state.active.uid)
+    );
+
+    // later saves keep the limit, until it is turned off
+    run_cmd("save", &mut state).unwrap();
+    assert!(store::read_segments(&store, "segment_test.tree")
+        .unwrap()
+        .is_some());
+    run_cmd("save --max-text 0", &mut state).unwrap();
+    assert!(store::read_segments(&store, "segment_test.tree")
+        .unwrap()
+        .is_none());
+    assert!(store.exists(&edited.segments[0].file).unwrap());
+    run_cmd("save", &mut state).unwrap();
+    assert!(!store
+        .list()
+        .unwrap()
+        .iter()
+        .any(|f| f.starts_with("segment_test.tree.seg")));
+
+    // a changed segment is refused rather than loaded with the wrong text
+    run_cmd("save --max-text 40", &mut state).unwrap();
+    let table = store::read_segments(&store, "segment_test.tree")
+        .unwrap()
+        .unwrap();
+    store.write(&table.segments[0].file, b"Woland").unwrap();
+    let err = run_cmd("load segment_test", &mut state).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<cmd::Error>(),
+        Some(cmd::Error::SegmentMismatch(_))
+    ));
+}
+
+#[test]
+/// Test keeping several projects open, switching between them with separate histories, and
+/// copying nodes from one project into another
+fn open_projects() {
+    let (mut state, _store) = memory_state(DialogueTreeData::new("barks"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new val rum 1", &mut state).unwrap();
+    run_cmd("new node cat \"Hm\"", &mut state).unwrap();
+    run_cmd("new node cat \"Don't touch me\"", &mut state).unwrap();
+    run_cmd("new edge 0 1 \"Poke\"", &mut state).unwrap();
+    run_cmd("save", &mut state).unwrap();
+    run_cmd("new project chapter", &mut state).unwrap();
+
+    // opening keeps the previous project open, with its own history
+    run_cmd("project open chapter", &mut state).unwrap();
+    assert_eq!(state.active.name, "chapter");
+    assert!(run_cmd("project open barks", &mut state).is_err());
+    run_cmd("new name woland Woland", &mut state).unwrap();
+    run_cmd("new node woland \"Good evening\"", &mut state).unwrap();
+    run_cmd("project switch barks", &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 2);
+    assert!(!state.history.is_modified());
+    assert_eq!(run_cmd("project list", &mut state).unwrap(), 2);
+    assert!(state.scratchpad.contains("  chapter: 1 nodes (modified)"));
+
+    // copied nodes are one change in the other project's history
+    assert_eq!(run_cmd("project copy chapter 0 1", &mut state).unwrap(), 1);
+    assert_eq!(state.active.tree.nodes().len(), 2);
+    assert!(run_cmd("project copy missing 0", &mut state).is_err());
+    run_cmd("project switch chapter", &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 3);
+    assert_eq!(state.active.tree.edges().len(), 1);
+    assert_eq!(state.active.tree.source_of(0).unwrap(), 1);
+    assert_eq!(state.active.tree.target_of(0).unwrap(), 2);
+    assert!(state
+        .active
+        .val_table
+        .contains_key(&KeyString::from("rum").unwrap()));
+    assert!(cmd::util::validate_tree(&state.active).is_ok());
+    run_cmd("undo", &mut state).unwrap();
+    assert_eq!(state.active.tree.nodes().len(), 1);
+    assert!(state.active.val_table.is_empty());
+
+    // loading replaces only the active project, and modified projects need a forced close
+    run_cmd("project switch barks", &mut state).unwrap();
+    assert!(run_cmd("project close chapter", &mut state).is_err());
+    run_cmd("load barks", &mut state).unwrap();
+    assert_eq!(state.open.len(), 1);
+    assert_eq!(run_cmd("project close -f chapter", &mut state).unwrap(), 1);
+    assert!(run_cmd("project switch chapter", &mut state).is_err());
+}
+
+#[test]
+/// Test copying nodes to clipboard text and pasting them into another project, renaming keys and
+/// reporting the keys that were missing
+fn clipboard() {
+    let (mut source, _source_store) = memory_state(DialogueTreeData::new("barks"));
+    run_cmd("new name cat Behemoth", &mut source).unwrap();
+    run_cmd("new name kor Koroviev", &mut source).unwrap();
+    run_cmd("new val rum 1", &mut source).unwrap();
+    run_cmd("new val gold 5", &mut source).unwrap();
+    run_cmd("new node cat \"Hm\"", &mut source).unwrap();
+    run_cmd("new node kor \"Don't touch ::cat::\"", &mut source).unwrap();
+    run_cmd("new node cat \"Not copied\"", &mut source).unwrap();
+    run_cmd(
+        "new edge -r Greater(rum,0) -e Add(rum,1) 0 1 \"Poke\"",
+        &mut source,
+    )
+    .unwrap();
+    run_cmd("new edge 1 2 \"Leave\"", &mut source).unwrap();
+    run_cmd("edit tag violence 1", &mut source).unwrap();
+
+    let text = clipboard::copy(&source.active, &[1, 0])
+        .unwrap()
+        .to_json()
+        .unwrap();
+    let payload = clipboard::Payload::parse(&text).unwrap();
+    assert_eq!(payload.nodes.len(), 2);
+    assert_eq!(payload.nodes[1].speaker, "kor");
+    assert_eq!(payload.nodes[1].text, "Don't touch ::cat::");
+    assert_eq!(payload.nodes[1].tags, "violence");
+    assert_eq!(payload.edges.len(), 1);
+    assert_eq!(payload.edges[0].requirement, "Greater(rum,0)");
+    // only the keys used by the copied nodes are included
+    assert_eq!(payload.names.len(), 2);
+    assert_eq!(payload.vals.keys().collect::<Vec<_>>(), vec!["rum"]);
+
+    // the cat is called kot in the other project, which has a different name for kor
+    let (mut target, _target_store) = memory_state(DialogueTreeData::new("chapter"));
+    run_cmd("new name kot Kot", &mut target).unwrap();
+    run_cmd("new name kor Fagot", &mut target).unwrap();
+    run_cmd("new node kot \"Meow\"", &mut target).unwrap();
+    let remap = clipboard::parse_remap("cat=kot, rum=wine").unwrap();
+    let pasted = clipboard::paste(&mut target.active, &payload, &remap).unwrap();
+    assert_eq!((pasted.first, pasted.nodes), (1, 2));
+    assert!(pasted.missing_names.is_empty());
+    assert_eq!(pasted.missing_vals, vec![KeyString::from("wine").unwrap()]);
+    assert_eq!(pasted.differing, vec![KeyString::from("kor").unwrap()]);
+    assert_eq!(
+        pasted.to_string(),
+        "pasted 2 nodes starting at node 1; added missing vals: wine; kept the project's \
+         entries for: kor"
+    );
+    target.history.push(
+        EventGroup {
+            events: pasted.events,
+        }
+        .into(),
+        &mut target.active,
+    );
+
+    let renderer = &mut cmd::util::TextRenderer::default();
+    let (name, text) = renderer
+        .node(&target.active, &target.active.name_table, 2)
+        .map(|(name, text)| (name.to_string(), text.to_string()))
+        .unwrap();
+    assert_eq!((name.as_str(), text.as_str()), ("Fagot", "Don't touch Kot"));
+    let edge = target.active.tree.get_edge(0).unwrap();
+    assert_eq!(edge.requirement.to_string(), "Greater(wine,0)");
+    assert_eq!(edge.effect.to_string(), "Add(wine,1)");
+    assert_eq!(
+        target.active.tree.get_node(2).unwrap().flags.to_string(),
+        "violence"
+    );
+    assert_eq!(target.active.name_table.len(), 2);
+    cmd::util::validate_tree(&target.active).unwrap();
+
+    // the paste is undone as one change
+    run_cmd("undo", &mut target).unwrap();
+    assert_eq!(target.active.tree.nodes().len(), 1);
+    assert!(target.active.val_table.is_empty());
+
+    // ordinary text and bad key maps are refused
+    let err = clipboard::Payload::parse("Hm").unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<cmd::Error>(),
+        Some(cmd::Error::InvalidClipboard)
+    ));
+    assert!(clipboard::parse_remap("cat").is_err());
+    assert!(clipboard::parse_remap("").unwrap().is_empty());
+}
+
+#[test]
+/// Test sharing names and values through a library, which is merged on load, left out of the
+/// saved project, and checked for conflicts
+fn shared_library() {
+    let (mut state, store) = memory_state(DialogueTreeData::new("roster_test"));
+    run_cmd("new name cat Behemoth", &mut state).unwrap();
+    run_cmd("new name kor Koroviev", &mut state).unwrap();
+    run_cmd("new val rus_lit 50", &mut state).unwrap();
+    assert_eq!(run_cmd("library create roster", &mut state).unwrap(), 3);
+    assert!(run_cmd("library create roster", &mut state).is_err());
+
+    let mut state = EditorState::with_store(
+        DialogueTreeData::new("chapter"),
+        std::sync::Arc::new(store.clone()),
+    );
+    run_cmd("new name cat Kot", &mut state).unwrap();
+    run_cmd("new node cat \"Hm\"", &mut state).unwrap();
+    assert!(run_cmd("library add missing", &mut state).is_err());
+    assert_eq!(run_cmd("library add roster", &mut state).unwrap(), 2);
+    assert!(run_cmd("library add roster", &mut state).is_err());
+    assert_eq!(
+        state.active.name_table[&KeyString::from("kor").unwrap()].as_str(),
+        "Koroviev"
+    );
+    // the project keeps its own entry, and the conflict is reported
+    assert_eq!(
+        state.active.name_table[&KeyString::from("cat").unwrap()].as_str(),
+        "Kot"
+    );
+    let problems = cmd::util::library_problems(&state.active, &store);
+    assert_eq!(problems.len(), 1);
+    assert!(matches!(
+        problems[0],
+        cmd::util::LibraryProblem::NameConflict(_, _, _, _)
+    ));
+    run_cmd("undo", &mut state).unwrap();
+    assert!(state.active.libraries.is_empty());
+    assert_eq!(state.active.name_table.len(), 1);
+    run_cmd("redo", &mut state).unwrap();
+
+    // library entries are not saved with the project, and come back on load
+    run_cmd("save", &mut state).unwrap();
+    let saved = migrate::decode(&store.read("chapter.tree").unwrap()).unwrap();
+    assert_eq!(saved.libraries, vec!["roster".to_string()]);
+    assert_eq!(saved.name_table.len(), 1);
+    assert!(saved.val_table.is_empty());
+    run_cmd("load chapter", &mut state).unwrap();
+    assert_eq!(state.active.name_table.len(), 2);
+    assert_eq!(state.active.val_table.len(), 1);
+
+    // changes to the library reach every project using it
+    let mut roster = store::read_library(&store, "roster").unwrap();
+    roster.names.insert(
+        KeyString::from("hel").unwrap(),
+        NameString::from("Hella").unwrap(),
+    );
+    store::replace_library(&store, "roster", &roster).unwrap();
+    run_cmd("load chapter", &mut state).unwrap();
+    assert!(state
+        .active
+        .name_table
+        .contains_key(&KeyString::from("hel").unwrap()));
+
+    // removing the library keeps its entries in the project
+    assert_eq!(run_cmd("library remove roster", &mut state).unwrap(), 0);
+    run_cmd("save", &mut state).unwrap();
+    let saved = migrate::decode(&store.read("chapter.tree").unwrap()).unwrap();
+    assert_eq!(saved.name_table.len(), 3);
+}
@@ -0,0 +1,145 @@
+use arbor_core::tree::*;
+use arbor_core::*;
+use proptest::prelude::*;
+
+/// Number of random sequences of operations to test
+const CASES: u32 = 256;
+/// Maximum number of operations in a sequence
+const MAX_OPS: usize = 64;
+
+/// A single random modification to a tree. Indices are taken modulo the current number of nodes
+/// or edges when the operation is applied, so that most generated operations are valid
+#[derive(Debug, Clone)]
+enum Op {
+    AddNode,
+    RemoveNode(usize),
+    EditNode(usize),
+    AddEdge(usize, usize),
+    RemoveEdge(usize),
+    EditEdge(usize),
+    MoveLink(usize, usize),
+}
+
+/// Generate a random operation, weighted towards additions so that trees grow over a sequence
+fn op() -> impl Strategy<Value = Op> {
+    let index = || 0..MAX_OPS;
+    prop_oneof![
+        3 => Just(Op::AddNode),
+        1 => index().prop_map(Op::RemoveNode),
+        1 => index().prop_map(Op::EditNode),
+        4 => (index(), index()).prop_map(|(s, t)| Op::AddEdge(s, t)),
+        2 => index().prop_map(Op::RemoveEdge),
+        1 => index().prop_map(Op::EditEdge),
+        2 => (index(), 0..8usize).prop_map(|(i, placement)| Op::MoveLink(i, placement)),
+    ]
+}
+
+/// Dummy node whose contents identify the step that created it
+fn dialogue(step: usize) -> Dialogue {
+    Dialogue::new(Section::new([step, step], step as u64))
+}
+
+/// Dummy edge whose contents identify the step that created it
+fn choice(step: usize) -> Choice {
+    Choice::new(
+        Section::new([step, step], step as u64),
+        ReqKind::No,
+        EffectKind::No,
+    )
+}
+
+/// Apply an operation to the tree, returning the event describing the change if it succeeded
+fn apply(data: &mut DialogueTreeData, op: &Op, step: usize) -> Result<DialogueTreeEvent> {
+    let tree = &mut data.tree;
+    let nodes = tree.nodes().len().max(1);
+    let edges = tree.edges().len().max(1);
+    let event = match *op {
+        Op::AddNode => tree.add_node(dialogue(step))?.into(),
+        Op::RemoveNode(i) => tree.remove_node(i % nodes)?.into(),
+        Op::EditNode(i) => tree.edit_node(i % nodes, dialogue(step))?.into(),
+        Op::AddEdge(s, t) => tree.add_edge(s % nodes, t % nodes, choice(step))?.into(),
+        Op::RemoveEdge(i) => tree.remove_edge(i % edges)?.into(),
+        Op::EditEdge(i) => tree.edit_edge(i % edges, choice(step))?.into(),
+        Op::MoveLink(i, placement) => {
+            let source = tree.source_of(i % edges)?;
+            tree.edit_link_order(source, i % edges, placement)?.into()
+        }
+    };
+    Ok(event)
+}
+
+/// Check that the internal lists of the tree are consistent with each other
+fn check_invariants(tree: &Tree) {
+    let nodes = tree.nodes().len();
+    let edges = tree.edges().len();
+    assert_eq!(tree.node_links.len(), nodes);
+    assert_eq!(tree.edge_links.len(), edges);
+    assert_eq!(tree.edge_sources.len(), edges);
+    assert_eq!(tree.edge_targets.len(), edges);
+    assert!(tree.edge_sources.iter().all(|s| *s < nodes));
+    assert!(tree.edge_targets.iter().all(|t| *t < nodes));
+
+    // every edge must appear exactly once, in the outgoing list of its source node
+    let mut visited = vec![false; edges];
+    for node in 0..nodes {
+        let mut link = tree.node_links[node];
+        while link != EdgeIndex::end() {
+            assert!(link < edges, "link {} out of range", link);
+            assert!(!visited[link], "edge {} linked more than once", link);
+            assert_eq!(tree.edge_sources[link], node);
+            visited[link] = true;
+            link = tree.edge_links[link];
+        }
+    }
+    assert!(
+        visited.iter().all(|v| *v),
+        "edge missing from outgoing lists"
+    );
+}
+
+/// Apply a sequence of modifications to a tree, checking the tree invariants after each step
+/// and that undoing and redoing all events restores identical trees
+fn check_sequence(ops: &[Op]) {
+    let mut data = DialogueTreeData::default();
+    let initial = format!("{:?}", data.tree);
+    let mut events = Vec::new();
+    let mut snapshots = Vec::new();
+
+    for (step, op) in ops.iter().enumerate() {
+        let before = format!("{:?}", data.tree);
+        match apply(&mut data, op, step) {
+            Ok(event) => {
+                snapshots.push(before);
+                events.push(event);
+            }
+            // failed operations must not modify the tree
+            Err(_) => assert_eq!(before, format!("{:?}", data.tree)),
+        }
+        check_invariants(&data.tree);
+    }
+    let last = format!("{:?}", data.tree);
+
+    for (event, snapshot) in events.iter().zip(snapshots.iter()).rev() {
+        event.undo(&mut data).unwrap();
+        check_invariants(&data.tree);
+        assert_eq!(snapshot, &format!("{:?}", data.tree));
+    }
+    assert_eq!(initial, format!("{:?}", data.tree));
+
+    for event in events.iter() {
+        event.redo(&mut data).unwrap();
+        check_invariants(&data.tree);
+    }
+    assert_eq!(last, format!("{:?}", data.tree));
+}
+
+proptest! {
+    // failing sequences are shrunk, and their seeds saved to tree_props.proptest-regressions so
+    // that they are tried first on the next run
+    #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+    #[test]
+    fn tree_invariants(ops in prop::collection::vec(op(), 1..MAX_OPS)) {
+        check_sequence(&ops);
+    }
+}
@@ -0,0 +1,72 @@
+/// Text animations. Animations are advanced by the frame delta returned from
+/// [render::Renderer::end_frame], so they play at the same speed regardless of frame rate
+///
+use crate::{render, text};
+use std::time::Duration;
+
+/// Default speed of the typewriter animation, in glyphs per second
+pub const DEFAULT_GLYPHS_PER_SECOND: f32 = 40.0;
+
+/// Typewriter animation, revealing text one glyph at a time. Each glyph fades in as it is
+/// revealed, and the rest of the text can be shown at once with [Typewriter::skip]
+pub struct Typewriter {
+    text: String,
+    /// Number of glyphs in the text
+    glyphs: usize,
+    /// Number of glyphs revealed so far, the fractional part is the opacity of the next glyph
+    revealed: f32,
+    /// Glyphs revealed per second
+    pub speed: f32,
+}
+
+impl Typewriter {
+    /// Start revealing text at the default speed
+    pub fn new<S: Into<String>>(text: S) -> Self {
+        let text = text.into();
+        Self {
+            glyphs: text.chars().count(),
+            text,
+            revealed: 0.0,
+            speed: DEFAULT_GLYPHS_PER_SECOND,
+        }
+    }
+
+    /// Set the speed of the animation in glyphs per second
+    pub fn with_speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    /// Advance the animation by the time elapsed since the last frame. Returns true if there is
+    /// still text left to reveal, and another frame should be drawn
+    pub fn update(&mut self, delta: Duration) -> bool {
+        self.revealed = (self.revealed + delta.as_secs_f32() * self.speed).min(self.glyphs as f32);
+        !self.is_done()
+    }
+
+    /// Reveal all of the text immediately
+    pub fn skip(&mut self) {
+        self.revealed = self.glyphs as f32;
+    }
+
+    /// Check if all of the text has been revealed
+    pub fn is_done(&self) -> bool {
+        self.revealed >= self.glyphs as f32
+    }
+
+    /// The full text being revealed
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Add the revealed part of the text to the draw list
+    pub fn draw(
+        &self,
+        list: &mut render::DrawList,
+        z: f32,
+        style: text::Style,
+        position: (f32, f32),
+    ) {
+        list.text_revealed(z, style, position, self.text.as_str(), self.revealed);
+    }
+}
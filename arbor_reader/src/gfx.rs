@@ -23,6 +23,12 @@ pub struct Context {
     pub swap_chain: wgpu::SwapChain,
     pub depth_texture: wgpu::Texture,
     pub depth_view: wgpu::TextureView,
+    /// Multisampled render targets for the shape pipeline, if MSAA is enabled
+    pub msaa: Option<MsaaTargets>,
+    /// Number of MSAA samples per pixel used by the shape pipeline. 1 disables MSAA
+    pub sample_count: u32,
+    /// DPI scale factor of the window
+    pub scale: f64,
     pub staging_belt: wgpu::util::StagingBelt,
     pub thread_pool: futures::executor::LocalPool,
     pub thread_spawner: futures::executor::LocalSpawner,
@@ -43,22 +49,75 @@ impl Context {
             .create_swap_chain(&self.surface, &frame_descriptor);
 
         log::trace!("Create swapchain depth textures");
-        self.depth_texture = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Depth buffer"),
+        self.depth_texture = create_depth_texture(&self.device, size, 1);
+        self.depth_view = self
+            .depth_texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        self.msaa = MsaaTargets::new(&self.device, size, self.sample_count);
+    }
+
+    /// Respond to a new DPI scale factor. The swapchain is resized to the new physical size of the
+    /// window
+    pub fn rescale(&mut self, scale: f64, size: window::Size) {
+        self.scale = scale;
+        self.resize(size);
+    }
+}
+
+/// Multisampled color and depth targets. Shapes are drawn to these, then resolved to the frame
+pub struct MsaaTargets {
+    pub color_view: wgpu::TextureView,
+    pub depth_view: wgpu::TextureView,
+}
+
+impl MsaaTargets {
+    /// Create targets for the given sample count. Returns None if MSAA is disabled
+    fn new(device: &wgpu::Device, size: window::Size, sample_count: u32) -> Option<Self> {
+        if sample_count <= 1 {
+            return None;
+        }
+        let color = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("MSAA color buffer"),
             size: wgpu::Extent3d {
                 width: size.width,
                 height: size.height,
                 depth_or_array_layers: 1,
             },
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Depth32Float,
+            format: OUTPUT_FORMAT,
             usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
         });
+        let depth = create_depth_texture(device, size, sample_count);
+        Some(Self {
+            color_view: color.create_view(&wgpu::TextureViewDescriptor::default()),
+            depth_view: depth.create_view(&wgpu::TextureViewDescriptor::default()),
+        })
     }
 }
 
+/// Create a depth texture matching the swapchain size
+fn create_depth_texture(
+    device: &wgpu::Device,
+    size: window::Size,
+    sample_count: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Depth buffer"),
+        size: wgpu::Extent3d {
+            width: size.width,
+            height: size.height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
+    })
+}
+
 /// Storage for per-frame gpu information
 pub struct Frame {
     pub data: wgpu::SwapChainFrame,
@@ -196,7 +255,10 @@ impl Brush {
                     stencil: wgpu::StencilState::default(),
                     bias: wgpu::DepthBiasState::default(),
                 }),
-                multisample: wgpu::MultisampleState::default(),
+                multisample: wgpu::MultisampleState {
+                    count: context.sample_count,
+                    ..Default::default()
+                },
                 fragment: Some(wgpu::FragmentState {
                     module: &fragment_shader_module,
                     entry_point: "main",
@@ -380,12 +442,15 @@ impl Quad {
 }
 
 /// Wraps the async init function with blocking call
-pub fn init(window: &Window) -> Context {
-    futures::executor::block_on(initialize_gfx(window))
+///
+/// sample_count sets the number of MSAA samples used by the shape pipeline, 1 disables MSAA. 4
+/// samples are supported on all backends
+pub fn init(window: &Window, sample_count: u32) -> Context {
+    futures::executor::block_on(initialize_gfx(window, sample_count))
 }
 
 /// Creates the GPU handle and returns the window surface used
-async fn initialize_gfx(window: &Window) -> Context {
+async fn initialize_gfx(window: &Window, sample_count: u32) -> Context {
     log::info!("Initializing gfx...");
     log::trace!("Create instance");
     let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
@@ -434,22 +499,12 @@ async fn initialize_gfx(window: &Window) -> Context {
     let swap_chain = device.create_swap_chain(&surface, &frame_descriptor);
 
     log::trace!("Create swapchain depth textures");
-    let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
-        label: Some("Depth buffer"),
-        size: wgpu::Extent3d {
-            width: size.width,
-            height: size.height,
-            depth_or_array_layers: 1,
-        },
-        mip_level_count: 1,
-        sample_count: 1,
-        dimension: wgpu::TextureDimension::D2,
-        format: wgpu::TextureFormat::Depth32Float,
-        usage: wgpu::TextureUsage::RENDER_ATTACHMENT,
-    });
-
+    let depth_texture = create_depth_texture(&device, size, 1);
     let depth_view = depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
 
+    log::trace!("Create MSAA targets");
+    let msaa = MsaaTargets::new(&device, size, sample_count);
+
     log::trace!("Create staging belt and thread pool utilities");
     let staging_belt = wgpu::util::StagingBelt::new(1024);
     let thread_pool = futures::executor::LocalPool::new();
@@ -466,6 +521,9 @@ async fn initialize_gfx(window: &Window) -> Context {
         swap_chain,
         depth_texture,
         depth_view,
+        msaa,
+        sample_count,
+        scale: window.scale_factor(),
         staging_belt,
         thread_pool,
         thread_spawner,
@@ -507,16 +565,24 @@ pub fn begin_frame(context: &Context) -> anyhow::Result<(wgpu::CommandEncoder, F
 
 /// Start recording a renderpass on a given render target. Returns a command encoder to use for
 /// draw calls
+///
+/// If MSAA is enabled, the pass draws to the multisampled targets and resolves to the frame
 pub fn begin_renderpass<'render>(
+    context: &'render Context,
     encoder: &'render mut wgpu::CommandEncoder,
     frame: &'render Frame,
 ) -> wgpu::RenderPass<'render> {
+    let (view, resolve_target, depth_view) = match context.msaa.as_ref() {
+        Some(msaa) => (&msaa.color_view, Some(frame.view()), &msaa.depth_view),
+        None => (frame.view(), None, &frame.depth_view),
+    };
+
     // Clear frame
     let renderpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
         label: Some("Render pass"),
         color_attachments: &[wgpu::RenderPassColorAttachment {
-            view: frame.view(),
-            resolve_target: None,
+            view,
+            resolve_target,
             ops: wgpu::Operations {
                 load: wgpu::LoadOp::Clear(wgpu::Color {
                     r: 0.4,
@@ -528,7 +594,7 @@ pub fn begin_renderpass<'render>(
             },
         }],
         depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-            view: &frame.depth_view,
+            view: depth_view,
             depth_ops: Some(wgpu::Operations {
                 load: wgpu::LoadOp::Clear(1.0),
                 store: true,
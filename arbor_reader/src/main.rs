@@ -1,16 +1,32 @@
 #![allow(dead_code)]
+mod anim;
 mod gfx;
+mod profile;
+mod render;
 mod text;
+mod toast;
 mod ui;
 mod window;
 
-use std::{io::Write, time::Duration};
+use profile::Section;
+use std::io::Write;
+use winit::event::VirtualKeyCode;
 use winit::event_loop;
 
 const INITIAL_WIDTH: u32 = 1920;
 const INITIAL_HEIGHT: u32 = 1080;
+/// Number of MSAA samples used when drawing shapes, 1 disables MSAA
+const MSAA_SAMPLES: u32 = 4;
 
 fn main() {
+    // logging, with errors also shown as notifications
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let verbose = arbor_cli::logger::take_verbose(&mut args);
+    // playthroughs start from this preset of the opened project, see DialogueTreeData::presets
+    let preset = take_preset(&mut args);
+    let (level, notify, logged_errors) = toast::log_errors();
+    arbor_cli::logger::init(verbose, Some((level, notify)));
+
     // console output
     let mut stdout = std::io::stdout();
 
@@ -19,27 +35,71 @@ fn main() {
         window::init("arbor_reader", INITIAL_WIDTH, INITIAL_HEIGHT);
 
     // Renderer
-    let mut gfx_context = gfx::init(&window);
+    let mut gfx_context = gfx::init(&window, MSAA_SAMPLES);
+
+    // the glyph cache size is read from the settings file, next to the logging settings
+    let cache_settings = text::CacheSettings::load(&arbor_core::logging::Settings::path())
+        .unwrap_or_else(|e| {
+            log::error!("failed to read glyph cache settings: {}", e);
+            text::CacheSettings::default()
+        });
+    let mut renderer = render::Builder::new()
+        .cache_settings(cache_settings)
+        .build(&gfx_context);
 
     // sprites
-    let sprite_brush = gfx::Brush::new_sprite_brush(&gfx_context);
     let test_texture = gfx::Texture::from_bytes(
         &gfx_context,
-        &sprite_brush,
+        renderer
+            .sprite
+            .as_ref()
+            .expect("sprite renderer is enabled"),
         include_bytes!("../data/images/test.png"),
     )
     .expect("failed to load texture");
 
     let test_quad = gfx::Quad::from_test_vertices(&gfx_context);
+    // the test quad covers the middle half of the window
+    let test_rect = ui::Rect::from_coords(
+        INITIAL_WIDTH as f64 * 0.25,
+        INITIAL_WIDTH as f64 * 0.75,
+        INITIAL_HEIGHT as f64 * 0.25,
+        INITIAL_HEIGHT as f64 * 0.75,
+    );
 
     //let ui_rect = ui::Rect::from_tuple((400.0, 400.0, 200.0, 200.0));
     let ui_rect = ui::Rect::from_coords(400.0, 600.0, 400.0, 600.0);
     let mut ui_quad = ui_rect.to_quad(&gfx_context, window.inner_size());
 
     // text
-    let mut text_renderer = text::Renderer::new(&gfx_context);
+    if let Some(text_renderer) = renderer.text.as_mut() {
+        // optional fonts covering scripts and emoji missing from the bundled fonts
+        let fallback_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/data/fonts/fallback");
+        match text_renderer.load_fallback_dir(std::path::Path::new(fallback_dir)) {
+            Ok(count) => log::info!("loaded {} fallback fonts", count),
+            Err(e) => log::warn!("no fallback fonts loaded: {}", e),
+        }
+    }
 
-    let mut last_frame_duration = Duration::new(1, 0);
+    // frame time profiler, the overlay is toggled with F3
+    let mut profiler = profile::Profiler::new();
+    let mut last_submit = render::SubmitStats::default();
+
+    // project opened by dropping a .tree file onto the window
+    let mut project: Option<arbor_core::EditorState> = None;
+    // file dropped while a playthrough is in progress, opened once the player confirms
+    let mut pending_drop: Option<std::path::PathBuf> = None;
+    // playthrough of the opened project, and the choices leaving its current node
+    let mut runner: Option<arbor_core::runner::Runner> = None;
+    let mut choices = ui::ChoiceList::new((10.0, 200.0), 600.0, ui::LockedChoices::Grey);
+    // dialogue of the current node, revealed by a typewriter animation before its choices show
+    let mut dialogue: Option<anim::Typewriter> = None;
+    // name typed by the player when a choice asks for one, see Runner::prompt
+    let mut answer = String::new();
+    let mut frame_delta = std::time::Duration::default();
+
+    // on-screen notifications
+    let mut toasts = toast::Toasts::new();
 
     event_loop.run(move |event, _, control_flow| {
         // set control flow to only update when explicitly called
@@ -48,6 +108,7 @@ fn main() {
         if window_state.update(event) {
             return;
         }
+        profiler.begin(Section::Input);
 
         if window_state.quit {
             *control_flow = event_loop::ControlFlow::Exit;
@@ -59,10 +120,177 @@ fn main() {
         }
 
         if window_state.rescale {
-            std::unimplemented!();
+            gfx_context.rescale(window_state.scale, window_state.size);
+            if let Some(text_renderer) = renderer.text.as_mut() {
+                text_renderer.set_scale(window_state.scale);
+            }
+            ui_quad = ui_rect.to_quad(&gfx_context, window.inner_size());
+        }
+
+        // set when the playthrough moves to another node, to show its dialogue and choices
+        let mut node_changed = false;
+        // the reader never changes the project, but opening another file ends the playthrough in
+        // progress, so the player is asked to confirm first
+        let mut to_open = None;
+        for path in window_state.dropped_files.drain(..) {
+            if runner.is_some() {
+                toasts.warn(
+                    "replace the current playthrough?",
+                    &format!(
+                        "press enter to open {}, or escape to keep playing",
+                        path.display()
+                    ),
+                );
+                pending_drop = Some(path);
+            } else {
+                to_open = Some(path);
+            }
         }
 
         let input = &window_state.input;
+        // the key that confirms or cancels the drop is not passed on to the playthrough
+        let confirming = pending_drop.is_some();
+        if confirming {
+            if input.key_pressed(VirtualKeyCode::Return) {
+                to_open = pending_drop.take();
+            } else if input.key_pressed(VirtualKeyCode::Escape) {
+                pending_drop = None;
+            }
+        }
+        if let Some(path) = to_open {
+            match arbor_core::cmd::util::open_tree_file(&path) {
+                Ok(state) => {
+                    toasts.info("project opened", &path.display().to_string());
+                    let report = arbor_core::cmd::util::key_usage(&state.active);
+                    if !report.is_empty() {
+                        toasts.warn("project has unused or missing keys", &report.to_string());
+                    }
+                    let start = state.cursor.unwrap_or(0);
+                    let values = starting_values(&state.active, preset.as_deref(), &mut toasts);
+                    runner =
+                        arbor_core::runner::Runner::with_assumptions(&state.active, start, &values)
+                            .ok();
+                    choices.locked = state.active.choice_policy.unmet.into();
+                    project = Some(state);
+                    dialogue = None;
+                    answer.clear();
+                    node_changed = true;
+                }
+                Err(e) => toasts.error(
+                    &format!("failed to open {}", path.display()),
+                    &format!("{:?}", e),
+                ),
+            }
+        }
+
+        if input.key_pressed(VirtualKeyCode::F3) {
+            profiler.visible = !profiler.visible;
+        }
+        // F5 reloads the project from disk, keeping the playthrough at the same place
+        if input.key_pressed(VirtualKeyCode::F5) {
+            if let Some(state) = project.as_mut() {
+                let path = state.active.name.clone() + arbor_core::TREE_EXT;
+                let reloaded =
+                    arbor_core::store::read_data(state.store.as_ref(), &path).and_then(|data| {
+                        arbor_core::cmd::util::validate_tree(&data)?;
+                        Ok(data)
+                    });
+                match reloaded {
+                    Ok(data) => {
+                        let start = state.cursor.unwrap_or(0);
+                        if let Some(current) = runner.as_mut() {
+                            match current.hot_reload(&state.active, &data, start) {
+                                Ok(reload) => {
+                                    toasts.info("project reloaded", &format!("{:?}", reload))
+                                }
+                                Err(e) => {
+                                    toasts.warn("playthrough restarted", &format!("{:?}", e));
+                                    let values =
+                                        starting_values(&data, preset.as_deref(), &mut toasts);
+                                    runner = arbor_core::runner::Runner::with_assumptions(
+                                        &data, 0, &values,
+                                    )
+                                    .ok();
+                                }
+                            }
+                        }
+                        choices.locked = data.choice_policy.unmet.into();
+                        state.active = data;
+                        dialogue = None;
+                        node_changed = true;
+                    }
+                    Err(e) => toasts.error("failed to reload project", &format!("{:?}", e)),
+                }
+            }
+        }
+        toasts.receive(&logged_errors);
+        let toast_clicked = toasts.update(input, window_state.size, window_state.scale);
+        if let (Some(state), Some(runner)) = (project.as_ref(), runner.as_mut()) {
+            match dialogue.as_mut() {
+                // input is held back from the playthrough while a dropped file awaits confirmation
+                _ if confirming => {}
+                // clicking while dialogue is being revealed shows the rest of it at once
+                Some(typewriter) if !typewriter.is_done() => {
+                    if input.cursor_pressed() && !toast_clicked {
+                        typewriter.skip();
+                    }
+                }
+                // the player types the name they were asked for, and presses enter to submit it
+                _ if runner.prompt.is_some() => {
+                    for c in input.text.chars() {
+                        match c {
+                            '\u{8}' => {
+                                answer.pop();
+                            }
+                            '\r' | '\n' => match runner.answer(&state.active, &answer) {
+                                Ok(()) => {
+                                    answer.clear();
+                                    node_changed = true;
+                                }
+                                Err(e) => toasts.warn("invalid name", &e.to_string()),
+                            },
+                            c if !c.is_control() => answer.push(c),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {
+                    if let Some(ui::ChoiceEvent::Selected(index)) = choices.handle_input(input) {
+                        match runner.choose(&state.active, index) {
+                            Ok(()) => node_changed = true,
+                            Err(e) => toasts.error("choice unavailable", &format!("{:?}", e)),
+                        }
+                    }
+                }
+            }
+            if node_changed {
+                dialogue = match runner.dialogue(&state.active) {
+                    // narration without a narrator label is shown without a speaker
+                    Ok((name, text)) if name.is_empty() => Some(anim::Typewriter::new(text)),
+                    Ok((name, text)) => Some(anim::Typewriter::new(format!("{}: {}", name, text))),
+                    Err(e) => {
+                        toasts.error("failed to show dialogue", &format!("{:?}", e));
+                        None
+                    }
+                };
+            }
+            // choices are laid out again when the node or the window scale changes
+            if node_changed || window_state.rescale {
+                if let Err(e) = choices.update(
+                    &state.active,
+                    runner.node,
+                    &runner.names,
+                    &runner.vals,
+                    window_state.scale,
+                ) {
+                    toasts.error("failed to list choices", &format!("{:?}", e));
+                    choices.clear();
+                }
+            }
+        }
+
+        profiler.begin(Section::Layout);
+        let toast_quads = toasts.quads(&gfx_context, window_state.size);
 
         //
         // after this point, window_state is now ready to be inspected
@@ -70,44 +298,151 @@ fn main() {
         //
 
         // RENDER
-        let (mut encoder, frame) = gfx::begin_frame(&gfx_context).unwrap();
-
-        let mut renderpass = gfx::begin_renderpass(&mut encoder, &frame);
-        gfx::draw_sprite(&mut renderpass, &sprite_brush, &test_texture, &test_quad);
-        gfx::draw_sprite(&mut renderpass, &sprite_brush, &test_texture, &ui_quad);
-        gfx::end_renderpass(renderpass);
+        let mut list = render::DrawList::new();
+        list.sprite(0.0, &test_texture, &test_quad, test_rect);
+        list.sprite(0.1, &test_texture, &ui_quad, ui_rect);
 
-        text_renderer.enqueue(
-            text::styles::DIALOGUE,
-            (10.0, 10.0),
-            0.1,
-            format!("\rframe_time: {:?}", last_frame_duration).as_str(),
-        );
-        text_renderer.enqueue(
-            text::styles::DIALOGUE,
-            (10.0, 20.0),
-            0.1,
-            format!("\rmouse_cursor: {:?}", input.cursor_position).as_str(),
-        );
+        let (x, y) = profiler.draw(&mut list, (10.0, 10.0));
+        if profiler.visible {
+            list.text(
+                profile::OVERLAY_Z,
+                text::styles::DIALOGUE,
+                (x, y),
+                format!("\rmouse_cursor: {:?}", input.cursor_position),
+            );
+            if let Some(text_renderer) = renderer.text.as_ref() {
+                let cache = text_renderer.metrics();
+                list.text(
+                    profile::OVERLAY_Z,
+                    text::styles::DIALOGUE,
+                    (x, y + 10.0),
+                    format!(
+                        "\rglyph_cache: {} glyphs, {}/{} px, {} evicted, {} trims",
+                        cache.glyphs,
+                        cache.used_pixels,
+                        cache.peak_pixels,
+                        cache.evictions,
+                        cache.trims
+                    ),
+                );
+            }
+            list.text(
+                profile::OVERLAY_Z,
+                text::styles::DIALOGUE,
+                (x, y + 20.0),
+                format!(
+                    "\rdraw_list: {} sprites, {} texts, {} culled, {} texture binds",
+                    last_submit.sprites,
+                    last_submit.texts,
+                    last_submit.culled,
+                    last_submit.texture_binds
+                ),
+            );
+        }
 
         if ui_rect.clicked(input) {
-            text_renderer.enqueue(
+            list.text(
+                0.1,
                 text::styles::TITLE,
                 (ui_rect.x1 as f32, ui_rect.x2 as f32),
-                0.1,
                 "clicked!",
             );
         }
-        text_renderer.enqueue(text::styles::TITLE, (100.0, 100.0), 0.0, "Dracula");
-        text_renderer.enqueue(
+        if let Some(path) = pending_drop.as_ref() {
+            list.text(
+                0.1,
+                text::styles::DIALOGUE,
+                (10.0, 110.0),
+                format!(
+                    "\ropen {}? enter to confirm, escape to cancel",
+                    path.display()
+                ),
+            );
+        } else if let Some(state) = project.as_ref() {
+            list.text(
+                0.1,
+                text::styles::DIALOGUE,
+                (10.0, 110.0),
+                format!("\rproject: {}", state.active.name),
+            );
+        } else if window_state.file_hovered {
+            list.text(
+                0.1,
+                text::styles::DIALOGUE,
+                (10.0, 110.0),
+                "\rdrop a .tree file to open it",
+            );
+        }
+        let prompt = runner.as_ref().and_then(|runner| runner.prompt);
+        let draw_choices = |list: &mut render::DrawList| match prompt {
+            Some(key) => list.text(
+                0.5,
+                text::styles::DIALOGUE,
+                (10.0, 200.0),
+                format!("\renter {}: {}_", key, answer),
+            ),
+            None => choices.draw(list, input, window_state.scale),
+        };
+        match dialogue.as_mut() {
+            Some(typewriter) => {
+                if typewriter.update(frame_delta) {
+                    // keep drawing frames until all of the dialogue is revealed
+                    window.request_redraw();
+                } else {
+                    draw_choices(&mut list);
+                }
+                typewriter.draw(&mut list, 0.5, text::styles::DIALOGUE, (10.0, 130.0));
+            }
+            None => draw_choices(&mut list),
+        }
+        toasts.draw(
+            &mut list,
+            &toast_quads,
+            &test_texture,
+            window_state.size,
+            window_state.scale,
+        );
+        list.text(0.0, text::styles::TITLE, (100.0, 100.0), "Dracula");
+        list.text(
+            0.0,
             text::styles::DIALOGUE,
             (400.0, 400.0),
-            0.0,
             "Enter of your own free will!",
         );
-        text_renderer.draw(&mut gfx_context, &mut encoder, window_state.size, &frame);
 
-        last_frame_duration = gfx::end_frame(&mut gfx_context, encoder, frame);
+        let mut draw = renderer
+            .begin_frame(&mut gfx_context, window_state.size)
+            .unwrap();
+        last_submit = renderer.submit(&mut draw, list, &mut profiler);
+
+        profiler.begin(Section::GpuSubmit);
+        frame_delta = renderer.end_frame(draw).delta;
+        profiler.end_frame();
         stdout.flush().unwrap();
     });
 }
+
+/// Remove --preset and the preset name following it from the command line arguments
+fn take_preset(args: &mut Vec<String>) -> Option<String> {
+    let position = args.iter().position(|arg| arg == "--preset")?;
+    let preset = args.get(position + 1).cloned();
+    args.drain(position..(position + 2).min(args.len()));
+    preset
+}
+
+/// Starting values of a playthrough of the project. Projects without the preset start from their
+/// own values, with a warning
+fn starting_values(
+    data: &arbor_core::DialogueTreeData,
+    preset: Option<&str>,
+    toasts: &mut toast::Toasts,
+) -> arbor_core::runner::Assumptions {
+    match preset.map(|preset| data.preset(preset)) {
+        Some(Ok(values)) => values.clone(),
+        Some(Err(e)) => {
+            toasts.warn("starting from the project's values", &e.to_string());
+            Default::default()
+        }
+        None => Default::default(),
+    }
+}
@@ -0,0 +1,165 @@
+/// Frame time profiler. Times each subsystem of a frame, and keeps a rolling window of samples to
+/// report averages and worst-case times in an on-screen overlay
+///
+use crate::{render, text};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of frames kept for rolling averages and worst-case times
+const ROLLING_FRAMES: usize = 120;
+/// Vertical spacing between lines of the overlay
+const LINE_HEIGHT: f32 = 10.0;
+/// Depth of the overlay in the draw list
+pub const OVERLAY_Z: f32 = 0.8;
+
+/// Subsystems of a frame that are timed separately
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Input = 0,
+    Layout,
+    TextEnqueue,
+    ShapeDraw,
+    GlyphProcessing,
+    GpuSubmit,
+}
+
+/// Table of all sections in display order. Should match the [Section] enum ordering
+const SECTIONS: &[Section] = &[
+    Section::Input,
+    Section::Layout,
+    Section::TextEnqueue,
+    Section::ShapeDraw,
+    Section::GlyphProcessing,
+    Section::GpuSubmit,
+];
+
+impl Section {
+    fn name(self) -> &'static str {
+        match self {
+            Section::Input => "input",
+            Section::Layout => "layout",
+            Section::TextEnqueue => "text enqueue",
+            Section::ShapeDraw => "shape draws",
+            Section::GlyphProcessing => "glyph processing",
+            Section::GpuSubmit => "gpu submit",
+        }
+    }
+}
+
+/// Rolling average and worst-case time of a section
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stats {
+    pub average: Duration,
+    pub worst: Duration,
+}
+
+/// Collects per-section timings for each frame
+pub struct Profiler {
+    /// Show the overlay
+    pub visible: bool,
+    /// Samples for each section in the last ROLLING_FRAMES frames, oldest first
+    samples: Vec<VecDeque<Duration>>,
+    /// Samples for whole frames in the last ROLLING_FRAMES frames, oldest first. Only timed
+    /// sections count towards the frame, so time spent waiting for events is left out
+    frames: VecDeque<Duration>,
+    /// Time accumulated by each section in the current frame
+    current: Vec<Duration>,
+    /// Section being timed, and when it began
+    running: Option<(Section, Instant)>,
+}
+
+impl Profiler {
+    /// Create a profiler with no samples, the overlay starts hidden
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            samples: vec![VecDeque::with_capacity(ROLLING_FRAMES); SECTIONS.len()],
+            frames: VecDeque::with_capacity(ROLLING_FRAMES),
+            current: vec![Duration::default(); SECTIONS.len()],
+            running: None,
+        }
+    }
+
+    /// Start timing a section, ending any section that was already running. A section may be
+    /// timed more than once per frame, the times are added together
+    pub fn begin(&mut self, section: Section) {
+        self.end();
+        self.running = Some((section, Instant::now()));
+    }
+
+    /// Stop timing the running section
+    pub fn end(&mut self) {
+        if let Some((section, start)) = self.running.take() {
+            self.current[section as usize] += start.elapsed();
+        }
+    }
+
+    /// Finish the current frame, adding its timings to the rolling window
+    pub fn end_frame(&mut self) {
+        self.end();
+        push_sample(&mut self.frames, self.current.iter().sum());
+        for (samples, current) in self.samples.iter_mut().zip(self.current.iter_mut()) {
+            push_sample(samples, std::mem::take(current));
+        }
+    }
+
+    /// Rolling stats for a section
+    pub fn stats(&self, section: Section) -> Stats {
+        stats(&self.samples[section as usize])
+    }
+
+    /// Rolling stats for whole frames
+    pub fn frame_stats(&self) -> Stats {
+        stats(&self.frames)
+    }
+
+    /// Add the overlay text to the draw list, if the overlay is visible. Returns the position
+    /// below the last line, so other debug text can follow it
+    pub fn draw(&self, list: &mut render::DrawList, position: (f32, f32)) -> (f32, f32) {
+        if !self.visible {
+            return position;
+        }
+        let (x, mut y) = position;
+        let frame = self.frame_stats();
+        list.text(
+            OVERLAY_Z,
+            text::styles::DIALOGUE,
+            (x, y),
+            format!("\rframe: avg {:?} worst {:?}", frame.average, frame.worst),
+        );
+        for section in SECTIONS.iter() {
+            y += LINE_HEIGHT;
+            let stats = self.stats(*section);
+            list.text(
+                OVERLAY_Z,
+                text::styles::DIALOGUE,
+                (x, y),
+                format!(
+                    "\r  {}: avg {:?} worst {:?}",
+                    section.name(),
+                    stats.average,
+                    stats.worst
+                ),
+            );
+        }
+        (x, y + LINE_HEIGHT)
+    }
+}
+
+/// Add a sample to a rolling window, dropping the oldest sample if the window is full
+fn push_sample(samples: &mut VecDeque<Duration>, sample: Duration) {
+    if samples.len() == ROLLING_FRAMES {
+        samples.pop_front();
+    }
+    samples.push_back(sample);
+}
+
+fn stats(samples: &VecDeque<Duration>) -> Stats {
+    if samples.is_empty() {
+        return Stats::default();
+    }
+    Stats {
+        average: samples.iter().sum::<Duration>() / samples.len() as u32,
+        worst: samples.iter().max().copied().unwrap_or_default(),
+    }
+}
@@ -0,0 +1,348 @@
+/// Owns the sub-renderers used to draw a frame, and the per-frame state passed to their draw calls
+///
+/// Sub-renderers are enabled or disabled with a [Builder]. Draw calls take a single [DrawContext]
+/// holding the gpu context, command encoder, and target frame, rather than each of these
+/// separately
+///
+/// Each frame, everything to draw is added to a [DrawList], which is culled, sorted, and batched
+/// when it is submitted
+///
+use crate::profile::{Profiler, Section};
+use crate::{gfx, text, ui, window};
+use std::time::{Duration, Instant};
+
+/// Longest frame delta reported by [Renderer::end_frame]. The reader only redraws when there is
+/// something to update, so the time since the last frame may include a long idle wait
+const MAX_FRAME_DELTA: Duration = Duration::from_millis(100);
+
+/// Builder for a [Renderer]. All sub-renderers are enabled by default
+pub struct Builder {
+    sprite: bool,
+    text: bool,
+    cache_settings: text::CacheSettings,
+}
+
+impl Builder {
+    /// Create a builder with all sub-renderers enabled
+    pub fn new() -> Self {
+        Self {
+            sprite: true,
+            text: true,
+            cache_settings: text::CacheSettings::default(),
+        }
+    }
+
+    /// Enable or disable the sprite renderer
+    pub fn sprite(mut self, enabled: bool) -> Self {
+        self.sprite = enabled;
+        self
+    }
+
+    /// Enable or disable the text renderer
+    pub fn text(mut self, enabled: bool) -> Self {
+        self.text = enabled;
+        self
+    }
+
+    /// Set the glyph cache settings used by the text renderer
+    pub fn cache_settings(mut self, settings: text::CacheSettings) -> Self {
+        self.cache_settings = settings;
+        self
+    }
+
+    /// Create the enabled sub-renderers
+    pub fn build(self, context: &gfx::Context) -> Renderer {
+        Renderer {
+            sprite: self.sprite.then(|| gfx::Brush::new_sprite_brush(context)),
+            text: self
+                .text
+                .then(|| text::Renderer::new(context, self.cache_settings)),
+            last_frame: None,
+        }
+    }
+}
+
+/// Per-frame state needed by all draw calls
+pub struct DrawContext<'a> {
+    pub context: &'a mut gfx::Context,
+    pub encoder: gfx::CommandEncoder,
+    pub frame: gfx::Frame,
+    pub size: window::Size,
+}
+
+/// Collection of sub-renderers. Disabled sub-renderers are None, and their draw calls do nothing
+pub struct Renderer {
+    pub sprite: Option<gfx::Brush>,
+    pub text: Option<text::Renderer>,
+    /// When the previous frame was submitted
+    last_frame: Option<Instant>,
+}
+
+/// Timing of a submitted frame
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FrameTime {
+    /// Time spent building and submitting the frame
+    pub build: Duration,
+    /// Time since the previous frame was submitted, capped at MAX_FRAME_DELTA. Zero for the
+    /// first frame. Animations should be advanced by this amount
+    pub delta: Duration,
+}
+
+impl Renderer {
+    /// Begin a new frame, see [gfx::begin_frame]
+    pub fn begin_frame<'a>(
+        &self,
+        context: &'a mut gfx::Context,
+        size: window::Size,
+    ) -> anyhow::Result<DrawContext<'a>> {
+        let (encoder, frame) = gfx::begin_frame(context)?;
+        Ok(DrawContext {
+            context,
+            encoder,
+            frame,
+            size,
+        })
+    }
+
+    /// Draw a frame's draw list. Items outside the window or their clip rect are culled, then
+    /// sprites are drawn back to front in a single render pass, binding each texture only when it
+    /// changes. Text is drawn afterwards, above all sprites, with its z value as depth. Each stage
+    /// is timed by the profiler
+    pub fn submit(
+        &mut self,
+        draw: &mut DrawContext,
+        list: DrawList,
+        profiler: &mut Profiler,
+    ) -> SubmitStats {
+        let mut stats = SubmitStats::default();
+        let screen =
+            ui::Rect::from_tuple((0.0, 0.0, draw.size.width as f64, draw.size.height as f64));
+
+        let mut entries = list.entries;
+        let total = entries.len();
+        entries.retain(|entry| entry.visible(&screen, draw.context.scale));
+        stats.culled = total - entries.len();
+        // stable sort, items with equal z keep the order they were added in
+        entries.sort_by(|a, b| a.z.partial_cmp(&b.z).unwrap_or(std::cmp::Ordering::Equal));
+
+        profiler.begin(Section::ShapeDraw);
+        if let Some(brush) = self.sprite.as_ref() {
+            let mut renderpass =
+                gfx::begin_renderpass(&*draw.context, &mut draw.encoder, &draw.frame);
+            renderpass.set_pipeline(&brush.pipeline);
+            let mut bound: Option<&gfx::Texture> = None;
+            let mut scissor = None;
+            for entry in entries.iter() {
+                if let Item::Sprite { texture, quad, .. } = &entry.item {
+                    let clip = entry.clip.map(|clip| scissor_rect(&clip, &screen));
+                    if clip != scissor {
+                        let (x, y, w, h) = clip.unwrap_or_else(|| scissor_rect(&screen, &screen));
+                        renderpass.set_scissor_rect(x, y, w, h);
+                        scissor = clip;
+                    }
+                    if !bound.map_or(false, |b| std::ptr::eq(b, *texture)) {
+                        renderpass.set_bind_group(0, &texture.bind_group, &[]);
+                        bound = Some(*texture);
+                        stats.texture_binds += 1;
+                    }
+                    renderpass.set_vertex_buffer(0, quad.vertex_buffer.slice(..));
+                    renderpass.draw(0..quad.num_verts, 0..1);
+                    stats.sprites += 1;
+                }
+            }
+            gfx::end_renderpass(renderpass);
+        }
+
+        if let Some(text) = self.text.as_mut() {
+            profiler.begin(Section::TextEnqueue);
+            for entry in entries.into_iter() {
+                if let Item::Text {
+                    style,
+                    position,
+                    text: string,
+                    revealed,
+                } = entry.item
+                {
+                    match revealed {
+                        Some(revealed) => {
+                            text.enqueue_revealed(style, position, entry.z, &string, revealed)
+                        }
+                        None => text.enqueue(style, position, entry.z, &string),
+                    }
+                    stats.texts += 1;
+                }
+            }
+            profiler.begin(Section::GlyphProcessing);
+            text.draw(draw.context, &mut draw.encoder, draw.size, &draw.frame);
+        }
+        stats
+    }
+
+    /// Submit the frame, see [gfx::end_frame]
+    pub fn end_frame(&mut self, draw: DrawContext) -> FrameTime {
+        let build = gfx::end_frame(draw.context, draw.encoder, draw.frame);
+        let now = Instant::now();
+        let delta = self.last_frame.map_or(Duration::default(), |last| {
+            (now - last).min(MAX_FRAME_DELTA)
+        });
+        self.last_frame = Some(now);
+        FrameTime { build, delta }
+    }
+}
+
+/// Something to draw
+enum Item<'a> {
+    /// A textured quad, with its bounds in physical pixels for culling
+    Sprite {
+        texture: &'a gfx::Texture,
+        quad: &'a gfx::Quad,
+        bounds: ui::Rect,
+    },
+    /// A line of text, positioned in logical pixels
+    Text {
+        style: text::Style,
+        position: (f32, f32),
+        text: String,
+        /// Number of glyphs shown, for text being revealed by an animation. None shows all text
+        revealed: Option<f32>,
+    },
+}
+
+/// An item in a draw list, with its depth and clip rect
+struct Entry<'a> {
+    z: f32,
+    clip: Option<ui::Rect>,
+    item: Item<'a>,
+}
+
+impl<'a> Entry<'a> {
+    /// Check if any part of the item may be visible on screen and within its clip rect. The
+    /// extent of text isn't known before layout, so text is culled by its position only
+    fn visible(&self, screen: &ui::Rect, scale: f64) -> bool {
+        let area = self.clip.as_ref().unwrap_or(screen);
+        match &self.item {
+            Item::Sprite { bounds, .. } => overlaps(bounds, area) && overlaps(bounds, screen),
+            Item::Text { position, .. } => {
+                let x = position.0 as f64 * scale;
+                let y = position.1 as f64 * scale;
+                let point = ui::Rect::from_coords(x, x, y, y);
+                overlaps(&point, area) && overlaps(&point, screen)
+            }
+        }
+    }
+}
+
+/// Retained list of everything to draw in a frame. Items are drawn in order of increasing z, and
+/// items with equal z are drawn in the order they were added
+#[derive(Default)]
+pub struct DrawList<'a> {
+    entries: Vec<Entry<'a>>,
+    clip: Option<ui::Rect>,
+}
+
+impl<'a> DrawList<'a> {
+    /// Create an empty draw list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Clip items added after this call to a rect in physical pixels. None removes the clip
+    pub fn set_clip(&mut self, clip: Option<ui::Rect>) {
+        self.clip = clip;
+    }
+
+    /// Add a sprite, bounds is the area covered by the quad in physical pixels
+    pub fn sprite(
+        &mut self,
+        z: f32,
+        texture: &'a gfx::Texture,
+        quad: &'a gfx::Quad,
+        bounds: ui::Rect,
+    ) {
+        self.entries.push(Entry {
+            z,
+            clip: self.clip,
+            item: Item::Sprite {
+                texture,
+                quad,
+                bounds,
+            },
+        });
+    }
+
+    /// Add a line of text, see [text::Renderer::enqueue]
+    pub fn text<S: Into<String>>(
+        &mut self,
+        z: f32,
+        style: text::Style,
+        position: (f32, f32),
+        text: S,
+    ) {
+        self.entries.push(Entry {
+            z,
+            clip: self.clip,
+            item: Item::Text {
+                style,
+                position,
+                text: text.into(),
+                revealed: None,
+            },
+        });
+    }
+
+    /// Add a line of text with only its first glyphs visible, see
+    /// [text::Renderer::enqueue_revealed]
+    pub fn text_revealed<S: Into<String>>(
+        &mut self,
+        z: f32,
+        style: text::Style,
+        position: (f32, f32),
+        text: S,
+        revealed: f32,
+    ) {
+        self.entries.push(Entry {
+            z,
+            clip: self.clip,
+            item: Item::Text {
+                style,
+                position,
+                text: text.into(),
+                revealed: Some(revealed),
+            },
+        });
+    }
+
+    /// Number of items in the list
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the list has no items
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Counts of the work done to submit a draw list, for the performance overlay
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SubmitStats {
+    pub sprites: usize,
+    pub texts: usize,
+    pub culled: usize,
+    /// Number of times a texture was bound, sprites sharing a texture in a row are batched
+    pub texture_binds: usize,
+}
+
+/// Check if two rects overlap, including touching edges
+fn overlaps(a: &ui::Rect, b: &ui::Rect) -> bool {
+    a.x1 <= b.x2 && a.x2 >= b.x1 && a.y1 <= b.y2 && a.y2 >= b.y1
+}
+
+/// Convert a clip rect to a scissor rect, clamped to the screen
+fn scissor_rect(clip: &ui::Rect, screen: &ui::Rect) -> (u32, u32, u32, u32) {
+    let x1 = clip.x1.max(screen.x1).min(screen.x2);
+    let x2 = clip.x2.max(screen.x1).min(screen.x2);
+    let y1 = clip.y1.max(screen.y1).min(screen.y2);
+    let y2 = clip.y2.max(screen.y1).min(screen.y2);
+    (x1 as u32, y1 as u32, (x2 - x1) as u32, (y2 - y1) as u32)
+}
@@ -2,11 +2,16 @@
 ///
 use crate::gfx::{self, OUTPUT_FORMAT};
 use crate::window;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 use wgpu::DepthStencilState;
+use wgpu_glyph::ab_glyph::Font as _;
 pub use wgpu_glyph::GlyphBrush;
-use wgpu_glyph::{ab_glyph, GlyphBrushBuilder, Section, Text};
+use wgpu_glyph::{ab_glyph, FontId, GlyphBrushBuilder, Section, Text};
 
 /// Enum for all supported fonts, used as an index into the [TextRenderer]'s [glyph_brush]
+#[derive(Clone, Copy)]
 pub enum Font {
     LoraRegular = 0,
 }
@@ -20,20 +25,61 @@ pub mod styles {
 
     pub const TITLE: Style = Style {
         font: Font::LoraRegular,
+        fallbacks: &[],
         color: [0.8, 0.8, 0.8, 1.0],
         size: 48.0,
     };
     pub const DIALOGUE: Style = Style {
         font: Font::LoraRegular,
+        fallbacks: &[],
         color: [0.8, 0.8, 0.8, 1.0],
         size: 12.0,
     };
+    pub const NOTIFY_INFO: Style = Style {
+        font: Font::LoraRegular,
+        fallbacks: &[],
+        color: [0.8, 0.8, 0.8, 1.0],
+        size: 14.0,
+    };
+    pub const NOTIFY_WARNING: Style = Style {
+        font: Font::LoraRegular,
+        fallbacks: &[],
+        color: [0.9, 0.7, 0.2, 1.0],
+        size: 14.0,
+    };
+    pub const NOTIFY_ERROR: Style = Style {
+        font: Font::LoraRegular,
+        fallbacks: &[],
+        color: [0.9, 0.2, 0.2, 1.0],
+        size: 14.0,
+    };
+    pub const CHOICE: Style = Style {
+        font: Font::LoraRegular,
+        fallbacks: &[],
+        color: [0.9, 0.9, 0.9, 1.0],
+        size: 16.0,
+    };
+    pub const CHOICE_LOCKED: Style = Style {
+        font: Font::LoraRegular,
+        fallbacks: &[],
+        color: [0.45, 0.45, 0.45, 1.0],
+        size: 16.0,
+    };
+    pub const TOOLTIP: Style = Style {
+        font: Font::LoraRegular,
+        fallbacks: &[],
+        color: [0.9, 0.8, 0.5, 1.0],
+        size: 12.0,
+    };
 }
 
 /// StyleData for text types. Contains all information needed by other modules to render text
 /// as desired
 pub struct Style {
     pub font: Font,
+    /// Fonts to try in order for characters missing from the main font, before falling back on
+    /// any fonts loaded at runtime
+    pub fallbacks: &'static [Font],
     /// color of the text, stored in RGBA format
     pub color: [f32; 4],
     /// Size of the text. This is an arbitrary/relative value, as the actual drawn size will
@@ -41,10 +87,140 @@ pub struct Style {
     pub size: f32,
 }
 
+/// Key of the glyph cache settings in the settings file, see arbor_core::logging::Settings::path
+pub const CACHE_SETTINGS_KEY: &str = "glyph_cache";
+
+/// Settings for the glyph cache texture, as stored in the settings file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CacheSettings {
+    /// Size of the cache texture when created, and after it is trimmed
+    pub initial_size: (u32, u32),
+    /// Estimated texture area in pixels the glyphs in use may take up. Past it the least recently
+    /// used glyphs are evicted
+    pub max_pixels: u64,
+    /// Number of frames a glyph may go unused before it is evicted
+    pub max_idle_frames: u64,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            initial_size: (512, 512),
+            max_pixels: 1024 * 1024,
+            max_idle_frames: 600,
+        }
+    }
+}
+
+impl CacheSettings {
+    /// Read the glyph cache settings from a settings file. A missing file or a file without
+    /// glyph cache settings gives the default settings
+    ///
+    /// # Errors
+    ///
+    /// Error if the file exists but cannot be read or parsed
+    pub fn load(path: &Path) -> anyhow::Result<CacheSettings> {
+        let settings: serde_json::Map<String, serde_json::Value> =
+            match std::fs::read_to_string(path) {
+                Ok(s) => serde_json::from_str(&s)?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+                Err(e) => return Err(e.into()),
+            };
+        match settings.get(CACHE_SETTINGS_KEY) {
+            Some(cache) => Ok(serde_json::from_value(cache.clone())?),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+/// Usage of the glyph cache, shown in the performance overlay
+///
+/// wgpu_glyph does not expose its cache texture, so areas are estimated from the size of each
+/// glyph used
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CacheMetrics {
+    /// Glyphs in use, those not yet evicted
+    pub glyphs: usize,
+    /// Estimated texture area used by those glyphs, in pixels
+    pub used_pixels: u64,
+    /// Estimated texture area the cache has grown to since it was last trimmed, in pixels. Each
+    /// glyph added to the cache counts, including glyphs added again after they were evicted
+    pub peak_pixels: u64,
+    /// Number of glyphs evicted, least recently used first
+    pub evictions: usize,
+    /// Number of times the cache texture was rebuilt to reclaim GPU memory
+    pub trims: usize,
+}
+
+/// Key for tracking glyph usage: font index, rounded pixel size, and character
+type GlyphKey = (usize, u32, char);
+
+/// Estimated texture area of a glyph, in pixels
+fn glyph_pixels((_, size, _): GlyphKey) -> u64 {
+    size as u64 * size as u64
+}
+
+/// Glyphs in use, in the order they were last used, so the least recently used glyph is evicted
+/// first
+#[derive(Default)]
+struct GlyphLru {
+    /// Glyphs by the use count at their last use, oldest first
+    order: BTreeMap<u64, GlyphKey>,
+    /// Use count and frame of the last use of each glyph
+    last_used: HashMap<GlyphKey, (u64, u64)>,
+    /// Number of uses so far, increased on every use
+    uses: u64,
+    /// Estimated texture area of the glyphs, in pixels
+    pixels: u64,
+}
+
+impl GlyphLru {
+    /// Mark a glyph as the most recently used. Returns true if the glyph was not in use
+    fn touch(&mut self, key: GlyphKey, frame: u64) -> bool {
+        self.uses += 1;
+        self.order.insert(self.uses, key);
+        match self.last_used.insert(key, (self.uses, frame)) {
+            Some((old, _)) => {
+                self.order.remove(&old);
+                false
+            }
+            None => {
+                self.pixels += glyph_pixels(key);
+                true
+            }
+        }
+    }
+
+    /// Frame the least recently used glyph was last used in
+    fn oldest_frame(&self) -> Option<u64> {
+        let key = self.order.values().next()?;
+        self.last_used.get(key).map(|(_, frame)| *frame)
+    }
+
+    /// Evict the least recently used glyph
+    fn evict(&mut self) -> Option<GlyphKey> {
+        let (_, key) = self.order.pop_first()?;
+        self.last_used.remove(&key);
+        self.pixels -= glyph_pixels(key);
+        Some(key)
+    }
+}
+
 /// Stores data needed to render text
 pub struct Renderer {
     /// glyph_brush storing all initialized font data
     glyph_brush: GlyphBrush<DepthStencilState>,
+    /// Fonts loaded at runtime, tried in order after the fonts of a style
+    fallbacks: Vec<FontId>,
+    settings: CacheSettings,
+    /// Glyphs queued recently. Glyphs unused for longer than the idle window, or past the size
+    /// limit, are evicted, least recently used first
+    glyph_usage: GlyphLru,
+    frame: u64,
+    metrics: CacheMetrics,
+    /// DPI scale factor, applied to the position and size of all queued text
+    scale: f32,
 }
 
 impl Renderer {
@@ -54,7 +230,7 @@ impl Renderer {
     ///
     /// # Panics
     /// If the passed slice isn't a valid font binary
-    pub fn new(context: &gfx::Context) -> Renderer {
+    pub fn new(context: &gfx::Context, settings: CacheSettings) -> Renderer {
         // Load all the fonts into a vec
         //
         // Implementation note:
@@ -66,7 +242,25 @@ impl Renderer {
             .map(|f| ab_glyph::FontArc::try_from_slice(f).expect("font loading failed"))
             .collect();
 
-        let glyph_brush = GlyphBrushBuilder::using_fonts(fonts)
+        Renderer {
+            glyph_brush: Renderer::build_brush(context, fonts, settings.initial_size),
+            fallbacks: Vec::new(),
+            settings,
+            glyph_usage: GlyphLru::default(),
+            frame: 0,
+            metrics: CacheMetrics::default(),
+            scale: context.scale as f32,
+        }
+    }
+
+    /// Build a glyph brush for a set of fonts, with a cache texture of the given size
+    fn build_brush(
+        context: &gfx::Context,
+        fonts: Vec<ab_glyph::FontArc>,
+        cache_size: (u32, u32),
+    ) -> GlyphBrush<DepthStencilState> {
+        GlyphBrushBuilder::using_fonts(fonts)
+            .initial_cache_size(cache_size)
             .depth_stencil_state(wgpu::DepthStencilState {
                 format: wgpu::TextureFormat::Depth32Float,
                 depth_write_enabled: true,
@@ -74,25 +268,194 @@ impl Renderer {
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             })
-            .build(&context.device, OUTPUT_FORMAT);
-        Renderer { glyph_brush }
+            .build(&context.device, OUTPUT_FORMAT)
+    }
+
+    /// Set the DPI scale factor. Text queued afterwards is positioned and sized for the new scale
+    pub fn set_scale(&mut self, scale: f64) {
+        self.scale = scale as f32;
+    }
+
+    /// Current DPI scale factor
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    /// Current usage of the glyph cache
+    pub fn metrics(&self) -> CacheMetrics {
+        self.metrics
+    }
+
+    /// Evict the least recently used glyphs while they have gone unused for longer than the idle
+    /// window, or take up more than the size limit, and update the cache metrics. If most of the
+    /// space the cache has grown to is no longer in use, the glyph brush is rebuilt at its
+    /// initial size to release the larger texture
+    fn trim_cache(&mut self, context: &gfx::Context) {
+        let idle_since = self.frame.saturating_sub(self.settings.max_idle_frames);
+        while self.glyph_usage.pixels > self.settings.max_pixels
+            || self
+                .glyph_usage
+                .oldest_frame()
+                .is_some_and(|frame| frame < idle_since)
+        {
+            if self.glyph_usage.evict().is_none() {
+                break;
+            }
+            self.metrics.evictions += 1;
+        }
+
+        let used_pixels = self.glyph_usage.pixels;
+        self.metrics.glyphs = self.glyph_usage.last_used.len();
+        self.metrics.used_pixels = used_pixels;
+
+        let (width, height) = self.settings.initial_size;
+        let initial_pixels = width as u64 * height as u64;
+        if self.metrics.peak_pixels > initial_pixels && used_pixels * 4 < self.metrics.peak_pixels {
+            log::info!(
+                "trimming glyph cache, {} of {} pixels in use",
+                used_pixels,
+                self.metrics.peak_pixels
+            );
+            let fonts = self.glyph_brush.fonts().to_vec();
+            self.glyph_brush = Renderer::build_brush(context, fonts, self.settings.initial_size);
+            self.metrics.peak_pixels = used_pixels;
+            self.metrics.trims += 1;
+        }
+    }
+
+    /// Load a font file from disk and add it to the end of the fallback chain used by all styles.
+    /// Use this to cover scripts and emoji missing from the bundled fonts. Only outline glyphs are
+    /// drawn, so color emoji render in the color of the style
+    pub fn load_fallback(&mut self, path: &Path) -> anyhow::Result<()> {
+        let font = ab_glyph::FontArc::try_from_vec(std::fs::read(path)?)?;
+        let id = self.glyph_brush.add_font(font);
+        self.fallbacks.push(id);
+        Ok(())
+    }
+
+    /// Load all .ttf and .otf files in a directory as fallback fonts, in order of file name.
+    /// Returns the number of fonts loaded
+    pub fn load_fallback_dir(&mut self, dir: &Path) -> anyhow::Result<usize> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| {
+                matches!(
+                    path.extension().and_then(|e| e.to_str()),
+                    Some("ttf") | Some("otf")
+                )
+            })
+            .collect();
+        paths.sort();
+        for path in paths.iter() {
+            self.load_fallback(path)?;
+        }
+        Ok(paths.len())
+    }
+
+    /// Find the first font in the fallback chain of a style that has a glyph for a character.
+    /// Characters missing from every font use the main font of the style
+    fn font_for(&self, style: &Style, c: char) -> FontId {
+        let fonts = self.glyph_brush.fonts();
+        std::iter::once(style.font)
+            .chain(style.fallbacks.iter().copied())
+            .map(|font| FontId(font as usize))
+            .chain(self.fallbacks.iter().copied())
+            .find(|id| fonts[id.0].glyph_id(c).0 != 0)
+            .unwrap_or(FontId(style.font as usize))
     }
 
     /// Enqueues text to be drawn by a subsequent call to [draw]
+    ///
+    /// The position and size of the text are in logical pixels, and are scaled by the DPI scale
+    /// factor. The text is split into runs of characters that share a font from the style's
+    /// fallback chain
     pub fn enqueue(&mut self, style: Style, position: (f32, f32), height: f32, text: &str) {
+        let mut runs = Vec::new();
+        let mut start = 0;
+        let mut current = None;
+        let scaled_size = style.size * self.scale;
+        for (i, c) in text.char_indices() {
+            let font = self.use_glyph(&style, c);
+            match current {
+                Some(run_font) if run_font != font => {
+                    runs.push((&text[start..i], run_font));
+                    start = i;
+                    current = Some(font);
+                }
+                Some(_) => {}
+                None => current = Some(font),
+            }
+        }
+        if let Some(run_font) = current {
+            runs.push((&text[start..], run_font));
+        }
+
         // Queue text on top, it will be drawn first.
         // Depth buffer will make it appear on top.
         self.glyph_brush.queue(Section {
-            screen_position: position,
-            text: vec![Text::default()
-                .with_text(text)
-                .with_scale(style.size)
-                .with_color(style.color)
-                .with_z(height)],
+            screen_position: (position.0 * self.scale, position.1 * self.scale),
+            text: runs
+                .into_iter()
+                .map(|(run, font)| {
+                    Text::new(run)
+                        .with_font_id(font)
+                        .with_scale(scaled_size)
+                        .with_color(style.color)
+                        .with_z(height)
+                })
+                .collect(),
+            ..Section::default()
+        });
+    }
+
+    /// Enqueues text with only its first glyphs visible, for reveal animations. revealed is the
+    /// number of glyphs shown, a fractional part fades in the next glyph
+    ///
+    /// Each character is queued separately, and hidden glyphs are drawn fully transparent, so the
+    /// section has the same text and layout each frame as more of it is revealed. glyph_brush
+    /// only recolors cached glyphs in that case, rather than shaping the text again
+    pub fn enqueue_revealed(
+        &mut self,
+        style: Style,
+        position: (f32, f32),
+        height: f32,
+        text: &str,
+        revealed: f32,
+    ) {
+        let scaled_size = style.size * self.scale;
+        let [r, g, b, a] = style.color;
+        let mut glyphs = Vec::with_capacity(text.len());
+        for (n, (i, c)) in text.char_indices().enumerate() {
+            let font = self.use_glyph(&style, c);
+            let alpha = (revealed - n as f32).clamp(0.0, 1.0);
+            glyphs.push(
+                Text::new(&text[i..i + c.len_utf8()])
+                    .with_font_id(font)
+                    .with_scale(scaled_size)
+                    .with_color([r, g, b, a * alpha])
+                    .with_z(height),
+            );
+        }
+
+        self.glyph_brush.queue(Section {
+            screen_position: (position.0 * self.scale, position.1 * self.scale),
+            text: glyphs,
             ..Section::default()
         });
     }
 
+    /// Find the font used to draw a character, and mark its glyph as used this frame
+    fn use_glyph(&mut self, style: &Style, c: char) -> FontId {
+        let font = self.font_for(style, c);
+        if !c.is_whitespace() {
+            let key = (font.0, (style.size * self.scale).ceil() as u32, c);
+            if self.glyph_usage.touch(key, self.frame) {
+                self.metrics.peak_pixels += glyph_pixels(key);
+            }
+        }
+        font
+    }
+
     /// Draw all text that was queued up
     pub fn draw(
         &mut self,
@@ -123,5 +486,8 @@ impl Renderer {
                 size.height,
             )
             .expect("Draw queued");
+
+        self.frame += 1;
+        self.trim_cache(context);
     }
 }
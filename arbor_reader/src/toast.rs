@@ -0,0 +1,213 @@
+/// On-screen notification queue. Toasts are stacked in the top right corner of the window, and
+/// are dismissed automatically after a timeout. Clicking a toast expands it to show its details,
+/// and keeps it on screen until it is clicked again
+///
+use crate::{gfx, render, text, ui, window};
+use std::collections::VecDeque;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+/// Time a toast stays on screen before being dismissed, unless it is expanded
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Maximum number of toasts kept in the queue, older toasts are dropped first
+const MAX_TOASTS: usize = 8;
+
+// layout of toasts in logical pixels
+const TOAST_WIDTH: f64 = 360.0;
+const TOAST_HEIGHT: f64 = 24.0;
+const DETAIL_LINE_HEIGHT: f64 = 14.0;
+const TOAST_MARGIN: f64 = 8.0;
+const TEXT_PADDING: f32 = 6.0;
+/// Depth of toasts in the draw list, above all other items
+const TOAST_Z: f32 = 0.9;
+
+/// Severity of a notification, sets the color it is drawn with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    fn style(self) -> text::Style {
+        match self {
+            Level::Info => text::styles::NOTIFY_INFO,
+            Level::Warning => text::styles::NOTIFY_WARNING,
+            Level::Error => text::styles::NOTIFY_ERROR,
+        }
+    }
+}
+
+/// Create a log notify function that sends logged errors to a channel, so they can be shown as
+/// notifications with [Toasts::receive]. Errors logged by the notifications themselves are not
+/// sent, since they are already on screen
+pub fn log_errors() -> (
+    log::LevelFilter,
+    arbor_cli::logger::Notify,
+    mpsc::Receiver<String>,
+) {
+    let (sender, receiver) = mpsc::channel();
+    // Sender is not Sync, so the notify function shares it through a mutex
+    let sender = std::sync::Mutex::new(sender);
+    let notify = move |record: &log::Record| {
+        if record.target() == module_path!() {
+            return;
+        }
+        if let Ok(sender) = sender.lock() {
+            // the receiver is dropped when the window closes, later errors are only logged
+            let _ = sender.send(format!("{}: {}", record.target(), record.args()));
+        }
+    };
+    (log::LevelFilter::Error, Box::new(notify), receiver)
+}
+
+/// A single notification
+pub struct Toast {
+    pub level: Level,
+    /// One line summary, always shown
+    pub summary: String,
+    /// Longer description, shown when the toast is expanded
+    pub details: String,
+    created: Instant,
+    expanded: bool,
+}
+
+impl Toast {
+    /// Height of the toast on screen, expanded toasts grow to fit their details
+    fn height(&self) -> f64 {
+        if self.expanded {
+            TOAST_HEIGHT + DETAIL_LINE_HEIGHT * self.details.lines().count() as f64
+        } else {
+            TOAST_HEIGHT
+        }
+    }
+}
+
+/// Queue of notifications to show on screen
+pub struct Toasts {
+    queue: VecDeque<Toast>,
+    timeout: Duration,
+}
+
+impl Toasts {
+    /// Create an empty notification queue
+    pub fn new() -> Self {
+        Self {
+            queue: VecDeque::with_capacity(MAX_TOASTS),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Add a notification to the queue. Notifications are also logged at the matching level
+    pub fn push(&mut self, level: Level, summary: &str, details: &str) {
+        match level {
+            Level::Info => log::info!("{}: {}", summary, details),
+            Level::Warning => log::warn!("{}: {}", summary, details),
+            Level::Error => log::error!("{}: {}", summary, details),
+        }
+        if self.queue.len() == MAX_TOASTS {
+            self.queue.pop_front();
+        }
+        self.queue.push_back(Toast {
+            level,
+            summary: summary.to_string(),
+            details: details.to_string(),
+            created: Instant::now(),
+            expanded: false,
+        });
+    }
+
+    /// Convenience method to push an info notification
+    pub fn info(&mut self, summary: &str, details: &str) {
+        self.push(Level::Info, summary, details);
+    }
+
+    /// Convenience method to push a warning notification
+    pub fn warn(&mut self, summary: &str, details: &str) {
+        self.push(Level::Warning, summary, details);
+    }
+
+    /// Convenience method to push an error notification
+    pub fn error(&mut self, summary: &str, details: &str) {
+        self.push(Level::Error, summary, details);
+    }
+
+    /// Show errors sent by the notify function from [log_errors]
+    pub fn receive(&mut self, errors: &mpsc::Receiver<String>) {
+        for details in errors.try_iter() {
+            self.error("error logged", &details);
+        }
+    }
+
+    /// Check if there are no notifications on screen
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    /// Screen area of each toast in physical pixels, from the top of the stack down
+    fn rects(&self, size: window::Size, scale: f64) -> impl Iterator<Item = (&Toast, ui::Rect)> {
+        let x = size.width as f64 - (TOAST_WIDTH + TOAST_MARGIN) * scale;
+        let mut y = TOAST_MARGIN * scale;
+        self.queue.iter().rev().map(move |toast| {
+            let height = toast.height() * scale;
+            let rect = ui::Rect::from_tuple((x, y, TOAST_WIDTH * scale, height));
+            y += height + TOAST_MARGIN * scale;
+            (toast, rect)
+        })
+    }
+
+    /// Expire old notifications and toggle expanded notifications that were clicked this frame.
+    /// Returns true if the cursor was captured by a notification
+    pub fn update(&mut self, input: &window::Input, size: window::Size, scale: f64) -> bool {
+        let clicked = self
+            .rects(size, scale)
+            .position(|(_, rect)| rect.clicked(input))
+            .map(|i| self.queue.len() - 1 - i);
+        if let Some(index) = clicked {
+            let toast = &mut self.queue[index];
+            toast.expanded = !toast.expanded;
+            // restart the timeout once a toast is collapsed again
+            toast.created = Instant::now();
+        }
+
+        let timeout = self.timeout;
+        self.queue
+            .retain(|toast| toast.expanded || toast.created.elapsed() < timeout);
+        clicked.is_some()
+    }
+
+    /// Create background quads for each notification, to be passed to [Toasts::draw]
+    pub fn quads(&self, context: &gfx::Context, size: window::Size) -> Vec<gfx::Quad> {
+        self.rects(size, context.scale)
+            .map(|(_, rect)| rect.to_quad(context, size))
+            .collect()
+    }
+
+    /// Add each notification to the draw list, above everything else. quads must be created by
+    /// [Toasts::quads] this frame
+    pub fn draw<'a>(
+        &self,
+        list: &mut render::DrawList<'a>,
+        quads: &'a [gfx::Quad],
+        texture: &'a gfx::Texture,
+        size: window::Size,
+        scale: f64,
+    ) {
+        for ((toast, rect), quad) in self.rects(size, scale).zip(quads.iter()) {
+            list.sprite(TOAST_Z, texture, quad, rect);
+            // toasts are laid out in physical pixels, while text is positioned in logical pixels
+            let x = (rect.x1 / scale) as f32 + TEXT_PADDING;
+            let y = (rect.y1 / scale) as f32 + TEXT_PADDING;
+            list.text(TOAST_Z, toast.level.style(), (x, y), toast.summary.as_str());
+            if toast.expanded {
+                list.text(
+                    TOAST_Z,
+                    text::styles::DIALOGUE,
+                    (x, y + TOAST_HEIGHT as f32 - TEXT_PADDING),
+                    toast.details.as_str(),
+                );
+            }
+        }
+    }
+}
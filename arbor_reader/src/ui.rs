@@ -1,4 +1,4 @@
-use crate::{gfx, window};
+use crate::{gfx, render, text, window};
 
 /// Data for the size and position of a rectangular area. The rectangular area is in screen
 /// coordinates. Values are stored as float64 for easy checking against mouse cursor data
@@ -9,6 +9,7 @@ use crate::{gfx, window};
 ///   |            |
 ///   |            |
 /// x2y1 -------- x2y2
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Rect {
     pub x1: f64,
     pub x2: f64,
@@ -84,3 +85,147 @@ impl Rect {
         input.cursor_released() && self.hovered(input)
     }
 }
+
+// layout of choice lists in logical pixels
+const CHOICE_LINE_HEIGHT: f64 = 24.0;
+const TOOLTIP_OFFSET: f32 = 16.0;
+/// Depth of choice text in the draw list
+const CHOICE_Z: f32 = 0.5;
+/// Depth of choice tooltips in the draw list, above other ui
+const TOOLTIP_Z: f32 = 0.7;
+
+/// How to show choices whose requirements are not met
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockedChoices {
+    /// Show locked choices in a greyed out style. They can't be selected, but show a tooltip
+    /// explaining the requirement when hovered
+    Grey,
+    /// Leave locked choices out of the list
+    Hide,
+}
+
+impl From<arbor_core::UnmetChoices> for LockedChoices {
+    /// Show locked choices the way the project's choice policy asks for
+    fn from(unmet: arbor_core::UnmetChoices) -> Self {
+        match unmet {
+            arbor_core::UnmetChoices::Disable => LockedChoices::Grey,
+            arbor_core::UnmetChoices::Hide => LockedChoices::Hide,
+        }
+    }
+}
+
+/// Events emitted by a [ChoiceList]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChoiceEvent {
+    /// A choice was clicked, holds the index of the choice's edge
+    Selected(arbor_core::tree::EdgeIndex),
+}
+
+/// A choice as laid out by a [ChoiceList]
+struct ChoiceItem {
+    choice: arbor_core::runner::ChoiceState,
+    /// Explanation of the requirement, shown as a tooltip for locked choices
+    tooltip: String,
+    /// Area of the choice in physical pixels
+    rect: Rect,
+}
+
+/// Reusable widget listing the outgoing choices of a node. Choices are laid out top to bottom
+/// from the origin, and their requirements are checked against a snapshot of the name and val
+/// tables passed to [ChoiceList::update]
+pub struct ChoiceList {
+    /// Top left of the list in logical pixels
+    pub origin: (f64, f64),
+    /// Width of each choice in logical pixels
+    pub width: f64,
+    pub locked: LockedChoices,
+    items: Vec<ChoiceItem>,
+}
+
+impl ChoiceList {
+    /// Create an empty choice list
+    pub fn new(origin: (f64, f64), width: f64, locked: LockedChoices) -> Self {
+        Self {
+            origin,
+            width,
+            locked,
+            items: Vec::new(),
+        }
+    }
+
+    /// Lay out the choices leaving a node. Should be called whenever the node, the tables, or the
+    /// window scale change
+    pub fn update(
+        &mut self,
+        data: &arbor_core::DialogueTreeData,
+        node: arbor_core::tree::NodeIndex,
+        names: &arbor_core::NameTable,
+        vals: &arbor_core::ValTable,
+        scale: f64,
+    ) -> arbor_core::Result<()> {
+        let locked = self.locked;
+        let (x, y) = self.origin;
+        let width = self.width;
+        self.items = arbor_core::runner::choice_states(data, node, names, vals)?
+            .into_iter()
+            .filter(|choice| choice.met || locked == LockedChoices::Grey)
+            .enumerate()
+            .map(|(i, choice)| {
+                let top = y + CHOICE_LINE_HEIGHT * i as f64;
+                ChoiceItem {
+                    tooltip: arbor_core::runner::describe_requirement(
+                        &choice.requirement,
+                        names,
+                        vals,
+                    ),
+                    rect: Rect::from_tuple((
+                        x * scale,
+                        top * scale,
+                        width * scale,
+                        CHOICE_LINE_HEIGHT * scale,
+                    )),
+                    choice,
+                }
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Remove all choices, e.g. once the dialogue has ended
+    pub fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    /// Check for a click on a choice this frame. Locked choices can't be selected
+    pub fn handle_input(&self, input: &window::Input) -> Option<ChoiceEvent> {
+        self.items
+            .iter()
+            .find(|item| item.choice.met && item.rect.clicked(input))
+            .map(|item| ChoiceEvent::Selected(item.choice.index))
+    }
+
+    /// Add the choices to the draw list, along with a tooltip for a hovered locked choice
+    pub fn draw(&self, list: &mut render::DrawList, input: &window::Input, scale: f64) {
+        for item in self.items.iter() {
+            // choices are laid out in physical pixels, while text is positioned in logical pixels
+            let position = ((item.rect.x1 / scale) as f32, (item.rect.y1 / scale) as f32);
+            let style = if item.choice.met {
+                text::styles::CHOICE
+            } else {
+                text::styles::CHOICE_LOCKED
+            };
+            list.text(CHOICE_Z, style, position, item.choice.text.as_str());
+
+            if !item.choice.met && item.rect.hovered(input) {
+                let x = (input.cursor_position.x / scale) as f32 + TOOLTIP_OFFSET;
+                let y = (input.cursor_position.y / scale) as f32 + TOOLTIP_OFFSET;
+                list.text(
+                    TOOLTIP_Z,
+                    text::styles::TOOLTIP,
+                    (x, y),
+                    item.tooltip.as_str(),
+                );
+            }
+        }
+    }
+}
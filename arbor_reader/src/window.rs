@@ -5,13 +5,20 @@
 ///
 /// All inner match statements where possible adhere to conditional moves to avoid excess branching
 ///
+use std::path::PathBuf;
 use winit::dpi::{PhysicalPosition, PhysicalSize};
-use winit::event::{ElementState, Event, MouseButton, TouchPhase, WindowEvent};
+use winit::event::{
+    ElementState, Event, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta, TouchPhase,
+    VirtualKeyCode, WindowEvent,
+};
 
 /// Public type for standarized 2 dimensional window size.
 pub type Size = PhysicalSize<u32>;
 pub type Position = PhysicalPosition<f64>;
 
+/// Number of pixels scrolled per line, for mice that report scrolling in lines
+const SCROLL_LINE_HEIGHT: f64 = 20.0;
+
 /// Stores state of window actions, created from a raw winit handle
 pub struct WindowState {
     /// Tracked state for user input
@@ -26,6 +33,10 @@ pub struct WindowState {
     pub rescale: bool,
     /// status flag indicating the app should quit
     pub quit: bool,
+    /// Files dropped onto the window this frame
+    pub dropped_files: Vec<PathBuf>,
+    /// status flag indicating a file is being dragged over the window
+    pub file_hovered: bool,
 }
 
 impl WindowState {
@@ -37,6 +48,8 @@ impl WindowState {
             resize: false,
             rescale: false,
             quit: false,
+            dropped_files: Vec::new(),
+            file_hovered: false,
         }
     }
 
@@ -68,6 +81,7 @@ impl WindowState {
     fn prepare(&mut self) {
         self.resize = false;
         self.rescale = false;
+        self.dropped_files.clear();
         self.input.prepare();
     }
 
@@ -79,9 +93,19 @@ impl WindowState {
                 self.resize = true;
                 self.size = size;
             }
-            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+            WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => {
                 self.rescale = true;
                 self.scale = scale_factor;
+                self.size = *new_inner_size;
+            }
+            WindowEvent::HoveredFile(_) => self.file_hovered = true,
+            WindowEvent::HoveredFileCancelled => self.file_hovered = false,
+            WindowEvent::DroppedFile(path) => {
+                self.file_hovered = false;
+                self.dropped_files.push(path);
             }
             _ => self.input.process_input_event(event),
         }
@@ -92,6 +116,12 @@ impl WindowState {
 pub struct Input {
     pub cursor_position: Position,
     pub text: String,
+    /// Distance scrolled this frame in pixels, positive y scrolls up
+    pub scroll_delta: Position,
+    /// Modifier keys currently held
+    pub modifiers: ModifiersState,
+    /// Keys pressed this frame
+    keys_pressed: Vec<VirtualKeyCode>,
     cursor_pressed: bool,
     cursor_last_pressed: bool,
 }
@@ -102,6 +132,9 @@ impl Input {
         Self {
             cursor_position: Position::new(0.0, 0.0),
             text: String::with_capacity(100),
+            scroll_delta: Position::new(0.0, 0.0),
+            modifiers: ModifiersState::empty(),
+            keys_pressed: Vec::new(),
             cursor_pressed: false,
             cursor_last_pressed: false,
         }
@@ -111,12 +144,35 @@ impl Input {
     fn prepare(&mut self) {
         self.cursor_last_pressed = self.cursor_pressed;
         self.text.clear();
+        self.scroll_delta = Position::new(0.0, 0.0);
+        self.keys_pressed.clear();
     }
 
     fn process_input_event(&mut self, event: WindowEvent) {
         match event {
             WindowEvent::ReceivedCharacter(c) => self.text.push(c),
             WindowEvent::CursorMoved { position, .. } => self.cursor_position = position,
+            WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers,
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => self.keys_pressed.push(key),
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (x, y) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => {
+                        (x as f64 * SCROLL_LINE_HEIGHT, y as f64 * SCROLL_LINE_HEIGHT)
+                    }
+                    MouseScrollDelta::PixelDelta(position) => (position.x, position.y),
+                };
+                // accumulate, multiple wheel events may arrive in a single frame
+                self.scroll_delta.x += x;
+                self.scroll_delta.y += y;
+            }
             WindowEvent::MouseInput {
                 state: ElementState::Pressed,
                 button: MouseButton::Left,
@@ -154,6 +210,26 @@ impl Input {
     pub fn cursor_held(&self) -> bool {
         self.cursor_pressed & self.cursor_last_pressed
     }
+
+    /// Check if a key was pressed this frame. Held keys repeat at the OS key repeat rate
+    pub fn key_pressed(&self, key: VirtualKeyCode) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    /// Check if the mouse wheel or touchpad scrolled this frame
+    pub fn scrolled(&self) -> bool {
+        self.scroll_delta.x != 0.0 || self.scroll_delta.y != 0.0
+    }
+
+    /// Check if the control key, or the command key on macOS, is held. Used to switch scrolling
+    /// to zooming
+    pub fn zoom_modifier(&self) -> bool {
+        if cfg!(target_os = "macos") {
+            self.modifiers.logo()
+        } else {
+            self.modifiers.ctrl()
+        }
+    }
 }
 
 /// Convenience function to create a winit window and WindowState handle
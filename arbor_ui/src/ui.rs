@@ -1,10 +1,16 @@
 use super::util::lorem_ipsum;
-use arbor_core::{cmd, tree, DialogueTreeData, EditorState, Executable, KeyString, NameString};
+use arbor_core::cmd::util::{KeyKind, Reference};
+use arbor_core::{
+    analysis, clipboard, cmd, query, runner, tree, ContentFlags, DialogueTreeData,
+    DialogueTreeEvent, EditorState, EffectKind, Event, EventGroup, Executable, KeyString,
+    LayoutEdit, NameString, ReqKind, NARRATOR_KEY,
+};
 use eframe::egui;
 use eframe::epi;
 use egui::emath::{Pos2, Rect, RectTransform};
 use egui::util::History;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 // constants for maximum width to show for text throughout UI
 const MAX_NAME_WIDTH: f32 = 128.0;
@@ -15,6 +21,9 @@ const MAX_TEXT_WIDTH: f32 = 512.0;
 const MAX_NAME_LEN: usize = 32;
 const MAX_TEXT_LEN: usize = 256;
 
+// maximum number of search results to list, more results are counted but not shown
+const MAX_SEARCH_RESULTS: usize = 200;
+
 #[derive(Serialize, Deserialize, Clone, Copy)]
 pub enum Selection {
     None,
@@ -28,11 +37,18 @@ pub struct ArborUi {
     new_window: NewProjectWindow,
     load_window: LoadWindow,
     rebuild_window: RebuildWindow,
+    open_window: OpenFileWindow,
     backend_panel: BackendPanel,
     name_editor: NameEditor,
     value_editor: ValueEditor,
     node_editor: NodeEditor,
     edge_editor: EdgeEditor,
+    bookmark_editor: BookmarkEditor,
+    selection_editor: SelectionEditor,
+    clipboard: ClipboardEditor,
+    inspector: Inspector,
+    preview: PlaythroughPreview,
+    search_panel: SearchPanel,
     state: arbor_core::EditorState,
     active_selection: Selection,
 }
@@ -44,11 +60,18 @@ impl Default for ArborUi {
             new_window: Default::default(),
             load_window: Default::default(),
             rebuild_window: Default::default(),
+            open_window: Default::default(),
             backend_panel: Default::default(),
             name_editor: Default::default(),
             value_editor: Default::default(),
             node_editor: Default::default(),
             edge_editor: Default::default(),
+            bookmark_editor: Default::default(),
+            selection_editor: Default::default(),
+            clipboard: Default::default(),
+            inspector: Default::default(),
+            preview: Default::default(),
+            search_panel: Default::default(),
             state: EditorState::new(DialogueTreeData::default()),
             active_selection: Selection::None,
         }
@@ -80,9 +103,12 @@ impl epi::App for ArborUi {
         egui::Window::new("Load Project")
             .open(&mut load_window_open)
             .show(ctx, |ui| {
-                self.load_window.ui_content(&mut self.state, ui);
+                self.load_window.ui_content(&mut self.state, ui, frame);
             });
         self.load_window.open &= load_window_open;
+        if !self.load_window.open {
+            self.load_window.release(frame);
+        }
 
         let mut rebuild_window_open = self.rebuild_window.open;
         egui::Window::new("Rebuild Project")
@@ -92,15 +118,50 @@ impl epi::App for ArborUi {
             });
         self.rebuild_window.open &= rebuild_window_open;
 
+        let mut open_window_open = self.open_window.open;
+        egui::Window::new("Open File")
+            .open(&mut open_window_open)
+            .show(ctx, |ui| {
+                self.open_window.ui_content(ui);
+            });
+        self.open_window.open &= open_window_open;
+        self.open_window.update(ctx, &mut self.state);
+
         let mut backend_panel_open = self.backend_panel.open;
+        let memory_stats = self.state.memory_stats();
         egui::Window::new("BackendPanel")
             .open(&mut backend_panel_open)
             .show(ctx, |ui| {
                 self.backend_panel.update(ctx, frame);
                 self.backend_panel.ui(ui, frame);
+                self.backend_panel.memory_ui(ui, &memory_stats);
             });
         self.backend_panel.open = backend_panel_open;
 
+        // copy and paste nodes with the keyboard, unless a text field is being edited. Pasted
+        // text that was not copied from arbor is ignored
+        if !ctx.wants_keyboard_input() {
+            let events = ctx.input().events.clone();
+            for event in events.iter() {
+                match event {
+                    egui::Event::Copy => {
+                        let copied = self
+                            .clipboard
+                            .copy(&self.state.active, &self.painting.selected);
+                        if let Some(text) = copied {
+                            ctx.output().copied_text = text;
+                        }
+                    }
+                    egui::Event::Text(text) => {
+                        if let Some(pasted) = self.clipboard.paste(&mut self.state, text) {
+                            self.painting.selected = pasted.collect();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
         // Draw rest of UI now that project status is sorted out
         //
         egui::TopPanel::top("Menu").show(ctx, |ui| {
@@ -113,8 +174,11 @@ impl epi::App for ArborUi {
                     if ui.button("load").clicked() {
                         self.load_window.open = true;
                     }
+                    if ui.button("open file").clicked() {
+                        self.open_window.open = true;
+                    }
                     if ui.button("save").clicked() {
-                        let res = cmd::Save::new().execute(&mut self.state);
+                        let res = cmd::Save::new(false, false).execute(&mut self.state);
                         match res {
                             Ok(_) => {}
                             Err(e) => println!("{}", e),
@@ -133,22 +197,30 @@ impl epi::App for ArborUi {
 
                 egui::menu::menu(ui, "Edit", |ui| {
                     ui.separator();
+                    // node indices may change when history is undone or redone, so the
+                    // multi-selection is cleared rather than left pointing at other nodes
                     if ui.button("undo").clicked() {
                         let res = cmd::Undo::new().execute(&mut self.state);
                         match res {
-                            Ok(_) => {}
+                            Ok(_) => self.painting.selected.clear(),
                             Err(e) => println!("{}", e),
                         }
                     }
                     if ui.button("redo").clicked() {
                         let res = cmd::Redo::new().execute(&mut self.state);
                         match res {
-                            Ok(_) => {}
+                            Ok(_) => self.painting.selected.clear(),
                             Err(e) => println!("{}", e),
                         }
                     }
                 });
 
+                egui::menu::menu(ui, "View", |ui| {
+                    ui.separator();
+                    ui.checkbox(&mut self.preview.open, "playthrough preview");
+                    ui.checkbox(&mut self.search_panel.open, "search");
+                });
+
                 egui::menu::menu(ui, "Test", |ui| {
                     ui.separator();
                     if ui.button("lorem ipsum").clicked() {
@@ -162,18 +234,34 @@ impl epi::App for ArborUi {
             });
         });
 
+        // effects of the selected edge are previewed with the values of the playthrough preview
+        let simulation = self.preview.runner(&self.state.active);
         egui::Window::new("Editor Tools").show(ctx, |ui| {
-            egui::CollapsingHeader::new("Name Editor").show(ui, |ui| {
-                // left panel for editing tools on selected node
+            egui::CollapsingHeader::new("Inspector").show(ui, |ui| {
                 egui::ScrollArea::auto_sized().show(ui, |ui| {
-                    self.name_editor.ui_content(&mut self.state, ui);
+                    let jump = self.inspector.ui_content(
+                        &self.state.active,
+                        self.active_selection,
+                        simulation.as_ref(),
+                        ui,
+                    );
+                    if let Some(jump) = jump {
+                        self.jump(jump);
+                    }
                 });
             });
 
-            egui::CollapsingHeader::new("Value Editor").show(ui, |ui| {
-                // left panel for editing tools on selected node
+            egui::CollapsingHeader::new("Name Table").show(ui, |ui| {
                 egui::ScrollArea::auto_sized().show(ui, |ui| {
-                    self.value_editor.ui_content(&mut self.state, ui);
+                    self.name_editor
+                        .ui_content(&mut self.state, self.active_selection, ui);
+                });
+            });
+
+            egui::CollapsingHeader::new("Val Table").show(ui, |ui| {
+                egui::ScrollArea::auto_sized().show(ui, |ui| {
+                    self.value_editor
+                        .ui_content(&mut self.state, self.active_selection, ui);
                 });
             });
 
@@ -189,29 +277,133 @@ impl epi::App for ArborUi {
                     self.edge_editor.ui_content(&mut self.state, ui);
                 });
             });
+
+            egui::CollapsingHeader::new("Bookmarks").show(ui, |ui| {
+                egui::ScrollArea::auto_sized().show(ui, |ui| {
+                    let goto =
+                        self.bookmark_editor
+                            .ui_content(&mut self.state, self.active_selection, ui);
+                    if let Some(node_index) = goto {
+                        self.painting.focus = Some(node_index);
+                        self.active_selection = Selection::Node(node_index);
+                    }
+                });
+            });
+
+            egui::CollapsingHeader::new("Selection").show(ui, |ui| {
+                egui::ScrollArea::auto_sized().show(ui, |ui| {
+                    self.selection_editor.ui_content(
+                        &mut self.state,
+                        &mut self.painting.selected,
+                        self.painting.grid_size,
+                        ui,
+                    );
+                });
+            });
+
+            egui::CollapsingHeader::new("Clipboard").show(ui, |ui| {
+                self.clipboard
+                    .ui_content(&self.state.active, &self.painting.selected, ui);
+            });
+
+            egui::CollapsingHeader::new("Groups").show(ui, |ui| {
+                egui::ScrollArea::auto_sized().show(ui, |ui| {
+                    groups_ui(&self.state.active, &mut self.painting, ui);
+                });
+            });
+
+            egui::CollapsingHeader::new("History").show(ui, |ui| {
+                egui::ScrollArea::auto_sized().show(ui, |ui| {
+                    if history_ui(&mut self.state, ui) {
+                        self.painting.selected.clear();
+                    }
+                });
+            });
         });
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading(self.state.active.name.clone());
-            self.painting.ui_control(ui);
-            egui::Frame::dark_canvas(ui.style()).show(ui, |ui| {
-                self.active_selection = self.painting.ui_content(
-                    &mut self.state.active,
-                    &mut self.state.history,
+        // search results are listed above the inspector, so that a clicked result can be
+        // inspected without leaving the search
+        if self.search_panel.open {
+            egui::SidePanel::left("Search", MAX_TEXT_WIDTH).show(ctx, |ui| {
+                let found =
+                    self.search_panel
+                        .ui_content(&mut self.state.active, self.active_selection, ui);
+                ui.separator();
+                let inspected = self.inspector.ui_content(
+                    &self.state.active,
                     self.active_selection,
+                    simulation.as_ref(),
                     ui,
                 );
+                for jump in found.into_iter().chain(inspected) {
+                    self.jump(jump);
+                }
             });
+        }
+
+        self.painting.usages = self.inspector.usages(&self.state.active);
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.heading(self.state.active.name.clone());
+            self.painting.ui_control(ui);
+            if self.preview.open {
+                // split view, tree on the left and a playthrough of the selected node on the right
+                ui.columns(2, |columns| {
+                    egui::Frame::dark_canvas(columns[0].style()).show(&mut columns[0], |ui| {
+                        self.active_selection = self.painting.ui_content(
+                            &mut self.state.active,
+                            &mut self.state.history,
+                            self.active_selection,
+                            ui,
+                        );
+                    });
+                    egui::ScrollArea::auto_sized().show(&mut columns[1], |ui| {
+                        self.preview
+                            .ui_content(&self.state.active, self.active_selection, ui);
+                    });
+                });
+            } else {
+                egui::Frame::dark_canvas(ui.style()).show(ui, |ui| {
+                    self.active_selection = self.painting.ui_content(
+                        &mut self.state.active,
+                        &mut self.state.history,
+                        self.active_selection,
+                        ui,
+                    );
+                });
+            }
         });
     }
 }
 
-/// Window for loading a project
+impl ArborUi {
+    /// Follow a jump requested by the inspector or the search panel
+    fn jump(&mut self, jump: Jump) {
+        match jump {
+            Jump::Key(KeyKind::Name, key) => {
+                self.name_editor.filter_buf = key.to_string();
+            }
+            Jump::Key(KeyKind::Val, key) => {
+                self.value_editor.filter_buf = key.to_string();
+            }
+            Jump::Select(selection, node_index) => {
+                self.active_selection = selection;
+                self.painting.focus = Some(node_index);
+            }
+        }
+    }
+}
+
+/// Window for loading a project. Projects in the store are listed with a thumbnail of their
+/// graph to tell similarly named projects apart, see arbor_core::thumbnail
 #[derive(Serialize, Deserialize)]
 pub struct LoadWindow {
     name_buf: String,
     open: bool,
     was_none: bool,
+    /// Thumbnail textures by project file, None if the thumbnail could not be drawn. Released
+    /// when the window closes, so that projects changed in the meantime are drawn again
+    #[serde(skip)]
+    thumbnails: BTreeMap<String, Option<egui::TextureId>>,
 }
 
 impl Default for LoadWindow {
@@ -220,6 +412,7 @@ impl Default for LoadWindow {
             name_buf: String::with_capacity(MAX_NAME_LEN),
             open: false,
             was_none: false,
+            thumbnails: BTreeMap::new(),
         }
     }
 }
@@ -227,7 +420,12 @@ impl Default for LoadWindow {
 impl LoadWindow {
     /// Content for Load Project window. Returns flag if a new project has been loaded into the
     /// editor state by arbor_core
-    pub fn ui_content(&mut self, state: &mut EditorState, ui: &mut egui::Ui) {
+    pub fn ui_content(
+        &mut self,
+        state: &mut EditorState,
+        ui: &mut egui::Ui,
+        frame: &mut epi::Frame<'_>,
+    ) {
         ui.label("project name");
         ui.add(
             egui::TextEdit::singleline(&mut self.name_buf)
@@ -235,16 +433,61 @@ impl LoadWindow {
                 .desired_width(MAX_NAME_WIDTH),
         );
         ui.separator();
+
+        // projects in the store, filtered by the typed name
+        let files = match state.store.list() {
+            Ok(files) => files,
+            Err(e) => {
+                ui.colored_label(egui::Color32::RED, e.to_string());
+                Vec::new()
+            }
+        };
+        let thumbnail_size = egui::vec2(
+            arbor_core::thumbnail::SIZE as f32,
+            arbor_core::thumbnail::SIZE as f32,
+        );
+        let mut chosen = None;
+        egui::ScrollArea::auto_sized().show(ui, |ui| {
+            for file in files.iter() {
+                let name = match file.strip_suffix(arbor_core::TREE_EXT) {
+                    Some(name) if name.contains(self.name_buf.as_str()) => name,
+                    _ => continue,
+                };
+                let texture = *self
+                    .thumbnails
+                    .entry(file.clone())
+                    .or_insert_with(|| load_thumbnail(state, file, frame));
+                ui.horizontal(|ui| {
+                    match texture {
+                        Some(texture) => {
+                            ui.image(texture, thumbnail_size);
+                        }
+                        None => {
+                            // keep the names lined up when a thumbnail can't be drawn
+                            ui.allocate_exact_size(thumbnail_size, egui::Sense::hover());
+                        }
+                    }
+                    if ui.button(name).clicked() {
+                        chosen = Some(name.to_string());
+                    }
+                });
+            }
+        });
+        ui.separator();
         if ui.button("load project").clicked() {
-            let res = arbor_core::cmd::Load::new(self.name_buf.drain(..).collect()).execute(state);
+            chosen = Some(self.name_buf.drain(..).collect());
+        }
+        if let Some(name) = chosen {
+            self.name_buf.clear();
+            let res = arbor_core::cmd::Load::new(name).execute(state);
             match res {
                 Ok(_) => {
                     // if ok, close the load project window
                     self.open = false;
                     // Check if tree has many default positions, if so, give the user a warning
-                    let zeroed_count = state.active.tree.nodes().iter().fold(0, |sum, n| {
-                        sum + (n.pos.x == 0.0 && n.pos.y == 0.0) as usize
-                    });
+                    let zeroed_count = (0..state.active.tree.nodes().len())
+                        .filter(|&i| state.active.position(i) == arbor_core::Position::default())
+                        .count();
                     // NOTE: Right now show the warning if more than 1 node is at zero, maybe
                     // increase this threshold if using a really large tree?
                     if zeroed_count > 1 {
@@ -269,6 +512,39 @@ impl LoadWindow {
             }
         }
     }
+
+    /// Free the thumbnail textures, so that they are drawn again the next time the window opens
+    pub fn release(&mut self, frame: &mut epi::Frame<'_>) {
+        for texture in std::mem::take(&mut self.thumbnails).values().flatten() {
+            frame.tex_allocator().free(*texture);
+        }
+    }
+}
+
+/// Get the cached thumbnail of a project file and upload it as a texture, drawing the thumbnail
+/// again if the project has changed since it was cached
+fn load_thumbnail(
+    state: &EditorState,
+    file: &str,
+    frame: &mut epi::Frame<'_>,
+) -> Option<egui::TextureId> {
+    let thumbnail = match arbor_core::thumbnail::cached(state.store.as_ref(), file) {
+        Ok(thumbnail) => thumbnail,
+        Err(e) => {
+            println!("{}", e);
+            return None;
+        }
+    };
+    let pixels: Vec<egui::Color32> = thumbnail
+        .pixels
+        .chunks(4)
+        .map(|p| egui::Color32::from_rgba_premultiplied(p[0], p[1], p[2], p[3]))
+        .collect();
+    Some(
+        frame
+            .tex_allocator()
+            .alloc_srgba_premultiplied((thumbnail.width, thumbnail.height), &pixels),
+    )
 }
 
 /// Window for creating a new project
@@ -358,11 +634,250 @@ impl RebuildWindow {
     }
 }
 
-/// Struct for editing names
+/// Opens tree files from anywhere on disk by their path. If the active project has unsaved
+/// changes, the user is asked to confirm before it is replaced
+///
+/// Dropping files onto the window is only supported by arbor_reader. eframe 0.11 does not pass
+/// winit file drop events on to the app, so drag-and-drop is out of scope for this editor until
+/// eframe is upgraded
+#[derive(Serialize, Deserialize, Default)]
+pub struct OpenFileWindow {
+    path_buf: String,
+    open: bool,
+    /// File waiting on confirmation to discard unsaved changes
+    pending: Option<std::path::PathBuf>,
+}
+
+impl OpenFileWindow {
+    /// Request to open a tree file, replacing the active project
+    pub fn request(&mut self, path: std::path::PathBuf) {
+        self.pending = Some(path);
+    }
+
+    /// Content for the Open File window
+    pub fn ui_content(&mut self, ui: &mut egui::Ui) {
+        ui.label("path to .tree file");
+        ui.add(
+            egui::TextEdit::singleline(&mut self.path_buf)
+                .text_style(egui::TextStyle::Monospace)
+                .desired_width(MAX_TEXT_WIDTH),
+        );
+        ui.separator();
+        if ui.button("open file").clicked() {
+            let path = self.path_buf.drain(..).collect::<String>();
+            self.request(path.into());
+            self.open = false;
+        }
+    }
+
+    /// Open any pending file, asking for confirmation first if the active project has unsaved
+    /// changes
+    pub fn update(&mut self, ctx: &egui::CtxRef, state: &mut EditorState) {
+        let path = match self.pending.as_ref() {
+            Some(path) => path.clone(),
+            None => return,
+        };
+
+        let mut confirmed = !state.history.is_modified();
+        if !confirmed {
+            let mut cancelled = false;
+            egui::Window::new("Unsaved Changes")
+                .collapsible(false)
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "The project {} has unsaved changes that will be lost by opening {}",
+                        state.active.name,
+                        path.display()
+                    ));
+                    ui.horizontal(|ui| {
+                        confirmed = ui.button("discard changes and open").clicked();
+                        cancelled = ui.button("cancel").clicked();
+                    });
+                });
+            if cancelled {
+                self.pending = None;
+            }
+        }
+
+        if confirmed {
+            self.pending = None;
+            match cmd::util::open_tree_file(&path) {
+                Ok(opened) => *state = opened,
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+}
+
+/// Collect the keys referenced by the selected node or edge, so table panels can highlight them
+fn selected_keys(
+    data: &DialogueTreeData,
+    active_selection: Selection,
+) -> cmd::util::ElementKeys<'_> {
+    match active_selection {
+        Selection::Node(node_index) => cmd::util::node_keys(data, node_index),
+        Selection::Edge(edge_index) => cmd::util::edge_keys(data, edge_index),
+        Selection::None => Default::default(),
+    }
+}
+
+/// Check if a table entry matches a search filter, ignoring case. An empty filter matches all
+fn matches_filter(filter: &str, key: &str, value: &str) -> bool {
+    let filter = filter.to_lowercase();
+    key.to_lowercase().contains(&filter) || value.to_lowercase().contains(&filter)
+}
+
+/// Draw the key of a table entry, highlighted if it is referenced by the selection
+fn key_label(ui: &mut egui::Ui, key: &str, referenced: bool) {
+    let label = egui::Label::new(key).monospace();
+    if referenced {
+        ui.add(label.strong().text_color(egui::Color32::YELLOW));
+    } else {
+        ui.add(label);
+    }
+}
+
+/// Draw the undo/redo history, oldest change first, with the most recent change selected.
+/// Clicking a change undoes or redoes every change up to and including it, and changes that
+/// haven't been undone can be reverted on their own. Returns true if the history was moved
+fn history_ui(state: &mut EditorState, ui: &mut egui::Ui) -> bool {
+    let mut target = None;
+    let mut revert = None;
+    ui.vertical(|ui| {
+        if state.history.record.is_empty() {
+            ui.label("no changes");
+        }
+        let position = state.history.position;
+        for (i, event) in state.history.record.iter().enumerate() {
+            // text edits are titled here and shown as a word diff below
+            let change = event.text_change(&state.active);
+            let description = match &change {
+                Some(change) => change.title.clone(),
+                None => event.describe(&state.active),
+            };
+            let text = if i < position {
+                description
+            } else {
+                format!("(undone) {}", description)
+            };
+            ui.horizontal(|ui| {
+                if i < position
+                    && ui
+                        .small_button("revert")
+                        .on_hover_text("undo only this change, keeping later changes")
+                        .clicked()
+                {
+                    revert = Some(i + 1);
+                }
+                if ui
+                    .selectable_label(i + 1 == position, text)
+                    .on_hover_text("undo or redo to this change")
+                    .clicked()
+                {
+                    target = Some(i + 1);
+                }
+            });
+            if let Some(change) = change {
+                word_diff_ui(ui, &cmd::util::word_diff(&change.old, &change.new));
+            }
+        }
+    });
+
+    let res = match (target, revert) {
+        (_, Some(change)) => Some(state.history.revert(&mut state.active, change)),
+        (Some(target), None) => Some(cmd::history::Jump::new(target).execute(state).map(|_| ())),
+        (None, None) => None,
+    };
+    match res {
+        Some(Ok(_)) => true,
+        Some(Err(e)) => {
+            println!("{}", e);
+            false
+        }
+        None => false,
+    }
+}
+
+/// Draw a word diff as wrapped text, with removed words struck out in red and added words
+/// underlined in green
+fn word_diff_ui(ui: &mut egui::Ui, diff: &[cmd::util::WordDiff]) {
+    ui.horizontal_wrapped(|ui| {
+        ui.spacing_mut().item_spacing.x = 0.0;
+        for span in diff {
+            let label = match span {
+                cmd::util::WordDiff::Same(text) => egui::Label::new(text),
+                cmd::util::WordDiff::Removed(text) => egui::Label::new(text)
+                    .strikethrough()
+                    .text_color(egui::Color32::RED),
+                cmd::util::WordDiff::Added(text) => egui::Label::new(text)
+                    .underline()
+                    .text_color(egui::Color32::GREEN),
+            };
+            ui.add(label);
+        }
+    });
+}
+
+/// Draw the groups of nodes for the chosen grouping, each with a checkbox to collapse it into a
+/// single node in the graph view. Collapsed groups expand again when clicked in the graph view
+fn groups_ui(data: &DialogueTreeData, painting: &mut TreePainting, ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        ui.label("group by");
+        for by in analysis::GroupBy::ALL.iter() {
+            if ui
+                .selectable_label(painting.group_by == *by, by.to_string())
+                .clicked()
+                && painting.group_by != *by
+            {
+                painting.group_by = *by;
+                painting.collapsed.clear();
+            }
+        }
+    });
+
+    let labels = match analysis::group_labels(data, painting.group_by) {
+        Ok(labels) => labels,
+        Err(e) => {
+            ui.label(e.to_string());
+            return;
+        }
+    };
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for label in labels {
+        *counts.entry(label).or_default() += 1;
+    }
+
+    ui.horizontal(|ui| {
+        if ui.button("collapse all").clicked() {
+            painting.collapsed = counts.keys().cloned().collect();
+        }
+        if ui.button("expand all").clicked() {
+            painting.collapsed.clear();
+        }
+    });
+    for (label, count) in counts {
+        let mut collapsed = painting.collapsed.contains(&label);
+        if ui
+            .checkbox(&mut collapsed, format!("{} ({} nodes)", label, count))
+            .changed()
+        {
+            if collapsed {
+                painting.collapsed.insert(label);
+            } else {
+                painting.collapsed.remove(&label);
+            }
+        }
+    }
+}
+
+/// Panel listing the name table, with inline editing and removal of entries. All changes go
+/// through core commands so they can be undone
 #[derive(Serialize, Deserialize)]
 pub struct NameEditor {
     key_buf: String,
     text_buf: String,
+    filter_buf: String,
+    edit: Option<(KeyString, String)>,
 }
 
 impl Default for NameEditor {
@@ -370,27 +885,95 @@ impl Default for NameEditor {
         Self {
             key_buf: String::with_capacity(MAX_NAME_LEN),
             text_buf: String::with_capacity(MAX_NAME_LEN),
+            filter_buf: String::with_capacity(MAX_NAME_LEN),
+            edit: None,
         }
     }
 }
 
 impl NameEditor {
-    pub fn ui_content(&mut self, state: &mut EditorState, ui: &mut egui::Ui) -> egui::Response {
+    pub fn ui_content(
+        &mut self,
+        state: &mut EditorState,
+        active_selection: Selection,
+        ui: &mut egui::Ui,
+    ) -> egui::Response {
         ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                ui.label("search");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.filter_buf)
+                        .text_style(egui::TextStyle::Monospace)
+                        .desired_width(MAX_NAME_WIDTH),
+                );
+            });
+            ui.separator();
+
+            let referenced = selected_keys(&state.active, active_selection);
+            let mut names: Vec<_> = state
+                .active
+                .name_table
+                .iter()
+                .filter(|(k, v)| matches_filter(&self.filter_buf, k, v))
+                .map(|(k, v)| (*k, *v, referenced.names.contains(k.as_str())))
+                .collect();
+            names.sort_unstable_by_key(|entry| entry.0);
+
+            for (key, name, is_referenced) in names {
+                ui.horizontal(|ui| {
+                    key_label(ui, key.as_str(), is_referenced);
+                    match &mut self.edit {
+                        Some((edit_key, buf)) if *edit_key == key => {
+                            ui.add(
+                                egui::TextEdit::singleline(buf)
+                                    .text_style(egui::TextStyle::Monospace)
+                                    .desired_width(MAX_NAME_WIDTH),
+                            );
+                            if ui.small_button("ok").clicked() {
+                                // FIXME: proper error handling for if namestring is too long
+                                let res = cmd::edit::Name::new(
+                                    key,
+                                    NameString::from(buf.as_str()).unwrap_or_default(),
+                                )
+                                .execute(state);
+                                match res {
+                                    Ok(_) => self.edit = None,
+                                    Err(e) => println!("{}", e),
+                                }
+                            }
+                            if ui.small_button("cancel").clicked() {
+                                self.edit = None;
+                            }
+                        }
+                        _ => {
+                            ui.label(name.as_str());
+                            if ui.small_button("edit").clicked() {
+                                self.edit = Some((key, name.to_string()));
+                            }
+                            if ui.small_button("x").clicked() {
+                                let res = cmd::remove::Name::new(key).execute(state);
+                                if let Err(e) = res {
+                                    println!("{}", e);
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+            ui.separator();
+
             ui.label("key");
             ui.add(
                 egui::TextEdit::singleline(&mut self.key_buf)
                     .text_style(egui::TextStyle::Monospace)
                     .desired_width(MAX_NAME_WIDTH),
             );
-            ui.separator();
             ui.label("name");
             ui.add(
                 egui::TextEdit::singleline(&mut self.text_buf)
                     .text_style(egui::TextStyle::Monospace)
                     .desired_width(MAX_NAME_WIDTH),
             );
-            ui.separator();
 
             if ui.button("new name").clicked() {
                 let res = cmd::new::Name::new(
@@ -413,10 +996,14 @@ impl NameEditor {
     }
 }
 
+/// Panel listing the val table, with inline editing and removal of entries. All changes go
+/// through core commands so they can be undone
 #[derive(Serialize, Deserialize)]
 pub struct ValueEditor {
     key_buf: String,
     value: u32,
+    filter_buf: String,
+    edit: Option<(KeyString, u32)>,
 }
 
 impl Default for ValueEditor {
@@ -424,23 +1011,82 @@ impl Default for ValueEditor {
         Self {
             key_buf: String::with_capacity(MAX_NAME_LEN),
             value: 0,
+            filter_buf: String::with_capacity(MAX_NAME_LEN),
+            edit: None,
         }
     }
 }
 
 impl ValueEditor {
-    pub fn ui_content(&mut self, state: &mut EditorState, ui: &mut egui::Ui) -> egui::Response {
+    pub fn ui_content(
+        &mut self,
+        state: &mut EditorState,
+        active_selection: Selection,
+        ui: &mut egui::Ui,
+    ) -> egui::Response {
         ui.vertical(|ui| {
+            ui.horizontal(|ui| {
+                ui.label("search");
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.filter_buf)
+                        .text_style(egui::TextStyle::Monospace)
+                        .desired_width(MAX_NAME_WIDTH),
+                );
+            });
+            ui.separator();
+
+            let referenced = selected_keys(&state.active, active_selection);
+            let mut vals: Vec<_> = state
+                .active
+                .val_table
+                .iter()
+                .filter(|(k, v)| matches_filter(&self.filter_buf, k, &v.to_string()))
+                .map(|(k, v)| (*k, *v, referenced.vals.contains(k.as_str())))
+                .collect();
+            vals.sort_unstable_by_key(|entry| entry.0);
+
+            for (key, value, is_referenced) in vals {
+                ui.horizontal(|ui| {
+                    key_label(ui, key.as_str(), is_referenced);
+                    match &mut self.edit {
+                        Some((edit_key, buf)) if *edit_key == key => {
+                            ui.add(egui::DragValue::new(buf));
+                            if ui.small_button("ok").clicked() {
+                                let res = cmd::edit::Val::new(key, *buf).execute(state);
+                                match res {
+                                    Ok(_) => self.edit = None,
+                                    Err(e) => println!("{}", e),
+                                }
+                            }
+                            if ui.small_button("cancel").clicked() {
+                                self.edit = None;
+                            }
+                        }
+                        _ => {
+                            ui.label(value.to_string());
+                            if ui.small_button("edit").clicked() {
+                                self.edit = Some((key, value));
+                            }
+                            if ui.small_button("x").clicked() {
+                                let res = cmd::remove::Val::new(key).execute(state);
+                                if let Err(e) = res {
+                                    println!("{}", e);
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+            ui.separator();
+
             ui.label("key");
             ui.add(
                 egui::TextEdit::singleline(&mut self.key_buf)
                     .text_style(egui::TextStyle::Monospace)
                     .desired_width(MAX_NAME_WIDTH),
             );
-            ui.separator();
             ui.label("initial value");
             ui.add(egui::DragValue::new(&mut self.value));
-            ui.separator();
 
             if ui.button("new value").clicked() {
                 let res = cmd::new::Val::new(
@@ -457,123 +1103,1010 @@ impl ValueEditor {
                     Err(e) => println!("{}", e),
                 }
             }
-        })
-        .response
+        })
+        .response
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NodeEditor {
+    name_buf: String,
+    text_buf: String,
+}
+
+impl Default for NodeEditor {
+    fn default() -> Self {
+        Self {
+            name_buf: String::with_capacity(MAX_NAME_LEN),
+            text_buf: String::with_capacity(MAX_TEXT_LEN),
+        }
+    }
+}
+
+impl NodeEditor {
+    pub fn ui_content(&mut self, state: &mut EditorState, ui: &mut egui::Ui) -> egui::Response {
+        ui.vertical(|ui| {
+            ui.label("name");
+            egui::ComboBox::from_label(
+                // display the selected key's name value
+                cmd::util::speaker_name(&self.name_buf, &state.active.name_table)
+                    .unwrap_or_default()
+                    .to_string(),
+            )
+            .selected_text(self.name_buf.clone())
+            .show_ui(ui, |ui| {
+                // Name must be in key form when selecting,
+                for name in state.active.name_table.keys() {
+                    ui.selectable_value(&mut self.name_buf, name.to_string(), name.as_str());
+                }
+                // narrator is always available, even if not in the name table
+                if !state.active.name_table.contains_key(NARRATOR_KEY) {
+                    ui.selectable_value(&mut self.name_buf, NARRATOR_KEY.to_string(), "narrator");
+                }
+            });
+            ui.separator();
+            ui.label("text");
+            ui.add(
+                egui::TextEdit::multiline(&mut self.text_buf)
+                    .text_style(egui::TextStyle::Monospace)
+                    .desired_width(f32::INFINITY)
+                    .desired_rows(10),
+            );
+            ui.separator();
+
+            if ui.button("new node").clicked() {
+                let res = cmd::new::Node::new(
+                    self.name_buf.drain(..).collect(),
+                    self.text_buf.drain(..).collect(),
+                )
+                .execute(state);
+                match res {
+                    Ok(node_index) => {
+                        let pos = Some(arbor_core::Position::new(0.3, 0.3));
+                        if let Ok(event) = state.active.set_position(node_index, pos) {
+                            state.history.push(event.into(), &mut state.active);
+                        }
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+        })
+        .response
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EdgeEditor {
+    source_node: usize,
+    target_node: usize,
+    text_buf: String,
+    requirement: KindBuilder,
+    effect: KindBuilder,
+}
+
+impl Default for EdgeEditor {
+    fn default() -> Self {
+        Self {
+            source_node: 0,
+            target_node: 0,
+            text_buf: String::with_capacity(MAX_TEXT_LEN),
+            requirement: Default::default(),
+            effect: Default::default(),
+        }
+    }
+}
+
+impl EdgeEditor {
+    pub fn ui_content(&mut self, state: &mut EditorState, ui: &mut egui::Ui) -> egui::Response {
+        ui.vertical(|ui| {
+            ui.label("source node");
+            ui.add(egui::DragValue::new(&mut self.source_node));
+
+            ui.label("target Node");
+            ui.add(egui::DragValue::new(&mut self.target_node));
+
+            ui.separator();
+            ui.add(
+                egui::TextEdit::multiline(&mut self.text_buf)
+                    .text_style(egui::TextStyle::Monospace)
+                    .desired_width(f32::INFINITY)
+                    .desired_rows(10),
+            );
+            ui.separator();
+
+            self.requirement.ui_content(
+                "requirement",
+                &ReqKind::KINDS,
+                ReqKind::uses_name,
+                state,
+                ui,
+            );
+            ui.separator();
+            self.effect.ui_content(
+                "effect",
+                &EffectKind::KINDS,
+                EffectKind::uses_name,
+                state,
+                ui,
+            );
+            ui.separator();
+
+            if ui.button("new edge").clicked() {
+                let requirement = self.requirement.build().parse::<ReqKind>();
+                let effect = self.effect.build().parse::<EffectKind>();
+                let res = match (requirement, effect) {
+                    (Ok(requirement), Ok(effect)) => cmd::new::Edge::new(
+                        self.source_node,
+                        self.target_node,
+                        self.text_buf.drain(..).collect(),
+                        Some(requirement),
+                        Some(effect),
+                    )
+                    .execute(state),
+                    (Err(e), _) | (_, Err(e)) => Err(e),
+                };
+                match res {
+                    Ok(_) => {
+                        self.requirement = Default::default();
+                        self.effect = Default::default();
+                        println!("successfully added edge")
+                    }
+                    Err(e) => println!("{}", e),
+                }
+            }
+        })
+        .response
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BookmarkEditor {
+    key_buf: String,
+}
+
+impl Default for BookmarkEditor {
+    fn default() -> Self {
+        Self {
+            key_buf: String::with_capacity(MAX_NAME_LEN),
+        }
+    }
+}
+
+impl BookmarkEditor {
+    /// Draw the bookmark list and controls, return the node index of a bookmark to go to if one
+    /// was clicked
+    pub fn ui_content(
+        &mut self,
+        state: &mut EditorState,
+        active_selection: Selection,
+        ui: &mut egui::Ui,
+    ) -> Option<tree::NodeIndex> {
+        let mut goto = None;
+        ui.vertical(|ui| {
+            let mut bookmarks: Vec<_> = state.active.bookmarks.keys().copied().collect();
+            bookmarks.sort_unstable();
+            for key in bookmarks {
+                ui.horizontal(|ui| {
+                    if ui.button(key.as_str()).clicked() {
+                        match cmd::bookmark::Goto::new(key).execute(state) {
+                            Ok(node_index) => goto = Some(node_index),
+                            Err(e) => println!("{}", e),
+                        }
+                    }
+                    if ui.small_button("x").clicked() {
+                        let res = cmd::bookmark::Remove::new(key).execute(state);
+                        if let Err(e) = res {
+                            println!("{}", e);
+                        }
+                    }
+                });
+            }
+            ui.separator();
+
+            ui.label("key");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.key_buf)
+                    .text_style(egui::TextStyle::Monospace)
+                    .desired_width(MAX_NAME_WIDTH),
+            );
+            if let Selection::Node(node_index) = active_selection {
+                if ui.button("bookmark selected node").clicked() {
+                    match KeyString::from(self.key_buf.as_str()) {
+                        Ok(key) if key.is_empty() => println!("bookmark key is empty"),
+                        Ok(key) => {
+                            let res = cmd::bookmark::Add::new(key, node_index).execute(state);
+                            match res {
+                                Ok(_) => self.key_buf.clear(),
+                                Err(e) => println!("{}", e),
+                            }
+                        }
+                        Err(e) => println!("{}", e),
+                    }
+                }
+            }
+        });
+        goto
+    }
+}
+
+/// Copy and paste of nodes between arbor windows through the system clipboard, see
+/// arbor_core::clipboard. Ctrl-C copies the nodes selected in the graph, and ctrl-V pastes copied
+/// nodes after the existing ones, renaming keys with the key map first
+#[derive(Serialize, Deserialize, Default)]
+pub struct ClipboardEditor {
+    remap_buf: String,
+    /// result of the last copy or paste
+    #[serde(skip)]
+    report: String,
+}
+
+impl ClipboardEditor {
+    /// Clipboard text for the selected nodes, None if nothing is selected or copying failed
+    pub fn copy(
+        &mut self,
+        data: &DialogueTreeData,
+        selected: &BTreeSet<tree::NodeIndex>,
+    ) -> Option<String> {
+        if selected.is_empty() {
+            return None;
+        }
+        let nodes: Vec<tree::NodeIndex> = selected.iter().copied().collect();
+        match clipboard::copy(data, &nodes).and_then(|payload| payload.to_json()) {
+            Ok(text) => {
+                self.report = format!("copied {} nodes", nodes.len());
+                Some(text)
+            }
+            Err(e) => {
+                self.report = e.to_string();
+                None
+            }
+        }
+    }
+
+    /// Paste nodes from clipboard text as a single change in the history, and return the indices
+    /// of the pasted nodes. None if the text was not copied from arbor or pasting failed
+    pub fn paste(
+        &mut self,
+        state: &mut EditorState,
+        text: &str,
+    ) -> Option<std::ops::Range<tree::NodeIndex>> {
+        let payload = clipboard::Payload::parse(text).ok()?;
+        let res = clipboard::parse_remap(&self.remap_buf)
+            .and_then(|remap| clipboard::paste(&mut state.active, &payload, &remap));
+        match res {
+            Ok(mut pasted) => {
+                self.report = pasted.to_string();
+                let events = std::mem::take(&mut pasted.events);
+                state
+                    .history
+                    .push(EventGroup { events }.into(), &mut state.active);
+                Some(pasted.first..pasted.first + pasted.nodes)
+            }
+            Err(e) => {
+                self.report = e.to_string();
+                None
+            }
+        }
+    }
+
+    pub fn ui_content(
+        &mut self,
+        data: &DialogueTreeData,
+        selected: &BTreeSet<tree::NodeIndex>,
+        ui: &mut egui::Ui,
+    ) {
+        ui.label(
+            "ctrl-C copies the selected nodes, ctrl-V pastes nodes copied in any arbor window",
+        );
+        ui.horizontal(|ui| {
+            ui.label("key map");
+            ui.add(egui::TextEdit::singleline(&mut self.remap_buf).desired_width(MAX_NAME_WIDTH))
+                .on_hover_text("rename copied keys when pasting, e.g. cat=kot,gold=coins");
+        });
+        let copy = egui::Button::new("copy selected nodes").enabled(!selected.is_empty());
+        if ui.add(copy).clicked() {
+            if let Some(text) = self.copy(data, selected) {
+                ui.output().copied_text = text;
+            }
+        }
+        if !self.report.is_empty() {
+            ui.add(egui::Label::new(&self.report).wrap(true));
+        }
+    }
+}
+
+/// Live preview of a playthrough, starting from the selected node. The playthrough is re-run from
+/// the start node with the choices made so far on every frame, so edits to the tree show up
+/// immediately
+#[derive(Serialize, Deserialize, Default)]
+pub struct PlaythroughPreview {
+    pub open: bool,
+    start: Option<tree::NodeIndex>,
+    path: Vec<tree::EdgeIndex>,
+    /// Preset the playthrough starts from, None to start from the project's values
+    #[serde(default)]
+    preset: Option<String>,
+    /// Names entered for the prompts of the playthrough so far, in order
+    #[serde(default)]
+    answers: Vec<String>,
+    #[serde(default)]
+    answer_buf: String,
+}
+
+impl PlaythroughPreview {
+    /// The runner at the current point of the playthrough, None if the preview is closed or has
+    /// no start node
+    pub fn runner(&self, data: &DialogueTreeData) -> Option<runner::Runner> {
+        match (self.open, self.start) {
+            (true, Some(start)) => runner::Runner::replay_with(
+                data,
+                start,
+                &self.assumptions(data),
+                &self.path,
+                &self.answers,
+            )
+            .ok(),
+            _ => None,
+        }
+    }
+
+    /// Starting values of the selected preset. A preset that was removed starts from the
+    /// project's values
+    fn assumptions(&self, data: &DialogueTreeData) -> runner::Assumptions {
+        self.preset
+            .as_ref()
+            .and_then(|preset| data.presets.get(preset))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn ui_content(
+        &mut self,
+        data: &DialogueTreeData,
+        active_selection: Selection,
+        ui: &mut egui::Ui,
+    ) {
+        // restart the playthrough whenever a different node is selected
+        if let Selection::Node(node_index) = active_selection {
+            if self.start != Some(node_index) {
+                self.start = Some(node_index);
+                self.path.clear();
+                self.answers.clear();
+            }
+        }
+
+        ui.heading("Playthrough");
+        if !data.presets.is_empty() {
+            let before = self.preset.clone();
+            egui::ComboBox::from_label("starting values")
+                .selected_text(self.preset.as_deref().unwrap_or("project values"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.preset, None, "project values");
+                    for name in data.presets.keys() {
+                        ui.selectable_value(&mut self.preset, Some(name.clone()), name.as_str());
+                    }
+                });
+            // the choices made so far may not be available from other starting values
+            if self.preset != before {
+                self.path.clear();
+                self.answers.clear();
+            }
+        }
+        let start = match self.start {
+            Some(start) => start,
+            None => {
+                ui.label("select a node to start a playthrough");
+                return;
+            }
+        };
+        let assumptions = self.assumptions(data);
+        let runner =
+            match runner::Runner::replay_with(data, start, &assumptions, &self.path, &self.answers)
+            {
+                Ok(runner) => runner,
+                Err(e) => {
+                    println!("{}", e);
+                    self.start = None;
+                    self.path.clear();
+                    self.answers.clear();
+                    return;
+                }
+            };
+        // drop any choices that are no longer available after an edit
+        self.path.truncate(runner.path.len());
+
+        match runner.dialogue(data) {
+            Ok((speaker, text)) if speaker.is_empty() => {
+                ui.add(egui::Label::new(text).italics().wrap(true));
+            }
+            Ok((speaker, text)) => {
+                ui.add(egui::Label::new(speaker).strong());
+                ui.add(egui::Label::new(text).wrap(true));
+            }
+            Err(e) => {
+                ui.colored_label(egui::Color32::RED, e.to_string());
+            }
+        }
+        ui.separator();
+
+        // the player answers a prompt before the next choices are shown
+        if let Some(key) = runner.prompt {
+            ui.label(format!("enter {}", key));
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.answer_buf).desired_width(MAX_NAME_WIDTH),
+                );
+                let valid = !self.answer_buf.trim().is_empty()
+                    && NameString::from(self.answer_buf.trim()).is_ok();
+                if ui.add(egui::Button::new("ok").enabled(valid)).clicked() {
+                    self.answers.push(self.answer_buf.trim().to_string());
+                    self.answer_buf.clear();
+                }
+            });
+        }
+
+        let mut chosen = None;
+        // choices with unmet requirements are shown disabled unless the choice policy hides them
+        match runner.shown_choices(data) {
+            _ if runner.prompt.is_some() => {}
+            Ok(choices) if choices.is_empty() => {
+                ui.label("(end)");
+            }
+            Ok(choices) => {
+                for (i, choice) in choices.iter().enumerate() {
+                    let button = egui::Button::new(format!("{}. {}", i + 1, choice.text))
+                        .enabled(choice.met);
+                    let mut response = ui.add(button);
+                    if !choice.met {
+                        response = response.on_hover_text(runner::describe_requirement(
+                            &choice.requirement,
+                            &runner.names,
+                            &runner.vals,
+                        ));
+                    }
+                    if response.clicked() {
+                        chosen = Some(choice.index);
+                    }
+                }
+            }
+            Err(e) => {
+                ui.colored_label(egui::Color32::RED, e.to_string());
+            }
+        }
+        if let Some(edge_index) = chosen {
+            self.path.push(edge_index);
+        }
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            if ui.button("back").clicked() {
+                let answered = runner.prompt.is_none();
+                let popped = self
+                    .path
+                    .pop()
+                    .and_then(|edge| data.tree.get_edge(edge).ok());
+                // the answer to the prompt of the choice taken back, or of the node it led to, is
+                // dropped with it
+                let is_prompt = |effect: &EffectKind| matches!(effect, EffectKind::Prompt(_));
+                let prompted = popped.map_or(false, |edge| {
+                    is_prompt(&edge.effect)
+                        || cmd::util::on_enter_of(data, runner.node)
+                            .map_or(false, |effects| effects.iter().any(is_prompt))
+                });
+                if answered && prompted {
+                    self.answers.pop();
+                }
+            }
+            if ui.button("restart").clicked() {
+                self.path.clear();
+                self.answers.clear();
+            }
+        });
+        egui::CollapsingHeader::new("values").show(ui, |ui| {
+            let mut vals: Vec<_> = runner.vals.iter().collect();
+            vals.sort_unstable();
+            for (key, val) in vals {
+                ui.label(format!("{}: {}", key, val));
+            }
+        });
+    }
+}
+
+/// Sidebar searching the text of every node and edge as the query is typed, see
+/// cmd::util::search. Each match is listed with the text around it, and clicking a match selects
+/// it and centers the view on it
+#[derive(Serialize, Deserialize)]
+pub struct SearchPanel {
+    pub open: bool,
+    query_buf: String,
+    ignore_case: bool,
+}
+
+impl Default for SearchPanel {
+    fn default() -> Self {
+        Self {
+            open: false,
+            query_buf: String::with_capacity(MAX_NAME_LEN),
+            ignore_case: true,
+        }
+    }
+}
+
+impl SearchPanel {
+    pub fn ui_content(
+        &mut self,
+        data: &mut DialogueTreeData,
+        active_selection: Selection,
+        ui: &mut egui::Ui,
+    ) -> Option<Jump> {
+        ui.horizontal(|ui| {
+            ui.label("find");
+            ui.text_edit_singleline(&mut self.query_buf);
+            ui.checkbox(&mut self.ignore_case, "ignore case");
+        });
+        if self.query_buf.is_empty() {
+            ui.label("type to search dialogue and choices");
+            return None;
+        }
+
+        // the search index is only rebuilt when the text changes, so searching every frame
+        // stays fast
+        let found = match cmd::util::search(data, &self.query_buf, self.ignore_case) {
+            Ok(found) => found,
+            Err(e) => {
+                ui.colored_label(egui::Color32::RED, e.to_string());
+                return None;
+            }
+        };
+        ui.label(format!("{} matches", found.len()));
+
+        let mut jump = None;
+        egui::ScrollArea::from_max_height(MAX_TEXT_WIDTH).show(ui, |ui| {
+            for reference in found.iter().take(MAX_SEARCH_RESULTS) {
+                let (section, selection, node_index) = match *reference {
+                    Reference::NodeText(i) => match data.tree.get_node(i) {
+                        Ok(node) => (node.section, Selection::Node(i), i),
+                        Err(_) => continue,
+                    },
+                    Reference::EdgeText(i) => {
+                        match (data.tree.get_edge(i), data.tree.source_of(i)) {
+                            (Ok(edge), Ok(source)) => (edge.section, Selection::Edge(i), source),
+                            _ => continue,
+                        }
+                    }
+                    _ => continue,
+                };
+                let text = data.text.get(section[0]..section[1]).unwrap_or_default();
+                let context = match cmd::util::find_prose(text, &self.query_buf, self.ignore_case) {
+                    Some(at) => {
+                        cmd::util::excerpt(text, at..at + self.query_buf.len(), MAX_NAME_LEN * 2)
+                    }
+                    None => continue,
+                };
+                let selected = match (active_selection, selection) {
+                    (Selection::Node(a), Selection::Node(b)) => a == b,
+                    (Selection::Edge(a), Selection::Edge(b)) => a == b,
+                    _ => false,
+                };
+                if ui
+                    .selectable_label(selected, format!("{}: {}", reference, context))
+                    .clicked()
+                {
+                    jump = Some(Jump::Select(selection, node_index));
+                }
+            }
+            if found.len() > MAX_SEARCH_RESULTS {
+                ui.label(format!("and {} more", found.len() - MAX_SEARCH_RESULTS));
+            }
+        });
+        jump
+    }
+}
+
+/// Where the inspector or the search panel asks the rest of the UI to jump to
+pub enum Jump {
+    /// Show the table entry of a key
+    Key(KeyKind, KeyString),
+    /// Select a node or edge, and center the view on the given node
+    Select(Selection, tree::NodeIndex),
+}
+
+/// Panel describing the selected node or edge. Keys referenced by the selection can be clicked to
+/// jump to their table entry and highlight every other place they are used
+#[derive(Serialize, Deserialize, Default)]
+pub struct Inspector {
+    /// key whose usages are listed and highlighted
+    #[serde(skip)]
+    focus: Option<(KeyKind, KeyString)>,
+}
+
+impl Inspector {
+    /// Every reference to the focused key, empty if no key is focused
+    pub fn usages(&self, data: &DialogueTreeData) -> Vec<Reference> {
+        match &self.focus {
+            Some((kind, key)) => cmd::util::key_references(data, *kind, key.as_str()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Draw the description of the selection. Effects are previewed with the names and values of
+    /// the simulation if one is given, and with the starting values of the project otherwise
+    pub fn ui_content(
+        &mut self,
+        data: &DialogueTreeData,
+        active_selection: Selection,
+        simulation: Option<&runner::Runner>,
+        ui: &mut egui::Ui,
+    ) -> Option<Jump> {
+        let mut picked = None;
+        ui.vertical(|ui| match active_selection {
+            Selection::Node(node_index) => {
+                if data.tree.get_node(node_index).is_err() {
+                    return;
+                }
+                ui.label(format!("node {}", node_index));
+                text_keys_ui(ui, cmd::util::node_keys(data, node_index), &mut picked);
+            }
+            Selection::Edge(edge_index) => {
+                let edge = match data.tree.get_edge(edge_index) {
+                    Ok(edge) => edge,
+                    Err(_) => return,
+                };
+                ui.label(format!("edge {}", edge_index));
+                ui.horizontal(|ui| {
+                    ui.label(format!("requirement: {}", edge.requirement));
+                    if let Some((kind, key)) = cmd::util::requirement_key(&edge.requirement) {
+                        key_button(ui, kind, key.as_str(), &mut picked);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label(format!("effect: {}", edge.effect));
+                    if let Some((kind, key)) = cmd::util::effect_key(&edge.effect) {
+                        key_button(ui, kind, key.as_str(), &mut picked);
+                    }
+                });
+                effect_preview_ui(ui, data, &edge.effect, simulation);
+                text_keys_ui(ui, cmd::util::edge_keys(data, edge_index), &mut picked);
+            }
+            Selection::None => {
+                ui.label("select a node or edge to inspect");
+            }
+        });
+
+        let mut jump = None;
+        if let Some((kind, key)) = picked {
+            jump = Some(Jump::Key(kind, key));
+            self.focus = Some((kind, key));
+        }
+
+        let (kind, key) = match self.focus {
+            Some(focus) => focus,
+            None => return jump,
+        };
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label(format!("usages of {}", key));
+            if ui.small_button("clear").clicked() {
+                self.focus = None;
+            }
+        });
+        let definition = match kind {
+            KeyKind::Name => data.name_table.get(&key).map(|name| name.to_string()),
+            KeyKind::Val => data.val_table.get(&key).map(|val| val.to_string()),
+        };
+        match definition {
+            Some(definition) => {
+                ui.label(format!("defined as {}", definition));
+            }
+            None => {
+                ui.colored_label(egui::Color32::RED, "not in the table");
+            }
+        }
+        for reference in self.usages(data) {
+            let target = match (reference.node(), reference.edge()) {
+                (Some(node_index), _) => Some((Selection::Node(node_index), node_index)),
+                (_, Some(edge_index)) => data
+                    .tree
+                    .source_of(edge_index)
+                    .ok()
+                    .map(|source| (Selection::Edge(edge_index), source)),
+                _ => None,
+            };
+            let clicked = ui.selectable_label(false, reference.to_string()).clicked();
+            if let (true, Some((selection, node_index))) = (clicked, target) {
+                jump = Some(Jump::Select(selection, node_index));
+            }
+        }
+        jump
+    }
+}
+
+/// Draw the value changed by an effect before and after the effect is applied
+fn effect_preview_ui(
+    ui: &mut egui::Ui,
+    data: &DialogueTreeData,
+    effect: &EffectKind,
+    simulation: Option<&runner::Runner>,
+) {
+    let (names, vals, source) = match simulation {
+        Some(runner) => (&runner.names, &runner.vals, "in playthrough"),
+        None => (&data.name_table, &data.val_table, "from start"),
+    };
+    let preview = match runner::preview_effect(effect, names, vals) {
+        Some(preview) => preview,
+        None => return,
+    };
+    let before = preview.before.as_deref().unwrap_or("missing");
+    let text = format!("{} {}: {} → {}", source, preview.key, before, preview.after);
+    if before == preview.after {
+        ui.label(text)
+            .on_hover_text("the effect doesn't change the value");
+    } else {
+        ui.colored_label(egui::Color32::LIGHT_BLUE, text);
+    }
+}
+
+/// Draw a clickable key, recording it as picked when clicked
+fn key_button(
+    ui: &mut egui::Ui,
+    kind: KeyKind,
+    key: &str,
+    picked: &mut Option<(KeyKind, KeyString)>,
+) {
+    if ui.small_button(key).on_hover_text("show usages").clicked() {
+        if let Ok(key) = KeyString::from(key) {
+            *picked = Some((kind, key));
+        }
     }
 }
 
+/// Draw the name keys used by the speaker or text of a node or edge as clickable keys
+fn text_keys_ui(
+    ui: &mut egui::Ui,
+    keys: cmd::util::ElementKeys<'_>,
+    picked: &mut Option<(KeyKind, KeyString)>,
+) {
+    let mut names: Vec<&str> = keys.names.into_iter().collect();
+    if names.is_empty() {
+        return;
+    }
+    names.sort_unstable();
+    ui.horizontal(|ui| {
+        ui.label("names");
+        for key in names {
+            key_button(ui, KeyKind::Name, key, picked);
+        }
+    });
+}
+
+/// Step by step builder for requirements and effects, so that the user picks the kind and key
+/// from lists rather than typing the full requirement/effect syntax
 #[derive(Serialize, Deserialize)]
-pub struct NodeEditor {
-    name_buf: String,
-    text_buf: String,
+pub struct KindBuilder {
+    kind: String,
+    key_buf: String,
+    value_buf: String,
 }
 
-impl Default for NodeEditor {
+impl Default for KindBuilder {
     fn default() -> Self {
         Self {
-            name_buf: String::with_capacity(MAX_NAME_LEN),
-            text_buf: String::with_capacity(MAX_TEXT_LEN),
+            kind: String::from("No"),
+            key_buf: String::with_capacity(MAX_NAME_LEN),
+            value_buf: String::with_capacity(MAX_NAME_LEN),
         }
     }
 }
 
-impl NodeEditor {
-    pub fn ui_content(&mut self, state: &mut EditorState, ui: &mut egui::Ui) -> egui::Response {
-        ui.vertical(|ui| {
-            ui.label("name");
-            egui::ComboBox::from_label(
-                state // display the selected key's name value
-                    .active
-                    .name_table
-                    .get(self.name_buf.as_str())
-                    .unwrap_or(&arbor_core::NameString::new())
-                    .to_string(),
-            )
-            .selected_text(self.name_buf.clone())
+impl KindBuilder {
+    pub fn ui_content(
+        &mut self,
+        label: &str,
+        kinds: &[&str],
+        uses_name: fn(&str) -> bool,
+        state: &EditorState,
+        ui: &mut egui::Ui,
+    ) {
+        egui::ComboBox::from_label(label)
+            .selected_text(self.kind.clone())
             .show_ui(ui, |ui| {
-                // Name must be in key form when selecting,
-                for name in state.active.name_table.keys() {
-                    ui.selectable_value(&mut self.name_buf, name.to_string(), name.as_str());
+                for kind in kinds.iter() {
+                    ui.selectable_value(&mut self.kind, kind.to_string(), *kind);
                 }
             });
-            ui.separator();
-            ui.label("text");
-            ui.add(
-                egui::TextEdit::multiline(&mut self.text_buf)
-                    .text_style(egui::TextStyle::Monospace)
-                    .desired_width(f32::INFINITY)
-                    .desired_rows(10),
-            );
-            ui.separator();
+        if self.kind == "No" {
+            return;
+        }
 
-            if ui.button("new node").clicked() {
-                let res = cmd::new::Node::new(
-                    self.name_buf.drain(..).collect(),
-                    self.text_buf.drain(..).collect(),
-                )
-                .execute(state);
-                match res {
-                    Ok(node_index) => {
-                        state.active.tree.nodes_mut()[node_index].pos =
-                            arbor_core::Position::new(0.3, 0.3)
-                    }
-                    Err(e) => println!("{}", e),
+        // keys are chosen from the table that matches the kind of requirement/effect
+        egui::ComboBox::from_label(format!("{} key", label))
+            .selected_text(self.key_buf.clone())
+            .show_ui(ui, |ui| {
+                let mut keys: Vec<&KeyString> = if uses_name(&self.kind) {
+                    state.active.name_table.keys().collect()
+                } else {
+                    state.active.val_table.keys().collect()
+                };
+                keys.sort_unstable();
+                for key in keys {
+                    ui.selectable_value(&mut self.key_buf, key.to_string(), key.as_str());
                 }
-            }
-        })
-        .response
+            });
+        if !EffectKind::takes_value(&self.kind) {
+            return;
+        }
+        ui.label(if uses_name(&self.kind) {
+            "name"
+        } else {
+            "value"
+        });
+        ui.add(
+            egui::TextEdit::singleline(&mut self.value_buf)
+                .text_style(egui::TextStyle::Monospace)
+                .desired_width(MAX_NAME_WIDTH),
+        );
+    }
+
+    /// Get the requirement/effect in the format accepted by from_str
+    pub fn build(&self) -> String {
+        if self.kind == "No" {
+            self.kind.clone()
+        } else if !EffectKind::takes_value(&self.kind) {
+            format!("{}({})", self.kind, self.key_buf)
+        } else {
+            format!("{}({},{})", self.kind, self.key_buf, self.value_buf)
+        }
     }
 }
 
+/// Bulk actions on the nodes selected in the graph view, or by a query. Every action goes through
+/// a core command, so each one is a single change in the undo/redo history
 #[derive(Serialize, Deserialize)]
-pub struct EdgeEditor {
-    source_node: usize,
-    target_node: usize,
-    text_buf: String,
+pub struct SelectionEditor {
+    #[serde(default)]
+    query_buf: String,
+    flags_buf: String,
+    dx: f32,
+    dy: f32,
+    export_name_buf: String,
 }
 
-impl Default for EdgeEditor {
+impl Default for SelectionEditor {
     fn default() -> Self {
         Self {
-            source_node: 0,
-            target_node: 0,
-            text_buf: String::with_capacity(MAX_TEXT_LEN),
+            query_buf: String::new(),
+            flags_buf: String::with_capacity(MAX_NAME_LEN),
+            dx: 0.0,
+            dy: 0.0,
+            export_name_buf: String::with_capacity(MAX_NAME_LEN),
         }
     }
 }
 
-impl EdgeEditor {
-    pub fn ui_content(&mut self, state: &mut EditorState, ui: &mut egui::Ui) -> egui::Response {
+impl SelectionEditor {
+    pub fn ui_content(
+        &mut self,
+        state: &mut EditorState,
+        selected: &mut BTreeSet<tree::NodeIndex>,
+        grid_size: f32,
+        ui: &mut egui::Ui,
+    ) -> egui::Response {
         ui.vertical(|ui| {
-            ui.label("source node");
-            ui.add(egui::DragValue::new(&mut self.source_node));
-
-            ui.label("target Node");
-            ui.add(egui::DragValue::new(&mut self.target_node));
-
+            ui.label(format!(
+                "{} nodes selected, ctrl-click or shift-drag in the graph to select",
+                selected.len()
+            ));
+            // select the nodes matched by a query, see arbor_core::query
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.query_buf))
+                    .on_hover_text("e.g. select nodes where speaker == cat and out_degree == 0");
+                if ui.button("select").clicked() {
+                    let res = self
+                        .query_buf
+                        .parse::<query::Query>()
+                        .and_then(|query| query.select_nodes(&state.active));
+                    match res {
+                        Ok(nodes) => *selected = nodes.into_iter().collect(),
+                        Err(e) => println!("{}", e),
+                    }
+                }
+            });
+            if selected.is_empty() {
+                return;
+            }
+            let nodes: Vec<tree::NodeIndex> = selected.iter().copied().collect();
+            if ui.button("clear selection").clicked() {
+                selected.clear();
+            }
             ui.separator();
-            ui.add(
-                egui::TextEdit::multiline(&mut self.text_buf)
-                    .text_style(egui::TextStyle::Monospace)
-                    .desired_width(f32::INFINITY)
-                    .desired_rows(10),
-            );
+
+            if ui.button("delete").clicked() {
+                match cmd::remove::Nodes::new(nodes.clone()).execute(state) {
+                    // the remaining nodes are renumbered, so the selection no longer applies
+                    Ok(_) => selected.clear(),
+                    Err(e) => println!("{}", e),
+                }
+            }
             ui.separator();
 
-            if ui.button("new edge").clicked() {
-                let res = cmd::new::Edge::new(
-                    self.source_node,
-                    self.target_node,
-                    self.text_buf.drain(..).collect(),
-                    None,
-                    None,
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.flags_buf).desired_width(MAX_NAME_WIDTH),
                 )
-                .execute(state);
-                match res {
-                    Ok(_) => println!("successfully added edge"),
-                    Err(e) => println!("{}", e),
+                .on_hover_text("violence, profanity, or spoiler");
+                if ui.button("tag").clicked() {
+                    let res = match self.flags_buf.parse::<ContentFlags>() {
+                        Ok(flags) => cmd::edit::Tag::new(flags, nodes.clone()).execute(state),
+                        Err(e) => Err(e.into()),
+                    };
+                    if let Err(e) = res {
+                        println!("{}", e);
+                    }
+                }
+            });
+            ui.separator();
+
+            // layout tools, each button is a single change in the history
+            let mut layout: Option<Box<dyn Executable>> = None;
+            ui.horizontal(|ui| {
+                if ui.button("align left").clicked() {
+                    let align = cmd::edit::Align::new(cmd::edit::Side::Left, nodes.clone());
+                    layout = Some(Box::new(align));
+                }
+                if ui.button("align top").clicked() {
+                    let align = cmd::edit::Align::new(cmd::edit::Side::Top, nodes.clone());
+                    layout = Some(Box::new(align));
+                }
+            });
+            ui.horizontal(|ui| {
+                if ui.button("distribute horizontally").clicked() {
+                    layout = Some(Box::new(cmd::edit::Distribute::new(false, nodes.clone())));
+                }
+                if ui.button("distribute vertically").clicked() {
+                    layout = Some(Box::new(cmd::edit::Distribute::new(true, nodes.clone())));
                 }
+            });
+            if ui
+                .button("snap to grid")
+                .on_hover_text("the grid size is set above the graph")
+                .clicked()
+            {
+                layout = Some(Box::new(cmd::edit::Snap::new(grid_size, nodes.clone())));
+            }
+            if let Some(Err(e)) = layout.map(|cmd| cmd.execute(state)) {
+                println!("{}", e);
             }
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("x");
+                ui.add(egui::DragValue::new(&mut self.dx).speed(0.01));
+                ui.label("y");
+                ui.add(egui::DragValue::new(&mut self.dy).speed(0.01));
+                if ui.button("move").clicked() {
+                    if let Err(e) =
+                        cmd::edit::Move::new(self.dx, self.dy, nodes.clone()).execute(state)
+                    {
+                        println!("{}", e);
+                    }
+                }
+            });
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::TextEdit::singleline(&mut self.export_name_buf)
+                        .desired_width(MAX_NAME_WIDTH),
+                );
+                if ui.button("export subtree").clicked() {
+                    let res =
+                        cmd::export::Subtree::new(self.export_name_buf.clone(), nodes.clone())
+                            .execute(state);
+                    if let Err(e) = res {
+                        println!("{}", e);
+                    }
+                }
+            });
         })
         .response
     }
@@ -597,6 +2130,46 @@ pub struct TreePainting {
     /// temporary storage of the position of a node being dragged. Used to add a click-and-drag
     /// event to the undo/redo history
     node_drag_pos: arbor_core::Position,
+    /// round node positions to a grid while they are dragged
+    #[serde(default)]
+    pub snap_to_grid: bool,
+    /// spacing of the grid used for snapping, in tree coordinates
+    #[serde(default = "default_grid_size")]
+    pub grid_size: f32,
+    /// node to center the view on during the next frame
+    #[serde(skip)]
+    pub focus: Option<tree::NodeIndex>,
+    /// nodes picked by ctrl-click or a selection box, for bulk actions. Node indices change when
+    /// nodes are removed, so the selection is cleared whenever that may happen
+    #[serde(skip)]
+    pub selected: BTreeSet<tree::NodeIndex>,
+    /// starting positions of the selected nodes while they are dragged together, used to add
+    /// the whole move to the undo/redo history as one change
+    #[serde(skip)]
+    group_drag: Vec<(tree::NodeIndex, arbor_core::Position)>,
+    /// corner of the selection box where the shift-drag started, in paint area coordinates
+    #[serde(skip)]
+    band_start: Option<egui::Pos2>,
+    /// references to the key focused in the inspector, whose nodes and edges are highlighted
+    #[serde(skip)]
+    pub usages: Vec<Reference>,
+    /// how nodes are grouped for collapsing
+    #[serde(skip)]
+    pub group_by: analysis::GroupBy,
+    /// labels of the groups drawn as a single node, see analysis::group_labels
+    #[serde(skip)]
+    pub collapsed: BTreeSet<String>,
+}
+
+/// A collapsed group of nodes, drawn as a single node at the center of its nodes
+struct Cluster {
+    label: String,
+    center: arbor_core::Position,
+    nodes: usize,
+    /// number of edges entering the group from outside of it
+    entries: usize,
+    /// number of edges leaving the group
+    exits: usize,
 }
 
 impl Default for TreePainting {
@@ -613,10 +2186,23 @@ impl Default for TreePainting {
             pan_start: egui::pos2(0.0, 0.0),
             origin: egui::pos2(0.0, 0.0),
             node_drag_pos: arbor_core::Position::default(),
+            snap_to_grid: false,
+            grid_size: default_grid_size(),
+            focus: None,
+            selected: BTreeSet::new(),
+            group_drag: Vec::new(),
+            band_start: None,
+            usages: Vec::new(),
+            group_by: analysis::GroupBy::default(),
+            collapsed: BTreeSet::new(),
         }
     }
 }
 
+fn default_grid_size() -> f32 {
+    0.1
+}
+
 impl TreePainting {
     #[inline]
     fn transform(&self, p: egui::Pos2) -> egui::Pos2 {
@@ -647,6 +2233,13 @@ impl TreePainting {
             ui.label("y position");
             ui.add(egui::DragValue::new(&mut self.pan.y));
             ui.separator();
+            ui.checkbox(&mut self.snap_to_grid, "snap");
+            ui.add(
+                egui::DragValue::new(&mut self.grid_size)
+                    .speed(0.01)
+                    .clamp_range(0.01..=10.0),
+            );
+            ui.separator();
         })
         .response
     }
@@ -672,16 +2265,46 @@ impl TreePainting {
         );
         let from_screen = to_screen.inverse();
 
+        // nodes and edges using the key focused in the inspector are highlighted
+        let used_nodes: HashSet<_> = self.usages.iter().filter_map(Reference::node).collect();
+        let used_edges: HashSet<_> = self.usages.iter().filter_map(Reference::edge).collect();
+
+        // pan so that the focused node is in the center of the paint area, expanding its group
+        // if it is collapsed
+        if let Some(i) = self.focus.take().filter(|&i| data.tree.get_node(i).is_ok()) {
+            if !self.collapsed.is_empty() {
+                if let Ok(labels) = analysis::group_labels(data, self.group_by) {
+                    self.collapsed.remove(&labels[i]);
+                }
+            }
+            let center = response.rect.square_proportions() * 0.5;
+            let pos = data.position(i);
+            self.pan = egui::pos2(center.x - pos.x * self.zoom, center.y - pos.y * self.zoom);
+        }
+
+        // nodes of collapsed groups are drawn as a single node for each group. Edges within a
+        // group are hidden, and edges entering or leaving it are drawn to the group instead
+        let (cluster_of, clusters) = self.clusters(data);
+        let position = |i: tree::NodeIndex| match cluster_of[i] {
+            Some(c) => clusters[c].center,
+            None => data.position(i),
+        };
+
         // draw edges first, since they need to be behind nodes
         for (edge_index, choice) in data.tree.edges().iter().enumerate() {
-            let slice = &data.text[choice.section[0]..choice.section[1]];
-            let _res = cmd::util::parse_edge(slice, &data.name_table, &mut self.hover_text_buf);
-
             let source_node_index = data.tree.source_of(edge_index).unwrap();
             let target_node_index = data.tree.target_of(edge_index).unwrap();
+            if cluster_of[source_node_index].is_some()
+                && cluster_of[source_node_index] == cluster_of[target_node_index]
+            {
+                continue;
+            }
+
+            let slice = &data.text[choice.section[0]..choice.section[1]];
+            let _res = cmd::util::parse_edge(slice, &data.name_table, &mut self.hover_text_buf);
 
-            let source_pos = data.tree.get_node(source_node_index).unwrap().pos;
-            let target_pos = data.tree.get_node(target_node_index).unwrap().pos;
+            let source_pos = position(source_node_index);
+            let target_pos = position(target_node_index);
 
             let source_coord = to_screen * self.transform(egui::pos2(source_pos.x, source_pos.y));
             let target_coord = to_screen * self.transform(egui::pos2(target_pos.x, target_pos.y));
@@ -715,13 +2338,8 @@ impl TreePainting {
 
             // change edge color if this edge is the actively selected thing
             let edge_color = match active_selection {
-                Selection::Edge(e) => {
-                    if e == edge_index {
-                        self.select_color
-                    } else {
-                        self.stroke
-                    }
-                }
+                Selection::Edge(e) if e == edge_index => self.select_color,
+                _ if used_edges.contains(&edge_index) => egui::Color32::YELLOW,
                 _ => self.stroke,
             };
 
@@ -743,9 +2361,20 @@ impl TreePainting {
             );
         }
 
+        let modifiers = ui.input().modifiers;
+        // dragging a selected node drags the whole selection. The rest of the selection is
+        // moved after the loop, since only one node can be borrowed at a time inside it
+        let mut group_start = None;
+        let mut group_offset = None;
+        let mut group_released = false;
+        let mut released = None;
+
         // loop over the nodes, draw them, and update their location if being dragged
-        for (i, n) in data.tree.nodes_mut().iter_mut().enumerate() {
-            let pos = n.pos;
+        for (i, n) in data.tree.nodes().iter().enumerate() {
+            if cluster_of[i].is_some() {
+                continue;
+            }
+            let pos = data.layout.get(&n.line.id).copied().unwrap_or_default();
 
             let p = egui::pos2(pos.x, pos.y);
             let coord = to_screen * self.transform(p);
@@ -764,43 +2393,58 @@ impl TreePainting {
                 &mut self.hover_text_buf,
             );
 
+            // ctrl-click adds or removes a node from the multi-selection
+            if resp.clicked() && modifiers.command {
+                if !self.selected.remove(&i) {
+                    self.selected.insert(i);
+                }
+            }
+
+            // save initial position of a node when starting the drag, used below when qualifying
+            // the node movement in the undo/redo history
+            if resp.drag_started() {
+                self.node_drag_pos = pos;
+                if self.selected.contains(&i) {
+                    group_start = Some(i);
+                }
+            }
+
             // move node with mouse drag
             if let Some(pointer_pos) = resp.interact_pointer_pos() {
                 let new_pos = self.reform(from_screen * pointer_pos);
                 // bypass normal cmd interface here to avoid spamming event history during a drag
-                n.pos = arbor_core::Position::new(new_pos.x, new_pos.y);
+                let mut moved = arbor_core::Position::new(new_pos.x, new_pos.y);
+                if self.snap_to_grid {
+                    moved = cmd::util::snap(moved, self.grid_size);
+                }
+                data.layout.insert(n.line.id, moved);
                 active_selection = Selection::Node(i);
+                if self.selected.contains(&i) {
+                    group_offset = Some((
+                        i,
+                        moved.x - self.node_drag_pos.x,
+                        moved.y - self.node_drag_pos.y,
+                    ));
+                }
             }
 
-            // save initial position of a node when starting the drag, used below when qualifying
-            // the node movement in the undo/redo history
-            if resp.drag_started() {
-                self.node_drag_pos = n.pos;
-            }
-
-            // qualify node movement in event history after drag release
-            if resp.drag_released() {
-                let mut old_pos_node = *n;
-                old_pos_node.pos = self.node_drag_pos;
-                history.push(
-                    arbor_core::tree::event::NodeEdit {
-                        index: i,
-                        from: old_pos_node,
-                        to: *n,
-                    }
-                    .into(),
-                );
+            // qualify node movement in event history after drag release. The history needs the
+            // whole tree, so the move is added after the loop along with group drags
+            if resp.drag_released() && !self.group_drag.is_empty() {
+                group_released = true;
+            } else if resp.drag_released() {
+                released = Some(LayoutEdit {
+                    index: i,
+                    from: Some(self.node_drag_pos),
+                    to: data.layout.get(&n.line.id).copied(),
+                });
             }
 
-            // get custom fill color for active selection
+            // get custom fill color for active selection and the multi-selection
             let fill_color = match active_selection {
-                Selection::Node(n) => {
-                    if n == i {
-                        self.select_color
-                    } else {
-                        self.fill
-                    }
-                }
+                Selection::Node(n) if n == i => self.select_color,
+                _ if self.selected.contains(&i) => self.select_color.linear_multiply(0.5),
+                _ if used_nodes.contains(&i) => egui::Color32::YELLOW,
                 _ => self.fill,
             };
 
@@ -825,27 +2469,207 @@ impl TreePainting {
             }
         }
 
+        // draw collapsed groups on top of the nodes, clicking one expands it
+        let cluster_size = self.node_size * 2.0;
+        for cluster in clusters.iter() {
+            let p = egui::pos2(cluster.center.x, cluster.center.y);
+            let coord = to_screen * self.transform(p);
+            let rect =
+                Rect::from_center_size(coord, egui::vec2(cluster_size * 2., cluster_size * 2.));
+            let resp = ui
+                .interact(
+                    rect,
+                    egui::Id::new(&cluster.label).with("__cluster"),
+                    egui::Sense::click(),
+                )
+                .on_hover_text(format!(
+                    "{} nodes, {} choices in, {} choices out\nclick to expand",
+                    cluster.nodes, cluster.entries, cluster.exits
+                ));
+            if resp.clicked() {
+                self.collapsed.remove(&cluster.label);
+            }
+            if response.rect.contains(coord) {
+                painter.add(egui::Shape::circle_filled(
+                    coord,
+                    cluster_size * self.zoom,
+                    self.fill,
+                ));
+                painter.add(egui::Shape::circle_stroke(
+                    coord,
+                    cluster_size * self.zoom,
+                    (self.zoom * 2.0, self.stroke),
+                ));
+                painter.text(
+                    coord,
+                    egui::Align2::CENTER_CENTER,
+                    &cluster.label,
+                    egui::TextStyle::Body,
+                    egui::Color32::BLACK,
+                );
+            }
+        }
+
+        // record where each selected node started, the dragged node has already been moved
+        if let Some(dragged) = group_start {
+            let start = self.node_drag_pos;
+            self.group_drag = self
+                .selected
+                .iter()
+                .filter_map(|&i| {
+                    if i == dragged {
+                        Some((i, start))
+                    } else {
+                        data.tree.get_node(i).ok().map(|_| (i, data.position(i)))
+                    }
+                })
+                .collect();
+        }
+
+        // move the rest of the selection by the same offset as the dragged node
+        if let Some((dragged, dx, dy)) = group_offset {
+            for &(i, start) in self.group_drag.iter().filter(|(i, _)| *i != dragged) {
+                if let Ok(node) = data.tree.get_node(i) {
+                    let moved = arbor_core::Position::new(start.x + dx, start.y + dy);
+                    data.layout.insert(node.line.id, moved);
+                }
+            }
+        }
+
+        // qualify a single node move in the event history
+        if let Some(edit) = released {
+            history.push(edit.into(), data);
+        }
+
+        // qualify the whole group move as one change in the event history
+        if group_released {
+            let mut events: Vec<DialogueTreeEvent> = Vec::with_capacity(self.group_drag.len());
+            for (i, start) in self.group_drag.drain(..) {
+                if data.tree.get_node(i).is_ok() {
+                    events.push(
+                        LayoutEdit {
+                            index: i,
+                            from: Some(start),
+                            to: data.placement(i),
+                        }
+                        .into(),
+                    );
+                }
+            }
+            history.push(EventGroup { events }.into(), data);
+        }
+
         // handle dragging to pan screen after drawing nodes so that clicking/dragging nodes
-        // has priority
+        // has priority. Shift-dragging draws a selection box instead
         let pan_response = response.interact(egui::Sense::drag());
         if let Some(pointer_pos) = pan_response.interact_pointer_pos() {
             if pan_response.drag_started() {
-                self.pan_start = from_screen * pointer_pos;
-                self.origin = self.pan;
+                if modifiers.shift {
+                    self.band_start = Some(from_screen * pointer_pos);
+                } else {
+                    self.pan_start = from_screen * pointer_pos;
+                    self.origin = self.pan;
+                }
+            }
+            match self.band_start {
+                Some(start) => {
+                    let band = Rect::from_two_pos(to_screen * start, pointer_pos);
+                    painter.rect_stroke(band, 0.0, (1.0, self.select_color));
+                }
+                None => {
+                    let pan_vec = (from_screen * pointer_pos) - self.pan_start;
+                    self.pan = self.origin + pan_vec;
+                }
+            }
+        }
+
+        // select every node inside the box when a shift-drag is released, adding to the
+        // selection if ctrl is held
+        if pan_response.drag_released() {
+            if let (Some(start), Some(end)) = (self.band_start.take(), pan_response.hover_pos()) {
+                let band = Rect::from_two_pos(to_screen * start, end);
+                if !modifiers.command {
+                    self.selected.clear();
+                }
+                for i in 0..data.tree.nodes().len() {
+                    let pos = data.position(i);
+                    let coord = to_screen * self.transform(egui::pos2(pos.x, pos.y));
+                    if cluster_of[i].is_none() && band.contains(coord) {
+                        self.selected.insert(i);
+                    }
+                }
             }
-            let pan_vec = (from_screen * pointer_pos) - self.pan_start;
-            self.pan = self.origin + pan_vec;
         }
 
         // clear selection if a click occured that didn't hit a node/edge up above
         if pan_response.clicked() {
             active_selection = Selection::None;
+            self.selected.clear();
         }
 
         // return active selection to rest of the UI
         active_selection
     }
 
+    /// Find the collapsed group each node belongs to, as an index into the returned groups
+    fn clusters(&self, data: &DialogueTreeData) -> (Vec<Option<usize>>, Vec<Cluster>) {
+        let node_count = data.tree.nodes().len();
+        let labels = match analysis::group_labels(data, self.group_by) {
+            Ok(labels) if !self.collapsed.is_empty() => labels,
+            _ => return (vec![None; node_count], Vec::new()),
+        };
+
+        let mut ids: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut clusters: Vec<Cluster> = Vec::new();
+        let mut cluster_of = Vec::with_capacity(node_count);
+        for (i, label) in labels.iter().enumerate() {
+            if !self.collapsed.contains(label) {
+                cluster_of.push(None);
+                continue;
+            }
+            let id = *ids.entry(label.as_str()).or_insert_with(|| {
+                clusters.push(Cluster {
+                    label: label.clone(),
+                    center: arbor_core::Position::default(),
+                    nodes: 0,
+                    entries: 0,
+                    exits: 0,
+                });
+                clusters.len() - 1
+            });
+            let pos = data.position(i);
+            let cluster = &mut clusters[id];
+            cluster.center.x += pos.x;
+            cluster.center.y += pos.y;
+            cluster.nodes += 1;
+            cluster_of.push(Some(id));
+        }
+        for cluster in clusters.iter_mut() {
+            cluster.center.x /= cluster.nodes as f32;
+            cluster.center.y /= cluster.nodes as f32;
+        }
+
+        for edge_index in 0..data.tree.edges().len() {
+            let (source, target) = match (
+                data.tree.source_of(edge_index),
+                data.tree.target_of(edge_index),
+            ) {
+                (Ok(source), Ok(target)) => (cluster_of[source], cluster_of[target]),
+                _ => continue,
+            };
+            if source == target {
+                continue;
+            }
+            if let Some(c) = source {
+                clusters[c].exits += 1;
+            }
+            if let Some(c) = target {
+                clusters[c].entries += 1;
+            }
+        }
+        (cluster_of, clusters)
+    }
+
     fn edge_text_popup(
         ctx: &egui::CtxRef,
         edge_index: usize,
@@ -1113,6 +2937,35 @@ impl BackendPanel {
         ui.separator();
     }
 
+    /// Show the memory used by each part of the active project, to help diagnose bloat
+    fn memory_ui(&self, ui: &mut egui::Ui, stats: &arbor_core::MemoryStats) {
+        egui::CollapsingHeader::new("🗄 Project memory usage")
+            .default_open(false)
+            .show(ui, |ui| {
+                let parts = [
+                    ("Text", stats.text),
+                    ("Nodes", stats.nodes),
+                    ("Edges", stats.edges),
+                    ("Links", stats.links),
+                    ("Tables", stats.tables),
+                    ("Validation cache", stats.cache),
+                    ("History", stats.history),
+                ];
+                egui::Grid::new("memory_stats")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (part, bytes) in parts.iter() {
+                            ui.label(*part);
+                            ui.label(cmd::util::format_bytes(*bytes));
+                            ui.end_row();
+                        }
+                        ui.label("Total");
+                        ui.label(cmd::util::format_bytes(stats.total()));
+                        ui.end_row();
+                    });
+            });
+    }
+
     fn pixels_per_point_ui(
         &mut self,
         ui: &mut egui::Ui,
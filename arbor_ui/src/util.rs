@@ -42,7 +42,7 @@ pub fn lorem_ipsum(state: &mut EditorState, count: usize) -> Result<()> {
         );
         let idx = cmd::new::Node::new(key.to_string(), TEXT[text_start..text_end].to_string())
             .execute(state)?;
-        state.active.tree.get_node_mut(idx)?.pos = pos;
+        state.active.set_position(idx, Some(pos))?;
     }
 
     // create a ton of edges